@@ -1,11 +1,13 @@
 use clap::Parser;
 use conduwuit::Result;
+use service::admin::Tier;
 
 use crate::{
-	appservice, appservice::AppserviceCommand, check, check::CheckCommand, command::Command,
-	debug, debug::DebugCommand, federation, federation::FederationCommand, media,
-	media::MediaCommand, query, query::QueryCommand, room, room::RoomCommand, server,
-	server::ServerCommand, user, user::UserCommand,
+	appservice, appservice::AppserviceCommand, check, check::CheckCommand, command::Command, db,
+	db::DbCommand, debug, debug::DebugCommand, federation, federation::FederationCommand, jobs,
+	jobs::JobsCommand, media, media::MediaCommand, query, query::QueryCommand, registry, reports,
+	reports::ReportsCommand, room, room::RoomCommand, server, server::ServerCommand, user,
+	user::UserCommand, Tiered,
 };
 
 #[derive(Debug, Parser)]
@@ -35,6 +37,14 @@ pub(super) enum AdminCommand {
 	/// - Commands for managing media
 	Media(MediaCommand),
 
+	#[command(subcommand)]
+	/// - Commands for managing background jobs
+	Jobs(JobsCommand),
+
+	#[command(subcommand)]
+	/// - Commands for handling room and event reports from users
+	Reports(ReportsCommand),
+
 	#[command(subcommand)]
 	/// - Commands for checking integrity
 	Check(CheckCommand),
@@ -43,11 +53,56 @@ pub(super) enum AdminCommand {
 	/// - Commands for debugging things
 	Debug(DebugCommand),
 
+	#[command(subcommand)]
+	/// - Commands for inspecting the database
+	Db(DbCommand),
+
 	#[command(subcommand)]
 	/// - Low-level queries for database getters and iterators
 	Query(QueryCommand),
 }
 
+impl Tiered for AdminCommand {
+	fn tier(&self) -> Tier {
+		use AdminCommand::*;
+
+		match self {
+			| Appservices(command) => command.tier(),
+			| Media(command) => command.tier(),
+			| Jobs(command) => command.tier(),
+			| Reports(command) => command.tier(),
+			| Users(command) => command.tier(),
+			| Rooms(command) => command.tier(),
+			| Federation(command) => command.tier(),
+			| Server(command) => command.tier(),
+			| Debug(command) => command.tier(),
+			| Db(command) => command.tier(),
+			| Query(command) => command.tier(),
+			| Check(command) => command.tier(),
+		}
+	}
+
+	fn registry() -> registry::Registry {
+		[
+			("appservices", AppserviceCommand::registry()),
+			("users", UserCommand::registry()),
+			("rooms", RoomCommand::registry()),
+			("federation", FederationCommand::registry()),
+			("server", ServerCommand::registry()),
+			("media", MediaCommand::registry()),
+			("jobs", JobsCommand::registry()),
+			("reports", ReportsCommand::registry()),
+			("check", CheckCommand::registry()),
+			("debug", DebugCommand::registry()),
+			("db", DbCommand::registry()),
+			("query", QueryCommand::registry()),
+		]
+		.into_iter()
+		.flat_map(|(name, commands)| registry::prefixed(name, commands))
+		.collect()
+	}
+}
+
 #[tracing::instrument(skip_all, name = "command")]
 pub(super) async fn process(command: AdminCommand, context: &Command<'_>) -> Result {
 	use AdminCommand::*;
@@ -55,11 +110,14 @@ pub(super) async fn process(command: AdminCommand, context: &Command<'_>) -> Res
 	match command {
 		| Appservices(command) => appservice::process(command, context).await?,
 		| Media(command) => media::process(command, context).await?,
+		| Jobs(command) => jobs::process(command, context).await?,
+		| Reports(command) => reports::process(command, context).await?,
 		| Users(command) => user::process(command, context).await?,
 		| Rooms(command) => room::process(command, context).await?,
 		| Federation(command) => federation::process(command, context).await?,
 		| Server(command) => server::process(command, context).await?,
 		| Debug(command) => debug::process(command, context).await?,
+		| Db(command) => db::process(command, context).await?,
 		| Query(command) => query::process(command, context).await?,
 		| Check(command) => check::process(command, context).await?,
 	};
@@ -1,9 +1,13 @@
+use std::{fmt::Write, time::SystemTime};
+
+use conduwuit::utils::time;
+use conduwuit_service::{appservice::ConflictReport, deactivation::DeactivateOptions};
 use ruma::{api::appservice::Registration, events::room::message::RoomMessageEventContent};
 
 use crate::{admin_command, Result};
 
 #[admin_command]
-pub(super) async fn register(&self) -> Result<RoomMessageEventContent> {
+pub(super) async fn register(&self, dry_run: bool) -> Result<RoomMessageEventContent> {
 	if self.body.len() < 2
 		|| !self.body[0].trim().starts_with("```")
 		|| self.body.last().unwrap_or(&"").trim() != "```"
@@ -16,6 +20,18 @@ pub(super) async fn register(&self) -> Result<RoomMessageEventContent> {
 	let appservice_config_body = self.body[1..self.body.len().checked_sub(1).unwrap()].join("\n");
 	let parsed_config = serde_yaml::from_str::<Registration>(&appservice_config_body);
 	match parsed_config {
+		| Ok(registration) if dry_run => {
+			let report = self
+				.services
+				.appservice
+				.check_registration(&registration)
+				.await;
+
+			Ok(RoomMessageEventContent::notice_markdown(conflict_report(
+				&registration.id,
+				&report,
+			)))
+		},
 		| Ok(registration) => match self
 			.services
 			.appservice
@@ -82,3 +98,159 @@ pub(super) async fn list_registered(&self) -> Result<RoomMessageEventContent> {
 	let output = format!("Appservices ({}): {}", appservices.len(), appservices.join(", "));
 	Ok(RoomMessageEventContent::text_plain(output))
 }
+
+#[admin_command]
+pub(super) async fn status(
+	&self,
+	appservice_identifier: Option<String>,
+) -> Result<RoomMessageEventContent> {
+	let appservices = match appservice_identifier {
+		| Some(id)
+			if self
+				.services
+				.appservice
+				.get_registration(&id)
+				.await
+				.is_none() =>
+			return Ok(RoomMessageEventContent::text_plain("Appservice does not exist.")),
+		| Some(id) => vec![id],
+		| None => self.services.appservice.iter_ids().await,
+	};
+
+	let now = SystemTime::now();
+	let ago = |at: SystemTime| time::pretty(now.duration_since(at).unwrap_or_default());
+
+	let mut out = String::new();
+	for id in &appservices {
+		let status = self.services.sending.appservice_status(id);
+		let depth = self.services.sending.appservice_queue_depth(id).await;
+		writeln!(
+			out,
+			"**{id}**: {} events being sent, {} queued, {} transactions delivered",
+			depth.active, depth.queued, status.delivered
+		)?;
+
+		match status.last_success {
+			| Some(at) => writeln!(out, "- Last delivery {} ago", ago(at))?,
+			| None => writeln!(out, "- Nothing delivered since startup")?,
+		}
+
+		if status.failures > 0 {
+			writeln!(out, "- Failing; {} attempts so far", status.failures)?;
+		}
+
+		if let Some((at, error)) = &status.last_error {
+			writeln!(out, "- Last error {} ago: {error}", ago(*at))?;
+		}
+
+		if let Some(at) = status.next_retry {
+			let retry_in = at.duration_since(now).unwrap_or_default();
+			writeln!(out, "- Next retry in {}", time::pretty(retry_in))?;
+		}
+	}
+
+	if out.is_empty() {
+		out.push_str("No appservices are registered.");
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+pub(super) async fn list_users(
+	&self,
+	appservice_identifier: String,
+) -> Result<RoomMessageEventContent> {
+	let users = self
+		.services
+		.appservice
+		.namespace_users(&appservice_identifier)
+		.await?;
+
+	let mut out = format!("Users of {appservice_identifier} ({}):\n```\n", users.len());
+	for user_id in &users {
+		writeln!(out, "{user_id}")?;
+	}
+	out.push_str("```");
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+pub(super) async fn deactivate_users(
+	&self,
+	appservice_identifier: String,
+	erase: bool,
+	remove_pushers: bool,
+) -> Result<RoomMessageEventContent> {
+	let options = DeactivateOptions {
+		erase,
+		remove_pushers,
+		..DeactivateOptions::default()
+	};
+
+	let count = self
+		.services
+		.appservice
+		.deactivate_namespace_users(&appservice_identifier, options)
+		.await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Queued deactivation of {count} users of {appservice_identifier} in the background."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn leave_rooms(
+	&self,
+	appservice_identifier: String,
+) -> Result<RoomMessageEventContent> {
+	let count = self
+		.services
+		.appservice
+		.leave_namespace_users(&appservice_identifier)
+		.await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Queued {count} users of {appservice_identifier} to leave their rooms in the background."
+	)))
+}
+
+fn conflict_report(id: &str, report: &ConflictReport) -> String {
+	let mut out = if report.is_acceptable() {
+		format!("Appservice {id} can be registered.\n")
+	} else {
+		format!("Appservice {id} can't be registered.\n")
+	};
+
+	for error in &report.errors {
+		writeln!(out, "- {error}").expect("should be able to write to string buffer");
+	}
+
+	if !report.appservices.is_empty() {
+		writeln!(
+			out,
+			"\nConflicting exclusive namespaces with: {}",
+			report.appservices.join(", ")
+		)
+		.expect("should be able to write to string buffer");
+	}
+
+	if !report.users.is_empty() {
+		writeln!(out, "\nExisting users it would take over ({}):", report.users.len())
+			.expect("should be able to write to string buffer");
+		for user_id in &report.users {
+			writeln!(out, "- {user_id}").expect("should be able to write to string buffer");
+		}
+	}
+
+	if !report.aliases.is_empty() {
+		writeln!(out, "\nExisting aliases it would take over ({}):", report.aliases.len())
+			.expect("should be able to write to string buffer");
+		for alias in &report.aliases {
+			writeln!(out, "- {alias}").expect("should be able to write to string buffer");
+		}
+	}
+
+	out
+}
@@ -14,8 +14,14 @@ pub(super) enum AppserviceCommand {
 	/// which must be provided in a Markdown code block below the command.
 	///
 	/// Registering a new bridge using the ID of an existing bridge will replace
-	/// the old one.
-	Register,
+	/// the old one. Registrations whose exclusive namespaces conflict with
+	/// another appservice are refused.
+	Register {
+		/// Only report problems with the registration, and the existing users
+		/// and aliases it would take over, without registering it
+		#[arg(long)]
+		dry_run: bool,
+	},
 
 	/// - Unregister an appservice using its ID
 	///
@@ -37,4 +43,49 @@ pub(super) enum AppserviceCommand {
 	/// - List all the currently registered appservices
 	#[clap(alias("list"))]
 	ListRegistered,
+
+	/// - Show the delivery of transactions to appservices
+	///
+	/// Shows the events waiting for each appservice, and the last error and
+	/// next retry of appservices which failed to accept transactions.
+	Status {
+		/// The appservice to show; all of them if not given
+		appservice_identifier: Option<String>,
+	},
+
+	/// - List the users in an appservice's exclusive namespaces
+	///
+	/// These are the puppets or ghosts of a bridge, without its sender user.
+	/// Admin accounts are never listed.
+	ListUsers {
+		/// The appservice whose users to list
+		appservice_identifier: String,
+	},
+
+	/// - Deactivate all users in an appservice's exclusive namespaces
+	///
+	/// Use this when decommissioning a bridge. The users listed by
+	/// `list-users` are deactivated in the background at the pace set by
+	/// `bulk_deactivation_interval_ms`, with progress reported to the admin
+	/// room. They leave all joined rooms and reject their invites.
+	DeactivateUsers {
+		/// The appservice whose users to deactivate
+		appservice_identifier: String,
+		#[arg(long)]
+		/// Clear the display name, avatar and other profile fields
+		erase: bool,
+		#[arg(long)]
+		/// Delete all pushers
+		remove_pushers: bool,
+	},
+
+	/// - Make all users in an appservice's exclusive namespaces leave their
+	///   rooms
+	///
+	/// The users listed by `list-users` leave all joined rooms and reject
+	/// their invites in the background, keeping their accounts.
+	LeaveRooms {
+		/// The appservice whose users should leave their rooms
+		appservice_identifier: String,
+	},
 }
@@ -0,0 +1,211 @@
+use std::{collections::BTreeMap, ffi::CStr, fmt::Write};
+
+use conduwuit::{err, utils::bytes, Result};
+use conduwuit_database::Map;
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use ruma::events::room::message::RoomMessageEventContent;
+use tokio::time::Instant;
+
+use crate::{
+	admin_command,
+	utils::{is_secret_key, is_secret_val, redacted},
+};
+
+const ESTIMATE_NUM_KEYS: &CStr = c"rocksdb.estimate-num-keys";
+const SST_SIZE: &CStr = c"rocksdb.total-sst-files-size";
+const PENDING_COMPACTION_BYTES: &CStr = c"rocksdb.estimate-pending-compaction-bytes";
+const MEMTABLES_SIZE: &CStr = c"rocksdb.size-all-mem-tables";
+
+/// Properties listed by `properties` when none is requested.
+const INTEGER_PROPERTIES: &[&CStr] = &[
+	ESTIMATE_NUM_KEYS,
+	c"rocksdb.estimate-live-data-size",
+	SST_SIZE,
+	c"rocksdb.live-sst-files-size",
+	PENDING_COMPACTION_BYTES,
+	c"rocksdb.compaction-pending",
+	c"rocksdb.num-running-compactions",
+	c"rocksdb.num-running-flushes",
+	c"rocksdb.mem-table-flush-pending",
+	c"rocksdb.num-immutable-mem-table",
+	c"rocksdb.cur-size-active-mem-table",
+	c"rocksdb.cur-size-all-mem-tables",
+	MEMTABLES_SIZE,
+	c"rocksdb.num-entries-active-mem-table",
+	c"rocksdb.num-deletes-active-mem-table",
+	c"rocksdb.estimate-table-readers-mem",
+	c"rocksdb.num-live-versions",
+	c"rocksdb.background-errors",
+	c"rocksdb.actual-delayed-write-rate",
+	c"rocksdb.is-write-stopped",
+];
+
+#[admin_command]
+pub(super) async fn get(&self, map: String, key: String) -> Result<RoomMessageEventContent> {
+	let key = decode_hex(&key)?;
+	let map = self.services.db.get(&map)?;
+	let timer = Instant::now();
+	let handle = map.get(&key).await?;
+	let query_time = timer.elapsed();
+	let val = show_val(map.name(), &handle);
+
+	Ok(RoomMessageEventContent::notice_markdown(format!(
+		"Query completed in {query_time:?}:\n\n```\n{val}\n```"
+	)))
+}
+
+#[admin_command]
+pub(super) async fn scan(
+	&self,
+	map: String,
+	prefix: Option<String>,
+	limit: usize,
+) -> Result<RoomMessageEventContent> {
+	let prefix = prefix.as_deref().map(decode_hex).transpose()?;
+	let prefix = prefix.unwrap_or_default();
+	let map = self.services.db.get(&map)?;
+	let name = map.name();
+
+	writeln!(self, "```").await?;
+
+	let timer = Instant::now();
+	let mut count: usize = 0;
+	map.raw_stream_prefix(&prefix)
+		.take(limit)
+		.try_for_each(|(key, val)| {
+			count = count.saturating_add(1);
+			writeln!(self, "{}\n  {}", show_key(name, key), show_val(name, val))
+		})
+		.boxed()
+		.await?;
+
+	let query_time = timer.elapsed();
+	self.write_str(&format!("```\n\n{count} records; query completed in {query_time:?}"))
+		.await?;
+
+	Ok(RoomMessageEventContent::text_plain(""))
+}
+
+#[admin_command]
+pub(super) async fn stats(&self, map: Option<String>) -> Result<RoomMessageEventContent> {
+	let maps: Vec<_> = match &map {
+		| Some(name) => vec![self.services.db.get(name)?],
+		| None => self.services.db.iter().map(|(_, map)| map).collect(),
+	};
+
+	// Bytes in each level of each map
+	let mut levels: BTreeMap<String, BTreeMap<i32, u64>> = BTreeMap::new();
+	for file in self.services.db.db.file_list() {
+		let file = file?;
+		let size = u64::try_from(file.size)?;
+		let level = levels
+			.entry(file.column_family_name)
+			.or_default()
+			.entry(file.level)
+			.or_default();
+
+		*level = level.saturating_add(size);
+	}
+
+	writeln!(self, "| map | keys | size | levels | pending compaction | memtables |").await?;
+	writeln!(self, "| :-- | ---: | ---: | :----- | ---: | ---: |").await?;
+	for map in maps {
+		let level_sizes = levels
+			.get(map.name())
+			.into_iter()
+			.flatten()
+			.map(|(level, size)| format!("L{level} {}", pretty(*size)))
+			.collect::<Vec<_>>()
+			.join(", ");
+
+		writeln!(
+			self,
+			"| {} | {} | {} | {level_sizes} | {} | {} |",
+			map.name(),
+			property(map, ESTIMATE_NUM_KEYS, |keys| keys.to_string()),
+			property(map, SST_SIZE, pretty),
+			property(map, PENDING_COMPACTION_BYTES, pretty),
+			property(map, MEMTABLES_SIZE, pretty),
+		)
+		.await?;
+	}
+
+	Ok(RoomMessageEventContent::notice_plain(""))
+}
+
+#[admin_command]
+pub(super) async fn properties(
+	&self,
+	map: String,
+	property: Option<String>,
+) -> Result<RoomMessageEventContent> {
+	let map = self.services.db.get(&map)?;
+	if let Some(property) = property {
+		let value = map.property(&property)?;
+		return Ok(RoomMessageEventContent::notice_markdown(format!(
+			"```\n{}\n```",
+			value.trim()
+		)));
+	}
+
+	let mut out = String::from("| property | value |\n| :------- | ----: |\n");
+	for property in INTEGER_PROPERTIES {
+		let value = map
+			.property_integer(property)
+			.map_or_else(|_| "-".to_owned(), |value| value.to_string());
+
+		writeln!(out, "| {} | {value} |", property.to_string_lossy())?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+/// Formats an integer property of a map, or "n/a" if it can't be read, so one
+/// map doesn't hide the others.
+fn property(map: &Map, name: &CStr, format: fn(u64) -> String) -> String {
+	map.property_integer(name)
+		.map_or_else(|_| "n/a".to_owned(), format)
+}
+
+fn pretty(bytes: u64) -> String { bytes::pretty(bytes.try_into().unwrap_or(usize::MAX)) }
+
+fn show_key(map: &str, key: &[u8]) -> String {
+	if is_secret_key(map) {
+		return redacted(key);
+	}
+
+	format!("{} {:?}", encode_hex(key), String::from_utf8_lossy(key))
+}
+
+fn show_val(map: &str, val: &[u8]) -> String {
+	if is_secret_val(map) {
+		return redacted(val);
+	}
+
+	format!("{:?}", String::from_utf8_lossy(val))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+	bytes
+		.iter()
+		.fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+			_ = write!(s, "{b:02x}");
+			s
+		})
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+	let hex = hex.trim_start_matches("0x");
+	if hex.len() % 2 != 0 {
+		return Err(err!("Hex string has an odd number of digits."));
+	}
+
+	(0..hex.len())
+		.step_by(2)
+		.map(|i| {
+			hex.get(i..i.saturating_add(2))
+				.and_then(|digits| u8::from_str_radix(digits, 16).ok())
+				.ok_or_else(|| err!("Invalid hex digits at offset {i}."))
+		})
+		.collect()
+}
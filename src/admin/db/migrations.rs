@@ -0,0 +1,44 @@
+use std::fmt::Write;
+
+use clap::Subcommand;
+use conduwuit::Result;
+use ruma::events::room::message::RoomMessageEventContent;
+use service::migrations::{self, MigrationState};
+
+use crate::{admin_command, admin_command_dispatch};
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(crate) enum DbMigrationsCommand {
+	/// - Show the schema version and which migrations are applied
+	///
+	/// Background migrations run while the server is up and resume after a
+	/// restart.
+	Status,
+}
+
+#[admin_command]
+async fn status(&self) -> Result<RoomMessageEventContent> {
+	let (version, statuses) = migrations::status(self.services).await;
+
+	let mut out = format!(
+		"Schema version {version}\n\n| Migration | Runs | State |\n| --- | --- | --- |\n"
+	);
+
+	for status in statuses {
+		let runs = if status.background {
+			"in the background"
+		} else {
+			"at startup"
+		};
+		let state = match status.state {
+			| MigrationState::Pending => "pending",
+			| MigrationState::Running => "in progress",
+			| MigrationState::Applied => "applied",
+		};
+
+		writeln!(out, "| {} | {runs} | {state} |", status.name)?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
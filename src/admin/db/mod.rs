@@ -0,0 +1,98 @@
+mod commands;
+mod migrations;
+
+use clap::Subcommand;
+use conduwuit::Result;
+
+use self::migrations::DbMigrationsCommand;
+use crate::admin_command_dispatch;
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+/// Inspect and maintain the database while the server is running
+///
+/// Keys are given and shown in hex. Secrets such as access tokens and
+/// password hashes are redacted.
+pub(super) enum DbCommand {
+	/// - Get the value of a key in a map
+	Get {
+		/// Map name
+		map: String,
+
+		/// Key, in hex
+		key: String,
+	},
+
+	/// - List the records of a map in key order
+	Scan {
+		/// Map name
+		map: String,
+
+		/// Only records whose key starts with this, in hex
+		#[arg(long)]
+		prefix: Option<String>,
+
+		/// Maximum number of records listed
+		#[arg(short, long, default_value("50"))]
+		limit: usize,
+	},
+
+	/// - Compact maps, e.g. pduid_pdu after a large purge
+	///
+	/// This reclaims the space of deleted records. Every map is compacted
+	/// unless some are given. Same as `query raw compact`.
+	Compact {
+		/// Map name; may be repeated
+		#[arg(short, long, alias("column"), alias("cf"))]
+		map: Option<Vec<String>>,
+
+		/// Lower bound of the keys compacted
+		#[arg(long)]
+		start: Option<String>,
+
+		/// Upper bound of the keys compacted
+		#[arg(long)]
+		stop: Option<String>,
+
+		/// Level compacted from
+		#[arg(long)]
+		from: Option<usize>,
+
+		/// Level compacted into
+		#[arg(long)]
+		into: Option<usize>,
+
+		/// How many maps are compacted in parallel. If zero, one is compacted
+		/// at a time, blocking automatic compactions until complete.
+		#[arg(long)]
+		parallelism: Option<usize>,
+
+		/// Recompact the output of the first pass until nothing is left to
+		/// compact
+		#[arg(long, default_value("false"))]
+		exhaustive: bool,
+	},
+
+	/// - Show the size, level sizes, pending compaction and memtable usage of
+	///   each map
+	Stats {
+		/// Only show this map
+		map: Option<String>,
+	},
+
+	/// - Show the RocksDB properties of a map
+	///
+	/// Without a property, the integer properties are listed. Others, such as
+	/// rocksdb.stats or rocksdb.levelstats, can be requested by name.
+	Properties {
+		/// Map name
+		map: String,
+
+		/// Property name
+		property: Option<String>,
+	},
+
+	#[command(subcommand)]
+	/// - Inspect the database migrations
+	Migrations(DbMigrationsCommand),
+}
@@ -1,8 +1,8 @@
 use std::{
-	collections::HashMap,
+	collections::{BTreeMap, HashMap, HashSet},
 	fmt::Write,
 	iter::once,
-	time::{Instant, SystemTime},
+	time::{Duration, Instant, SystemTime},
 };
 
 use conduwuit::{
@@ -11,21 +11,25 @@
 		stream::{IterStream, ReadyExt},
 		string::EMPTY,
 	},
-	warn, Error, PduEvent, PduId, RawPduId, Result,
+	warn, Err, Error, PduEvent, PduId, RawPduId, Result,
 };
 use futures::{FutureExt, StreamExt, TryStreamExt};
 use ruma::{
 	api::{client::error::ErrorKind, federation::event::get_room_state},
-	events::room::message::RoomMessageEventContent,
+	events::{room::message::RoomMessageEventContent, StateEventType},
 	CanonicalJsonObject, EventId, OwnedEventId, OwnedRoomOrAliasId, RoomId, RoomVersionId,
 	ServerName,
 };
-use service::rooms::{
-	short::{ShortEventId, ShortRoomId},
-	state_compressor::HashSetCompressStateEvent,
+use service::{
+	rooms::{
+		short::{ShortEventId, ShortRoomId, ShortStateHash, ShortStateKey},
+		state_compressor::HashSetCompressStateEvent,
+	},
+	Services,
 };
 use tracing_subscriber::EnvFilter;
 
+use super::GraphFormat;
 use crate::admin_command;
 
 #[admin_command]
@@ -135,7 +139,17 @@ pub(super) async fn get_pdu(&self, event_id: Box<EventId>) -> Result<RoomMessage
 				json_text
 			)))
 		},
-		| Err(_) => Ok(RoomMessageEventContent::text_plain("PDU not found locally.")),
+		| Err(_) => match self
+			.services
+			.rooms
+			.outlier
+			.get_rejection_reason(&event_id)
+			.await
+		{
+			| Ok(reason) =>
+				Ok(RoomMessageEventContent::text_plain(format!("PDU was rejected: {reason}"))),
+			| Err(_) => Ok(RoomMessageEventContent::text_plain("PDU not found locally.")),
+		},
 	}
 }
 
@@ -422,7 +436,10 @@ pub(super) async fn change_log_level(
 	let handles = &["console"];
 
 	if reset {
-		let old_filter_layer = match EnvFilter::try_new(&self.services.server.config.log) {
+		let old_filter_layer = match EnvFilter::builder()
+			.with_regex(self.services.server.config.log_filter_regex)
+			.parse(&self.services.server.config.log)
+		{
 			| Ok(s) => s,
 			| Err(e) => {
 				return Ok(RoomMessageEventContent::text_plain(format!(
@@ -453,7 +470,10 @@ pub(super) async fn change_log_level(
 	}
 
 	if let Some(filter) = filter {
-		let new_filter_layer = match EnvFilter::try_new(filter) {
+		let new_filter_layer = match EnvFilter::builder()
+			.with_regex(self.services.server.config.log_filter_regex)
+			.parse(filter)
+		{
 			| Ok(s) => s,
 			| Err(e) => {
 				return Ok(RoomMessageEventContent::text_plain(format!(
@@ -480,7 +500,17 @@ pub(super) async fn change_log_level(
 		}
 	}
 
-	Ok(RoomMessageEventContent::text_plain("No log level was specified."))
+	let current = self
+		.services
+		.server
+		.log
+		.reload
+		.current(handles[0])
+		.map_or_else(|| self.services.server.config.log.clone(), |filter| filter.to_string());
+
+	Ok(RoomMessageEventContent::notice_markdown(format!(
+		"Current log filter: `{current}`"
+	)))
 }
 
 #[admin_command]
@@ -816,6 +846,169 @@ pub(super) async fn get_verify_keys(
 	Ok(RoomMessageEventContent::notice_markdown(out))
 }
 
+#[admin_command]
+pub(super) async fn rejected(
+	&self,
+	room_id: OwnedRoomOrAliasId,
+	limit: usize,
+	json: bool,
+) -> Result<RoomMessageEventContent> {
+	let room_id = self.services.rooms.alias.resolve(&room_id).await?;
+	let rejected: Vec<_> = self
+		.services
+		.rooms
+		.outlier
+		.rejected_pdus(&room_id)
+		.take(limit)
+		.collect()
+		.await;
+
+	if rejected.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain(
+			"No rejected events are recorded for this room.",
+		));
+	}
+
+	let mut out = format!("Most recent {} rejected events in {room_id}:\n", rejected.len());
+	for pdu in rejected {
+		let rejected_at = Duration::from_millis(pdu.rejected_at);
+		let rejected_at = utils::time::format(SystemTime::UNIX_EPOCH + rejected_at, "%+");
+		writeln!(out, "- `{}` at {rejected_at}: {}", pdu.event_id, pdu.reason)?;
+
+		if json {
+			let json = serde_json::to_string_pretty(&pdu.pdu)?;
+			writeln!(out, "```json\n{json}\n```")?;
+		}
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+pub(super) async fn event_graph(
+	&self,
+	room_id: OwnedRoomOrAliasId,
+	limit: usize,
+	format: GraphFormat,
+) -> Result<RoomMessageEventContent> {
+	let room_id = self.services.rooms.alias.resolve(&room_id).await?;
+	let extremities: HashSet<OwnedEventId> = self
+		.services
+		.rooms
+		.state
+		.get_forward_extremities(&room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let pdus: Vec<PduEvent> = self
+		.services
+		.rooms
+		.timeline
+		.pdus_rev(None, &room_id, None)
+		.ready_filter_map(Result::ok)
+		.map(|(_, pdu)| pdu)
+		.take(limit)
+		.collect()
+		.await;
+
+	if pdus.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No events found in this room."));
+	}
+
+	let out = match format {
+		| GraphFormat::Dot => event_graph_dot(&room_id, &pdus, &extremities)?,
+		| GraphFormat::Json => event_graph_json(&room_id, &pdus, &extremities)?,
+	};
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+fn event_graph_dot(
+	room_id: &RoomId,
+	pdus: &[PduEvent],
+	extremities: &HashSet<OwnedEventId>,
+) -> Result<String> {
+	let in_graph: HashSet<&EventId> = pdus.iter().map(|pdu| &*pdu.event_id).collect();
+
+	let mut out = format!(
+		"Last {} events of {room_id}; forward extremities are bold, state events filled, events \
+		 outside the export dashed:\n```dot\ndigraph \"{}\" {{\n\tnode [shape=box];\n",
+		pdus.len(),
+		dot_escape(room_id.as_str()),
+	);
+
+	for pdu in pdus {
+		let event_id = dot_escape(pdu.event_id.as_str());
+		let mut label =
+			format!("{event_id}\\ndepth {} {}", pdu.depth, dot_escape(&pdu.kind.to_string()));
+		if let Some(state_key) = &pdu.state_key {
+			write!(label, " {}", dot_escape(state_key))?;
+		}
+		write!(label, "\\n{}", dot_escape(pdu.sender.as_str()))?;
+
+		let mut style = Vec::new();
+		if pdu.state_key.is_some() {
+			style.push("filled");
+		}
+		if extremities.contains(&pdu.event_id) {
+			style.push("bold");
+		}
+
+		writeln!(out, "\t\"{event_id}\" [label=\"{label}\", style=\"{}\"];", style.join(","))?;
+	}
+
+	for pdu in pdus {
+		let event_id = dot_escape(pdu.event_id.as_str());
+		for prev in &pdu.prev_events {
+			let prev_id = dot_escape(prev.as_str());
+			if !in_graph.contains(&**prev) {
+				writeln!(out, "\t\"{prev_id}\" [style=dashed];")?;
+			}
+			writeln!(out, "\t\"{event_id}\" -> \"{prev_id}\";")?;
+		}
+	}
+
+	out.push_str("}\n```");
+
+	Ok(out)
+}
+
+/// Escapes a string for a quoted DOT identifier or label. Event IDs of old
+/// room versions, event types and state keys may contain anything.
+fn dot_escape(s: &str) -> String { s.replace('\\', "\\\\").replace('"', "\\\"") }
+
+fn event_graph_json(
+	room_id: &RoomId,
+	pdus: &[PduEvent],
+	extremities: &HashSet<OwnedEventId>,
+) -> Result<String> {
+	let events: Vec<_> = pdus
+		.iter()
+		.map(|pdu| {
+			serde_json::json!({
+				"event_id": pdu.event_id,
+				"prev_events": pdu.prev_events,
+				"depth": pdu.depth,
+				"type": pdu.kind,
+				"sender": pdu.sender,
+				"state_key": pdu.state_key,
+				"forward_extremity": extremities.contains(&pdu.event_id),
+			})
+		})
+		.collect();
+
+	let graph = serde_json::json!({
+		"room_id": room_id,
+		"forward_extremities": extremities,
+		"events": events,
+	});
+
+	let json = serde_json::to_string_pretty(&graph)?;
+
+	Ok(format!("Last {} events of {room_id}:\n```json\n{json}\n```", pdus.len()))
+}
+
 #[admin_command]
 pub(super) async fn resolve_true_destination(
 	&self,
@@ -1013,3 +1206,141 @@ pub(super) async fn trim_memory(&self) -> Result<RoomMessageEventContent> {
 
 	Ok(RoomMessageEventContent::notice_plain(""))
 }
+
+#[admin_command]
+pub(super) async fn room_state(
+	&self,
+	room_id: OwnedRoomOrAliasId,
+	event_id: Option<OwnedEventId>,
+) -> Result<RoomMessageEventContent> {
+	let room_id = self.services.rooms.alias.resolve(&room_id).await?;
+	let shortstatehash = match &event_id {
+		| Some(event_id) => {
+			let pdu = self.services.rooms.timeline.get_pdu(event_id).await?;
+			if pdu.room_id != room_id {
+				return Err!("Event {event_id} is in {}, not {room_id}.", pdu.room_id);
+			}
+
+			self.services
+				.rooms
+				.state_accessor
+				.pdu_shortstatehash(event_id)
+				.await?
+		},
+		| None =>
+			self.services
+				.rooms
+				.state
+				.get_room_shortstatehash(&room_id)
+				.await?,
+	};
+
+	let state = state_snapshot(self.services, shortstatehash).await;
+
+	let mut out = format!(
+		"State of {room_id} at shortstatehash {shortstatehash} ({} entries):\n```\n",
+		state.len()
+	);
+	for (shortstatekey, entry) in &state {
+		writeln!(out, "{shortstatekey}\t{entry}")?;
+	}
+	out.push_str("```");
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+pub(super) async fn state_diff(
+	&self,
+	event_a: OwnedEventId,
+	event_b: OwnedEventId,
+) -> Result<RoomMessageEventContent> {
+	let state_accessor = &self.services.rooms.state_accessor;
+	let shortstatehash_a = state_accessor.pdu_shortstatehash(&event_a).await?;
+	let shortstatehash_b = state_accessor.pdu_shortstatehash(&event_b).await?;
+
+	let state_a = state_snapshot(self.services, shortstatehash_a).await;
+	let mut state_b = state_snapshot(self.services, shortstatehash_b).await;
+
+	let mut out = format!(
+		"State diff from {event_a} (shortstatehash {shortstatehash_a}) to {event_b} \
+		 (shortstatehash {shortstatehash_b}):\n```\n"
+	);
+
+	let mut changes: usize = 0;
+	for (shortstatekey, entry_a) in &state_a {
+		match state_b.remove(shortstatekey) {
+			| Some(entry_b) if entry_b.event_id == entry_a.event_id => continue,
+			| Some(entry_b) => {
+				writeln!(out, "~ {shortstatekey}\t{entry_a}\n  -> {entry_b}")?;
+			},
+			| None => writeln!(out, "- {shortstatekey}\t{entry_a}")?,
+		}
+
+		changes = changes.saturating_add(1);
+	}
+
+	for (shortstatekey, entry_b) in &state_b {
+		writeln!(out, "+ {shortstatekey}\t{entry_b}")?;
+		changes = changes.saturating_add(1);
+	}
+
+	if changes == 0 {
+		out.push_str("(no differences)\n");
+	}
+	out.push_str("```");
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+struct StateEntry {
+	kind: StateEventType,
+	state_key: String,
+	event_id: OwnedEventId,
+	origin: Option<String>,
+}
+
+impl std::fmt::Display for StateEntry {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let origin = self.origin.as_deref().unwrap_or("?");
+		write!(f, "({}, {:?})\t{}\t{origin}", self.kind, self.state_key, self.event_id)
+	}
+}
+
+/// Loads a full state snapshot keyed by shortstatekey, resolving each entry's
+/// type, state key and the server of its sender.
+async fn state_snapshot(
+	services: &Services,
+	shortstatehash: ShortStateHash,
+) -> BTreeMap<ShortStateKey, StateEntry> {
+	let ids: Vec<(ShortStateKey, OwnedEventId)> = services
+		.rooms
+		.state_accessor
+		.state_full_ids(shortstatehash)
+		.collect()
+		.await;
+
+	let mut state = BTreeMap::new();
+	for (shortstatekey, event_id) in ids {
+		let Ok((kind, state_key)) = services
+			.rooms
+			.short
+			.get_statekey_from_short(shortstatekey)
+			.await
+		else {
+			continue;
+		};
+
+		let origin = services
+			.rooms
+			.timeline
+			.get_pdu(&event_id)
+			.await
+			.ok()
+			.map(|pdu| pdu.sender.server_name().to_string());
+
+		state.insert(shortstatekey, StateEntry { kind, state_key, event_id, origin });
+	}
+
+	state
+}
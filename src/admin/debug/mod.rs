@@ -1,9 +1,9 @@
 mod commands;
 pub(crate) mod tester;
 
-use clap::Subcommand;
+use clap::{Subcommand, ValueEnum};
 use conduwuit::Result;
-use ruma::{EventId, OwnedRoomOrAliasId, RoomId, ServerName};
+use ruma::{EventId, OwnedEventId, OwnedRoomOrAliasId, RoomId, ServerName};
 use service::rooms::short::{ShortEventId, ShortRoomId};
 
 use self::tester::TesterCommand;
@@ -115,7 +115,11 @@ pub(super) enum DebugCommand {
 
 	/// - Change tracing log level/filter on the fly
 	///
-	/// This accepts the same format as the `log` config option.
+	/// This accepts the same format as the `log` config option, including
+	/// per-target directives such as `info,conduwuit_service::sending=debug`.
+	/// Without a filter, the current one is shown. The change lasts until the
+	/// next restart.
+	#[clap(alias = "log-level")]
 	ChangeLogLevel {
 		/// Log level/filter
 		filter: Option<String>,
@@ -179,6 +183,55 @@ pub(super) enum DebugCommand {
 		server_name: Box<ServerName>,
 	},
 
+	/// - Prints the full state of a room, or the state before an event in it
+	///
+	/// Every entry is listed with its shortstatekey and the server of the
+	/// event's sender.
+	RoomState {
+		room_id: OwnedRoomOrAliasId,
+
+		/// Print the state before this event instead of the current state
+		event_id: Option<OwnedEventId>,
+	},
+
+	/// - Prints the difference between the states before two events
+	StateDiff {
+		event_a: OwnedEventId,
+		event_b: OwnedEventId,
+	},
+
+	/// - List the most recently rejected events in a room along with the reason
+	///   each was rejected
+	Rejected {
+		room_id: OwnedRoomOrAliasId,
+
+		/// Maximum number of rejected events to list
+		#[arg(short, long, default_value("25"))]
+		limit: usize,
+
+		/// Include the JSON of each rejected event
+		#[arg(short, long)]
+		json: bool,
+	},
+
+	/// - Export the recent event graph of a room for visualization
+	///
+	/// Lists the most recent events of the timeline with their prev_events
+	/// edges, depth and whether they are state events or forward
+	/// extremities, as Graphviz DOT (render with e.g. `dot -Tsvg`) or JSON.
+	/// Useful to see where our copy of a room diverged from other servers.
+	EventGraph {
+		room_id: OwnedRoomOrAliasId,
+
+		/// Maximum number of events to export
+		#[arg(short, long, default_value("500"))]
+		limit: usize,
+
+		/// Output format
+		#[arg(short, long, value_enum, default_value_t = GraphFormat::Dot)]
+		format: GraphFormat,
+	},
+
 	/// - Runs a server name through conduwuit's true destination resolution
 	///   process
 	///
@@ -240,3 +293,12 @@ pub(super) enum DebugCommand {
 	#[clap(hide(true))]
 	Tester(TesterCommand),
 }
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub(crate) enum GraphFormat {
+	/// Graphviz DOT
+	Dot,
+
+	/// JSON
+	Json,
+}
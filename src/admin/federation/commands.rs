@@ -1,9 +1,10 @@
-use std::fmt::Write;
+use std::{cmp::Reverse, fmt::Write};
 
 use conduwuit::Result;
 use futures::StreamExt;
 use ruma::{
-	events::room::message::RoomMessageEventContent, OwnedRoomId, RoomId, ServerName, UserId,
+	events::room::message::RoomMessageEventContent, OwnedRoomId, OwnedServerName, RoomId,
+	ServerName, UserId,
 };
 
 use crate::{admin_command, get_room_info};
@@ -39,6 +40,40 @@ pub(super) async fn incoming_federation(&self) -> Result<RoomMessageEventContent
 	Ok(RoomMessageEventContent::text_plain(&msg))
 }
 
+#[admin_command]
+pub(super) async fn incoming_transactions(
+	&self,
+	server_name: Option<OwnedServerName>,
+) -> Result<RoomMessageEventContent> {
+	let mut origins: Vec<_> = self
+		.services
+		.rate_limiting
+		.origin_stats()
+		.into_iter()
+		.filter(|(origin, _)| server_name.as_ref().is_none_or(|server| server == origin))
+		.collect();
+
+	if origins.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No transactions received."));
+	}
+
+	origins.sort_by_key(|(_, stats)| Reverse(stats.accepted));
+
+	let mut msg = String::from(
+		"| Origin | Active | Accepted | Rate limited | Concurrency limited |\n| --- | --- | --- \
+		 | --- | --- |\n",
+	);
+	for (origin, stats) in &origins {
+		writeln!(
+			msg,
+			"| {origin} | {} | {} | {} | {} |",
+			stats.active, stats.accepted, stats.rate_limited, stats.concurrency_limited
+		)?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(msg))
+}
+
 #[admin_command]
 pub(super) async fn fetch_support_well_known(
 	&self,
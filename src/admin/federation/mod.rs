@@ -2,7 +2,7 @@
 
 use clap::Subcommand;
 use conduwuit::Result;
-use ruma::{RoomId, ServerName, UserId};
+use ruma::{OwnedServerName, RoomId, ServerName, UserId};
 
 use crate::admin_command_dispatch;
 
@@ -12,6 +12,12 @@ pub(super) enum FederationCommand {
 	/// - List all rooms we are currently handling an incoming pdu from
 	IncomingFederation,
 
+	/// - Show how many transactions each remote server has had accepted or
+	///   rejected by the transaction rate and concurrency limits, busiest first
+	IncomingTransactions {
+		server_name: Option<OwnedServerName>,
+	},
+
 	/// - Disables incoming federation handling for a room.
 	DisableRoom {
 		room_id: Box<RoomId>,
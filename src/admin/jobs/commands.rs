@@ -0,0 +1,67 @@
+use std::{
+	fmt::Write,
+	time::{Duration, UNIX_EPOCH},
+};
+
+use conduwuit::{
+	utils::{time::format, ReadyExt},
+	Result,
+};
+use futures::StreamExt;
+use ruma::events::room::message::RoomMessageEventContent;
+use service::jobs::Job;
+
+use crate::admin_command;
+
+#[admin_command]
+pub(super) async fn list(&self, all: bool) -> Result<RoomMessageEventContent> {
+	let jobs: Vec<Job> = self
+		.services
+		.jobs
+		.jobs()
+		.ready_filter(|job| all || job.status.is_pending())
+		.collect()
+		.await;
+
+	if jobs.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No background jobs."));
+	}
+
+	let mut msg = format!("Background jobs ({}):\n```\n", jobs.len());
+	for job in &jobs {
+		writeln!(msg, "{}", summary(job))?;
+	}
+	msg.push_str("```");
+
+	Ok(RoomMessageEventContent::notice_markdown(msg))
+}
+
+#[admin_command]
+pub(super) async fn status(&self, id: u64) -> Result<RoomMessageEventContent> {
+	let job = self.services.jobs.get_job(id).await?;
+	let created = format(UNIX_EPOCH + Duration::from_millis(job.created), "%+");
+
+	let report: String = job.report.iter().map(|line| format!("\n{line}")).collect();
+
+	Ok(RoomMessageEventContent::notice_markdown(format!(
+		"```\n{}\ncreated: {created}\nfailed items: {}{report}\n```",
+		summary(&job),
+		job.failed,
+	)))
+}
+
+#[admin_command]
+pub(super) async fn cancel(&self, id: u64) -> Result<RoomMessageEventContent> {
+	self.services.jobs.cancel(id).await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!("Cancelling job {id}.")))
+}
+
+fn summary(job: &Job) -> String {
+	let progress = match job.total {
+		| Some(total) => format!("{}/{total}", job.done),
+		| None => "-".to_owned(),
+	};
+
+	format!("{:>8} {progress:>12}  {}  ({})", job.id, job.kind, job.status)
+}
@@ -0,0 +1,30 @@
+mod commands;
+
+use clap::Subcommand;
+use conduwuit::Result;
+
+use crate::admin_command_dispatch;
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(super) enum JobsCommand {
+	/// - List all background jobs and their progress
+	List {
+		/// Also list completed, failed and cancelled jobs
+		#[arg(short, long)]
+		all: bool,
+	},
+
+	/// - Show the status and progress of a background job
+	Status {
+		id: u64,
+	},
+
+	/// - Cancel a queued or running background job
+	///
+	/// A running job stops before its next item; work already done is not
+	/// undone.
+	Cancel {
+		id: u64,
+	},
+}
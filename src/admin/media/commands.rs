@@ -1,16 +1,22 @@
-use std::time::Duration;
+use std::{fmt::Write, path::PathBuf, time::Duration};
 
 use conduwuit::{
-	debug, debug_info, debug_warn, error, info, trace, utils::time::parse_timepoint_ago, Result,
+	debug, debug_info, debug_warn, info, trace,
+	utils::time::{now_millis, parse_duration},
+	Result,
 };
-use conduwuit_service::media::Dim;
+use conduwuit_service::{jobs::JobKind, media::Dim};
+use futures::StreamExt;
 use ruma::{
 	events::room::message::RoomMessageEventContent, EventId, Mxc, MxcUri, OwnedMxcUri,
-	OwnedServerName, ServerName,
+	OwnedServerName, OwnedUserId, ServerName,
 };
 
 use crate::{admin_command, utils::parse_local_user_id};
 
+/// Default manifest location, in the database directory.
+const MANIFEST_FILE_NAME: &str = "media-manifest.txt";
+
 #[admin_command]
 pub(super) async fn delete(
 	&self,
@@ -192,41 +198,25 @@ pub(super) async fn delete_list(&self) -> Result<RoomMessageEventContent> {
 
 	let mut failed_parsed_mxcs: usize = 0;
 
-	let mxc_list = self
-		.body
-		.to_vec()
-		.drain(1..self.body.len().checked_sub(1).unwrap())
+	let mxcs = self.body[1..self.body.len().saturating_sub(1)]
+		.iter()
 		.filter_map(|mxc_s| {
-			mxc_s
-				.try_into()
-				.inspect_err(|e| {
-					debug_warn!("Failed to parse user-provided MXC URI: {e}");
-
-					failed_parsed_mxcs = failed_parsed_mxcs.saturating_add(1);
-				})
-				.ok()
+			let mxc = OwnedMxcUri::from(*mxc_s);
+			if mxc.validate().is_err() {
+				debug_warn!("Failed to parse user-provided MXC URI: {mxc}");
+				failed_parsed_mxcs = failed_parsed_mxcs.saturating_add(1);
+				return None;
+			}
+
+			Some(mxc)
 		})
-		.collect::<Vec<Mxc<'_>>>();
-
-	let mut mxc_deletion_count: usize = 0;
-
-	for mxc in &mxc_list {
-		trace!(%failed_parsed_mxcs, %mxc_deletion_count, "Deleting MXC {mxc} in bulk");
-		match self.services.media.delete(mxc).await {
-			| Ok(()) => {
-				debug_info!("Successfully deleted {mxc} from filesystem and database");
-				mxc_deletion_count = mxc_deletion_count.saturating_add(1);
-			},
-			| Err(e) => {
-				debug_warn!("Failed to delete {mxc}, ignoring error and skipping: {e}");
-				continue;
-			},
-		}
-	}
+		.collect();
+
+	let id = self.services.jobs.enqueue(JobKind::DeleteMedia { mxcs })?;
 
 	Ok(RoomMessageEventContent::text_plain(format!(
-		"Finished bulk MXC deletion, deleted {mxc_deletion_count} total MXCs from our database \
-		 and the filesystem. {failed_parsed_mxcs} MXCs failed to be parsed from the database.",
+		"Queued bulk MXC deletion as job {id}. {failed_parsed_mxcs} MXCs failed to be parsed. \
+		 Use `jobs status {id}` to follow its progress.",
 	)))
 }
 
@@ -245,20 +235,19 @@ pub(super) async fn delete_past_remote_media(
 	}
 	assert!(!(before && after), "--before and --after should not be specified together");
 
-	let duration = parse_timepoint_ago(&duration)?;
-	let deleted_count = self
+	let duration: u64 = parse_duration(&duration)?.as_millis().try_into()?;
+	let id = self
 		.services
-		.media
-		.delete_all_remote_media_at_after_time(
-			duration,
+		.jobs
+		.enqueue(JobKind::DeleteMediaAtAfterTime {
+			time: now_millis().saturating_sub(duration),
 			before,
 			after,
-			yes_i_want_to_delete_local_media,
-		)
-		.await?;
+			include_local: yes_i_want_to_delete_local_media,
+		})?;
 
 	Ok(RoomMessageEventContent::text_plain(format!(
-		"Deleted {deleted_count} total files.",
+		"Queued media deletion as job {id}. Use `jobs status {id}` to follow its progress.",
 	)))
 }
 
@@ -269,10 +258,13 @@ pub(super) async fn delete_all_from_user(
 ) -> Result<RoomMessageEventContent> {
 	let user_id = parse_local_user_id(self.services, &username)?;
 
-	let deleted_count = self.services.media.delete_from_user(&user_id).await?;
+	let id = self
+		.services
+		.jobs
+		.enqueue(JobKind::DeleteUserMedia { user_id })?;
 
 	Ok(RoomMessageEventContent::text_plain(format!(
-		"Deleted {deleted_count} total files.",
+		"Queued media deletion as job {id}. Use `jobs status {id}` to follow its progress.",
 	)))
 }
 
@@ -282,59 +274,117 @@ pub(super) async fn delete_all_from_server(
 	server_name: Box<ServerName>,
 	yes_i_want_to_delete_local_media: bool,
 ) -> Result<RoomMessageEventContent> {
-	if server_name == self.services.globals.server_name() && !yes_i_want_to_delete_local_media {
+	if self.services.globals.server_is_ours(&server_name) && !yes_i_want_to_delete_local_media {
 		return Ok(RoomMessageEventContent::text_plain(
 			"This command only works for remote media by default.",
 		));
 	}
 
-	let Ok(all_mxcs) = self
+	let id = self
+		.services
+		.jobs
+		.enqueue(JobKind::DeleteServerMedia { server_name: server_name.into() })?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Queued media deletion as job {id}. Use `jobs status {id}` to follow its progress.",
+	)))
+}
+
+#[admin_command]
+pub(super) async fn usage(&self, limit: usize) -> Result<RoomMessageEventContent> {
+	let users: Vec<OwnedUserId> = self
+		.services
+		.users
+		.list_local_users()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let mut usage = Vec::with_capacity(users.len());
+	for user_id in users {
+		let bytes = self.services.media.media_usage(&user_id).await;
+		if bytes > 0 {
+			usage.push((user_id, bytes));
+		}
+	}
+
+	usage.sort_by(|a, b| b.1.cmp(&a.1));
+
+	let mut out = format!("Media usage of {} users:\n```\n", usage.len());
+	for (user_id, bytes) in usage.iter().take(limit) {
+		writeln!(out, "{bytes:>16} {user_id}")?;
+	}
+	out.push_str("```");
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+pub(super) async fn list_quarantined(&self) -> Result<RoomMessageEventContent> {
+	let quarantined = self.services.media.quarantined_media().await;
+
+	let mut out = format!("{} quarantined uploads:\n```\n", quarantined.len());
+	for (mxc, entry) in &quarantined {
+		writeln!(out, "{mxc} {} {}", entry.user_id, entry.signature)?;
+	}
+	out.push_str("```");
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+pub(super) async fn release_quarantined(
+	&self,
+	mxc: OwnedMxcUri,
+) -> Result<RoomMessageEventContent> {
+	let entry = self
 		.services
 		.media
-		.get_all_mxcs()
-		.await
-		.inspect_err(|e| error!("Failed to get MXC URIs from our database: {e}"))
-	else {
+		.release_quarantined(&mxc.as_str().try_into()?)
+		.await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Released {mxc}, uploaded by {} and flagged as {}.",
+		entry.user_id, entry.signature
+	)))
+}
+
+#[admin_command]
+pub(super) async fn purge_remote_media(
+	&self,
+	unused_for: Option<String>,
+	larger_than: Option<u64>,
+) -> Result<RoomMessageEventContent> {
+	if unused_for.is_none() && larger_than.is_none() {
 		return Ok(RoomMessageEventContent::text_plain(
-			"Failed to get MXC URIs from our database",
+			"Please pick at least one of --unused-for or --larger-than.",
 		));
-	};
-
-	let mut deleted_count: usize = 0;
-
-	for mxc in all_mxcs {
-		let Ok(mxc_server_name) = mxc.server_name().inspect_err(|e| {
-			debug_warn!(
-				"Failed to parse MXC {mxc} server name from database, ignoring error and \
-				 skipping: {e}"
-			);
-		}) else {
-			continue;
-		};
-
-		if mxc_server_name != server_name
-			|| (self.services.globals.server_is_ours(mxc_server_name)
-				&& !yes_i_want_to_delete_local_media)
-		{
-			trace!("skipping MXC URI {mxc}");
-			continue;
-		}
+	}
 
-		let mxc: Mxc<'_> = mxc.as_str().try_into()?;
+	let cutoff = unused_for
+		.as_deref()
+		.map(parse_duration)
+		.transpose()?
+		.map(|duration| duration.as_millis().try_into())
+		.transpose()?
+		.map(|duration: u64| now_millis().saturating_sub(duration));
 
-		match self.services.media.delete(&mxc).await {
-			| Ok(()) => {
-				deleted_count = deleted_count.saturating_add(1);
-			},
-			| Err(e) => {
-				debug_warn!("Failed to delete {mxc}, ignoring error and skipping: {e}");
-				continue;
-			},
-		}
+	let mxcs = self
+		.services
+		.media
+		.unused_remote_media(cutoff, larger_than)
+		.await?;
+
+	if mxcs.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No remote media matched."));
 	}
 
+	let count = mxcs.len();
+	let id = self.services.jobs.enqueue(JobKind::DeleteMedia { mxcs })?;
+
 	Ok(RoomMessageEventContent::text_plain(format!(
-		"Deleted {deleted_count} total files.",
+		"Queued deletion of {count} remote media files as job {id}. Use `jobs status {id}` to \
+		 follow its progress.",
 	)))
 }
 
@@ -394,3 +444,59 @@ pub(super) async fn get_remote_thumbnail(
 	let out = format!("```\n{result:#?}\nreceived {len} bytes for file content.\n```");
 	Ok(RoomMessageEventContent::notice_markdown(out))
 }
+
+#[admin_command]
+pub(super) async fn create_manifest(
+	&self,
+	output: Option<PathBuf>,
+) -> Result<RoomMessageEventContent> {
+	let output = output.unwrap_or_else(|| {
+		self.services
+			.server
+			.config
+			.database_path
+			.join(MANIFEST_FILE_NAME)
+	});
+	let count = self.services.media.write_manifest(&output).await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Recorded {count} media files in {}.",
+		output.display()
+	)))
+}
+
+#[admin_command]
+pub(super) async fn verify_manifest(
+	&self,
+	manifest: Option<PathBuf>,
+) -> Result<RoomMessageEventContent> {
+	let manifest = manifest.unwrap_or_else(|| {
+		self.services
+			.server
+			.config
+			.database_path
+			.join(MANIFEST_FILE_NAME)
+	});
+	let report = self.services.media.verify_manifest(&manifest).await?;
+
+	let mut out = format!(
+		"Checked {} media files against {}: {} missing, {} changed, {} not in the manifest.\n",
+		report.checked,
+		manifest.display(),
+		report.missing.len(),
+		report.mismatched.len(),
+		report.unlisted.len()
+	);
+
+	for (label, files) in [
+		("Missing", &report.missing),
+		("Changed", &report.mismatched),
+		("Not in the manifest", &report.unlisted),
+	] {
+		if !files.is_empty() {
+			writeln!(out, "\n{label}:\n```\n{}\n```", files.join("\n"))?;
+		}
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
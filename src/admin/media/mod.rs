@@ -1,5 +1,7 @@
 mod commands;
 
+use std::path::PathBuf;
+
 use clap::Subcommand;
 use conduwuit::Result;
 use ruma::{EventId, MxcUri, OwnedMxcUri, OwnedServerName, ServerName};
@@ -11,6 +13,7 @@
 pub(super) enum MediaCommand {
 	/// - Deletes a single media file from our database and on the filesystem
 	///   via a single MXC URL or event ID (not redacted)
+	#[moderator]
 	Delete {
 		/// The MXC URL to delete
 		#[arg(long)]
@@ -23,13 +26,13 @@ pub(super) enum MediaCommand {
 	},
 
 	/// - Deletes a codeblock list of MXC URLs from our database and on the
-	///   filesystem. This will always ignore errors.
+	///   filesystem. This will always ignore errors. Runs as a background job.
 	DeleteList,
 
 	/// - Deletes all remote (and optionally local) media created before or
 	///   after \[duration] time using filesystem metadata first created at
 	///   date, or fallback to last modified date. This will always ignore
-	///   errors by default.
+	///   errors by default. Runs as a background job.
 	DeletePastRemoteMedia {
 		/// - The relative time (e.g. 30s, 5m, 7d) within which to search
 		duration: String,
@@ -48,13 +51,13 @@ pub(super) enum MediaCommand {
 	},
 
 	/// - Deletes all the local media from a local user on our server. This will
-	///   always ignore errors by default.
+	///   always ignore errors by default. Runs as a background job.
 	DeleteAllFromUser {
 		username: String,
 	},
 
 	/// - Deletes all remote media from the specified remote server. This will
-	///   always ignore errors by default.
+	///   always ignore errors by default. Runs as a background job.
 	DeleteAllFromServer {
 		server_name: Box<ServerName>,
 
@@ -63,6 +66,37 @@ pub(super) enum MediaCommand {
 		yes_i_want_to_delete_local_media: bool,
 	},
 
+	/// - Lists the local users using the most space for uploaded media
+	Usage {
+		#[arg(short, long, default_value("20"))]
+		limit: usize,
+	},
+
+	/// - Lists uploads quarantined by the content scanner
+	#[moderator]
+	ListQuarantined,
+
+	/// - Makes a quarantined upload downloadable again, e.g. after a false
+	///   positive of the content scanner
+	#[moderator]
+	ReleaseQuarantined {
+		mxc: OwnedMxcUri,
+	},
+
+	/// - Deletes remote media not downloaded from this server within
+	///   \[duration] (e.g. 30d), and/or taking at least \[larger_than] bytes.
+	///   Runs as a background job.
+	PurgeRemoteMedia {
+		/// - The relative time (e.g. 30s, 5m, 7d) of the last download
+		#[arg(long)]
+		unused_for: Option<String>,
+
+		/// - Minimum size in bytes
+		#[arg(long)]
+		larger_than: Option<u64>,
+	},
+
+	#[moderator]
 	GetFileInfo {
 		/// The MXC URL to lookup info for.
 		mxc: OwnedMxcUri,
@@ -95,4 +129,18 @@ pub(super) enum MediaCommand {
 		#[arg(short, long, default_value("800"))]
 		height: u32,
 	},
+
+	/// - Writes the path, SHA-256 hash and size of every file in the media
+	///   directory to a manifest file
+	///
+	/// Defaults to `media-manifest.txt` in the database directory.
+	CreateManifest {
+		output: Option<PathBuf>,
+	},
+
+	/// - Checks the media directory against a manifest for missing, changed or
+	///   unlisted files
+	VerifyManifest {
+		manifest: Option<PathBuf>,
+	},
 }
@@ -6,15 +6,19 @@
 pub(crate) mod admin;
 pub(crate) mod command;
 pub(crate) mod processor;
+pub(crate) mod registry;
 mod tests;
 pub(crate) mod utils;
 
 pub(crate) mod appservice;
 pub(crate) mod check;
+pub(crate) mod db;
 pub(crate) mod debug;
 pub(crate) mod federation;
+pub(crate) mod jobs;
 pub(crate) mod media;
 pub(crate) mod query;
+pub(crate) mod reports;
 pub(crate) mod room;
 pub(crate) mod server;
 pub(crate) mod user;
@@ -33,6 +37,21 @@
 
 pub(crate) const PAGE_SIZE: usize = 100;
 
+/// Permission tier required to run a command. Implemented by
+/// `admin_command_dispatch`, where commands marked `#[moderator]` are
+/// available to moderator admins; commands dispatched by hand are for owners.
+pub(crate) trait Tiered {
+	fn tier(&self) -> service::admin::Tier { service::admin::Tier::Owner }
+
+	/// The commands of this group and the tier each requires.
+	fn registry() -> registry::Registry
+	where
+		Self: Sized,
+	{
+		vec![(Vec::new(), service::admin::Tier::Owner)]
+	}
+}
+
 conduwuit::mod_ctor! {}
 conduwuit::mod_dtor! {}
 conduwuit::rustc_flags_capture! {}
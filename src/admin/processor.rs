@@ -33,7 +33,7 @@
 use tracing::Level;
 use tracing_subscriber::{filter::LevelFilter, EnvFilter};
 
-use crate::{admin, admin::AdminCommand, Command};
+use crate::{admin, admin::AdminCommand, registry, Command, Tiered};
 
 #[must_use]
 pub(super) fn complete(line: &str) -> String { complete_command(AdminCommand::command(), line) }
@@ -58,6 +58,14 @@ async fn process_command(services: Arc<Services>, input: &CommandInput) -> Proce
 		| Ok(parsed) => parsed,
 	};
 
+	if command.tier() > input.tier {
+		let message = "This command can only be run from the admin room by owner admins.";
+		return Err(reply(
+			RoomMessageEventContent::notice_plain(message),
+			input.reply_id.as_deref(),
+		));
+	}
+
 	let context = Command {
 		services: &services,
 		body: &body,
@@ -172,6 +180,13 @@ fn parse<'a>(
 	let lines = input.command.lines().filter(|line| !line.trim().is_empty());
 	let command_line = lines.clone().next().expect("command missing first line");
 	let body = lines.skip(1).collect();
+	if let Some(help) = registry::restricted_help(input.tier, &parse_line(command_line)) {
+		return Err(reply(
+			RoomMessageEventContent::notice_markdown(help),
+			input.reply_id.as_deref(),
+		));
+	}
+
 	match parse_command(command_line) {
 		| Ok((command, args)) => Ok((command, args, body)),
 		| Err(error) => {
@@ -16,6 +16,8 @@ pub(crate) enum AppserviceCommand {
 	All,
 }
 
+impl crate::Tiered for AppserviceCommand {}
+
 /// All the getters and iterators from src/database/key_value/appservice.rs
 pub(super) async fn process(subcommand: AppserviceCommand, context: &Command<'_>) -> Result {
 	let services = context.services;
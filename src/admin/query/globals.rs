@@ -20,6 +20,8 @@ pub(crate) enum GlobalsCommand {
 	},
 }
 
+impl crate::Tiered for GlobalsCommand {}
+
 /// All the getters and iterators from src/database/key_value/globals.rs
 pub(super) async fn process(subcommand: GlobalsCommand, context: &Command<'_>) -> Result {
 	let services = context.services;
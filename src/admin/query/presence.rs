@@ -22,6 +22,8 @@ pub(crate) enum PresenceCommand {
 	},
 }
 
+impl crate::Tiered for PresenceCommand {}
+
 /// All the getters and iterators in key_value/presence.rs
 pub(super) async fn process(subcommand: PresenceCommand, context: &Command<'_>) -> Result {
 	let services = context.services;
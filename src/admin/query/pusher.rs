@@ -13,6 +13,8 @@ pub(crate) enum PusherCommand {
 	},
 }
 
+impl crate::Tiered for PusherCommand {}
+
 pub(super) async fn process(subcommand: PusherCommand, context: &Command<'_>) -> Result {
 	let services = context.services;
 
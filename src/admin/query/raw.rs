@@ -2,7 +2,7 @@
 
 use clap::Subcommand;
 use conduwuit::{
-	apply, at, is_zero,
+	at, is_zero,
 	utils::{
 		stream::{ReadyExt, TryIgnore, TryParallelExt},
 		string::EMPTY,
@@ -14,7 +14,10 @@
 use ruma::events::room::message::RoomMessageEventContent;
 use tokio::time::Instant;
 
-use crate::{admin_command, admin_command_dispatch};
+use crate::{
+	admin_command, admin_command_dispatch,
+	utils::{is_secret_key, is_secret_val, redacted},
+};
 
 #[admin_command_dispatch]
 #[derive(Debug, Subcommand)]
@@ -160,8 +163,9 @@ pub(crate) enum RawCommand {
 	},
 }
 
+/// Also dispatched by `db compact`.
 #[admin_command]
-pub(super) async fn compact(
+pub(crate) async fn compact(
 	&self,
 	map: Option<Vec<String>>,
 	start: Option<String>,
@@ -281,7 +285,7 @@ pub(super) async fn raw_keys(
 	prefix
 		.as_deref()
 		.map_or_else(|| map.raw_keys().boxed(), |prefix| map.raw_keys_prefix(prefix).boxed())
-		.map_ok(String::from_utf8_lossy)
+		.map_ok(|key| shown(is_secret_key(map.name()), key))
 		.try_for_each(|str| writeln!(self, "{str:?}"))
 		.boxed()
 		.await?;
@@ -482,8 +486,7 @@ pub(super) async fn raw_iter(
 	prefix
 		.as_deref()
 		.map_or_else(|| map.raw_stream().boxed(), |prefix| map.raw_stream_prefix(prefix).boxed())
-		.map_ok(apply!(2, String::from_utf8_lossy))
-		.map_ok(apply!(2, Cow::into_owned))
+		.map_ok(|keyval| shown_keyval(map.name(), keyval))
 		.try_for_each(|keyval| writeln!(self, "{keyval:?}"))
 		.boxed()
 		.await?;
@@ -507,7 +510,7 @@ pub(super) async fn raw_keys_from(
 	let map = self.services.db.get(&map)?;
 	let timer = Instant::now();
 	map.raw_keys_from(&start)
-		.map_ok(String::from_utf8_lossy)
+		.map_ok(|key| shown(is_secret_key(map.name()), key))
 		.take(limit.unwrap_or(usize::MAX))
 		.try_for_each(|str| writeln!(self, "{str:?}"))
 		.boxed()
@@ -531,8 +534,7 @@ pub(super) async fn raw_iter_from(
 	let timer = Instant::now();
 	let result = map
 		.raw_stream_from(&start)
-		.map_ok(apply!(2, String::from_utf8_lossy))
-		.map_ok(apply!(2, Cow::into_owned))
+		.map_ok(|keyval| shown_keyval(map.name(), keyval))
 		.take(limit.unwrap_or(usize::MAX))
 		.try_collect::<Vec<(String, String)>>()
 		.await?;
@@ -561,7 +563,7 @@ pub(super) async fn raw_get(&self, map: String, key: String) -> Result<RoomMessa
 	let timer = Instant::now();
 	let handle = map.get(&key).await?;
 	let query_time = timer.elapsed();
-	let result = String::from_utf8_lossy(&handle);
+	let result = shown(is_secret_val(map.name()), &handle);
 
 	Ok(RoomMessageEventContent::notice_markdown(format!(
 		"Query completed in {query_time:?}:\n\n```rs\n{result:?}\n```"
@@ -574,3 +576,19 @@ pub(super) async fn raw_maps(&self) -> Result<RoomMessageEventContent> {
 
 	Ok(RoomMessageEventContent::notice_markdown(format!("{list:#?}")))
 }
+
+/// The bytes as a string, unless they're secret.
+fn shown(secret: bool, bytes: &[u8]) -> Cow<'_, str> {
+	if secret {
+		Cow::Owned(redacted(bytes))
+	} else {
+		String::from_utf8_lossy(bytes)
+	}
+}
+
+fn shown_keyval(map: &str, (key, val): (&[u8], &[u8])) -> (String, String) {
+	let key = shown(is_secret_key(map), key).into_owned();
+	let val = shown(is_secret_val(map), val).into_owned();
+
+	(key, val)
+}
@@ -23,6 +23,8 @@ pub(crate) enum RoomAliasCommand {
 	AllLocalAliases,
 }
 
+impl crate::Tiered for RoomAliasCommand {}
+
 /// All the getters and iterators in src/database/key_value/rooms/alias.rs
 pub(super) async fn process(subcommand: RoomAliasCommand, context: &Command<'_>) -> Result {
 	let services = context.services;
@@ -76,6 +76,8 @@ pub(crate) enum RoomStateCacheCommand {
 	},
 }
 
+impl crate::Tiered for RoomStateCacheCommand {}
+
 pub(super) async fn process(subcommand: RoomStateCacheCommand, context: &Command<'_>) -> Result {
 	let services = context.services;
 
@@ -61,6 +61,8 @@ pub(crate) enum SendingCommand {
 	},
 }
 
+impl crate::Tiered for SendingCommand {}
+
 /// All the getters and iterators in key_value/sending.rs
 pub(super) async fn process(subcommand: SendingCommand, context: &Command<'_>) -> Result {
 	let c = reprocess(subcommand, context).await?;
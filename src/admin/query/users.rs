@@ -1,3 +1,5 @@
+use std::fmt::Write;
+
 use clap::Subcommand;
 use conduwuit::Result;
 use futures::stream::StreamExt;
@@ -29,6 +31,11 @@ pub(crate) enum UsersCommand {
 		user_id: OwnedUserId,
 	},
 
+	/// - Number of devices and of `/sync` requests in progress for a user
+	CountSessions {
+		user_id: OwnedUserId,
+	},
+
 	GetDeviceMetadata {
 		user_id: OwnedUserId,
 		device_id: OwnedDeviceId,
@@ -61,6 +68,21 @@ pub(crate) enum UsersCommand {
 		device_id: OwnedDeviceId,
 	},
 
+	/// - Number of undelivered to-device messages per device, largest queues
+	///   first
+	ToDeviceQueues {
+		/// Only the devices of this user
+		user_id: Option<OwnedUserId>,
+
+		/// Maximum number of devices to list
+		#[arg(short, long, default_value("20"))]
+		limit: usize,
+	},
+
+	/// - Removes expired to-device messages and the oldest ones of devices over
+	///   the queue limit now instead of at the next hourly check
+	PruneToDeviceEvents,
+
 	GetLatestBackup {
 		user_id: OwnedUserId,
 	},
@@ -293,6 +315,18 @@ async fn list_devices(&self, user_id: OwnedUserId) -> Result<RoomMessageEventCon
 	)))
 }
 
+#[admin_command]
+async fn count_sessions(&self, user_id: OwnedUserId) -> Result<RoomMessageEventContent> {
+	let timer = tokio::time::Instant::now();
+	let devices = self.services.users.count_devices(&user_id).await;
+	let syncs = self.services.sync.active_syncs(&user_id);
+	let query_time = timer.elapsed();
+
+	Ok(RoomMessageEventContent::notice_markdown(format!(
+		"Query completed in {query_time:?}:\n\ndevices: {devices}\nsyncs in progress: {syncs}"
+	)))
+}
+
 #[admin_command]
 async fn list_devices_metadata(&self, user_id: OwnedUserId) -> Result<RoomMessageEventContent> {
 	let timer = tokio::time::Instant::now();
@@ -422,3 +456,39 @@ async fn get_to_device_events(
 		"Query completed in {query_time:?}:\n\n```rs\n{result:#?}\n```"
 	)))
 }
+
+#[admin_command]
+async fn to_device_queues(
+	&self,
+	user_id: Option<OwnedUserId>,
+	limit: usize,
+) -> Result<RoomMessageEventContent> {
+	let sizes = self
+		.services
+		.users
+		.to_device_queue_sizes(user_id.as_deref())
+		.await;
+
+	let total: usize = sizes.iter().map(|(.., size)| size).sum();
+	let mut out = format!(
+		"{total} to-device messages queued for {} devices, limit {}:\n```\n",
+		sizes.len(),
+		self.services.server.config.to_device_queue_limit
+	);
+
+	for (user_id, device_id, size) in sizes.iter().take(limit) {
+		writeln!(out, "{size}\t{user_id}\t{device_id}")?;
+	}
+	out.push_str("```");
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+async fn prune_to_device_events(&self) -> Result<RoomMessageEventContent> {
+	let removed = self.services.users.prune_to_device_events().await;
+
+	Ok(RoomMessageEventContent::notice_plain(format!(
+		"Removed {removed} to-device messages."
+	)))
+}
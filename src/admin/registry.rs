@@ -0,0 +1,85 @@
+//! Registry of the admin commands and the permission tier each requires, from
+//! which the help of admins who can't run every command is generated.
+
+use std::fmt::Write;
+
+use clap::{Command, CommandFactory};
+use service::admin::Tier;
+
+use crate::{admin::AdminCommand, Tiered};
+
+/// Paths of commands, or of groups of subcommands, with the tier they require.
+pub(crate) type Registry = Vec<(Vec<String>, Tier)>;
+
+/// Nests the entries of a group of subcommands under its name.
+pub(crate) fn prefixed(name: &str, registry: Registry) -> Registry {
+	registry
+		.into_iter()
+		.map(|(path, tier)| {
+			let path = [name.to_owned()].into_iter().chain(path).collect();
+			(path, tier)
+		})
+		.collect()
+}
+
+/// Help for an admin of a tier which can't run every command, when `argv`
+/// asks for help. It lists the commands under the requested one which they
+/// may run; `None` leaves the help to clap, e.g. when they may run all of
+/// them.
+pub(crate) fn restricted_help(tier: Tier, argv: &[String]) -> Option<String> {
+	let wants_help = argv.len() <= 1 || argv.iter().any(|arg| arg == "-h" || arg == "--help");
+	if tier >= Tier::Owner || !wants_help {
+		return None;
+	}
+
+	let requested: Vec<&str> = argv
+		.iter()
+		.skip(1)
+		.map(String::as_str)
+		.filter(|arg| !arg.starts_with('-'))
+		.collect();
+
+	let registry = AdminCommand::registry();
+	let allowed = registry.iter().filter(|(_, required)| *required <= tier);
+	if allowed
+		.clone()
+		.any(|(path, _)| starts_with(&requested, path))
+	{
+		return None;
+	}
+
+	let root = AdminCommand::command();
+	let mut help = String::new();
+	for (path, _) in allowed.filter(|(path, _)| starts_with(path, &requested)) {
+		let about = find(&root, path)
+			.and_then(Command::get_about)
+			.map(ToString::to_string)
+			.unwrap_or_default();
+
+		let about = about.trim_start_matches("- ");
+		writeln!(help, "- `{}`: {about}", path.join(" ")).expect("help buffer");
+	}
+
+	if help.is_empty() {
+		return Some("You can't run this command.".to_owned());
+	}
+
+	Some(format!("Commands you can run:\n{help}"))
+}
+
+fn find<'a>(root: &'a Command, path: &[String]) -> Option<&'a Command> {
+	path.iter()
+		.try_fold(root, |command, name| command.find_subcommand(name))
+}
+
+fn starts_with<A, B>(path: &[A], prefix: &[B]) -> bool
+where
+	A: AsRef<str>,
+	B: AsRef<str>,
+{
+	path.len() >= prefix.len()
+		&& path
+			.iter()
+			.zip(prefix)
+			.all(|(a, b)| a.as_ref() == b.as_ref())
+}
@@ -0,0 +1,57 @@
+use std::{
+	fmt::Write,
+	time::{Duration, UNIX_EPOCH},
+};
+
+use conduwuit::{
+	utils::{time::format, ReadyExt},
+	Result,
+};
+use futures::StreamExt;
+use ruma::events::room::message::RoomMessageEventContent;
+use service::reports::Report;
+
+use crate::admin_command;
+
+#[admin_command]
+pub(super) async fn list_reports(&self, all: bool) -> Result<RoomMessageEventContent> {
+	let reports: Vec<Report> = self
+		.services
+		.reports
+		.reports()
+		.ready_filter(|report| all || report.resolved.is_none())
+		.collect()
+		.await;
+
+	if reports.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No reports."));
+	}
+
+	let mut msg = format!("Reports ({}):\n", reports.len());
+	for report in &reports {
+		let created = format(UNIX_EPOCH + Duration::from_millis(report.created), "%+");
+		let target = report
+			.event_id
+			.as_ref()
+			.map_or_else(|| "room".to_owned(), |event_id| format!("event {event_id} in"));
+		let status = if report.resolved.is_some() { " (resolved)" } else { "" };
+
+		writeln!(
+			msg,
+			"- {}{status}: {} reported {target} {} at {created}: {}",
+			report.id,
+			report.reporter,
+			report.room_id,
+			report.reason.as_deref().unwrap_or("no reason given"),
+		)?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(msg))
+}
+
+#[admin_command]
+pub(super) async fn resolve(&self, id: u64) -> Result<RoomMessageEventContent> {
+	self.services.reports.resolve(id).await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!("Resolved report {id}.")))
+}
@@ -0,0 +1,25 @@
+mod commands;
+
+use clap::Subcommand;
+use conduwuit::Result;
+
+use crate::admin_command_dispatch;
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(super) enum ReportsCommand {
+	/// - List unresolved room and event reports from local users
+	#[moderator]
+	#[clap(alias = "list")]
+	ListReports {
+		/// Also list resolved reports
+		#[arg(short, long)]
+		all: bool,
+	},
+
+	/// - Mark a report as dealt with
+	#[moderator]
+	Resolve {
+		id: u64,
+	},
+}
@@ -44,6 +44,8 @@ pub(crate) enum RoomAliasCommand {
 	},
 }
 
+impl crate::Tiered for RoomAliasCommand {}
+
 pub(super) async fn process(command: RoomAliasCommand, context: &Command<'_>) -> Result {
 	let c = reprocess(command, context).await?;
 	context.write_str(c.body()).await?;
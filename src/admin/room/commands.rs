@@ -1,6 +1,24 @@
-use conduwuit::Result;
+use std::{borrow::Borrow, collections::HashMap, path::PathBuf, sync::Arc};
+
+use conduwuit::{err, Err, Result};
 use futures::StreamExt;
-use ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId};
+use ruma::{
+	events::{
+		room::{
+			member::{MembershipState, RoomMemberEventContent},
+			message::RoomMessageEventContent,
+		},
+		StateEventType,
+	},
+	OwnedEventId, OwnedRoomId, OwnedRoomOrAliasId, OwnedUserId,
+};
+use service::{
+	jobs::{read_room_export, JobKind},
+	rooms::{
+		short::ShortStateKey,
+		state_compressor::{CompressedState, HashSetCompressStateEvent},
+	},
+};
 
 use crate::{admin_command, get_room_info, PAGE_SIZE};
 
@@ -61,9 +79,199 @@ pub(super) async fn list_rooms(
 	Ok(RoomMessageEventContent::notice_markdown(output_plain))
 }
 
+#[admin_command]
+pub(super) async fn delete(
+	&self,
+	block: bool,
+	purge: bool,
+	message: Option<String>,
+	room: OwnedRoomOrAliasId,
+) -> Result<RoomMessageEventContent> {
+	let room_id = self.services.rooms.alias.resolve(&room).await?;
+	if self.services.admin.is_admin_room(&room_id).await {
+		return Err!("Not allowed to delete the admin room.");
+	}
+
+	let id =
+		self.services
+			.jobs
+			.enqueue(JobKind::DeleteRoom { room_id, block, purge, message })?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Queued deletion of {room} as job {id}. Use `jobs status {id}` to follow its progress."
+	)))
+}
+
 #[admin_command]
 pub(super) async fn exists(&self, room_id: OwnedRoomId) -> Result<RoomMessageEventContent> {
 	let result = self.services.rooms.metadata.exists(&room_id).await;
 
 	Ok(RoomMessageEventContent::notice_markdown(format!("{result}")))
 }
+
+#[admin_command]
+pub(super) async fn import(
+	&self,
+	path: PathBuf,
+	yes_i_want_to_do_this: bool,
+) -> Result<RoomMessageEventContent> {
+	if !yes_i_want_to_do_this {
+		return Err!(
+			"Imported events are trusted without authorization or signature checks. You must \
+			 pass --yes-i-want-to-do-this to proceed."
+		);
+	}
+
+	let export = read_room_export(&path).await?;
+	let room_id = export.room_id;
+	if self.services.rooms.metadata.exists(&room_id).await {
+		return Err!("{room_id} already exists on this server.");
+	}
+
+	let count = export.events.len();
+	let version = export.room_version;
+	let id = self
+		.services
+		.jobs
+		.enqueue(JobKind::ImportRoom { room_id: room_id.clone(), path })?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Queued import of {count} events of {room_id} (room version {version}) as job {id}. Use \
+		 `jobs status {id}` to follow its progress."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn force_state(
+	&self,
+	room_id: OwnedRoomId,
+	event_id: OwnedEventId,
+	yes_i_want_to_do_this: bool,
+) -> Result<RoomMessageEventContent> {
+	if !yes_i_want_to_do_this {
+		return Err!(
+			"Replacing the current state of a room can break it further. You must pass \
+			 --yes-i-want-to-do-this to proceed."
+		);
+	}
+
+	let pdu = self
+		.services
+		.rooms
+		.timeline
+		.get_non_outlier_pdu(&event_id)
+		.await
+		.map_err(|_| err!("Event {event_id} not found or is an outlier."))?;
+
+	if pdu.room_id != room_id {
+		return Err!("Event {event_id} does not belong to {room_id}.");
+	}
+
+	let event_shortstatehash = self
+		.services
+		.rooms
+		.state_accessor
+		.pdu_shortstatehash(&event_id)
+		.await?;
+
+	let mut state: HashMap<ShortStateKey, OwnedEventId> = self
+		.services
+		.rooms
+		.state_accessor
+		.state_full_ids(event_shortstatehash)
+		.collect()
+		.await;
+
+	if let Some(state_key) = &pdu.state_key {
+		let shortstatekey = self
+			.services
+			.rooms
+			.short
+			.get_or_create_shortstatekey(&pdu.kind.to_string().into(), state_key)
+			.await;
+
+		state.insert(shortstatekey, event_id.clone());
+	}
+
+	let state_lock = self.services.rooms.state.mutex.lock(&room_id).await;
+
+	let compressed: CompressedState = self
+		.services
+		.rooms
+		.state_compressor
+		.compress_state_events(state.iter().map(|(ssk, eid)| (ssk, eid.borrow())))
+		.collect()
+		.await;
+
+	let HashSetCompressStateEvent { shortstatehash, added, removed } = self
+		.services
+		.rooms
+		.state_compressor
+		.save_state(&room_id, Arc::new(compressed))
+		.await?;
+
+	self.services
+		.rooms
+		.state
+		.force_state(&room_id, shortstatehash, added, removed, &state_lock)
+		.await?;
+
+	// Members who only joined after the event have no membership in the new
+	// state; drop them from the room so membership caches match.
+	let members: Vec<OwnedUserId> = self
+		.services
+		.rooms
+		.state_cache
+		.room_members(&room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for user_id in &members {
+		let in_state = self
+			.services
+			.rooms
+			.state_accessor
+			.room_state_get_content::<RoomMemberEventContent>(
+				&room_id,
+				&StateEventType::RoomMember,
+				user_id.as_str(),
+			)
+			.await
+			.is_ok_and(|content| content.membership == MembershipState::Join);
+
+		if !in_state {
+			self.services
+				.rooms
+				.state_cache
+				.update_membership(
+					&room_id,
+					user_id,
+					RoomMemberEventContent::new(MembershipState::Leave),
+					user_id,
+					None,
+					None,
+					false,
+				)
+				.await?;
+		}
+	}
+
+	self.services
+		.rooms
+		.state_cache
+		.update_joined_count(&room_id)
+		.await;
+
+	self.services
+		.rooms
+		.state_cache
+		.clear_appservice_in_room_cache();
+
+	drop(state_lock);
+
+	Ok(RoomMessageEventContent::notice_markdown(format!(
+		"Reset the state of {room_id} to the state at {event_id} ({} state events).",
+		state.len()
+	)))
+}
@@ -25,6 +25,8 @@ pub(crate) enum RoomDirectoryCommand {
 	},
 }
 
+impl crate::Tiered for RoomDirectoryCommand {}
+
 pub(super) async fn process(command: RoomDirectoryCommand, context: &Command<'_>) -> Result {
 	let c = reprocess(command, context).await?;
 	context.write_str(c.body()).await?;
@@ -3,14 +3,17 @@
 mod directory;
 mod info;
 mod moderation;
+mod policy;
+
+use std::path::PathBuf;
 
 use clap::Subcommand;
 use conduwuit::Result;
-use ruma::OwnedRoomId;
+use ruma::{OwnedEventId, OwnedRoomId, OwnedRoomOrAliasId};
 
 use self::{
 	alias::RoomAliasCommand, directory::RoomDirectoryCommand, info::RoomInfoCommand,
-	moderation::RoomModerationCommand,
+	moderation::RoomModerationCommand, policy::RoomPolicyCommand,
 };
 use crate::admin_command_dispatch;
 
@@ -18,6 +21,7 @@
 #[derive(Debug, Subcommand)]
 pub(super) enum RoomCommand {
 	/// - List all rooms the server knows about
+	#[moderator]
 	#[clap(alias = "list")]
 	ListRooms {
 		page: Option<usize>,
@@ -38,12 +42,18 @@ pub(super) enum RoomCommand {
 
 	#[command(subcommand)]
 	/// - View information about a room we know about
+	#[moderator]
 	Info(RoomInfoCommand),
 
 	#[command(subcommand)]
 	/// - Manage moderation of remote or local rooms
+	#[moderator]
 	Moderation(RoomModerationCommand),
 
+	#[command(subcommand)]
+	/// - Manage the moderation policy lists this server enforces
+	Policy(RoomPolicyCommand),
+
 	#[command(subcommand)]
 	/// - Manage rooms' aliases
 	Alias(RoomAliasCommand),
@@ -52,8 +62,68 @@ pub(super) enum RoomCommand {
 	/// - Manage the room directory
 	Directory(RoomDirectoryCommand),
 
+	/// - Deletes a room in the background: evicts all our local users (admins
+	///   included), removes its local aliases and unpublishes it from the room
+	///   directory
+	///
+	/// To also ban the room so it can't be joined again, use --block. To
+	/// delete its history, state and the media no other room or profile
+	/// refers to, use --purge. Evicted users are sent --message as a server
+	/// notice.
+	#[clap(alias = "delete-room")]
+	Delete {
+		#[arg(long)]
+		/// Bans the room so local users can't join it again
+		block: bool,
+
+		#[arg(long)]
+		/// Deletes the room's events, state and media only it refers to
+		purge: bool,
+
+		#[arg(long)]
+		/// Server notice sent to every evicted user
+		message: Option<String>,
+
+		/// The room in the format of `!roomid:example.com` or a room alias in
+		/// the format of `#roomalias:example.com`
+		room: OwnedRoomOrAliasId,
+	},
+
 	/// - Check if we know about a room
+	#[moderator]
 	Exists {
 		room_id: OwnedRoomId,
 	},
+
+	/// - Recreates a room from an export in the background, as a last resort
+	///   when the servers it was on are gone for good
+	///
+	/// Takes the `rooms/<room_id>/events` file of a Synapse `export-data`
+	/// directory, or our archive format: a JSON object with the room's PDUs
+	/// under `events`. The events are trusted as they are; they notify nobody
+	/// and none are sent to other servers. The room must not already exist
+	/// here. Events which could not be imported are listed in the job's report.
+	///
+	/// Requires the `--yes-i-want-to-do-this` flag.
+	Import {
+		path: PathBuf,
+
+		#[arg(long)]
+		yes_i_want_to_do_this: bool,
+	},
+
+	/// - Resets the current state of a room to the state at an event
+	///
+	/// The new state is the state before the event plus the event itself if
+	/// it is a state event. This is meant to recover rooms broken by bad
+	/// state resolution; no events are sent, other servers are unaffected.
+	///
+	/// Requires the `--yes-i-want-to-do-this` flag.
+	ForceState {
+		room_id: OwnedRoomId,
+		event_id: OwnedEventId,
+
+		#[arg(long)]
+		yes_i_want_to_do_this: bool,
+	},
 }
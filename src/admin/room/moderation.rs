@@ -74,6 +74,27 @@ pub(crate) enum RoomModerationCommand {
 		/// information
 		no_details: bool,
 	},
+
+	/// - Drops typing notifications in a room and stops exchanging public read
+	///   receipts in it over federation
+	///
+	/// Meant for huge public rooms where this traffic dominates. Rooms can
+	/// also be listed in the `ephemeral_disabled_rooms` config option.
+	DisableEphemeral {
+		/// The room in the format of `!roomid:example.com` or a room alias in
+		/// the format of `#roomalias:example.com`
+		room: Box<RoomOrAliasId>,
+	},
+
+	/// - Allows typing notifications and read receipts in a room again
+	EnableEphemeral {
+		/// The room in the format of `!roomid:example.com` or a room alias in
+		/// the format of `#roomalias:example.com`
+		room: Box<RoomOrAliasId>,
+	},
+
+	/// - List the rooms with typing notifications and read receipts disabled
+	ListEphemeralDisabledRooms,
 }
 
 #[admin_command]
@@ -637,3 +658,83 @@ async fn list_banned_rooms(&self, no_details: bool) -> Result<RoomMessageEventCo
 
 	Ok(RoomMessageEventContent::notice_markdown(output_plain))
 }
+
+#[admin_command]
+async fn disable_ephemeral(&self, room: Box<RoomOrAliasId>) -> Result<RoomMessageEventContent> {
+	let room_id = self.services.rooms.alias.resolve(&room).await?;
+	self.services
+		.rooms
+		.metadata
+		.disable_ephemeral(&room_id, true);
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Typing notifications in {room_id} are now dropped and public read receipts are no \
+		 longer exchanged over federation."
+	)))
+}
+
+#[admin_command]
+async fn enable_ephemeral(&self, room: Box<RoomOrAliasId>) -> Result<RoomMessageEventContent> {
+	let room_id = self.services.rooms.alias.resolve(&room).await?;
+	self.services
+		.rooms
+		.metadata
+		.disable_ephemeral(&room_id, false);
+
+	let in_config = self
+		.services
+		.server
+		.config
+		.ephemeral_disabled_rooms
+		.contains(&room_id);
+
+	Ok(RoomMessageEventContent::text_plain(if in_config {
+		format!(
+			"{room_id} is listed in the ephemeral_disabled_rooms config option, remove it there \
+			 too."
+		)
+	} else {
+		format!("Typing notifications and read receipts in {room_id} are allowed again.")
+	}))
+}
+
+#[admin_command]
+async fn list_ephemeral_disabled_rooms(&self) -> Result<RoomMessageEventContent> {
+	let mut room_ids: Vec<OwnedRoomId> = self
+		.services
+		.rooms
+		.metadata
+		.list_ephemeral_disabled_rooms()
+		.map(Into::into)
+		.collect()
+		.await;
+
+	room_ids.extend(
+		self.services
+			.server
+			.config
+			.ephemeral_disabled_rooms
+			.iter()
+			.cloned(),
+	);
+	room_ids.sort_unstable();
+	room_ids.dedup();
+
+	if room_ids.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain(
+			"No rooms have typing notifications and read receipts disabled.",
+		));
+	}
+
+	let output_plain = format!(
+		"Rooms with ephemeral events disabled ({}):\n```\n{}\n```",
+		room_ids.len(),
+		room_ids
+			.iter()
+			.map(ToString::to_string)
+			.collect::<Vec<_>>()
+			.join("\n")
+	);
+
+	Ok(RoomMessageEventContent::notice_markdown(output_plain))
+}
@@ -0,0 +1,146 @@
+use std::fmt::Write;
+
+use clap::Subcommand;
+use conduwuit::{Err, Result};
+use ruma::{
+	events::room::message::RoomMessageEventContent, OwnedRoomId, RoomId, ServerName, UserId,
+};
+
+use crate::{admin_command, admin_command_dispatch};
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(crate) enum RoomPolicyCommand {
+	/// - Enforce the ban rules of a moderation policy list room this server is
+	///   in
+	Subscribe {
+		room_id: OwnedRoomId,
+	},
+
+	/// - Stop enforcing a policy list subscribed to from the admin room
+	///
+	/// Lists in the `policy_list_rooms` config option stay subscribed.
+	Unsubscribe {
+		room_id: OwnedRoomId,
+	},
+
+	/// - List subscribed policy lists and how many ban rules each has
+	#[clap(alias = "list")]
+	ListSubscribed,
+
+	/// - List the enforced ban rules
+	///
+	/// Only rules whose entity or reason contains the filter are shown.
+	Rules {
+		filter: Option<String>,
+	},
+
+	/// - Show the rule banning a user ID, room ID or server name, if any
+	Check {
+		entity: String,
+	},
+}
+
+#[admin_command]
+async fn subscribe(&self, room_id: OwnedRoomId) -> Result<RoomMessageEventContent> {
+	let count = self.services.moderation.subscribe(&room_id).await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Subscribed to {room_id}, enforcing {count} ban rules."
+	)))
+}
+
+#[admin_command]
+async fn unsubscribe(&self, room_id: OwnedRoomId) -> Result<RoomMessageEventContent> {
+	if self
+		.services
+		.server
+		.config
+		.policy_list_rooms
+		.contains(&room_id)
+	{
+		return Err!(
+			"{room_id} is subscribed to in the config; remove it from policy_list_rooms."
+		);
+	}
+
+	self.services.moderation.unsubscribe(&room_id);
+
+	Ok(RoomMessageEventContent::text_plain(format!("Unsubscribed from {room_id}.")))
+}
+
+#[admin_command]
+async fn list_subscribed(&self) -> Result<RoomMessageEventContent> {
+	let subscriptions = self.services.moderation.subscriptions().await;
+	if subscriptions.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("Not subscribed to any policy lists."));
+	}
+
+	let rules = self.services.moderation.rules();
+	let mut out = format!("Subscribed policy lists ({}):\n", subscriptions.len());
+	for room_id in &subscriptions {
+		let count = rules
+			.iter()
+			.filter(|((policy_room, ..), _)| policy_room == room_id)
+			.count();
+
+		writeln!(out, "- {room_id}: {count} ban rules")?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+async fn rules(&self, filter: Option<String>) -> Result<RoomMessageEventContent> {
+	let rules: Vec<_> = self
+		.services
+		.moderation
+		.rules()
+		.into_iter()
+		.filter(|(_, rule)| {
+			filter.as_deref().is_none_or(|filter| {
+				rule.entity.contains(filter)
+					|| rule
+						.reason
+						.as_deref()
+						.is_some_and(|reason| reason.contains(filter))
+			})
+		})
+		.collect();
+
+	if rules.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No ban rules found."));
+	}
+
+	let mut out = format!("Ban rules ({}):\n", rules.len());
+	for ((room_id, kind, _), rule) in &rules {
+		let reason = rule.reason.as_deref().unwrap_or("no reason given");
+		writeln!(out, "- {kind} `{}` ({reason}) from {room_id}", rule.entity)?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+async fn check(&self, entity: String) -> Result<RoomMessageEventContent> {
+	let moderation = &self.services.moderation;
+	let rule = if let Ok(user_id) = UserId::parse(&entity) {
+		moderation.user_ban(&user_id)
+	} else if let Ok(room_id) = RoomId::parse(&entity) {
+		moderation.room_ban(&room_id)
+	} else if let Ok(server_name) = ServerName::parse(&entity) {
+		moderation.server_ban(&server_name)
+	} else {
+		return Err!("{entity} is not a user ID, room ID or server name.");
+	};
+
+	let Some(rule) = rule else {
+		return Ok(RoomMessageEventContent::text_plain(format!("{entity} is not banned.")));
+	};
+
+	let reason = rule.reason.as_deref().unwrap_or("no reason given");
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"{entity} is banned by the rule for {} ({reason}).",
+		rule.entity
+	)))
+}
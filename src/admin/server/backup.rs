@@ -0,0 +1,94 @@
+use std::{path::PathBuf, sync::Arc};
+
+use clap::Subcommand;
+use conduwuit::Result;
+use ruma::events::room::message::RoomMessageEventContent;
+use service::jobs::JobKind;
+
+use crate::{admin_command, admin_command_dispatch};
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(crate) enum ServerBackupCommand {
+	/// - Back up the database to database_backup_path while online, as a job
+	///
+	/// Backups are incremental: files unchanged since a previous backup are
+	/// shared with it. Each new backup is verified once written.
+	Create,
+
+	/// - List the database backups
+	List,
+
+	/// - Check that the files of a backup are all present with their expected
+	///   sizes
+	Verify {
+		backup_id: u32,
+	},
+
+	/// - Restore a backup into an empty directory
+	///
+	/// The running database is left alone. To switch to the restored one,
+	/// point database_path at the directory and restart.
+	Restore {
+		/// Where to restore the backup to
+		path: PathBuf,
+
+		/// Backup to restore, the latest one by default
+		backup_id: Option<u32>,
+	},
+}
+
+#[admin_command]
+async fn create(&self) -> Result<RoomMessageEventContent> {
+	let id = self
+		.services
+		.jobs
+		.enqueue_exclusive(JobKind::BackupDatabase)
+		.await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Queued a database backup as job {id}. Use `jobs status {id}` to follow its progress."
+	)))
+}
+
+#[admin_command]
+async fn list(&self) -> Result<RoomMessageEventContent> {
+	let result = self.services.db.db.backup_list()?;
+
+	if result.is_empty() {
+		Ok(RoomMessageEventContent::text_plain("No backups found."))
+	} else {
+		Ok(RoomMessageEventContent::text_plain(result))
+	}
+}
+
+#[admin_command]
+async fn verify(&self, backup_id: u32) -> Result<RoomMessageEventContent> {
+	let db = Arc::clone(&self.services.db);
+	self.services
+		.server
+		.runtime()
+		.spawn_blocking(move || db.db.backup_verify(backup_id))
+		.await??;
+
+	Ok(RoomMessageEventContent::text_plain(format!("Backup #{backup_id} is intact.")))
+}
+
+#[admin_command]
+async fn restore(
+	&self,
+	path: PathBuf,
+	backup_id: Option<u32>,
+) -> Result<RoomMessageEventContent> {
+	let db = Arc::clone(&self.services.db);
+	let path_ = path.clone();
+	self.services
+		.server
+		.runtime()
+		.spawn_blocking(move || db.db.backup_restore(backup_id, &path_))
+		.await??;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Restored the backup into {path:?}. Point database_path at it and restart to use it."
+	)))
+}
@@ -0,0 +1,88 @@
+use std::fmt::Write;
+
+use clap::Subcommand;
+use conduwuit::{Err, Result};
+use ruma::events::room::message::RoomMessageEventContent;
+
+use crate::{admin_command, admin_command_dispatch};
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(crate) enum ServerCacheCommand {
+	/// - List the in-memory caches with their size and hit ratio since startup
+	List,
+
+	/// - Change the capacity of a cache without restarting
+	///
+	/// Shrinking a cache evicts its least recently used entries. The change
+	/// lasts until the next config reload or restart.
+	Resize {
+		cache: String,
+		capacity: usize,
+	},
+
+	/// - Resize every cache to its configured capacity scaled by the modifier
+	///
+	/// Without a modifier, the cache_capacity_modifier from the config is
+	/// used.
+	ResizeAll {
+		modifier: Option<f64>,
+	},
+
+	/// - Remove every entry of a cache
+	Clear {
+		cache: String,
+	},
+}
+
+#[admin_command]
+async fn list(&self) -> Result<RoomMessageEventContent> {
+	let mut out = String::from(
+		"| Cache | Entries | Capacity | Hits | Misses | Hit ratio |\n| --- | --- | --- | --- | \
+		 --- | --- |\n",
+	);
+
+	for (name, stats) in self.services.caches().await {
+		let ratio = stats
+			.hit_ratio()
+			.map_or_else(|| "-".to_owned(), |ratio| format!("{:.1}%", ratio * 100.0));
+
+		writeln!(
+			out,
+			"| {name} | {} | {} | {} | {} | {ratio} |",
+			stats.entries, stats.capacity, stats.hits, stats.misses
+		)?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+async fn resize(&self, cache: String, capacity: usize) -> Result<RoomMessageEventContent> {
+	self.services.set_cache_capacity(&cache, capacity).await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Capacity of {cache} set to {capacity}."
+	)))
+}
+
+#[admin_command]
+async fn resize_all(&self, modifier: Option<f64>) -> Result<RoomMessageEventContent> {
+	let modifier = modifier.unwrap_or(self.services.server.config.cache_capacity_modifier);
+	if !modifier.is_finite() || modifier < 0.0 {
+		return Err!("The modifier must be a non-negative number.");
+	}
+
+	let resized = self.services.resize_caches(modifier).await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Resized {resized} caches with modifier {modifier}."
+	)))
+}
+
+#[admin_command]
+async fn clear(&self, cache: String) -> Result<RoomMessageEventContent> {
+	self.services.clear_cache_named(&cache).await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!("Cleared {cache}.")))
+}
@@ -1,10 +1,13 @@
-use std::{fmt::Write, path::PathBuf, sync::Arc};
+use std::{fmt::Write, path::PathBuf};
 
 use conduwuit::{info, utils::time, warn, Err, Result};
 use ruma::events::room::message::RoomMessageEventContent;
 
 use crate::admin_command;
 
+/// Default file name of `export-keys`, in the database directory.
+const SIGNING_KEY_FILE_NAME: &str = "signing.key";
+
 #[admin_command]
 pub(super) async fn uptime(&self) -> Result<RoomMessageEventContent> {
 	let elapsed = self
@@ -33,7 +36,10 @@ pub(super) async fn reload_config(
 	path: Option<PathBuf>,
 ) -> Result<RoomMessageEventContent> {
 	let path = path.as_deref().into_iter();
-	self.services.config.reload(path)?;
+	let config = self.services.config.reload(path)?;
+	self.services
+		.resize_caches(config.cache_capacity_modifier)
+		.await?;
 
 	Ok(RoomMessageEventContent::text_plain("Successfully reconfigured."))
 }
@@ -91,34 +97,84 @@ pub(super) async fn clear_caches(&self) -> Result<RoomMessageEventContent> {
 }
 
 #[admin_command]
-pub(super) async fn list_backups(&self) -> Result<RoomMessageEventContent> {
-	let result = self.services.db.db.backup_list()?;
+pub(super) async fn rate_limits(
+	&self,
+	filter: Option<String>,
+) -> Result<RoomMessageEventContent> {
+	let mut buckets: Vec<_> = self
+		.services
+		.rate_limiting
+		.buckets()
+		.into_iter()
+		.filter(|(key, _)| {
+			filter
+				.as_deref()
+				.is_none_or(|filter| key.to_string().contains(filter))
+		})
+		.collect();
+
+	if buckets.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No rate limit buckets found."));
+	}
+
+	buckets.sort_by(|(_, a), (_, b)| a.tokens.total_cmp(&b.tokens));
 
-	if result.is_empty() {
-		Ok(RoomMessageEventContent::text_plain("No backups found."))
-	} else {
-		Ok(RoomMessageEventContent::text_plain(result))
+	let mut out = String::from("Rate limit buckets (tokens left):\n");
+	for (key, bucket) in &buckets {
+		writeln!(out, "- {key}: {:.2}", bucket.tokens)?;
 	}
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
 }
 
 #[admin_command]
-pub(super) async fn backup_database(&self) -> Result<RoomMessageEventContent> {
-	let db = Arc::clone(&self.services.db);
-	let mut result = self
+pub(super) async fn reset_rate_limits(&self, filter: String) -> Result<RoomMessageEventContent> {
+	let reset = self
 		.services
-		.server
-		.runtime()
-		.spawn_blocking(move || match db.db.backup() {
-			| Ok(()) => String::new(),
-			| Err(e) => e.to_string(),
-		})
+		.rate_limiting
+		.reset(|key| key.to_string().contains(&filter));
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Refilled {reset} rate limit buckets."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn export_keys(
+	&self,
+	output: Option<PathBuf>,
+) -> Result<RoomMessageEventContent> {
+	let output = output.unwrap_or_else(|| {
+		self.services
+			.server
+			.config
+			.database_path
+			.join(SIGNING_KEY_FILE_NAME)
+	});
+
+	self.services
+		.server_keys
+		.export_signing_key(&output)
 		.await?;
 
-	if result.is_empty() {
-		result = self.services.db.db.backup_list()?;
-	}
+	warn!("Exported the signing key to {}", output.display());
 
-	Ok(RoomMessageEventContent::notice_markdown(result))
+	Ok(RoomMessageEventContent::notice_plain(format!(
+		"Wrote the signing key to {} on the server. Anyone with it can impersonate this server; \
+		 move it somewhere safe or delete it.",
+		output.display()
+	)))
+}
+
+#[admin_command]
+pub(super) async fn import_keys(&self, path: PathBuf) -> Result<RoomMessageEventContent> {
+	let key_id = self.services.server_keys.import_signing_key(&path).await?;
+
+	warn!("Imported signing key {key_id} from {}", path.display());
+
+	Ok(RoomMessageEventContent::notice_plain(format!(
+		"Imported signing key {key_id}. Restart the server to start using it."
+	)))
 }
 
 #[admin_command]
@@ -129,6 +185,20 @@ pub(super) async fn admin_notice(&self, message: Vec<String>) -> Result<RoomMess
 	Ok(RoomMessageEventContent::notice_plain("Notice was sent to #admins"))
 }
 
+#[admin_command]
+pub(super) async fn announce(&self, message: Vec<String>) -> Result<RoomMessageEventContent> {
+	let message = message.join(" ");
+	if message.is_empty() {
+		return Err!("The announcement message cannot be empty.");
+	}
+
+	self.services.server_notices.announce(message)?;
+
+	Ok(RoomMessageEventContent::notice_plain(
+		"Announcement queued, progress will be reported here.",
+	))
+}
+
 #[admin_command]
 pub(super) async fn reload_mods(&self) -> Result<RoomMessageEventContent> {
 	self.services.server.reload()?;
@@ -1,3 +1,5 @@
+mod backup;
+mod cache;
 mod commands;
 
 use std::path::PathBuf;
@@ -5,6 +7,7 @@
 use clap::Subcommand;
 use conduwuit::Result;
 
+use self::{backup::ServerBackupCommand, cache::ServerCacheCommand};
 use crate::admin_command_dispatch;
 
 #[admin_command_dispatch]
@@ -39,18 +42,59 @@ pub(super) enum ServerCommand {
 	/// - Clears all of Conduwuit's caches
 	ClearCaches,
 
-	/// - Performs an online backup of the database (only available for RocksDB
-	///   at the moment)
-	BackupDatabase,
+	#[command(subcommand)]
+	/// - Inspect and tune the in-memory caches
+	Cache(ServerCacheCommand),
+
+	#[command(subcommand)]
+	/// - Create, verify and restore online database backups
+	Backup(ServerBackupCommand),
+
+	/// - Write the server's signing key to a file on the server, in the format
+	///   of Synapse's `signing.key` file
+	///
+	/// Defaults to `signing.key` in the database directory. The file must not
+	/// exist yet, and is only readable by the server's user. Anyone with this
+	/// key can impersonate the server; keep it secret.
+	ExportKeys {
+		output: Option<PathBuf>,
+	},
 
-	/// - List database backups
-	ListBackups,
+	/// - Replace the server's signing key with one read from a file on the
+	///   server, e.g. when migrating from another host
+	///
+	/// Takes a file in the format written by `export-keys`. The new key is
+	/// used after a restart.
+	ImportKeys {
+		path: PathBuf,
+	},
+
+	/// - Show the event rate limit buckets, emptiest first
+	///
+	/// Only buckets matching the filter are shown, e.g. a user ID, a server
+	/// name or a room ID.
+	RateLimits {
+		filter: Option<String>,
+	},
+
+	/// - Refill the event rate limit buckets matching the filter
+	ResetRateLimits {
+		filter: String,
+	},
 
 	/// - Send a message to the admin room.
 	AdminNotice {
 		message: Vec<String>,
 	},
 
+	/// - Send a server notice to every active local user.
+	///
+	/// Runs in the background at the configured pace; progress is reported in
+	/// the admin room.
+	Announce {
+		message: Vec<String>,
+	},
+
 	/// - Hot-reload the server
 	#[clap(alias = "reload")]
 	ReloadMods,
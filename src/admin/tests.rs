@@ -24,3 +24,19 @@ fn get_help_inner(input: &str) {
 	assert!(error.contains("Commands:"));
 	assert!(error.contains("Options:"));
 }
+
+#[test]
+fn registry_names_commands() {
+	use clap::CommandFactory;
+
+	use crate::{admin::AdminCommand, Tiered};
+
+	let root = AdminCommand::command();
+	for (path, _) in AdminCommand::registry() {
+		let command = path
+			.iter()
+			.try_fold(&root, |command, name| command.find_subcommand(name));
+
+		assert!(command.is_some(), "{path:?} is not a command");
+	}
+}
@@ -1,10 +1,10 @@
-use std::{collections::BTreeMap, fmt::Write as _};
+use std::{collections::BTreeMap, fmt::Write as _, time::Duration};
 
 use api::client::{full_user_deactivate, join_room_by_id_helper, leave_room};
 use conduwuit::{
-	debug_warn, error, info, is_equal_to,
-	utils::{self, ReadyExt},
-	warn, PduBuilder, Result,
+	debug_warn, err, error, info, is_equal_to,
+	utils::{self, time::parse_duration, ReadyExt},
+	warn, Err, PduBuilder, Result,
 };
 use conduwuit_api::client::{leave_all_rooms, update_avatar_url, update_displayname};
 use futures::StreamExt;
@@ -20,6 +20,7 @@
 	},
 	EventId, OwnedRoomId, OwnedRoomOrAliasId, OwnedUserId, RoomId, UserId,
 };
+use service::{deactivation::DeactivateOptions, jobs::JobKind, onboarding::NewUser};
 
 use crate::{
 	admin_command, get_room_info,
@@ -29,6 +30,88 @@
 const AUTO_GEN_PASSWORD_LENGTH: usize = 25;
 const BULK_JOIN_REASON: &str = "Bulk force joining this room as initiated by the server admin.";
 
+#[admin_command]
+pub(super) async fn bulk_create_users(&self) -> Result<RoomMessageEventContent> {
+	if self.body.len() < 2
+		|| !self.body[0].trim().starts_with("```")
+		|| self.body.last().unwrap_or(&"").trim() != "```"
+	{
+		return Ok(RoomMessageEventContent::text_plain(
+			"Expected code block in command body. Add --help for details.",
+		));
+	}
+
+	let lines = &self.body[1..self.body.len().saturating_sub(1)];
+	let mut users: Vec<NewUser> = if lines
+		.first()
+		.is_some_and(|line| line.trim_start().starts_with('['))
+	{
+		serde_json::from_str(&lines.join("\n"))
+			.map_err(|e| err!("Invalid JSON list of users: {e}"))?
+	} else {
+		lines
+			.iter()
+			.filter(|line| !line.trim().is_empty())
+			.map(|line| parse_csv_user(line))
+			.collect::<Result<Vec<_>>>()?
+	};
+
+	let generated = self.services.onboarding.hash_passwords(&mut users)?;
+
+	let count = users.len();
+	let id = self.services.jobs.enqueue(JobKind::CreateUsers { users })?;
+
+	let mut msg = format!(
+		"Queued creation of {count} users as job {id}. Use `jobs status {id}` to follow its \
+		 progress and see the results."
+	);
+
+	if !generated.is_empty() {
+		msg.push_str("\n\nGenerated passwords, which are shown only this once:\n```\n");
+		for (localpart, password) in &generated {
+			writeln!(msg, "{localpart}: {password}")?;
+		}
+		msg.push_str("```");
+	}
+
+	Ok(RoomMessageEventContent::text_markdown(msg))
+}
+
+/// Parses `localpart,password,displayname,rooms`, with all but the localpart
+/// optional.
+fn parse_csv_user(line: &str) -> Result<NewUser> {
+	let mut columns = line.split(',').map(str::trim);
+	let localpart = columns
+		.next()
+		.filter(|localpart| !localpart.is_empty())
+		.ok_or_else(|| err!("Missing localpart in line {line:?}"))?;
+
+	let mut optional = || {
+		columns
+			.next()
+			.filter(|column| !column.is_empty())
+			.map(ToOwned::to_owned)
+	};
+
+	let password = optional();
+	let displayname = optional();
+	let rooms = optional()
+		.unwrap_or_default()
+		.split_whitespace()
+		.map(OwnedRoomOrAliasId::try_from)
+		.collect::<Result<_, _>>()
+		.map_err(|e| err!("Invalid room in line {line:?}: {e}"))?;
+
+	Ok(NewUser {
+		localpart: localpart.to_owned(),
+		password,
+		password_hash: None,
+		sso: None,
+		displayname,
+		rooms,
+	})
+}
+
 #[admin_command]
 pub(super) async fn list_users(&self) -> Result<RoomMessageEventContent> {
 	let users: Vec<_> = self
@@ -389,6 +472,61 @@ pub(super) async fn deactivate_all(
 	}
 }
 
+#[admin_command]
+pub(super) async fn deactivate_bulk(
+	&self,
+	erase: bool,
+	reject_invites: bool,
+	leave_rooms: bool,
+	remove_pushers: bool,
+	force: bool,
+) -> Result<RoomMessageEventContent> {
+	if self.body.len() < 2
+		|| !self.body[0].trim().starts_with("```")
+		|| self.body.last().unwrap_or(&"").trim() != "```"
+	{
+		return Ok(RoomMessageEventContent::text_plain(
+			"Expected code block in command body. Add --help for details.",
+		));
+	}
+
+	let usernames = &self.body[1..self.body.len().saturating_sub(1)];
+
+	let mut user_ids: Vec<OwnedUserId> = Vec::with_capacity(usernames.len());
+	let mut skipped = String::new();
+	for username in usernames {
+		match parse_active_local_user_id(self.services, username).await {
+			| Ok(user_id) if user_id == self.services.globals.server_user => {
+				writeln!(skipped, "{username}: the server service account")?;
+			},
+			| Ok(user_id) if !force && self.services.users.is_admin(&user_id).await => {
+				writeln!(skipped, "{username}: an admin and --force is not set")?;
+			},
+			| Ok(user_id) => user_ids.push(user_id),
+			| Err(e) => writeln!(skipped, "{username}: {e}")?,
+		}
+	}
+
+	let count = user_ids.len();
+	let options = DeactivateOptions {
+		erase,
+		reject_invites,
+		leave_rooms,
+		remove_pushers,
+	};
+
+	self.services
+		.deactivation
+		.deactivate_bulk(user_ids, options)?;
+
+	let mut msg = format!("Queued deactivation of {count} users in the background.");
+	if !skipped.is_empty() {
+		write!(msg, "\n\nSkipped:\n```\n{skipped}```")?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(msg))
+}
+
 #[admin_command]
 pub(super) async fn list_joined_rooms(&self, user_id: String) -> Result<RoomMessageEventContent> {
 	// Validate user id
@@ -941,3 +1079,117 @@ pub(super) async fn redact_event(
 
 	Ok(RoomMessageEventContent::text_plain(""))
 }
+
+#[admin_command]
+pub(super) async fn send_notice(
+	&self,
+	user_id: Option<String>,
+	all: bool,
+	message: Vec<String>,
+) -> Result<RoomMessageEventContent> {
+	let message = message.join(" ");
+	if message.is_empty() {
+		return Err!("The notice message cannot be empty.");
+	}
+
+	if all {
+		self.services.server_notices.announce(message)?;
+
+		return Ok(RoomMessageEventContent::notice_plain(
+			"Server notice queued for all users, progress will be reported here.",
+		));
+	}
+
+	let Some(user_id) = user_id else {
+		return Err!("Specify a user or use --all.");
+	};
+
+	let user_id = parse_active_local_user_id(self.services, &user_id).await?;
+	let event_id = self
+		.services
+		.server_notices
+		.send_notice(&user_id, &message)
+		.await?;
+
+	Ok(RoomMessageEventContent::notice_markdown(format!(
+		"Sent server notice to {user_id}: {event_id}"
+	)))
+}
+
+#[admin_command]
+pub(super) async fn create_registration_token(
+	&self,
+	token: Option<String>,
+	uses_allowed: Option<u64>,
+	expires_in: Option<String>,
+) -> Result<RoomMessageEventContent> {
+	let expiry_time = expires_in
+		.as_deref()
+		.map(parse_duration)
+		.transpose()?
+		.map(|duration| -> Result<u64> {
+			let duration: u64 = duration.as_millis().try_into()?;
+			Ok(utils::millis_since_unix_epoch().saturating_add(duration))
+		})
+		.transpose()?;
+
+	let (token, _) = self
+		.services
+		.registration_tokens
+		.create(token, uses_allowed, expiry_time)
+		.await?;
+
+	Ok(RoomMessageEventContent::notice_markdown(format!(
+		"Created registration token `{token}`."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn list_registration_tokens(&self) -> Result<RoomMessageEventContent> {
+	let now = utils::millis_since_unix_epoch();
+	let tokens: Vec<_> = self.services.registration_tokens.tokens().collect().await;
+
+	if tokens.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No registration tokens found."));
+	}
+
+	let mut out = format!("Registration tokens ({}):\n", tokens.len());
+	for (token, info) in &tokens {
+		let uses_allowed = info
+			.uses_allowed
+			.map_or_else(|| "unlimited".to_owned(), |uses| uses.to_string());
+
+		let expiry = info.expiry_time.map_or_else(
+			|| "never expires".to_owned(),
+			|expiry| {
+				let remaining = expiry.saturating_sub(now) / 1000;
+				format!("expires in {}", utils::time::pretty(Duration::from_secs(remaining)))
+			},
+		);
+
+		let status = if info.is_usable(now) {
+			"valid"
+		} else {
+			"exhausted or expired"
+		};
+		writeln!(
+			out,
+			"- `{token}`: {} completed, {} pending of {uses_allowed} uses, {expiry} ({status})",
+			info.completed, info.pending
+		)?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(out))
+}
+
+#[admin_command]
+pub(super) async fn revoke_registration_token(
+	&self,
+	token: String,
+) -> Result<RoomMessageEventContent> {
+	self.services.registration_tokens.revoke(&token).await?;
+
+	Ok(RoomMessageEventContent::notice_markdown(format!(
+		"Revoked registration token `{token}`."
+	)))
+}
@@ -18,6 +18,16 @@ pub(super) enum UserCommand {
 		password: Option<String>,
 	},
 
+	/// - Create many users from a code block, as a background job
+	///
+	/// The code block holds either a JSON list of objects with `localpart`
+	/// and optionally `password`, `sso` (`{"provider": .., "subject": ..}`),
+	/// `displayname` and `rooms`, or CSV lines of
+	/// `localpart,password,displayname,rooms` with space separated rooms and
+	/// empty columns left out. Generated passwords are shown once, in the
+	/// reply to this command.
+	BulkCreateUsers,
+
 	/// - Reset user password
 	ResetPassword {
 		/// Username of the user for whom the password should be reset
@@ -59,12 +69,41 @@ pub(super) enum UserCommand {
 		force: bool,
 	},
 
+	/// - Deactivate a list of users in the background
+	///
+	/// Users are deactivated one at a time at the pace set by
+	/// `bulk_deactivation_interval_ms`, with progress reported to the admin
+	/// room, so the admin room stays usable while a large list is processed.
+	/// All devices and access tokens of the users are removed.
+	///
+	/// This command needs a newline separated list of users provided in a
+	/// Markdown code block below the command.
+	DeactivateBulk {
+		#[arg(long)]
+		/// Clear the display name, avatar and other profile fields
+		erase: bool,
+		#[arg(long)]
+		/// Reject all pending invites
+		reject_invites: bool,
+		#[arg(long)]
+		/// Leave all joined rooms
+		leave_rooms: bool,
+		#[arg(long)]
+		/// Delete all pushers
+		remove_pushers: bool,
+		#[arg(short, long)]
+		/// Also deactivate admin accounts
+		force: bool,
+	},
+
 	/// - List local users in the database
+	#[moderator]
 	#[clap(alias = "list")]
 	ListUsers,
 
 	/// - Lists all the rooms (local and remote) that the specified user is
 	///   joined in
+	#[moderator]
 	ListJoinedRooms {
 		user_id: String,
 	},
@@ -123,6 +162,7 @@ pub(super) enum UserCommand {
 	///   user
 	///
 	/// This is only valid for local users
+	#[moderator]
 	RedactEvent {
 		event_id: Box<EventId>,
 	},
@@ -142,6 +182,24 @@ pub(super) enum UserCommand {
 		yes_i_want_to_do_this: bool,
 	},
 
+	/// - Sends a server notice to a local user, or to every local user with
+	///   `--all`.
+	///
+	/// The notice is delivered in a dedicated room created for the user on
+	/// first use. With `--all` it is sent in the background at the pace set by
+	/// `server_notices_announce_interval_ms`, with progress reported here.
+	SendNotice {
+		/// The user to send the notice to
+		#[arg(short, long, required_unless_present = "all", conflicts_with = "all")]
+		user_id: Option<String>,
+
+		/// Send the notice to all active local users
+		#[arg(short, long)]
+		all: bool,
+
+		message: Vec<String>,
+	},
+
 	/// - Force joins all local users to the specified room.
 	///
 	/// At least 1 server admin must be in the room to reduce abuse.
@@ -153,4 +211,31 @@ pub(super) enum UserCommand {
 		#[arg(long)]
 		yes_i_want_to_do_this: bool,
 	},
+
+	/// - Creates a registration token for the `m.login.registration_token`
+	///   registration flow
+	///
+	/// A random token is generated unless one is given.
+	CreateRegistrationToken {
+		/// The token, made of A-Z, a-z, 0-9, '.', '_', '~' and '-'
+		#[arg(long)]
+		token: Option<String>,
+
+		/// How many registrations the token allows; unlimited if not given
+		#[arg(long)]
+		uses_allowed: Option<u64>,
+
+		/// How long until the token expires (e.g. 30m, 7d); never if not
+		/// given
+		#[arg(long)]
+		expires_in: Option<String>,
+	},
+
+	/// - Lists registration tokens created from the admin room and their usage
+	ListRegistrationTokens,
+
+	/// - Revokes a registration token created from the admin room
+	RevokeRegistrationToken {
+		token: String,
+	},
 }
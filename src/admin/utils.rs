@@ -2,6 +2,35 @@
 use ruma::{OwnedRoomId, OwnedUserId, RoomId, UserId};
 use service::Services;
 
+/// Maps whose keys are secrets, such as access tokens.
+const SECRET_KEY_MAPS: &[&str] = &[
+	"emailsecret_sid",
+	"logintoken_expiresatuserid",
+	"openidtoken_expiresatuserid",
+	"registrationtoken_info",
+	"registrationtokensession_reservedts",
+	"token_userdeviceid",
+];
+
+/// Maps whose values are secrets, such as password hashes, the signing key in
+/// `global` and the tokens of appservice registrations.
+const SECRET_VAL_MAPS: &[&str] = &[
+	"emailsid_session",
+	"global",
+	"id_appserviceregistrations",
+	"userdevicesessionid_uiaainfo",
+	"userdeviceid_token",
+	"userid_password",
+];
+
+/// Whether the keys of the database map must not be shown.
+pub(crate) fn is_secret_key(map: &str) -> bool { SECRET_KEY_MAPS.contains(&map) }
+
+/// Whether the values of the database map must not be shown.
+pub(crate) fn is_secret_val(map: &str) -> bool { SECRET_VAL_MAPS.contains(&map) }
+
+pub(crate) fn redacted(bytes: &[u8]) -> String { format!("<redacted {} bytes>", bytes.len()) }
+
 pub(crate) fn escape_html(s: &str) -> String {
 	s.replace('&', "&amp;")
 		.replace('<', "&lt;")
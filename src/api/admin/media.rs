@@ -0,0 +1,159 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use axum::extract::State;
+use conduwuit::{err, info, Err, Result};
+use ruma::Mxc;
+
+use super::check_admin;
+use crate::Ruma;
+
+/// # `DELETE /_synapse/admin/v1/media/{serverName}/{mediaId}`
+///
+/// Deletes a single piece of media from the database and the filesystem.
+pub(crate) async fn admin_delete_media_route(
+	State(services): State<crate::State>,
+	body: Ruma<delete_media::Request>,
+) -> Result<delete_media::Response> {
+	check_admin(&services, body.sender_user()).await?;
+
+	let mxc = Mxc {
+		server_name: &body.server_name,
+		media_id: &body.media_id,
+	};
+
+	services.media.delete(&mxc).await?;
+
+	Ok(delete_media::Response {
+		deleted_media: vec![body.media_id.clone()],
+		total: 1,
+	})
+}
+
+/// # `DELETE /_synapse/admin/v1/users/{userId}/media`
+///
+/// Deletes all media uploaded by a local user.
+pub(crate) async fn admin_delete_user_media_route(
+	State(services): State<crate::State>,
+	body: Ruma<delete_user_media::Request>,
+) -> Result<delete_user_media::Response> {
+	check_admin(&services, body.sender_user()).await?;
+
+	if !services.globals.user_is_local(&body.user_id) {
+		return Err!(Request(InvalidParam("Can only delete media of local users.")));
+	}
+
+	let total = services.media.delete_from_user(&body.user_id).await?;
+
+	Ok(delete_user_media::Response { total })
+}
+
+/// # `POST /_synapse/admin/v1/purge_media_cache`
+///
+/// Deletes cached remote media last accessed before `before_ts`.
+pub(crate) async fn admin_purge_media_cache_route(
+	State(services): State<crate::State>,
+	body: Ruma<purge_media_cache::Request>,
+) -> Result<purge_media_cache::Response> {
+	let sender_user = body.sender_user();
+	check_admin(&services, sender_user).await?;
+
+	let before = UNIX_EPOCH
+		.checked_add(Duration::from_millis(body.before_ts))
+		.ok_or_else(|| err!(Request(InvalidParam("before_ts is out of range."))))?;
+
+	let deleted = services
+		.media
+		.delete_all_remote_media_at_after_time(before, true, false, false)
+		.await?;
+
+	info!("{sender_user} purged {deleted} remote media files through the admin API.");
+
+	Ok(purge_media_cache::Response { deleted })
+}
+
+pub(crate) mod delete_media {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata, OwnedServerName,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: DELETE,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_synapse/admin/v1/media/:server_name/:media_id",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		#[ruma_api(path)]
+		pub server_name: OwnedServerName,
+
+		#[ruma_api(path)]
+		pub media_id: String,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		pub deleted_media: Vec<String>,
+
+		pub total: usize,
+	}
+}
+
+pub(crate) mod delete_user_media {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata, OwnedUserId,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: DELETE,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_synapse/admin/v1/users/:user_id/media",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		#[ruma_api(path)]
+		pub user_id: OwnedUserId,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		pub total: usize,
+	}
+}
+
+pub(crate) mod purge_media_cache {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: POST,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_synapse/admin/v1/purge_media_cache",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		/// Milliseconds since the unix epoch.
+		#[ruma_api(query)]
+		pub before_ts: u64,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		pub deleted: usize,
+	}
+}
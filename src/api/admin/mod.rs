@@ -0,0 +1,32 @@
+//! A subset of the [Synapse admin API](https://element-hq.github.io/synapse/latest/usage/administration/admin_api/index.html)
+//! so existing tooling such as synapse-admin can manage this server. Every
+//! endpoint requires the caller to be a server admin.
+//!
+//! Ruma does not provide these endpoints so they are defined here.
+
+pub(super) mod media;
+pub(super) mod rooms;
+pub(super) mod users;
+
+use conduwuit::{Err, Result};
+use ruma::UserId;
+use service::Services;
+
+pub(super) use self::{media::*, rooms::*, users::*};
+
+/// Page size used when the client doesn't ask for one.
+const DEFAULT_LIMIT: usize = 100;
+
+async fn check_admin(services: &Services, user_id: &UserId) -> Result {
+	if !services.users.is_admin(user_id).await {
+		return Err!(Request(Forbidden("You are not a server admin.")));
+	}
+
+	Ok(())
+}
+
+/// The `next_token`/`next_batch` Synapse returns when more results remain.
+fn next_offset(from: usize, returned: usize, total: usize) -> Option<usize> {
+	let next = from.saturating_add(returned);
+	(next < total).then_some(next)
+}
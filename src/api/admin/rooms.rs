@@ -0,0 +1,298 @@
+use axum::extract::State;
+use conduwuit::{info, warn, Err, Result};
+use futures::StreamExt;
+use ruma::{OwnedRoomAliasId, OwnedRoomId, OwnedUserId, RoomId, RoomVersionId};
+use serde::{Deserialize, Serialize};
+use service::{jobs::JobKind, Services};
+
+use super::{check_admin, next_offset, DEFAULT_LIMIT};
+use crate::{client::leave_room, Ruma};
+
+/// # `GET /_synapse/admin/v1/rooms`
+///
+/// Lists the rooms known to this server, paginated by offset.
+pub(crate) async fn admin_list_rooms_route(
+	State(services): State<crate::State>,
+	body: Ruma<list_rooms::Request>,
+) -> Result<list_rooms::Response> {
+	check_admin(&services, body.sender_user()).await?;
+
+	let room_ids: Vec<OwnedRoomId> = services
+		.rooms
+		.metadata
+		.iter_ids()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let total_rooms = room_ids.len();
+	let offset = body.from.unwrap_or(0);
+	let limit = body.limit.unwrap_or(DEFAULT_LIMIT);
+
+	let mut rooms = Vec::with_capacity(limit.min(total_rooms));
+	for room_id in room_ids.iter().skip(offset).take(limit) {
+		rooms.push(room_details(&services, room_id).await);
+	}
+
+	Ok(list_rooms::Response {
+		next_batch: next_offset(offset, rooms.len(), total_rooms),
+		rooms,
+		offset,
+		total_rooms,
+	})
+}
+
+/// # `GET /_synapse/admin/v1/rooms/{roomId}`
+///
+/// Gets the details of a room known to this server.
+pub(crate) async fn admin_get_room_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_room::Request>,
+) -> Result<get_room::Response> {
+	check_admin(&services, body.sender_user()).await?;
+
+	if !services.rooms.metadata.exists(&body.room_id).await {
+		return Err!(Request(NotFound("Room not found.")));
+	}
+
+	Ok(get_room::Response {
+		room: room_details(&services, &body.room_id).await,
+	})
+}
+
+/// # `DELETE /_synapse/admin/v1/rooms/{roomId}`
+///
+/// Removes all local users from a room, drops its local aliases and
+/// unpublishes it from the room directory. With `block` set the room is also
+/// banned so nobody local can join it again. With `purge` set its history,
+/// state and the media only it refers to are then deleted by a background
+/// job, shown by `!admin jobs`.
+pub(crate) async fn admin_delete_room_route(
+	State(services): State<crate::State>,
+	body: Ruma<delete_room::Request>,
+) -> Result<delete_room::Response> {
+	let sender_user = body.sender_user();
+	check_admin(&services, sender_user).await?;
+
+	let room_id = &body.room_id;
+	if services.admin.is_admin_room(room_id).await {
+		return Err!(Request(Forbidden("Not allowed to delete the admin room.")));
+	}
+
+	if body.block {
+		services.rooms.metadata.ban_room(room_id, true);
+	}
+
+	let local_users: Vec<OwnedUserId> = services
+		.rooms
+		.state_cache
+		.local_users_in_room(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let (mut kicked_users, mut failed_to_kick_users) = (Vec::new(), Vec::new());
+	for user_id in local_users {
+		match leave_room(&services, &user_id, room_id, None).await {
+			| Ok(()) => kicked_users.push(user_id),
+			| Err(e) => {
+				warn!(%user_id, "Failed to remove user from {room_id}: {e}");
+				failed_to_kick_users.push(user_id);
+			},
+		}
+	}
+
+	let local_aliases: Vec<OwnedRoomAliasId> = services
+		.rooms
+		.alias
+		.local_aliases_for_room(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for alias in &local_aliases {
+		services
+			.rooms
+			.alias
+			.remove_alias(alias, &services.globals.server_user)
+			.await
+			.ok();
+	}
+
+	services.rooms.directory.set_not_public(room_id);
+
+	if body.purge {
+		services.jobs.enqueue(JobKind::DeleteRoom {
+			room_id: room_id.clone(),
+			block: body.block,
+			purge: true,
+			message: None,
+		})?;
+	}
+
+	info!("Room {room_id} was deleted by {sender_user} through the admin API.");
+
+	Ok(delete_room::Response {
+		kicked_users,
+		failed_to_kick_users,
+		local_aliases,
+		new_room_id: None,
+	})
+}
+
+/// A room as Synapse's admin API describes it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct RoomDetails {
+	pub room_id: OwnedRoomId,
+
+	pub name: Option<String>,
+
+	pub canonical_alias: Option<OwnedRoomAliasId>,
+
+	pub joined_members: u64,
+
+	pub joined_local_members: usize,
+
+	pub version: Option<RoomVersionId>,
+
+	pub public: bool,
+}
+
+async fn room_details(services: &Services, room_id: &RoomId) -> RoomDetails {
+	RoomDetails {
+		room_id: room_id.to_owned(),
+		name: services.rooms.state_accessor.get_name(room_id).await.ok(),
+		canonical_alias: services
+			.rooms
+			.state_accessor
+			.get_canonical_alias(room_id)
+			.await
+			.ok(),
+		joined_members: services
+			.rooms
+			.state_cache
+			.room_joined_count(room_id)
+			.await
+			.unwrap_or(0),
+		joined_local_members: services
+			.rooms
+			.state_cache
+			.local_users_in_room(room_id)
+			.count()
+			.await,
+		version: services.rooms.state.get_room_version(room_id).await.ok(),
+		public: services.rooms.directory.is_public_room(room_id).await,
+	}
+}
+
+pub(crate) mod list_rooms {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata,
+	};
+
+	pub use super::RoomDetails;
+
+	const METADATA: Metadata = metadata! {
+		method: GET,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_synapse/admin/v1/rooms",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		#[ruma_api(query)]
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		pub from: Option<usize>,
+
+		#[ruma_api(query)]
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		pub limit: Option<usize>,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		pub rooms: Vec<RoomDetails>,
+
+		pub offset: usize,
+
+		pub total_rooms: usize,
+
+		#[serde(skip_serializing_if = "Option::is_none")]
+		pub next_batch: Option<usize>,
+	}
+}
+
+pub(crate) mod get_room {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata, OwnedRoomId,
+	};
+
+	pub use super::RoomDetails;
+
+	const METADATA: Metadata = metadata! {
+		method: GET,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_synapse/admin/v1/rooms/:room_id",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		#[ruma_api(path)]
+		pub room_id: OwnedRoomId,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		#[serde(flatten)]
+		pub room: RoomDetails,
+	}
+}
+
+pub(crate) mod delete_room {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata, OwnedRoomAliasId, OwnedRoomId, OwnedUserId,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: DELETE,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_synapse/admin/v1/rooms/:room_id",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		#[ruma_api(path)]
+		pub room_id: OwnedRoomId,
+
+		/// Ban the room so local users can't join it again.
+		#[serde(default)]
+		pub block: bool,
+
+		/// Also delete the room's history and state in the background.
+		#[serde(default)]
+		pub purge: bool,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		pub kicked_users: Vec<OwnedUserId>,
+
+		pub failed_to_kick_users: Vec<OwnedUserId>,
+
+		pub local_aliases: Vec<OwnedRoomAliasId>,
+
+		pub new_room_id: Option<OwnedRoomId>,
+	}
+}
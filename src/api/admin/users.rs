@@ -0,0 +1,236 @@
+use axum::extract::State;
+use conduwuit::{info, Err, Result};
+use futures::StreamExt;
+use ruma::{OwnedRoomId, OwnedUserId, UserId};
+use serde::{Deserialize, Serialize};
+use service::Services;
+
+use super::{check_admin, next_offset, DEFAULT_LIMIT};
+use crate::{client::full_user_deactivate, Ruma};
+
+/// # `GET /_synapse/admin/v2/users`
+///
+/// Lists local users, paginated by offset.
+pub(crate) async fn admin_list_users_route(
+	State(services): State<crate::State>,
+	body: Ruma<list_users::Request>,
+) -> Result<list_users::Response> {
+	check_admin(&services, body.sender_user()).await?;
+
+	let user_ids: Vec<OwnedUserId> = services
+		.users
+		.list_local_users()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let total = user_ids.len();
+	let from = body.from.unwrap_or(0);
+	let limit = body.limit.unwrap_or(DEFAULT_LIMIT);
+
+	let mut users = Vec::with_capacity(limit.min(total));
+	for user_id in user_ids.iter().skip(from).take(limit) {
+		users.push(user_details(&services, user_id).await);
+	}
+
+	Ok(list_users::Response {
+		next_token: next_offset(from, users.len(), total).map(|next| next.to_string()),
+		users,
+		total,
+	})
+}
+
+/// # `GET /_synapse/admin/v2/users/{userId}`
+///
+/// Gets the details of a local user.
+pub(crate) async fn admin_get_user_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_user::Request>,
+) -> Result<get_user::Response> {
+	check_admin(&services, body.sender_user()).await?;
+
+	if !services.globals.user_is_local(&body.user_id)
+		|| !services.users.exists(&body.user_id).await
+	{
+		return Err!(Request(NotFound("User not found.")));
+	}
+
+	Ok(get_user::Response {
+		user: user_details(&services, &body.user_id).await,
+	})
+}
+
+/// # `POST /_synapse/admin/v1/deactivate/{userId}`
+///
+/// Deactivates a local user and removes them from all their rooms. Erasing
+/// their messages is rejected as unsupported.
+pub(crate) async fn admin_deactivate_user_route(
+	State(services): State<crate::State>,
+	body: Ruma<deactivate_user::Request>,
+) -> Result<deactivate_user::Response> {
+	let sender_user = body.sender_user();
+	check_admin(&services, sender_user).await?;
+
+	let user_id = &body.user_id;
+	if !services.globals.user_is_local(user_id) || !services.users.exists(user_id).await {
+		return Err!(Request(NotFound("User not found.")));
+	}
+
+	if *user_id == services.globals.server_user {
+		return Err!(Request(Forbidden("Not allowed to deactivate the server service account.")));
+	}
+
+	if body.erase {
+		return Err!(Request(InvalidParam("Erasing a user's messages is not supported.")));
+	}
+
+	let all_joined_rooms: Vec<OwnedRoomId> = services
+		.rooms
+		.state_cache
+		.rooms_joined(user_id)
+		.map(Into::into)
+		.collect()
+		.await;
+
+	full_user_deactivate(&services, user_id, &all_joined_rooms).await?;
+
+	info!("User {user_id} was deactivated by {sender_user} through the admin API.");
+
+	Ok(deactivate_user::Response {
+		id_server_unbind_result: "no-support".to_owned(),
+	})
+}
+
+/// A user as Synapse's admin API describes them.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct UserDetails {
+	pub name: OwnedUserId,
+
+	pub displayname: Option<String>,
+
+	pub avatar_url: Option<String>,
+
+	pub admin: bool,
+
+	pub deactivated: bool,
+}
+
+async fn user_details(services: &Services, user_id: &UserId) -> UserDetails {
+	UserDetails {
+		name: user_id.to_owned(),
+		displayname: services.users.displayname(user_id).await.ok(),
+		avatar_url: services
+			.users
+			.avatar_url(user_id)
+			.await
+			.ok()
+			.map(|url| url.to_string()),
+		admin: services.users.is_admin(user_id).await,
+		deactivated: services
+			.users
+			.is_deactivated(user_id)
+			.await
+			.unwrap_or(false),
+	}
+}
+
+pub(crate) mod list_users {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata,
+	};
+
+	pub use super::UserDetails;
+
+	const METADATA: Metadata = metadata! {
+		method: GET,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_synapse/admin/v2/users",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		#[ruma_api(query)]
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		pub from: Option<usize>,
+
+		#[ruma_api(query)]
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		pub limit: Option<usize>,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		pub users: Vec<UserDetails>,
+
+		#[serde(skip_serializing_if = "Option::is_none")]
+		pub next_token: Option<String>,
+
+		pub total: usize,
+	}
+}
+
+pub(crate) mod get_user {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata, OwnedUserId,
+	};
+
+	pub use super::UserDetails;
+
+	const METADATA: Metadata = metadata! {
+		method: GET,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_synapse/admin/v2/users/:user_id",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		#[ruma_api(path)]
+		pub user_id: OwnedUserId,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		#[serde(flatten)]
+		pub user: UserDetails,
+	}
+}
+
+pub(crate) mod deactivate_user {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata, OwnedUserId,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: POST,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_synapse/admin/v1/deactivate/:user_id",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		#[ruma_api(path)]
+		pub user_id: OwnedUserId,
+
+		/// Hide the user's messages from those who join rooms later. Not
+		/// supported; the profile is always cleared.
+		#[serde(default)]
+		pub erase: bool,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		pub id_server_unbind_result: String,
+	}
+}
@@ -1,23 +1,29 @@
 use std::fmt::Write;
 
-use axum::extract::State;
+use axum::{
+	extract::{Query, State},
+	response::IntoResponse,
+};
 use axum_client_ip::InsecureClientIp;
 use conduwuit::{
-	debug_info, error, info, is_equal_to, utils, utils::ReadyExt, warn, Error, PduBuilder, Result,
+	debug_info, error, info, is_equal_to, utils, utils::ReadyExt, warn, Err, Error, PduBuilder,
+	Result,
 };
 use futures::{FutureExt, StreamExt};
+use http::StatusCode;
 use register::RegistrationKind;
 use ruma::{
 	api::client::{
 		account::{
-			change_password, check_registration_token_validity, deactivate, get_3pids,
-			get_username_availability,
+			add_3pid, change_password, check_registration_token_validity, deactivate,
+			delete_3pid, get_3pids, get_username_availability,
 			register::{self, LoginType},
 			request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn,
+			request_password_change_token_via_email, request_registration_token_via_email,
 			whoami, ThirdPartyIdRemovalStatus,
 		},
 		error::ErrorKind,
-		uiaa::{AuthFlow, AuthType, UiaaInfo},
+		uiaa::{AuthData, AuthFlow, AuthType, UiaaInfo},
 	},
 	events::{
 		room::{
@@ -26,9 +32,12 @@
 		},
 		GlobalAccountDataEventType, StateEventType,
 	},
-	push, OwnedRoomId, UserId,
+	push,
+	thirdparty::{Medium, ThirdPartyIdentifierInit},
+	MilliSecondsSinceUnixEpoch, OwnedRoomId, UserId,
 };
-use service::Services;
+use serde::Deserialize;
+use service::{email::ValidationKind, Services};
 
 use super::{join_room_by_id_helper, DEVICE_ID_LENGTH, SESSION_ID_LENGTH, TOKEN_LENGTH};
 use crate::Ruma;
@@ -132,10 +141,11 @@ pub(crate) async fn register_route(
 	if is_guest
 		&& (!services.globals.allow_guest_registration()
 			|| (services.globals.allow_registration()
-				&& services.globals.registration_token.is_some()))
+				&& (services.registration_tokens.required().await
+					|| services.server.config.registration_requires_email)))
 	{
 		info!(
-			"Guest registration disabled / registration enabled with token configured, \
+			"Guest registration disabled / registration enabled with token or email configured, \
 			 rejecting guest registration attempt, initial device name: \"{}\"",
 			body.initial_device_display_name.as_deref().unwrap_or("")
 		);
@@ -230,31 +240,34 @@ pub(crate) async fn register_route(
 	}
 
 	// UIAA
-	let mut uiaainfo;
-	let skip_auth = if services.globals.registration_token.is_some() {
-		// Registration token required
-		uiaainfo = UiaaInfo {
-			flows: vec![AuthFlow {
-				stages: vec![AuthType::RegistrationToken],
-			}],
-			completed: Vec::new(),
-			params: Box::default(),
-			session: None,
-			auth_error: None,
-		};
-		body.appservice_info.is_some()
-	} else {
-		// No registration token necessary, but clients must still go through the flow
-		uiaainfo = UiaaInfo {
-			flows: vec![AuthFlow { stages: vec![AuthType::Dummy] }],
-			completed: Vec::new(),
-			params: Box::default(),
-			session: None,
-			auth_error: None,
-		};
+	let mut stages = Vec::new();
+	if services.registration_tokens.required().await {
+		stages.push(AuthType::RegistrationToken);
+	}
+
+	if services.server.config.registration_requires_email {
+		stages.push(AuthType::EmailIdentity);
+	}
+
+	let skip_auth = if stages.is_empty() {
+		// No registration token or email necessary, but clients must still go
+		// through the flow
+		stages.push(AuthType::Dummy);
 		body.appservice_info.is_some() || is_guest
+	} else {
+		body.appservice_info.is_some()
 	};
 
+	let mut uiaainfo = UiaaInfo {
+		flows: vec![AuthFlow { stages }],
+		completed: Vec::new(),
+		params: Box::default(),
+		session: None,
+		auth_error: None,
+	};
+
+	let mut completed_auth = Vec::new();
+	let mut session = None;
 	if !skip_auth {
 		if let Some(auth) = &body.auth {
 			let (worked, uiaainfo) = services
@@ -270,7 +283,11 @@ pub(crate) async fn register_route(
 			if !worked {
 				return Err(Error::Uiaa(uiaainfo));
 			}
-		// Success!
+
+			// Success!
+			let completed_session = uiaainfo.session.expect("session is always set");
+			completed_auth = services.uiaa.take_completed_auth(&completed_session);
+			session = Some(completed_session);
 		} else if let Some(json) = body.json_body {
 			uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
 			services.uiaa.create(
@@ -288,8 +305,68 @@ pub(crate) async fn register_route(
 
 	let password = if is_guest { None } else { body.password.as_deref() };
 
+	// A registration token from the store was reserved for the session by the
+	// UIAA stage above
+	let registration_token = completed_auth
+		.iter()
+		.find_map(|auth| match auth {
+			| AuthData::RegistrationToken(auth) => Some(auth.token.trim()),
+			| _ => None,
+		})
+		.zip(session.as_deref());
+
+	let email_creds = completed_auth.iter().find_map(|auth| match auth {
+		| AuthData::EmailIdentity(auth) => Some(&auth.thirdparty_id_creds),
+		| _ => None,
+	});
+
 	// Create user
-	services.users.create(&user_id, password)?;
+	let created = async {
+		let email = match email_creds {
+			| Some(creds) => Some(
+				services
+					.email
+					.validated_email(
+						creds.sid.as_str(),
+						creds.client_secret.as_str(),
+						Some(ValidationKind::Registration),
+					)
+					.await?,
+			),
+			| None => None,
+		};
+
+		if let Some(email) = &email {
+			if services.email.find_user(email).await.is_ok() {
+				return Err!(Request(ThreepidInUse("This email address is already in use.")));
+			}
+		}
+
+		services.users.create(&user_id, password)?;
+
+		Ok(email)
+	}
+	.await;
+
+	let email = match created {
+		| Ok(email) => email,
+		| Err(e) => {
+			if let Some((token, session)) = registration_token {
+				services.registration_tokens.release(token, session).await;
+			}
+
+			return Err(e);
+		},
+	};
+
+	if let Some((token, session)) = registration_token {
+		services.registration_tokens.complete(token, session).await;
+	}
+
+	if let (Some(email), Some(creds)) = (email, email_creds) {
+		services.email.bind(&user_id, &email).await?;
+		services.email.consume(creds.sid.as_str()).await;
+	}
 
 	// Default to pretty displayname
 	let mut displayname = user_id.localpart().to_owned();
@@ -323,6 +400,10 @@ pub(crate) async fn register_route(
 		)
 		.await?;
 
+	if body.appservice_info.is_none() && !is_guest {
+		services.onboarding.run_registration_hooks(&user_id).await;
+	}
+
 	// Inhibit login does not work for guests
 	if !is_guest && body.inhibit_login {
 		return Ok(register::v3::Response {
@@ -516,16 +597,17 @@ pub(crate) async fn change_password_route(
 	InsecureClientIp(client): InsecureClientIp,
 	body: Ruma<change_password::v3::Request>,
 ) -> Result<change_password::v3::Response> {
-	// Authentication for this endpoint was made optional, but we need
-	// authentication currently
-	let sender_user = body
-		.sender_user
-		.as_ref()
-		.ok_or_else(|| Error::BadRequest(ErrorKind::MissingToken, "Missing access token."))?;
+	// Without authentication, the password can only be reset through an email
+	// address bound to the account
+	if body.sender_user.is_none() {
+		return reset_password_with_email(&services, body).await;
+	}
+
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
 	let mut uiaainfo = UiaaInfo {
-		flows: vec![AuthFlow { stages: vec![AuthType::Password] }],
+		flows: services.uiaa.reauth_flows(),
 		completed: Vec::new(),
 		params: Box::default(),
 		session: None,
@@ -583,6 +665,110 @@ pub(crate) async fn change_password_route(
 	Ok(change_password::v3::Response {})
 }
 
+/// Resets the password of the account an email address validated through
+/// `/account/password/email/requestToken` is bound to.
+async fn reset_password_with_email(
+	services: &Services,
+	body: Ruma<change_password::v3::Request>,
+) -> Result<change_password::v3::Response> {
+	if !services.email.enabled() {
+		return Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token."));
+	}
+
+	let mut uiaainfo = UiaaInfo {
+		flows: vec![AuthFlow { stages: vec![AuthType::EmailIdentity] }],
+		completed: Vec::new(),
+		params: Box::default(),
+		session: None,
+		auth_error: None,
+	};
+
+	let anonymous = UserId::parse_with_server_name("", services.globals.server_name())
+		.expect("we know this is valid");
+
+	let Some(auth) = &body.auth else {
+		let Some(json) = body.json_body else {
+			return Err(Error::BadRequest(ErrorKind::NotJson, "Not json."));
+		};
+
+		uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
+		services
+			.uiaa
+			.create(&anonymous, "".into(), &uiaainfo, &json);
+
+		return Err(Error::Uiaa(uiaainfo));
+	};
+
+	let (worked, uiaainfo) = services
+		.uiaa
+		.try_auth(&anonymous, "".into(), auth, &uiaainfo)
+		.await?;
+
+	if !worked {
+		return Err(Error::Uiaa(uiaainfo));
+	}
+
+	let session = uiaainfo.session.as_deref().expect("session is always set");
+	let Some(creds) = services
+		.uiaa
+		.take_completed_auth(session)
+		.into_iter()
+		.find_map(|auth| match auth {
+			| AuthData::EmailIdentity(auth) => Some(auth.thirdparty_id_creds),
+			| _ => None,
+		})
+	else {
+		return Err!(Request(ThreepidAuthFailed("No validated email address was given.")));
+	};
+
+	let email = services
+		.email
+		.validated_email(
+			creds.sid.as_str(),
+			creds.client_secret.as_str(),
+			Some(ValidationKind::PasswordReset),
+		)
+		.await?;
+
+	let user_id = services.email.find_user(&email).await?;
+	if services
+		.users
+		.is_deactivated(&user_id)
+		.await
+		.unwrap_or(true)
+	{
+		return Err!(Request(Forbidden("This account has been deactivated.")));
+	}
+
+	services
+		.users
+		.set_password(&user_id, Some(&body.new_password))?;
+
+	services.email.consume(creds.sid.as_str()).await;
+
+	if body.logout_devices {
+		services
+			.users
+			.all_device_ids(&user_id)
+			.for_each(|id| services.users.remove_device(&user_id, id))
+			.await;
+	}
+
+	info!("User {user_id} reset their password through their email address.");
+
+	if services.server.config.admin_room_notices {
+		services
+			.admin
+			.send_message(RoomMessageEventContent::notice_plain(format!(
+				"User {user_id} reset their password through their email address."
+			)))
+			.await
+			.ok();
+	}
+
+	Ok(change_password::v3::Response {})
+}
+
 /// # `GET _matrix/client/r0/account/whoami`
 ///
 /// Get `user_id` of the sender user.
@@ -629,7 +815,7 @@ pub(crate) async fn deactivate_route(
 	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
 	let mut uiaainfo = UiaaInfo {
-		flows: vec![AuthFlow { stages: vec![AuthType::Password] }],
+		flows: services.uiaa.reauth_flows(),
 		completed: Vec::new(),
 		params: Box::default(),
 		session: None,
@@ -692,13 +878,33 @@ pub(crate) async fn deactivate_route(
 ///
 /// Get a list of third party identifiers associated with this account.
 ///
-/// - Currently always returns empty list
+/// - Only email addresses are supported
 pub(crate) async fn third_party_route(
+	State(services): State<crate::State>,
 	body: Ruma<get_3pids::v3::Request>,
 ) -> Result<get_3pids::v3::Response> {
-	let _sender_user = body.sender_user.as_ref().expect("user is authenticated");
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
-	Ok(get_3pids::v3::Response::new(Vec::new()))
+	let threepids = services
+		.email
+		.user_emails(sender_user)
+		.map(|(address, binding)| {
+			ThirdPartyIdentifierInit {
+				address,
+				medium: Medium::Email,
+				validated_at: MilliSecondsSinceUnixEpoch(
+					binding.validated_at.try_into().unwrap_or_default(),
+				),
+				added_at: MilliSecondsSinceUnixEpoch(
+					binding.added_at.try_into().unwrap_or_default(),
+				),
+			}
+			.into()
+		})
+		.collect()
+		.await;
+
+	Ok(get_3pids::v3::Response::new(threepids))
 }
 
 /// # `POST /_matrix/client/v3/account/3pid/email/requestToken`
@@ -709,12 +915,186 @@ pub(crate) async fn third_party_route(
 /// - 403 signals that The homeserver does not allow the third party identifier
 ///   as a contact option.
 pub(crate) async fn request_3pid_management_token_via_email_route(
-	_body: Ruma<request_3pid_management_token_via_email::v3::Request>,
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	body: Ruma<request_3pid_management_token_via_email::v3::Request>,
 ) -> Result<request_3pid_management_token_via_email::v3::Response> {
-	Err(Error::BadRequest(
-		ErrorKind::ThreepidDenied,
-		"Third party identifier is not allowed",
-	))
+	if services.email.find_user(&body.email).await.is_ok() {
+		return Err!(Request(ThreepidInUse("This email address is already in use.")));
+	}
+
+	let sid = services
+		.email
+		.request_token(
+			ValidationKind::AddThreepid,
+			&body.email,
+			&body.client_secret,
+			body.send_attempt.into(),
+			client,
+		)
+		.await?;
+
+	Ok(request_3pid_management_token_via_email::v3::Response::new(sid))
+}
+
+/// # `POST /_matrix/client/v3/register/email/requestToken`
+///
+/// Sends a validation email for registering with the `m.login.email.identity`
+/// UIAA stage.
+pub(crate) async fn request_registration_token_via_email_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	body: Ruma<request_registration_token_via_email::v3::Request>,
+) -> Result<request_registration_token_via_email::v3::Response> {
+	if !services.globals.allow_registration() {
+		return Err!(Request(Forbidden("Registration has been disabled.")));
+	}
+
+	if services.email.find_user(&body.email).await.is_ok() {
+		return Err!(Request(ThreepidInUse("This email address is already in use.")));
+	}
+
+	let sid = services
+		.email
+		.request_token(
+			ValidationKind::Registration,
+			&body.email,
+			&body.client_secret,
+			body.send_attempt.into(),
+			client,
+		)
+		.await?;
+
+	Ok(request_registration_token_via_email::v3::Response::new(sid))
+}
+
+/// # `POST /_matrix/client/v3/account/password/email/requestToken`
+///
+/// Sends a validation email for resetting the password of the account the
+/// address is bound to.
+pub(crate) async fn request_password_change_token_via_email_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	body: Ruma<request_password_change_token_via_email::v3::Request>,
+) -> Result<request_password_change_token_via_email::v3::Response> {
+	services.email.find_user(&body.email).await?;
+
+	let sid = services
+		.email
+		.request_token(
+			ValidationKind::PasswordReset,
+			&body.email,
+			&body.client_secret,
+			body.send_attempt.into(),
+			client,
+		)
+		.await?;
+
+	Ok(request_password_change_token_via_email::v3::Response::new(sid))
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/add`
+///
+/// Adds an email address validated through `/account/3pid/email/requestToken`
+/// to the account.
+///
+/// - Requires UIAA to verify user password
+pub(crate) async fn add_3pid_route(
+	State(services): State<crate::State>,
+	body: Ruma<add_3pid::v3::Request>,
+) -> Result<add_3pid::v3::Response> {
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
+
+	let mut uiaainfo = UiaaInfo {
+		flows: services.uiaa.reauth_flows(),
+		completed: Vec::new(),
+		params: Box::default(),
+		session: None,
+		auth_error: None,
+	};
+
+	if let Some(auth) = &body.auth {
+		let (worked, uiaainfo) = services
+			.uiaa
+			.try_auth(sender_user, sender_device, auth, &uiaainfo)
+			.await?;
+
+		if !worked {
+			return Err(Error::Uiaa(uiaainfo));
+		}
+	} else if let Some(json) = body.json_body {
+		uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
+		services
+			.uiaa
+			.create(sender_user, sender_device, &uiaainfo, &json);
+
+		return Err(Error::Uiaa(uiaainfo));
+	} else {
+		return Err(Error::BadRequest(ErrorKind::NotJson, "Not json."));
+	}
+
+	let email = services
+		.email
+		.validated_email(
+			body.sid.as_str(),
+			body.client_secret.as_str(),
+			Some(ValidationKind::AddThreepid),
+		)
+		.await?;
+
+	services.email.bind(sender_user, &email).await?;
+	services.email.consume(body.sid.as_str()).await;
+
+	Ok(add_3pid::v3::Response {})
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/delete`
+///
+/// Removes an email address from the account.
+pub(crate) async fn delete_3pid_route(
+	State(services): State<crate::State>,
+	body: Ruma<delete_3pid::v3::Request>,
+) -> Result<delete_3pid::v3::Response> {
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+	if body.medium != Medium::Email {
+		return Err!(Request(InvalidParam("Only email addresses are supported.")));
+	}
+
+	services.email.unbind(sender_user, &body.address).await?;
+
+	Ok(delete_3pid::v3::Response::new(ThirdPartyIdRemovalStatus::NoSupport))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ValidateEmailQuery {
+	sid: String,
+	client_secret: String,
+	token: String,
+}
+
+/// # `GET /_conduwuit/email/validate`
+///
+/// Followed from the link in validation emails.
+pub(crate) async fn validate_email_route(
+	State(services): State<crate::State>,
+	Query(query): Query<ValidateEmailQuery>,
+) -> impl IntoResponse {
+	match services
+		.email
+		.validate(&query.sid, &query.client_secret, &query.token)
+		.await
+	{
+		| Ok(()) => (
+			StatusCode::OK,
+			"Your email address has been validated. You can return to your client to continue.",
+		),
+		| Err(e) => {
+			debug_info!("Failed to validate email session {}: {e}", query.sid);
+			(StatusCode::BAD_REQUEST, "This validation link is invalid or has expired.")
+		},
+	}
 }
 
 /// # `POST /_matrix/client/v3/account/3pid/msisdn/requestToken`
@@ -737,20 +1117,21 @@ pub(crate) async fn request_3pid_management_token_via_msisdn_route(
 ///
 /// Checks if the provided registration token is valid at the time of checking
 ///
-/// Currently does not have any ratelimiting, and this isn't very practical as
-/// there is only one registration token allowed.
+/// Currently does not have any ratelimiting.
 pub(crate) async fn check_registration_token_validity(
 	State(services): State<crate::State>,
 	body: Ruma<check_registration_token_validity::v1::Request>,
 ) -> Result<check_registration_token_validity::v1::Response> {
-	let Some(reg_token) = services.globals.registration_token.clone() else {
+	if !services.registration_tokens.required().await {
 		return Err(Error::BadRequest(
 			ErrorKind::forbidden(),
 			"Server does not allow token registration.",
 		));
-	};
+	}
+
+	let valid = services.registration_tokens.is_valid(&body.token).await;
 
-	Ok(check_registration_token_validity::v1::Response { valid: reg_token == body.token })
+	Ok(check_registration_token_validity::v1::Response { valid })
 }
 
 /// Runs through all the deactivation steps:
@@ -766,6 +1147,7 @@ pub async fn full_user_deactivate(
 	all_joined_rooms: &[OwnedRoomId],
 ) -> Result<()> {
 	services.users.deactivate_account(user_id).await.ok();
+	services.email.unbind_all(user_id).await;
 	super::update_displayname(services, user_id, None, all_joined_rooms).await;
 	super::update_avatar_url(services, user_id, None, None, all_joined_rooms).await;
 
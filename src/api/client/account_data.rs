@@ -134,6 +134,36 @@ async fn set_account_data(
 	let data: serde_json::Value = serde_json::from_str(data.get())
 		.map_err(|e| err!(Request(BadJson(warn!("Invalid JSON provided: {e}")))))?;
 
+	if MARKED_UNREAD.contains(&event_type_s) {
+		let Some(room_id) = room_id else {
+			return Err!(Request(BadJson(
+				"{event_type_s} can only be set as room account data."
+			)));
+		};
+
+		MarkedUnreadContent::deserialize(&data).map_err(|e| {
+			err!(Request(BadJson(debug_warn!("Invalid {event_type_s} content: {e}"))))
+		})?;
+
+		// Clients still on the unstable prefix see the flag set by updated clients
+		// and the other way around.
+		for event_type_s in MARKED_UNREAD {
+			update(services, Some(room_id), sender_user, event_type_s, &data).await?;
+		}
+
+		return Ok(());
+	}
+
+	update(services, room_id, sender_user, event_type_s, &data).await
+}
+
+async fn update(
+	services: &Services,
+	room_id: Option<&RoomId>,
+	sender_user: &UserId,
+	event_type_s: &str,
+	data: &serde_json::Value,
+) -> Result {
 	services
 		.account_data
 		.update(
@@ -148,6 +178,15 @@ async fn set_account_data(
 		.await
 }
 
+/// Stable and unstable (MSC2867) types of the marked unread room account data.
+const MARKED_UNREAD: [&str; 2] = ["m.marked_unread", "com.famedly.marked_unread"];
+
+#[derive(Deserialize)]
+struct MarkedUnreadContent {
+	#[allow(dead_code)]
+	unread: bool,
+}
+
 #[derive(Deserialize)]
 struct ExtractRoomEventContent {
 	content: Raw<AnyRoomAccountDataEventContent>,
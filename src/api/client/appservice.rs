@@ -1,6 +1,7 @@
 use axum::extract::State;
 use conduwuit::{err, Err, Result};
 use ruma::api::{appservice::ping, client::appservice::request_ping};
+use service::{appservice::RegistrationInfo, deactivation::DeactivateOptions};
 
 use crate::Ruma;
 
@@ -43,3 +44,153 @@ pub(crate) async fn appservice_ping(
 
 	Ok(request_ping::v1::Response { duration: timer.elapsed() })
 }
+
+/// # `GET /_conduwuit/appservice/v1/users`
+///
+/// Lists the users in the calling appservice's exclusive namespaces, without
+/// its sender user.
+pub(crate) async fn appservice_users_route(
+	State(services): State<crate::State>,
+	body: Ruma<appservice_users::Request>,
+) -> Result<appservice_users::Response> {
+	let info = calling_appservice(body.appservice_info.as_ref())?;
+	let users = services
+		.appservice
+		.namespace_users(&info.registration.id)
+		.await?;
+
+	Ok(appservice_users::Response { users })
+}
+
+/// # `POST /_conduwuit/appservice/v1/users/deactivate`
+///
+/// Queues deactivation of all users in the calling appservice's exclusive
+/// namespaces, e.g. when the bridge is decommissioned. Returns how many were
+/// queued; they are deactivated and leave their rooms in the background.
+pub(crate) async fn appservice_deactivate_users_route(
+	State(services): State<crate::State>,
+	body: Ruma<appservice_deactivate_users::Request>,
+) -> Result<appservice_deactivate_users::Response> {
+	let info = calling_appservice(body.appservice_info.as_ref())?;
+	let options = DeactivateOptions {
+		erase: body.erase,
+		remove_pushers: body.remove_pushers,
+		..DeactivateOptions::default()
+	};
+
+	let count = services
+		.appservice
+		.deactivate_namespace_users(&info.registration.id, options)
+		.await?;
+
+	Ok(appservice_deactivate_users::Response { count })
+}
+
+/// # `POST /_conduwuit/appservice/v1/users/leave`
+///
+/// Queues all users in the calling appservice's exclusive namespaces to
+/// leave their rooms and reject their invites, keeping the accounts. Returns
+/// how many were queued.
+pub(crate) async fn appservice_leave_rooms_route(
+	State(services): State<crate::State>,
+	body: Ruma<appservice_leave_rooms::Request>,
+) -> Result<appservice_leave_rooms::Response> {
+	let info = calling_appservice(body.appservice_info.as_ref())?;
+	let count = services
+		.appservice
+		.leave_namespace_users(&info.registration.id)
+		.await?;
+
+	Ok(appservice_leave_rooms::Response { count })
+}
+
+/// The appservice whose as_token authenticated the request.
+fn calling_appservice(info: Option<&RegistrationInfo>) -> Result<&RegistrationInfo> {
+	info.ok_or_else(|| {
+		err!(Request(Forbidden("This endpoint can only be called by appservices.")))
+	})
+}
+
+pub(crate) mod appservice_users {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata, OwnedUserId,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: GET,
+		rate_limited: false,
+		authentication: AppserviceToken,
+		history: {
+			unstable => "/_conduwuit/appservice/v1/users",
+		}
+	};
+
+	#[request(error = Error)]
+	#[derive(Default)]
+	pub struct Request {}
+
+	#[response(error = Error)]
+	pub struct Response {
+		pub users: Vec<OwnedUserId>,
+	}
+}
+
+pub(crate) mod appservice_deactivate_users {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: POST,
+		rate_limited: false,
+		authentication: AppserviceToken,
+		history: {
+			unstable => "/_conduwuit/appservice/v1/users/deactivate",
+		}
+	};
+
+	/// Cleanup done besides leaving their rooms; see `DeactivateOptions`.
+	#[request(error = Error)]
+	#[derive(Default)]
+	pub struct Request {
+		#[serde(default)]
+		pub erase: bool,
+
+		#[serde(default)]
+		pub remove_pushers: bool,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		/// How many users were queued for deactivation.
+		pub count: usize,
+	}
+}
+
+pub(crate) mod appservice_leave_rooms {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: POST,
+		rate_limited: false,
+		authentication: AppserviceToken,
+		history: {
+			unstable => "/_conduwuit/appservice/v1/users/leave",
+		}
+	};
+
+	#[request(error = Error)]
+	#[derive(Default)]
+	pub struct Request {}
+
+	#[response(error = Error)]
+	pub struct Response {
+		/// How many users were queued to leave their rooms.
+		pub count: usize,
+	}
+}
@@ -8,8 +8,9 @@
 		get_backup_keys_for_room, get_backup_keys_for_session, get_latest_backup_info,
 		update_backup_version,
 	},
-	UInt,
+	RoomId, UInt,
 };
+use service::key_backups::BackupState;
 
 use crate::{Result, Ruma};
 
@@ -56,19 +57,15 @@ pub(crate) async fn get_latest_backup_info_route(
 		.await
 		.map_err(|_| err!(Request(NotFound("Key backup does not exist."))))?;
 
+	let BackupState { count, etag } = services
+		.key_backups
+		.backup_state(body.sender_user(), &version)
+		.await;
+
 	Ok(get_latest_backup_info::v3::Response {
 		algorithm,
-		count: (UInt::try_from(
-			services
-				.key_backups
-				.count_keys(body.sender_user(), &version)
-				.await,
-		)
-		.expect("user backup keys count should not be that high")),
-		etag: services
-			.key_backups
-			.get_etag(body.sender_user(), &version)
-			.await,
+		count: (UInt::try_from(count).expect("user backup keys count should not be that high")),
+		etag,
 		version,
 	})
 }
@@ -88,17 +85,15 @@ pub(crate) async fn get_backup_info_route(
 			err!(Request(NotFound("Key backup does not exist at version {:?}", body.version)))
 		})?;
 
+	let BackupState { count, etag } = services
+		.key_backups
+		.backup_state(body.sender_user(), &body.version)
+		.await;
+
 	Ok(get_backup_info::v3::Response {
 		algorithm,
-		count: services
-			.key_backups
-			.count_keys(body.sender_user(), &body.version)
-			.await
-			.try_into()?,
-		etag: services
-			.key_backups
-			.get_etag(body.sender_user(), &body.version)
-			.await,
+		count: count.try_into()?,
+		etag,
 		version: body.version.clone(),
 	})
 }
@@ -144,26 +139,18 @@ pub(crate) async fn add_backup_keys_route(
 		)));
 	}
 
-	for (room_id, room) in &body.rooms {
-		for (session_id, key_data) in &room.sessions {
-			services
-				.key_backups
-				.add_key(body.sender_user(), &body.version, room_id, session_id, key_data)
-				.await?;
-		}
-	}
+	let keys = body.rooms.iter().flat_map(|(room_id, room)| {
+		room.sessions
+			.iter()
+			.map(move |(session_id, key_data)| (&**room_id, session_id.as_str(), key_data))
+	});
 
-	Ok(add_backup_keys::v3::Response {
-		count: services
-			.key_backups
-			.count_keys(body.sender_user(), &body.version)
-			.await
-			.try_into()?,
-		etag: services
-			.key_backups
-			.get_etag(body.sender_user(), &body.version)
-			.await,
-	})
+	let BackupState { count, etag } = services
+		.key_backups
+		.add_keys(body.sender_user(), &body.version, keys)
+		.await?;
+
+	Ok(add_backup_keys::v3::Response { count: count.try_into()?, etag })
 }
 
 /// # `PUT /_matrix/client/r0/room_keys/keys/{roomId}`
@@ -189,24 +176,17 @@ pub(crate) async fn add_backup_keys_for_room_route(
 		)));
 	}
 
-	for (session_id, key_data) in &body.sessions {
-		services
-			.key_backups
-			.add_key(body.sender_user(), &body.version, &body.room_id, session_id, key_data)
-			.await?;
-	}
+	let keys = body
+		.sessions
+		.iter()
+		.map(|(session_id, key_data)| (&*body.room_id, session_id.as_str(), key_data));
 
-	Ok(add_backup_keys_for_room::v3::Response {
-		count: services
-			.key_backups
-			.count_keys(body.sender_user(), &body.version)
-			.await
-			.try_into()?,
-		etag: services
-			.key_backups
-			.get_etag(body.sender_user(), &body.version)
-			.await,
-	})
+	let BackupState { count, etag } = services
+		.key_backups
+		.add_keys(body.sender_user(), &body.version, keys)
+		.await?;
+
+	Ok(add_backup_keys_for_room::v3::Response { count: count.try_into()?, etag })
 }
 
 /// # `PUT /_matrix/client/r0/room_keys/keys/{roomId}/{sessionId}`
@@ -232,28 +212,13 @@ pub(crate) async fn add_backup_keys_for_session_route(
 		)));
 	}
 
-	services
+	let key = (&*body.room_id, body.session_id.as_str(), &body.session_data);
+	let BackupState { count, etag } = services
 		.key_backups
-		.add_key(
-			body.sender_user(),
-			&body.version,
-			&body.room_id,
-			&body.session_id,
-			&body.session_data,
-		)
+		.add_keys(body.sender_user(), &body.version, [key])
 		.await?;
 
-	Ok(add_backup_keys_for_session::v3::Response {
-		count: services
-			.key_backups
-			.count_keys(body.sender_user(), &body.version)
-			.await
-			.try_into()?,
-		etag: services
-			.key_backups
-			.get_etag(body.sender_user(), &body.version)
-			.await,
-	})
+	Ok(add_backup_keys_for_session::v3::Response { count: count.try_into()?, etag })
 }
 
 /// # `GET /_matrix/client/r0/room_keys/keys`
@@ -311,22 +276,12 @@ pub(crate) async fn delete_backup_keys_route(
 	State(services): State<crate::State>,
 	body: Ruma<delete_backup_keys::v3::Request>,
 ) -> Result<delete_backup_keys::v3::Response> {
-	services
+	let BackupState { count, etag } = services
 		.key_backups
 		.delete_all_keys(body.sender_user(), &body.version)
-		.await;
+		.await?;
 
-	Ok(delete_backup_keys::v3::Response {
-		count: services
-			.key_backups
-			.count_keys(body.sender_user(), &body.version)
-			.await
-			.try_into()?,
-		etag: services
-			.key_backups
-			.get_etag(body.sender_user(), &body.version)
-			.await,
-	})
+	Ok(delete_backup_keys::v3::Response { count: count.try_into()?, etag })
 }
 
 /// # `DELETE /_matrix/client/r0/room_keys/keys/{roomId}`
@@ -336,22 +291,29 @@ pub(crate) async fn delete_backup_keys_for_room_route(
 	State(services): State<crate::State>,
 	body: Ruma<delete_backup_keys_for_room::v3::Request>,
 ) -> Result<delete_backup_keys_for_room::v3::Response> {
-	services
+	let BackupState { count, etag } = services
 		.key_backups
 		.delete_room_keys(body.sender_user(), &body.version, &body.room_id)
-		.await;
+		.await?;
 
-	Ok(delete_backup_keys_for_room::v3::Response {
-		count: services
-			.key_backups
-			.count_keys(body.sender_user(), &body.version)
-			.await
-			.try_into()?,
-		etag: services
-			.key_backups
-			.get_etag(body.sender_user(), &body.version)
-			.await,
-	})
+	Ok(delete_backup_keys_for_room::v3::Response { count: count.try_into()?, etag })
+}
+
+/// # `POST /_matrix/client/unstable/org.conduwuit/room_keys/keys/delete`
+///
+/// Delete the keys of several rooms from the backup at once, changing the
+/// etag once.
+pub(crate) async fn delete_backup_keys_for_rooms_route(
+	State(services): State<crate::State>,
+	body: Ruma<delete_backup_keys_for_rooms::Request>,
+) -> Result<delete_backup_keys_for_rooms::Response> {
+	let room_ids: Vec<&RoomId> = body.rooms.iter().map(AsRef::as_ref).collect();
+	let BackupState { count, etag } = services
+		.key_backups
+		.delete_rooms_keys(body.sender_user(), &body.version, &room_ids)
+		.await?;
+
+	Ok(delete_backup_keys_for_rooms::Response { count: count.try_into()?, etag })
 }
 
 /// # `DELETE /_matrix/client/r0/room_keys/keys/{roomId}/{sessionId}`
@@ -361,20 +323,45 @@ pub(crate) async fn delete_backup_keys_for_session_route(
 	State(services): State<crate::State>,
 	body: Ruma<delete_backup_keys_for_session::v3::Request>,
 ) -> Result<delete_backup_keys_for_session::v3::Response> {
-	services
+	let BackupState { count, etag } = services
 		.key_backups
 		.delete_room_key(body.sender_user(), &body.version, &body.room_id, &body.session_id)
-		.await;
+		.await?;
 
-	Ok(delete_backup_keys_for_session::v3::Response {
-		count: services
-			.key_backups
-			.count_keys(body.sender_user(), &body.version)
-			.await
-			.try_into()?,
-		etag: services
-			.key_backups
-			.get_etag(body.sender_user(), &body.version)
-			.await,
-	})
+	Ok(delete_backup_keys_for_session::v3::Response { count: count.try_into()?, etag })
+}
+
+pub(crate) mod delete_backup_keys_for_rooms {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata, OwnedRoomId, UInt,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: POST,
+		rate_limited: true,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_matrix/client/unstable/org.conduwuit/room_keys/keys/delete",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		/// The backup version to delete from.
+		#[ruma_api(query)]
+		pub version: String,
+
+		/// The rooms whose keys are deleted.
+		pub rooms: Vec<OwnedRoomId>,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		/// The number of keys left in the backup.
+		pub count: UInt,
+
+		/// The new etag of the backup.
+		pub etag: String,
+	}
 }
@@ -3,20 +3,20 @@
 	at, err, ref_at,
 	utils::{
 		future::TryExtExt,
-		stream::{BroadbandExt, ReadyExt, TryIgnore, WidebandExt},
+		stream::{BroadbandExt, ReadyExt, TryIgnore},
 		IterStream,
 	},
 	Err, PduEvent, Result,
 };
 use futures::{
-	future::{join, join3, try_join3, OptionFuture},
-	FutureExt, StreamExt, TryFutureExt, TryStreamExt,
+	future::{join, try_join3, OptionFuture},
+	stream, FutureExt, StreamExt, TryFutureExt, TryStreamExt,
 };
 use ruma::{api::client::context::get_context, events::StateEventType, OwnedEventId, UserId};
 use service::rooms::{lazy_loading, lazy_loading::Options, short::ShortStateKey};
 
 use crate::{
-	client::message::{event_filter, ignored_filter, lazy_loading_witness, visibility_filter},
+	client::message::{event_filter, ignored_filter, lazy_loading_witness},
 	Ruma,
 };
 
@@ -27,8 +27,10 @@
 ///
 /// Allows loading room history around an event.
 ///
-/// - Only works if the user is joined (TODO: always allow, but only show events
-///   if the user was joined, depending on history_visibility)
+/// - Only events the user may see as per the room's history visibility are
+///   returned around the event
+/// - The state is that at the last event returned, with members limited to
+///   those the client needs when lazy loading
 pub(crate) async fn get_context_route(
 	State(services): State<crate::State>,
 	body: Ruma<get_context::v3::Request>,
@@ -75,7 +77,11 @@ pub(crate) async fn get_context_route(
 
 	let base_count = base_id.pdu_count();
 
-	let base_event = ignored_filter(&services, (base_count, base_pdu), sender_user);
+	// Visibility is checked a batch at a time, sharing the resolution of the
+	// state the events were sent in.
+	let batch_len = (limit / 2).max(1);
+	let ignored = services.users.ignored_users(sender_user).await;
+	let base_event = ignored_filter(&services, &ignored, (base_count, base_pdu));
 
 	let events_before = services
 		.rooms
@@ -83,8 +89,15 @@ pub(crate) async fn get_context_route(
 		.pdus_rev(Some(sender_user), room_id, Some(base_count))
 		.ignore_err()
 		.ready_filter_map(|item| event_filter(item, filter))
-		.wide_filter_map(|item| ignored_filter(&services, item, sender_user))
-		.wide_filter_map(|item| visibility_filter(&services, item, sender_user))
+		.ready_filter_map(|item| ignored_filter(&services, &ignored, item))
+		.ready_chunks(batch_len)
+		.then(|events| {
+			services
+				.rooms
+				.state_accessor
+				.user_can_see_events(sender_user, room_id, events)
+		})
+		.flat_map(stream::iter)
 		.take(limit / 2)
 		.collect();
 
@@ -94,13 +107,19 @@ pub(crate) async fn get_context_route(
 		.pdus(Some(sender_user), room_id, Some(base_count))
 		.ignore_err()
 		.ready_filter_map(|item| event_filter(item, filter))
-		.wide_filter_map(|item| ignored_filter(&services, item, sender_user))
-		.wide_filter_map(|item| visibility_filter(&services, item, sender_user))
+		.ready_filter_map(|item| ignored_filter(&services, &ignored, item))
+		.ready_chunks(batch_len)
+		.then(|events| {
+			services
+				.rooms
+				.state_accessor
+				.user_can_see_events(sender_user, room_id, events)
+		})
+		.flat_map(stream::iter)
 		.take(limit / 2)
 		.collect();
 
-	let (base_event, events_before, events_after): (_, Vec<_>, Vec<_>) =
-		join3(base_event, events_before, events_after).await;
+	let (events_before, events_after): (Vec<_>, Vec<_>) = join(events_before, events_after).await;
 
 	let lazy_loading_context = lazy_loading::Context {
 		user_id: sender_user,
@@ -130,7 +149,8 @@ pub(crate) async fn get_context_route(
 	let state_ids = services
 		.rooms
 		.state_accessor
-		.pdu_shortstatehash(state_at)
+		.state_at(room_id, state_at.as_str())
+		.map_ok(at!(0))
 		.or_else(|_| services.rooms.state.get_room_shortstatehash(room_id))
 		.map_ok(|shortstatehash| {
 			services
@@ -0,0 +1,54 @@
+use axum::extract::State;
+use conduwuit::{Err, Result};
+use service::jobs::JobKind;
+
+use crate::Ruma;
+
+/// # `POST /_conduwuit/admin/backup`
+///
+/// Starts a database backup like `!admin server backup create`, for
+/// scheduling backups outside the admin room. Only server admins may call
+/// this. The backup runs as a background job whose id is returned; its
+/// outcome is shown by `!admin jobs`. Fails with a conflict while another
+/// backup is still queued or running.
+pub(crate) async fn conduwuit_backup_route(
+	State(services): State<crate::State>,
+	body: Ruma<conduwuit_backup::Request>,
+) -> Result<conduwuit_backup::Response> {
+	if !services.admin.user_is_admin(body.sender_user()).await {
+		return Err!(Request(Forbidden("Only server admins can create backups.")));
+	}
+
+	let job_id = services
+		.jobs
+		.enqueue_exclusive(JobKind::BackupDatabase)
+		.await?;
+
+	Ok(conduwuit_backup::Response { job_id })
+}
+
+pub(crate) mod conduwuit_backup {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: POST,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_conduwuit/admin/backup",
+		}
+	};
+
+	#[request(error = Error)]
+	#[derive(Default)]
+	pub struct Request {}
+
+	#[response(error = Error, status = ACCEPTED)]
+	pub struct Response {
+		/// The job creating the backup.
+		pub job_id: u64,
+	}
+}
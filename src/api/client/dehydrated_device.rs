@@ -0,0 +1,128 @@
+use axum::extract::State;
+use conduwuit::{err, Err, Result};
+use futures::StreamExt;
+use ruma::api::client::dehydrated_device::{
+	delete_dehydrated_device, get_dehydrated_device, get_events, put_dehydrated_device,
+};
+
+use crate::Ruma;
+
+/// # `PUT /_matrix/client/unstable/org.matrix.msc3814.v1/dehydrated_device`
+///
+/// Stores the sender's dehydrated device along with its keys, replacing any
+/// previous one (MSC3814).
+pub(crate) async fn put_dehydrated_device_route(
+	State(services): State<crate::State>,
+	body: Ruma<put_dehydrated_device::unstable::Request>,
+) -> Result<put_dehydrated_device::unstable::Response> {
+	let sender_user = body.sender_user();
+	let body = body.body;
+
+	services
+		.users
+		.set_dehydrated_device(
+			sender_user,
+			&body.device_id,
+			body.initial_device_display_name,
+			body.device_data,
+		)
+		.await?;
+
+	services
+		.users
+		.add_device_keys(sender_user, &body.device_id, &body.device_keys)
+		.await;
+
+	for (key_id, one_time_key) in &body.one_time_keys {
+		services
+			.users
+			.add_one_time_key(sender_user, &body.device_id, key_id, one_time_key)
+			.await?;
+	}
+
+	for (key_id, fallback_key) in &body.fallback_keys {
+		services
+			.users
+			.add_fallback_key(sender_user, &body.device_id, key_id, fallback_key)
+			.await;
+	}
+
+	Ok(put_dehydrated_device::unstable::Response { device_id: body.device_id })
+}
+
+/// # `GET /_matrix/client/unstable/org.matrix.msc3814.v1/dehydrated_device`
+///
+/// Gets the sender's dehydrated device so a new login can rehydrate it.
+pub(crate) async fn get_dehydrated_device_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_dehydrated_device::unstable::Request>,
+) -> Result<get_dehydrated_device::unstable::Response> {
+	let dehydrated = services
+		.users
+		.get_dehydrated_device(body.sender_user())
+		.await?;
+
+	Ok(get_dehydrated_device::unstable::Response {
+		device_id: dehydrated.device_id,
+		device_data: dehydrated.device_data,
+	})
+}
+
+/// # `DELETE /_matrix/client/unstable/org.matrix.msc3814.v1/dehydrated_device`
+///
+/// Deletes the sender's dehydrated device, usually once it was rehydrated.
+pub(crate) async fn delete_dehydrated_device_route(
+	State(services): State<crate::State>,
+	body: Ruma<delete_dehydrated_device::unstable::Request>,
+) -> Result<delete_dehydrated_device::unstable::Response> {
+	let device_id = services
+		.users
+		.remove_dehydrated_device(body.sender_user())
+		.await?;
+
+	Ok(delete_dehydrated_device::unstable::Response { device_id })
+}
+
+/// # `POST /_matrix/client/unstable/org.matrix.msc3814.v1/dehydrated_device/{deviceId}/events`
+///
+/// Gets the to-device messages the dehydrated device received. Passing the
+/// returned `next_batch` back deletes the messages returned before it.
+pub(crate) async fn get_dehydrated_events_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_events::unstable::Request>,
+) -> Result<get_events::unstable::Response> {
+	let sender_user = body.sender_user();
+	if !services
+		.users
+		.is_dehydrated_device(sender_user, &body.device_id)
+		.await
+	{
+		return Err!(Request(Forbidden("This is not your dehydrated device.")));
+	}
+
+	let since: Option<u64> = body
+		.next_batch
+		.as_deref()
+		.map(str::parse)
+		.transpose()
+		.map_err(|_| err!(Request(InvalidParam("Invalid next_batch token."))))?;
+
+	if let Some(since) = since {
+		services
+			.users
+			.remove_to_device_events(sender_user, &body.device_id, since)
+			.await;
+	}
+
+	let until = services.globals.current_count()?;
+	let events: Vec<_> = services
+		.users
+		.get_to_device_events(sender_user, &body.device_id, since, Some(until))
+		.collect()
+		.await;
+
+	Ok(get_events::unstable::Response {
+		next_batch: Some(until.to_string()),
+		events,
+	})
+}
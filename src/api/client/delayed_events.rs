@@ -0,0 +1,188 @@
+//! Delayed events, an implementation of [MSC4140](https://github.com/matrix-org/matrix-spec-proposals/pull/4140)
+//!
+//! Events are delayed by sending them to the usual `send` and `state`
+//! endpoints with the `org.matrix.msc4140.delay` query parameter. Ruma does
+//! not provide the endpoints managing them yet so they are defined here.
+
+use std::time::Duration;
+
+use axum::{
+	extract::State,
+	response::{IntoResponse, Response},
+	Json,
+};
+use conduwuit::{err, utils, Result};
+use futures::StreamExt;
+use ruma::api::client::{message::send_message_event, state::send_state_event};
+use serde::Deserialize;
+use service::{delayed_events::DelayedEvent, Services};
+
+use crate::Ruma;
+
+/// Query parameters of `send` and `state` beyond the spec.
+#[derive(Deserialize)]
+pub(crate) struct DelayQuery {
+	/// Milliseconds to wait before sending the event.
+	#[serde(rename = "org.matrix.msc4140.delay")]
+	pub(crate) delay: Option<u64>,
+}
+
+/// Stages a message event sent with a delay to be sent into the room once the
+/// delay has passed, unless it is cancelled or restarted before then.
+pub(crate) async fn send_delayed_message_event(
+	services: &Services,
+	body: &Ruma<send_message_event::v3::Request>,
+	delay: u64,
+) -> Result<Response> {
+	let sender_user = body.sender_user();
+	let sender_device = body.sender_device.as_deref();
+
+	// Check if this is a new transaction id
+	if let Ok(response) = services
+		.transaction_ids
+		.existing_txnid(sender_user, sender_device, &body.txn_id)
+		.await
+	{
+		let delay_id = utils::string_from_bytes(&response)
+			.map_err(|e| err!(Database("Invalid delay_id in txnid data: {e:?}")))?;
+
+		return Ok(delay_id_response(delay_id));
+	}
+
+	let content = serde_json::from_str(body.body.body.json().get())
+		.map_err(|e| err!(Request(BadJson("Invalid JSON body: {e}"))))?;
+
+	let delay_id = services
+		.delayed_events
+		.schedule(
+			sender_user,
+			&body.room_id,
+			body.event_type.to_string().into(),
+			None,
+			content,
+			Duration::from_millis(delay),
+		)
+		.await?;
+
+	services.transaction_ids.add_txnid(
+		sender_user,
+		sender_device,
+		&body.txn_id,
+		delay_id.as_bytes(),
+	);
+
+	Ok(delay_id_response(delay_id))
+}
+
+/// Stages a state event sent with a delay to be sent into the room once the
+/// delay has passed, unless it is cancelled or restarted before then.
+pub(crate) async fn send_delayed_state_event(
+	services: &Services,
+	body: &Ruma<send_state_event::v3::Request>,
+	delay: u64,
+) -> Result<Response> {
+	let content = serde_json::from_str(body.body.body.json().get())
+		.map_err(|e| err!(Request(BadJson("Invalid JSON body: {e}"))))?;
+
+	let delay_id = services
+		.delayed_events
+		.schedule(
+			body.sender_user(),
+			&body.room_id,
+			body.event_type.to_string().into(),
+			Some(body.state_key.clone()),
+			content,
+			Duration::from_millis(delay),
+		)
+		.await?;
+
+	Ok(delay_id_response(delay_id))
+}
+
+fn delay_id_response(delay_id: String) -> Response {
+	Json(serde_json::json!({ "delay_id": delay_id })).into_response()
+}
+
+/// # `POST /_matrix/client/unstable/org.matrix.msc4140/delayed_events/{delayId}`
+///
+/// Cancels, restarts or immediately sends one of the user's delayed events.
+pub(crate) async fn update_delayed_event_route(
+	State(services): State<crate::State>,
+	body: Ruma<update_delayed_event::Request>,
+) -> Result<update_delayed_event::Response> {
+	services
+		.delayed_events
+		.update(body.sender_user(), &body.delay_id, body.action)
+		.await?;
+
+	Ok(update_delayed_event::Response {})
+}
+
+/// # `GET /_matrix/client/unstable/org.matrix.msc4140/delayed_events`
+///
+/// Lists the user's pending delayed events.
+pub(crate) async fn get_delayed_events_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_delayed_events::Request>,
+) -> Result<get_delayed_events::Response> {
+	let delayed_events: Vec<DelayedEvent> = services
+		.delayed_events
+		.delayed_events_for_user(body.sender_user())
+		.collect()
+		.await;
+
+	Ok(get_delayed_events::Response { delayed_events })
+}
+
+pub(crate) mod update_delayed_event {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata,
+	};
+	use service::delayed_events::DelayedEventAction;
+
+	const METADATA: Metadata = metadata! {
+		method: POST,
+		rate_limited: true,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_matrix/client/unstable/org.matrix.msc4140/delayed_events/:delay_id",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		#[ruma_api(path)]
+		pub delay_id: String,
+
+		pub action: DelayedEventAction,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {}
+}
+
+pub(crate) mod get_delayed_events {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata,
+	};
+	use service::delayed_events::DelayedEvent;
+
+	const METADATA: Metadata = metadata! {
+		method: GET,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_matrix/client/unstable/org.matrix.msc4140/delayed_events",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {}
+
+	#[response(error = Error)]
+	pub struct Response {
+		pub delayed_events: Vec<DelayedEvent>,
+	}
+}
@@ -6,7 +6,7 @@
 	api::client::{
 		device::{self, delete_device, delete_devices, get_device, get_devices, update_device},
 		error::ErrorKind,
-		uiaa::{AuthFlow, AuthType, UiaaInfo},
+		uiaa::UiaaInfo,
 	},
 	MilliSecondsSinceUnixEpoch,
 };
@@ -100,7 +100,7 @@ pub(crate) async fn delete_device_route(
 
 	// UIAA
 	let mut uiaainfo = UiaaInfo {
-		flows: vec![AuthFlow { stages: vec![AuthType::Password] }],
+		flows: services.uiaa.reauth_flows(),
 		completed: Vec::new(),
 		params: Box::default(),
 		session: None,
@@ -157,7 +157,7 @@ pub(crate) async fn delete_devices_route(
 
 	// UIAA
 	let mut uiaainfo = UiaaInfo {
-		flows: vec![AuthFlow { stages: vec![AuthType::Password] }],
+		flows: services.uiaa.reauth_flows(),
 		completed: Vec::new(),
 		params: Box::default(),
 		session: None,
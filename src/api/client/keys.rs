@@ -1,7 +1,7 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 
 use axum::extract::State;
-use conduwuit::{err, utils, Error, Result};
+use conduwuit::{err, result::LogErr, utils, Error, Result};
 use futures::{stream::FuturesUnordered, StreamExt};
 use ruma::{
 	api::{
@@ -11,7 +11,7 @@
 				claim_keys, get_key_changes, get_keys, upload_keys, upload_signatures,
 				upload_signing_keys,
 			},
-			uiaa::{AuthFlow, AuthType, UiaaInfo},
+			uiaa::UiaaInfo,
 		},
 		federation,
 	},
@@ -22,7 +22,10 @@
 
 use super::SESSION_ID_LENGTH;
 use crate::{
-	service::{users::parse_master_key, Services},
+	service::{
+		users::{parse_master_key, ClaimKeys},
+		Services,
+	},
 	Ruma,
 };
 
@@ -31,6 +34,7 @@
 /// Publish end-to-end encryption keys for the sender device.
 ///
 /// - Adds one time keys
+/// - Replaces fallback keys
 /// - If there are no device keys yet: Adds device keys (TODO: merge with
 ///   existing keys?)
 pub(crate) async fn upload_keys_route(
@@ -46,6 +50,13 @@ pub(crate) async fn upload_keys_route(
 			.await?;
 	}
 
+	for (key_id, fallback_key) in &body.fallback_keys {
+		services
+			.users
+			.add_fallback_key(sender_user, sender_device, key_id, fallback_key)
+			.await;
+	}
+
 	if let Some(device_keys) = &body.device_keys {
 		// TODO: merge this and the existing event?
 		// This check is needed to assure that signatures are kept
@@ -118,7 +129,7 @@ pub(crate) async fn upload_signing_keys_route(
 
 	// UIAA
 	let mut uiaainfo = UiaaInfo {
-		flows: vec![AuthFlow { stages: vec![AuthType::Password] }],
+		flows: services.uiaa.reauth_flows(),
 		completed: Vec::new(),
 		params: Box::default(),
 		session: None,
@@ -157,6 +168,13 @@ pub(crate) async fn upload_signing_keys_route(
 				true, // notify so that other users see the new keys
 			)
 			.await?;
+
+		services
+			.users
+			.send_signing_key_update(sender_user)
+			.await
+			.log_err()
+			.ok();
 	}
 
 	Ok(upload_signing_keys::v3::Response {})
@@ -209,6 +227,17 @@ pub(crate) async fn upload_signatures_route(
 		}
 	}
 
+	// Signatures on the sender's own keys are published to the servers of the
+	// users they share rooms with; those on other users' keys stay here.
+	if body.signed_keys.contains_key(sender_user) {
+		services
+			.users
+			.send_signing_key_update(sender_user)
+			.await
+			.log_err()
+			.ok();
+	}
+
 	Ok(upload_signatures::v3::Response {
 		failures: BTreeMap::new(), // TODO: integrate
 	})
@@ -387,27 +416,28 @@ pub(crate) async fn get_keys_helper<F>(
 	while let Some((server, response)) = futures.next().await {
 		if let Ok(response) = response {
 			for (user, master_key) in response.master_keys {
-				let (master_key_id, mut master_key) = parse_master_key(&user, &master_key)?;
+				let (master_key_id, _) = parse_master_key(&user, &master_key)?;
 
-				if let Ok(our_master_key) = services
-					.users
-					.get_key(&master_key_id, sender_user, &user, &allowed_signatures)
-					.await
-				{
-					let (_, mut our_master_key) = parse_master_key(&user, &our_master_key)?;
-					master_key.signatures.append(&mut our_master_key.signatures);
-				}
-				let json = serde_json::to_value(master_key).expect("to_value always works");
-				let raw = serde_json::from_value(json).expect("Raw::from_value always works");
+				// Stored merged with the signatures our users made on it, which the
+				// sender sees their own of
 				services
 					.users
 					.add_cross_signing_keys(
-						&user, &raw, &None, &None,
-						false, /* Dont notify. A notification would trigger another key request
-						       * resulting in an endless loop */
+						&user,
+						&master_key,
+						&None,
+						&None,
+						false, /* Dont notify. A notification would trigger another key
+						        * request resulting in an endless loop */
 					)
 					.await?;
-				master_keys.insert(user.clone(), raw);
+
+				let master_key = services
+					.users
+					.get_key(&master_key_id, sender_user, &user, &allowed_signatures)
+					.await?;
+
+				master_keys.insert(user.clone(), master_key);
 			}
 
 			self_signing_keys.extend(response.self_signing_keys);
@@ -458,21 +488,23 @@ pub(crate) async fn claim_keys_helper(
 ) -> Result<claim_keys::v3::Response> {
 	let mut one_time_keys = BTreeMap::new();
 
-	let mut get_over_federation = BTreeMap::new();
+	let mut get_over_federation = BTreeMap::<_, ClaimKeys>::new();
 
 	for (user_id, map) in one_time_keys_input {
 		if !services.globals.user_is_local(user_id) {
 			get_over_federation
 				.entry(user_id.server_name())
-				.or_insert_with(Vec::new)
-				.push((user_id, map));
+				.or_default()
+				.insert(user_id.clone(), map.clone());
+
+			continue;
 		}
 
 		let mut container = BTreeMap::new();
 		for (device_id, key_algorithm) in map {
 			if let Ok(one_time_keys) = services
 				.users
-				.take_one_time_key(user_id, device_id, key_algorithm)
+				.claim_one_time_key(user_id, device_id, key_algorithm)
 				.await
 			{
 				let mut c = BTreeMap::new();
@@ -487,18 +519,12 @@ pub(crate) async fn claim_keys_helper(
 
 	let mut futures: FuturesUnordered<_> = get_over_federation
 		.into_iter()
-		.map(|(server, vec)| async move {
-			let mut one_time_keys_input_fed = BTreeMap::new();
-			for (user_id, keys) in vec {
-				one_time_keys_input_fed.insert(user_id.clone(), keys.clone());
-			}
+		.map(|(server, keys)| async move {
 			(
 				server,
 				services
-					.sending
-					.send_federation_request(server, federation::keys::claim_keys::v1::Request {
-						one_time_keys: one_time_keys_input_fed,
-					})
+					.users
+					.claim_remote_one_time_keys(server, keys)
 					.await,
 			)
 		})
@@ -507,7 +533,7 @@ pub(crate) async fn claim_keys_helper(
 	while let Some((server, response)) = futures.next().await {
 		match response {
 			| Ok(keys) => {
-				one_time_keys.extend(keys.one_time_keys);
+				one_time_keys.extend(keys);
 			},
 			| Err(_e) => {
 				failures.insert(server.to_string(), json!({}));
@@ -1,6 +1,10 @@
 use std::time::Duration;
 
-use axum::extract::State;
+use axum::{
+	body::Body,
+	extract::State,
+	response::{IntoResponse, Response},
+};
 use axum_client_ip::InsecureClientIp;
 use conduwuit::{
 	err,
@@ -8,9 +12,10 @@
 	Err, Result,
 };
 use conduwuit_service::{
-	media::{Dim, FileMeta, CACHE_CONTROL_IMMUTABLE, CORP_CROSS_ORIGIN, MXC_LENGTH},
+	media::{Dim, FileMeta, FileStream, CACHE_CONTROL_IMMUTABLE, CORP_CROSS_ORIGIN, MXC_LENGTH},
 	Services,
 };
+use http::header;
 use reqwest::Url;
 use ruma::{
 	api::client::{
@@ -18,12 +23,12 @@
 			get_content, get_content_as_filename, get_content_thumbnail, get_media_config,
 			get_media_preview,
 		},
-		media::create_content,
+		media::{create_content, create_content_async, create_mxc_uri},
 	},
-	Mxc, UserId,
+	MilliSecondsSinceUnixEpoch, Mxc, UInt, UserId,
 };
 
-use crate::Ruma;
+use crate::{Ruma, RumaResponse};
 
 /// # `GET /_matrix/client/v1/media/config`
 pub(crate) async fn get_media_config_route(
@@ -81,6 +86,59 @@ pub(crate) async fn create_content_route(
 	})
 }
 
+/// # `POST /_matrix/media/v1/create`
+///
+/// Creates an MXC URI to upload content to later (MSC2246), so a message can
+/// be sent before its media finished uploading.
+pub(crate) async fn create_mxc_uri_route(
+	State(services): State<crate::State>,
+	body: Ruma<create_mxc_uri::v1::Request>,
+) -> Result<create_mxc_uri::v1::Response> {
+	let user = body.sender_user.as_ref().expect("user is authenticated");
+
+	let (content_uri, expires_at) = services.media.create_pending(user).await?;
+	let unused_expires_at = UInt::new(expires_at).map(MilliSecondsSinceUnixEpoch);
+
+	Ok(create_mxc_uri::v1::Response { content_uri, unused_expires_at })
+}
+
+/// # `PUT /_matrix/media/v3/upload/{serverName}/{mediaId}`
+///
+/// Uploads the content of an MXC URI created with `/create`.
+#[tracing::instrument(
+	name = "media_upload_async",
+	level = "debug",
+	skip_all,
+	fields(%client),
+)]
+pub(crate) async fn create_content_async_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	body: Ruma<create_content_async::v3::Request>,
+) -> Result<create_content_async::v3::Response> {
+	let user = body.sender_user.as_ref().expect("user is authenticated");
+
+	if !services.globals.server_is_ours(&body.server_name) {
+		return Err!(Request(Forbidden("Media can only be uploaded to this server.")));
+	}
+
+	let mxc = Mxc {
+		server_name: &body.server_name,
+		media_id: &body.media_id,
+	};
+
+	let filename = body.filename.as_deref();
+	let content_type = body.content_type.as_deref();
+	let content_disposition = make_content_disposition(None, content_type, filename);
+
+	services
+		.media
+		.upload_pending(&mxc, user, Some(&content_disposition), content_type, &body.file)
+		.await?;
+
+	Ok(create_content_async::v3::Response {})
+}
+
 /// # `GET /_matrix/client/v1/media/thumbnail/{serverName}/{mediaId}`
 ///
 /// Load media thumbnail from our server or over federation.
@@ -94,28 +152,36 @@ pub(crate) async fn get_content_thumbnail_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
 	body: Ruma<get_content_thumbnail::v1::Request>,
-) -> Result<get_content_thumbnail::v1::Response> {
+) -> Result<Response> {
 	let user = body.sender_user.as_ref().expect("user is authenticated");
 
-	let dim = Dim::from_ruma(body.width, body.height, body.method.clone())?;
+	let dim = Dim::from_ruma(body.width, body.height, body.method.clone())?
+		.with_animated(body.animated);
 	let mxc = Mxc {
 		server_name: &body.server_name,
 		media_id: &body.media_id,
 	};
 
+	services.media.check_user_access(user, &mxc).await?;
+
+	if let Some(file) = services.media.get_thumbnail_stream(&mxc, &dim).await? {
+		return stream_file(file, None);
+	}
+
 	let FileMeta {
 		content,
 		content_type,
 		content_disposition,
 	} = fetch_thumbnail(&services, &mxc, user, body.timeout_ms, &dim).await?;
 
-	Ok(get_content_thumbnail::v1::Response {
+	Ok(RumaResponse(get_content_thumbnail::v1::Response {
 		file: content.expect("entire file contents"),
 		content_type: content_type.map(Into::into),
 		cross_origin_resource_policy: Some(CORP_CROSS_ORIGIN.into()),
 		cache_control: Some(CACHE_CONTROL_IMMUTABLE.into()),
 		content_disposition,
 	})
+	.into_response())
 }
 
 /// # `GET /_matrix/client/v1/media/download/{serverName}/{mediaId}`
@@ -131,7 +197,7 @@ pub(crate) async fn get_content_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
 	body: Ruma<get_content::v1::Request>,
-) -> Result<get_content::v1::Response> {
+) -> Result<Response> {
 	let user = body.sender_user.as_ref().expect("user is authenticated");
 
 	let mxc = Mxc {
@@ -139,19 +205,26 @@ pub(crate) async fn get_content_route(
 		media_id: &body.media_id,
 	};
 
+	services.media.check_user_access(user, &mxc).await?;
+
+	if let Some(file) = services.media.get_stream(&mxc).await? {
+		return stream_file(file, None);
+	}
+
 	let FileMeta {
 		content,
 		content_type,
 		content_disposition,
 	} = fetch_file(&services, &mxc, user, body.timeout_ms, None).await?;
 
-	Ok(get_content::v1::Response {
+	Ok(RumaResponse(get_content::v1::Response {
 		file: content.expect("entire file contents"),
 		content_type: content_type.map(Into::into),
 		cross_origin_resource_policy: Some(CORP_CROSS_ORIGIN.into()),
 		cache_control: Some(CACHE_CONTROL_IMMUTABLE.into()),
 		content_disposition,
 	})
+	.into_response())
 }
 
 /// # `GET /_matrix/client/v1/media/download/{serverName}/{mediaId}/{fileName}`
@@ -167,7 +240,7 @@ pub(crate) async fn get_content_as_filename_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
 	body: Ruma<get_content_as_filename::v1::Request>,
-) -> Result<get_content_as_filename::v1::Response> {
+) -> Result<Response> {
 	let user = body.sender_user.as_ref().expect("user is authenticated");
 
 	let mxc = Mxc {
@@ -175,19 +248,26 @@ pub(crate) async fn get_content_as_filename_route(
 		media_id: &body.media_id,
 	};
 
+	services.media.check_user_access(user, &mxc).await?;
+
+	if let Some(file) = services.media.get_stream(&mxc).await? {
+		return stream_file(file, Some(&body.filename));
+	}
+
 	let FileMeta {
 		content,
 		content_type,
 		content_disposition,
 	} = fetch_file(&services, &mxc, user, body.timeout_ms, Some(&body.filename)).await?;
 
-	Ok(get_content_as_filename::v1::Response {
+	Ok(RumaResponse(get_content_as_filename::v1::Response {
 		file: content.expect("entire file contents"),
 		content_type: content_type.map(Into::into),
 		cross_origin_resource_policy: Some(CORP_CROSS_ORIGIN.into()),
 		cache_control: Some(CACHE_CONTROL_IMMUTABLE.into()),
 		content_disposition,
 	})
+	.into_response())
 }
 
 /// # `GET /_matrix/client/v1/media/preview_url`
@@ -264,6 +344,32 @@ async fn fetch_thumbnail(
 	})
 }
 
+/// Sends a file we have as it is read from the media store, with the headers
+/// of the buffered download responses.
+fn stream_file(file: FileStream, filename: Option<&str>) -> Result<Response> {
+	let FileStream {
+		content,
+		size,
+		content_type,
+		content_disposition,
+	} = file;
+
+	let content_disposition =
+		make_content_disposition(content_disposition.as_ref(), content_type.as_deref(), filename);
+
+	let content_type = content_type
+		.as_deref()
+		.unwrap_or("application/octet-stream");
+
+	Ok(Response::builder()
+		.header(header::CONTENT_TYPE, content_type)
+		.header(header::CONTENT_LENGTH, size)
+		.header(header::CONTENT_DISPOSITION, content_disposition.to_string())
+		.header(header::CROSS_ORIGIN_RESOURCE_POLICY, CORP_CROSS_ORIGIN)
+		.header(header::CACHE_CONTROL, CACHE_CONTROL_IMMUTABLE)
+		.body(Body::from_stream(content))?)
+}
+
 async fn fetch_file(
 	services: &Services,
 	mxc: &Mxc<'_>,
@@ -302,7 +408,12 @@ async fn fetch_thumbnail_meta(
 	}
 
 	if services.globals.server_is_ours(mxc.server_name) {
-		return Err!(Request(NotFound("Local thumbnail not found.")));
+		services.media.wait_for_upload(mxc, timeout_ms).await?;
+		return services
+			.media
+			.get_thumbnail(mxc, dim)
+			.await?
+			.ok_or_else(|| err!(Request(NotFound("Local thumbnail not found."))));
 	}
 
 	services
@@ -322,7 +433,12 @@ async fn fetch_file_meta(
 	}
 
 	if services.globals.server_is_ours(mxc.server_name) {
-		return Err!(Request(NotFound("Local media not found.")));
+		services.media.wait_for_upload(mxc, timeout_ms).await?;
+		return services
+			.media
+			.get(mxc)
+			.await?
+			.ok_or_else(|| err!(Request(NotFound("Local media not found."))));
 	}
 
 	services
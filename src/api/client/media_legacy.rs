@@ -142,6 +142,8 @@ pub(crate) async fn get_content_legacy_route(
 		media_id: &body.media_id,
 	};
 
+	services.media.check_legacy_access(&mxc).await?;
+
 	if let Some(FileMeta {
 		content,
 		content_type,
@@ -227,6 +229,8 @@ pub(crate) async fn get_content_as_filename_legacy_route(
 		media_id: &body.media_id,
 	};
 
+	services.media.check_legacy_access(&mxc).await?;
+
 	if let Some(FileMeta {
 		content,
 		content_type,
@@ -314,6 +318,8 @@ pub(crate) async fn get_content_thumbnail_legacy_route(
 		media_id: &body.media_id,
 	};
 
+	services.media.check_legacy_access(&mxc).await?;
+
 	let dim = Dim::from_ruma(body.width, body.height, body.method.clone())?;
 	if let Some(FileMeta {
 		content,
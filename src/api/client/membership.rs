@@ -4,6 +4,7 @@
 	iter::once,
 	net::IpAddr,
 	sync::Arc,
+	time::Duration,
 };
 
 use axum::extract::State;
@@ -45,8 +46,11 @@
 };
 use service::{
 	appservice::RegistrationInfo,
+	deactivation::LeaverFuture,
 	pdu::gen_event_id,
+	rate_limiting::Action,
 	rooms::{
+		auto_accept::{JoinerFuture, PendingInvite},
 		state::RoomMutexGuard,
 		state_compressor::{CompressedState, HashSetCompressStateEvent},
 	},
@@ -55,6 +59,11 @@
 
 use crate::{client::full_user_deactivate, Ruma};
 
+/// Rounds of make_leave/send_leave attempts over all candidate servers when
+/// leaving a room we are not in.
+const REMOTE_LEAVE_ROUNDS: u32 = 3;
+const REMOTE_LEAVE_BACKOFF: Duration = Duration::from_secs(2);
+
 /// Checks if the room is banned in any way possible and the sender user is not
 /// an admin.
 ///
@@ -175,6 +184,10 @@ pub(crate) async fn join_room_by_id_route(
 	body: Ruma<join_room_by_id::v3::Request>,
 ) -> Result<join_room_by_id::v3::Response> {
 	let sender_user = body.sender_user();
+	services
+		.rate_limiting
+		.check_user(sender_user, Action::Join)
+		.await?;
 
 	banned_room_check(
 		&services,
@@ -247,6 +260,11 @@ pub(crate) async fn join_room_by_id_or_alias_route(
 	let appservice_info = &body.appservice_info;
 	let body = body.body;
 
+	services
+		.rate_limiting
+		.check_user(sender_user, Action::Join)
+		.await?;
+
 	let (servers, room_id) = match OwnedRoomId::try_from(body.room_id_or_alias) {
 		| Ok(room_id) => {
 			banned_room_check(
@@ -498,6 +516,11 @@ pub(crate) async fn invite_user_route(
 		return Err!(Request(Forbidden("Invites are not allowed on this server.")));
 	}
 
+	services
+		.rate_limiting
+		.check_user(sender_user, Action::Invite)
+		.await?;
+
 	banned_room_check(
 		&services,
 		sender_user,
@@ -508,11 +531,11 @@ pub(crate) async fn invite_user_route(
 	.await?;
 
 	if let invite_user::v3::InvitationRecipient::UserId { user_id } = &body.recipient {
-		let sender_ignored_recipient = services.users.user_is_ignored(sender_user, user_id);
-		let recipient_ignored_by_sender = services.users.user_is_ignored(user_id, sender_user);
+		let sender_ignored_recipient = services.users.user_is_ignored(user_id, sender_user);
+		let recipient_ignored_sender = services.users.user_is_ignored(sender_user, user_id);
 
-		let (sender_ignored_recipient, recipient_ignored_by_sender) =
-			join!(sender_ignored_recipient, recipient_ignored_by_sender);
+		let (sender_ignored_recipient, recipient_ignored_sender) =
+			join!(sender_ignored_recipient, recipient_ignored_sender);
 
 		if sender_ignored_recipient {
 			return Err!(Request(Forbidden(
@@ -531,9 +554,9 @@ pub(crate) async fn invite_user_route(
 			}
 		}
 
-		if recipient_ignored_by_sender {
-			// silently drop the invite to the recipient if they've been ignored by the
-			// sender, pretend it worked
+		if recipient_ignored_sender {
+			// silently drop the invite if the recipient ignores the sender, pretend it
+			// worked
 			return Ok(invite_user::v3::Response {});
 		}
 
@@ -745,16 +768,48 @@ pub(crate) async fn joined_rooms_route(
 
 /// # `POST /_matrix/client/r0/rooms/{roomId}/members`
 ///
-/// Lists all joined users in a room (TODO: at a specific point in time, with a
-/// specific membership).
+/// Lists all joined users in a room, or at a specific point in time with `at`
+/// (TODO: with a specific membership).
 ///
 /// - Only works if the user is currently joined
+/// - With `at`, only if the user could see the room's history at that point
 pub(crate) async fn get_member_events_route(
 	State(services): State<crate::State>,
 	body: Ruma<get_member_events::v3::Request>,
 ) -> Result<get_member_events::v3::Response> {
 	let sender_user = body.sender_user();
 
+	if let Some(at) = body.at.as_deref() {
+		let (shortstatehash, event_id) = services
+			.rooms
+			.state_accessor
+			.state_at(&body.room_id, at)
+			.await?;
+
+		if !services
+			.rooms
+			.state_accessor
+			.user_can_see_event(sender_user, &body.room_id, &event_id)
+			.await
+		{
+			return Err!(Request(Forbidden(
+				"You don't have permission to view this room at this point."
+			)));
+		}
+
+		return Ok(get_member_events::v3::Response {
+			chunk: services
+				.rooms
+				.state_accessor
+				.state_full(shortstatehash)
+				.ready_filter(|((ty, _), _)| *ty == StateEventType::RoomMember)
+				.map(at!(1))
+				.map(PduEvent::into_member_event)
+				.collect()
+				.await,
+		});
+	}
+
 	if !services
 		.rooms
 		.state_accessor
@@ -825,6 +880,8 @@ pub async fn join_room_by_id_helper(
 	third_party_signed: Option<&ThirdPartySigned>,
 	appservice_info: &Option<RegistrationInfo>,
 ) -> Result<join_room_by_id::v3::Response> {
+	services.moderation.check_join(sender_user, room_id).await?;
+
 	let state_lock = services.rooms.state.mutex.lock(room_id).await;
 
 	let user_is_guest = services
@@ -1004,7 +1061,7 @@ async fn join_room_by_id_helper_remote(
 	let send_join_request = federation::membership::create_join_event::v2::Request {
 		room_id: room_id.to_owned(),
 		event_id: event_id.clone(),
-		omit_members: false,
+		omit_members: services.server.config.allow_partial_state_joins,
 		pdu: services
 			.sending
 			.convert_to_outgoing_federation_event(join_event.clone())
@@ -1018,6 +1075,13 @@ async fn join_room_by_id_helper_remote(
 
 	info!("send_join finished");
 
+	let partial_state = services.server.config.allow_partial_state_joins
+		&& send_join_response.room_state.members_omitted;
+
+	if partial_state {
+		info!("{remote_server} omitted members from send_join; joining with partial state");
+	}
+
 	if join_authorized_via_users_server.is_some() {
 		if let Some(signed_raw) = &send_join_response.room_state.event {
 			debug_info!(
@@ -1213,6 +1277,24 @@ async fn join_room_by_id_helper_remote(
 		.append_to_state(&parsed_join_pdu)
 		.await?;
 
+	if partial_state {
+		let servers_in_room: Vec<OwnedServerName> = send_join_response
+			.room_state
+			.servers_in_room
+			.iter()
+			.flatten()
+			.filter_map(|server| ServerName::parse(server.as_str()).ok())
+			.chain(once(remote_server.clone()))
+			.filter(|server| !services.globals.server_is_ours(server))
+			.collect();
+
+		services.rooms.partial_state.mark_partial_state(
+			room_id,
+			event_id.clone(),
+			servers_in_room,
+		);
+	}
+
 	info!("Appending new room join event");
 	services
 		.rooms
@@ -1233,6 +1315,10 @@ async fn join_room_by_id_helper_remote(
 		.state
 		.set_room_state(room_id, statehash_after_join, &state_lock);
 
+	if partial_state {
+		services.rooms.partial_state.queue_resync(room_id);
+	}
+
 	Ok(())
 }
 
@@ -1552,6 +1638,20 @@ pub(crate) async fn invite_helper(
 		return Err!(Request(Forbidden("Invites are not allowed on this server.")));
 	}
 
+	services
+		.moderation
+		.check_invite(sender_user, room_id)
+		.await?;
+
+	if services.globals.user_is_local(user_id)
+		&& !services
+			.users
+			.accepts_invite_from(user_id, sender_user)
+			.await
+	{
+		return Err!(Request(Forbidden("{user_id} does not accept invites from you.")));
+	}
+
 	if !services.globals.user_is_local(user_id) {
 		let (pdu, pdu_json, invite_room_state) = {
 			let state_lock = services.rooms.state.mutex.lock(room_id).await;
@@ -1679,6 +1779,28 @@ pub(crate) async fn invite_helper(
 	Ok(())
 }
 
+/// Joins a room on behalf of a user whose invite is being auto-accepted.
+/// Installed as the joiner of the `rooms::auto_accept` service.
+pub fn auto_accept_join(services: Arc<Services>, invite: PendingInvite) -> JoinerFuture {
+	Box::pin(async move {
+		let PendingInvite { user_id, room_id, servers, .. } = invite;
+
+		join_room_by_id_helper(&services, &user_id, &room_id, None, &servers, None, &None)
+			.await
+			.map(|_| ())
+	})
+}
+
+/// Leaves or rejects the invite to a room on behalf of a user being
+/// deactivated. Installed as the leaver of the `deactivation` service.
+pub fn deactivation_leave(
+	services: Arc<Services>,
+	user_id: OwnedUserId,
+	room_id: OwnedRoomId,
+) -> LeaverFuture {
+	Box::pin(async move { leave_room(&services, &user_id, &room_id, None).await })
+}
+
 // Make a user leave all their joined rooms, forgets all rooms, and ignores
 // errors
 pub async fn leave_all_rooms(services: &Services, user_id: &UserId) {
@@ -1723,9 +1845,15 @@ pub async fn leave_room(
 		.is_knocked(user_id, room_id)
 		.await
 	{
-		if let Err(e) = remote_leave_room(services, user_id, room_id).await {
-			warn!(%user_id, "Failed to leave room {room_id} remotely: {e}");
-			// Don't tell the client about this error
+		// Don't tell the client about remote errors, and don't keep them waiting on
+		// unresponsive servers either.
+		let timeout = Duration::from_secs(services.server.config.remote_leave_timeout);
+		match tokio::time::timeout(timeout, remote_leave_room(services, user_id, room_id, reason))
+			.await
+		{
+			| Ok(Ok(())) => {},
+			| Ok(Err(e)) => warn!(%user_id, "Failed to leave room {room_id} remotely: {e}"),
+			| Err(_) => warn!(%user_id, "Timed out leaving room {room_id} remotely"),
 		}
 
 		let last_state = services
@@ -1807,14 +1935,14 @@ pub async fn leave_room(
 	Ok(())
 }
 
+/// Performs the make_leave/send_leave handshake for a room we are not in,
+/// trying every server we know of and retrying a few rounds with backoff.
 async fn remote_leave_room(
 	services: &Services,
 	user_id: &UserId,
 	room_id: &RoomId,
+	reason: Option<String>,
 ) -> Result<()> {
-	let mut make_leave_response_and_server =
-		Err!(BadServerResponse("No server available to assist in leaving."));
-
 	let mut servers: HashSet<OwnedServerName> = services
 		.rooms
 		.state_cache
@@ -1863,26 +1991,51 @@ async fn remote_leave_room(
 
 	debug_info!("servers in remote_leave_room: {servers:?}");
 
-	for remote_server in servers {
-		let make_leave_response = services
-			.sending
-			.send_federation_request(
-				&remote_server,
-				federation::membership::prepare_leave_event::v1::Request {
-					room_id: room_id.to_owned(),
-					user_id: user_id.to_owned(),
-				},
-			)
-			.await;
-
-		make_leave_response_and_server = make_leave_response.map(|r| (r, remote_server));
+	let mut last_error = err!(BadServerResponse("No server available to assist in leaving."));
+	for round in 0..REMOTE_LEAVE_ROUNDS {
+		if round > 0 {
+			tokio::time::sleep(REMOTE_LEAVE_BACKOFF.saturating_mul(round)).await;
+		}
 
-		if make_leave_response_and_server.is_ok() {
-			break;
+		for remote_server in &servers {
+			match remote_leave_room_via(
+				services,
+				user_id,
+				room_id,
+				reason.as_deref(),
+				remote_server,
+			)
+			.await
+			{
+				| Ok(()) => return Ok(()),
+				| Err(e) => {
+					debug_warn!(%remote_server, "Failed to leave {room_id} via remote server: {e}");
+					last_error = e;
+				},
+			}
 		}
 	}
 
-	let (make_leave_response, remote_server) = make_leave_response_and_server?;
+	Err(last_error)
+}
+
+async fn remote_leave_room_via(
+	services: &Services,
+	user_id: &UserId,
+	room_id: &RoomId,
+	reason: Option<&str>,
+	remote_server: &ServerName,
+) -> Result<()> {
+	let make_leave_response = services
+		.sending
+		.send_federation_request(
+			remote_server,
+			federation::membership::prepare_leave_event::v1::Request {
+				room_id: room_id.to_owned(),
+				user_id: user_id.to_owned(),
+			},
+		)
+		.await?;
 
 	let Some(room_version_id) = make_leave_response.room_version else {
 		return Err!(BadServerResponse("Remote room version is not supported by conduwuit"));
@@ -1901,6 +2054,12 @@ async fn remote_leave_room(
 		err!(BadServerResponse("Invalid make_leave event json received from server: {e:?}"))
 	})?;
 
+	if let Some(reason) = reason {
+		if let Some(CanonicalJsonValue::Object(content)) = leave_event_stub.get_mut("content") {
+			content.insert("reason".to_owned(), CanonicalJsonValue::String(reason.to_owned()));
+		}
+	}
+
 	// TODO: Is origin needed?
 	leave_event_stub.insert(
 		"origin".to_owned(),
@@ -1942,7 +2101,7 @@ async fn remote_leave_room(
 	services
 		.sending
 		.send_federation_request(
-			&remote_server,
+			remote_server,
 			federation::membership::create_leave_event::v2::Request {
 				room_id: room_id.to_owned(),
 				event_id,
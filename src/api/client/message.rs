@@ -1,12 +1,13 @@
 use axum::extract::State;
+use axum_client_ip::InsecureClientIp;
 use conduwuit::{
-	at, is_equal_to,
+	at, debug, is_equal_to,
 	utils::{
 		result::{FlatOk, LogErr},
 		stream::{BroadbandExt, TryIgnore, WidebandExt},
 		IterStream, ReadyExt,
 	},
-	Event, PduCount, PduEvent, Result,
+	Err, Event, PduCount, PduEvent, Result,
 };
 use futures::{future::OptionFuture, pin_mut, FutureExt, StreamExt, TryFutureExt};
 use ruma::{
@@ -19,15 +20,17 @@
 	RoomId, UserId,
 };
 use service::{
+	rate_limiting::Action,
 	rooms::{
 		lazy_loading,
 		lazy_loading::{Options, Witness},
-		timeline::PdusIterItem,
+		timeline::{PdusIterItem, Position},
 	},
+	users::IgnoredUsers,
 	Services,
 };
 
-use crate::Ruma;
+use crate::{client::state::check_peek_ratelimit, Ruma};
 
 /// list of safe and common non-state events to ignore if the user is ignored
 const IGNORED_MESSAGE_TYPES: &[TimelineEventType; 17] = &[
@@ -59,27 +62,29 @@
 ///
 /// - Only works if the user is joined (TODO: always allow, but only show events
 ///   where the user was joined, depending on `history_visibility`)
+/// - Without an access token: only works for world readable rooms, and only
+///   shows events from while the history was world readable
 pub(crate) async fn get_message_events_route(
 	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
 	body: Ruma<get_message_events::v3::Request>,
 ) -> Result<get_message_events::v3::Response> {
 	debug_assert!(IGNORED_MESSAGE_TYPES.is_sorted(), "IGNORED_MESSAGE_TYPES is not sorted");
-	let sender = body.sender();
-	let (sender_user, sender_device) = sender;
 	let room_id = &body.room_id;
 	let filter = &body.filter;
 
-	let from: PduCount = body
+	let from: Position = body
 		.from
 		.as_deref()
 		.map(str::parse)
 		.transpose()?
 		.unwrap_or_else(|| match body.dir {
-			| Direction::Forward => PduCount::min(),
-			| Direction::Backward => PduCount::max(),
+			| Direction::Forward => Position::Topological { depth: 0, count: PduCount::min() },
+			| Direction::Backward =>
+				Position::Topological { depth: u64::MAX, count: PduCount::max() },
 		});
 
-	let to: Option<PduCount> = body.to.as_deref().map(str::parse).flat_ok();
+	let to: Option<Position> = body.to.as_deref().map(str::parse).flat_ok();
 
 	let limit: usize = body
 		.limit
@@ -87,37 +92,42 @@ pub(crate) async fn get_message_events_route(
 		.unwrap_or(LIMIT_DEFAULT)
 		.min(LIMIT_MAX);
 
-	if matches!(body.dir, Direction::Backward) {
-		services
+	if body.sender_user.is_none() {
+		check_peek_ratelimit(&services, client)?;
+
+		if !services
 			.rooms
-			.timeline
-			.backfill_if_required(room_id, from)
-			.boxed()
+			.state_accessor
+			.is_world_readable(room_id)
 			.await
-			.log_err()
-			.ok();
+		{
+			return Err!(Request(Forbidden("Room is not world readable.")));
+		}
 	}
 
-	let it = match body.dir {
-		| Direction::Forward => services
-			.rooms
-			.timeline
-			.pdus(Some(sender_user), room_id, Some(from))
-			.ignore_err()
-			.boxed(),
+	if let Some(sender_user) = body.sender_user.as_deref() {
+		if matches!(body.dir, Direction::Backward) {
+			backfill(&services, sender_user, room_id, from).await;
+		}
+	}
 
-		| Direction::Backward => services
-			.rooms
-			.timeline
-			.pdus_rev(Some(sender_user), room_id, Some(from))
-			.ignore_err()
-			.boxed(),
+	let Some((sender_user, sender_device)) = body
+		.sender_user
+		.as_deref()
+		.zip(body.sender_device.as_deref())
+	else {
+		return Ok(peek_message_events(&services, &body, from, to, limit).await);
 	};
 
-	let events: Vec<_> = it
-		.ready_take_while(|(count, _)| Some(*count) != to)
+	let ignored = services.users.ignored_users(sender_user).await;
+	let events: Vec<_> = services
+		.rooms
+		.timeline
+		.pdus_topological(Some(sender_user), room_id, from, body.dir)
+		.ignore_err()
+		.ready_take_while(|item| !reached(item, to, body.dir))
 		.ready_filter_map(|item| event_filter(item, filter))
-		.wide_filter_map(|item| ignored_filter(&services, item, sender_user))
+		.ready_filter_map(|item| ignored_filter(&services, &ignored, item))
 		.wide_filter_map(|item| visibility_filter(&services, item, sender_user))
 		.take(limit)
 		.collect()
@@ -127,7 +137,7 @@ pub(crate) async fn get_message_events_route(
 		user_id: sender_user,
 		device_id: sender_device,
 		room_id,
-		token: Some(from.into_unsigned()),
+		token: Some(from.count().into_unsigned()),
 		options: Some(&filter.lazy_load_options),
 	};
 
@@ -149,7 +159,7 @@ pub(crate) async fn get_message_events_route(
 		.collect()
 		.await;
 
-	let next_token = events.last().map(at!(0));
+	let next_token = events.last().map(|(count, pdu)| Position::of(*count, pdu));
 
 	let chunk = events
 		.into_iter()
@@ -165,6 +175,110 @@ pub(crate) async fn get_message_events_route(
 	})
 }
 
+/// Fetches older history from other servers for a user who can see the room,
+/// as often as their rate limit allows. Clients without an access token only
+/// get the history we already have.
+async fn backfill(services: &Services, sender_user: &UserId, room_id: &RoomId, from: Position) {
+	if !services
+		.rooms
+		.state_accessor
+		.user_can_see_state_events(sender_user, room_id)
+		.await
+	{
+		return;
+	}
+
+	if services
+		.rate_limiting
+		.check_user(sender_user, Action::Backfill)
+		.await
+		.is_err()
+	{
+		debug!(%sender_user, %room_id, "Not backfilling, rate limited");
+		return;
+	}
+
+	services
+		.rooms
+		.timeline
+		.backfill_if_required(room_id, from)
+		.boxed()
+		.await
+		.log_err()
+		.ok();
+}
+
+/// Paginates a world readable room for a client without an access token.
+/// There is no user to apply ignores or lazy loading for.
+async fn peek_message_events(
+	services: &Services,
+	body: &get_message_events::v3::Request,
+	from: Position,
+	to: Option<Position>,
+	limit: usize,
+) -> get_message_events::v3::Response {
+	let room_id = &body.room_id;
+	let filter = &body.filter;
+
+	let events: Vec<_> = services
+		.rooms
+		.timeline
+		.pdus_topological(None, room_id, from, body.dir)
+		.ignore_err()
+		.ready_take_while(|item| !reached(item, to, body.dir))
+		.ready_filter_map(|item| event_filter(item, filter))
+		.wide_filter_map(|item| async move {
+			let (_, pdu) = &item;
+			services
+				.rooms
+				.state_accessor
+				.anonymous_can_see_event(&pdu.event_id)
+				.await
+				.then_some(item)
+		})
+		.take(limit)
+		.collect()
+		.await;
+
+	let next_token = events.last().map(|(count, pdu)| Position::of(*count, pdu));
+
+	let chunk = events
+		.into_iter()
+		.map(at!(1))
+		.map(|pdu| pdu.to_room_event())
+		.collect();
+
+	get_message_events::v3::Response {
+		start: from.to_string(),
+		end: next_token.as_ref().map(ToString::to_string),
+		chunk,
+		state: Vec::new(),
+	}
+}
+
+/// Whether pagination reached the event at the `to` token. A stream token
+/// names the event to stop at; a topological token is also passed by any event
+/// beyond it in the direction.
+fn reached((count, pdu): &PdusIterItem, to: Option<Position>, dir: Direction) -> bool {
+	let Some(to) = to else {
+		return false;
+	};
+
+	if *count == to.count() {
+		return true;
+	}
+
+	let Some(to) = to.topological_key() else {
+		return false;
+	};
+
+	let position = (u64::from(pdu.depth), *count);
+	match dir {
+		| Direction::Forward => position >= to,
+		| Direction::Backward => position <= to,
+	}
+}
+
 pub(crate) async fn lazy_loading_witness<'a, I>(
 	services: &Services,
 	lazy_loading_context: &lazy_loading::Context<'_>,
@@ -225,10 +339,13 @@ async fn get_member_event(
 		.ok()
 }
 
-pub(crate) async fn ignored_filter(
+/// Hides events the user shouldn't be served: dummy events, and messages from
+/// users they ignore or from forbidden servers. State events are kept so the
+/// room's history remains coherent.
+pub(crate) fn ignored_filter(
 	services: &Services,
+	ignored: &IgnoredUsers,
 	item: PdusIterItem,
-	user_id: &UserId,
 ) -> Option<PdusIterItem> {
 	let (_, pdu) = &item;
 
@@ -239,7 +356,7 @@ pub(crate) async fn ignored_filter(
 	}
 
 	if IGNORED_MESSAGE_TYPES.binary_search(&pdu.kind).is_ok()
-		&& (services.users.user_is_ignored(&pdu.sender, user_id).await
+		&& (ignored.contains(&pdu.sender)
 			|| services
 				.server
 				.config
@@ -0,0 +1,353 @@
+use std::{
+	fmt::{Display, Write},
+	sync::atomic::Ordering,
+};
+
+use axum::{
+	extract::State,
+	http::{header, HeaderMap},
+	response::IntoResponse,
+};
+use conduwuit::{metrics::Histogram, Err, Result};
+use service::Services;
+use subtle::ConstantTimeEq;
+
+/// Content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// # `GET /_conduwuit/metrics`
+///
+/// Runtime, request, database, federation, cache and sync metrics in the
+/// Prometheus text format, when enabled by `metrics_endpoint`.
+pub(crate) async fn conduwuit_metrics_route(
+	State(services): State<crate::State>,
+	headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+	if !services.server.config.metrics_endpoint {
+		return Err!(Request(NotFound("The metrics endpoint is disabled.")));
+	}
+
+	metrics(&services, &headers).await
+}
+
+/// # `GET /metrics`
+///
+/// The same metrics, on the separate listener at `metrics_address`.
+pub(crate) async fn metrics_listener_route(
+	State(services): State<crate::State>,
+	headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+	metrics(&services, &headers).await
+}
+
+async fn metrics(services: &Services, headers: &HeaderMap) -> Result<impl IntoResponse> {
+	let config = &services.server.config;
+	if let Some(token) = &config.metrics_token {
+		let provided = headers
+			.get(header::AUTHORIZATION)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| value.strip_prefix("Bearer "));
+
+		match provided {
+			| None => return Err!(Request(MissingToken("Missing metrics token."))),
+			| Some(provided) if !bool::from(provided.as_bytes().ct_eq(token.as_bytes())) =>
+				return Err!(Request(Forbidden("Invalid metrics token."))),
+			| Some(_) => (),
+		}
+	}
+
+	let mut out = String::new();
+	let metrics = &services.server.metrics;
+	if let Some(runtime) = metrics.runtime_metrics() {
+		gauge(&mut out, "tokio_workers", "Async worker threads.", runtime.num_workers());
+		gauge(
+			&mut out,
+			"tokio_alive_tasks",
+			"Tasks not yet finished.",
+			runtime.num_alive_tasks(),
+		);
+		gauge(
+			&mut out,
+			"tokio_global_queue_depth",
+			"Tasks waiting in the shared run queue.",
+			runtime.global_queue_depth(),
+		);
+
+		#[cfg(tokio_unstable)]
+		{
+			gauge(
+				&mut out,
+				"tokio_blocking_threads",
+				"Threads of tokio's blocking pool.",
+				runtime.num_blocking_threads(),
+			);
+			gauge(
+				&mut out,
+				"tokio_idle_blocking_threads",
+				"Idle threads of tokio's blocking pool.",
+				runtime.num_idle_blocking_threads(),
+			);
+			gauge(
+				&mut out,
+				"tokio_blocking_queue_depth",
+				"Tasks waiting for a thread of tokio's blocking pool.",
+				runtime.blocking_queue_depth(),
+			);
+
+			describe(
+				&mut out,
+				"tokio_worker_local_queue_depth",
+				"gauge",
+				"Tasks in a worker's queue.",
+			);
+			for worker in 0..runtime.num_workers() {
+				sample(
+					&mut out,
+					&format!("conduwuit_tokio_worker_local_queue_depth{{worker=\"{worker}\"}}"),
+					runtime.worker_local_queue_depth(worker),
+				);
+			}
+
+			describe(
+				&mut out,
+				"tokio_worker_busy_seconds_total",
+				"counter",
+				"Time a worker spent running tasks.",
+			);
+			for worker in 0..runtime.num_workers() {
+				sample(
+					&mut out,
+					&format!("conduwuit_tokio_worker_busy_seconds_total{{worker=\"{worker}\"}}"),
+					runtime.worker_total_busy_duration(worker).as_secs_f64(),
+				);
+			}
+		}
+	}
+
+	gauge(
+		&mut out,
+		"requests_active",
+		"Requests being handled.",
+		metrics.requests_handle_active.load(Ordering::Relaxed),
+	);
+	counter(
+		&mut out,
+		"requests_total",
+		"Requests handled.",
+		metrics.requests_handle_finished.load(Ordering::Relaxed),
+	);
+	counter(
+		&mut out,
+		"requests_panicked_total",
+		"Requests which panicked.",
+		metrics.requests_panic.load(Ordering::Relaxed),
+	);
+
+	let db_pool = services.db.pool_stats();
+	gauge(&mut out, "db_pool_workers", "Database request threads.", db_pool.workers);
+	gauge(
+		&mut out,
+		"db_pool_busy",
+		"Database request threads handling a request.",
+		db_pool.busy,
+	);
+	gauge(
+		&mut out,
+		"db_pool_queued",
+		"Database requests waiting for a thread.",
+		db_pool.queued,
+	);
+
+	let blocking = services.server.blocking.stats();
+	gauge(
+		&mut out,
+		"blocking_pool_workers",
+		"Threads for CPU-bound work.",
+		blocking.workers.load(Ordering::Relaxed),
+	);
+	gauge(
+		&mut out,
+		"blocking_pool_busy",
+		"Threads for CPU-bound work running a job.",
+		blocking.busy.load(Ordering::Relaxed),
+	);
+	gauge(
+		&mut out,
+		"blocking_pool_queued",
+		"CPU-bound jobs waiting for a thread.",
+		blocking.queued.load(Ordering::Relaxed),
+	);
+	counter(
+		&mut out,
+		"blocking_pool_jobs_total",
+		"CPU-bound jobs completed.",
+		blocking.completed.load(Ordering::Relaxed),
+	);
+	#[allow(clippy::cast_precision_loss, clippy::as_conversions)]
+	counter(
+		&mut out,
+		"blocking_pool_busy_seconds_total",
+		"Time spent running CPU-bound jobs.",
+		blocking.busy_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+	);
+
+	describe(
+		&mut out,
+		"request_duration_seconds",
+		"histogram",
+		"Time taken to handle requests, by route.",
+	);
+	metrics.request_durations.for_each(|route, histogram| {
+		histogram_samples(
+			&mut out,
+			"request_duration_seconds",
+			Some(("route", route)),
+			histogram,
+		);
+	});
+
+	describe(
+		&mut out,
+		"db_query_duration_seconds",
+		"histogram",
+		"Time taken by database queries run on the request threads, by kind.",
+	);
+	metrics.db_query_durations.for_each(|kind, histogram| {
+		histogram_samples(&mut out, "db_query_duration_seconds", Some(("kind", kind)), histogram);
+	});
+
+	describe(
+		&mut out,
+		"federation_transaction_duration_seconds",
+		"histogram",
+		"Time taken to send federation transactions, by destination; destinations past the \
+		 first 1024 are grouped as \"other\".",
+	);
+	metrics
+		.federation_transaction_durations
+		.for_each(|destination, histogram| {
+			histogram_samples(
+				&mut out,
+				"federation_transaction_duration_seconds",
+				Some(("destination", destination)),
+				histogram,
+			);
+		});
+
+	describe(
+		&mut out,
+		"federation_transaction_failures_total",
+		"counter",
+		"Federation transactions which failed, by destination; destinations past the first 1024 \
+		 are grouped as \"other\".",
+	);
+	metrics
+		.federation_transaction_failures
+		.for_each(|destination, failures| {
+			sample(
+				&mut out,
+				&format!(
+					"conduwuit_federation_transaction_failures_total{{destination=\"{}\"}}",
+					escape(destination)
+				),
+				failures,
+			);
+		});
+
+	describe(
+		&mut out,
+		"incoming_pdu_duration_seconds",
+		"histogram",
+		"Time taken to handle incoming PDUs.",
+	);
+	histogram_samples(
+		&mut out,
+		"incoming_pdu_duration_seconds",
+		None,
+		&metrics.incoming_pdu_durations,
+	);
+
+	describe(
+		&mut out,
+		"sync_lag_seconds",
+		"histogram",
+		"Time from a local user sending an event to its delivery by an incremental sync.",
+	);
+	histogram_samples(&mut out, "sync_lag_seconds", None, &metrics.sync_lag);
+
+	let caches = services.caches().await;
+	for (name, kind, help) in [
+		("cache_entries", "gauge", "Entries held by a cache."),
+		("cache_capacity", "gauge", "Entries a cache can hold."),
+		("cache_hits_total", "counter", "Cache lookups which found an entry."),
+		("cache_misses_total", "counter", "Cache lookups which found none."),
+	] {
+		describe(&mut out, name, kind, help);
+		for (cache, stats) in &caches {
+			let value = match name {
+				| "cache_entries" => u64::try_from(stats.entries)?,
+				| "cache_capacity" => u64::try_from(stats.capacity)?,
+				| "cache_hits_total" => stats.hits,
+				| _ => stats.misses,
+			};
+
+			sample(&mut out, &format!("conduwuit_{name}{{cache=\"{cache}\"}}"), value);
+		}
+	}
+
+	Ok(([(header::CONTENT_TYPE, CONTENT_TYPE)], out))
+}
+
+/// Writes the buckets, sum and count of a histogram, optionally labelled.
+fn histogram_samples(
+	out: &mut String,
+	name: &str,
+	label: Option<(&str, &str)>,
+	histogram: &Histogram,
+) {
+	let label = label.map(|(key, value)| format!("{key}=\"{}\"", escape(value)));
+
+	let labels = label
+		.as_deref()
+		.map_or_else(String::new, |label| format!("{{{label}}}"));
+	let with_le = |le: &dyn Display| match &label {
+		| Some(label) => format!("{{{label},le=\"{le}\"}}"),
+		| None => format!("{{le=\"{le}\"}}"),
+	};
+
+	for (bound, count) in histogram.buckets() {
+		sample(out, &format!("conduwuit_{name}_bucket{}", with_le(&bound)), count);
+	}
+
+	let count = histogram.count();
+	sample(out, &format!("conduwuit_{name}_bucket{}", with_le(&"+Inf")), count);
+	sample(out, &format!("conduwuit_{name}_sum{labels}"), histogram.sum());
+	sample(out, &format!("conduwuit_{name}_count{labels}"), count);
+}
+
+/// Escapes a label value as the exposition format requires.
+fn escape(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl Display) {
+	describe(out, name, "gauge", help);
+	sample(out, &format!("conduwuit_{name}"), value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: impl Display) {
+	describe(out, name, "counter", help);
+	sample(out, &format!("conduwuit_{name}"), value);
+}
+
+fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+	writeln!(out, "# HELP conduwuit_{name} {help}").expect("write to string");
+	writeln!(out, "# TYPE conduwuit_{name} {kind}").expect("write to string");
+}
+
+fn sample(out: &mut String, name: &str, value: impl Display) {
+	writeln!(out, "{name} {value}").expect("write to string");
+}
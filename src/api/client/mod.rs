@@ -5,6 +5,9 @@
 pub(super) mod backup;
 pub(super) mod capabilities;
 pub(super) mod context;
+pub(super) mod db_backup;
+pub(super) mod dehydrated_device;
+pub(super) mod delayed_events;
 pub(super) mod device;
 pub(super) mod directory;
 pub(super) mod filter;
@@ -13,6 +16,7 @@
 pub(super) mod media_legacy;
 pub(super) mod membership;
 pub(super) mod message;
+pub(super) mod metrics;
 pub(super) mod openid;
 pub(super) mod presence;
 pub(super) mod profile;
@@ -24,8 +28,10 @@
 pub(super) mod room;
 pub(super) mod search;
 pub(super) mod send;
+pub(super) mod server_notices;
 pub(super) mod session;
 pub(super) mod space;
+pub(super) mod sso;
 pub(super) mod state;
 pub(super) mod sync;
 pub(super) mod tag;
@@ -47,6 +53,9 @@
 pub(super) use backup::*;
 pub(super) use capabilities::*;
 pub(super) use context::*;
+pub(super) use db_backup::*;
+pub(super) use dehydrated_device::*;
+pub(super) use delayed_events::*;
 pub(super) use device::*;
 pub(super) use directory::*;
 pub(super) use filter::*;
@@ -54,8 +63,11 @@
 pub(super) use media::*;
 pub(super) use media_legacy::*;
 pub(super) use membership::*;
-pub use membership::{join_room_by_id_helper, leave_all_rooms, leave_room};
+pub use membership::{
+	auto_accept_join, deactivation_leave, join_room_by_id_helper, leave_all_rooms, leave_room,
+};
 pub(super) use message::*;
+pub(super) use metrics::*;
 pub(super) use openid::*;
 pub(super) use presence::*;
 pub(super) use profile::*;
@@ -68,8 +80,10 @@
 pub(super) use room::*;
 pub(super) use search::*;
 pub(super) use send::*;
+pub(super) use server_notices::*;
 pub(super) use session::*;
 pub(super) use space::*;
+pub(super) use sso::*;
 pub(super) use state::*;
 pub(super) use sync::*;
 pub(super) use tag::*;
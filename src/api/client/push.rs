@@ -1,25 +1,21 @@
 use axum::extract::State;
-use conduwuit::{err, Err};
+use conduwuit::{at, err, utils::ReadyExt, Err};
+use futures::StreamExt;
 use ruma::{
 	api::client::{
 		error::ErrorKind,
 		push::{
-			delete_pushrule, get_pushers, get_pushrule, get_pushrule_actions,
+			delete_pushrule, get_notifications, get_pushers, get_pushrule, get_pushrule_actions,
 			get_pushrule_enabled, get_pushrules_all, get_pushrules_global_scope, set_pusher,
 			set_pushrule, set_pushrule_actions, set_pushrule_enabled,
 		},
 	},
-	events::{
-		push_rules::{PushRulesEvent, PushRulesEventContent},
-		GlobalAccountDataEventType,
-	},
 	push::{
-		InsertPushRuleError, PredefinedContentRuleId, PredefinedOverrideRuleId,
-		RemovePushRuleError, Ruleset,
+		Action, InsertPushRuleError, PredefinedContentRuleId, PredefinedOverrideRuleId,
+		RemovePushRuleError,
 	},
-	CanonicalJsonObject, CanonicalJsonValue,
+	uint, MilliSecondsSinceUnixEpoch, UInt,
 };
-use service::Services;
 
 use crate::{Error, Result, Ruma};
 
@@ -31,70 +27,9 @@ pub(crate) async fn get_pushrules_all_route(
 	body: Ruma<get_pushrules_all::v3::Request>,
 ) -> Result<get_pushrules_all::v3::Response> {
 	let sender_user = body.sender_user();
+	let global = services.pusher.get_stored_ruleset(sender_user).await?;
 
-	let Some(content_value) = services
-		.account_data
-		.get_global::<CanonicalJsonObject>(sender_user, GlobalAccountDataEventType::PushRules)
-		.await
-		.ok()
-		.and_then(|event| event.get("content").cloned())
-		.filter(CanonicalJsonValue::is_object)
-	else {
-		// user somehow has non-existent push rule event. recreate it and return server
-		// default silently
-		return recreate_push_rules_and_return(&services, sender_user).await;
-	};
-
-	let account_data_content =
-		serde_json::from_value::<PushRulesEventContent>(content_value.into()).map_err(|e| {
-			err!(Database(warn!("Invalid push rules account data event in database: {e}")))
-		})?;
-
-	let mut global_ruleset = account_data_content.global;
-
-	// remove old deprecated mentions push rules as per MSC4210
-	// and update the stored server default push rules
-	#[allow(deprecated)]
-	{
-		use ruma::push::RuleKind::*;
-		if global_ruleset
-			.get(Override, PredefinedOverrideRuleId::ContainsDisplayName.as_str())
-			.is_some()
-			|| global_ruleset
-				.get(Override, PredefinedOverrideRuleId::RoomNotif.as_str())
-				.is_some()
-			|| global_ruleset
-				.get(Content, PredefinedContentRuleId::ContainsUserName.as_str())
-				.is_some()
-		{
-			global_ruleset
-				.remove(Override, PredefinedOverrideRuleId::ContainsDisplayName)
-				.ok();
-			global_ruleset
-				.remove(Override, PredefinedOverrideRuleId::RoomNotif)
-				.ok();
-			global_ruleset
-				.remove(Content, PredefinedContentRuleId::ContainsUserName)
-				.ok();
-
-			global_ruleset.update_with_server_default(Ruleset::server_default(sender_user));
-
-			services
-				.account_data
-				.update(
-					None,
-					sender_user,
-					GlobalAccountDataEventType::PushRules.to_string().into(),
-					&serde_json::to_value(PushRulesEvent {
-						content: PushRulesEventContent { global: global_ruleset.clone() },
-					})
-					.expect("to json always works"),
-				)
-				.await?;
-		}
-	};
-
-	Ok(get_pushrules_all::v3::Response { global: global_ruleset })
+	Ok(get_pushrules_all::v3::Response { global })
 }
 
 /// # `GET /_matrix/client/r0/pushrules/global/`
@@ -106,88 +41,10 @@ pub(crate) async fn get_pushrules_global_route(
 	State(services): State<crate::State>,
 	body: Ruma<get_pushrules_global_scope::v3::Request>,
 ) -> Result<get_pushrules_global_scope::v3::Response> {
-	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
-
-	let Some(content_value) = services
-		.account_data
-		.get_global::<CanonicalJsonObject>(sender_user, GlobalAccountDataEventType::PushRules)
-		.await
-		.ok()
-		.and_then(|event| event.get("content").cloned())
-		.filter(CanonicalJsonValue::is_object)
-	else {
-		// user somehow has non-existent push rule event. recreate it and return server
-		// default silently
-		services
-			.account_data
-			.update(
-				None,
-				sender_user,
-				GlobalAccountDataEventType::PushRules.to_string().into(),
-				&serde_json::to_value(PushRulesEvent {
-					content: PushRulesEventContent {
-						global: Ruleset::server_default(sender_user),
-					},
-				})
-				.expect("to json always works"),
-			)
-			.await?;
-
-		return Ok(get_pushrules_global_scope::v3::Response {
-			global: Ruleset::server_default(sender_user),
-		});
-	};
-
-	let account_data_content =
-		serde_json::from_value::<PushRulesEventContent>(content_value.into()).map_err(|e| {
-			err!(Database(warn!("Invalid push rules account data event in database: {e}")))
-		})?;
-
-	let mut global_ruleset = account_data_content.global;
+	let sender_user = body.sender_user();
+	let global = services.pusher.get_stored_ruleset(sender_user).await?;
 
-	// remove old deprecated mentions push rules as per MSC4210
-	// and update the stored server default push rules
-	#[allow(deprecated)]
-	{
-		use ruma::push::RuleKind::*;
-		if global_ruleset
-			.get(Override, PredefinedOverrideRuleId::ContainsDisplayName.as_str())
-			.is_some()
-			|| global_ruleset
-				.get(Override, PredefinedOverrideRuleId::RoomNotif.as_str())
-				.is_some()
-			|| global_ruleset
-				.get(Content, PredefinedContentRuleId::ContainsUserName.as_str())
-				.is_some()
-		{
-			global_ruleset
-				.remove(Override, PredefinedOverrideRuleId::ContainsDisplayName)
-				.ok();
-			global_ruleset
-				.remove(Override, PredefinedOverrideRuleId::RoomNotif)
-				.ok();
-			global_ruleset
-				.remove(Content, PredefinedContentRuleId::ContainsUserName)
-				.ok();
-
-			global_ruleset.update_with_server_default(Ruleset::server_default(sender_user));
-
-			services
-				.account_data
-				.update(
-					None,
-					sender_user,
-					GlobalAccountDataEventType::PushRules.to_string().into(),
-					&serde_json::to_value(PushRulesEvent {
-						content: PushRulesEventContent { global: global_ruleset.clone() },
-					})
-					.expect("to json always works"),
-				)
-				.await?;
-		}
-	};
-
-	Ok(get_pushrules_global_scope::v3::Response { global: global_ruleset })
+	Ok(get_pushrules_global_scope::v3::Response { global })
 }
 
 /// # `GET /_matrix/client/r0/pushrules/{scope}/{kind}/{ruleId}`
@@ -208,15 +65,9 @@ pub(crate) async fn get_pushrule_route(
 		return Err!(Request(NotFound("Push rule not found.")));
 	}
 
-	let event: PushRulesEvent = services
-		.account_data
-		.get_global(sender_user, GlobalAccountDataEventType::PushRules)
-		.await
-		.map_err(|_| err!(Request(NotFound("PushRules event not found."))))?;
+	let ruleset = services.pusher.get_stored_ruleset(sender_user).await?;
 
-	let rule = event
-		.content
-		.global
+	let rule = ruleset
 		.get(body.kind.clone(), &body.rule_id)
 		.map(Into::into);
 
@@ -237,17 +88,11 @@ pub(crate) async fn set_pushrule_route(
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 	let body = body.body;
 
-	let mut account_data: PushRulesEvent = services
-		.account_data
-		.get_global(sender_user, GlobalAccountDataEventType::PushRules)
-		.await
-		.map_err(|_| err!(Request(NotFound("PushRules event not found."))))?;
-
-	if let Err(error) = account_data.content.global.insert(
-		body.rule.clone(),
-		body.after.as_deref(),
-		body.before.as_deref(),
-	) {
+	let mut ruleset = services.pusher.get_stored_ruleset(sender_user).await?;
+
+	if let Err(error) =
+		ruleset.insert(body.rule.clone(), body.after.as_deref(), body.before.as_deref())
+	{
 		let err = match error {
 			| InsertPushRuleError::ServerDefaultRuleId => Error::BadRequest(
 				ErrorKind::InvalidParam,
@@ -275,15 +120,7 @@ pub(crate) async fn set_pushrule_route(
 		return Err(err);
 	}
 
-	services
-		.account_data
-		.update(
-			None,
-			sender_user,
-			GlobalAccountDataEventType::PushRules.to_string().into(),
-			&serde_json::to_value(account_data).expect("to json value always works"),
-		)
-		.await?;
+	services.pusher.set_ruleset(sender_user, &ruleset).await?;
 
 	Ok(set_pushrule::v3::Response {})
 }
@@ -306,15 +143,9 @@ pub(crate) async fn get_pushrule_actions_route(
 		return Err!(Request(NotFound("Push rule not found.")));
 	}
 
-	let event: PushRulesEvent = services
-		.account_data
-		.get_global(sender_user, GlobalAccountDataEventType::PushRules)
-		.await
-		.map_err(|_| err!(Request(NotFound("PushRules event not found."))))?;
+	let ruleset = services.pusher.get_stored_ruleset(sender_user).await?;
 
-	let actions = event
-		.content
-		.global
+	let actions = ruleset
 		.get(body.kind.clone(), &body.rule_id)
 		.map(|rule| rule.actions().to_owned())
 		.ok_or_else(|| err!(Request(NotFound("Push rule not found."))))?;
@@ -331,30 +162,16 @@ pub(crate) async fn set_pushrule_actions_route(
 ) -> Result<set_pushrule_actions::v3::Response> {
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
-	let mut account_data: PushRulesEvent = services
-		.account_data
-		.get_global(sender_user, GlobalAccountDataEventType::PushRules)
-		.await
-		.map_err(|_| err!(Request(NotFound("PushRules event not found."))))?;
+	let mut ruleset = services.pusher.get_stored_ruleset(sender_user).await?;
 
-	if account_data
-		.content
-		.global
+	if ruleset
 		.set_actions(body.kind.clone(), &body.rule_id, body.actions.clone())
 		.is_err()
 	{
 		return Err(Error::BadRequest(ErrorKind::NotFound, "Push rule not found."));
 	}
 
-	services
-		.account_data
-		.update(
-			None,
-			sender_user,
-			GlobalAccountDataEventType::PushRules.to_string().into(),
-			&serde_json::to_value(account_data).expect("to json value always works"),
-		)
-		.await?;
+	services.pusher.set_ruleset(sender_user, &ruleset).await?;
 
 	Ok(set_pushrule_actions::v3::Response {})
 }
@@ -377,15 +194,9 @@ pub(crate) async fn get_pushrule_enabled_route(
 		return Ok(get_pushrule_enabled::v3::Response { enabled: false });
 	}
 
-	let event: PushRulesEvent = services
-		.account_data
-		.get_global(sender_user, GlobalAccountDataEventType::PushRules)
-		.await
-		.map_err(|_| err!(Request(NotFound("PushRules event not found."))))?;
+	let ruleset = services.pusher.get_stored_ruleset(sender_user).await?;
 
-	let enabled = event
-		.content
-		.global
+	let enabled = ruleset
 		.get(body.kind.clone(), &body.rule_id)
 		.map(ruma::push::AnyPushRuleRef::enabled)
 		.ok_or_else(|| err!(Request(NotFound("Push rule not found."))))?;
@@ -402,30 +213,16 @@ pub(crate) async fn set_pushrule_enabled_route(
 ) -> Result<set_pushrule_enabled::v3::Response> {
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
-	let mut account_data: PushRulesEvent = services
-		.account_data
-		.get_global(sender_user, GlobalAccountDataEventType::PushRules)
-		.await
-		.map_err(|_| err!(Request(NotFound("PushRules event not found."))))?;
+	let mut ruleset = services.pusher.get_stored_ruleset(sender_user).await?;
 
-	if account_data
-		.content
-		.global
+	if ruleset
 		.set_enabled(body.kind.clone(), &body.rule_id, body.enabled)
 		.is_err()
 	{
 		return Err(Error::BadRequest(ErrorKind::NotFound, "Push rule not found."));
 	}
 
-	services
-		.account_data
-		.update(
-			None,
-			sender_user,
-			GlobalAccountDataEventType::PushRules.to_string().into(),
-			&serde_json::to_value(account_data).expect("to json value always works"),
-		)
-		.await?;
+	services.pusher.set_ruleset(sender_user, &ruleset).await?;
 
 	Ok(set_pushrule_enabled::v3::Response {})
 }
@@ -439,17 +236,9 @@ pub(crate) async fn delete_pushrule_route(
 ) -> Result<delete_pushrule::v3::Response> {
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
-	let mut account_data: PushRulesEvent = services
-		.account_data
-		.get_global(sender_user, GlobalAccountDataEventType::PushRules)
-		.await
-		.map_err(|_| err!(Request(NotFound("PushRules event not found."))))?;
+	let mut ruleset = services.pusher.get_stored_ruleset(sender_user).await?;
 
-	if let Err(error) = account_data
-		.content
-		.global
-		.remove(body.kind.clone(), &body.rule_id)
-	{
+	if let Err(error) = ruleset.remove(body.kind.clone(), &body.rule_id) {
 		let err = match error {
 			| RemovePushRuleError::ServerDefault => Error::BadRequest(
 				ErrorKind::InvalidParam,
@@ -463,15 +252,7 @@ pub(crate) async fn delete_pushrule_route(
 		return Err(err);
 	}
 
-	services
-		.account_data
-		.update(
-			None,
-			sender_user,
-			GlobalAccountDataEventType::PushRules.to_string().into(),
-			&serde_json::to_value(account_data).expect("to json value always works"),
-		)
-		.await?;
+	services.pusher.set_ruleset(sender_user, &ruleset).await?;
 
 	Ok(delete_pushrule::v3::Response {})
 }
@@ -509,28 +290,79 @@ pub(crate) async fn set_pushers_route(
 	Ok(set_pusher::v3::Response::new())
 }
 
-/// user somehow has bad push rules, these must always exist per spec.
-/// so recreate it and return server default silently
-async fn recreate_push_rules_and_return(
-	services: &Services,
-	sender_user: &ruma::UserId,
-) -> Result<get_pushrules_all::v3::Response> {
-	services
-		.account_data
-		.update(
-			None,
-			sender_user,
-			GlobalAccountDataEventType::PushRules.to_string().into(),
-			&serde_json::to_value(PushRulesEvent {
-				content: PushRulesEventContent {
-					global: Ruleset::server_default(sender_user),
-				},
-			})
-			.expect("to json always works"),
-		)
-		.await?;
+/// # `GET /_matrix/client/v3/notifications`
+///
+/// Lists the events which notified the user, newest first, and whether
+/// they've read them since.
+pub(crate) async fn get_notifications_route(
+	State(services): State<crate::State>,
+	ref body: Ruma<get_notifications::v3::Request>,
+) -> Result<get_notifications::v3::Response> {
+	let sender_user = body.sender_user();
 
-	Ok(get_pushrules_all::v3::Response {
-		global: Ruleset::server_default(sender_user),
+	// Use limit or else 50, with maximum 100
+	let limit = body
+		.limit
+		.unwrap_or_else(|| uint!(50))
+		.try_into()
+		.unwrap_or(50)
+		.min(100);
+
+	let from: u64 = body
+		.from
+		.as_deref()
+		.map(str::parse::<u64>)
+		.transpose()
+		.map_err(|e| err!(Request(InvalidParam("Invalid from token: {e}"))))?
+		.unwrap_or(u64::MAX);
+
+	let only_highlight = body.only.as_deref() == Some("highlight");
+
+	let notifications: Vec<(u64, get_notifications::v3::Notification)> = services
+		.rooms
+		.user
+		.notifications_until(sender_user, from)
+		.ready_filter(|(_, notification)| {
+			!only_highlight || notification.actions.iter().any(Action::is_highlight)
+		})
+		.filter_map(|(count, notification)| async move {
+			let pdu = services
+				.rooms
+				.timeline
+				.get_pdu(&notification.event_id)
+				.await
+				.ok()?;
+
+			let read = services
+				.rooms
+				.user
+				.notification_read(sender_user, &notification.room_id, count)
+				.await;
+
+			let ts = UInt::try_from(notification.ts).unwrap_or_default();
+			let notification = get_notifications::v3::Notification {
+				actions: notification.actions,
+				event: pdu.to_sync_room_event(),
+				profile_tag: None,
+				read,
+				room_id: notification.room_id,
+				ts: MilliSecondsSinceUnixEpoch(ts),
+			};
+
+			Some((count, notification))
+		})
+		.take(limit)
+		.collect()
+		.await;
+
+	Ok(get_notifications::v3::Response {
+		next_token: notifications
+			.last()
+			.filter(|_| notifications.len() >= limit)
+			.map(at!(0))
+			.as_ref()
+			.map(ToString::to_string),
+
+		notifications: notifications.into_iter().map(at!(1)).collect(),
 	})
 }
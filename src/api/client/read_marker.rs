@@ -8,10 +8,10 @@
 		receipt::{ReceiptThread, ReceiptType},
 		RoomAccountDataEventType,
 	},
-	MilliSecondsSinceUnixEpoch,
+	EventId, MilliSecondsSinceUnixEpoch, RoomId, UserId,
 };
 
-use crate::{Result, Ruma};
+use crate::{service::Services, Result, Ruma};
 
 /// # `POST /_matrix/client/r0/rooms/{roomId}/read_markers`
 ///
@@ -42,11 +42,18 @@ pub(crate) async fn set_read_marker_route(
 			.await?;
 	}
 
-	if body.private_read_receipt.is_some() || body.read_receipt.is_some() {
-		services
-			.rooms
-			.user
-			.reset_notification_counts(sender_user, &body.room_id);
+	for event in [&body.read_receipt, &body.private_read_receipt]
+		.into_iter()
+		.flatten()
+	{
+		mark_notifications_read(
+			&services,
+			sender_user,
+			&body.room_id,
+			event,
+			&ReceiptThread::Unthreaded,
+		)
+		.await;
 	}
 
 	// ping presence
@@ -115,14 +122,24 @@ pub(crate) async fn create_receipt_route(
 ) -> Result<create_receipt::v3::Response> {
 	let sender_user = body.sender_user();
 
+	if matches!(body.receipt_type, create_receipt::v3::ReceiptType::FullyRead)
+		&& body.thread != ReceiptThread::Unthreaded
+	{
+		return Err!(Request(InvalidParam("m.fully_read can't be given a thread.")));
+	}
+
 	if matches!(
 		&body.receipt_type,
 		create_receipt::v3::ReceiptType::Read | create_receipt::v3::ReceiptType::ReadPrivate
 	) {
-		services
-			.rooms
-			.user
-			.reset_notification_counts(sender_user, &body.room_id);
+		mark_notifications_read(
+			&services,
+			sender_user,
+			&body.room_id,
+			&body.event_id,
+			&body.thread,
+		)
+		.await;
 	}
 
 	// ping presence
@@ -159,7 +176,7 @@ pub(crate) async fn create_receipt_route(
 						sender_user.to_owned(),
 						ruma::events::receipt::Receipt {
 							ts: Some(MilliSecondsSinceUnixEpoch::now()),
-							thread: ReceiptThread::Unthreaded,
+							thread: body.thread.clone(),
 						},
 					)]),
 				)]),
@@ -206,3 +223,29 @@ pub(crate) async fn create_receipt_route(
 
 	Ok(create_receipt::v3::Response {})
 }
+
+/// Clears the notifications covered by a receipt at the event, updating the
+/// badge of the user's apps when their count changed.
+async fn mark_notifications_read(
+	services: &Services,
+	user_id: &UserId,
+	room_id: &RoomId,
+	event_id: &EventId,
+	thread: &ReceiptThread,
+) {
+	// Backfilled events are older than anything which notified
+	let Ok(PduCount::Normal(count)) = services.rooms.timeline.get_pdu_count(event_id).await
+	else {
+		return;
+	};
+
+	let changed = services
+		.rooms
+		.user
+		.mark_notifications_read(user_id, room_id, count, thread)
+		.await;
+
+	if changed {
+		services.pusher.send_badge_updates(user_id).await;
+	}
+}
@@ -20,6 +20,9 @@
 	Error, Result, Ruma,
 };
 
+/// Characters of a reported event's body shown in the admin room.
+const EVENT_PREVIEW_LEN: usize = 200;
+
 /// # `POST /_matrix/client/v3/rooms/{roomId}/report`
 ///
 /// Reports an abusive room to homeserver admins
@@ -58,16 +61,29 @@ pub(crate) async fn report_room_route(
 		)));
 	}
 
-	// send admin room message that we received the report with an @room ping for
-	// urgency
+	let report = services.reports.add(
+		sender_user.clone(),
+		body.room_id.clone(),
+		None,
+		body.reason.clone(),
+		None,
+	)?;
+
+	// The reason is attacker controlled, so it only goes in the escaped HTML. The
+	// plain body, which push rules match on, only holds IDs.
 	services
 		.admin
-		.send_message(message::RoomMessageEventContent::text_markdown(format!(
-			"@room Room report received from {} -\n\nRoom ID: {}\n\nReport Reason: {}",
-			sender_user.to_owned(),
-			body.room_id,
-			body.reason.as_deref().unwrap_or("")
-		)))
+		.send_message(message::RoomMessageEventContent::text_html(
+			format!("Room report {} received for {}", report.id, body.room_id),
+			format!(
+				"<p>Room report {} received from {}</p><p>Room ID: {}</p><p>Report Reason: \
+				 <code>{}</code></p>",
+				report.id,
+				escape_html(sender_user.as_str()),
+				escape_html(body.room_id.as_str()),
+				escape_html(body.reason.as_deref().unwrap_or("")),
+			),
+		))
 		.await
 		.ok();
 
@@ -112,20 +128,37 @@ pub(crate) async fn report_event_route(
 	)
 	.await?;
 
-	// send admin room message that we received the report with an @room ping for
-	// urgency
+	let report = services.reports.add(
+		sender_user.clone(),
+		pdu.room_id.clone(),
+		Some(pdu.event_id.clone().into()),
+		body.reason.clone(),
+		body.score.map(Into::into),
+	)?;
+
+	// The reason and event are attacker controlled, so they only go in the escaped
+	// HTML. The plain body, which push rules match on, only holds IDs.
 	services
 		.admin
-		.send_message(message::RoomMessageEventContent::text_markdown(format!(
-			"@room Event report received from {} -\n\nEvent ID: {}\nRoom ID: {}\nSent By: \
-			 {}\n\nReport Score: {}\nReport Reason: {}",
-			sender_user.to_owned(),
-			pdu.event_id,
-			pdu.room_id,
-			pdu.sender,
-			body.score.unwrap_or_else(|| ruma::Int::from(0)),
-			body.reason.as_deref().unwrap_or("")
-		)))
+		.send_message(message::RoomMessageEventContent::text_html(
+			format!(
+				"Event report {} received for {} in {}",
+				report.id, pdu.event_id, pdu.room_id
+			),
+			format!(
+				"<p>Event report {} received from {}</p><p>Event ID: {}<br>Room ID: {}<br>Sent \
+				 By: {}<br>Event: <code>{}</code></p><p>Report Score: {}<br>Report Reason: \
+				 <code>{}</code></p>",
+				report.id,
+				escape_html(sender_user.as_str()),
+				escape_html(pdu.event_id.as_str()),
+				escape_html(pdu.room_id.as_str()),
+				escape_html(pdu.sender.as_str()),
+				escape_html(&event_preview(&pdu)),
+				body.score.unwrap_or_else(|| ruma::Int::from(0)),
+				escape_html(body.reason.as_deref().unwrap_or("")),
+			),
+		))
 		.await
 		.ok();
 
@@ -189,6 +222,28 @@ async fn is_event_report_valid(
 	Ok(())
 }
 
+/// The event type, followed by the start of its body if it has one.
+fn event_preview(pdu: &PduEvent) -> String {
+	let content = pdu.get_content_as_value();
+	let Some(body) = content.get("body").and_then(serde_json::Value::as_str) else {
+		return pdu.kind.to_string();
+	};
+
+	let mut preview: String = body.chars().take(EVENT_PREVIEW_LEN).collect();
+	if preview.len() < body.len() {
+		preview.push('…');
+	}
+
+	format!("{} \"{preview}\"", pdu.kind)
+}
+
+fn escape_html(s: &str) -> String {
+	s.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
 /// even though this is kinda security by obscurity, let's still make a small
 /// random delay sending a response per spec suggestion regarding
 /// enumerating for potential events existing in our server.
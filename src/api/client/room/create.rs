@@ -14,6 +14,7 @@
 		room::{
 			canonical_alias::RoomCanonicalAliasEventContent,
 			create::RoomCreateEventContent,
+			encryption::RoomEncryptionEventContent,
 			guest_access::{GuestAccess, RoomGuestAccessEventContent},
 			history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
 			join_rules::{JoinRule, RoomJoinRulesEventContent},
@@ -26,7 +27,8 @@
 	},
 	int,
 	serde::{JsonObject, Raw},
-	CanonicalJsonObject, Int, OwnedRoomAliasId, OwnedRoomId, OwnedUserId, RoomId, RoomVersionId,
+	CanonicalJsonObject, EventEncryptionAlgorithm, Int, OwnedRoomAliasId, OwnedRoomId,
+	OwnedUserId, RoomId, RoomVersionId,
 };
 use serde_json::{json, value::to_raw_value};
 use service::{appservice::RegistrationInfo, Services};
@@ -239,13 +241,13 @@ pub(crate) async fn create_room_route(
 
 	if preset == RoomPreset::TrustedPrivateChat {
 		for invite in &body.invite {
-			if services.users.user_is_ignored(sender_user, invite).await {
+			if services.users.user_is_ignored(invite, sender_user).await {
 				return Err!(Request(Forbidden(
 					"You cannot invite users you have ignored to rooms."
 				)));
-			} else if services.users.user_is_ignored(invite, sender_user).await {
-				// silently drop the invite to the recipient if they've been ignored by the
-				// sender, pretend it worked
+			} else if services.users.user_is_ignored(sender_user, invite).await {
+				// silently drop the invite if the recipient ignores the sender, pretend it
+				// worked
 				continue;
 			}
 
@@ -353,6 +355,7 @@ pub(crate) async fn create_room_route(
 		.await?;
 
 	// 6. Events listed in initial_state
+	let mut encrypted = false;
 	for event in &body.initial_state {
 		let mut pdu_builder = event.deserialize_as::<PduBuilder>().map_err(|e| {
 			warn!("Invalid initial state event: {:?}", e);
@@ -380,6 +383,7 @@ pub(crate) async fn create_room_route(
 			continue;
 		}
 
+		encrypted |= pdu_builder.event_type == TimelineEventType::RoomEncryption;
 		services
 			.rooms
 			.timeline
@@ -388,6 +392,28 @@ pub(crate) async fn create_room_route(
 			.await?;
 	}
 
+	// Direct messages are encrypted even if the client didn't ask for it
+	if body.is_direct
+		&& !encrypted
+		&& services.server.config.encrypt_direct_messages
+		&& services.globals.allow_encryption()
+	{
+		services
+			.rooms
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(
+					String::new(),
+					&RoomEncryptionEventContent::new(EventEncryptionAlgorithm::MegolmV1AesSha2),
+				),
+				sender_user,
+				&room_id,
+				&state_lock,
+			)
+			.boxed()
+			.await?;
+	}
+
 	// 7. Events implied by name and topic
 	if let Some(name) = &body.name {
 		services
@@ -420,13 +446,13 @@ pub(crate) async fn create_room_route(
 	// 8. Events implied by invite (and TODO: invite_3pid)
 	drop(state_lock);
 	for user_id in &body.invite {
-		if services.users.user_is_ignored(sender_user, user_id).await {
+		if services.users.user_is_ignored(user_id, sender_user).await {
 			return Err!(Request(Forbidden(
 				"You cannot invite users you have ignored to rooms."
 			)));
-		} else if services.users.user_is_ignored(user_id, sender_user).await {
-			// silently drop the invite to the recipient if they've been ignored by the
-			// sender, pretend it worked
+		} else if services.users.user_is_ignored(sender_user, user_id).await {
+			// silently drop the invite if the recipient ignores the sender, pretend it
+			// worked
 			continue;
 		}
 
@@ -32,11 +32,8 @@ pub(crate) async fn get_room_event_route(
 
 	let (token, mut event, visible) = try_join!(token, event, visible)?;
 
-	if !visible
-		|| ignored_filter(&services, (token, event.clone()), body.sender_user())
-			.await
-			.is_none()
-	{
+	let ignored = services.users.ignored_users(body.sender_user()).await;
+	if !visible || ignored_filter(&services, &ignored, (token, event.clone())).is_none() {
 		return Err!(Request(Forbidden("You don't have permission to view this event.")));
 	}
 
@@ -1,7 +1,7 @@
 use std::cmp::max;
 
 use axum::extract::State;
-use conduwuit::{err, info, pdu::PduBuilder, Error, Result};
+use conduwuit::{debug_warn, err, info, pdu::PduBuilder, utils::stream::ReadyExt, Error, Result};
 use futures::StreamExt;
 use ruma::{
 	api::client::{error::ErrorKind, room::upgrade_room},
@@ -13,7 +13,7 @@
 		},
 		StateEventType, TimelineEventType,
 	},
-	int, CanonicalJsonObject, RoomId, RoomVersionId,
+	int, CanonicalJsonObject, OwnedUserId, RoomId, RoomVersionId,
 };
 use serde_json::{json, value::to_raw_value};
 
@@ -41,6 +41,7 @@
 /// - Sender user joins the room
 /// - Transfers some state events
 /// - Moves local aliases
+/// - Invites joined local users if `room_upgrade_invite_local_members` is set
 /// - Modifies old room power levels to prevent users from speaking
 pub(crate) async fn upgrade_room_route(
 	State(services): State<crate::State>,
@@ -227,6 +228,38 @@ pub(crate) async fn upgrade_room_route(
 			.await?;
 	}
 
+	// Invite the local members of the old room so they can follow along
+	if services.server.config.room_upgrade_invite_local_members {
+		let local_members: Vec<OwnedUserId> = services
+			.rooms
+			.state_cache
+			.local_users_in_room(&body.room_id)
+			.ready_filter(|user_id| *user_id != &**sender_user)
+			.map(ToOwned::to_owned)
+			.collect()
+			.await;
+
+		for user_id in local_members {
+			let invite = services
+				.rooms
+				.timeline
+				.build_and_append_pdu(
+					PduBuilder::state(
+						user_id.to_string(),
+						&RoomMemberEventContent::new(MembershipState::Invite),
+					),
+					sender_user,
+					&replacement_room,
+					&state_lock,
+				)
+				.await;
+
+			if let Err(e) = invite {
+				debug_warn!(%user_id, "Failed to invite user to upgraded room: {e}");
+			}
+		}
+	}
+
 	// Moves any local aliases to the new room
 	let mut local_aliases = services
 		.rooms
@@ -266,6 +299,10 @@ pub(crate) async fn upgrade_room_route(
 			})?,
 	);
 
+	// Change lock back to the old room
+	drop(state_lock);
+	let state_lock = services.rooms.state.mutex.lock(&body.room_id).await;
+
 	// Modify the power levels in the old room to prevent sending of events and
 	// inviting new users
 	services
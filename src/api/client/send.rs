@@ -1,11 +1,18 @@
 use std::collections::BTreeMap;
 
-use axum::extract::State;
+use axum::{
+	extract::{Query, State},
+	response::{IntoResponse, Response},
+};
 use conduwuit::{err, Err};
 use ruma::{api::client::message::send_message_event, events::MessageLikeEventType};
 use serde_json::from_str;
 
-use crate::{service::pdu::PduBuilder, utils, Result, Ruma};
+use super::{send_delayed_message_event, DelayQuery};
+use crate::{
+	service::{pdu::PduBuilder, rate_limiting::Action, Services},
+	utils, Result, Ruma, RumaResponse,
+};
 
 /// # `PUT /_matrix/client/v3/rooms/{roomId}/send/{eventType}/{txnId}`
 ///
@@ -16,9 +23,25 @@
 /// - The only requirement for the content is that it has to be valid json
 /// - Tries to send the event into the room, auth rules will determine if it is
 ///   allowed
+/// - With the `org.matrix.msc4140.delay` query parameter the event is sent
+///   later instead, returning a `delay_id` (MSC4140)
 pub(crate) async fn send_message_event_route(
 	State(services): State<crate::State>,
+	Query(query): Query<DelayQuery>,
 	body: Ruma<send_message_event::v3::Request>,
+) -> Result<Response> {
+	if let Some(delay) = query.delay {
+		return send_delayed_message_event(&services, &body, delay).await;
+	}
+
+	send_message_event(&services, &body)
+		.await
+		.map(|response| RumaResponse(response).into_response())
+}
+
+async fn send_message_event(
+	services: &Services,
+	body: &Ruma<send_message_event::v3::Request>,
 ) -> Result<send_message_event::v3::Response> {
 	let sender_user = body.sender_user();
 	let sender_device = body.sender_device.as_deref();
@@ -60,6 +83,11 @@ pub(crate) async fn send_message_event_route(
 		});
 	}
 
+	services
+		.rate_limiting
+		.check_user(sender_user, Action::Message)
+		.await?;
+
 	let mut unsigned = BTreeMap::new();
 	unsigned.insert("transaction_id".to_owned(), body.txn_id.to_string().into());
 
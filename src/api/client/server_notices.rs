@@ -0,0 +1,88 @@
+//! Sending server notices over HTTP, for server admins automating
+//! announcements without going through the admin room.
+
+use axum::extract::State;
+use conduwuit::{Err, Result};
+
+use crate::Ruma;
+
+/// # `POST /_conduwuit/admin/server_notice`
+///
+/// Sends a server notice to one local user, or queues it for every active
+/// local user if `all` is set, sent in the background like `!admin server
+/// announce`. Only server admins may use this.
+pub(crate) async fn send_server_notice_route(
+	State(services): State<crate::State>,
+	body: Ruma<send_server_notice::Request>,
+) -> Result<send_server_notice::Response> {
+	if !services.users.is_admin(body.sender_user()).await {
+		return Err!(Request(Forbidden("Only server admins can send server notices.")));
+	}
+
+	if body.body.is_empty() {
+		return Err!(Request(InvalidParam("The notice body cannot be empty.")));
+	}
+
+	match (&body.user_id, body.all) {
+		| (Some(_), true) | (None, false) =>
+			Err!(Request(InvalidParam("Exactly one of user_id or all must be specified."))),
+		| (Some(user_id), false) => {
+			if !services.globals.user_is_local(user_id) {
+				return Err!(Request(InvalidParam(
+					"Server notices can only be sent to local users."
+				)));
+			}
+
+			let event_id = services
+				.server_notices
+				.send_notice(user_id, &body.body)
+				.await?;
+
+			Ok(send_server_notice::Response { event_id: Some(event_id), queued: false })
+		},
+		| (None, true) => {
+			services.server_notices.announce(body.body.clone())?;
+
+			Ok(send_server_notice::Response { event_id: None, queued: true })
+		},
+	}
+}
+
+pub(crate) mod send_server_notice {
+	use ruma::{
+		api::{client::Error, request, response, Metadata},
+		metadata, OwnedEventId, OwnedUserId,
+	};
+
+	const METADATA: Metadata = metadata! {
+		method: POST,
+		rate_limited: false,
+		authentication: AccessToken,
+		history: {
+			unstable => "/_conduwuit/admin/server_notice",
+		}
+	};
+
+	#[request(error = Error)]
+	pub struct Request {
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		pub user_id: Option<OwnedUserId>,
+
+		#[serde(default)]
+		pub all: bool,
+
+		/// The notice, as markdown.
+		pub body: String,
+	}
+
+	#[response(error = Error)]
+	pub struct Response {
+		/// The notice's event ID when sent to a single user.
+		#[serde(skip_serializing_if = "Option::is_none")]
+		pub event_id: Option<OwnedEventId>,
+
+		/// Whether the notice was queued for every user. Progress is reported
+		/// in the admin room.
+		pub queued: bool,
+	}
+}
@@ -11,7 +11,10 @@
 			get_login_token,
 			get_login_types::{
 				self,
-				v3::{ApplicationServiceLoginType, PasswordLoginType, TokenLoginType},
+				v3::{
+					ApplicationServiceLoginType, IdentityProvider, PasswordLoginType,
+					SsoLoginType, TokenLoginType,
+				},
 			},
 			login::{
 				self,
@@ -23,7 +26,7 @@
 	},
 	OwnedUserId, UserId,
 };
-use service::uiaa::SESSION_ID_LENGTH;
+use service::{rate_limiting::Action, uiaa::SESSION_ID_LENGTH};
 
 use super::{DEVICE_ID_LENGTH, TOKEN_LENGTH};
 use crate::{utils, utils::hash, Error, Result, Ruma};
@@ -38,13 +41,31 @@ pub(crate) async fn get_login_types_route(
 	InsecureClientIp(client): InsecureClientIp,
 	_body: Ruma<get_login_types::v3::Request>,
 ) -> Result<get_login_types::v3::Response> {
-	Ok(get_login_types::v3::Response::new(vec![
+	let mut flows = vec![
 		get_login_types::v3::LoginType::Password(PasswordLoginType::default()),
 		get_login_types::v3::LoginType::ApplicationService(ApplicationServiceLoginType::default()),
 		get_login_types::v3::LoginType::Token(TokenLoginType {
 			get_login_token: services.server.config.login_via_existing_session,
 		}),
-	]))
+	];
+
+	if services.sso.enabled() {
+		let identity_providers = services
+			.sso
+			.providers()
+			.iter()
+			.map(|provider| {
+				let name = provider.name.clone().unwrap_or_else(|| provider.id.clone());
+				let mut identity_provider = IdentityProvider::new(provider.id.clone(), name);
+				identity_provider.icon.clone_from(&provider.icon);
+				identity_provider
+			})
+			.collect();
+
+		flows.push(get_login_types::v3::LoginType::Sso(SsoLoginType { identity_providers }));
+	}
+
+	Ok(get_login_types::v3::Response::new(flows))
 }
 
 /// # `POST /_matrix/client/v3/login`
@@ -111,7 +132,8 @@ pub(crate) async fn login_route(
 		},
 		| login::v3::LoginInfo::Token(login::v3::Token { token }) => {
 			debug!("Got token login type");
-			if !services.server.config.login_via_existing_session {
+			// Login tokens are also handed out by identity providers' logins
+			if !services.server.config.login_via_existing_session && !services.sso.enabled() {
 				return Err!(Request(Unknown("Token login is not enabled.")));
 			}
 			services.users.find_from_login_token(token).await?
@@ -232,7 +254,10 @@ pub(crate) async fn login_route(
 /// # `POST /_matrix/client/v1/login/get_token`
 ///
 /// Allows a logged-in user to get a short-lived token which can be used
-/// to log in with the m.login.token flow.
+/// to log in with the m.login.token flow, e.g. to sign in on another device.
+///
+/// - Requires UIA; each completed UIA session is good for one token
+/// - Rate limited per user by `rate_limit_login_token_per_second`
 ///
 /// <https://spec.matrix.org/v1.13/client-server-api/#post_matrixclientv1loginget_token>
 #[tracing::instrument(skip_all, fields(%client), name = "login_token")]
@@ -248,11 +273,8 @@ pub(crate) async fn login_token_route(
 	let sender_user = body.sender_user();
 	let sender_device = body.sender_device();
 
-	// This route SHOULD have UIA
-	// TODO: How do we make only UIA sessions that have not been used before valid?
-
 	let mut uiaainfo = uiaa::UiaaInfo {
-		flows: vec![uiaa::AuthFlow { stages: vec![uiaa::AuthType::Password] }],
+		flows: services.uiaa.reauth_flows(),
 		completed: Vec::new(),
 		params: Box::default(),
 		session: None,
@@ -260,6 +282,13 @@ pub(crate) async fn login_token_route(
 	};
 
 	if let Some(auth) = &body.auth {
+		// Checked first, so a rate limited request leaves the UIAA session to
+		// be retried.
+		services
+			.rate_limiting
+			.check_user(sender_user, Action::LoginToken)
+			.await?;
+
 		let (worked, uiaainfo) = services
 			.uiaa
 			.try_auth(sender_user, sender_device, auth, &uiaainfo)
@@ -269,7 +298,8 @@ pub(crate) async fn login_token_route(
 			return Err(Error::Uiaa(uiaainfo));
 		}
 
-		// Success!
+		// Success! The UIAA session is gone now, so it can't be used for
+		// another token.
 	} else if let Some(json) = body.json_body.as_ref() {
 		uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
 		services
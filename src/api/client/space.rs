@@ -1,12 +1,7 @@
-use std::str::FromStr;
-
 use axum::extract::State;
-use ruma::{
-	api::client::{error::ErrorKind, space::get_hierarchy},
-	UInt,
-};
+use ruma::{api::client::space::get_hierarchy, UInt};
 
-use crate::{service::rooms::spaces::PaginationToken, Error, Result, Ruma};
+use crate::{Result, Ruma};
 
 /// # `GET /_matrix/client/v1/rooms/{room_id}/hierarchy`
 ///
@@ -28,21 +23,6 @@ pub(crate) async fn get_hierarchy_route(
 		.unwrap_or_else(|| UInt::from(3_u32))
 		.min(UInt::from(10_u32));
 
-	let key = body
-		.from
-		.as_ref()
-		.and_then(|s| PaginationToken::from_str(s).ok());
-
-	// Should prevent unexpeded behaviour in (bad) clients
-	if let Some(ref token) = key {
-		if token.suggested_only != body.suggested_only || token.max_depth != max_depth {
-			return Err(Error::BadRequest(
-				ErrorKind::InvalidParam,
-				"suggested_only and max_depth cannot change on paginated requests",
-			));
-		}
-	}
-
 	services
 		.rooms
 		.spaces
@@ -50,7 +30,7 @@ pub(crate) async fn get_hierarchy_route(
 			sender_user,
 			&body.room_id,
 			limit.try_into().unwrap_or(10),
-			key.map_or(vec![], |token| token.short_room_ids),
+			body.from.as_deref(),
 			max_depth.into(),
 			body.suggested_only,
 		)
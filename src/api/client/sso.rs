@@ -0,0 +1,177 @@
+use std::net::IpAddr;
+
+use axum::{
+	extract::{Query, State},
+	response::{Html, IntoResponse, Redirect, Response},
+};
+use axum_client_ip::InsecureClientIp;
+use conduwuit::{debug_info, utils::HtmlEscape, Result};
+use http::{header, HeaderMap, StatusCode};
+use reqwest::Url;
+use ruma::api::client::session::{sso_login, sso_login_with_provider};
+use serde::Deserialize;
+use service::{
+	sso::{Completion, Intent, Redirection},
+	Services,
+};
+
+use crate::Ruma;
+
+/// # `GET /_matrix/client/v3/login/sso/redirect`
+///
+/// Redirects the user to the first configured identity provider, which sends
+/// them back to `redirectUrl` with a login token.
+pub(crate) async fn sso_login_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	body: Ruma<sso_login::v3::Request>,
+) -> Result<sso_login::v3::Response> {
+	let Redirection { location, cookie } =
+		start_login(&services, None, &body.redirect_url, client).await?;
+
+	let mut response = sso_login::v3::Response::new(location.into());
+	response.cookie = Some(cookie);
+
+	Ok(response)
+}
+
+/// # `GET /_matrix/client/v3/login/sso/redirect/{idpId}`
+///
+/// Redirects the user to the given identity provider, which sends them back
+/// to `redirectUrl` with a login token.
+pub(crate) async fn sso_login_with_provider_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	body: Ruma<sso_login_with_provider::v3::Request>,
+) -> Result<sso_login_with_provider::v3::Response> {
+	let Redirection { location, cookie } =
+		start_login(&services, Some(&body.idp_id), &body.redirect_url, client).await?;
+
+	let mut response = sso_login_with_provider::v3::Response::new(location.into());
+	response.cookie = Some(cookie);
+
+	Ok(response)
+}
+
+async fn start_login(
+	services: &Services,
+	provider_id: Option<&str>,
+	redirect_url: &str,
+	client: IpAddr,
+) -> Result<Redirection> {
+	let redirect_url = redirect_url.to_owned();
+
+	services
+		.sso
+		.start(provider_id, Intent::Login { redirect_url }, client)
+		.await
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SsoFallbackQuery {
+	session: String,
+}
+
+/// # `GET /_matrix/client/v3/auth/m.login.sso/fallback/web`
+///
+/// Opened by clients for the `m.login.sso` UIAA stage; the user is sent to
+/// the identity provider to confirm their identity.
+pub(crate) async fn sso_fallback_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	Query(query): Query<SsoFallbackQuery>,
+) -> Response {
+	let intent = Intent::Reauth { session: query.session };
+	match services.sso.start(None, intent, client).await {
+		| Ok(Redirection { location, cookie }) =>
+			([(header::SET_COOKIE, cookie)], Redirect::to(location.as_str())).into_response(),
+		| Err(e) => (StatusCode::BAD_REQUEST, e.message()).into_response(),
+	}
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SsoCallbackQuery {
+	state: String,
+	code: Option<String>,
+	error: Option<String>,
+}
+
+/// Page shown once the `m.login.sso` UIAA stage is done, notifying the
+/// client as the fallback authentication spec describes.
+const REAUTH_DONE_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><title>Authentication complete</title></head>
+<body>
+<p>Thank you. You can now return to your client to continue.</p>
+<script>
+if (window.onAuthDone) {
+	window.onAuthDone();
+} else if (window.opener && window.opener.postMessage) {
+	window.opener.postMessage(\"authDone\", \"*\");
+}
+</script>
+</body>
+</html>
+";
+
+/// Page asking the user to confirm being sent back to the client which started
+/// the login, so a login started by a phishing site doesn't hand it the login
+/// token unnoticed.
+fn confirm_page(redirect_url: &Url) -> String {
+	let mut client = redirect_url.clone();
+	client.set_query(None);
+	client.set_fragment(None);
+
+	let client = HtmlEscape(client.as_str());
+	let link = HtmlEscape(redirect_url.as_str());
+
+	format!(
+		"<!DOCTYPE html>
+<html>
+<head><title>Continue to {client}?</title></head>
+<body>
+<p>You are about to log in to <strong>{client}</strong>.</p>
+<p>Only continue if you were logging in to this client; otherwise close this page.</p>
+<p><a href=\"{link}\">Continue to {client}</a></p>
+</body>
+</html>
+"
+	)
+}
+
+/// # `GET /_conduwuit/sso/callback`
+///
+/// Identity providers send the browser back here once the user logged in.
+/// Only the browser which started the login, carrying its session cookie,
+/// can complete it.
+pub(crate) async fn sso_callback_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	headers: HeaderMap,
+	Query(query): Query<SsoCallbackQuery>,
+) -> Response {
+	let Some(code) = query.code else {
+		let error = query.error.as_deref().unwrap_or("no authorization code");
+		debug_info!("Identity provider returned without a code: {error}");
+		return (StatusCode::BAD_REQUEST, "The identity provider did not log you in.")
+			.into_response();
+	};
+
+	let cookies = headers
+		.get(header::COOKIE)
+		.and_then(|cookies| cookies.to_str().ok());
+
+	match services
+		.sso
+		.finish(&query.state, &code, cookies, client)
+		.await
+	{
+		| Ok(Completion::Login { redirect_url }) =>
+			Html(confirm_page(&redirect_url)).into_response(),
+		| Ok(Completion::Reauth) => Html(REAUTH_DONE_PAGE).into_response(),
+		| Err(e) => {
+			debug_info!("Failed to complete SSO login: {e}");
+			(StatusCode::FORBIDDEN, e.message()).into_response()
+		},
+	}
+}
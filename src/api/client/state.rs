@@ -1,11 +1,21 @@
-use axum::extract::State;
-use conduwuit::{err, pdu::PduBuilder, utils::BoolExt, Err, PduEvent, Result};
-use futures::TryStreamExt;
+use std::net::IpAddr;
+
+use axum::{
+	extract::{Query, State},
+	response::{IntoResponse, Response},
+};
+use axum_client_ip::InsecureClientIp;
+use conduwuit::{err, pdu::PduBuilder, utils::BoolExt, Err, Error, PduEvent, Result};
+use futures::{StreamExt, TryStreamExt};
 use ruma::{
-	api::client::state::{get_state_events, get_state_events_for_key, send_state_event},
+	api::client::{
+		error::ErrorKind,
+		state::{get_state_events, get_state_events_for_key, send_state_event},
+	},
 	events::{
 		room::{
 			canonical_alias::RoomCanonicalAliasEventContent,
+			encryption::RoomEncryptionEventContent,
 			history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
 			join_rules::{JoinRule, RoomJoinRulesEventContent},
 			member::{MembershipState, RoomMemberEventContent},
@@ -13,22 +23,32 @@
 		AnyStateEventContent, StateEventType,
 	},
 	serde::Raw,
-	OwnedEventId, RoomId, UserId,
+	EventId, OwnedEventId, RoomId, UserId,
 };
+use serde::Deserialize;
 use service::Services;
 
+use super::{send_delayed_state_event, DelayQuery};
 use crate::{Ruma, RumaResponse};
 
 /// # `PUT /_matrix/client/*/rooms/{roomId}/state/{eventType}/{stateKey}`
 ///
 /// Sends a state event into the room.
+///
+/// - With the `org.matrix.msc4140.delay` query parameter the event is sent
+///   later instead, returning a `delay_id` (MSC4140)
 pub(crate) async fn send_state_event_for_key_route(
 	State(services): State<crate::State>,
+	Query(query): Query<DelayQuery>,
 	body: Ruma<send_state_event::v3::Request>,
-) -> Result<send_state_event::v3::Response> {
+) -> Result<Response> {
+	if let Some(delay) = query.delay {
+		return send_delayed_state_event(&services, &body, delay).await;
+	}
+
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
-	Ok(send_state_event::v3::Response {
+	let response = send_state_event::v3::Response {
 		event_id: send_state_event_for_key_helper(
 			&services,
 			sender_user,
@@ -43,7 +63,9 @@ pub(crate) async fn send_state_event_for_key_route(
 			},
 		)
 		.await?,
-	})
+	};
+
+	Ok(RumaResponse(response).into_response())
 }
 
 /// # `PUT /_matrix/client/*/rooms/{roomId}/state/{eventType}`
@@ -51,11 +73,18 @@ pub(crate) async fn send_state_event_for_key_route(
 /// Sends a state event into the room.
 pub(crate) async fn send_state_event_for_empty_key_route(
 	State(services): State<crate::State>,
+	query: Query<DelayQuery>,
 	body: Ruma<send_state_event::v3::Request>,
-) -> Result<RumaResponse<send_state_event::v3::Response>> {
-	send_state_event_for_key_route(State(services), body)
-		.await
-		.map(RumaResponse)
+) -> Result<Response> {
+	send_state_event_for_key_route(State(services), query, body).await
+}
+
+/// Query parameters of `/state` beyond the spec.
+#[derive(Deserialize)]
+pub(crate) struct StateAtQuery {
+	/// An event ID or a sync or pagination token to get the state at instead
+	/// of the current state.
+	at: Option<String>,
 }
 
 /// # `GET /_matrix/client/v3/rooms/{roomid}/state`
@@ -64,17 +93,48 @@ pub(crate) async fn send_state_event_for_empty_key_route(
 ///
 /// - If not joined: Only works if current room history visibility is world
 ///   readable
+/// - With `?at=`, gets the state at an event or a token instead, if the user
+///   could see the room's history at that point
 pub(crate) async fn get_state_events_route(
 	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	Query(query): Query<StateAtQuery>,
 	body: Ruma<get_state_events::v3::Request>,
 ) -> Result<get_state_events::v3::Response> {
-	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+	if let Some(at) = query.at.as_deref() {
+		let (shortstatehash, event_id) = services
+			.rooms
+			.state_accessor
+			.state_at(&body.room_id, at)
+			.await?;
 
-	if !services
-		.rooms
-		.state_accessor
-		.user_can_see_state_events(sender_user, &body.room_id)
-		.await
+		if !can_see_state_at(
+			&services,
+			body.sender_user.as_deref(),
+			client,
+			&body.room_id,
+			&event_id,
+		)
+		.await?
+		{
+			return Err!(Request(Forbidden(
+				"You don't have permission to view the room state at this point."
+			)));
+		}
+
+		return Ok(get_state_events::v3::Response {
+			room_state: services
+				.rooms
+				.state_accessor
+				.state_full_pdus(shortstatehash)
+				.map(PduEvent::into_state_event)
+				.collect()
+				.await,
+		});
+	}
+
+	if !can_see_state_events(&services, body.sender_user.as_deref(), client, &body.room_id)
+		.await?
 	{
 		return Err!(Request(Forbidden("You don't have permission to view the room state.")));
 	}
@@ -100,15 +160,11 @@ pub(crate) async fn get_state_events_route(
 ///   readable
 pub(crate) async fn get_state_events_for_key_route(
 	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
 	body: Ruma<get_state_events_for_key::v3::Request>,
 ) -> Result<get_state_events_for_key::v3::Response> {
-	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
-
-	if !services
-		.rooms
-		.state_accessor
-		.user_can_see_state_events(sender_user, &body.room_id)
-		.await
+	if !can_see_state_events(&services, body.sender_user.as_deref(), client, &body.room_id)
+		.await?
 	{
 		return Err!(Request(Forbidden("You don't have permission to view the room state.")));
 	}
@@ -147,13 +203,78 @@ pub(crate) async fn get_state_events_for_key_route(
 ///   readable
 pub(crate) async fn get_state_events_for_empty_key_route(
 	State(services): State<crate::State>,
+	client: InsecureClientIp,
 	body: Ruma<get_state_events_for_key::v3::Request>,
 ) -> Result<RumaResponse<get_state_events_for_key::v3::Response>> {
-	get_state_events_for_key_route(State(services), body)
+	get_state_events_for_key_route(State(services), client, body)
 		.await
 		.map(RumaResponse)
 }
 
+/// Whether the requester may read the current state of a room. Clients
+/// without an access token are peeking and only see world readable rooms.
+async fn can_see_state_events(
+	services: &Services,
+	sender_user: Option<&UserId>,
+	client: IpAddr,
+	room_id: &RoomId,
+) -> Result<bool> {
+	let Some(sender_user) = sender_user else {
+		check_peek_ratelimit(services, client)?;
+
+		return Ok(services
+			.rooms
+			.state_accessor
+			.anonymous_can_see_state_events(room_id)
+			.await);
+	};
+
+	Ok(services
+		.rooms
+		.state_accessor
+		.user_can_see_state_events(sender_user, room_id)
+		.await)
+}
+
+/// Whether the room state at the given event may be seen, i.e. the room's
+/// history was visible to the user, or world readable, at that event.
+pub(crate) async fn can_see_state_at(
+	services: &Services,
+	sender_user: Option<&UserId>,
+	client: IpAddr,
+	room_id: &RoomId,
+	event_id: &EventId,
+) -> Result<bool> {
+	let Some(sender_user) = sender_user else {
+		check_peek_ratelimit(services, client)?;
+
+		return Ok(services
+			.rooms
+			.state_accessor
+			.anonymous_can_see_event(event_id)
+			.await);
+	};
+
+	Ok(services
+		.rooms
+		.state_accessor
+		.user_can_see_event(sender_user, room_id, event_id)
+		.await)
+}
+
+/// Rejects unauthenticated peeking clients which exceed their rate limit.
+pub(crate) fn check_peek_ratelimit(services: &Services, client: IpAddr) -> Result {
+	if services.globals.peek_ratelimited(client) {
+		return Err(Error::Request(
+			ErrorKind::LimitExceeded { retry_after: None },
+			"Too many requests, slow down.".into(),
+			http::StatusCode::TOO_MANY_REQUESTS,
+		));
+	}
+
+	Ok(())
+}
+
 async fn send_state_event_for_key_helper(
 	services: &Services,
 	sender: &UserId,
@@ -199,10 +320,35 @@ async fn allowed_to_send_state_event(
 			)));
 		},
 		// Forbid m.room.encryption if encryption is disabled
-		| StateEventType::RoomEncryption =>
+		| StateEventType::RoomEncryption => {
 			if !services.globals.allow_encryption() {
 				return Err!(Request(Forbidden("Encryption is disabled on this homeserver.")));
-			},
+			}
+
+			// Once set, encryption may not be removed or its algorithm changed
+			if services.server.config.forbid_disabling_encryption {
+				if let Ok(current) = services
+					.rooms
+					.state_accessor
+					.room_state_get_content::<RoomEncryptionEventContent>(
+						room_id,
+						&StateEventType::RoomEncryption,
+						"",
+					)
+					.await
+				{
+					let unchanged =
+						serde_json::from_str::<RoomEncryptionEventContent>(json.json().get())
+							.is_ok_and(|content| content.algorithm == current.algorithm);
+
+					if !unchanged {
+						return Err!(Request(Forbidden(
+							"Encryption cannot be disabled or changed in this room."
+						)));
+					}
+				}
+			}
+		},
 		// admin room is a sensitive room, it should not ever be made public
 		| StateEventType::RoomJoinRules => {
 			if let Ok(admin_room_id) = services.admin.get_admin_room().await {
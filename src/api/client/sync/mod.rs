@@ -2,6 +2,8 @@
 mod v4;
 mod v5;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use conduwuit::{
 	utils::{
 		stream::{BroadbandExt, ReadyExt, TryIgnore},
@@ -11,6 +13,7 @@
 };
 use futures::{pin_mut, StreamExt};
 use ruma::{
+	api::client::filter::RoomEventFilter,
 	directory::RoomTypeFilter,
 	events::TimelineEventType::{
 		self, Beacon, CallInvite, PollStart, RoomEncrypted, RoomMessage, Sticker,
@@ -26,6 +29,11 @@
 pub(crate) const DEFAULT_BUMP_TYPES: &[TimelineEventType; 6] =
 	&[CallInvite, PollStart, Beacon, RoomEncrypted, RoomMessage, Sticker];
 
+/// Most events examined to fill a room's timeline. A filter matching few of
+/// a room's events would otherwise have the whole history read; the timeline
+/// is marked limited when the scan stops here.
+const TIMELINE_SCAN_MAX: usize = 1000;
+
 async fn load_timeline(
 	services: &Services,
 	sender_user: &UserId,
@@ -33,6 +41,7 @@ async fn load_timeline(
 	roomsincecount: PduCount,
 	next_batch: Option<PduCount>,
 	limit: usize,
+	filter: Option<&RoomEventFilter>,
 ) -> Result<(Vec<(PduCount, PduEvent)>, bool), Error> {
 	let last_timeline_count = services
 		.rooms
@@ -44,13 +53,17 @@ async fn load_timeline(
 		return Ok((Vec::new(), false));
 	}
 
+	let scanned = AtomicUsize::new(0);
 	let non_timeline_pdus = services
 		.rooms
 		.timeline
 		.pdus_rev(Some(sender_user), room_id, None)
 		.ignore_err()
 		.ready_skip_while(|&(pducount, _)| pducount > next_batch.unwrap_or_else(PduCount::max))
-		.ready_take_while(|&(pducount, _)| pducount > roomsincecount);
+		.ready_take_while(|&(pducount, _)| pducount > roomsincecount)
+		.take(TIMELINE_SCAN_MAX)
+		.inspect(|_| _ = scanned.fetch_add(1, Ordering::Relaxed))
+		.ready_filter(|(_, pdu)| filter.is_none_or(|filter| pdu.matches(filter)));
 
 	// Take the last events for the timeline
 	pin_mut!(non_timeline_pdus);
@@ -60,7 +73,8 @@ async fn load_timeline(
 
 	// They /sync response doesn't always return all messages, so we say the output
 	// is limited unless there are events in non_timeline_pdus
-	let limited = non_timeline_pdus.next().await.is_some();
+	let limited = non_timeline_pdus.next().await.is_some()
+		|| scanned.load(Ordering::Relaxed) >= TIMELINE_SCAN_MAX;
 
 	Ok((timeline_pdus, limited))
 }
@@ -9,11 +9,12 @@
 	at, err, error, extract_variant, is_equal_to, pair_of,
 	pdu::{Event, EventHash},
 	ref_at,
-	result::FlatOk,
+	result::{FlatOk, LogErr},
 	utils::{
 		self,
 		math::ruma_from_u64,
-		stream::{BroadbandExt, Tools, TryExpect, WidebandExt},
+		project_fields,
+		stream::{BroadbandExt, Tools, TryExpect},
 		BoolExt, IterStream, ReadyExt, TryFutureExtExt,
 	},
 	PduCount, PduEvent, Result,
@@ -23,16 +24,18 @@
 		lazy_loading,
 		lazy_loading::{Options, Witness},
 		short::ShortStateHash,
+		timeline::PdusIterItem,
 	},
+	sync::Snapshot,
 	Services,
 };
 use futures::{
 	future::{join, join3, join4, join5, try_join, try_join4, OptionFuture},
-	FutureExt, StreamExt, TryFutureExt, TryStreamExt,
+	pin_mut, FutureExt, StreamExt, TryFutureExt, TryStreamExt,
 };
 use ruma::{
 	api::client::{
-		filter::FilterDefinition,
+		filter::{FilterDefinition, RoomEventFilter},
 		sync::sync_events::{
 			self,
 			v3::{
@@ -53,11 +56,18 @@
 	serde::Raw,
 	uint, DeviceId, EventId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
 };
+use serde_json::value::to_raw_value;
 use service::rooms::short::{ShortEventId, ShortStateKey};
 
 use super::{load_timeline, share_encrypted_room};
 use crate::{client::ignored_filter, Ruma, RumaResponse};
 
+/// Timeline events per room when the filter doesn't limit them.
+const TIMELINE_LIMIT_DEFAULT: usize = 10;
+
+/// Upper bound on the timeline events per room a filter can ask for.
+const TIMELINE_LIMIT_MAX: usize = 100;
+
 #[derive(Default)]
 struct StateChanges {
 	heroes: Option<Vec<OwnedUserId>>,
@@ -118,6 +128,7 @@ pub(crate) async fn sync_events_route(
 	body: Ruma<sync_events::v3::Request>,
 ) -> Result<sync_events::v3::Response, RumaResponse<UiaaResponse>> {
 	let (sender_user, sender_device) = body.sender();
+	let _sync_guard = services.sync.start_sync(sender_user)?;
 
 	// Presence update
 	if services.globals.allow_local_presence() {
@@ -128,7 +139,7 @@ pub(crate) async fn sync_events_route(
 	}
 
 	// Setup watchers, so if there's no response, we can wait for them
-	let watcher = services.sync.watch(sender_user, sender_device);
+	let watcher = services.sync.watch(sender_user, sender_device).await;
 
 	let response = build_sync_events(&services, &body).await?;
 	if body.body.full_state
@@ -180,6 +191,7 @@ pub(crate) async fn build_sync_events(
 		.rooms
 		.state_cache
 		.rooms_joined(sender_user)
+		.ready_filter(|room_id| room_included(&filter, room_id))
 		.map(ToOwned::to_owned)
 		.broad_filter_map(|room_id| {
 			load_joined_room(
@@ -209,10 +221,13 @@ pub(crate) async fn build_sync_events(
 			},
 		);
 
+	// Rooms left before an initial sync are only included when asked for
+	let include_leave = since != 0 || filter.room.include_leave;
 	let left_rooms = services
 		.rooms
 		.state_cache
 		.rooms_left(sender_user)
+		.ready_filter(|(room_id, _)| include_leave && room_included(&filter, room_id))
 		.broad_filter_map(|(room_id, _)| {
 			handle_left_room(
 				services,
@@ -233,7 +248,8 @@ pub(crate) async fn build_sync_events(
 		.rooms
 		.state_cache
 		.rooms_invited(sender_user)
-		.fold_default(|mut invited_rooms: BTreeMap<_, _>, (room_id, invite_state)| async move {
+		.ready_filter(|(room_id, _)| room_included(&filter, room_id))
+		.broad_filter_map(|(room_id, invite_state)| async move {
 			let invite_count = services
 				.rooms
 				.state_cache
@@ -243,22 +259,23 @@ pub(crate) async fn build_sync_events(
 
 			// Invited before last sync
 			if Some(since) >= invite_count {
-				return invited_rooms;
+				return None;
 			}
 
 			let invited_room = InvitedRoom {
 				invite_state: InviteState { events: invite_state },
 			};
 
-			invited_rooms.insert(room_id, invited_room);
-			invited_rooms
-		});
+			Some((room_id, invited_room))
+		})
+		.collect::<BTreeMap<_, _>>();
 
 	let knocked_rooms = services
 		.rooms
 		.state_cache
 		.rooms_knocked(sender_user)
-		.fold_default(|mut knocked_rooms: BTreeMap<_, _>, (room_id, knock_state)| async move {
+		.ready_filter(|(room_id, _)| room_included(&filter, room_id))
+		.broad_filter_map(|(room_id, knock_state)| async move {
 			let knock_count = services
 				.rooms
 				.state_cache
@@ -268,16 +285,16 @@ pub(crate) async fn build_sync_events(
 
 			// Knocked before last sync
 			if Some(since) >= knock_count {
-				return knocked_rooms;
+				return None;
 			}
 
 			let knocked_room = KnockedRoom {
 				knock_state: KnockState { events: knock_state },
 			};
 
-			knocked_rooms.insert(room_id, knocked_room);
-			knocked_rooms
-		});
+			Some((room_id, knocked_room))
+		})
+		.collect::<BTreeMap<_, _>>();
 
 	let presence_updates: OptionFuture<_> = services
 		.globals
@@ -287,7 +304,7 @@ pub(crate) async fn build_sync_events(
 
 	let account_data = services
 		.account_data
-		.changes_since(None, sender_user, since, Some(next_batch))
+		.changes_for_sync(None, sender_user, since, next_batch)
 		.ready_filter_map(|e| extract_variant!(e, AnyRawAccountDataEvent::Global))
 		.collect();
 
@@ -346,8 +363,12 @@ pub(crate) async fn build_sync_events(
 			left: device_list_left.into_iter().collect(),
 		},
 		device_one_time_keys_count,
-		// Fallback keys are not yet supported
-		device_unused_fallback_key_types: None,
+		device_unused_fallback_key_types: Some(
+			services
+				.users
+				.unused_fallback_key_types(sender_user, sender_device)
+				.await,
+		),
 		next_batch: next_batch.to_string(),
 		presence: Presence {
 			events: presence_updates
@@ -542,7 +563,9 @@ async fn handle_left_room(
 				continue;
 			};
 
-			left_state_events.push(pdu.to_sync_state_event());
+			if pdu.matches(&filter.room.state) {
+				left_state_events.push(project_event(pdu.to_sync_state_event(), filter));
+			}
 		}
 	}
 
@@ -558,6 +581,27 @@ async fn handle_left_room(
 	}))
 }
 
+/// Records how long the newest of our users' events took to be delivered.
+/// Remote events are left out, as their timestamps are only as good as the
+/// remote server's clock.
+fn observe_sync_lag(services: &Services, timeline_pdus: &[PdusIterItem]) {
+	let Some((_, pdu)) = timeline_pdus
+		.iter()
+		.rev()
+		.find(|(_, pdu)| services.globals.user_is_local(&pdu.sender))
+	else {
+		return;
+	};
+
+	let sent: u64 = pdu.origin_server_ts.into();
+	let lag = utils::millis_since_unix_epoch().saturating_sub(sent);
+	services
+		.server
+		.metrics
+		.sync_lag
+		.observe(Duration::from_millis(lag));
+}
+
 #[tracing::instrument(
 	name = "joined",
 	level = "debug",
@@ -593,35 +637,83 @@ async fn load_joined_room(
 		.ok()
 		.map(Ok);
 
-	let timeline = load_timeline(
-		services,
-		sender_user,
-		room_id,
-		sincecount,
-		Some(next_batchcount),
-		10_usize,
-	);
+	// Initial syncs start from the room's snapshot shared by all its members
+	let snapshot: OptionFuture<_> = (since == 0)
+		.then(|| services.sync.initial_snapshot(room_id).ok())
+		.into();
 
-	let receipt_events = services
-		.rooms
-		.read_receipt
-		.readreceipts_since(room_id, since)
-		.filter_map(|(read_user, _, edu)| async move {
+	let (snapshot, ignored) = join(snapshot, services.users.ignored_users(sender_user)).await;
+	let snapshot = snapshot.flatten();
+
+	let snapshot_timeline: OptionFuture<_> = snapshot
+		.as_deref()
+		.map(|snapshot| {
+			timeline_from_snapshot(
+				services,
+				sender_user,
+				snapshot,
+				next_batchcount,
+				&filter.room.timeline,
+			)
+		})
+		.into();
+
+	let snapshot_timeline = snapshot_timeline.await.flatten();
+
+	let timeline: OptionFuture<_> = snapshot_timeline
+		.is_none()
+		.then(|| {
+			load_timeline(
+				services,
+				sender_user,
+				room_id,
+				sincecount,
+				Some(next_batchcount),
+				timeline_limit(&filter.room.timeline),
+				Some(&filter.room.timeline),
+			)
+		})
+		.into();
+
+	let is_ignored = |read_user: &UserId| ignored.contains(read_user);
+	let snapshot_receipts = snapshot.as_deref().map(|snapshot| {
+		snapshot
+			.receipts
+			.iter()
+			.filter(|(read_user, _)| !is_ignored(read_user))
+			.cloned()
+			.collect::<HashMap<_, _>>()
+	});
+
+	let receipt_events: OptionFuture<_> = snapshot_receipts
+		.is_none()
+		.then(|| {
 			services
-				.users
-				.user_is_ignored(read_user, sender_user)
-				.await
-				.or_some((read_user.to_owned(), edu))
+				.rooms
+				.read_receipt
+				.readreceipts_since(room_id, since)
+				.ready_filter_map(|(read_user, _, edu)| {
+					(!is_ignored(read_user)).then(|| (read_user.to_owned(), edu))
+				})
+				.collect::<HashMap<OwnedUserId, Raw<AnySyncEphemeralRoomEvent>>>()
 		})
-		.collect::<HashMap<OwnedUserId, Raw<AnySyncEphemeralRoomEvent>>>()
-		.map(Ok);
+		.into();
 
-	let (current_shortstatehash, since_shortstatehash, timeline, receipt_events) =
-		try_join4(current_shortstatehash, since_shortstatehash, timeline, receipt_events)
-			.boxed()
-			.await?;
+	let (current_shortstatehash, since_shortstatehash, timeline, receipt_events) = try_join4(
+		current_shortstatehash,
+		since_shortstatehash,
+		timeline.map(Option::transpose),
+		receipt_events.map(Ok),
+	)
+	.boxed()
+	.await?;
+
+	let (timeline_pdus, limited) = snapshot_timeline.or(timeline).unwrap_or_default();
+	if since != 0 {
+		observe_sync_lag(services, &timeline_pdus);
+	}
 
-	let (timeline_pdus, limited) = timeline;
+	let receipt_events = snapshot_receipts.or(receipt_events).unwrap_or_default();
 	let initial = since_shortstatehash.is_none();
 	let lazy_loading_enabled = filter.room.state.lazy_load_options.is_enabled()
 		|| filter.room.timeline.lazy_load_options.is_enabled();
@@ -704,6 +796,7 @@ async fn load_joined_room(
 		current_shortstatehash,
 		joined_since_last_sync,
 		witness.as_ref(),
+		snapshot.as_deref(),
 	)
 	.boxed()
 	.await?;
@@ -735,15 +828,15 @@ async fn load_joined_room(
 	let room_events = timeline_pdus
 		.into_iter()
 		.stream()
-		.wide_filter_map(|item| ignored_filter(services, item, sender_user))
+		.ready_filter_map(|item| ignored_filter(services, &ignored, item))
 		.map(at!(1))
 		.chain(joined_sender_member.into_iter().stream())
-		.map(|pdu| pdu.to_sync_room_event())
+		.map(|pdu| project_event(pdu.to_sync_room_event(), filter))
 		.collect::<Vec<_>>();
 
 	let account_data_events = services
 		.account_data
-		.changes_since(Some(room_id), sender_user, since, Some(next_batch))
+		.changes_for_sync(Some(room_id), sender_user, since, next_batch)
 		.ready_filter_map(|e| extract_variant!(e, AnyRawAccountDataEvent::Room))
 		.collect();
 
@@ -779,6 +872,17 @@ async fn load_joined_room(
 		})
 		.into();
 
+	let unread_count: OptionFuture<_> = send_notification_counts
+		.then(|| {
+			services
+				.rooms
+				.user
+				.unread_count(sender_user, room_id)
+				.map(TryInto::try_into)
+				.unwrap_or(uint!(0))
+		})
+		.into();
+
 	let typing_events = services
 		.rooms
 		.typing
@@ -798,7 +902,7 @@ async fn load_joined_room(
 		})
 		.unwrap_or(Vec::new());
 
-	let unread_notifications = join(notification_count, highlight_count);
+	let unread_notifications = join3(notification_count, highlight_count, unread_count);
 	let events = join3(room_events, account_data_events, typing_events);
 	let (unread_notifications, events, device_updates) =
 		join3(unread_notifications, events, device_updates)
@@ -806,7 +910,7 @@ async fn load_joined_room(
 			.await;
 
 	let (room_events, account_data_events, typing_events) = events;
-	let (notification_count, highlight_count) = unread_notifications;
+	let (notification_count, highlight_count, unread_count) = unread_notifications;
 
 	device_list_updates.extend(device_updates);
 
@@ -854,6 +958,7 @@ async fn load_joined_room(
 				.collect(),
 		},
 		unread_notifications: UnreadNotificationsCount { highlight_count, notification_count },
+		unread_count,
 		timeline: Timeline {
 			limited: limited || joined_since_last_sync,
 			prev_batch: prev_batch.as_ref().map(ToString::to_string),
@@ -862,7 +967,9 @@ async fn load_joined_room(
 		state: RoomState {
 			events: state_events
 				.iter()
+				.filter(|pdu| pdu.matches(&filter.room.state))
 				.map(PduEvent::to_sync_state_event)
+				.map(|event| project_event(event, filter))
 				.collect(),
 		},
 		ephemeral: Ephemeral { events: edus },
@@ -893,6 +1000,7 @@ async fn calculate_state_changes(
 	current_shortstatehash: ShortStateHash,
 	joined_since_last_sync: bool,
 	witness: Option<&Witness>,
+	snapshot: Option<&Snapshot>,
 ) -> Result<StateChanges> {
 	if since_shortstatehash.is_none() {
 		calculate_state_initial(
@@ -903,6 +1011,7 @@ async fn calculate_state_changes(
 			filter,
 			current_shortstatehash,
 			witness,
+			snapshot,
 		)
 		.await
 	} else {
@@ -931,39 +1040,69 @@ async fn calculate_state_initial(
 	_filter: &FilterDefinition,
 	current_shortstatehash: ShortStateHash,
 	witness: Option<&Witness>,
+	snapshot: Option<&Snapshot>,
 ) -> Result<StateChanges> {
-	let (shortstatekeys, event_ids): (Vec<_>, Vec<_>) = services
-		.rooms
-		.state_accessor
-		.state_full_ids(current_shortstatehash)
-		.unzip()
-		.await;
-
-	let state_events = services
-		.rooms
-		.short
-		.multi_get_statekey_from_short(shortstatekeys.into_iter().stream())
-		.zip(event_ids.into_iter().stream())
-		.ready_filter_map(|item| Some((item.0.ok()?, item.1)))
-		.ready_filter_map(|((event_type, state_key), event_id)| {
-			let lazy = !full_state
-				&& event_type == StateEventType::RoomMember
-				&& state_key.as_str().try_into().is_ok_and(|user_id: &UserId| {
-					sender_user != user_id
-						&& witness.is_some_and(|witness| !witness.contains(user_id))
-				});
+	// Members the client hasn't seen are left out when lazy loading
+	let lazy_member = |state_key: &str| {
+		!full_state
+			&& state_key.try_into().is_ok_and(|user_id: &UserId| {
+				sender_user != user_id
+					&& witness.is_some_and(|witness| !witness.contains(user_id))
+			})
+	};
 
-			lazy.or_some(event_id)
+	let snapshot_state: OptionFuture<_> = snapshot
+		.filter(|snapshot| snapshot.shortstatehash() == current_shortstatehash)
+		.map(|snapshot| {
+			snapshot
+				.state
+				.iter()
+				.filter(|(member, _)| !member.as_deref().is_some_and(|m| lazy_member(m.as_str())))
+				.stream()
+				.broad_filter_map(|(_, event_id)| async move {
+					services.rooms.timeline.get_pdu(event_id).await.ok()
+				})
+				.collect::<Vec<_>>()
 		})
-		.broad_filter_map(|event_id: OwnedEventId| async move {
-			services.rooms.timeline.get_pdu(&event_id).await.ok()
+		.into();
+
+	let snapshot_state = snapshot_state.await;
+
+	let state_events: OptionFuture<_> = snapshot_state
+		.is_none()
+		.then(|| async move {
+			let (shortstatekeys, event_ids): (Vec<_>, Vec<_>) = services
+				.rooms
+				.state_accessor
+				.state_full_ids(current_shortstatehash)
+				.unzip()
+				.await;
+
+			services
+				.rooms
+				.short
+				.multi_get_statekey_from_short(shortstatekeys.into_iter().stream())
+				.zip(event_ids.into_iter().stream())
+				.ready_filter_map(|item| Some((item.0.ok()?, item.1)))
+				.ready_filter_map(|((event_type, state_key), event_id)| {
+					let lazy = event_type == StateEventType::RoomMember
+						&& lazy_member(state_key.as_str());
+
+					lazy.or_some(event_id)
+				})
+				.broad_filter_map(|event_id: OwnedEventId| async move {
+					services.rooms.timeline.get_pdu(&event_id).await.ok()
+				})
+				.collect::<Vec<_>>()
+				.await
 		})
-		.collect()
-		.map(Ok);
+		.into();
 
 	let counts = calculate_counts(services, room_id, sender_user);
 	let ((joined_member_count, invited_member_count, heroes), state_events) =
-		try_join(counts, state_events).boxed().await?;
+		try_join(counts, state_events.map(Ok)).boxed().await?;
+
+	let state_events = snapshot_state.or(state_events).unwrap_or_default();
 
 	// The state_events above should contain all timeline_users, let's mark them as
 	// lazy loaded.
@@ -997,8 +1136,8 @@ async fn calculate_state_incremental<'a>(
 	let encrypted_room = services
 		.rooms
 		.state_accessor
-		.state_get(current_shortstatehash, &StateEventType::RoomEncryption, "")
-		.is_ok()
+		.room_flags_at(room_id, current_shortstatehash)
+		.map(|flags| flags.encrypted)
 		.await;
 
 	let state_get_shorteventid = |user_id: &'a UserId| {
@@ -1258,3 +1397,85 @@ async fn fold_hero(
 	heroes.push(user_id.to_owned());
 	heroes
 }
+
+/// Whether the room passes the `rooms` and `not_rooms` of the filter.
+fn room_included(filter: &FilterDefinition, room_id: &RoomId) -> bool {
+	let filter = &filter.room;
+	!filter.not_rooms.iter().any(is_equal_to!(room_id))
+		&& filter
+			.rooms
+			.as_ref()
+			.is_none_or(|rooms| rooms.iter().any(is_equal_to!(room_id)))
+}
+
+/// Takes the timeline of an initial sync from the room's snapshot, or None
+/// when the snapshot doesn't hold enough of it to fill the filter's limit.
+async fn timeline_from_snapshot(
+	services: &Services,
+	sender_user: &UserId,
+	snapshot: &Snapshot,
+	next_batch: PduCount,
+	filter: &RoomEventFilter,
+) -> Option<(Vec<(PduCount, PduEvent)>, bool)> {
+	let limit = timeline_limit(filter);
+	let pdus = snapshot
+		.timeline
+		.iter()
+		.rev()
+		.filter(|(count, _)| *count <= next_batch)
+		.stream()
+		.filter_map(|(count, event_id)| async move {
+			let mut pdu = services.rooms.timeline.get_pdu(event_id).await.ok()?;
+			if *pdu.sender != *sender_user {
+				pdu.remove_transaction_id().log_err().ok();
+			}
+
+			Some((*count, pdu))
+		})
+		.ready_filter(|(_, pdu)| pdu.matches(filter));
+
+	pin_mut!(pdus);
+	let timeline: Vec<_> = pdus.by_ref().take(limit).collect().await;
+	if timeline.len() < limit && snapshot.limited {
+		return None;
+	}
+
+	let limited = snapshot.limited || pdus.next().await.is_some();
+	let timeline = timeline
+		.into_iter()
+		.rev()
+		.map(|(count, mut pdu)| {
+			pdu.add_age().log_err().ok();
+			(count, pdu)
+		})
+		.collect();
+
+	Some((timeline, limited))
+}
+
+/// Number of timeline events per room the filter asks for.
+fn timeline_limit(filter: &RoomEventFilter) -> usize {
+	filter
+		.limit
+		.map(usize::try_from)
+		.flat_ok()
+		.unwrap_or(TIMELINE_LIMIT_DEFAULT)
+		.clamp(1, TIMELINE_LIMIT_MAX)
+}
+
+/// Projects the event onto the `event_fields` of the filter, if it lists any.
+fn project_event<T>(event: Raw<T>, filter: &FilterDefinition) -> Raw<T> {
+	let Some(fields) = filter
+		.event_fields
+		.as_deref()
+		.filter(|fields| !fields.is_empty())
+	else {
+		return event;
+	};
+
+	event
+		.deserialize_as::<serde_json::Map<String, serde_json::Value>>()
+		.ok()
+		.and_then(|object| to_raw_value(&project_fields(&object, fields)).ok())
+		.map_or(event, Raw::from_json)
+}
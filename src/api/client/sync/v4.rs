@@ -52,8 +52,10 @@ pub(crate) async fn sync_events_v4_route(
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 	let sender_device = body.sender_device.expect("user is authenticated");
 	let mut body = body.body;
+	let _sync_guard = services.sync.start_sync(sender_user)?;
+
 	// Setup watchers, so if there's no response, we can wait for them
-	let watcher = services.sync.watch(sender_user, &sender_device);
+	let watcher = services.sync.watch(sender_user, &sender_device).await;
 
 	let next_batch = services.globals.next_count()?;
 
@@ -153,7 +155,7 @@ pub(crate) async fn sync_events_v4_route(
 	if body.extensions.account_data.enabled.unwrap_or(false) {
 		account_data.global = services
 			.account_data
-			.changes_since(None, sender_user, globalsince, Some(next_batch))
+			.changes_for_sync(None, sender_user, globalsince, next_batch)
 			.ready_filter_map(|e| extract_variant!(e, AnyRawAccountDataEvent::Global))
 			.collect()
 			.await;
@@ -164,7 +166,7 @@ pub(crate) async fn sync_events_v4_route(
 					room.clone(),
 					services
 						.account_data
-						.changes_since(Some(&room), sender_user, globalsince, Some(next_batch))
+						.changes_for_sync(Some(&room), sender_user, globalsince, next_batch)
 						.ready_filter_map(|e| extract_variant!(e, AnyRawAccountDataEvent::Room))
 						.collect()
 						.await,
@@ -203,9 +205,9 @@ pub(crate) async fn sync_events_v4_route(
 			let encrypted_room = services
 				.rooms
 				.state_accessor
-				.state_get(current_shortstatehash, &StateEventType::RoomEncryption, "")
+				.room_flags_at(room_id, current_shortstatehash)
 				.await
-				.is_ok();
+				.encrypted;
 
 			if let Some(since_shortstatehash) = since_shortstatehash {
 				// Skip if there are only timeline changes
@@ -490,6 +492,7 @@ pub(crate) async fn sync_events_v4_route(
 		);
 	}
 
+	let ignored = services.users.ignored_users(sender_user).await;
 	let mut rooms = BTreeMap::new();
 	for (room_id, (required_state_request, timeline_limit, roomsince)) in &todo_rooms {
 		let roomsincecount = PduCount::Normal(*roomsince);
@@ -516,6 +519,7 @@ pub(crate) async fn sync_events_v4_route(
 				roomsincecount,
 				None,
 				*timeline_limit,
+				None,
 			)
 			.await
 			{
@@ -531,7 +535,7 @@ pub(crate) async fn sync_events_v4_route(
 			room_id.to_owned(),
 			services
 				.account_data
-				.changes_since(Some(room_id), sender_user, *roomsince, Some(next_batch))
+				.changes_for_sync(Some(room_id), sender_user, *roomsince, next_batch)
 				.ready_filter_map(|e| extract_variant!(e, AnyRawAccountDataEvent::Room))
 				.collect()
 				.await,
@@ -558,13 +562,7 @@ pub(crate) async fn sync_events_v4_route(
 			.rooms
 			.read_receipt
 			.readreceipts_since(room_id, *roomsince)
-			.filter_map(|(read_user, _ts, v)| async move {
-				services
-					.users
-					.user_is_ignored(read_user, sender_user)
-					.await
-					.or_some(v)
-			})
+			.ready_filter_map(|(read_user, _ts, v)| (!ignored.contains(read_user)).then_some(v))
 			.collect()
 			.await;
 
@@ -606,11 +604,9 @@ pub(crate) async fn sync_events_v4_route(
 
 		let room_events: Vec<_> = timeline_pdus
 			.iter()
-			.stream()
-			.filter_map(|item| ignored_filter(&services, item.clone(), sender_user))
+			.filter_map(|item| ignored_filter(&services, &ignored, item.clone()))
 			.map(|(_, pdu)| pdu.to_sync_room_event())
-			.collect()
-			.await;
+			.collect();
 
 		for (_, pdu) in timeline_pdus {
 			let ts = MilliSecondsSinceUnixEpoch(pdu.origin_server_ts);
@@ -801,8 +797,12 @@ pub(crate) async fn sync_events_v4_route(
 					.users
 					.count_one_time_keys(sender_user, &sender_device)
 					.await,
-				// Fallback keys are not yet supported
-				device_unused_fallback_key_types: None,
+				device_unused_fallback_key_types: Some(
+					services
+						.users
+						.unused_fallback_key_types(sender_user, &sender_device)
+						.await,
+				),
 			},
 			account_data,
 			receipts,
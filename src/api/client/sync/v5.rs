@@ -55,9 +55,10 @@ pub(crate) async fn sync_events_v5_route(
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 	let mut body = body.body;
+	let _sync_guard = services.sync.start_sync(sender_user)?;
 
 	// Setup watchers, so if there's no response, we can wait for them
-	let watcher = services.sync.watch(sender_user, sender_device);
+	let watcher = services.sync.watch(sender_user, sender_device).await;
 
 	let next_batch = services.globals.next_count()?;
 
@@ -144,7 +145,7 @@ pub(crate) async fn sync_events_v5_route(
 		lists: BTreeMap::new(),
 		rooms: BTreeMap::new(),
 		extensions: sync_events::v5::response::Extensions {
-			account_data: collect_account_data(services, sync_info).await,
+			account_data: collect_account_data(services, sync_info, next_batch).await,
 			e2ee: collect_e2ee(services, sync_info, &all_joined_rooms).await?,
 			to_device: collect_to_device(services, sync_info, next_batch).await,
 			receipts: collect_receipts(services).await,
@@ -348,6 +349,7 @@ async fn process_rooms(
 	response: &mut sync_events::v5::Response,
 	body: &sync_events::v5::Request,
 ) -> Result<BTreeMap<OwnedRoomId, sync_events::v5::response::Room>> {
+	let ignored = services.users.ignored_users(sender_user).await;
 	let mut rooms = BTreeMap::new();
 	for (room_id, (required_state_request, timeline_limit, roomsince)) in todo_rooms {
 		let roomsincecount = PduCount::Normal(*roomsince);
@@ -374,6 +376,7 @@ async fn process_rooms(
 				roomsincecount,
 				Some(PduCount::from(next_batch)),
 				*timeline_limit,
+				None,
 			)
 			.await
 			{
@@ -390,7 +393,7 @@ async fn process_rooms(
 				room_id.to_owned(),
 				services
 					.account_data
-					.changes_since(Some(room_id), sender_user, *roomsince, Some(next_batch))
+					.changes_for_sync(Some(room_id), sender_user, *roomsince, next_batch)
 					.ready_filter_map(|e| extract_variant!(e, AnyRawAccountDataEvent::Room))
 					.collect()
 					.await,
@@ -418,13 +421,7 @@ async fn process_rooms(
 			.rooms
 			.read_receipt
 			.readreceipts_since(room_id, *roomsince)
-			.filter_map(|(read_user, _ts, v)| async move {
-				services
-					.users
-					.user_is_ignored(read_user, sender_user)
-					.await
-					.or_some(v)
-			})
+			.ready_filter_map(|(read_user, _ts, v)| (!ignored.contains(read_user)).then_some(v))
 			.collect()
 			.await;
 
@@ -476,11 +473,9 @@ async fn process_rooms(
 
 		let room_events: Vec<_> = timeline_pdus
 			.iter()
-			.stream()
-			.filter_map(|item| ignored_filter(&services, item.clone(), sender_user))
+			.filter_map(|item| ignored_filter(&services, &ignored, item.clone()))
 			.map(|(_, pdu)| pdu.to_sync_room_event())
-			.collect()
-			.await;
+			.collect();
 
 		for (_, pdu) in timeline_pdus {
 			let ts = pdu.origin_server_ts;
@@ -632,6 +627,7 @@ async fn process_rooms(
 async fn collect_account_data(
 	services: crate::State,
 	(sender_user, _, globalsince, body): (&UserId, &DeviceId, u64, &sync_events::v5::Request),
+	next_batch: u64,
 ) -> sync_events::v5::response::AccountData {
 	let mut account_data = sync_events::v5::response::AccountData {
 		global: Vec::new(),
@@ -644,7 +640,7 @@ async fn collect_account_data(
 
 	account_data.global = services
 		.account_data
-		.changes_since(None, sender_user, globalsince, None)
+		.changes_for_sync(None, sender_user, globalsince, next_batch)
 		.ready_filter_map(|e| extract_variant!(e, AnyRawAccountDataEvent::Global))
 		.collect()
 		.await;
@@ -655,7 +651,7 @@ async fn collect_account_data(
 				room.clone(),
 				services
 					.account_data
-					.changes_since(Some(room), sender_user, globalsince, None)
+					.changes_for_sync(Some(room), sender_user, globalsince, next_batch)
 					.ready_filter_map(|e| extract_variant!(e, AnyRawAccountDataEvent::Room))
 					.collect()
 					.await,
@@ -710,9 +706,9 @@ async fn collect_e2ee<'a>(
 		let encrypted_room = services
 			.rooms
 			.state_accessor
-			.state_get(current_shortstatehash, &StateEventType::RoomEncryption, "")
+			.room_flags_at(room_id, current_shortstatehash)
 			.await
-			.is_ok();
+			.encrypted;
 
 		if let Some(since_shortstatehash) = since_shortstatehash {
 			// Skip if there are only timeline changes
@@ -854,7 +850,12 @@ async fn collect_e2ee<'a>(
 			.users
 			.count_one_time_keys(sender_user, sender_device)
 			.await,
-		device_unused_fallback_key_types: None,
+		device_unused_fallback_key_types: Some(
+			services
+				.users
+				.unused_fallback_key_types(sender_user, sender_device)
+				.await,
+		),
 	})
 }
 
@@ -8,7 +8,9 @@
 		client::{error::ErrorKind, to_device::send_event_to_device},
 		federation::{self, transactions::edu::DirectDeviceContent},
 	},
+	serde::Raw,
 	to_device::DeviceIdOrAllDevices,
+	OwnedDeviceId, OwnedUserId, ServerName, UserId,
 };
 use service::sending::EduBuf;
 
@@ -34,74 +36,82 @@ pub(crate) async fn send_event_to_device_route(
 		return Ok(send_event_to_device::v3::Response {});
 	}
 
+	let mut remote: BTreeMap<&ServerName, BTreeMap<OwnedUserId, _>> = BTreeMap::new();
+
+	// Deliveries of an identical payload are coalesced and written together;
+	// clients commonly send the same content to every device of a user.
+	let mut local: BTreeMap<&str, (&Raw<_>, Vec<(&UserId, OwnedDeviceId)>)> = BTreeMap::new();
+
 	for (target_user_id, map) in &body.messages {
+		if !services.globals.user_is_local(target_user_id) {
+			remote
+				.entry(target_user_id.server_name())
+				.or_default()
+				.insert(target_user_id.clone(), map.clone());
+
+			continue;
+		}
+
 		for (target_device_id_maybe, event) in map {
-			if !services.globals.user_is_local(target_user_id) {
-				let mut map = BTreeMap::new();
-				map.insert(target_device_id_maybe.clone(), event.clone());
-				let mut messages = BTreeMap::new();
-				messages.insert(target_user_id.clone(), map);
-				let count = services.globals.next_count()?;
-
-				let mut buf = EduBuf::new();
-				serde_json::to_writer(
-					&mut buf,
-					&federation::transactions::edu::Edu::DirectToDevice(DirectDeviceContent {
-						sender: sender_user.clone(),
-						ev_type: body.event_type.clone(),
-						message_id: count.to_string().into(),
-						messages,
-					}),
-				)
-				.expect("DirectToDevice EDU can be serialized");
-
-				services
-					.sending
-					.send_edu_server(target_user_id.server_name(), buf)?;
-
-				continue;
-			}
-
-			let event_type = &body.event_type.to_string();
-
-			let event = event
-				.deserialize_as()
-				.map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Event is invalid"))?;
-
-			match target_device_id_maybe {
-				| DeviceIdOrAllDevices::DeviceId(target_device_id) => {
-					services
-						.users
-						.add_to_device_event(
-							sender_user,
-							target_user_id,
-							target_device_id,
-							event_type,
-							event,
-						)
-						.await;
-				},
-
-				| DeviceIdOrAllDevices::AllDevices => {
-					let (event_type, event) = (&event_type, &event);
+			let target_device_ids: Vec<OwnedDeviceId> = match target_device_id_maybe {
+				| DeviceIdOrAllDevices::DeviceId(target_device_id) =>
+					vec![target_device_id.clone()],
+				| DeviceIdOrAllDevices::AllDevices =>
 					services
 						.users
 						.all_device_ids(target_user_id)
-						.for_each(|target_device_id| {
-							services.users.add_to_device_event(
-								sender_user,
-								target_user_id,
-								target_device_id,
-								event_type,
-								event.clone(),
-							)
-						})
-						.await;
-				},
-			}
+						.map(ToOwned::to_owned)
+						.collect()
+						.await,
+			};
+
+			let (_, targets) = local
+				.entry(event.json().get())
+				.or_insert_with(|| (event, Vec::new()));
+
+			targets.extend(
+				target_device_ids
+					.into_iter()
+					.map(|target_device_id| (&**target_user_id, target_device_id)),
+			);
 		}
 	}
 
+	let event_type = &body.event_type.to_string();
+	for (event, targets) in local.into_values() {
+		let event: serde_json::Value = event
+			.deserialize_as()
+			.map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Event is invalid"))?;
+
+		services.users.add_to_device_events(
+			sender_user,
+			targets
+				.iter()
+				.map(|(user_id, device_id)| (*user_id, &**device_id)),
+			event_type,
+			&event,
+		);
+	}
+
+	// Everything for one server is sent in a single EDU.
+	for (server_name, messages) in remote {
+		let count = services.globals.next_count()?;
+
+		let mut buf = EduBuf::new();
+		serde_json::to_writer(
+			&mut buf,
+			&federation::transactions::edu::Edu::DirectToDevice(DirectDeviceContent {
+				sender: sender_user.clone(),
+				ev_type: body.event_type.clone(),
+				message_id: count.to_string().into(),
+				messages,
+			}),
+		)
+		.expect("DirectToDevice EDU can be serialized");
+
+		services.sending.send_edu_server(server_name, buf)?;
+	}
+
 	// Save transaction id with empty data
 	services
 		.transaction_ids
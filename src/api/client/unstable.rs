@@ -2,7 +2,7 @@
 
 use axum::extract::State;
 use axum_client_ip::InsecureClientIp;
-use conduwuit::Err;
+use conduwuit::{utils::stream::ReadyExt, Err};
 use futures::StreamExt;
 use ruma::{
 	api::{
@@ -25,11 +25,14 @@
 use super::{update_avatar_url, update_displayname};
 use crate::{Error, Result, Ruma, RumaResponse};
 
+/// Rooms returned per page of mutual rooms.
+const MUTUAL_ROOMS_LIMIT: usize = 100;
+
 /// # `GET /_matrix/client/unstable/uk.half-shot.msc2666/user/mutual_rooms`
 ///
-/// Gets all the rooms the sender shares with the specified user.
-///
-/// TODO: Implement pagination, currently this just returns everything
+/// Gets the rooms the sender shares with the specified user, local or remote,
+/// from the joined members index. Paginated by room ID, [`MUTUAL_ROOMS_LIMIT`]
+/// rooms at a time.
 ///
 /// An implementation of [MSC2666](https://github.com/matrix-org/matrix-spec-proposals/pull/2666)
 #[tracing::instrument(skip_all, fields(%client), name = "mutual_rooms")]
@@ -47,21 +50,26 @@ pub(crate) async fn get_mutual_rooms_route(
 		));
 	}
 
-	if !services.users.exists(&body.user_id).await {
-		return Ok(mutual_rooms::unstable::Response { joined: vec![], next_batch_token: None });
-	}
-
-	let mutual_rooms: Vec<OwnedRoomId> = services
+	let from = body.batch_token.as_deref().unwrap_or_default();
+	let mut mutual_rooms: Vec<OwnedRoomId> = services
 		.rooms
 		.state_cache
 		.get_shared_rooms(sender_user, &body.user_id)
+		.ready_filter(|room_id| room_id.as_str() > from)
 		.map(ToOwned::to_owned)
 		.collect()
 		.await;
 
+	// The shared rooms don't come in order, which the batch token relies on
+	mutual_rooms.sort_unstable();
+	let next_batch_token = (mutual_rooms.len() > MUTUAL_ROOMS_LIMIT).then(|| {
+		mutual_rooms.truncate(MUTUAL_ROOMS_LIMIT);
+		mutual_rooms.last().map(ToString::to_string)
+	});
+
 	Ok(mutual_rooms::unstable::Response {
 		joined: mutual_rooms,
-		next_batch_token: None,
+		next_batch_token: next_batch_token.flatten(),
 	})
 }
 
@@ -118,55 +126,23 @@ pub(crate) async fn get_room_summary(
 		));
 	}
 
+	let view = services
+		.rooms
+		.state_accessor
+		.room_summary_view(&room_id)
+		.await?;
+
 	Ok(get_summary::msc3266::Response {
 		room_id: room_id.clone(),
-		canonical_alias: services
-			.rooms
-			.state_accessor
-			.get_canonical_alias(&room_id)
-			.await
-			.ok(),
-		avatar_url: services
-			.rooms
-			.state_accessor
-			.get_avatar(&room_id)
-			.await
-			.into_option()
-			.unwrap_or_default()
-			.url,
-		guest_can_join: services.rooms.state_accessor.guest_can_join(&room_id).await,
-		name: services.rooms.state_accessor.get_name(&room_id).await.ok(),
-		num_joined_members: services
-			.rooms
-			.state_cache
-			.room_joined_count(&room_id)
-			.await
-			.unwrap_or(0)
-			.try_into()?,
-		topic: services
-			.rooms
-			.state_accessor
-			.get_room_topic(&room_id)
-			.await
-			.ok(),
-		world_readable: services
-			.rooms
-			.state_accessor
-			.is_world_readable(&room_id)
-			.await,
-		join_rule: services
-			.rooms
-			.state_accessor
-			.get_join_rule(&room_id)
-			.await
-			.unwrap_or_default()
-			.0,
-		room_type: services
-			.rooms
-			.state_accessor
-			.get_room_type(&room_id)
-			.await
-			.ok(),
+		canonical_alias: view.canonical_alias,
+		avatar_url: view.avatar_url,
+		guest_can_join: view.guest_can_join,
+		name: view.name,
+		num_joined_members: view.joined_members.try_into()?,
+		topic: view.topic,
+		world_readable: view.world_readable,
+		join_rule: view.join_rule.into(),
+		room_type: view.room_type,
 		room_version: services.rooms.state.get_room_version(&room_id).await.ok(),
 		membership: if let Some(sender_user) = sender_user {
 			services
@@ -179,12 +155,7 @@ pub(crate) async fn get_room_summary(
 		} else {
 			None
 		},
-		encryption: services
-			.rooms
-			.state_accessor
-			.get_room_encryption(&room_id)
-			.await
-			.ok(),
+		encryption: view.encryption,
 	})
 }
 
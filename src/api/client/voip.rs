@@ -2,7 +2,7 @@
 
 use axum::extract::State;
 use base64::{engine::general_purpose, Engine as _};
-use conduwuit::{utils, Err};
+use conduwuit::{err, utils, Err};
 use hmac::{Hmac, Mac};
 use ruma::{api::client::voip::get_turn_server_info, SecondsSinceUnixEpoch, UserId};
 use sha1::Sha1;
@@ -15,7 +15,10 @@
 
 /// # `GET /_matrix/client/r0/voip/turnServer`
 ///
-/// TODO: Returns information about the recommended turn server.
+/// Returns the TURN servers clients should use along with credentials for
+/// them. With `turn_secret` set these are ephemeral credentials following the
+/// TURN REST API (coturn's `use-auth-secret`) which expire after `turn_ttl`,
+/// otherwise the static `turn_username` and `turn_password` are returned.
 pub(crate) async fn turn_server_route(
 	State(services): State<crate::State>,
 	body: Ruma<get_turn_server_info::v3::Request>,
@@ -28,20 +31,19 @@ pub(crate) async fn turn_server_route(
 	let turn_secret = services.globals.turn_secret.clone();
 
 	let (username, password) = if !turn_secret.is_empty() {
-		let expiry = SecondsSinceUnixEpoch::from_system_time(
-			SystemTime::now()
-				.checked_add(Duration::from_secs(services.globals.turn_ttl()))
-				.expect("TURN TTL should not get this high"),
-		)
-		.expect("time is valid");
+		let expiry = SystemTime::now()
+			.checked_add(Duration::from_secs(services.globals.turn_ttl()))
+			.and_then(SecondsSinceUnixEpoch::from_system_time)
+			.ok_or_else(|| err!(Config("turn_ttl", "TURN TTL is too large.")))?;
 
-		let user = body.sender_user.unwrap_or_else(|| {
-			UserId::parse_with_server_name(
+		// Guests get a random user so their credentials can't be linked.
+		let user = match body.sender_user {
+			| Some(user) => user,
+			| None => UserId::parse_with_server_name(
 				utils::random_string(RANDOM_USER_ID_LENGTH).to_lowercase(),
 				&services.server.name,
-			)
-			.unwrap()
-		});
+			)?,
+		};
 
 		let username: String = format!("{}:{}", expiry.get(), user);
 
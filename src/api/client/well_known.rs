@@ -1,9 +1,6 @@
 use axum::{extract::State, response::IntoResponse, Json};
 use ruma::api::client::{
-	discovery::{
-		discover_homeserver::{self, HomeserverInfo, SlidingSyncProxyInfo},
-		discover_support::{self, Contact},
-	},
+	discovery::discover_support::{self, Contact},
 	error::ErrorKind,
 };
 
@@ -12,21 +9,40 @@
 /// # `GET /.well-known/matrix/client`
 ///
 /// Returns the .well-known URL if it is configured, otherwise returns 404.
+///
+/// Served without Ruma's response type so the MatrixRTC foci (MSC4143) can be
+/// included.
 pub(crate) async fn well_known_client(
 	State(services): State<crate::State>,
-	_body: Ruma<discover_homeserver::Request>,
-) -> Result<discover_homeserver::Response> {
-	let client_url = match services.server.config.well_known.client.as_ref() {
+) -> Result<impl IntoResponse> {
+	let well_known = &services.server.config.well_known;
+	let client_url = match well_known.client.as_ref() {
 		| Some(url) => url.to_string(),
 		| None => return Err(Error::BadRequest(ErrorKind::NotFound, "Not found.")),
 	};
 
-	Ok(discover_homeserver::Response {
-		homeserver: HomeserverInfo { base_url: client_url.clone() },
-		identity_server: None,
-		sliding_sync_proxy: Some(SlidingSyncProxyInfo { url: client_url }),
-		tile_server: None,
-	})
+	let mut response = serde_json::json!({
+		"m.homeserver": { "base_url": client_url },
+		"org.matrix.msc3575.proxy": { "url": client_url },
+	});
+
+	if !well_known.rtc_focus_server_urls.is_empty() {
+		let rtc_foci: Vec<_> = well_known
+			.rtc_focus_server_urls
+			.iter()
+			.map(|url| {
+				serde_json::json!({
+					"type": "livekit",
+					"livekit_service_url": url,
+				})
+			})
+			.collect();
+
+		response["org.matrix.msc4143.rtc_foci"] = rtc_foci.clone().into();
+		response["m.rtc_foci"] = rtc_foci.into();
+	}
+
+	Ok(Json(response))
 }
 
 /// # `GET /.well-known/matrix/support`
@@ -1,5 +1,6 @@
 #![allow(clippy::toplevel_ref_arg)]
 
+pub mod admin;
 pub mod client;
 pub mod router;
 pub mod server;
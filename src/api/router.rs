@@ -14,10 +14,20 @@
 };
 use conduwuit::{err, Server};
 use http::{uri, Uri};
+use ruma::api::client::{
+	authenticated_media::{get_content, get_content_as_filename, get_content_thumbnail},
+	message::send_message_event,
+	state::send_state_event,
+};
 
 use self::handler::RouterExt;
 pub(super) use self::{args::Args as Ruma, response::RumaResponse, state::State};
-use crate::{client, server};
+use crate::{admin, client, server};
+
+/// Routes of the separate metrics listener at `metrics_address`.
+pub fn build_metrics(router: Router<State>) -> Router<State> {
+	router.route("/metrics", get(client::metrics_listener_route))
+}
 
 pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 	let config = &server.config;
@@ -35,6 +45,8 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::get_login_types_route)
 		.ruma_route(&client::login_route)
 		.ruma_route(&client::login_token_route)
+		.ruma_route(&client::sso_login_route)
+		.ruma_route(&client::sso_login_with_provider_route)
 		.ruma_route(&client::whoami_route)
 		.ruma_route(&client::logout_route)
 		.ruma_route(&client::logout_all_route)
@@ -42,6 +54,10 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::deactivate_route)
 		.ruma_route(&client::third_party_route)
 		.ruma_route(&client::request_3pid_management_token_via_email_route)
+		.ruma_route(&client::request_registration_token_via_email_route)
+		.ruma_route(&client::request_password_change_token_via_email_route)
+		.ruma_route(&client::add_3pid_route)
+		.ruma_route(&client::delete_3pid_route)
 		.ruma_route(&client::request_3pid_management_token_via_msisdn_route)
 		.ruma_route(&client::check_registration_token_validity)
 		.ruma_route(&client::get_capabilities_route)
@@ -82,6 +98,7 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::add_backup_keys_for_room_route)
 		.ruma_route(&client::add_backup_keys_for_session_route)
 		.ruma_route(&client::delete_backup_keys_for_room_route)
+		.ruma_route(&client::delete_backup_keys_for_rooms_route)
 		.ruma_route(&client::delete_backup_keys_for_session_route)
 		.ruma_route(&client::delete_backup_keys_route)
 		.ruma_route(&client::get_backup_keys_for_room_route)
@@ -117,8 +134,22 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::get_protocols_route)
 		.route("/_matrix/client/unstable/thirdparty/protocols",
 			get(client::get_protocols_route_unstable))
-		.ruma_route(&client::send_message_event_route)
-		.ruma_route(&client::send_state_event_for_key_route)
+		.ruma_raw_route::<send_message_event::v3::Request, _, _>(client::send_message_event_route)
+		.ruma_raw_route::<send_state_event::v3::Request, _, _>(
+			client::send_state_event_for_key_route,
+		)
+		.ruma_route(&client::update_delayed_event_route)
+		.ruma_route(&client::get_delayed_events_route)
+		.ruma_route(&client::send_server_notice_route)
+		.ruma_route(&admin::admin_list_users_route)
+		.ruma_route(&admin::admin_get_user_route)
+		.ruma_route(&admin::admin_deactivate_user_route)
+		.ruma_route(&admin::admin_list_rooms_route)
+		.ruma_route(&admin::admin_get_room_route)
+		.ruma_route(&admin::admin_delete_room_route)
+		.ruma_route(&admin::admin_delete_media_route)
+		.ruma_route(&admin::admin_delete_user_media_route)
+		.ruma_route(&admin::admin_purge_media_cache_route)
 		.ruma_route(&client::get_state_events_route)
 		.ruma_route(&client::get_state_events_for_key_route)
 		// Ruma doesn't have support for multiple paths for a single endpoint yet, and these routes
@@ -153,9 +184,15 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::turn_server_route)
 		.ruma_route(&client::send_event_to_device_route)
 		.ruma_route(&client::create_content_route)
-		.ruma_route(&client::get_content_thumbnail_route)
-		.ruma_route(&client::get_content_route)
-		.ruma_route(&client::get_content_as_filename_route)
+		.ruma_route(&client::create_mxc_uri_route)
+		.ruma_route(&client::create_content_async_route)
+		.ruma_raw_route::<get_content_thumbnail::v1::Request, _, _>(
+			client::get_content_thumbnail_route,
+		)
+		.ruma_raw_route::<get_content::v1::Request, _, _>(client::get_content_route)
+		.ruma_raw_route::<get_content_as_filename::v1::Request, _, _>(
+			client::get_content_as_filename_route,
+		)
 		.ruma_route(&client::get_media_preview_route)
 		.ruma_route(&client::get_media_config_route)
 		.ruma_route(&client::get_devices_route)
@@ -163,6 +200,10 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::update_device_route)
 		.ruma_route(&client::delete_device_route)
 		.ruma_route(&client::delete_devices_route)
+		.ruma_route(&client::put_dehydrated_device_route)
+		.ruma_route(&client::get_dehydrated_device_route)
+		.ruma_route(&client::delete_dehydrated_device_route)
+		.ruma_route(&client::get_dehydrated_events_route)
 		.ruma_route(&client::get_tags_route)
 		.ruma_route(&client::update_tag_route)
 		.ruma_route(&client::delete_tag_route)
@@ -171,6 +212,7 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::get_key_changes_route)
 		.ruma_route(&client::get_pushers_route)
 		.ruma_route(&client::set_pushers_route)
+		.ruma_route(&client::get_notifications_route)
 		.ruma_route(&client::upgrade_room_route)
 		.ruma_route(&client::get_threads_route)
 		.ruma_route(&client::get_relating_events_with_rel_type_and_event_type_route)
@@ -184,8 +226,17 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 			get(client::get_room_summary_legacy)
 		)
 		.ruma_route(&client::well_known_support)
-		.ruma_route(&client::well_known_client)
+		.route("/.well-known/matrix/client", get(client::well_known_client))
 		.route("/_conduwuit/server_version", get(client::conduwuit_server_version))
+		.route("/_conduwuit/metrics", get(client::conduwuit_metrics_route))
+		.ruma_route(&client::conduwuit_backup_route)
+		.route(service::email::VALIDATION_PATH, get(client::validate_email_route))
+		.route(service::sso::CALLBACK_PATH, get(client::sso_callback_route))
+		.ruma_route(&client::appservice_users_route)
+		.ruma_route(&client::appservice_deactivate_users_route)
+		.ruma_route(&client::appservice_leave_rooms_route)
+		.route("/_matrix/client/r0/auth/m.login.sso/fallback/web", get(client::sso_fallback_route))
+		.route("/_matrix/client/v3/auth/m.login.sso/fallback/web", get(client::sso_fallback_route))
 		.ruma_route(&client::room_initial_sync_route)
 		.route("/client/server.json", get(client::syncv3_client_server_json));
 
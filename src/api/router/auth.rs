@@ -8,11 +8,14 @@
 use ruma::{
 	api::{
 		client::{
+			account::change_password,
 			directory::get_public_rooms,
 			error::ErrorKind,
+			message::get_message_events,
 			profile::{
 				get_avatar_url, get_display_name, get_profile, get_profile_key, get_timezone_key,
 			},
+			state::{get_state_events, get_state_events_for_key},
 			voip::get_turn_server_info,
 		},
 		federation::openid::get_openid_userinfo,
@@ -138,6 +141,36 @@ pub(super) async fn auth(
 					Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token."))
 				}
 			},
+			| &get_message_events::v3::Request::METADATA
+			| &get_state_events::v3::Request::METADATA
+			| &get_state_events_for_key::v3::Request::METADATA => {
+				// Peeking into world readable rooms; the handlers do the visibility and
+				// rate limit checks.
+				if services.server.config.allow_unauthenticated_peeking {
+					Ok(Auth {
+						origin: None,
+						sender_user: None,
+						sender_device: None,
+						appservice_info: None,
+					})
+				} else {
+					Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token."))
+				}
+			},
+			| &change_password::v3::Request::METADATA => {
+				// Resetting a forgotten password; the handler requires an email
+				// address bound to the account to have been validated.
+				if services.email.enabled() {
+					Ok(Auth {
+						origin: None,
+						sender_user: None,
+						sender_device: None,
+						appservice_info: None,
+					})
+				} else {
+					Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token."))
+				}
+			},
 			| _ => Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token.")),
 		},
 		| (
@@ -328,6 +361,8 @@ fn auth_server_checks(services: &Services, x_matrix: &XMatrix) -> Result<()> {
 		))));
 	}
 
+	services.moderation.check_server(origin)?;
+
 	Ok(())
 }
 
@@ -1,9 +1,14 @@
+use std::collections::BTreeMap;
+
 use axum::extract::State;
 use axum_client_ip::InsecureClientIp;
 use base64::{engine::general_purpose, Engine as _};
 use conduwuit::{err, utils, utils::hash::sha256, warn, Err, Error, PduEvent, Result};
 use ruma::{
-	api::{client::error::ErrorKind, federation::membership::create_invite},
+	api::{
+		appservice::event::push_events::v1::DeviceLists, client::error::ErrorKind,
+		federation::membership::create_invite,
+	},
 	events::room::member::{MembershipState, RoomMemberEventContent},
 	serde::JsonObject,
 	CanonicalJsonValue, OwnedUserId, UserId,
@@ -28,6 +33,10 @@ pub(crate) async fn create_invite_route(
 		.acl_check(body.origin(), &body.room_id)
 		.await?;
 
+	services
+		.rate_limiting
+		.check_federation(body.origin(), &body.room_id)?;
+
 	if !services.server.supported_room_version(&body.room_version) {
 		return Err(Error::BadRequest(
 			ErrorKind::IncompatibleRoomVersion { room_version: body.room_version.clone() },
@@ -108,6 +117,34 @@ pub(crate) async fn create_invite_route(
 		return Err!(Request(Forbidden("This server does not allow room invites.")));
 	}
 
+	if services
+		.server
+		.config
+		.forbidden_invite_server_names
+		.iter()
+		.any(|server| server == body.origin() || server == sender.server_name())
+	{
+		return Err!(Request(Forbidden("Invites from your server are not allowed.")));
+	}
+
+	services
+		.moderation
+		.check_invite(sender, &body.room_id)
+		.await?;
+
+	// Invites from ignored users are signed like any other but dropped, so the
+	// sender can't tell they are ignored.
+	let ignored = services.users.user_is_ignored(sender, &invited_user).await;
+
+	if !ignored
+		&& !services
+			.users
+			.accepts_invite_from(&invited_user, sender)
+			.await
+	{
+		return Err!(Request(Forbidden("{invited_user} does not accept invites from you.")));
+	}
+
 	let mut invite_state = body.invite_room_state.clone();
 
 	let mut event: JsonObject = serde_json::from_str(body.event.get())
@@ -124,11 +161,12 @@ pub(crate) async fn create_invite_route(
 	// join/invite through /send. If we are not in the room, we need to manually
 	// record the invited state for client /sync through update_membership(), and
 	// send the invite PDU to the relevant appservices.
-	if !services
-		.rooms
-		.state_cache
-		.server_in_room(services.globals.server_name(), &body.room_id)
-		.await
+	if !ignored
+		&& !services
+			.rooms
+			.state_cache
+			.server_in_room(services.globals.server_name(), &body.room_id)
+			.await
 	{
 		services
 			.rooms
@@ -155,6 +193,9 @@ pub(crate) async fn create_invite_route(
 							txn_id: general_purpose::URL_SAFE_NO_PAD
 								.encode(sha256::hash(pdu.event_id.as_bytes()))
 								.into(),
+							device_lists: DeviceLists::new(),
+							device_one_time_keys_count: BTreeMap::new(),
+							device_unused_fallback_key_types: BTreeMap::new(),
 							ephemeral: Vec::new(),
 							to_device: Vec::new(),
 						},
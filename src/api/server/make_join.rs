@@ -57,6 +57,11 @@ pub(crate) async fn create_join_event_template_route(
 		return Err!(Request(Forbidden("Server is banned on this homeserver.")));
 	}
 
+	services
+		.moderation
+		.check_join(&body.user_id, &body.room_id)
+		.await?;
+
 	if let Some(server) = body.room_id.server_name() {
 		if services
 			.server
@@ -95,20 +100,9 @@ pub(crate) async fn create_join_event_template_route(
 		{
 			let Some(auth_user) = services
 				.rooms
-				.state_cache
-				.local_users_in_room(&body.room_id)
-				.filter(|user| {
-					services.rooms.state_accessor.user_can_invite(
-						&body.room_id,
-						user,
-						&body.user_id,
-						&state_lock,
-					)
-				})
-				.boxed()
-				.next()
+				.state_accessor
+				.get_join_authorising_user(&body.room_id)
 				.await
-				.map(ToOwned::to_owned)
 			else {
 				return Err!(Request(UnableToGrantJoin(
 					"No user on this server is able to assist in joining."
@@ -164,6 +158,16 @@ pub(crate) async fn user_can_perform_restricted_join(
 		return Ok(false);
 	}
 
+	if services
+		.rooms
+		.state_cache
+		.is_invited(user_id, room_id)
+		.await
+	{
+		// invited users may join regardless of the restricted join rule
+		return Ok(false);
+	}
+
 	let Ok(join_rules_event_content) = services
 		.rooms
 		.state_accessor
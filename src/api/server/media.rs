@@ -30,6 +30,23 @@ pub(crate) async fn get_content_route(
 		media_id: &body.media_id,
 	};
 
+	services
+		.media
+		.wait_for_upload(&mxc, body.timeout_ms)
+		.await?;
+
+	services
+		.media
+		.check_server_access(body.origin(), &mxc)
+		.await?;
+
+	if let Some(location) = services.media.get_location(&mxc).await? {
+		return Ok(get_content::v1::Response {
+			content: FileOrLocation::Location(location),
+			metadata: ContentMetadata::new(),
+		});
+	}
+
 	let Some(FileMeta {
 		content,
 		content_type,
@@ -67,12 +84,22 @@ pub(crate) async fn get_content_thumbnail_route(
 	InsecureClientIp(client): InsecureClientIp,
 	body: Ruma<get_content_thumbnail::v1::Request>,
 ) -> Result<get_content_thumbnail::v1::Response> {
-	let dim = Dim::from_ruma(body.width, body.height, body.method.clone())?;
+	let dim = Dim::from_ruma(body.width, body.height, body.method.clone())?
+		.with_animated(body.animated);
 	let mxc = Mxc {
 		server_name: services.globals.server_name(),
 		media_id: &body.media_id,
 	};
 
+	services
+		.media
+		.wait_for_upload(&mxc, body.timeout_ms)
+		.await?;
+
+	services
+		.media
+		.check_server_access(body.origin(), &mxc)
+		.await?;
 	let Some(FileMeta {
 		content,
 		content_type,
@@ -1,4 +1,9 @@
-use std::{collections::BTreeMap, net::IpAddr, time::Instant};
+use std::{
+	collections::{BTreeMap, HashSet},
+	io,
+	net::IpAddr,
+	time::Instant,
+};
 
 use axum::extract::State;
 use axum_client_ip::InsecureClientIp;
@@ -31,7 +36,8 @@
 	events::receipt::{ReceiptEvent, ReceiptEventContent, ReceiptType},
 	serde::Raw,
 	to_device::DeviceIdOrAllDevices,
-	CanonicalJsonObject, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, ServerName, UserId,
+	CanonicalJsonObject, OwnedDeviceId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId,
+	ServerName, TransactionId, UserId,
 };
 use service::{
 	sending::{EDU_LIMIT, PDU_LIMIT},
@@ -70,18 +76,31 @@ pub(crate) async fn send_transaction_message_route(
 		)));
 	}
 
-	if body.pdus.len() > PDU_LIMIT {
-		return Err!(Request(Forbidden(
-			"Not allowed to send more than {PDU_LIMIT} PDUs in one transaction"
-		)));
-	}
+	let (pdu_count, edu_count) = (body.pdus.len(), body.edus.len());
+	if pdu_count > PDU_LIMIT || edu_count > EDU_LIMIT {
+		if !services.server.config.lenient_federation_transaction_limits {
+			return Err!(Request(TooLarge(
+				"Not allowed to send more than {PDU_LIMIT} PDUs and {EDU_LIMIT} EDUs in one \
+				 transaction"
+			)));
+		}
 
-	if body.edus.len() > EDU_LIMIT {
-		return Err!(Request(Forbidden(
-			"Not allowed to send more than {EDU_LIMIT} EDUs in one transaction"
-		)));
+		warn!(
+			id = ?body.transaction_id,
+			origin = ?body.origin(),
+			"Transaction exceeds limits with {pdu_count} PDUs and {edu_count} EDUs; only \
+			 processing the first {PDU_LIMIT} PDUs and {EDU_LIMIT} EDUs",
+		);
 	}
 
+	let (pdus, excess_pdus) = body.pdus.split_at(pdu_count.min(PDU_LIMIT));
+	let edus = &body.edus[..edu_count.min(EDU_LIMIT)];
+
+	let _txn_guard = services
+		.rate_limiting
+		.start_transaction(body.origin())
+		.inspect_err(|e| debug_warn!("Rejecting transaction {}: {e}", body.transaction_id))?;
+
 	let txn_start_time = Instant::now();
 	trace!(
 		pdus = body.pdus.len(),
@@ -92,23 +111,45 @@ pub(crate) async fn send_transaction_message_route(
 		"Starting txn",
 	);
 
-	let pdus = body
-		.pdus
+	let pdus = pdus
 		.iter()
 		.stream()
 		.broad_then(|pdu| services.rooms.event_handler.parse_incoming_pdu(pdu))
 		.inspect_err(|e| debug_warn!("Could not parse PDU: {e}"))
 		.ready_filter_map(Result::ok);
 
-	let edus = body
-		.edus
+	let edus = edus
 		.iter()
 		.map(|edu| edu.json().get())
 		.map(serde_json::from_str)
 		.filter_map(Result::ok)
 		.stream();
 
-	let results = handle(&services, &client, body.origin(), txn_start_time, pdus, edus).await?;
+	let mut results = handle(
+		&services,
+		&client,
+		body.origin(),
+		&body.transaction_id,
+		txn_start_time,
+		pdus,
+		edus,
+	)
+	.await?;
+
+	// PDUs past the limit are reported as failed rather than silently dropped.
+	excess_pdus
+		.iter()
+		.stream()
+		.broad_then(|pdu| services.rooms.event_handler.parse_incoming_pdu(pdu))
+		.ready_filter_map(Result::ok)
+		.ready_for_each(|(_, event_id, _)| {
+			let error = err!(Request(TooLarge(
+				"Exceeds the limit of {PDU_LIMIT} PDUs in one transaction"
+			)));
+
+			results.entry(event_id).or_insert(Err(error));
+		})
+		.await;
 
 	debug!(
 		pdus = body.pdus.len(),
@@ -126,6 +167,62 @@ pub(crate) async fn send_transaction_message_route(
 		}
 	}
 
+	// When we were interrupted part way through or the origin exceeded its rate
+	// limit, keep track of what was accepted and fail the transaction so the
+	// origin sends it again; the retry then only has to process the remaining
+	// PDUs.
+	let unprocessed = results
+		.values()
+		.filter(|result| result.as_ref().is_err_and(Error::is_interrupted))
+		.count();
+
+	let rate_limited = results
+		.values()
+		.filter_map(|result| result.as_ref().err())
+		.find(|e| matches!(e.kind(), ErrorKind::LimitExceeded { .. }));
+
+	if unprocessed > 0 || rate_limited.is_some() {
+		for (event_id, _) in results.iter().filter(|(_, result)| result.is_ok()) {
+			services.transaction_ids.add_federation_txn_accepted(
+				body.origin(),
+				&body.transaction_id,
+				event_id,
+			);
+		}
+
+		if let Some(e) = rate_limited {
+			debug_warn!(
+				id = ?body.transaction_id,
+				origin = ?body.origin(),
+				"Rate limited txn: {e}",
+			);
+
+			return Err(Error::Request(
+				e.kind(),
+				"Too many PDUs for a room, retry the transaction later".into(),
+				http::StatusCode::TOO_MANY_REQUESTS,
+			));
+		}
+
+		debug_warn!(
+			id = ?body.transaction_id,
+			origin = ?body.origin(),
+			"Interrupted txn with {unprocessed} PDUs left unprocessed",
+		);
+
+		return Err(Error::Io(io::Error::new(
+			io::ErrorKind::Interrupted,
+			format!(
+				"Shutting down; {unprocessed} PDUs were not processed, retry the transaction"
+			),
+		)));
+	}
+
+	services
+		.transaction_ids
+		.clear_federation_txn(body.origin(), &body.transaction_id)
+		.await;
+
 	Ok(send_transaction_message::v1::Response {
 		pdus: results
 			.into_iter()
@@ -138,6 +235,7 @@ async fn handle(
 	services: &Services,
 	client: &IpAddr,
 	origin: &ServerName,
+	txn_id: &TransactionId,
 	started: Instant,
 	pdus: impl Stream<Item = Pdu> + Send,
 	edus: impl Stream<Item = Edu> + Send,
@@ -158,7 +256,7 @@ async fn handle(
 		.into_iter()
 		.try_stream()
 		.broad_and_then(|(room_id, pdus): (_, Vec<_>)| {
-			handle_room(services, client, origin, started, room_id, pdus.into_iter())
+			handle_room(services, client, origin, txn_id, started, room_id, pdus.into_iter())
 				.map_ok(Vec::into_iter)
 				.map_ok(IterStream::try_stream)
 		})
@@ -168,7 +266,11 @@ async fn handle(
 		.await?;
 
 	// evaluate edus after pdus, at least for now.
-	edus.for_each_concurrent(automatic_width(), |edu| handle_edu(services, client, origin, edu))
+	let edus = edus.collect().map(dedup_edus).await;
+	let width = services.server.config.max_concurrent_inbound_edus.max(1);
+	edus.into_iter()
+		.stream()
+		.for_each_concurrent(width, |edu| handle_edu(services, client, origin, edu))
 		.boxed()
 		.await;
 
@@ -179,6 +281,7 @@ async fn handle_room(
 	services: &Services,
 	_client: &IpAddr,
 	origin: &ServerName,
+	txn_id: &TransactionId,
 	txn_start_time: Instant,
 	room_id: OwnedRoomId,
 	pdus: impl Iterator<Item = Pdu> + Send,
@@ -193,7 +296,29 @@ async fn handle_room(
 	let room_id = &room_id;
 	pdus.try_stream()
 		.and_then(|(_, event_id, value)| async move {
-			services.server.check_running()?;
+			// Remaining PDUs are reported as interrupted rather than dropped.
+			if let Err(e) = services.server.check_running() {
+				return Ok((event_id, Err(e)));
+			}
+
+			// Accepted before an earlier attempt of this transaction was cut short.
+			if services
+				.transaction_ids
+				.is_federation_txn_accepted(origin, txn_id, &event_id)
+				.await
+			{
+				return Ok((event_id, Ok(())));
+			}
+
+			if let Err(e) = services.rate_limiting.check_federation(origin, room_id) {
+				return Ok((event_id, Err(e)));
+			}
+
+			let sender: Option<&UserId> = value.get("sender").try_into().ok();
+			if let Err(e) = services.moderation.check_pdu(room_id, sender) {
+				return Ok((event_id, Err(e)));
+			}
+
 			let pdu_start_time = Instant::now();
 			let result = services
 				.rooms
@@ -214,6 +339,38 @@ async fn handle_room(
 		.await
 }
 
+/// Drops typing and presence updates which are superseded by a later update
+/// for the same user in the same transaction.
+fn dedup_edus(edus: Vec<Edu>) -> Vec<Edu> {
+	let mut typing = HashSet::new();
+	let mut presence = HashSet::new();
+
+	let mut deduped: Vec<_> = edus
+		.into_iter()
+		.rev()
+		.filter_map(|edu| match edu {
+			| Edu::Typing(content) => typing
+				.insert((content.room_id.clone(), content.user_id.clone()))
+				.then_some(Edu::Typing(content)),
+
+			| Edu::Presence(mut content) => {
+				content.push.reverse();
+				content
+					.push
+					.retain(|update| presence.insert(update.user_id.clone()));
+				content.push.reverse();
+
+				(!content.push.is_empty()).then_some(Edu::Presence(content))
+			},
+
+			| edu => Some(edu),
+		})
+		.collect();
+
+	deduped.reverse();
+	deduped
+}
+
 async fn handle_edu(services: &Services, client: &IpAddr, origin: &ServerName, edu: Edu) {
 	match edu {
 		| Edu::Presence(presence) if services.server.config.allow_incoming_presence =>
@@ -305,6 +462,15 @@ async fn handle_edu_receipt_room(
 	room_id: OwnedRoomId,
 	room_updates: ReceiptMap,
 ) {
+	if services
+		.rooms
+		.metadata
+		.is_ephemeral_disabled(&room_id)
+		.await
+	{
+		return;
+	}
+
 	if services
 		.rooms
 		.event_handler
@@ -385,6 +551,15 @@ async fn handle_edu_typing(
 	origin: &ServerName,
 	typing: TypingContent,
 ) {
+	if services
+		.rooms
+		.metadata
+		.is_ephemeral_disabled(&typing.room_id)
+		.await
+	{
+		return;
+	}
+
 	if typing.user_id.server_name() != origin {
 		debug_warn!(
 			%typing.user_id, %origin,
@@ -552,19 +727,21 @@ async fn handle_edu_direct_to_device_event(
 		},
 
 		| DeviceIdOrAllDevices::AllDevices => {
-			services
+			let target_device_ids: Vec<OwnedDeviceId> = services
 				.users
 				.all_device_ids(target_user_id)
-				.for_each(|target_device_id| {
-					services.users.add_to_device_event(
-						sender,
-						target_user_id,
-						target_device_id,
-						ev_type,
-						event.clone(),
-					)
-				})
+				.map(ToOwned::to_owned)
+				.collect()
 				.await;
+
+			services.users.add_to_device_events(
+				sender,
+				target_device_ids
+					.iter()
+					.map(|target_device_id| (target_user_id, &**target_device_id)),
+				ev_type,
+				&event,
+			);
 		},
 	}
 }
@@ -124,6 +124,8 @@ async fn create_join_event(
 		return Err!(Request(Forbidden("Not allowed to join on behalf of another server.")));
 	}
 
+	services.moderation.check_join(&sender, room_id).await?;
+
 	let state_key: OwnedUserId = serde_json::from_value(
 		value
 			.get("state_key")
@@ -166,6 +168,18 @@ async fn create_join_event(
 			)));
 		}
 
+		if !services
+			.rooms
+			.state_accessor
+			.user_can_authorise_join(room_id, &authorising_user)
+			.await
+		{
+			return Err!(Request(UnableToGrantJoin(
+				"Authorising user {authorising_user} does not have the power to invite, they \
+				 cannot authorise your join."
+			)));
+		}
+
 		if !super::user_can_perform_restricted_join(
 			services,
 			&state_key,
@@ -219,38 +233,36 @@ async fn create_join_event(
 		.collect()
 		.await;
 
-	let state = state_ids
+	let state: Vec<_> = state_ids
 		.iter()
 		.try_stream()
 		.broad_and_then(|event_id| services.rooms.timeline.get_pdu_json(event_id))
-		.broad_and_then(|pdu| {
-			services
-				.sending
-				.convert_to_outgoing_federation_event(pdu)
-				.map(Ok)
-		})
 		.try_collect()
 		.boxed()
 		.await?;
 
+	let state = services
+		.sending
+		.convert_to_outgoing_federation_events(state)
+		.await?;
+
 	let starting_events = state_ids.iter().map(Borrow::borrow);
-	let auth_chain = services
+	let auth_chain: Vec<_> = services
 		.rooms
 		.auth_chain
 		.event_ids_iter(room_id, starting_events)
 		.broad_and_then(|event_id| async move {
 			services.rooms.timeline.get_pdu_json(&event_id).await
 		})
-		.broad_and_then(|pdu| {
-			services
-				.sending
-				.convert_to_outgoing_federation_event(pdu)
-				.map(Ok)
-		})
 		.try_collect()
 		.boxed()
 		.await?;
 
+	let auth_chain = services
+		.sending
+		.convert_to_outgoing_federation_events(auth_chain)
+		.await?;
+
 	services.sending.send_pdu_room(room_id, &pdu_id).await?;
 
 	Ok(create_join_event::v1::RoomState {
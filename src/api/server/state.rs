@@ -2,7 +2,7 @@
 
 use axum::extract::State;
 use conduwuit::{at, err, utils::IterStream, Result};
-use futures::{FutureExt, StreamExt, TryStreamExt};
+use futures::{StreamExt, TryStreamExt};
 use ruma::{api::federation::event::get_room_state, OwnedEventId};
 
 use super::AccessCheck;
@@ -39,32 +39,30 @@ pub(crate) async fn get_room_state_route(
 		.collect()
 		.await;
 
-	let pdus = state_ids
+	let pdus: Vec<_> = state_ids
 		.iter()
 		.try_stream()
 		.and_then(|id| services.rooms.timeline.get_pdu_json(id))
-		.and_then(|pdu| {
-			services
-				.sending
-				.convert_to_outgoing_federation_event(pdu)
-				.map(Ok)
-		})
 		.try_collect()
 		.await?;
 
-	let auth_chain = services
+	let pdus = services
+		.sending
+		.convert_to_outgoing_federation_events(pdus)
+		.await?;
+
+	let auth_chain: Vec<_> = services
 		.rooms
 		.auth_chain
 		.event_ids_iter(&body.room_id, once(body.event_id.borrow()))
 		.and_then(|id| async move { services.rooms.timeline.get_pdu_json(&id).await })
-		.and_then(|pdu| {
-			services
-				.sending
-				.convert_to_outgoing_federation_event(pdu)
-				.map(Ok)
-		})
 		.try_collect()
 		.await?;
 
+	let auth_chain = services
+		.sending
+		.convert_to_outgoing_federation_events(auth_chain)
+		.await?;
+
 	Ok(get_room_state::v1::Response { auth_chain, pdus })
 }
@@ -0,0 +1,115 @@
+//! Dedicated thread-pool for CPU-bound work which would otherwise stall the
+//! tokio async workers, such as serializing the events of a large room state.
+//! Unlike tokio's blocking pool it isn't shared with file I/O, and its load is
+//! instrumented for the metrics endpoint.
+
+use std::{
+	panic::{catch_unwind, AssertUnwindSafe},
+	sync::{
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+		mpsc, Arc, Mutex,
+	},
+	thread,
+	time::Instant,
+};
+
+use tokio::sync::oneshot;
+
+use crate::{err, Result};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub struct Pool {
+	queue: mpsc::Sender<Job>,
+	stats: Arc<Stats>,
+}
+
+/// Load of the pool.
+#[derive(Debug, Default)]
+pub struct Stats {
+	pub workers: AtomicUsize,
+
+	/// Jobs waiting for a worker.
+	pub queued: AtomicUsize,
+
+	/// Workers running a job.
+	pub busy: AtomicUsize,
+
+	pub completed: AtomicU64,
+
+	/// Total time spent running jobs, in microseconds.
+	pub busy_micros: AtomicU64,
+}
+
+const WORKER_NAME: &str = "conduwuit:blocking";
+
+impl Pool {
+	#[must_use]
+	pub fn new(workers: usize) -> Self {
+		let (queue, recv) = mpsc::channel::<Job>();
+		let recv = Arc::new(Mutex::new(recv));
+		let stats = Arc::new(Stats::default());
+		for _ in 0..workers.max(1) {
+			let (recv, worker_stats) = (recv.clone(), stats.clone());
+			thread::Builder::new()
+				.name(WORKER_NAME.into())
+				.spawn(move || worker(&recv, &worker_stats))
+				.expect("failed to spawn blocking pool worker");
+
+			stats.workers.fetch_add(1, Ordering::Relaxed);
+		}
+
+		Self { queue, stats }
+	}
+
+	/// Runs `f` on the pool, waiting for its result.
+	#[tracing::instrument(
+		name = "blocking",
+		level = "debug",
+		skip(self, f),
+		fields(queued = self.stats.queued.load(Ordering::Relaxed)),
+	)]
+	pub async fn spawn<F, T>(&self, name: &'static str, f: F) -> Result<T>
+	where
+		F: FnOnce() -> T + Send + 'static,
+		T: Send + 'static,
+	{
+		let (send, recv) = oneshot::channel();
+		let job: Job = Box::new(move || _ = send.send(f()));
+
+		self.stats.queued.fetch_add(1, Ordering::Relaxed);
+		if self.queue.send(job).is_err() {
+			self.stats.queued.fetch_sub(1, Ordering::Relaxed);
+			return Err(err!("Blocking pool is shut down."));
+		}
+
+		recv.await
+			.map_err(|_| err!("Blocking job {name} panicked."))
+	}
+
+	#[inline]
+	#[must_use]
+	pub fn stats(&self) -> &Stats { &self.stats }
+}
+
+fn worker(recv: &Mutex<mpsc::Receiver<Job>>, stats: &Stats) {
+	loop {
+		let Ok(job) = recv.lock().expect("locked").recv() else {
+			break;
+		};
+
+		stats.queued.fetch_sub(1, Ordering::Relaxed);
+		stats.busy.fetch_add(1, Ordering::Relaxed);
+		let started = Instant::now();
+
+		// a panicking job drops its result sender, failing only its caller
+		_ = catch_unwind(AssertUnwindSafe(job));
+
+		let elapsed = started.elapsed().as_micros().try_into().unwrap_or(u64::MAX);
+		stats.busy_micros.fetch_add(elapsed, Ordering::Relaxed);
+		stats.completed.fetch_add(1, Ordering::Relaxed);
+		stats.busy.fetch_sub(1, Ordering::Relaxed);
+	}
+
+	stats.workers.fetch_sub(1, Ordering::Relaxed);
+}
@@ -1,10 +1,14 @@
-use std::env::consts::OS;
+use std::{collections::HashSet, env::consts::OS};
 
 use either::Either;
 use figment::Figment;
+use ruma::state_res::RoomVersion;
 
 use super::DEPRECATED_KEYS;
-use crate::{debug, debug_info, debug_warn, error, warn, Config, Err, Result, Server};
+use crate::{
+	debug, debug_info, debug_warn, error, info::room_version::configured_room_versions, warn,
+	Config, Err, Result,
+};
 
 /// Performs check() with additional checks specific to reloading old config
 /// with new config.
@@ -168,8 +172,43 @@ pub fn check(config: &Config) -> Result {
 		}
 	}
 
+	if config.smtp_url.is_some() && config.smtp_from.is_none() {
+		return Err!(Config("smtp_from", "smtp_from must be set to send emails."));
+	}
+
+	if config.registration_requires_email && config.smtp_url.is_none() {
+		return Err!(Config(
+			"registration_requires_email",
+			"smtp_url must be set to require an email address to register."
+		));
+	}
+
+	let mut identity_provider_ids = HashSet::new();
+	for provider in &config.identity_providers {
+		let id = &provider.id;
+		if id.is_empty()
+			|| !id
+				.chars()
+				.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+		{
+			return Err!(Config(
+				"identity_providers",
+				"Identity provider id {id:?} must be made of A-Z, a-z, 0-9, '.', '_' and '-'."
+			));
+		}
+
+		if !identity_provider_ids.insert(id) {
+			return Err!(Config(
+				"identity_providers",
+				"Identity provider id {id:?} is used more than once."
+			));
+		}
+	}
+
 	if config.allow_registration
 		&& !config.yes_i_am_very_very_sure_i_want_an_open_registration_server_prone_to_abuse
+		&& !config.registration_requires_token
+		&& !config.registration_requires_email
 		&& config.registration_token.is_none()
 		&& config.registration_token_file.is_none()
 	{
@@ -187,6 +226,8 @@ pub fn check(config: &Config) -> Result {
 
 	if config.allow_registration
 		&& config.yes_i_am_very_very_sure_i_want_an_open_registration_server_prone_to_abuse
+		&& !config.registration_requires_token
+		&& !config.registration_requires_email
 		&& config.registration_token.is_none()
 		&& config.registration_token_file.is_none()
 	{
@@ -249,7 +290,16 @@ pub fn check(config: &Config) -> Result {
 		}
 	}
 
-	if !Server::available_room_versions()
+	for version in &config.experimental_room_versions {
+		if RoomVersion::new(version).is_err() {
+			return Err!(Config(
+				"experimental_room_versions",
+				"Room version {version:?} is not implemented by this build"
+			));
+		}
+	}
+
+	if !configured_room_versions(config)
 		.any(|(version, _)| version == config.default_room_version)
 	{
 		return Err!(Config(
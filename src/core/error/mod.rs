@@ -195,6 +195,13 @@ pub fn status_code(&self) -> http::StatusCode {
 	/// Result where Ok(None) is instead Err(e) if e.is_not_found().
 	#[inline]
 	pub fn is_not_found(&self) -> bool { self.status_code() == http::StatusCode::NOT_FOUND }
+
+	/// Returns true for errors raised because the server is shutting down; the
+	/// same request can succeed when retried later.
+	#[inline]
+	pub fn is_interrupted(&self) -> bool {
+		matches!(self, Self::Io(error) if error.kind() == std::io::ErrorKind::Interrupted)
+	}
 }
 
 impl std::fmt::Debug for Error {
@@ -4,7 +4,7 @@
 
 use ruma::{api::client::discovery::get_capabilities::RoomVersionStability, RoomVersionId};
 
-use crate::{at, is_equal_to};
+use crate::{at, is_equal_to, Config};
 
 /// Supported and stable room versions
 pub const STABLE_ROOM_VERSIONS: &[RoomVersionId] = &[
@@ -30,14 +30,14 @@ pub fn supported_room_version(&self, version: &RoomVersionId) -> bool {
 
 	#[inline]
 	pub fn supported_room_versions(&self) -> impl Iterator<Item = RoomVersionId> + '_ {
-		Self::available_room_versions()
+		self.available_room_versions()
 			.filter(|(_, stability)| self.supported_stability(stability))
 			.map(at!(0))
 	}
 
 	#[inline]
-	pub fn available_room_versions() -> impl Iterator<Item = RoomVersion> {
-		available_room_versions()
+	pub fn available_room_versions(&self) -> impl Iterator<Item = RoomVersion> + '_ {
+		configured_room_versions(&self.config)
 	}
 
 	#[inline]
@@ -58,3 +58,26 @@ pub fn available_room_versions() -> impl Iterator<Item = RoomVersion> {
 		.zip(once(RoomVersionStability::Stable).cycle())
 		.chain(unstable_room_versions)
 }
+
+/// The built-in room versions plus any experimental versions opted into by the
+/// config, with the configured stability overrides applied.
+pub fn configured_room_versions(config: &Config) -> impl Iterator<Item = RoomVersion> + '_ {
+	let experimental_room_versions = config
+		.experimental_room_versions
+		.iter()
+		.filter(|version| !available_room_versions().any(|(known, _)| known == **version))
+		.cloned()
+		.zip(once(RoomVersionStability::Unstable).cycle());
+
+	available_room_versions()
+		.chain(experimental_room_versions)
+		.map(|(version, stability)| {
+			let stability = config
+				.room_version_stability_overrides
+				.get(&version)
+				.cloned()
+				.unwrap_or(stability);
+
+			(version, stability)
+		})
+}
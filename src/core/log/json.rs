@@ -0,0 +1,125 @@
+use std::{fmt, fmt::Write as _, time::SystemTime};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use tracing::{
+	field::{Field, Visit},
+	span::Record,
+	Event, Subscriber,
+};
+use tracing_subscriber::{
+	field::RecordFields,
+	fmt::{format::Writer, FmtContext, FormatEvent, FormatFields, FormattedFields},
+	registry::LookupSpan,
+};
+
+/// Formats each event as one JSON object per line, carrying the event's
+/// fields and the spans it occurred in, outermost first. It also formats the
+/// fields of spans, which it reads back when an event occurs in them.
+#[derive(Default)]
+pub struct JsonFormat;
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+	S: Subscriber + for<'a> LookupSpan<'a>,
+	N: for<'a> FormatFields<'a> + 'static,
+{
+	fn format_event(
+		&self,
+		ctx: &FmtContext<'_, S, N>,
+		mut writer: Writer<'_>,
+		event: &Event<'_>,
+	) -> fmt::Result {
+		let metadata = event.metadata();
+		let timestamp: DateTime<Utc> = SystemTime::now().into();
+
+		let mut fields = Map::new();
+		event.record(&mut JsonVisitor(&mut fields));
+
+		let spans: Vec<Value> = ctx
+			.event_scope()
+			.into_iter()
+			.flat_map(|scope| scope.from_root())
+			.map(|span| {
+				let mut object = span
+					.extensions()
+					.get::<FormattedFields<N>>()
+					.and_then(|fields| serde_json::from_str::<Map<String, Value>>(fields).ok())
+					.unwrap_or_default();
+
+				object.insert("name".into(), span.name().into());
+				Value::Object(object)
+			})
+			.collect();
+
+		let mut line = Map::new();
+		line.insert(
+			"timestamp".into(),
+			timestamp
+				.to_rfc3339_opts(SecondsFormat::Micros, true)
+				.into(),
+		);
+		line.insert("level".into(), metadata.level().as_str().into());
+		line.insert("target".into(), metadata.target().into());
+		line.insert("fields".into(), fields.into());
+		line.insert("spans".into(), spans.into());
+
+		let line = serde_json::to_string(&line).map_err(|_| fmt::Error)?;
+		writeln!(writer, "{line}")
+	}
+}
+
+impl<'writer> FormatFields<'writer> for JsonFormat {
+	fn format_fields<R>(&self, mut writer: Writer<'writer>, fields: R) -> fmt::Result
+	where
+		R: RecordFields,
+	{
+		let mut object = Map::new();
+		fields.record(&mut JsonVisitor(&mut object));
+
+		let object = serde_json::to_string(&object).map_err(|_| fmt::Error)?;
+		writer.write_str(&object)
+	}
+
+	fn add_fields(
+		&self,
+		current: &'writer mut FormattedFields<Self>,
+		fields: &Record<'_>,
+	) -> fmt::Result {
+		let mut object: Map<String, Value> =
+			serde_json::from_str(&current.fields).unwrap_or_default();
+
+		fields.record(&mut JsonVisitor(&mut object));
+		current.fields = serde_json::to_string(&object).map_err(|_| fmt::Error)?;
+
+		Ok(())
+	}
+}
+
+impl JsonVisitor<'_> {
+	fn insert(&mut self, field: &Field, value: Value) {
+		if field.name().starts_with('_') {
+			return;
+		}
+
+		self.0.insert(field.name().into(), value);
+	}
+}
+
+impl Visit for JsonVisitor<'_> {
+	fn record_f64(&mut self, field: &Field, value: f64) { self.insert(field, value.into()); }
+
+	fn record_i64(&mut self, field: &Field, value: i64) { self.insert(field, value.into()); }
+
+	fn record_u64(&mut self, field: &Field, value: u64) { self.insert(field, value.into()); }
+
+	fn record_bool(&mut self, field: &Field, value: bool) { self.insert(field, value.into()); }
+
+	fn record_str(&mut self, field: &Field, value: &str) { self.insert(field, value.into()); }
+
+	fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+		self.insert(field, format!("{value:?}").into());
+	}
+}
@@ -5,11 +5,13 @@
 pub mod console;
 pub mod fmt;
 pub mod fmt_span;
+pub mod json;
 mod reload;
 mod suppress;
 
 pub use capture::Capture;
 pub use console::{is_systemd_mode, ConsoleFormat, ConsoleWriter};
+pub use json::JsonFormat;
 pub use reload::{LogLevelReloadHandles, ReloadHandle};
 pub use suppress::Suppress;
 pub use tracing::Level;
@@ -0,0 +1,126 @@
+use std::{
+	collections::BTreeMap,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		RwLock,
+	},
+	time::Duration,
+};
+
+/// Upper bounds of the histogram buckets, in seconds.
+pub const BUCKETS: [f64; 14] = [
+	0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Distribution of durations, in the shape Prometheus expects: each bucket
+/// counts the observations at or below its bound.
+#[derive(Debug, Default)]
+pub struct Histogram {
+	buckets: [AtomicU64; BUCKETS.len()],
+	count: AtomicU64,
+	sum_micros: AtomicU64,
+}
+
+/// Most labels kept by [`Histograms`] and [`Counters`]; observations for any
+/// further label are aggregated under [`OVERFLOW_LABEL`], so a label taken
+/// from remote input such as a destination can't grow them without bound.
+pub const MAX_LABELS: usize = 1024;
+
+/// Label of the observations made once [`MAX_LABELS`] was reached.
+pub const OVERFLOW_LABEL: &str = "other";
+
+/// Histograms keyed by a label, e.g. the route or the destination.
+#[derive(Debug, Default)]
+pub struct Histograms(RwLock<BTreeMap<String, Histogram>>);
+
+/// Counters keyed by a label.
+#[derive(Debug, Default)]
+pub struct Counters(RwLock<BTreeMap<String, AtomicU64>>);
+
+impl Histogram {
+	pub fn observe(&self, elapsed: Duration) {
+		let secs = elapsed.as_secs_f64();
+		BUCKETS
+			.iter()
+			.zip(&self.buckets)
+			.filter(|(bound, _)| secs <= **bound)
+			.for_each(|(_, bucket)| _ = bucket.fetch_add(1, Ordering::Relaxed));
+
+		let micros = elapsed.as_micros().try_into().unwrap_or(u64::MAX);
+		self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+		self.count.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Bound of each bucket with its cumulative count.
+	pub fn buckets(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+		BUCKETS.iter().copied().zip(
+			self.buckets
+				.iter()
+				.map(|bucket| bucket.load(Ordering::Relaxed)),
+		)
+	}
+
+	#[must_use]
+	pub fn count(&self) -> u64 { self.count.load(Ordering::Relaxed) }
+
+	/// Sum of the observations, in seconds.
+	#[must_use]
+	#[allow(clippy::cast_precision_loss, clippy::as_conversions)]
+	pub fn sum(&self) -> f64 { self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0 }
+}
+
+impl Histograms {
+	pub fn observe(&self, label: &str, elapsed: Duration) {
+		if let Some(histogram) = self.0.read().expect("locked for reading").get(label) {
+			histogram.observe(elapsed);
+			return;
+		}
+
+		let mut histograms = self.0.write().expect("locked for writing");
+		let label = overflow(&histograms, label);
+		histograms
+			.entry(label.to_owned())
+			.or_default()
+			.observe(elapsed);
+	}
+
+	pub fn for_each(&self, mut f: impl FnMut(&str, &Histogram)) {
+		self.0
+			.read()
+			.expect("locked for reading")
+			.iter()
+			.for_each(|(label, histogram)| f(label, histogram));
+	}
+}
+
+impl Counters {
+	pub fn increment(&self, label: &str) {
+		if let Some(counter) = self.0.read().expect("locked for reading").get(label) {
+			counter.fetch_add(1, Ordering::Relaxed);
+			return;
+		}
+
+		let mut counters = self.0.write().expect("locked for writing");
+		let label = overflow(&counters, label);
+		counters
+			.entry(label.to_owned())
+			.or_default()
+			.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn for_each(&self, mut f: impl FnMut(&str, u64)) {
+		self.0
+			.read()
+			.expect("locked for reading")
+			.iter()
+			.for_each(|(label, counter)| f(label, counter.load(Ordering::Relaxed)));
+	}
+}
+
+fn overflow<'a, T>(map: &BTreeMap<String, T>, label: &'a str) -> &'a str {
+	if map.len() < MAX_LABELS || map.contains_key(label) {
+		label
+	} else {
+		OVERFLOW_LABEL
+	}
+}
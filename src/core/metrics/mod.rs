@@ -1,3 +1,5 @@
+mod histogram;
+
 use std::sync::atomic::AtomicU32;
 
 use tokio::runtime;
@@ -5,6 +7,8 @@
 #[cfg(tokio_unstable)]
 use tokio_metrics::{RuntimeIntervals, RuntimeMonitor};
 
+pub use self::histogram::{Counters, Histogram, Histograms, BUCKETS, MAX_LABELS, OVERFLOW_LABEL};
+
 pub struct Metrics {
 	_runtime: Option<runtime::Handle>,
 
@@ -22,6 +26,27 @@ pub struct Metrics {
 	pub requests_handle_active: AtomicU32,
 	pub requests_handle_finished: AtomicU32,
 	pub requests_panic: AtomicU32,
+
+	/// Time taken to handle requests, by route.
+	pub request_durations: Histograms,
+
+	/// Time taken by database queries run on the request pool, by kind.
+	pub db_query_durations: Histograms,
+
+	/// Time taken to send federation transactions, by destination. Only the
+	/// first [`MAX_LABELS`] destinations get their own histogram.
+	pub federation_transaction_durations: Histograms,
+
+	/// Federation transactions which failed, by destination, with the same
+	/// limit.
+	pub federation_transaction_failures: Counters,
+
+	/// Time taken to handle incoming PDUs.
+	pub incoming_pdu_durations: Histogram,
+
+	/// Time from one of our users sending an event to its delivery by an
+	/// incremental sync.
+	pub sync_lag: Histogram,
 }
 
 impl Metrics {
@@ -49,6 +74,13 @@ pub fn new(runtime: Option<runtime::Handle>) -> Self {
 			requests_handle_active: AtomicU32::new(0),
 			requests_handle_finished: AtomicU32::new(0),
 			requests_panic: AtomicU32::new(0),
+
+			request_durations: Histograms::default(),
+			db_query_durations: Histograms::default(),
+			federation_transaction_durations: Histograms::default(),
+			federation_transaction_failures: Counters::default(),
+			incoming_pdu_durations: Histogram::default(),
+			sync_lag: Histogram::default(),
 		}
 	}
 
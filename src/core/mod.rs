@@ -1,4 +1,5 @@
 pub mod alloc;
+pub mod blocking;
 pub mod config;
 pub mod debug;
 pub mod error;
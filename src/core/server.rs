@@ -9,7 +9,7 @@
 use ruma::OwnedServerName;
 use tokio::{runtime, sync::broadcast};
 
-use crate::{config, config::Config, log::Log, metrics::Metrics, Err, Result};
+use crate::{blocking, config, config::Config, log::Log, metrics::Metrics, Err, Result};
 
 /// Server runtime state; public portion
 pub struct Server {
@@ -45,11 +45,15 @@ pub struct Server {
 
 	/// Metrics subsystem state
 	pub metrics: Metrics,
+
+	/// Thread-pool for CPU-bound work
+	pub blocking: blocking::Pool,
 }
 
 impl Server {
 	#[must_use]
 	pub fn new(config: Config, runtime: Option<runtime::Handle>, log: Log) -> Self {
+		let pool = blocking::Pool::new(config.blocking_pool_workers);
 		Self {
 			name: config.server_name.clone(),
 			config: config::Manager::new(config),
@@ -61,6 +65,7 @@ pub fn new(config: Config, runtime: Option<runtime::Handle>, log: Log) -> Self {
 			signal: broadcast::channel::<&'static str>(1).0,
 			log,
 			metrics: Metrics::new(runtime),
+			blocking: pool,
 		}
 	}
 
@@ -1,6 +1,7 @@
 use std::{fmt, str::FromStr};
 
 use ruma::{canonical_json::try_from_json_map, CanonicalJsonError, CanonicalJsonObject};
+use serde_json::{Map, Value};
 
 use crate::Result;
 
@@ -45,3 +46,66 @@ fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
 	}
 	deserializer.deserialize_str(Visitor(std::marker::PhantomData))
 }
+
+/// Projects a JSON object onto `fields`, as the `event_fields` of a filter
+/// do: each field is a path of keys separated by `.`, where a literal `.` is
+/// escaped as `\.`. Fields missing from the object are skipped.
+#[must_use]
+pub fn project_fields<S: AsRef<str>>(
+	object: &Map<String, Value>,
+	fields: &[S],
+) -> Map<String, Value> {
+	let mut projected = Map::new();
+	for field in fields {
+		let path = split_field(field.as_ref());
+		copy_path(object, &mut projected, &path);
+	}
+
+	projected
+}
+
+fn copy_path(from: &Map<String, Value>, to: &mut Map<String, Value>, path: &[String]) {
+	let Some((key, rest)) = path.split_first() else {
+		return;
+	};
+
+	let Some(value) = from.get(key) else {
+		return;
+	};
+
+	if rest.is_empty() {
+		to.insert(key.clone(), value.clone());
+		return;
+	}
+
+	let Value::Object(from) = value else {
+		return;
+	};
+
+	let Value::Object(to) = to.entry(key.clone()).or_insert_with(|| Map::new().into()) else {
+		return;
+	};
+
+	copy_path(from, to, rest);
+}
+
+fn split_field(field: &str) -> Vec<String> {
+	let mut path = vec![String::new()];
+	let mut chars = field.chars();
+	while let Some(c) = chars.next() {
+		let c = match c {
+			| '.' => {
+				path.push(String::new());
+				continue;
+			},
+			| '\\' => chars.next().unwrap_or(c),
+			| c => c,
+		};
+
+		if let Some(key) = path.last_mut() {
+			key.push(c);
+		}
+	}
+
+	path
+}
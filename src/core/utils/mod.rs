@@ -31,7 +31,7 @@
 	future::TryExtExt as TryFutureExtExt,
 	hash::sha256::delimited as calculate_hash,
 	html::Escape as HtmlEscape,
-	json::{deserialize_from_str, to_canonical_object},
+	json::{deserialize_from_str, project_fields, to_canonical_object},
 	math::clamp,
 	mutex_map::{Guard as MutexMapGuard, MutexMap},
 	rand::{shuffle, string as random_string},
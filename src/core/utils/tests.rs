@@ -276,3 +276,26 @@ async fn set_intersection_sorted_stream2() {
 		.await;
 	assert!(r.eq(&["ccc", "ggg", "iii"]));
 }
+
+#[test]
+fn project_fields() {
+	use serde_json::json;
+	use utils::project_fields;
+
+	let event = json!({
+		"type": "m.room.message",
+		"sender": "@alice:example.com",
+		"content": {"body": "hi", "msgtype": "m.text"},
+		"m.dotted": {"key": 1},
+	});
+	let event = event.as_object().unwrap();
+
+	let projected = project_fields(event, &["type", "content.body", "content.missing"]);
+	assert_eq!(
+		serde_json::Value::Object(projected),
+		json!({"type": "m.room.message", "content": {"body": "hi"}})
+	);
+
+	let projected = project_fields(event, &[r"m\.dotted.key", "sender.nested"]);
+	assert_eq!(serde_json::Value::Object(projected), json!({"m.dotted": {"key": 1}}));
+}
@@ -23,6 +23,7 @@
 	WaitForCompactOptions,
 };
 
+pub use self::backup::BackupInfo;
 use crate::{
 	pool::Pool,
 	util::{map_err, result},
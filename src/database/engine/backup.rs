@@ -1,24 +1,30 @@
-use std::fmt::Write;
+use std::{fmt::Write, path::Path};
 
-use conduwuit::{error, implement, info, utils::time::rfc2822_from_seconds, warn, Result};
-use rocksdb::backup::{BackupEngine, BackupEngineOptions};
+use conduwuit::{error, implement, info, utils::time::rfc2822_from_seconds, Err, Result};
+use rocksdb::backup::{BackupEngine, BackupEngineInfo, BackupEngineOptions, RestoreOptions};
 
 use super::Engine;
 use crate::{or_else, util::map_err};
 
+/// A backup held by the backup engine. Backups share unchanged files, so
+/// `size` overstates what each one adds on disk.
+#[derive(Clone, Copy, Debug)]
+pub struct BackupInfo {
+	pub id: u32,
+	pub timestamp: i64,
+	pub size: u64,
+	pub files: u32,
+}
+
+/// Creates a backup, incrementally from the previous ones, and verifies it.
+/// Returns `None` if `database_backups_to_keep` is zero, which only purges the
+/// existing backups.
 #[implement(Engine)]
 #[tracing::instrument(skip(self))]
-pub fn backup(&self) -> Result {
-	let server = &self.ctx.server;
-	let config = &server.config;
-	let path = config.database_backup_path.as_ref();
-	if path.is_none() || path.is_some_and(|path| path.as_os_str().is_empty()) {
-		return Ok(());
-	}
-
-	let options =
-		BackupEngineOptions::new(path.expect("valid database backup path")).map_err(map_err)?;
-	let mut engine = BackupEngine::open(&options, &*self.ctx.env.lock()?).map_err(map_err)?;
+pub fn backup(&self) -> Result<Option<BackupInfo>> {
+	let config = &self.ctx.server.config;
+	let mut engine = self.backup_engine()?;
+	let mut created = None;
 	if config.database_backups_to_keep > 0 {
 		let flush = !self.is_read_only();
 		engine
@@ -26,11 +32,18 @@ pub fn backup(&self) -> Result {
 			.map_err(map_err)?;
 
 		let engine_info = engine.get_backup_info();
-		let info = &engine_info.last().expect("backup engine info is not empty");
+		let info = engine_info
+			.last()
+			.map(BackupInfo::from)
+			.expect("backup engine info is not empty");
+
+		engine.verify_backup(info.id).map_err(map_err)?;
 		info!(
-			"Created database backup #{} using {} bytes in {} files",
-			info.backup_id, info.size, info.num_files,
+			"Created and verified database backup #{} using {} bytes in {} files",
+			info.id, info.size, info.files,
 		);
+
+		created = Some(info);
 	}
 
 	if config.database_backups_to_keep >= 0 {
@@ -40,34 +53,97 @@ pub fn backup(&self) -> Result {
 		}
 	}
 
-	Ok(())
+	Ok(created)
 }
 
 #[implement(Engine)]
-pub fn backup_list(&self) -> Result<String> {
-	let server = &self.ctx.server;
-	let config = &server.config;
-	let path = config.database_backup_path.as_ref();
-	if path.is_none() || path.is_some_and(|path| path.as_os_str().is_empty()) {
-		return Ok("Configure database_backup_path to enable backups, or the path specified is \
-		           not valid"
-			.to_owned());
-	}
+pub fn backups(&self) -> Result<Vec<BackupInfo>> {
+	let engine = self.backup_engine()?;
 
+	Ok(engine
+		.get_backup_info()
+		.iter()
+		.map(BackupInfo::from)
+		.collect())
+}
+
+#[implement(Engine)]
+pub fn backup_list(&self) -> Result<String> {
 	let mut res = String::new();
-	let options =
-		BackupEngineOptions::new(path.expect("valid database backup path")).or_else(or_else)?;
-	let engine = BackupEngine::open(&options, &*self.ctx.env.lock()?).or_else(or_else)?;
-	for info in engine.get_backup_info() {
+	for info in self.backups()? {
 		writeln!(
 			res,
 			"#{} {}: {} bytes, {} files",
-			info.backup_id,
+			info.id,
 			rfc2822_from_seconds(info.timestamp),
 			info.size,
-			info.num_files,
+			info.files,
 		)?;
 	}
 
 	Ok(res)
 }
+
+/// Checks that the files of a backup are all present with their expected
+/// sizes.
+#[implement(Engine)]
+#[tracing::instrument(skip(self))]
+pub fn backup_verify(&self, id: u32) -> Result {
+	self.backup_engine()?.verify_backup(id).or_else(or_else)
+}
+
+/// Restores a backup, or the latest one, into `path`. The running database is
+/// left alone; the restored one is used by pointing `database_path` at `path`
+/// and restarting.
+#[implement(Engine)]
+#[tracing::instrument(skip(self))]
+pub fn backup_restore(&self, id: Option<u32>, path: &Path) -> Result {
+	let config = &self.ctx.server.config;
+	if path == config.database_path {
+		return Err!("Backups can't be restored over the running database.");
+	}
+
+	if path.read_dir().is_ok_and(|mut dir| dir.next().is_some()) {
+		return Err!("{path:?} isn't empty.");
+	}
+
+	let mut engine = self.backup_engine()?;
+	let options = RestoreOptions::default();
+	match id {
+		| Some(id) => engine.restore_from_backup(path, path, &options, id),
+		| None => engine.restore_from_latest_backup(path, path, &options),
+	}
+	.map_err(map_err)?;
+
+	info!("Restored database backup {id:?} into {path:?}");
+	Ok(())
+}
+
+#[implement(Engine)]
+fn backup_engine(&self) -> Result<BackupEngine> {
+	let config = &self.ctx.server.config;
+	let Some(path) = config
+		.database_backup_path
+		.as_ref()
+		.filter(|path| !path.as_os_str().is_empty())
+	else {
+		return Err!(Config(
+			"database_backup_path",
+			"Configure database_backup_path to enable backups."
+		));
+	};
+
+	let options = BackupEngineOptions::new(path).map_err(map_err)?;
+	BackupEngine::open(&options, &*self.ctx.env.lock()?).map_err(map_err)
+}
+
+impl From<&BackupEngineInfo> for BackupInfo {
+	fn from(info: &BackupEngineInfo) -> Self {
+		Self {
+			id: info.backup_id,
+			timestamp: info.timestamp,
+			size: info.size,
+			files: info.num_files,
+		}
+	}
+}
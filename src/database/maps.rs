@@ -53,6 +53,22 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "disabledroomids",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "ephemeraldisabledroomids",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "email_userid",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "emailsecret_sid",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "emailsid_session",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "eventid_outlierpdu",
 		cache_disp: CacheDisp::SharedWith("pduid_pdu"),
@@ -71,6 +87,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		index_size: 512,
 		..descriptor::RANDOM
 	},
+	Descriptor {
+		name: "eventid_rejectreason",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "eventid_shorteventid",
 		cache_disp: CacheDisp::Unique,
@@ -88,6 +108,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "id_appserviceregistrations",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "jobid_job",
+		..descriptor::SEQUENTIAL_SMALL
+	},
 	Descriptor {
 		name: "keychangeid_userid",
 		..descriptor::RANDOM
@@ -100,10 +124,26 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "lazyloadedids",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "mediaid_eventid",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "mediaid_file",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "mediaid_lastaccess",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "mediaid_pendingupload",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "mediaid_quarantine",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "mediaid_user",
 		..descriptor::RANDOM_SMALL
@@ -137,6 +177,36 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "referencedevents",
 		..descriptor::RANDOM
 	},
+	Descriptor {
+		name: "registrationtoken_info",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "registrationtokensession_reservedts",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "reportid_report",
+		..descriptor::SEQUENTIAL_SMALL
+	},
+	Descriptor {
+		name: "roomcount_rejectedpdu",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "roomdepthid_pduid",
+		key_size_hint: Some(24),
+		val_size_hint: Some(16),
+		..descriptor::SEQUENTIAL_SMALL
+	},
+	Descriptor {
+		name: "roomeventid_partialstatequeue",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "roomid_emptysince",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "roomid_invitedcount",
 		..descriptor::RANDOM_SMALL
@@ -149,10 +219,18 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "roomid_joinedcount",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "roomid_partialstate",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "roomid_pduleaves",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "roomid_policylist",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "roomid_shortroomid",
 		val_size_hint: Some(8),
@@ -225,6 +303,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "servername_destination",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "servernamecount_devicelistuserid",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "servername_educount",
 		..descriptor::RANDOM_SMALL
@@ -243,6 +325,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "serverroomids",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "servertxnideventid_accepted",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "shorteventid_authchain",
 		cache_disp: CacheDisp::Unique,
@@ -281,6 +367,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		key_size_hint: Some(48),
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "ssoidentity_userid",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "statehash_shortstatehash",
 		val_size_hint: Some(8),
@@ -297,6 +387,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "threadid_userids",
 		..descriptor::SEQUENTIAL_SMALL
 	},
+	Descriptor {
+		name: "todevicecount_queuedts",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "todeviceid_events",
 		..descriptor::RANDOM
@@ -311,6 +405,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "token_userdeviceid",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "tombstonedroomids",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "tokenids",
 		block_size: 512,
@@ -320,6 +418,14 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "url_previews",
 		..descriptor::RANDOM
 	},
+	Descriptor {
+		name: "userdelayid_delayedevent",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "userdeviceid_fallbackkeys",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "userdeviceid_metadata",
 		..descriptor::RANDOM_SMALL
@@ -348,6 +454,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "userid_blurhash",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "userid_dehydrateddevice",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "userid_devicelistversion",
 		..descriptor::RANDOM_SMALL
@@ -356,6 +466,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "userid_displayname",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "userid_email",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "userid_lastonetimekeyupdate",
 		..descriptor::RANDOM_SMALL
@@ -364,6 +478,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "userid_masterkeyid",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "userid_mediausage",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "userid_password",
 		..descriptor::RANDOM
@@ -376,14 +494,26 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "userid_selfsigningkeyid",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "userid_servernoticesroomid",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "userid_usersigningkeyid",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "useridcount_notification",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "useridprofilekey_value",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "usermediaid_pendingupload",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "openidtoken_expiresatuserid",
 		..descriptor::RANDOM_SMALL
@@ -392,6 +522,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "logintoken_expiresatuserid",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "userroomcount_unreadnotification",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "userroomid_highlightcount",
 		..descriptor::RANDOM
@@ -416,4 +550,8 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "userroomid_notificationcount",
 		..descriptor::RANDOM
 	},
+	Descriptor {
+		name: "userroomid_unreadcount",
+		..descriptor::RANDOM
+	},
 ];
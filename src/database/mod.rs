@@ -28,9 +28,11 @@
 pub use self::{
 	de::{Ignore, IgnoreAll},
 	deserialized::Deserialized,
+	engine::BackupInfo,
 	handle::Handle,
 	keyval::{serialize_key, serialize_val, KeyVal, Slice},
 	map::{compact, Get, Map, Qry},
+	pool::Stats as PoolStats,
 	ser::{serialize, serialize_to, serialize_to_vec, Cbor, Interfix, Json, Separator, SEP},
 };
 pub(crate) use self::{
@@ -79,6 +81,11 @@ pub fn is_read_only(&self) -> bool { self.db.is_read_only() }
 	#[inline]
 	#[must_use]
 	pub fn is_secondary(&self) -> bool { self.db.is_secondary() }
+
+	/// Load of the frontend thread-pool making database requests.
+	#[inline]
+	#[must_use]
+	pub fn pool_stats(&self) -> PoolStats { self.db.pool.stats() }
 }
 
 impl Index<&str> for Database {
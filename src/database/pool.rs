@@ -8,13 +8,12 @@
 	},
 	thread,
 	thread::JoinHandle,
+	time::Instant,
 };
 
 use async_channel::{QueueStrategy, Receiver, RecvError, Sender};
 use conduwuit::{
-	debug, debug_warn, err, error, implement,
-	result::DebugInspect,
-	trace,
+	debug, debug_warn, err, error, implement, trace,
 	utils::sys::compute::{get_affinity, nth_core_available, set_affinity},
 	Error, Result, Server,
 };
@@ -62,6 +61,18 @@ pub(crate) struct Seek {
 	pub(crate) res: Option<ResultSender<stream::State<'static>>>,
 }
 
+/// Load of the pool, for metrics.
+#[derive(Debug, Default)]
+pub struct Stats {
+	pub workers: usize,
+
+	/// Workers handling a request.
+	pub busy: usize,
+
+	/// Requests waiting for a worker.
+	pub queued: usize,
+}
+
 pub(crate) type BatchQuery<'a> = SmallVec<[KeyBuf; BATCH_INLINE]>;
 pub(crate) type BatchResult<'a> = SmallVec<[ResultHandle<'a>; BATCH_INLINE]>;
 pub(crate) type ResultHandle<'a> = Result<Handle<'a>>;
@@ -224,6 +235,15 @@ pub(crate) async fn execute_iter(self: &Arc<Self>, mut cmd: Seek) -> Result<stre
 		.await
 }
 
+#[implement(Pool)]
+pub(crate) fn stats(&self) -> Stats {
+	Stats {
+		workers: self.workers.lock().expect("locked").len(),
+		busy: self.busy.load(Ordering::Relaxed),
+		queued: self.queues.iter().map(Sender::len).sum(),
+	}
+}
+
 #[implement(Pool)]
 fn select_queue(&self) -> &Sender<Cmd> {
 	let core_id = get_affinity().next().unwrap_or(0);
@@ -331,22 +351,39 @@ fn worker_loop(self: &Arc<Self>, recv: &Receiver<Cmd>) {
 	fields(
 		receivers = recv.receiver_count(),
 		queued = recv.len(),
-		busy = self.busy.fetch_sub(1, Ordering::Relaxed) - 1,
+		busy = self.busy.load(Ordering::Relaxed).saturating_sub(1),
 	),
 )]
 fn worker_wait(self: &Arc<Self>, recv: &Receiver<Cmd>) -> Result<Cmd, RecvError> {
-	recv.recv_blocking().debug_inspect(|_| {
+	// outside the span fields, which aren't evaluated when tracing is disabled
+	self.busy.fetch_sub(1, Ordering::Relaxed);
+	recv.recv_blocking().inspect(|_| {
 		self.busy.fetch_add(1, Ordering::Relaxed);
 	})
 }
 
 #[implement(Pool)]
 fn worker_handle(self: &Arc<Self>, cmd: Cmd) {
-	match cmd {
-		| Cmd::Get(cmd) if cmd.key.len() == 1 => self.handle_get(cmd),
-		| Cmd::Get(cmd) => self.handle_batch(cmd),
-		| Cmd::Iter(cmd) => self.handle_iter(cmd),
+	let started = Instant::now();
+	let kind = match cmd {
+		| Cmd::Get(cmd) if cmd.key.len() == 1 => {
+			self.handle_get(cmd);
+			"get"
+		},
+		| Cmd::Get(cmd) => {
+			self.handle_batch(cmd);
+			"batch"
+		},
+		| Cmd::Iter(cmd) => {
+			self.handle_iter(cmd);
+			"seek"
+		},
 	};
+
+	self.server
+		.metrics
+		.db_query_durations
+		.observe(kind, started.elapsed());
 }
 
 #[implement(Pool)]
@@ -4,11 +4,13 @@
 
 use arrayvec::ArrayVec;
 use conduwuit::ruma::{serde::Raw, EventId, RoomId, UserId};
+use futures::FutureExt;
 use serde::Serialize;
 
 use crate::{
 	de, ser,
 	ser::{serialize_to_vec, Json},
+	watchers::Watchers,
 	Ignore, Interfix,
 };
 
@@ -546,3 +548,21 @@ fn serde_tuple_option_none_none_none() {
 	assert_eq!(None, cc.0);
 	assert_eq!(bb, cc);
 }
+
+#[test]
+fn watch_terminated_prefix_wakes_on_exact_key() {
+	let watchers = Watchers::default();
+	let watch = watchers.watch(b"!room:example.com\xFF");
+
+	watchers.wake(b"!room:example.com");
+	assert!(watch.now_or_never().is_some());
+}
+
+#[test]
+fn watch_terminated_prefix_ignores_longer_key() {
+	let watchers = Watchers::default();
+	let watch = watchers.watch(b"!room:example.com\xFF");
+
+	watchers.wake(b"!room:example.com.evil");
+	assert!(watch.now_or_never().is_none());
+}
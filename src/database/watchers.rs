@@ -7,6 +7,8 @@
 
 use tokio::sync::watch;
 
+use crate::ser::SEP;
+
 type Watcher = RwLock<HashMap<Vec<u8>, (watch::Sender<()>, watch::Receiver<()>)>>;
 
 #[derive(Default)]
@@ -44,11 +46,16 @@ pub(crate) fn wake(&self, key: &[u8]) {
 			}
 		}
 
+		// A prefix ending with the separator also covers the key which is
+		// exactly the part before it, e.g. a room ID keying a whole record.
+		let terminated = [key, &[SEP]].concat();
+		let terminated = watchers.contains_key(&terminated).then_some(terminated);
+
 		drop(watchers);
 
-		if !triggered.is_empty() {
+		if !triggered.is_empty() || terminated.is_some() {
 			let mut watchers = self.watchers.write().unwrap();
-			for prefix in triggered {
+			for prefix in triggered.into_iter().chain(terminated.as_deref()) {
 				if let Some(tx) = watchers.remove(prefix) {
 					tx.0.send(()).expect("channel should still be open");
 				}
@@ -15,9 +15,36 @@ pub(super) fn command(mut item: ItemFn, _args: &[Meta]) -> Result<TokenStream> {
 	Ok(item.into_token_stream().into())
 }
 
-pub(super) fn command_dispatch(item: ItemEnum, _args: &[Meta]) -> Result<TokenStream> {
+pub(super) fn command_dispatch(mut item: ItemEnum, _args: &[Meta]) -> Result<TokenStream> {
 	let name = &item.ident;
 	let arm: Vec<TokenStream2> = item.variants.iter().map(dispatch_arm).try_collect()?;
+	let tier_arm: Vec<TokenStream2> = item.variants.iter().map(tier_arm).collect();
+	let registry_arm: Vec<TokenStream2> = item.variants.iter().map(registry_arm).collect();
+
+	// clap doesn't know our attributes
+	for variant in &mut item.variants {
+		variant
+			.attrs
+			.retain(|attr| !attr.path().is_ident(MODERATOR));
+	}
+
+	let tier = quote! {
+		impl crate::Tiered for #name {
+			fn tier(&self) -> conduwuit_service::admin::Tier {
+				use #name::*;
+				match self {
+					#( #tier_arm )*
+				}
+			}
+
+			fn registry() -> crate::registry::Registry {
+				let mut registry = crate::registry::Registry::new();
+				#( #registry_arm )*
+				registry
+			}
+		}
+	};
+
 	let switch = quote! {
 		pub(super) async fn process(
 			command: #name,
@@ -31,12 +58,54 @@ pub(super) async fn process(
 		}
 	};
 
-	Ok([item.into_token_stream(), switch]
+	Ok([item.into_token_stream(), tier, switch]
 		.into_iter()
 		.collect::<TokenStream2>()
 		.into())
 }
 
+/// Marks a command, or a whole group of subcommands, as available to
+/// moderator admins. Anything else requires an owner.
+const MODERATOR: &str = "moderator";
+
+fn tier_arm(v: &Variant) -> TokenStream2 {
+	let name = &v.ident;
+	let moderator = v.attrs.iter().any(|attr| attr.path().is_ident(MODERATOR));
+	match &v.fields {
+		| _ if moderator => quote! {
+			#name { .. } => conduwuit_service::admin::Tier::Moderator,
+		},
+		| Fields::Unnamed(_) => quote! {
+			#name ( command ) => crate::Tiered::tier(command),
+		},
+		| _ => quote! {
+			#name { .. } => conduwuit_service::admin::Tier::Owner,
+		},
+	}
+}
+
+fn registry_arm(v: &Variant) -> TokenStream2 {
+	let name = camel_to_snake_string(&v.ident.to_string()).replace('_', "-");
+	let moderator = v.attrs.iter().any(|attr| attr.path().is_ident(MODERATOR));
+	match &v.fields {
+		| _ if moderator => quote! {
+			registry.push((vec![#name.to_owned()], conduwuit_service::admin::Tier::Moderator));
+		},
+		| Fields::Unnamed(fields) if !fields.unnamed.is_empty() => {
+			let ty = &fields.unnamed[0].ty;
+			quote! {
+				registry.extend(crate::registry::prefixed(
+					#name,
+					<#ty as crate::Tiered>::registry(),
+				));
+			}
+		},
+		| _ => quote! {
+			registry.push((vec![#name.to_owned()], conduwuit_service::admin::Tier::Owner));
+		},
+	}
+}
+
 fn dispatch_arm(v: &Variant) -> Result<TokenStream2> {
 	let name = &v.ident;
 	let target = camel_to_snake_string(&format!("{name}"));
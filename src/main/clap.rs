@@ -31,6 +31,10 @@ pub(crate) struct Args {
 	#[arg(long)]
 	pub(crate) execute: Vec<String>,
 
+	/// Log the pending database migrations, then exit without applying them.
+	#[arg(long, num_args(0))]
+	pub(crate) dry_run: bool,
+
 	/// Set functional testing modes if available. Ex '--test=smoke'
 	#[arg(long, hide(true))]
 	pub(crate) test: Vec<String>,
@@ -123,6 +127,10 @@ pub(crate) fn update(mut config: Figment, args: &Args) -> Result<Figment> {
 		config = config.join(("admin_console_automatic", true));
 	}
 
+	if args.dry_run {
+		config = config.merge(("migrations_dry_run", true));
+	}
+
 	// Execute commands after any commands listed in configuration file
 	config = config.adjoin(("admin_execute", &args.execute));
 
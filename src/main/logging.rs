@@ -3,7 +3,7 @@
 use conduwuit::{
 	config::Config,
 	debug_warn, err,
-	log::{capture, fmt_span, ConsoleFormat, ConsoleWriter, LogLevelReloadHandles},
+	log::{capture, fmt_span, ConsoleFormat, ConsoleWriter, JsonFormat, LogLevelReloadHandles},
 	result::UnwrapOrErr,
 	Result,
 };
@@ -26,11 +26,21 @@ pub(crate) fn init(
 		.with_regex(config.log_filter_regex)
 		.parse(&config.log)
 		.map_err(|e| err!(Config("log", "{e}.")))?;
-	let console_layer = fmt::Layer::new()
-		.with_span_events(console_span_events)
-		.event_format(ConsoleFormat::new(config))
-		.fmt_fields(ConsoleFormat::new(config))
-		.with_writer(ConsoleWriter::new(config));
+	let console_layer = if config.log_json {
+		fmt::Layer::new()
+			.with_span_events(console_span_events)
+			.event_format(JsonFormat)
+			.fmt_fields(JsonFormat)
+			.with_writer(ConsoleWriter::new(config))
+			.boxed()
+	} else {
+		fmt::Layer::new()
+			.with_span_events(console_span_events)
+			.event_format(ConsoleFormat::new(config))
+			.fmt_fields(ConsoleFormat::new(config))
+			.with_writer(ConsoleWriter::new(config))
+			.boxed()
+	};
 
 	let (console_reload_filter, console_reload_handle) =
 		reload::Layer::new(console_filter.clone());
@@ -53,6 +63,29 @@ pub(crate) fn init(
 		subscriber.with(sentry_layer.with_filter(sentry_reload_filter))
 	};
 
+	#[cfg(feature = "otlp_telemetry")]
+	let subscriber = {
+		let otlp_layer = if config.otlp_endpoint.is_some() {
+			let otlp_filter = EnvFilter::try_new(&config.otlp_filter)
+				.map_err(|e| err!(Config("otlp_filter", "{e}.")))?;
+			let trace_layer =
+				tracing_opentelemetry::layer().with_tracer(crate::otlp::tracer(config)?);
+			let metrics_layer =
+				tracing_opentelemetry::MetricsLayer::new(crate::otlp::meter_provider(config)?);
+			let (otlp_reload_filter, otlp_reload_handle) = reload::Layer::new(otlp_filter);
+			reload_handles.add("otlp", Box::new(otlp_reload_handle));
+			Some(
+				trace_layer
+					.and_then(metrics_layer)
+					.with_filter(otlp_reload_filter),
+			)
+		} else {
+			None
+		};
+
+		subscriber.with(otlp_layer)
+	};
+
 	#[cfg(feature = "perf_measurements")]
 	let (subscriber, flame_guard) = {
 		let (flame_layer, flame_guard) = if config.tracing_flame {
@@ -1,6 +1,7 @@
 pub(crate) mod clap;
 mod logging;
 mod mods;
+mod otlp;
 mod restart;
 mod runtime;
 mod sentry;
@@ -23,6 +24,9 @@ fn main() -> Result<(), Error> {
 	runtime.spawn(signal::signal(server.clone()));
 	runtime.block_on(async_main(&server))?;
 
+	#[cfg(feature = "otlp_telemetry")]
+	runtime.block_on(async { otlp::shutdown() });
+
 	// explicit drop here to trace thread and tls dtors
 	drop(runtime);
 
@@ -0,0 +1,178 @@
+#![cfg(feature = "otlp_telemetry")]
+
+use std::{
+	collections::HashMap,
+	sync::{atomic::Ordering, Arc, OnceLock, Weak},
+	time::Duration,
+};
+
+use conduwuit::{config::Config, err, Result};
+use opentelemetry::{
+	global,
+	metrics::MeterProvider,
+	trace::{Link, SamplingResult, SpanKind, TraceId},
+	Context, KeyValue,
+};
+use opentelemetry_otlp::{HttpExporterBuilder, WithExportConfig};
+use opentelemetry_sdk::{
+	metrics::SdkMeterProvider,
+	propagation::TraceContextPropagator,
+	runtime,
+	trace::{Sampler, ShouldSample, Tracer},
+	Resource,
+};
+
+static METER_PROVIDER: OnceLock<SdkMeterProvider> = OnceLock::new();
+
+/// Samples root spans at the ratio configured for the longest matching module
+/// path prefix, falling back to `otlp_sampling_ratio`.
+#[derive(Clone, Debug)]
+struct ModuleSampler {
+	default: Sampler,
+	overrides: Vec<(String, Sampler)>,
+}
+
+pub(crate) fn tracer(config: &Config) -> Result<Tracer> {
+	global::set_text_map_propagator(TraceContextPropagator::new());
+
+	let sampler = ModuleSampler {
+		default: Sampler::TraceIdRatioBased(config.otlp_sampling_ratio),
+		overrides: config
+			.otlp_sampling_overrides
+			.iter()
+			.rev()
+			.map(|(prefix, ratio)| (prefix.clone(), Sampler::TraceIdRatioBased(*ratio)))
+			.collect(),
+	};
+
+	opentelemetry_otlp::new_pipeline()
+		.tracing()
+		.with_exporter(exporter(config))
+		.with_trace_config(
+			opentelemetry_sdk::trace::config()
+				.with_sampler(Sampler::ParentBased(Box::new(sampler)))
+				.with_resource(resource()),
+		)
+		.install_batch(runtime::Tokio)
+		.map_err(|e| err!(Config("otlp_endpoint", "Failed to start OTLP trace export: {e}")))
+}
+
+pub(crate) fn meter_provider(config: &Config) -> Result<SdkMeterProvider> {
+	let provider = opentelemetry_otlp::new_pipeline()
+		.metrics(runtime::Tokio)
+		.with_exporter(exporter(config))
+		.with_resource(resource())
+		.with_period(Duration::from_secs(config.otlp_metrics_interval))
+		.build()
+		.map_err(|e| err!(Config("otlp_endpoint", "Failed to start OTLP metric export: {e}")))?;
+
+	METER_PROVIDER
+		.set(provider.clone())
+		.expect("METER_PROVIDER was not previously set");
+
+	Ok(provider)
+}
+
+/// Exports the server's request counters as gauges.
+pub(crate) fn register_metrics(server: &Arc<conduwuit::Server>) {
+	let Some(provider) = METER_PROVIDER.get() else {
+		return;
+	};
+
+	let meter = provider.meter("conduwuit");
+	let gauges: [(&'static str, &'static str, fn(&conduwuit::Server) -> u64); 3] = [
+		("conduwuit.requests.active", "Requests being handled", |server| {
+			server
+				.metrics
+				.requests_handle_active
+				.load(Ordering::Relaxed)
+				.into()
+		}),
+		("conduwuit.requests.finished", "Requests handled since startup", |server| {
+			server
+				.metrics
+				.requests_handle_finished
+				.load(Ordering::Relaxed)
+				.into()
+		}),
+		(
+			"conduwuit.requests.panicked",
+			"Requests which panicked since startup",
+			|server| server.metrics.requests_panic.load(Ordering::Relaxed).into(),
+		),
+	];
+
+	for (name, description, value) in gauges {
+		let server = Arc::downgrade(server);
+		meter
+			.u64_observable_gauge(name)
+			.with_description(description)
+			.with_callback(move |observer| {
+				if let Some(server) = Weak::upgrade(&server) {
+					observer.observe(value(&server), &[]);
+				}
+			})
+			.init();
+	}
+}
+
+/// Flushes anything not yet exported.
+pub(crate) fn shutdown() {
+	global::shutdown_tracer_provider();
+	if let Some(provider) = METER_PROVIDER.get() {
+		provider.shutdown().ok();
+	}
+}
+
+fn exporter(config: &Config) -> HttpExporterBuilder {
+	let endpoint = config
+		.otlp_endpoint
+		.as_ref()
+		.expect("OTLP export should only be started if otlp_endpoint is set");
+
+	let headers: HashMap<_, _> = config
+		.otlp_headers
+		.iter()
+		.map(|(name, value)| (name.clone(), value.clone()))
+		.collect();
+
+	opentelemetry_otlp::new_exporter()
+		.http()
+		.with_endpoint(endpoint.as_str().trim_end_matches('/'))
+		.with_headers(headers)
+}
+
+fn resource() -> Resource {
+	Resource::new([
+		KeyValue::new("service.name", "conduwuit"),
+		KeyValue::new("service.version", conduwuit::version()),
+	])
+}
+
+impl ShouldSample for ModuleSampler {
+	fn should_sample(
+		&self,
+		parent_context: Option<&Context>,
+		trace_id: TraceId,
+		name: &str,
+		span_kind: &SpanKind,
+		attributes: &[KeyValue],
+		links: &[Link],
+	) -> SamplingResult {
+		let module = attributes
+			.iter()
+			.find(|attribute| attribute.key.as_str() == "code.namespace")
+			.map(|attribute| attribute.value.as_str());
+
+		// overrides are sorted so longer prefixes come first
+		let sampler = module
+			.and_then(|module| {
+				self.overrides
+					.iter()
+					.find(|(prefix, _)| module.starts_with(prefix.as_str()))
+			})
+			.map_or(&self.default, |(_, sampler)| sampler);
+
+		sampler.should_sample(parent_context, trace_id, name, span_kind, attributes, links)
+	}
+}
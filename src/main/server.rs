@@ -69,11 +69,16 @@ pub(crate) fn new(
 			conduwuit::version(),
 		);
 
+		let server = Arc::new(conduwuit::Server::new(config, runtime.cloned(), Log {
+			reload: tracing_reload_handle,
+			capture,
+		}));
+
+		#[cfg(feature = "otlp_telemetry")]
+		crate::otlp::register_metrics(&server);
+
 		Ok(Arc::new(Self {
-			server: Arc::new(conduwuit::Server::new(config, runtime.cloned(), Log {
-				reload: tracing_reload_handle,
-				capture,
-			})),
+			server,
 
 			services: None.into(),
 
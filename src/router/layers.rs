@@ -54,7 +54,7 @@ pub(crate) fn build(services: &Arc<Services>) -> Result<(Router, Guard)> {
 		.layer(SetSensitiveHeadersLayer::new([header::AUTHORIZATION]))
 		.layer(
 			TraceLayer::new_for_http()
-				.make_span_with(tracing_span::<_>)
+				.make_span_with(tracing_span::<_>(services))
 				.on_failure(DefaultOnFailure::new().level(Level::ERROR))
 				.on_request(DefaultOnRequest::new().level(Level::TRACE))
 				.on_response(DefaultOnResponse::new().level(Level::DEBUG)),
@@ -198,18 +198,32 @@ fn catch_panic(
 		.expect("Failed to create response for our panic catcher?")
 }
 
-fn tracing_span<T>(request: &http::Request<T>) -> tracing::Span {
-	let path = request
-		.extensions()
-		.get::<MatchedPath>()
-		.map_or_else(|| request_path_str(request), truncated_matched_path);
-
-	tracing::span! {
-		parent: None,
-		debug::INFO_SPAN_LEVEL,
-		"router",
-		method = %request.method(),
-		%path,
+fn tracing_span<T>(
+	services: &Arc<Services>,
+) -> impl Fn(&http::Request<T>) -> tracing::Span + Clone {
+	let services = services.clone();
+	move |request| {
+		let path = request
+			.extensions()
+			.get::<MatchedPath>()
+			.map_or_else(|| request_path_str(request), truncated_matched_path);
+
+		let span = tracing::span! {
+			parent: None,
+			debug::INFO_SPAN_LEVEL,
+			"router",
+			method = %request.method(),
+			%path,
+		};
+
+		// Federation requests from cooperating servers continue their trace
+		if request.uri().path().starts_with("/_matrix/federation/") {
+			services
+				.federation
+				.accept_trace_context(&span, request.headers());
+		}
+
+		span
 	}
 }
 
@@ -1,11 +1,11 @@
 use std::{
 	fmt::Debug,
 	sync::{atomic::Ordering, Arc},
-	time::Duration,
+	time::{Duration, Instant},
 };
 
 use axum::{
-	extract::State,
+	extract::{MatchedPath, State},
 	response::{IntoResponse, Response},
 };
 use conduwuit::{debug, debug_error, debug_warn, err, error, trace, Result};
@@ -33,6 +33,16 @@ pub(crate) async fn handle(
 
 	let uri = req.uri().clone();
 	let method = req.method().clone();
+	let route = req
+		.extensions()
+		.get::<MatchedPath>()
+		.map_or("unmatched", MatchedPath::as_str);
+
+	// Labelled by the route rather than the path, so there's one histogram per
+	// endpoint however many rooms or users it's called for. Likewise arbitrary
+	// methods share one label.
+	let route = format!("{} {route}", method_label(&method));
+	let started = Instant::now();
 	let services_ = services.clone();
 	let parent = Span::current();
 	let task = services.server.runtime().spawn(async move {
@@ -49,9 +59,18 @@ pub(crate) async fn handle(
 		}
 	});
 
-	task.await
+	let result = task
+		.await
 		.map_err(unhandled)
-		.and_then(move |result| handle_result(&method, &uri, result))
+		.and_then(move |result| handle_result(&method, &uri, result));
+
+	services
+		.server
+		.metrics
+		.request_durations
+		.observe(&route, started.elapsed());
+
+	result
 }
 
 #[tracing::instrument(
@@ -118,6 +137,20 @@ fn handle_result(method: &Method, uri: &Uri, result: Response) -> Result<Respons
 	Ok(result)
 }
 
+/// The methods we serve get their own label; anything else is "OTHER".
+fn method_label(method: &Method) -> &'static str {
+	match *method {
+		| Method::GET => "GET",
+		| Method::POST => "POST",
+		| Method::PUT => "PUT",
+		| Method::DELETE => "DELETE",
+		| Method::HEAD => "HEAD",
+		| Method::OPTIONS => "OPTIONS",
+		| Method::PATCH => "PATCH",
+		| _ => "OTHER",
+	}
+}
+
 #[cold]
 fn unhandled<Error: Debug>(e: Error) -> StatusCode {
 	error!("unhandled error or panic during request: {e:?}");
@@ -1,4 +1,5 @@
 extern crate conduwuit_admin as admin;
+extern crate conduwuit_api as api;
 extern crate conduwuit_core as conduwuit;
 extern crate conduwuit_service as service;
 
@@ -24,9 +25,32 @@ pub(crate) async fn run(services: Arc<Services>) -> Result<()> {
 	let server = &services.server;
 	debug!("Start");
 
+	// The dry run only reports the pending migrations
+	if server.config.migrations_dry_run {
+		debug_info!("Dry run finished, not serving.");
+		return Ok(());
+	}
+
 	// Install the admin room callback here for now
 	admin::init(&services.admin).await;
 
+	// Install the invite auto-accept callback
+	_ = services
+		.rooms
+		.auto_accept
+		.joiner
+		.write()
+		.expect("locked for writing")
+		.insert(api::client::auto_accept_join);
+
+	// Install the bulk deactivation room leave callback
+	_ = services
+		.deactivation
+		.leaver
+		.write()
+		.expect("locked for writing")
+		.insert(api::client::deactivation_leave);
+
 	// Setup shutdown/signal handling
 	let handle = ServerHandle::new();
 	let (tx, _) = broadcast::channel::<()>(1);
@@ -53,6 +77,23 @@ pub(crate) async fn run(services: Arc<Services>) -> Result<()> {
 	// Remove the admin room callback
 	admin::fini(&services.admin).await;
 
+	// Remove the invite auto-accept callback
+	_ = services
+		.rooms
+		.auto_accept
+		.joiner
+		.write()
+		.expect("locked for writing")
+		.take();
+
+	// Remove the bulk deactivation room leave callback
+	_ = services
+		.deactivation
+		.leaver
+		.write()
+		.expect("locked for writing")
+		.take();
+
 	debug_info!("Finish");
 	res
 }
@@ -0,0 +1,27 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum_server::{bind, Handle as ServerHandle};
+use conduwuit::{debug_info, info, Result};
+use conduwuit_service::Services;
+
+use crate::router;
+
+/// Serve the metrics on their own listener, apart from the client and
+/// federation APIs.
+pub(super) async fn serve(
+	services: &Arc<Services>,
+	handle: ServerHandle,
+	addr: SocketAddr,
+) -> Result<()> {
+	let (app, _guard) = router::build_metrics(services);
+
+	info!("Serving metrics on {addr}");
+	bind(addr)
+		.handle(handle)
+		.serve(app.into_make_service())
+		.await?;
+
+	debug_info!("Stopped serving metrics on {addr}");
+
+	Ok(())
+}
@@ -1,3 +1,4 @@
+mod metrics;
 mod plain;
 #[cfg(feature = "direct_tls")]
 mod tls;
@@ -8,6 +9,7 @@
 use axum_server::Handle as ServerHandle;
 use conduwuit::{err, Result};
 use conduwuit_service::Services;
+use futures::future::{join, OptionFuture};
 use tokio::sync::broadcast;
 
 use super::layers;
@@ -27,20 +29,31 @@ pub(super) async fn serve(
 			.map_err(|e| err!(error!("channel error: {e}")));
 	}
 
+	let metrics: OptionFuture<_> = config
+		.metrics_address
+		.map(|addr| metrics::serve(&services, handle.clone(), addr))
+		.into();
+
 	let addrs = config.get_bind_addrs();
 	let (app, _guard) = layers::build(&services)?;
-	if cfg!(unix) && config.unix_socket_path.is_some() {
-		unix::serve(server, app, shutdown).await
-	} else if config.tls.certs.is_some() {
-		#[cfg(feature = "direct_tls")]
-		return tls::serve(server, app, handle, addrs).await;
-
-		#[cfg(not(feature = "direct_tls"))]
-		return conduwuit::Err!(Config(
-			"tls",
-			"conduwuit was not built with direct TLS support (\"direct_tls\")"
-		));
-	} else {
-		plain::serve(server, app, handle, addrs).await
-	}
+	let listener = async {
+		if cfg!(unix) && config.unix_socket_path.is_some() {
+			unix::serve(server, app, shutdown).await
+		} else if config.tls.certs.is_some() {
+			#[cfg(feature = "direct_tls")]
+			return tls::serve(server, app, handle, addrs).await;
+
+			#[cfg(not(feature = "direct_tls"))]
+			return conduwuit::Err!(Config(
+				"tls",
+				"conduwuit was not built with direct TLS support (\"direct_tls\")"
+			));
+		} else {
+			plain::serve(server, app, handle, addrs).await
+		}
+	};
+
+	let (result, metrics) = join(listener, metrics).await;
+	metrics.transpose()?;
+	result
 }
@@ -1,12 +1,12 @@
 use std::sync::Arc;
 
 use conduwuit::{
-	err, implement,
-	utils::{result::LogErr, stream::TryIgnore, ReadyExt},
-	Err, Result,
+	at, err, implement,
+	utils::{result::LogErr, stream::TryIgnore, IterStream, ReadyExt},
+	Err, Result, Server,
 };
-use database::{Deserialized, Handle, Ignore, Json, Map};
-use futures::{Stream, StreamExt, TryFutureExt};
+use database::{Deserialized, Get, Handle, Json, Map, Qry};
+use futures::{FutureExt, Stream, StreamExt, TryFutureExt};
 use ruma::{
 	events::{
 		AnyGlobalAccountDataEvent, AnyRawAccountDataEvent, AnyRoomAccountDataEvent,
@@ -30,6 +30,7 @@ struct Data {
 }
 
 struct Services {
+	server: Arc<Server>,
 	globals: Dep<globals::Service>,
 }
 
@@ -37,6 +38,7 @@ impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			services: Services {
+				server: args.server.clone(),
 				globals: args.depend::<globals::Service>("globals"),
 			},
 			db: Data {
@@ -124,6 +126,117 @@ pub async fn get_raw(
 		.await
 }
 
+/// Gets several account data events of the user at once. Types the user has
+/// no account data of are skipped.
+#[implement(Service)]
+pub fn get_many<'a, I>(
+	&'a self,
+	room_id: Option<&'a RoomId>,
+	user_id: &'a UserId,
+	kinds: I,
+) -> impl Stream<Item = AnyRawAccountDataEvent> + Send + 'a
+where
+	I: Iterator<Item = &'a str> + Send + 'a,
+{
+	kinds
+		.map(move |kind| (room_id, user_id, kind))
+		.stream()
+		.qry(&self.db.roomusertype_roomuserdataid)
+		.ignore_err()
+		.get(&self.db.roomuserdataid_accountdata)
+		.ignore_err()
+		.ready_filter_map(move |event| parse_event(room_id, &event))
+}
+
+/// Returns the account data of the user whose type starts with `prefix`,
+/// ordered by type.
+#[implement(Service)]
+pub fn stream_type_prefix<'a>(
+	&'a self,
+	room_id: Option<&'a RoomId>,
+	user_id: &'a UserId,
+	prefix: &'a str,
+) -> impl Stream<Item = AnyRawAccountDataEvent> + Send + 'a {
+	let prefix = (room_id, user_id, prefix);
+	self.db
+		.roomusertype_roomuserdataid
+		.stream_prefix_raw(&prefix)
+		.ignore_err()
+		.map(at!(1))
+		.get(&self.db.roomuserdataid_accountdata)
+		.ignore_err()
+		.ready_filter_map(move |event| parse_event(room_id, &event))
+}
+
+/// Returns the account data for a sync from `since` up to `to`: what changed
+/// since then, or for an initial sync the account data trimmed as configured.
+#[implement(Service)]
+pub fn changes_for_sync<'a>(
+	&'a self,
+	room_id: Option<&'a RoomId>,
+	user_id: &'a UserId,
+	since: u64,
+	to: u64,
+) -> impl Stream<Item = AnyRawAccountDataEvent> + Send + 'a {
+	if since == 0 {
+		self.initial_sync(room_id, user_id, to)
+			.map(IterStream::stream)
+			.flatten_stream()
+			.left_stream()
+	} else {
+		self.changes_since(room_id, user_id, since, Some(to))
+			.right_stream()
+	}
+}
+
+/// Returns the account data for an initial sync up to `to`. Types matching
+/// `account_data_initial_sync_exclude` are left out, and only the most
+/// recently changed events up to `account_data_initial_sync_limit` are kept.
+#[implement(Service)]
+pub async fn initial_sync(
+	&self,
+	room_id: Option<&RoomId>,
+	user_id: &UserId,
+	to: u64,
+) -> Vec<AnyRawAccountDataEvent> {
+	let config = &self.services.server.config;
+	let exclude = &config.account_data_initial_sync_exclude;
+	let limit = config.account_data_initial_sync_limit;
+	let is_spec = |kind: &str| kind.starts_with("m.");
+
+	let events: Vec<_> = self
+		.changes(room_id, user_id, 0, Some(to))
+		.ready_filter(|(_, kind, _)| {
+			is_spec(kind)
+				|| !exclude
+					.iter()
+					.any(|prefix| kind.starts_with(prefix.as_str()))
+		})
+		.collect()
+		.await;
+
+	if limit == 0 {
+		return events.into_iter().map(at!(2)).collect();
+	}
+
+	// Changes come oldest first; count back from the newest.
+	let mut kept: usize = 0;
+	let mut events: Vec<_> = events
+		.into_iter()
+		.rev()
+		.filter(|(_, kind, _)| {
+			is_spec(kind) || {
+				kept = kept.saturating_add(1);
+				kept <= limit
+			}
+		})
+		.map(at!(2))
+		.collect();
+
+	events.reverse();
+	events
+}
+
 /// Returns all changes to the account data that happened after `since`.
 #[implement(Service)]
 pub fn changes_since<'a>(
@@ -133,7 +246,21 @@ pub fn changes_since<'a>(
 	since: u64,
 	to: Option<u64>,
 ) -> impl Stream<Item = AnyRawAccountDataEvent> + Send + 'a {
-	type Key<'a> = (Option<&'a RoomId>, &'a UserId, u64, Ignore);
+	self.changes(room_id, user_id, since, to).map(at!(2))
+}
+
+/// Returns the changes to the account data after `since`, oldest first,
+/// along with the count and type of each. The count of the last change is a
+/// cursor from which a later call picks up.
+#[implement(Service)]
+pub fn changes<'a>(
+	&'a self,
+	room_id: Option<&'a RoomId>,
+	user_id: &'a UserId,
+	since: u64,
+	to: Option<u64>,
+) -> impl Stream<Item = (u64, String, AnyRawAccountDataEvent)> + Send + 'a {
+	type Key<'a> = (Option<&'a RoomId>, &'a UserId, u64, &'a str);
 
 	// Skip the data that's exactly at since, because we sent that last time
 	let first_possible = (room_id, user_id, since.saturating_add(1));
@@ -145,15 +272,19 @@ pub fn changes_since<'a>(
 		.ready_take_while(move |((room_id_, user_id_, count, _), _): &(Key<'_>, _)| {
 			room_id == *room_id_ && user_id == *user_id_ && to.is_none_or(|to| *count <= to)
 		})
-		.map(move |(_, v)| {
-			match room_id {
-				| Some(_) => serde_json::from_slice::<Raw<AnyRoomAccountDataEvent>>(v)
-					.map(AnyRawAccountDataEvent::Room),
-				| None => serde_json::from_slice::<Raw<AnyGlobalAccountDataEvent>>(v)
-					.map(AnyRawAccountDataEvent::Global),
-			}
-			.map_err(|e| err!(Database("Database contains invalid account data: {e}")))
-			.log_err()
+		.ready_filter_map(move |((_, _, count, kind), v): (Key<'_>, &[u8])| {
+			parse_event(room_id, v).map(|event| (count, kind.to_owned(), event))
 		})
-		.ignore_err()
+}
+
+fn parse_event(room_id: Option<&RoomId>, v: &[u8]) -> Option<AnyRawAccountDataEvent> {
+	match room_id {
+		| Some(_) => serde_json::from_slice::<Raw<AnyRoomAccountDataEvent>>(v)
+			.map(AnyRawAccountDataEvent::Room),
+		| None => serde_json::from_slice::<Raw<AnyGlobalAccountDataEvent>>(v)
+			.map(AnyRawAccountDataEvent::Global),
+	}
+	.map_err(|e| err!(Database("Database contains invalid account data: {e}")))
+	.log_err()
+	.ok()
 }
@@ -11,13 +11,20 @@
 
 use async_trait::async_trait;
 use conduwuit::{
-	debug, err, error, error::default_log, pdu::PduBuilder, Error, PduEvent, Result, Server,
+	debug, err, error, error::default_log, pdu::PduBuilder, utils::IterStream, Error, PduEvent,
+	Result, Server,
 };
 pub use create::create_admin_room;
-use futures::{FutureExt, TryFutureExt};
+use futures::{FutureExt, StreamExt, TryFutureExt};
 use loole::{Receiver, Sender};
 use ruma::{
-	events::room::message::{Relation, RoomMessageEventContent},
+	events::{
+		room::{
+			message::{Relation, RoomMessageEventContent},
+			power_levels::RoomPowerLevelsEventContent,
+		},
+		StateEventType,
+	},
 	OwnedEventId, OwnedRoomId, RoomId, UserId,
 };
 use tokio::sync::RwLock;
@@ -31,6 +38,7 @@ pub struct Service {
 	pub complete: StdRwLock<Option<Completer>>,
 	#[cfg(feature = "console")]
 	pub console: Arc<console::Console>,
+	moderator_room_ids: StdRwLock<Option<Vec<OwnedRoomId>>>,
 }
 
 struct Services {
@@ -40,6 +48,7 @@ struct Services {
 	timeline: Dep<rooms::timeline::Service>,
 	state: Dep<rooms::state::Service>,
 	state_cache: Dep<rooms::state_cache::Service>,
+	state_accessor: Dep<rooms::state_accessor::Service>,
 	account_data: Dep<account_data::Service>,
 	services: StdRwLock<Option<Weak<crate::Services>>>,
 }
@@ -49,6 +58,18 @@ struct Services {
 pub struct CommandInput {
 	pub command: String,
 	pub reply_id: Option<OwnedEventId>,
+
+	/// Which commands the issuer may run.
+	pub tier: Tier,
+}
+
+/// Permission tier of an admin. Owners are the members of the admin room and
+/// may run every command; moderators are the members of the
+/// `admin_moderator_rooms` and may only run the commands marked for them.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Tier {
+	Moderator,
+	Owner,
 }
 
 /// Prototype of the tab-completer. The input is buffered text when tab
@@ -85,6 +106,8 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
 				state: args.depend::<rooms::state::Service>("rooms::state"),
 				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				state_accessor: args
+					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
 				account_data: args.depend::<account_data::Service>("account_data"),
 				services: None.into(),
 			},
@@ -93,6 +116,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			complete: StdRwLock::new(None),
 			#[cfg(feature = "console")]
 			console: console::Console::new(&args),
+			moderator_room_ids: StdRwLock::new(None),
 		}))
 	}
 
@@ -156,21 +180,26 @@ pub async fn send_message(&self, message_content: RoomMessageEventContent) -> Re
 	/// Posts a command to the command processor queue and returns. Processing
 	/// will take place on the service worker's task asynchronously. Errors if
 	/// the queue is full.
-	pub fn command(&self, command: String, reply_id: Option<OwnedEventId>) -> Result<()> {
+	pub fn command(
+		&self,
+		command: String,
+		reply_id: Option<OwnedEventId>,
+		tier: Tier,
+	) -> Result<()> {
 		self.channel
 			.0
-			.send(CommandInput { command, reply_id })
+			.send(CommandInput { command, reply_id, tier })
 			.map_err(|e| err!("Failed to enqueue admin command: {e:?}"))
 	}
 
 	/// Dispatches a comamnd to the processor on the current task and waits for
-	/// completion.
+	/// completion. The command runs with owner permissions.
 	pub async fn command_in_place(
 		&self,
 		command: String,
 		reply_id: Option<OwnedEventId>,
 	) -> ProcessorResult {
-		self.process_command(CommandInput { command, reply_id })
+		self.process_command(CommandInput { command, reply_id, tier: Tier::Owner })
 			.await
 	}
 
@@ -233,6 +262,95 @@ pub async fn user_is_admin(&self, user_id: &UserId) -> bool {
 			.await
 	}
 
+	/// Gets the permission tier of a user, if they're an admin at all. Local
+	/// users are moderators when joined to a moderator room with a power
+	/// level given to them explicitly, above the room's default.
+	pub async fn user_tier(&self, user_id: &UserId) -> Option<Tier> {
+		if self.user_is_admin(user_id).await {
+			return Some(Tier::Owner);
+		}
+
+		if !self.services.globals.user_is_local(user_id) {
+			return None;
+		}
+
+		self.get_moderator_rooms()
+			.await
+			.iter()
+			.stream()
+			.any(|room_id| async move {
+				self.services.state_cache.is_joined(user_id, room_id).await
+					&& self.has_explicit_power(room_id, user_id).await
+			})
+			.await
+			.then_some(Tier::Moderator)
+	}
+
+	async fn has_explicit_power(&self, room_id: &RoomId, user_id: &UserId) -> bool {
+		self.services
+			.state_accessor
+			.room_state_get_content::<RoomPowerLevelsEventContent>(
+				room_id,
+				&StateEventType::RoomPowerLevels,
+				"",
+			)
+			.await
+			.is_ok_and(|content| {
+				content
+					.users
+					.get(user_id)
+					.is_some_and(|level| *level > content.users_default)
+			})
+	}
+
+	/// Gets the room IDs of the `admin_moderator_rooms` the server user is
+	/// joined to.
+	pub async fn get_moderator_rooms(&self) -> Vec<OwnedRoomId> {
+		let server_user = &self.services.globals.server_user;
+		self.moderator_room_ids()
+			.await
+			.into_iter()
+			.stream()
+			.filter_map(|room_id| async move {
+				self.services
+					.state_cache
+					.is_joined(server_user, &room_id)
+					.await
+					.then_some(room_id)
+			})
+			.collect()
+			.await
+	}
+
+	/// The room IDs the `admin_moderator_rooms` resolve to. They're kept once
+	/// all of them resolved, rather than resolving aliases for every message;
+	/// changing where an alias points takes a restart.
+	async fn moderator_room_ids(&self) -> Vec<OwnedRoomId> {
+		if let Some(room_ids) = self
+			.moderator_room_ids
+			.read()
+			.expect("locked for reading")
+			.clone()
+		{
+			return room_ids;
+		}
+
+		let rooms = &self.services.server.config.admin_moderator_rooms;
+		let room_ids: Vec<OwnedRoomId> = rooms
+			.iter()
+			.stream()
+			.filter_map(|room| self.services.alias.resolve(room).map(Result::ok))
+			.collect()
+			.await;
+
+		if room_ids.len() == rooms.len() {
+			*self.moderator_room_ids.write().expect("locked for writing") =
+				Some(room_ids.clone());
+		}
+
+		room_ids
+	}
+
 	/// Gets the room ID of the admin room
 	///
 	/// Errors are propagated from the database, and will have None if there is
@@ -265,7 +383,7 @@ async fn handle_response(&self, content: RoomMessageEventContent) -> Result<()>
 			return Ok(());
 		};
 
-		let response_sender = if self.is_admin_room(&pdu.room_id).await {
+		let response_sender = if self.is_any_admin_room(&pdu.room_id).await {
 			&self.services.globals.server_user
 		} else {
 			&pdu.sender
@@ -321,7 +439,9 @@ async fn handle_response_error(
 		Ok(())
 	}
 
-	pub async fn is_admin_command(&self, pdu: &PduEvent, body: &str) -> bool {
+	/// Whether the message is an admin command to run, returning the permission
+	/// tier of its sender if so.
+	pub async fn command_tier(&self, pdu: &PduEvent, body: &str) -> Option<Tier> {
 		// Server-side command-escape with public echo
 		let is_escape = body.starts_with('\\');
 		let is_public_escape = is_escape && body.trim_start_matches('\\').starts_with("!admin");
@@ -333,39 +453,37 @@ pub async fn is_admin_command(&self, pdu: &PduEvent, body: &str) -> bool {
 
 		// Expected backward branch
 		if !is_public_escape && !is_public_prefix {
-			return false;
+			return None;
 		}
 
 		// only allow public escaped commands by local admins
 		if is_public_escape && !self.services.globals.user_is_local(&pdu.sender) {
-			return false;
+			return None;
 		}
 
 		// Check if server-side command-escape is disabled by configuration
 		if is_public_escape && !self.services.server.config.admin_escape_commands {
-			return false;
+			return None;
 		}
 
-		// Prevent unescaped !admin from being used outside of the admin room
-		if is_public_prefix && !self.is_admin_room(&pdu.room_id).await {
-			return false;
+		// Prevent unescaped !admin from being used outside of the admin rooms
+		if is_public_prefix && !self.is_any_admin_room(&pdu.room_id).await {
+			return None;
 		}
 
 		// Only senders who are admin can proceed
-		if !self.user_is_admin(&pdu.sender).await {
-			return false;
-		}
+		let tier = self.user_tier(&pdu.sender).await?;
 
 		// This will evaluate to false if the emergency password is set up so that
 		// the administrator can execute commands as conduit
 		let emergency_password_set = self.services.globals.emergency_password().is_some();
 		let from_server = pdu.sender == *server_user && !emergency_password_set;
-		if from_server && self.is_admin_room(&pdu.room_id).await {
-			return false;
+		if from_server && self.is_any_admin_room(&pdu.room_id).await {
+			return None;
 		}
 
 		// Authentic admin command
-		true
+		Some(tier)
 	}
 
 	#[must_use]
@@ -376,6 +494,16 @@ pub async fn is_admin_room(&self, room_id_: &RoomId) -> bool {
 			.unwrap_or(false)
 	}
 
+	/// Whether the room is the admin room or one of the moderator rooms.
+	pub async fn is_any_admin_room(&self, room_id: &RoomId) -> bool {
+		self.is_admin_room(room_id).await
+			|| self
+				.get_moderator_rooms()
+				.await
+				.iter()
+				.any(|moderator_room| moderator_room == room_id)
+	}
+
 	/// Sets the self-reference to crate::Services which will provide context to
 	/// the admin commands.
 	pub(super) fn set_services(&self, services: Option<&Arc<crate::Services>>) {
@@ -0,0 +1,146 @@
+use conduwuit::{implement, utils::stream::ReadyExt};
+use futures::StreamExt;
+use ruma::{
+	api::appservice::{Namespace, Registration},
+	OwnedRoomAliasId, OwnedUserId, RoomAliasId, UserId,
+};
+
+use super::RegistrationInfo;
+
+/// What adding or replacing a registration would run into.
+#[derive(Debug, Default)]
+pub struct ConflictReport {
+	/// Reasons the registration can't be added.
+	pub errors: Vec<String>,
+
+	/// Other appservices claiming the same users, aliases or rooms
+	/// exclusively; the registration can't be added while they exist.
+	pub appservices: Vec<String>,
+
+	/// Existing users in the exclusive user namespaces, which the appservice
+	/// would take over.
+	pub users: Vec<OwnedUserId>,
+
+	/// Existing local aliases in the exclusive alias namespaces, which the
+	/// appservice would take over.
+	pub aliases: Vec<OwnedRoomAliasId>,
+}
+
+impl ConflictReport {
+	/// Whether the registration can be added.
+	#[must_use]
+	pub fn is_acceptable(&self) -> bool { self.errors.is_empty() && self.appservices.is_empty() }
+}
+
+/// Checks a registration against the other appservices and the existing
+/// users and aliases. A registration with the same ID is the one being
+/// replaced and is not checked against.
+#[implement(super::Service)]
+pub async fn check_registration(&self, registration: &Registration) -> ConflictReport {
+	let mut report = ConflictReport::default();
+	let server_name = self.services.globals.server_name();
+
+	if registration.id.is_empty() {
+		report.errors.push("The ID is empty.".to_owned());
+	}
+
+	if registration.as_token.is_empty() || registration.hs_token.is_empty() {
+		report
+			.errors
+			.push("The as_token and hs_token must be set.".to_owned());
+	} else if registration.as_token == registration.hs_token {
+		report
+			.errors
+			.push("The as_token and hs_token must differ.".to_owned());
+	}
+
+	let sender =
+		UserId::parse_with_server_name(registration.sender_localpart.as_str(), server_name).ok();
+	if sender.is_none() {
+		report.errors.push(format!(
+			"The sender_localpart {:?} is not a valid localpart.",
+			registration.sender_localpart
+		));
+	}
+
+	let info = match RegistrationInfo::try_from(registration.clone()) {
+		| Ok(info) => info,
+		| Err(e) => {
+			report.errors.push(format!("Invalid namespace regex: {e}"));
+			return report;
+		},
+	};
+
+	for (id, other) in self.read().await.iter() {
+		if *id == registration.id {
+			continue;
+		}
+
+		if other.registration.as_token == registration.as_token {
+			report
+				.errors
+				.push(format!("The as_token is already used by appservice {id}."));
+		}
+
+		let other_sender = UserId::parse_with_server_name(
+			other.registration.sender_localpart.as_str(),
+			server_name,
+		)
+		.ok();
+
+		let sender_taken = sender
+			.as_deref()
+			.is_some_and(|sender| other.is_exclusive_user_match(sender));
+		let other_sender_taken = other_sender
+			.as_deref()
+			.is_some_and(|other_sender| info.is_exclusive_user_match(other_sender));
+
+		let (ours, theirs) = (&registration.namespaces, &other.registration.namespaces);
+		let shared = shares_exclusive(&ours.users, &theirs.users)
+			|| shares_exclusive(&ours.aliases, &theirs.aliases)
+			|| shares_exclusive(&ours.rooms, &theirs.rooms);
+
+		if sender_taken || other_sender_taken || shared {
+			report.appservices.push(id.clone());
+		}
+	}
+
+	if info.users.exclusive.is_some() {
+		report.users = self
+			.services
+			.users
+			.stream()
+			.ready_filter(|user_id| {
+				user_id.server_name() == server_name
+					&& user_id.localpart() != registration.sender_localpart
+					&& info.users.is_exclusive_match(user_id.as_str())
+			})
+			.map(ToOwned::to_owned)
+			.collect()
+			.await;
+	}
+
+	if info.aliases.exclusive.is_some() {
+		report.aliases = self
+			.services
+			.alias
+			.all_local_aliases()
+			.ready_filter_map(|(_, localpart)| {
+				RoomAliasId::parse(format!("#{localpart}:{server_name}")).ok()
+			})
+			.ready_filter(|alias| info.aliases.is_exclusive_match(alias.as_str()))
+			.collect()
+			.await;
+	}
+
+	report
+}
+
+/// Whether both namespace lists claim an identical regex exclusively. Regexes
+/// can't be compared in general, so overlapping but different ones aren't
+/// caught.
+fn shares_exclusive(a: &[Namespace], b: &[Namespace]) -> bool {
+	a.iter()
+		.filter(|a| a.exclusive)
+		.any(|a| b.iter().any(|b| b.exclusive && a.regex == b.regex))
+}
@@ -1,17 +1,22 @@
+mod conflicts;
 mod namespace_regex;
+mod puppets;
 mod registration_info;
 
 use std::{collections::BTreeMap, sync::Arc};
 
 use async_trait::async_trait;
-use conduwuit::{err, utils::stream::TryIgnore, Result};
+use conduwuit::{err, utils::stream::TryIgnore, Err, Result};
 use database::Map;
 use futures::{Future, StreamExt, TryStreamExt};
 use ruma::{api::appservice::Registration, RoomAliasId, RoomId, UserId};
 use tokio::sync::RwLock;
 
-pub use self::{namespace_regex::NamespaceRegex, registration_info::RegistrationInfo};
-use crate::{sending, Dep};
+pub use self::{
+	conflicts::ConflictReport, namespace_regex::NamespaceRegex,
+	registration_info::RegistrationInfo,
+};
+use crate::{deactivation, globals, rooms, sending, users, Dep};
 
 pub struct Service {
 	registration_info: RwLock<BTreeMap<String, RegistrationInfo>>,
@@ -20,7 +25,11 @@ pub struct Service {
 }
 
 struct Services {
+	alias: Dep<rooms::alias::Service>,
+	deactivation: Dep<deactivation::Service>,
+	globals: Dep<globals::Service>,
 	sending: Dep<sending::Service>,
+	users: Dep<users::Service>,
 }
 
 struct Data {
@@ -33,7 +42,11 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			registration_info: RwLock::new(BTreeMap::new()),
 			services: Services {
+				alias: args.depend::<rooms::alias::Service>("rooms::alias"),
+				deactivation: args.depend::<deactivation::Service>("deactivation"),
+				globals: args.depend::<globals::Service>("globals"),
 				sending: args.depend::<sending::Service>("sending"),
+				users: args.depend::<users::Service>("users"),
 			},
 			db: Data {
 				id_appserviceregistrations: args.db["id_appserviceregistrations"].clone(),
@@ -43,14 +56,13 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 
 	async fn worker(self: Arc<Self>) -> Result<()> {
 		// Inserting registrations into cache
-		for appservice in self.iter_db_ids().await? {
-			self.registration_info.write().await.insert(
-				appservice.0,
-				appservice
-					.1
-					.try_into()
-					.expect("Should be validated on registration"),
-			);
+		for (id, registration) in self.iter_db_ids().await? {
+			let body = self.db.id_appserviceregistrations.get(&id).await?;
+			let info = RegistrationInfo::try_from(registration)
+				.expect("Should be validated on registration")
+				.with_unstable_features(&body);
+
+			self.registration_info.write().await.insert(id, info);
 		}
 
 		Ok(())
@@ -60,17 +72,32 @@ fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
 impl Service {
-	/// Registers an appservice and returns the ID to the caller
+	/// Registers an appservice, replacing the one with the same ID. Fails if
+	/// `check_registration` finds errors or conflicting appservices.
 	pub async fn register_appservice(
 		&self,
 		registration: &Registration,
 		appservice_config_body: &str,
 	) -> Result {
-		//TODO: Check for collisions between exclusive appservice namespaces
+		let report = self.check_registration(registration).await;
+		if let Some(error) = report.errors.first() {
+			return Err!(Request(InvalidParam("{error}")));
+		}
+
+		if !report.appservices.is_empty() {
+			let conflicts = report.appservices.join(", ");
+			return Err!(Request(Exclusive(
+				"Exclusive namespaces conflict with appservices: {conflicts}"
+			)));
+		}
+
+		let info = RegistrationInfo::try_from(registration.clone())?
+			.with_unstable_features(appservice_config_body.as_bytes());
+
 		self.registration_info
 			.write()
 			.await
-			.insert(registration.id.clone(), registration.clone().try_into()?);
+			.insert(registration.id.clone(), info);
 
 		self.db
 			.id_appserviceregistrations
@@ -137,6 +164,21 @@ pub async fn is_exclusive_user_id(&self, user_id: &UserId) -> bool {
 			.any(|info| info.is_exclusive_user_match(user_id))
 	}
 
+	/// Checks if a user's requests are exempt from rate limits: appservice
+	/// senders always are, and users in exclusive namespaces unless the
+	/// registration sets `rate_limited`.
+	pub async fn is_rate_limit_exempt(&self, user_id: &UserId) -> bool {
+		if !self.services.globals.user_is_local(user_id) {
+			return false;
+		}
+
+		self.read().await.values().any(|info| {
+			info.registration.sender_localpart == user_id.localpart()
+				|| (info.registration.rate_limited != Some(true)
+					&& info.users.is_exclusive_match(user_id.as_str()))
+		})
+	}
+
 	/// Checks if a given room alias matches any exclusive appservice regex
 	pub async fn is_exclusive_alias(&self, alias: &RoomAliasId) -> bool {
 		self.read()
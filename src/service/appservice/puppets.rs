@@ -0,0 +1,73 @@
+use conduwuit::{implement, utils::stream::ReadyExt, Err, Result};
+use futures::StreamExt;
+use ruma::OwnedUserId;
+
+use crate::deactivation::DeactivateOptions;
+
+/// Lists the local users in the appservice's exclusive user namespaces, i.e.
+/// its puppets or ghosts, without its sender user. Admins are never included,
+/// so they can't be deactivated by decommissioning a bridge.
+#[implement(super::Service)]
+pub async fn namespace_users(&self, appservice_id: &str) -> Result<Vec<OwnedUserId>> {
+	let Some(info) = self.read().await.get(appservice_id).cloned() else {
+		return Err!(Request(NotFound("Appservice {appservice_id} does not exist.")));
+	};
+
+	let server_name = self.services.globals.server_name();
+	let users: Vec<OwnedUserId> = self
+		.services
+		.users
+		.stream()
+		.ready_filter(|user_id| {
+			user_id.server_name() == server_name
+				&& user_id.localpart() != info.registration.sender_localpart
+				&& info.users.is_exclusive_match(user_id.as_str())
+		})
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let mut puppets = Vec::with_capacity(users.len());
+	for user_id in users {
+		if !self.services.users.is_admin(&user_id).await {
+			puppets.push(user_id);
+		}
+	}
+
+	Ok(puppets)
+}
+
+/// Queues deactivation of all of the appservice's puppets in the background.
+/// They always leave their rooms and reject their invites, since nothing is
+/// left to act for them. Returns how many were queued.
+#[implement(super::Service)]
+pub async fn deactivate_namespace_users(
+	&self,
+	appservice_id: &str,
+	options: DeactivateOptions,
+) -> Result<usize> {
+	let users = self.namespace_users(appservice_id).await?;
+	let count = users.len();
+	let options = DeactivateOptions {
+		reject_invites: true,
+		leave_rooms: true,
+		..options
+	};
+
+	self.services.deactivation.deactivate_bulk(users, options)?;
+
+	Ok(count)
+}
+
+/// Queues all of the appservice's puppets to leave their rooms and reject
+/// their invites in the background, keeping the accounts. Returns how many
+/// were queued.
+#[implement(super::Service)]
+pub async fn leave_namespace_users(&self, appservice_id: &str) -> Result<usize> {
+	let users = self.namespace_users(appservice_id).await?;
+	let count = users.len();
+
+	self.services.deactivation.leave_bulk(users)?;
+
+	Ok(count)
+}
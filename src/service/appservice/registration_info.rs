@@ -1,5 +1,6 @@
 use conduwuit::Result;
 use ruma::{api::appservice::Registration, UserId};
+use serde::Deserialize;
 
 use super::NamespaceRegex;
 
@@ -10,6 +11,25 @@ pub struct RegistrationInfo {
 	pub users: NamespaceRegex,
 	pub aliases: NamespaceRegex,
 	pub rooms: NamespaceRegex,
+
+	/// Whether typing notifications, read receipts and presence are pushed to
+	/// the appservice; set by `receive_ephemeral` or its MSC2409 unstable key.
+	pub receive_ephemeral: bool,
+
+	/// Whether device list changes and one-time key counts of the
+	/// appservice's users are pushed to it (MSC3202).
+	pub device_masquerading: bool,
+}
+
+/// Registration keys of unstable features, which `Registration` doesn't
+/// know about.
+#[derive(Debug, Default, Deserialize)]
+struct UnstableRegistration {
+	#[serde(default, rename = "de.sorunome.msc2409.push_ephemeral")]
+	push_ephemeral: bool,
+
+	#[serde(default, rename = "org.matrix.msc3202")]
+	device_masquerading: bool,
 }
 
 impl RegistrationInfo {
@@ -25,6 +45,17 @@ pub fn is_exclusive_user_match(&self, user_id: &UserId) -> bool {
 		self.users.is_exclusive_match(user_id.as_str())
 			|| self.registration.sender_localpart == user_id.localpart()
 	}
+
+	/// Enables the unstable features requested in the registration file.
+	#[must_use]
+	pub fn with_unstable_features(mut self, registration_body: &[u8]) -> Self {
+		let unstable: UnstableRegistration =
+			serde_yaml::from_slice(registration_body).unwrap_or_default();
+
+		self.receive_ephemeral |= unstable.push_ephemeral;
+		self.device_masquerading = unstable.device_masquerading;
+		self
+	}
 }
 
 impl TryFrom<Registration> for RegistrationInfo {
@@ -35,6 +66,8 @@ fn try_from(value: Registration) -> Result<Self, regex::Error> {
 			users: value.namespaces.users.clone().try_into()?,
 			aliases: value.namespaces.aliases.clone().try_into()?,
 			rooms: value.namespaces.rooms.clone().try_into()?,
+			receive_ephemeral: value.receive_ephemeral,
+			device_masquerading: false,
 			registration: value,
 		})
 	}
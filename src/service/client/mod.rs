@@ -4,6 +4,7 @@
 use either::Either;
 use ipaddress::IPAddress;
 use reqwest::redirect;
+use url::{Host, Url};
 
 use crate::{resolver, service};
 
@@ -36,6 +37,14 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			.clone()
 			.and_then(Either::right);
 
+		let cidr_range_denylist: Vec<IPAddress> = config
+			.ip_range_denylist
+			.iter()
+			.map(IPAddress::parse)
+			.inspect(|cidr| trace!("Denied CIDR range: {cidr:?}"))
+			.collect::<Result<_, String>>()
+			.map_err(|e| err!(Config("ip_range_denylist", e)))?;
+
 		Ok(Arc::new(Self {
 			default: base(config)?
 				.dns_resolver(resolver.resolver.clone())
@@ -46,8 +55,8 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 					builder_interface(builder, url_preview_bind_iface.as_deref())
 				})?
 				.local_address(url_preview_bind_addr)
-				.dns_resolver(resolver.resolver.clone())
-				.redirect(redirect::Policy::limited(3))
+				.dns_resolver(resolver.resolver.filtered(cidr_range_denylist.clone()))
+				.redirect(url_preview_redirects(cidr_range_denylist.clone()))
 				.build()?,
 
 			extern_media: base(config)?
@@ -105,19 +114,27 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				.redirect(redirect::Policy::limited(2))
 				.build()?,
 
-			cidr_range_denylist: config
-				.ip_range_denylist
-				.iter()
-				.map(IPAddress::parse)
-				.inspect(|cidr| trace!("Denied CIDR range: {cidr:?}"))
-				.collect::<Result<_, String>>()
-				.map_err(|e| err!(Config("ip_range_denylist", e)))?,
+			cidr_range_denylist,
 		}))
 	}
 
 	fn name(&self) -> &str { service::make_name(std::module_path!()) }
 }
 
+/// Follows up to three redirects, none of them to a denied IP address given
+/// directly in the URL; names are left to the filtering resolver.
+fn url_preview_redirects(denylist: Vec<IPAddress>) -> redirect::Policy {
+	redirect::Policy::custom(move |attempt| {
+		if attempt.previous().len() >= 3 {
+			attempt.error("too many redirects")
+		} else if !valid_host(&denylist, attempt.url()) {
+			attempt.error("redirect to a forbidden address")
+		} else {
+			attempt.follow()
+		}
+	})
+}
+
 fn base(config: &Config) -> Result<reqwest::ClientBuilder> {
 	let mut builder = reqwest::Client::builder()
 		.hickory_dns(true)
@@ -213,3 +230,20 @@ pub fn valid_cidr_range(&self, ip: &IPAddress) -> bool {
 		.iter()
 		.all(|cidr| !cidr.includes(ip))
 }
+
+/// Whether the URL doesn't name a denied IP address as its host. Requests to
+/// such URLs never reach the resolver, which filters the addresses of names.
+#[inline]
+#[must_use]
+#[implement(Service)]
+pub fn valid_url_host(&self, url: &Url) -> bool { valid_host(&self.cidr_range_denylist, url) }
+
+fn valid_host(denylist: &[IPAddress], url: &Url) -> bool {
+	let ip = match url.host() {
+		| Some(Host::Ipv4(ip)) => ip.to_string(),
+		| Some(Host::Ipv6(ip)) => ip.to_string(),
+		| Some(Host::Domain(_)) | None => return true,
+	};
+
+	IPAddress::parse(ip).is_ok_and(|ip| denylist.iter().all(|cidr| !cidr.includes(&ip)))
+}
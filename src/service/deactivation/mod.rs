@@ -0,0 +1,289 @@
+use std::{
+	future::Future,
+	pin::Pin,
+	sync::{Arc, RwLock as StdRwLock, Weak},
+	time::Duration,
+};
+
+use async_trait::async_trait;
+use conduwuit::{debug_warn, err, implement, info, utils::ReadyExt, Result, Server};
+use futures::StreamExt;
+use loole::{Receiver, Sender};
+use ruma::{api::client::push::set_pusher::v3::PusherAction, OwnedRoomId, OwnedUserId, UserId};
+use tokio::time::sleep;
+
+use crate::{admin, email, pusher, rooms, users, Dep};
+
+pub struct Service {
+	job_channel: (Sender<BulkDeactivation>, Receiver<BulkDeactivation>),
+	pub leaver: StdRwLock<Option<Leaver>>,
+	services: Services,
+}
+
+struct Services {
+	server: Arc<Server>,
+	admin: Dep<admin::Service>,
+	email: Dep<email::Service>,
+	pusher: Dep<pusher::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	users: Dep<users::Service>,
+	services: StdRwLock<Option<Weak<crate::Services>>>,
+}
+
+/// What to clean up besides the account itself. Deactivation always logs out
+/// every device, which revokes all of the user's access tokens.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeactivateOptions {
+	/// Clear the display name, avatar and other profile fields.
+	pub erase: bool,
+
+	/// Reject all invites the user has not answered.
+	pub reject_invites: bool,
+
+	/// Leave all joined rooms.
+	pub leave_rooms: bool,
+
+	/// Delete all of the user's pushers.
+	pub remove_pushers: bool,
+}
+
+/// A list of users to deactivate in the background.
+#[derive(Clone, Debug)]
+pub struct BulkDeactivation {
+	pub user_ids: Vec<OwnedUserId>,
+	pub options: DeactivateOptions,
+
+	/// Only leave the rooms and reject the invites, keeping the accounts.
+	pub leave_only: bool,
+}
+
+/// Prototype of the leave callback. Leaving rooms we are no longer resident in
+/// takes the federation leave flow, which lives in the reloadable api module.
+pub type Leaver = fn(Arc<crate::Services>, OwnedUserId, OwnedRoomId) -> LeaverFuture;
+
+/// Return type of the leaver
+pub type LeaverFuture = Pin<Box<dyn Future<Output = Result> + Send>>;
+
+/// How many users are deactivated between progress reports.
+const PROGRESS_INTERVAL: usize = 50;
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			job_channel: loole::unbounded(),
+			leaver: StdRwLock::new(None),
+			services: Services {
+				server: args.server.clone(),
+				admin: args.depend::<admin::Service>("admin"),
+				email: args.depend::<email::Service>("email"),
+				pusher: args.depend::<pusher::Service>("pusher"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				users: args.depend::<users::Service>("users"),
+				services: None.into(),
+			},
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		let receiver = self.job_channel.1.clone();
+		while let Ok(job) = receiver.recv_async().await {
+			self.run_job(job).await;
+		}
+
+		Ok(())
+	}
+
+	fn interrupt(&self) {
+		let (sender, _) = &self.job_channel;
+		if !sender.is_closed() {
+			sender.close();
+		}
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Queues a list of users to be deactivated in the background at the
+/// configured pace, with progress reported to the admin room. The caller is
+/// responsible for checking which users may be deactivated.
+#[implement(Service)]
+pub fn deactivate_bulk(&self, user_ids: Vec<OwnedUserId>, options: DeactivateOptions) -> Result {
+	self.job_channel
+		.0
+		.send(BulkDeactivation { user_ids, options, leave_only: false })
+		.map_err(|e| err!("Failed to queue bulk deactivation: {e}"))
+}
+
+/// Queues a list of users to leave all of their rooms and reject their
+/// invites in the background, like `deactivate_bulk` but keeping the
+/// accounts.
+#[implement(Service)]
+pub fn leave_bulk(&self, user_ids: Vec<OwnedUserId>) -> Result {
+	let options = DeactivateOptions {
+		reject_invites: true,
+		leave_rooms: true,
+		..DeactivateOptions::default()
+	};
+
+	self.job_channel
+		.0
+		.send(BulkDeactivation { user_ids, options, leave_only: true })
+		.map_err(|e| err!("Failed to queue bulk leave: {e}"))
+}
+
+#[implement(Service)]
+async fn run_job(&self, job: BulkDeactivation) {
+	let BulkDeactivation { user_ids, options, leave_only } = job;
+	let total = user_ids.len();
+	let interval =
+		Duration::from_millis(self.services.server.config.bulk_deactivation_interval_ms);
+	let (job_name, done_name) = if leave_only {
+		("Bulk leave", "removed from their rooms")
+	} else {
+		("Bulk deactivation", "deactivated")
+	};
+
+	info!("{job_name} of {total} users started in the background");
+	let (mut deactivated, mut failed) = (0_usize, 0_usize);
+	for (i, user_id) in user_ids.iter().enumerate() {
+		if !self.services.server.running() {
+			break;
+		}
+
+		let result = if leave_only {
+			self.leave_all(user_id, options).await;
+			Ok(())
+		} else {
+			self.deactivate(user_id, options).await
+		};
+
+		match result {
+			| Ok(()) => deactivated = deactivated.saturating_add(1),
+			| Err(e) => {
+				debug_warn!(%user_id, "Failed to deactivate user: {e}");
+				self.services
+					.admin
+					.send_text(&format!("Failed to deactivate {user_id}: {e}"))
+					.await;
+				failed = failed.saturating_add(1);
+			},
+		}
+
+		let done = i.saturating_add(1);
+		if done % PROGRESS_INTERVAL == 0 && done < total {
+			self.services
+				.admin
+				.send_text(&format!("{job_name} progress: {done}/{total} users"))
+				.await;
+		}
+
+		sleep(interval).await;
+	}
+
+	self.services
+		.admin
+		.send_text(&format!(
+			"{job_name} finished: {done_name} {deactivated} of {total} users, {failed} failed."
+		))
+		.await;
+}
+
+#[implement(Service)]
+async fn deactivate(&self, user_id: &UserId, options: DeactivateOptions) -> Result {
+	self.services.users.deactivate_account(user_id).await?;
+	self.services.email.unbind_all(user_id).await;
+
+	if options.erase {
+		self.services.users.set_displayname(user_id, None);
+		self.services.users.set_avatar_url(user_id, None);
+		self.services.users.set_blurhash(user_id, None);
+		self.services
+			.users
+			.all_profile_keys(user_id)
+			.ready_for_each(|(profile_key, _)| {
+				self.services
+					.users
+					.set_profile_key(user_id, &profile_key, None);
+			})
+			.await;
+	}
+
+	if options.remove_pushers {
+		for pusher in self.services.pusher.get_pushers(user_id).await {
+			self.services
+				.pusher
+				.set_pusher(user_id, &PusherAction::Delete(pusher.ids))
+				.await?;
+		}
+	}
+
+	self.leave_all(user_id, options).await;
+
+	Ok(())
+}
+
+#[implement(Service)]
+async fn leave_all(&self, user_id: &UserId, options: DeactivateOptions) {
+	let mut rooms: Vec<OwnedRoomId> = Vec::new();
+	if options.leave_rooms {
+		let joined: Vec<_> = self
+			.services
+			.state_cache
+			.rooms_joined(user_id)
+			.map(ToOwned::to_owned)
+			.collect()
+			.await;
+
+		rooms.extend(joined);
+	}
+
+	if options.reject_invites {
+		let invited: Vec<_> = self
+			.services
+			.state_cache
+			.rooms_invited(user_id)
+			.map(|(room_id, _)| room_id)
+			.collect()
+			.await;
+
+		rooms.extend(invited);
+	}
+
+	for room_id in rooms {
+		self.leave(user_id, room_id).await;
+	}
+}
+
+#[implement(Service)]
+async fn leave(&self, user_id: &UserId, room_id: OwnedRoomId) {
+	let Some(leaver) = *self.leaver.read().expect("locked for reading") else {
+		debug_warn!("No leaver installed; not leaving {room_id}");
+		return;
+	};
+
+	let Some(services) = self
+		.services
+		.services
+		.read()
+		.expect("locked for reading")
+		.clone()
+		.and_then(|weak| weak.upgrade())
+	else {
+		return;
+	};
+
+	if let Err(e) = leaver(services, user_id.to_owned(), room_id.clone()).await {
+		debug_warn!(%user_id, %room_id, "Failed to leave room: {e}");
+	}
+
+	self.services.state_cache.forget(&room_id, user_id);
+}
+
+/// Sets the self-reference to crate::Services which is passed to the leaver.
+#[implement(Service)]
+pub(crate) fn set_services(&self, services: Option<&Arc<crate::Services>>) {
+	let receiver = &mut *self.services.services.write().expect("locked for writing");
+	let weak = services.map(Arc::downgrade);
+	*receiver = weak;
+}
@@ -0,0 +1,326 @@
+mod tests;
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use conduwuit::{
+	debug, debug_warn, err, implement,
+	result::LogErr,
+	utils::{millis_since_unix_epoch, random_string, stream::TryIgnore},
+	Err, Error, PduBuilder, Result, Server,
+};
+use database::{Deserialized, Ignore, Interfix, Json, Map};
+use futures::{stream::FuturesUnordered, Stream, StreamExt};
+use loole::{Receiver, Sender};
+use ruma::{
+	api::client::error::ErrorKind, events::TimelineEventType, OwnedRoomId, OwnedUserId, RoomId,
+	UserId,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue as RawJsonValue;
+use tokio::time::sleep;
+
+use crate::{rooms, Dep};
+
+pub struct Service {
+	timer_channel: (Sender<TimerType>, Receiver<TimerType>),
+	services: Services,
+	db: Data,
+}
+
+struct Services {
+	server: Arc<Server>,
+	state: Dep<rooms::state::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	timeline: Dep<rooms::timeline::Service>,
+}
+
+struct Data {
+	userdelayid_delayedevent: Arc<Map>,
+}
+
+type TimerType = (OwnedUserId, String, Duration);
+
+/// An event staged by a client to be sent after a delay (MSC4140).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DelayedEvent {
+	pub delay_id: String,
+	pub room_id: OwnedRoomId,
+
+	#[serde(rename = "type")]
+	pub event_type: TimelineEventType,
+
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub state_key: Option<String>,
+
+	/// Milliseconds after `running_since` at which the event is sent.
+	pub delay: u64,
+
+	/// When the delay was last started or restarted, in milliseconds since
+	/// the unix epoch.
+	pub running_since: u64,
+
+	pub content: Box<RawJsonValue>,
+}
+
+/// What a client can do with one of its pending delayed events.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DelayedEventAction {
+	/// Drop the event without sending it.
+	Cancel,
+
+	/// Start the delay over from now.
+	Restart,
+
+	/// Send the event immediately.
+	Send,
+}
+
+const DELAY_ID_LENGTH: usize = 16;
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			timer_channel: loole::unbounded(),
+			services: Services {
+				server: args.server.clone(),
+				state: args.depend::<rooms::state::Service>("rooms::state"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
+			},
+			db: Data {
+				userdelayid_delayedevent: args.db["userdelayid_delayedevent"].clone(),
+			},
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		// Re-arm the timers of everything which was pending when we shut down;
+		// anything already overdue fires right away.
+		let pending: Vec<_> = self
+			.db
+			.userdelayid_delayedevent
+			.stream()
+			.ignore_err()
+			.map(|((user_id, _), event): ((&UserId, Ignore), DelayedEvent)| {
+				(user_id.to_owned(), event.delay_id.clone(), remaining(&event))
+			})
+			.collect()
+			.await;
+
+		let receiver = self.timer_channel.1.clone();
+		let mut timers: FuturesUnordered<_> =
+			pending.into_iter().map(delayed_event_timer).collect();
+
+		while !receiver.is_closed() {
+			tokio::select! {
+				Some((user_id, delay_id)) = timers.next() => {
+					self.process_timer(&user_id, &delay_id).await.log_err().ok();
+				},
+				timer = receiver.recv_async() => match timer {
+					| Err(_) => break,
+					| Ok(timer) => timers.push(delayed_event_timer(timer)),
+				},
+			}
+		}
+
+		Ok(())
+	}
+
+	fn interrupt(&self) {
+		let (timer_sender, _) = &self.timer_channel;
+		if !timer_sender.is_closed() {
+			timer_sender.close();
+		}
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Stages an event to be sent into a room by `user_id` after `delay`. Returns
+/// the delay id the client uses to manage it.
+#[implement(Service)]
+pub async fn schedule(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+	event_type: TimelineEventType,
+	state_key: Option<String>,
+	content: Box<RawJsonValue>,
+	delay: Duration,
+) -> Result<String> {
+	let max_delay = self.services.server.config.max_event_delay_s;
+	if max_delay == 0 {
+		return Err!(Request(Forbidden("Delayed events are disabled on this server.")));
+	}
+
+	if delay > Duration::from_secs(max_delay) {
+		return Err!(Request(InvalidParam(
+			"Requested delay exceeds the maximum of {max_delay} seconds."
+		)));
+	}
+
+	if !self.services.state_cache.is_joined(user_id, room_id).await {
+		return Err!(Request(Forbidden("You are not joined to this room.")));
+	}
+
+	let max_pending = self.services.server.config.max_delayed_events_per_user;
+	let pending = self.delayed_events_for_user(user_id).count().await;
+	if pending >= max_pending {
+		return Err(Error::Request(
+			ErrorKind::LimitExceeded { retry_after: None },
+			format!(
+				"You already have the maximum of {max_pending} pending delayed events; send or \
+				 cancel some first."
+			)
+			.into(),
+			http::StatusCode::TOO_MANY_REQUESTS,
+		));
+	}
+
+	let event = DelayedEvent {
+		delay_id: random_string(DELAY_ID_LENGTH),
+		room_id: room_id.to_owned(),
+		event_type,
+		state_key,
+		delay: delay.as_millis().try_into()?,
+		running_since: millis_since_unix_epoch(),
+		content,
+	};
+
+	self.db
+		.userdelayid_delayedevent
+		.put((user_id, &event.delay_id), Json(&event));
+
+	self.queue_timer(user_id, &event.delay_id, delay)?;
+
+	Ok(event.delay_id)
+}
+
+/// Applies a client's cancel, restart or send request to one of its pending
+/// delayed events.
+#[implement(Service)]
+pub async fn update(
+	&self,
+	user_id: &UserId,
+	delay_id: &str,
+	action: DelayedEventAction,
+) -> Result {
+	let mut event = self.get_delayed_event(user_id, delay_id).await?;
+
+	match action {
+		| DelayedEventAction::Cancel => {
+			self.db.userdelayid_delayedevent.del((user_id, delay_id));
+		},
+		| DelayedEventAction::Restart => {
+			event.running_since = millis_since_unix_epoch();
+			self.db
+				.userdelayid_delayedevent
+				.put((user_id, delay_id), Json(&event));
+
+			self.queue_timer(user_id, delay_id, Duration::from_millis(event.delay))?;
+		},
+		| DelayedEventAction::Send => {
+			self.db.userdelayid_delayedevent.del((user_id, delay_id));
+
+			self.send(user_id, event).await?;
+		},
+	}
+
+	Ok(())
+}
+
+#[implement(Service)]
+pub async fn get_delayed_event(&self, user_id: &UserId, delay_id: &str) -> Result<DelayedEvent> {
+	self.db
+		.userdelayid_delayedevent
+		.qry(&(user_id, delay_id))
+		.await
+		.deserialized()
+		.map_err(|_| err!(Request(NotFound("Delayed event not found."))))
+}
+
+/// All of a user's pending delayed events.
+#[implement(Service)]
+pub fn delayed_events_for_user<'a>(
+	&'a self,
+	user_id: &'a UserId,
+) -> impl Stream<Item = DelayedEvent> + Send + 'a {
+	type KeyVal<'a> = (Ignore, DelayedEvent);
+
+	let prefix = (user_id, Interfix);
+	self.db
+		.userdelayid_delayedevent
+		.stream_prefix(&prefix)
+		.ignore_err()
+		.map(|(_, event): KeyVal<'_>| event)
+}
+
+#[implement(Service)]
+fn queue_timer(&self, user_id: &UserId, delay_id: &str, delay: Duration) -> Result {
+	self.timer_channel
+		.0
+		.send((user_id.to_owned(), delay_id.to_owned(), delay))
+		.map_err(|e| err!("Failed to add delayed event timer: {e}"))
+}
+
+#[implement(Service)]
+async fn process_timer(&self, user_id: &UserId, delay_id: &str) -> Result {
+	let Ok(event) = self.get_delayed_event(user_id, delay_id).await else {
+		debug!(%user_id, %delay_id, "Delayed event was cancelled or already sent");
+		return Ok(());
+	};
+
+	// A restart queues a fresh timer; this one belongs to the earlier run.
+	if !remaining(&event).is_zero() {
+		return Ok(());
+	}
+
+	self.db.userdelayid_delayedevent.del((user_id, delay_id));
+
+	self.send(user_id, event).await
+}
+
+#[implement(Service)]
+async fn send(&self, user_id: &UserId, event: DelayedEvent) -> Result {
+	let DelayedEvent {
+		delay_id,
+		room_id,
+		event_type,
+		state_key,
+		content,
+		..
+	} = event;
+
+	let state_lock = self.services.state.mutex.lock(&room_id).await;
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type,
+				content,
+				state_key,
+				..Default::default()
+			},
+			user_id,
+			&room_id,
+			&state_lock,
+		)
+		.await
+		.inspect_err(|e| debug_warn!(%user_id, %delay_id, "Failed to send delayed event: {e}"))?;
+
+	Ok(())
+}
+
+fn remaining(event: &DelayedEvent) -> Duration {
+	let send_at = event.running_since.saturating_add(event.delay);
+	Duration::from_millis(send_at.saturating_sub(millis_since_unix_epoch()))
+}
+
+async fn delayed_event_timer((user_id, delay_id, delay): TimerType) -> (OwnedUserId, String) {
+	sleep(delay).await;
+
+	(user_id, delay_id)
+}
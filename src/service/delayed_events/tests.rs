@@ -0,0 +1,52 @@
+#![cfg(test)]
+
+use std::time::Duration;
+
+use conduwuit::utils::millis_since_unix_epoch;
+use ruma::owned_room_id;
+use serde_json::value::to_raw_value;
+
+use super::{remaining, DelayedEvent};
+
+fn event(running_since: u64, delay: u64) -> DelayedEvent {
+	DelayedEvent {
+		delay_id: "delay".to_owned(),
+		room_id: owned_room_id!("!room:example.com"),
+		event_type: "m.room.message".into(),
+		state_key: None,
+		delay,
+		running_since,
+		content: to_raw_value(&serde_json::json!({})).unwrap(),
+	}
+}
+
+#[test]
+fn remaining_counts_from_running_since() {
+	let left = remaining(&event(millis_since_unix_epoch(), 60_000));
+
+	assert!(left <= Duration::from_secs(60));
+	assert!(left > Duration::from_secs(50));
+}
+
+#[test]
+fn remaining_is_zero_once_due() {
+	let now = millis_since_unix_epoch();
+
+	assert!(remaining(&event(now.saturating_sub(10_000), 5_000)).is_zero());
+	assert!(remaining(&event(0, 0)).is_zero());
+}
+
+#[test]
+fn remaining_saturates() {
+	assert!(!remaining(&event(u64::MAX, u64::MAX)).is_zero());
+}
+
+#[test]
+fn restart_extends_remaining() {
+	let now = millis_since_unix_epoch();
+	let mut delayed = event(now.saturating_sub(50_000), 60_000);
+	assert!(remaining(&delayed) <= Duration::from_secs(10));
+
+	delayed.running_since = now;
+	assert!(remaining(&delayed) > Duration::from_secs(50));
+}
@@ -0,0 +1,522 @@
+use std::{fmt, net::IpAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use conduwuit::{
+	debug, debug_warn, err, implement, info,
+	utils::{self, millis_since_unix_epoch, stream::TryIgnore, ReadyExt},
+	Err, Result, Server,
+};
+use database::{Deserialized, Interfix, Json, Map};
+use futures::{Stream, StreamExt};
+use lettre::{
+	message::{header::ContentType, Mailbox},
+	Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use ruma::{ClientSecret, OwnedSessionId, OwnedUserId, SessionId, UserId};
+use serde::{Deserialize, Serialize};
+use tokio::{
+	sync::Notify,
+	time::{interval, MissedTickBehavior},
+};
+
+use crate::{globals, rate_limiting, Dep};
+
+pub struct Service {
+	mailer: Option<Mailer>,
+	services: Services,
+	db: Data,
+	interrupt: Notify,
+}
+
+struct Mailer {
+	transport: AsyncSmtpTransport<Tokio1Executor>,
+	from: Mailbox,
+}
+
+struct Services {
+	server: Arc<Server>,
+	globals: Dep<globals::Service>,
+	rate_limiting: Dep<rate_limiting::Service>,
+}
+
+struct Data {
+	emailsid_session: Arc<Map>,
+	emailsecret_sid: Arc<Map>,
+	email_userid: Arc<Map>,
+	userid_email: Arc<Map>,
+}
+
+/// What an email address is being validated for.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationKind {
+	Registration,
+	PasswordReset,
+	AddThreepid,
+}
+
+/// A validation email sent in response to a `requestToken` request.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Session {
+	email: String,
+	client_secret: String,
+	token: String,
+	kind: ValidationKind,
+	send_attempt: u64,
+
+	/// Milliseconds since the unix epoch.
+	created: u64,
+
+	/// When the link in the email was followed, in milliseconds since the
+	/// unix epoch.
+	validated: Option<u64>,
+}
+
+/// An email address bound to a local user.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Binding {
+	/// Milliseconds since the unix epoch.
+	pub added_at: u64,
+
+	/// Milliseconds since the unix epoch.
+	pub validated_at: u64,
+}
+
+const SESSION_ID_LENGTH: usize = 24;
+const TOKEN_LENGTH: usize = 32;
+
+/// How often sessions past `email_validation_lifetime` are removed.
+const SESSION_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Path of the link in validation emails.
+pub const VALIDATION_PATH: &str = "/_conduwuit/email/validate";
+
+const REGISTRATION_TEMPLATE: &str = "Hello,
+
+Someone asked to register an account on {server_name} with this email address.
+If this was you, follow the link below to continue:
+
+{link}
+
+If this wasn't you, you can ignore this email.
+";
+
+const PASSWORD_RESET_TEMPLATE: &str = "Hello,
+
+Someone asked to reset the password of the account using this email address on
+{server_name}. If this was you, follow the link below to continue:
+
+{link}
+
+If this wasn't you, you can ignore this email; your password won't change.
+";
+
+const ADD_THREEPID_TEMPLATE: &str = "Hello,
+
+Someone asked to add this email address to their account on {server_name}.
+If this was you, follow the link below to continue:
+
+{link}
+
+If this wasn't you, you can ignore this email.
+";
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let config = &args.server.config;
+		let mailer = config
+			.smtp_url
+			.as_deref()
+			.map(|url| -> Result<Mailer> {
+				let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+					.map_err(|e| err!(Config("smtp_url", "Invalid SMTP URL: {e}")))?
+					.build();
+
+				let from = config
+					.smtp_from
+					.as_deref()
+					.unwrap_or_default()
+					.parse()
+					.map_err(|e| err!(Config("smtp_from", "Invalid sender address: {e}")))?;
+
+				Ok(Mailer { transport, from })
+			})
+			.transpose()?;
+
+		Ok(Arc::new(Self {
+			mailer,
+			services: Services {
+				server: args.server.clone(),
+				globals: args.depend::<globals::Service>("globals"),
+				rate_limiting: args.depend::<rate_limiting::Service>("rate_limiting"),
+			},
+			db: Data {
+				emailsid_session: args.db["emailsid_session"].clone(),
+				emailsecret_sid: args.db["emailsecret_sid"].clone(),
+				email_userid: args.db["email_userid"].clone(),
+				userid_email: args.db["userid_email"].clone(),
+			},
+			interrupt: Notify::new(),
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		let mut i = interval(SESSION_PRUNE_INTERVAL);
+		i.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		loop {
+			tokio::select! {
+				() = self.interrupt.notified() => break,
+				_ = i.tick() => (),
+			}
+
+			let removed = self.prune_sessions().await;
+			if removed > 0 {
+				debug!("Removed {removed} expired email validation sessions");
+			}
+		}
+
+		Ok(())
+	}
+
+	fn interrupt(&self) { self.interrupt.notify_waiters(); }
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Whether sending email is configured.
+#[implement(Service)]
+#[must_use]
+pub fn enabled(&self) -> bool { self.mailer.is_some() }
+
+/// Sends a validation email for a `requestToken` request. Repeating a request
+/// with the same client secret, address and send attempt returns the same
+/// session without sending another email. Emails sent to each address and
+/// for each client address are rate limited.
+#[implement(Service)]
+pub async fn request_token(
+	&self,
+	kind: ValidationKind,
+	email: &str,
+	client_secret: &ClientSecret,
+	send_attempt: u64,
+	client: IpAddr,
+) -> Result<OwnedSessionId> {
+	let Some(mailer) = &self.mailer else {
+		return Err!(Request(ThreepidDenied("Email is not configured on this server.")));
+	};
+
+	let email = normalize(email)?;
+	let address: Address = email
+		.parse()
+		.map_err(|e| err!(Request(InvalidParam("Invalid email address: {e}"))))?;
+
+	let secret_key = (client_secret.as_str(), email.as_str());
+	if let Ok(sid) = self
+		.db
+		.emailsecret_sid
+		.qry(&secret_key)
+		.await
+		.deserialized::<String>()
+	{
+		if let Ok(session) = self.get_session(&sid).await {
+			if session.kind == kind && session.send_attempt >= send_attempt {
+				return SessionId::parse(sid)
+					.map_err(|e| err!(Database("Invalid email session ID: {e}")));
+			}
+		}
+	}
+
+	self.services.rate_limiting.check_email(&email, client)?;
+
+	let sid = utils::random_string(SESSION_ID_LENGTH);
+	let session = Session {
+		email: email.clone(),
+		client_secret: client_secret.to_string(),
+		token: utils::random_string(TOKEN_LENGTH),
+		kind,
+		send_attempt,
+		created: millis_since_unix_epoch(),
+		validated: None,
+	};
+
+	let message = Message::builder()
+		.from(mailer.from.clone())
+		.to(Mailbox::new(None, address))
+		.subject(format!("{} on {}", kind, self.services.globals.server_name()))
+		.header(ContentType::TEXT_PLAIN)
+		.body(self.render(&session, &sid).await)
+		.map_err(|e| err!("Failed to build validation email: {e}"))?;
+
+	mailer
+		.transport
+		.send(message)
+		.await
+		.map_err(|e| err!(Request(Unknown(warn!("Failed to send validation email: {e}")))))?;
+
+	self.db.emailsid_session.raw_put(&sid, Json(&session));
+	self.db.emailsecret_sid.put(secret_key, &sid);
+
+	info!("Sent {kind} validation email for session {sid}");
+	SessionId::parse(sid).map_err(|e| err!(Database("Invalid email session ID: {e}")))
+}
+
+/// Marks a session as validated when the link in its email is followed.
+#[implement(Service)]
+pub async fn validate(&self, sid: &str, client_secret: &str, token: &str) -> Result {
+	let mut session = self.get_session(sid).await?;
+	if session.client_secret != client_secret || session.token != token {
+		return Err!(Request(Forbidden("Invalid validation link.")));
+	}
+
+	if self.is_expired(&session) {
+		return Err!(Request(Forbidden("This validation link has expired.")));
+	}
+
+	if session.validated.is_none() {
+		session.validated = Some(millis_since_unix_epoch());
+		self.db.emailsid_session.raw_put(sid, Json(&session));
+	}
+
+	Ok(())
+}
+
+/// Returns the address of a session whose email was validated, optionally
+/// checking what it was requested for.
+#[implement(Service)]
+pub async fn validated_email(
+	&self,
+	sid: &str,
+	client_secret: &str,
+	kind: Option<ValidationKind>,
+) -> Result<String> {
+	let session = self.get_session(sid).await?;
+	if session.client_secret != client_secret {
+		return Err!(Request(Forbidden("Invalid client secret.")));
+	}
+
+	if session.validated.is_none() || self.is_expired(&session) {
+		return Err!(Request(ThreepidAuthFailed("The email address has not been validated.")));
+	}
+
+	if kind.is_some_and(|kind| kind != session.kind) {
+		return Err!(Request(ThreepidAuthFailed(
+			"The email address was validated for something else."
+		)));
+	}
+
+	Ok(session.email)
+}
+
+/// Removes a session once its validated address has been used.
+#[implement(Service)]
+pub async fn consume(&self, sid: &str) {
+	if let Ok(session) = self.get_session(sid).await {
+		self.db
+			.emailsecret_sid
+			.del((session.client_secret.as_str(), session.email.as_str()));
+	}
+
+	self.db.emailsid_session.remove(sid);
+}
+
+/// Binds a validated email address to a local user.
+#[implement(Service)]
+pub async fn bind(&self, user_id: &UserId, email: &str) -> Result {
+	let email = normalize(email)?;
+	if let Ok(owner) = self.find_user(&email).await {
+		if owner != user_id {
+			return Err!(Request(ThreepidInUse("This email address is already in use.")));
+		}
+	}
+
+	let now = millis_since_unix_epoch();
+	let binding = Binding { added_at: now, validated_at: now };
+
+	self.db.email_userid.insert(&email, user_id);
+	self.db
+		.userid_email
+		.put((user_id, email.as_str()), Json(binding));
+
+	Ok(())
+}
+
+/// Removes an email address from a local user.
+#[implement(Service)]
+pub async fn unbind(&self, user_id: &UserId, email: &str) -> Result {
+	let email = normalize(email)?;
+	if self
+		.find_user(&email)
+		.await
+		.is_ok_and(|owner| owner == user_id)
+	{
+		self.db.email_userid.remove(&email);
+	}
+
+	self.db.userid_email.del((user_id, email.as_str()));
+
+	Ok(())
+}
+
+/// Removes every email address from a local user, so that they can be used by
+/// other accounts.
+#[implement(Service)]
+pub async fn unbind_all(&self, user_id: &UserId) {
+	let emails: Vec<_> = self
+		.user_emails(user_id)
+		.map(|(email, _)| email)
+		.collect()
+		.await;
+
+	for email in emails {
+		self.unbind(user_id, &email).await.ok();
+	}
+}
+
+/// The email addresses bound to a local user.
+#[implement(Service)]
+pub fn user_emails<'a>(
+	&'a self,
+	user_id: &'a UserId,
+) -> impl Stream<Item = (String, Binding)> + Send + 'a {
+	let prefix = (user_id, Interfix);
+	self.db
+		.userid_email
+		.stream_prefix(&prefix)
+		.ignore_err()
+		.map(|((_, email), binding): ((&UserId, &str), Binding)| (email.to_owned(), binding))
+}
+
+/// The local user an email address is bound to.
+#[implement(Service)]
+pub async fn find_user(&self, email: &str) -> Result<OwnedUserId> {
+	let email = normalize(email)?;
+	self.db
+		.email_userid
+		.get(&email)
+		.await
+		.deserialized()
+		.map_err(|_| err!(Request(ThreepidNotFound("No account uses this email address."))))
+}
+
+#[implement(Service)]
+async fn get_session(&self, sid: &str) -> Result<Session> {
+	self.db
+		.emailsid_session
+		.get(sid)
+		.await
+		.deserialized()
+		.map_err(|_| err!(Request(NotFound("Unknown email validation session."))))
+}
+
+/// Removes the sessions past `email_validation_lifetime`, returning how many
+/// there were.
+#[implement(Service)]
+async fn prune_sessions(&self) -> usize {
+	let expired: Vec<(String, Session)> = self
+		.db
+		.emailsid_session
+		.stream()
+		.ignore_err()
+		.ready_filter_map(|(sid, session): (&str, Session)| {
+			self.is_expired(&session).then(|| (sid.to_owned(), session))
+		})
+		.collect()
+		.await;
+
+	for (sid, session) in &expired {
+		self.db
+			.emailsecret_sid
+			.del((session.client_secret.as_str(), session.email.as_str()));
+
+		self.db.emailsid_session.remove(sid);
+	}
+
+	expired.len()
+}
+
+#[implement(Service)]
+fn is_expired(&self, session: &Session) -> bool {
+	let lifetime = self
+		.services
+		.server
+		.config
+		.email_validation_lifetime
+		.saturating_mul(1000);
+
+	millis_since_unix_epoch() > session.created.saturating_add(lifetime)
+}
+
+/// Fills in the template for the session's kind, which may be overridden by a
+/// file in `email_template_dir`.
+#[implement(Service)]
+async fn render(&self, session: &Session, sid: &str) -> String {
+	let config = &self.services.server.config;
+	let base = config.well_known.client.as_ref().map_or_else(
+		|| format!("https://{}", self.services.globals.server_name()),
+		|url| url.as_str().trim_end_matches('/').to_owned(),
+	);
+
+	let link = format!(
+		"{base}{VALIDATION_PATH}?sid={sid}&client_secret={}&token={}",
+		session.client_secret, session.token
+	);
+
+	let template = match &config.email_template_dir {
+		| Some(dir) => {
+			let path: PathBuf = dir.join(format!("{}.txt", session.kind.file_stem()));
+			tokio::fs::read_to_string(&path)
+				.await
+				.inspect_err(|e| {
+					debug_warn!("Using the built-in template, failed to read {path:?}: {e}");
+				})
+				.ok()
+		},
+		| None => None,
+	};
+
+	template
+		.as_deref()
+		.unwrap_or_else(|| session.kind.template())
+		.replace("{server_name}", self.services.globals.server_name().as_str())
+		.replace("{email}", &session.email)
+		.replace("{link}", &link)
+		.replace("{token}", &session.token)
+}
+
+impl ValidationKind {
+	fn file_stem(self) -> &'static str {
+		match self {
+			| Self::Registration => "registration",
+			| Self::PasswordReset => "password_reset",
+			| Self::AddThreepid => "add_threepid",
+		}
+	}
+
+	fn template(self) -> &'static str {
+		match self {
+			| Self::Registration => REGISTRATION_TEMPLATE,
+			| Self::PasswordReset => PASSWORD_RESET_TEMPLATE,
+			| Self::AddThreepid => ADD_THREEPID_TEMPLATE,
+		}
+	}
+}
+
+impl fmt::Display for ValidationKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			| Self::Registration => "Registration",
+			| Self::PasswordReset => "Password reset",
+			| Self::AddThreepid => "Adding an email address",
+		})
+	}
+}
+
+fn normalize(email: &str) -> Result<String> {
+	let email = email.trim().to_lowercase();
+	if email.is_empty() || !email.contains('@') {
+		return Err!(Request(InvalidParam("Invalid email address.")));
+	}
+
+	Ok(email)
+}
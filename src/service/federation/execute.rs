@@ -106,6 +106,7 @@ async fn perform<T>(
 #[implement(super::Service)]
 fn prepare(&self, dest: &ServerName, mut request: http::Request<Vec<u8>>) -> Result<Request> {
 	self.sign_request(&mut request, dest);
+	self.inject_trace_context(dest, request.headers_mut());
 
 	let request = Request::try_from(request)?;
 	self.validate_url(request.url())?;
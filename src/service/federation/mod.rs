@@ -1,4 +1,5 @@
 mod execute;
+mod trace;
 
 use std::sync::Arc;
 
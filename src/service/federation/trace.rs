@@ -0,0 +1,110 @@
+//! Trace context propagation
+//!
+//! With the `otlp_telemetry` feature, W3C trace context is exchanged with the
+//! servers in `otlp_propagation_servers`, so a trace started on one of them
+//! continues on the other. Without it these do nothing.
+
+use conduwuit::implement;
+use http::HeaderMap;
+use ruma::ServerName;
+use tracing::Span;
+
+/// Adds the context of the current span to a request to `dest`, if it's a
+/// server traces are propagated to.
+#[implement(super::Service)]
+pub(super) fn inject_trace_context(&self, dest: &ServerName, headers: &mut HeaderMap) {
+	#[cfg(feature = "otlp_telemetry")]
+	if self.is_propagation_server(dest) {
+		use opentelemetry::global;
+		use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+		let context = Span::current().context();
+		global::get_text_map_propagator(|propagator| {
+			propagator.inject_context(&context, &mut otel::Injector(headers));
+		});
+	}
+
+	#[cfg(not(feature = "otlp_telemetry"))]
+	_ = (dest, headers);
+}
+
+/// Continues the trace of an incoming federation request in `span`, if the
+/// request carries trace context and its X-Matrix origin is a server traces
+/// are propagated from.
+///
+/// This happens before the request is authenticated, so the origin is only
+/// what the request claims; at worst, a forged request is traced under
+/// another server's trace.
+#[implement(super::Service)]
+pub fn accept_trace_context(&self, span: &Span, headers: &HeaderMap) {
+	#[cfg(feature = "otlp_telemetry")]
+	{
+		use http::header::AUTHORIZATION;
+		use opentelemetry::global;
+		use ruma::server_util::authorization::XMatrix;
+		use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+		if !headers.contains_key("traceparent") {
+			return;
+		}
+
+		let origin = headers
+			.get(AUTHORIZATION)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| XMatrix::parse(value).ok())
+			.map(|x_matrix| x_matrix.origin);
+
+		if !origin.is_some_and(|origin| self.is_propagation_server(&origin)) {
+			return;
+		}
+
+		let context = global::get_text_map_propagator(|propagator| {
+			propagator.extract(&otel::Extractor(headers))
+		});
+
+		span.set_parent(context);
+	}
+
+	#[cfg(not(feature = "otlp_telemetry"))]
+	_ = (span, headers);
+}
+
+#[cfg(feature = "otlp_telemetry")]
+#[implement(super::Service)]
+fn is_propagation_server(&self, server: &ServerName) -> bool {
+	self.services
+		.server
+		.config
+		.otlp_propagation_servers
+		.iter()
+		.any(|propagation_server| propagation_server == server)
+}
+
+#[cfg(feature = "otlp_telemetry")]
+mod otel {
+	use http::{HeaderMap, HeaderName, HeaderValue};
+
+	pub(super) struct Injector<'a>(pub(super) &'a mut HeaderMap);
+
+	pub(super) struct Extractor<'a>(pub(super) &'a HeaderMap);
+
+	impl opentelemetry::propagation::Injector for Injector<'_> {
+		fn set(&mut self, key: &str, value: String) {
+			let Ok(name) = HeaderName::from_bytes(key.as_bytes()) else {
+				return;
+			};
+
+			if let Ok(value) = HeaderValue::from_str(&value) {
+				self.0.insert(name, value);
+			}
+		}
+	}
+
+	impl opentelemetry::propagation::Extractor for Extractor<'_> {
+		fn get(&self, key: &str) -> Option<&str> {
+			self.0.get(key).and_then(|value| value.to_str().ok())
+		}
+
+		fn keys(&self) -> Vec<&str> { self.0.keys().map(HeaderName::as_str).collect() }
+	}
+}
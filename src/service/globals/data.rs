@@ -1,7 +1,7 @@
 use std::sync::{Arc, RwLock};
 
 use conduwuit::{utils, Result};
-use database::{Database, Deserialized, Map};
+use database::{BackupInfo, Database, Deserialized, Map};
 
 pub struct Data {
 	global: Arc<Map>,
@@ -74,7 +74,7 @@ pub fn bump_database_version(&self, new_version: u64) {
 	}
 
 	#[inline]
-	pub fn backup(&self) -> Result { self.db.db.backup() }
+	pub fn backup(&self) -> Result<Option<BackupInfo>> { self.db.db.backup() }
 
 	#[inline]
 	pub fn backup_list(&self) -> Result<String> { self.db.db.backup_list() }
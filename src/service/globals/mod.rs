@@ -3,8 +3,9 @@
 use std::{
 	collections::HashMap,
 	fmt::Write,
+	net::IpAddr,
 	sync::{Arc, RwLock},
-	time::Instant,
+	time::{Duration, Instant},
 };
 
 use conduwuit::{error, utils::bytes::pretty, Result, Server};
@@ -19,6 +20,7 @@ pub struct Service {
 	server: Arc<Server>,
 
 	pub bad_event_ratelimiter: Arc<RwLock<HashMap<OwnedEventId, RateLimitState>>>,
+	pub peek_ratelimiter: RwLock<HashMap<IpAddr, RateLimitState>>,
 	pub server_user: OwnedUserId,
 	pub admin_alias: OwnedRoomAliasId,
 	pub turn_secret: String,
@@ -27,6 +29,12 @@ pub struct Service {
 
 type RateLimitState = (Instant, u32); // Time if last failed try, number of failed tries
 
+/// Window over which unauthenticated peeking requests are counted per client.
+const PEEK_RATELIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Number of tracked peeking clients above which expired windows are pruned.
+const PEEK_RATELIMIT_PRUNE_LEN: usize = 4096;
+
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		let db = Data::new(&args);
@@ -61,6 +69,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			db,
 			server: args.server.clone(),
 			bad_event_ratelimiter: Arc::new(RwLock::new(HashMap::new())),
+			peek_ratelimiter: RwLock::new(HashMap::new()),
 			admin_alias: OwnedRoomAliasId::try_from(format!("#admins:{}", &args.server.name))
 				.expect("#admins:server_name is valid alias name"),
 			server_user: UserId::parse_with_server_name(
@@ -86,6 +95,10 @@ fn memory_usage(&self, out: &mut dyn Write) -> Result {
 
 		writeln!(out, "bad_event_ratelimiter: {ber_count} ({})", pretty(ber_bytes))?;
 
+		let prl_count = self.peek_ratelimiter.read()?.len();
+		let prl_bytes = prl_count.saturating_mul(size_of::<(IpAddr, RateLimitState)>());
+		writeln!(out, "peek_ratelimiter: {prl_count} ({})", pretty(prl_bytes))?;
+
 		Ok(())
 	}
 
@@ -94,6 +107,11 @@ fn clear_cache(&self) {
 			.write()
 			.expect("locked for writing")
 			.clear();
+
+		self.peek_ratelimiter
+			.write()
+			.expect("locked for writing")
+			.clear();
 	}
 
 	fn name(&self) -> &str { service::make_name(std::module_path!()) }
@@ -212,4 +230,25 @@ pub fn server_is_ours(&self, server_name: &ServerName) -> bool {
 
 	#[inline]
 	pub fn is_read_only(&self) -> bool { self.db.db.is_read_only() }
+
+	/// Counts an unauthenticated peeking request from `client`, returning true
+	/// if it exceeds the configured per-minute limit.
+	pub fn peek_ratelimited(&self, client: IpAddr) -> bool {
+		let limit = self.server.config.unauthenticated_peek_requests_per_minute;
+		let now = Instant::now();
+
+		let mut limiter = self.peek_ratelimiter.write().expect("locked for writing");
+		if limiter.len() >= PEEK_RATELIMIT_PRUNE_LEN {
+			limiter.retain(|_, (start, _)| now.duration_since(*start) < PEEK_RATELIMIT_WINDOW);
+		}
+
+		let (start, count) = limiter.entry(client).or_insert((now, 0));
+		if now.duration_since(*start) >= PEEK_RATELIMIT_WINDOW {
+			*start = now;
+			*count = 0;
+		}
+
+		*count = count.saturating_add(1);
+		*count > limit
+	}
 }
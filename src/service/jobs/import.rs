@@ -0,0 +1,281 @@
+use std::{
+	borrow::Borrow,
+	collections::{HashMap, HashSet},
+	fmt,
+	path::Path,
+	sync::{Arc, Mutex as StdMutex},
+};
+
+use conduwuit::{
+	err, implement, info,
+	pdu::{gen_event_id, PduEvent},
+	Err, Result,
+};
+use futures::{FutureExt, StreamExt};
+use ruma::{
+	events::room::create::RoomCreateEventContent, CanonicalJsonObject, CanonicalJsonValue,
+	OwnedEventId, OwnedRoomId, RoomId, RoomVersionId,
+};
+use serde::Deserialize;
+
+use super::Job;
+use crate::rooms::state_compressor::{CompressedState, HashSetCompressStateEvent};
+
+/// The events of a room read from an export, oldest first.
+pub struct RoomExport {
+	pub room_id: OwnedRoomId,
+	pub room_version: RoomVersionId,
+	pub events: Vec<ExportedEvent>,
+}
+
+pub struct ExportedEvent {
+	pub event_id: OwnedEventId,
+	value: CanonicalJsonObject,
+}
+
+/// Our archive format: a JSON object with the room's PDUs under `events`.
+#[derive(Deserialize)]
+struct Archive {
+	events: Vec<CanonicalJsonObject>,
+}
+
+#[derive(Deserialize)]
+struct CreateEvent {
+	room_id: OwnedRoomId,
+	content: RoomCreateEventContent,
+}
+
+/// Reads the events of a room from either our archive format or the
+/// `rooms/<room_id>/events` file written by Synapse's `export-data` command,
+/// which holds one PDU per line. Event IDs are kept as they were when the
+/// room version has them in the event, and recomputed from the event's
+/// content otherwise, which yields the original ones unless the export
+/// altered the events.
+pub async fn read_room_export(path: &Path) -> Result<RoomExport> {
+	let export = tokio::fs::read_to_string(path).await?;
+	let events: Vec<CanonicalJsonObject> = match serde_json::from_str::<Archive>(&export) {
+		| Ok(archive) => archive.events,
+		| Err(_) => export
+			.lines()
+			.filter(|line| !line.trim().is_empty())
+			.map(serde_json::from_str)
+			.collect::<Result<_, _>>()
+			.map_err(|e| err!("Invalid room export: {e}"))?,
+	};
+
+	let Some(create) = events
+		.iter()
+		.find(|event| event_type(event) == Some("m.room.create"))
+	else {
+		return Err!("The export has no m.room.create event.");
+	};
+
+	let CreateEvent { room_id, content } = serde_json::to_value(create)
+		.and_then(serde_json::from_value)
+		.map_err(|e| err!("Invalid m.room.create event in the export: {e}"))?;
+
+	let room_version = content.room_version;
+	let mut events = events
+		.into_iter()
+		.map(|mut value| {
+			// Synapse's local bookkeeping; not covered by the event's hashes.
+			value.remove("unsigned");
+
+			let event_id = match room_version {
+				| RoomVersionId::V1 | RoomVersionId::V2 => match value.get("event_id") {
+					| Some(CanonicalJsonValue::String(event_id)) =>
+						event_id.as_str().try_into()?,
+					| _ => return Err!("Event without an event_id in a v1 or v2 room."),
+				},
+				| _ => gen_event_id(&value, &room_version)?,
+			};
+
+			Ok(ExportedEvent { event_id, value })
+		})
+		.collect::<Result<Vec<_>>>()?;
+
+	events.sort_by_key(|event| {
+		(integer(&event.value, "depth"), integer(&event.value, "origin_server_ts"))
+	});
+
+	Ok(RoomExport { room_id, room_version, events })
+}
+
+/// Recreates a room from an export, for when the servers it was on are gone
+/// for good. Events are stored in order of depth with the state before them,
+/// without authorizing them or checking their signatures: the export is
+/// trusted. Like backfilled events, they notify nobody and nothing is sent to
+/// appservices or other servers. The room's state is then resolved from the
+/// export's forward extremities. Events already in the room are skipped, so an
+/// interrupted import picks up where it stopped; the events which could not
+/// be imported are listed in the job's report.
+#[implement(super::Service)]
+pub(super) async fn import_room(&self, job: &mut Job, room_id: &RoomId, path: &Path) -> Result {
+	let export = read_room_export(path).await?;
+	let exported = &export.room_id;
+	if exported != room_id {
+		return Err!("The export is of {exported}, not {room_id}.");
+	}
+
+	self.services.short.get_or_create_shortroomid(room_id).await;
+
+	let room_version = &export.room_version;
+	let imported = StdMutex::new((Vec::new(), HashSet::new()));
+	let report = StdMutex::new(Vec::new());
+	let result = self
+		.process(job, export.events, |event| {
+			let (imported, report) = (&imported, &report);
+			async move {
+				let event_id = event.event_id.clone();
+				match self.import_event(room_id, room_version, event).await {
+					| Ok(pdu) => {
+						let (events, referenced) = &mut *imported.lock().expect("locked");
+						referenced.extend(pdu.prev_events.iter().cloned());
+						events.push(event_id);
+						Ok(())
+					},
+					| Err(e) => {
+						report
+							.lock()
+							.expect("locked")
+							.push(format!("{event_id}: failed: {e}"));
+						Err(e)
+					},
+				}
+			}
+		})
+		.await;
+
+	job.report = report.into_inner().expect("locked");
+	result?;
+
+	let (events, referenced) = imported.into_inner().expect("locked");
+	let extremities: Vec<_> = events
+		.into_iter()
+		.filter(|event_id| !referenced.contains(event_id))
+		.collect();
+
+	if extremities.is_empty() {
+		return Err!("None of the events could be imported.");
+	}
+
+	self.set_imported_state(room_id, room_version, &extremities)
+		.await?;
+
+	info!(%room_id, version = %room_version, "Imported room from {}", path.display());
+	Ok(())
+}
+
+#[implement(super::Service)]
+async fn import_event(
+	&self,
+	room_id: &RoomId,
+	room_version: &RoomVersionId,
+	event: ExportedEvent,
+) -> Result<Arc<PduEvent>> {
+	let ExportedEvent { event_id, mut value } = event;
+	if self.services.timeline.get_pdu_id(&event_id).await.is_ok() {
+		return self
+			.services
+			.timeline
+			.get_pdu(&event_id)
+			.await
+			.map(Arc::new);
+	}
+
+	value.insert("event_id".into(), CanonicalJsonValue::String(event_id.as_str().into()));
+	let pdu = PduEvent::from_id_val(&event_id, value.clone())
+		.map(Arc::new)
+		.map_err(|e| err!("Invalid event {event_id}: {e}"))?;
+
+	if pdu.room_id != room_id {
+		return Err!("Event {event_id} is from another room.");
+	}
+
+	let event_handler = &self.services.event_handler;
+	let state = match pdu.prev_events.len() {
+		| 0 => Some(HashMap::new()),
+		| 1 => event_handler.state_at_incoming_degree_one(&pdu).await?,
+		| _ =>
+			event_handler
+				.state_after_events(&pdu.prev_events, room_id, room_version)
+				.await?,
+	};
+
+	let Some(state) = state else {
+		return Err!("The state before {event_id} is unknown: its previous events are missing.");
+	};
+
+	let state_ids_compressed = self.compress_state(&state).await;
+	self.services
+		.timeline
+		.append_imported_pdu(&pdu, &value, state_ids_compressed)
+		.await?;
+
+	Ok(pdu)
+}
+
+/// Resolves the state after the imported forward extremities and makes it the
+/// room's current state.
+#[implement(super::Service)]
+async fn set_imported_state(
+	&self,
+	room_id: &RoomId,
+	room_version: &RoomVersionId,
+	extremities: &[OwnedEventId],
+) -> Result {
+	let state = self
+		.services
+		.event_handler
+		.state_after_events(extremities, room_id, room_version)
+		.await?
+		.ok_or_else(|| err!("Could not resolve the state after the imported events."))?;
+
+	let state_ids_compressed = self.compress_state(&state).await;
+	let state_lock = self.services.state.mutex.lock(room_id).await;
+	let HashSetCompressStateEvent { shortstatehash, added, removed } = self
+		.services
+		.state_compressor
+		.save_state(room_id, state_ids_compressed)
+		.await?;
+
+	self.services
+		.state
+		.force_state(room_id, shortstatehash, added, removed, &state_lock)
+		.await?;
+
+	self.services
+		.state
+		.set_forward_extremities(room_id, extremities.iter().map(Borrow::borrow), &state_lock)
+		.await;
+
+	Ok(())
+}
+
+#[implement(super::Service)]
+async fn compress_state(&self, state: &HashMap<u64, OwnedEventId>) -> Arc<CompressedState> {
+	self.services
+		.state_compressor
+		.compress_state_events(state.iter().map(|(ssk, eid)| (ssk, eid.borrow())))
+		.collect()
+		.map(Arc::new)
+		.await
+}
+
+fn event_type(event: &CanonicalJsonObject) -> Option<&str> {
+	match event.get("type") {
+		| Some(CanonicalJsonValue::String(kind)) => Some(kind),
+		| _ => None,
+	}
+}
+
+fn integer(event: &CanonicalJsonObject, key: &str) -> i64 {
+	match event.get(key) {
+		| Some(CanonicalJsonValue::Integer(value)) => (*value).into(),
+		| _ => 0,
+	}
+}
+
+impl fmt::Display for ExportedEvent {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.event_id) }
+}
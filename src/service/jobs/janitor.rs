@@ -0,0 +1,149 @@
+use std::{mem::discriminant, time::Duration};
+
+use conduwuit::{
+	implement, info,
+	utils::{millis_since_unix_epoch, ReadyExt},
+	warn, Result,
+};
+use database::Deserialized;
+use futures::StreamExt;
+use ruma::{OwnedRoomId, RoomId};
+
+use super::{Job, JobKind};
+
+/// How often rooms are checked for having no local members.
+pub(super) const EMPTY_ROOMS_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Queues a job run on a timer unless one of its kind is still pending. The
+/// records of its earlier finished runs are removed, so only the last one is
+/// listed.
+#[implement(super::Service)]
+pub(super) async fn enqueue_periodic(&self, kind: JobKind) -> Result {
+	let same_kind = |job: &Job| discriminant(&job.kind) == discriminant(&kind);
+	let earlier: Vec<Job> = self.jobs().ready_filter(same_kind).collect().await;
+	if earlier.iter().any(|job| job.status.is_pending()) {
+		return Ok(());
+	}
+
+	for job in &earlier {
+		self.db.jobid_job.del(job.id);
+	}
+
+	self.enqueue(kind).map(|_| ())
+}
+
+/// Purges the rooms which have had no local members for longer than
+/// `purge_empty_rooms_after_days`, tombstoning them so they are left alone
+/// until a local user joins again. Rooms are first seen empty by one sweep and
+/// purged by a later one, so the delay starts when the janitor notices. Only
+/// the rooms' data is purged; media is left to the media commands, as other
+/// rooms may still refer to it.
+#[implement(super::Service)]
+pub(super) async fn sweep_empty_rooms(&self, job: &mut Job) -> Result {
+	let after_days = self.services.server.config.purge_empty_rooms_after_days;
+	if after_days == 0 {
+		return Ok(());
+	}
+
+	let now = millis_since_unix_epoch();
+	let cutoff = now.saturating_sub(after_days.saturating_mul(86_400_000));
+	let rooms: Vec<OwnedRoomId> = self
+		.services
+		.metadata
+		.iter_ids()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	job.total = Some(rooms.len().try_into()?);
+	self.save(job);
+
+	for room_id in rooms {
+		if self.is_cancelled(job.id) || !self.services.server.running() {
+			break;
+		}
+
+		job.done = job.done.saturating_add(1);
+		let empty_since = self
+			.db
+			.roomid_emptysince
+			.get(&room_id)
+			.await
+			.deserialized::<u64>();
+
+		let tombstoned = self.services.metadata.is_tombstoned(&room_id).await;
+		if self.has_local_members(&room_id).await {
+			if empty_since.is_ok() {
+				self.db.roomid_emptysince.remove(&room_id);
+			}
+
+			if tombstoned {
+				self.services.metadata.tombstone_room(&room_id, false);
+			}
+
+			continue;
+		}
+
+		if tombstoned {
+			continue;
+		}
+
+		let Ok(empty_since) = empty_since else {
+			self.db.roomid_emptysince.raw_put(&room_id, now);
+			continue;
+		};
+
+		if empty_since > cutoff {
+			continue;
+		}
+
+		info!(%room_id, "Purging room without local members for {after_days} days");
+		self.services.metadata.tombstone_room(&room_id, true);
+		self.db.roomid_emptysince.remove(&room_id);
+		if let Err(e) = self.purge_room(job, &room_id, false).await {
+			warn!(%room_id, "Failed to purge room without local members: {e}");
+			job.failed = job.failed.saturating_add(1);
+		}
+
+		self.save(job);
+	}
+
+	Ok(())
+}
+
+/// Whether a local user is joined to or invited into the room.
+#[implement(super::Service)]
+async fn has_local_members(&self, room_id: &RoomId) -> bool {
+	if self
+		.services
+		.state_cache
+		.local_users_in_room(room_id)
+		.next()
+		.await
+		.is_some()
+	{
+		return true;
+	}
+
+	self.services
+		.state_cache
+		.room_members_invited(room_id)
+		.ready_any(|user_id| self.services.globals.user_is_local(user_id))
+		.await
+}
+
+/// Removes the notifications logged for the notifications endpoint once
+/// they're older than `notification_log_retention_days`.
+#[implement(super::Service)]
+pub(super) async fn prune_notifications(&self) -> Result {
+	let retention_days = self.services.server.config.notification_log_retention_days;
+	if retention_days == 0 {
+		return Ok(());
+	}
+
+	let cutoff =
+		millis_since_unix_epoch().saturating_sub(retention_days.saturating_mul(86_400_000));
+	self.services.user.prune_notifications(cutoff).await;
+
+	Ok(())
+}
@@ -0,0 +1,665 @@
+mod import;
+mod janitor;
+mod purge;
+mod tests;
+
+use std::{
+	collections::HashSet,
+	fmt,
+	mem::discriminant,
+	path::PathBuf,
+	sync::{Arc, Mutex as StdMutex},
+	time::{Duration, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use conduwuit::{
+	debug, debug_warn, err, implement, info,
+	pdu::PduBuilder,
+	result::LogErr,
+	utils::{millis_since_unix_epoch, stream::TryIgnore, ReadyExt},
+	Err, Result, Server,
+};
+use database::{Database, Deserialized, Ignore, Json, Map};
+use futures::{stream::FuturesUnordered, Future, Stream, StreamExt};
+use loole::{Receiver, Sender};
+use ruma::{
+	events::room::member::{MembershipState, RoomMemberEventContent},
+	Mxc, OwnedMxcUri, OwnedRoomAliasId, OwnedRoomId, OwnedServerName, OwnedUserId, RoomId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+	sync::Mutex as TokioMutex,
+	time::{interval, MissedTickBehavior},
+};
+
+pub use self::import::{read_room_export, ExportedEvent, RoomExport};
+use crate::{
+	globals, media,
+	onboarding::{self, NewUser},
+	rooms, server_notices, Dep,
+};
+
+pub struct Service {
+	job_channel: (Sender<u64>, Receiver<u64>),
+	cancelled: StdMutex<HashSet<u64>>,
+	exclusive: TokioMutex<()>,
+	services: Services,
+	db: Data,
+}
+
+struct Services {
+	server: Arc<Server>,
+	globals: Dep<globals::Service>,
+	media: Dep<media::Service>,
+	onboarding: Dep<onboarding::Service>,
+	server_notices: Dep<server_notices::Service>,
+	alias: Dep<rooms::alias::Service>,
+	directory: Dep<rooms::directory::Service>,
+	event_handler: Dep<rooms::event_handler::Service>,
+	metadata: Dep<rooms::metadata::Service>,
+	short: Dep<rooms::short::Service>,
+	state: Dep<rooms::state::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	state_compressor: Dep<rooms::state_compressor::Service>,
+	timeline: Dep<rooms::timeline::Service>,
+	user: Dep<rooms::user::Service>,
+}
+
+struct Data {
+	jobid_job: Arc<Map>,
+	roomid_emptysince: Arc<Map>,
+	userid_avatarurl: Arc<Map>,
+	useridprofilekey_value: Arc<Map>,
+	db: Arc<Database>,
+}
+
+/// A long running operation, persisted so it survives restarts.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Job {
+	pub id: u64,
+	pub kind: JobKind,
+	pub status: JobStatus,
+
+	/// Items processed by the current run, including failed ones.
+	pub done: u64,
+
+	/// Items which could not be processed by the current run.
+	pub failed: u64,
+
+	/// Items the current run has to process, once known.
+	pub total: Option<u64>,
+
+	/// Milliseconds since the unix epoch.
+	pub created: u64,
+
+	/// Outcome of each item, for jobs whose results the admin needs to see.
+	#[serde(default)]
+	pub report: Vec<String>,
+}
+
+/// The operations which can run as a job. Each is safe to start over after a
+/// restart; items finished by an earlier run are either not found again or
+/// fail harmlessly.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+	/// Delete a list of media files.
+	DeleteMedia {
+		mxcs: Vec<OwnedMxcUri>,
+	},
+
+	/// Delete all media uploaded by a local user.
+	DeleteUserMedia {
+		user_id: OwnedUserId,
+	},
+
+	/// Delete all media from a server.
+	DeleteServerMedia {
+		server_name: OwnedServerName,
+	},
+
+	/// Delete remote (and optionally local) media created before or after a
+	/// point in time, in milliseconds since the unix epoch.
+	DeleteMediaAtAfterTime {
+		time: u64,
+		before: bool,
+		after: bool,
+		include_local: bool,
+	},
+
+	/// Remove all local users from a room, drop its local aliases and
+	/// unpublish it, optionally banning it too. With `purge` its history,
+	/// state, everything stored about it and the media only it refers to are
+	/// deleted as well. Evicted users are
+	/// sent `message` as a server notice if one is given.
+	DeleteRoom {
+		room_id: OwnedRoomId,
+		block: bool,
+
+		#[serde(default)]
+		purge: bool,
+
+		#[serde(default)]
+		message: Option<String>,
+	},
+
+	/// Create local accounts. Only the hashes of their passwords are stored.
+	CreateUsers {
+		users: Vec<NewUser>,
+	},
+
+	/// Recreate a room from the export at `path`.
+	ImportRoom {
+		room_id: OwnedRoomId,
+		path: PathBuf,
+	},
+
+	/// Purge the rooms left without local members for longer than
+	/// `purge_empty_rooms_after_days`. Queued by the janitor.
+	SweepEmptyRooms,
+
+	/// Remove the logged notifications older than
+	/// `notification_log_retention_days`. Queued by the janitor.
+	PruneNotifications,
+
+	/// Back up the database to `database_backup_path`. Only one can be
+	/// pending at a time; see [`Service::enqueue_exclusive`].
+	BackupDatabase,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+	Queued,
+	Running,
+	Completed,
+	Failed(String),
+	Cancelled,
+}
+
+impl JobStatus {
+	/// Whether the job is still to be run, or resumed after a restart.
+	#[must_use]
+	pub fn is_pending(&self) -> bool { matches!(self, Self::Queued | Self::Running) }
+
+	/// The status a run leaves the job in, or `None` when it was interrupted
+	/// by shutdown and stays running to be resumed on startup.
+	fn after_run(result: Result, cancelled: bool, shutting_down: bool) -> Option<Self> {
+		match result {
+			| Ok(()) if cancelled => Some(Self::Cancelled),
+			| Ok(()) if shutting_down => None,
+			| Ok(()) => Some(Self::Completed),
+			| Err(e) => Some(Self::Failed(e.to_string())),
+		}
+	}
+}
+
+/// Jobs in progress write their progress back after this many items.
+const PERSIST_INTERVAL: u64 = 100;
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			job_channel: loole::unbounded(),
+			cancelled: StdMutex::new(HashSet::new()),
+			exclusive: TokioMutex::new(()),
+			services: Services {
+				server: args.server.clone(),
+				globals: args.depend::<globals::Service>("globals"),
+				media: args.depend::<media::Service>("media"),
+				onboarding: args.depend::<onboarding::Service>("onboarding"),
+				server_notices: args.depend::<server_notices::Service>("server_notices"),
+				alias: args.depend::<rooms::alias::Service>("rooms::alias"),
+				directory: args.depend::<rooms::directory::Service>("rooms::directory"),
+				event_handler: args
+					.depend::<rooms::event_handler::Service>("rooms::event_handler"),
+				metadata: args.depend::<rooms::metadata::Service>("rooms::metadata"),
+				short: args.depend::<rooms::short::Service>("rooms::short"),
+				state: args.depend::<rooms::state::Service>("rooms::state"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				state_compressor: args
+					.depend::<rooms::state_compressor::Service>("rooms::state_compressor"),
+				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
+				user: args.depend::<rooms::user::Service>("rooms::user"),
+			},
+			db: Data {
+				jobid_job: args.db["jobid_job"].clone(),
+				roomid_emptysince: args.db["roomid_emptysince"].clone(),
+				userid_avatarurl: args.db["userid_avatarurl"].clone(),
+				useridprofilekey_value: args.db["useridprofilekey_value"].clone(),
+				db: args.db.clone(),
+			},
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		// Pick up everything which was queued or running when we shut down.
+		let pending: Vec<u64> = self
+			.jobs()
+			.ready_filter(|job| job.status.is_pending())
+			.map(|job| job.id)
+			.collect()
+			.await;
+
+		for id in pending {
+			self.queue(id)?;
+		}
+
+		let receiver = self.job_channel.1.clone();
+		let limit = self
+			.services
+			.server
+			.config
+			.background_job_concurrency
+			.max(1);
+		let mut running = FuturesUnordered::new();
+		let mut sweep = interval(janitor::EMPTY_ROOMS_INTERVAL);
+		sweep.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+		while !receiver.is_closed() {
+			tokio::select! {
+				Some(()) = running.next() => {},
+				_ = sweep.tick() => {
+					if self.services.server.config.purge_empty_rooms_after_days > 0 {
+						self.enqueue_periodic(JobKind::SweepEmptyRooms).await.log_err().ok();
+					}
+
+					if self.services.server.config.notification_log_retention_days > 0 {
+						self.enqueue_periodic(JobKind::PruneNotifications).await.log_err().ok();
+					}
+				},
+				id = receiver.recv_async(), if running.len() < limit => match id {
+					| Err(_) => break,
+					| Ok(id) => running.push(self.run(id)),
+				},
+			}
+		}
+
+		// Running jobs notice the shutdown and save their progress.
+		while running.next().await.is_some() {}
+
+		Ok(())
+	}
+
+	fn interrupt(&self) {
+		let (sender, _) = &self.job_channel;
+		if !sender.is_closed() {
+			sender.close();
+		}
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Persists and queues a new job. Returns its id.
+#[implement(Service)]
+pub fn enqueue(&self, kind: JobKind) -> Result<u64> {
+	let job = Job {
+		id: self.services.globals.next_count()?,
+		kind,
+		status: JobStatus::Queued,
+		done: 0,
+		failed: 0,
+		total: None,
+		created: millis_since_unix_epoch(),
+		report: Vec::new(),
+	};
+
+	self.save(&job);
+	self.queue(job.id)?;
+
+	info!(id = job.id, "Queued job: {}", job.kind);
+	Ok(job.id)
+}
+
+/// Queues a job unless one of the same kind is already queued or running, in
+/// which case it fails with a conflict. For operations which must not overlap.
+#[implement(Service)]
+pub async fn enqueue_exclusive(&self, kind: JobKind) -> Result<u64> {
+	let _lock = self.exclusive.lock().await;
+	let pending = self
+		.jobs()
+		.ready_any(|job| {
+			discriminant(&job.kind) == discriminant(&kind) && job.status.is_pending()
+		})
+		.await;
+
+	if pending {
+		return Err!(Conflict("A job of this kind is already pending."));
+	}
+
+	self.enqueue(kind)
+}
+
+/// Stops a queued or running job. A running job stops before its next item.
+#[implement(Service)]
+pub async fn cancel(&self, id: u64) -> Result {
+	let mut job = self.get_job(id).await?;
+	match job.status {
+		| JobStatus::Queued => {
+			job.status = JobStatus::Cancelled;
+			self.save(&job);
+		},
+		| JobStatus::Running => {
+			self.cancelled.lock().expect("locked").insert(id);
+		},
+		| _ => return Err!(Request(InvalidParam("Job {id} has already finished."))),
+	}
+
+	Ok(())
+}
+
+#[implement(Service)]
+pub async fn get_job(&self, id: u64) -> Result<Job> {
+	self.db
+		.jobid_job
+		.qry(&id)
+		.await
+		.deserialized()
+		.map_err(|_| err!(Request(NotFound("Job {id} not found."))))
+}
+
+/// All jobs, oldest first.
+#[implement(Service)]
+pub fn jobs(&self) -> impl Stream<Item = Job> + Send + '_ {
+	self.db
+		.jobid_job
+		.stream()
+		.ignore_err()
+		.map(|(_, job): (Ignore, Job)| job)
+}
+
+#[implement(Service)]
+fn queue(&self, id: u64) -> Result {
+	self.job_channel
+		.0
+		.send(id)
+		.map_err(|e| err!("Failed to queue job: {e}"))
+}
+
+#[implement(Service)]
+fn save(&self, job: &Job) { self.db.jobid_job.put(job.id, Json(job)); }
+
+#[implement(Service)]
+fn is_cancelled(&self, id: u64) -> bool { self.cancelled.lock().expect("locked").contains(&id) }
+
+#[implement(Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+async fn run(&self, id: u64) {
+	let Ok(mut job) = self.get_job(id).await else {
+		return;
+	};
+
+	if !job.status.is_pending() {
+		debug!("Job {id} is no longer pending");
+		return;
+	}
+
+	job.status = JobStatus::Running;
+	job.done = 0;
+	job.failed = 0;
+	job.total = None;
+	job.report.clear();
+	self.save(&job);
+
+	let result = match job.kind.clone() {
+		| JobKind::DeleteMedia { mxcs } => self.delete_media(&mut job, mxcs).await,
+		| JobKind::DeleteUserMedia { user_id } => {
+			let mxcs = self.services.media.get_user_mxcs(&user_id).await;
+			self.delete_media(&mut job, mxcs).await
+		},
+		| JobKind::DeleteServerMedia { server_name } => {
+			let mxcs = self.services.media.get_all_mxcs().await.map(|mxcs| {
+				mxcs.into_iter()
+					.filter(|mxc| mxc.server_name().is_ok_and(|s| s == &*server_name))
+					.collect()
+			});
+
+			match mxcs {
+				| Ok(mxcs) => self.delete_media(&mut job, mxcs).await,
+				| Err(e) => Err(e),
+			}
+		},
+		| JobKind::DeleteMediaAtAfterTime { time, before, after, include_local } => {
+			let time = UNIX_EPOCH + Duration::from_millis(time);
+			let mxcs = self
+				.services
+				.media
+				.get_remote_media_at_after_time(time, before, after, include_local)
+				.await;
+
+			match mxcs {
+				| Ok(mxcs) => self.delete_media(&mut job, mxcs).await,
+				| Err(e) => Err(e),
+			}
+		},
+		| JobKind::DeleteRoom { room_id, block, purge, message } =>
+			self.delete_room(&mut job, room_id, block, purge, message)
+				.await,
+		| JobKind::CreateUsers { users } => self.create_users(&mut job, users).await,
+		| JobKind::ImportRoom { room_id, path } =>
+			self.import_room(&mut job, &room_id, &path).await,
+		| JobKind::SweepEmptyRooms => self.sweep_empty_rooms(&mut job).await,
+		| JobKind::PruneNotifications => self.prune_notifications().await,
+		| JobKind::BackupDatabase => self.backup_database(&mut job).await,
+	};
+
+	let cancelled = self.cancelled.lock().expect("locked").remove(&id);
+
+	let shutting_down = !self.services.server.running();
+	let Some(status) = JobStatus::after_run(result, cancelled, shutting_down) else {
+		self.save(&job);
+		return;
+	};
+
+	job.status = status;
+
+	info!(id, done = job.done, failed = job.failed, "Job finished: {:?}", job.status);
+	self.save(&job);
+}
+
+/// Creates a database backup, reporting what was written.
+#[implement(Service)]
+async fn backup_database(&self, job: &mut Job) -> Result {
+	let db = Arc::clone(&self.db.db);
+	let created = self
+		.services
+		.server
+		.runtime()
+		.spawn_blocking(move || db.db.backup())
+		.await??;
+
+	job.report.push(created.map_or_else(
+		|| "database_backups_to_keep is 0; purged the existing backups instead.".to_owned(),
+		|info| {
+			format!(
+				"Created and verified backup #{}: {} bytes, {} files.",
+				info.id, info.size, info.files
+			)
+		},
+	));
+
+	Ok(())
+}
+
+/// Applies `f` to each item in turn, recording progress in the job. Stops
+/// early when the job is cancelled or the server shuts down.
+#[implement(Service)]
+async fn process<T, F, Fut>(&self, job: &mut Job, items: Vec<T>, f: F) -> Result
+where
+	T: fmt::Display + Send,
+	F: Fn(T) -> Fut + Send,
+	Fut: Future<Output = Result> + Send,
+{
+	job.total = Some(items.len().try_into()?);
+	self.save(job);
+
+	for item in items {
+		if self.is_cancelled(job.id) || !self.services.server.running() {
+			break;
+		}
+
+		let name = item.to_string();
+		if let Err(e) = f(item).await {
+			debug_warn!(id = job.id, "Job failed on {name}: {e}");
+			job.failed = job.failed.saturating_add(1);
+		}
+
+		job.done = job.done.saturating_add(1);
+		if job.done % PERSIST_INTERVAL == 0 {
+			self.save(job);
+		}
+	}
+
+	Ok(())
+}
+
+#[implement(Service)]
+async fn delete_media(&self, job: &mut Job, mxcs: Vec<OwnedMxcUri>) -> Result {
+	self.process(job, mxcs, |mxc| async move {
+		let mxc: Mxc<'_> = mxc.as_str().try_into()?;
+		self.services.media.delete(&mxc).await
+	})
+	.await
+}
+
+#[implement(Service)]
+async fn delete_room(
+	&self,
+	job: &mut Job,
+	room_id: OwnedRoomId,
+	block: bool,
+	purge: bool,
+	message: Option<String>,
+) -> Result {
+	if block {
+		self.services.metadata.ban_room(&room_id, true);
+	}
+
+	let local_users: Vec<OwnedUserId> = self
+		.services
+		.state_cache
+		.local_users_in_room(&room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let (room_id, message) = (&room_id, message.as_deref());
+	self.process(job, local_users, |user_id| async move {
+		let state_lock = self.services.state.mutex.lock(room_id).await;
+		self.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(
+					user_id.to_string(),
+					&RoomMemberEventContent::new(MembershipState::Leave),
+				),
+				&user_id,
+				room_id,
+				&state_lock,
+			)
+			.await?;
+
+		drop(state_lock);
+		if let Some(message) = message {
+			if let Err(e) = self
+				.services
+				.server_notices
+				.send_notice(&user_id, message)
+				.await
+			{
+				debug_warn!(%user_id, "Failed to notify user of room deletion: {e}");
+			}
+		}
+
+		Ok(())
+	})
+	.await?;
+
+	if self.is_cancelled(job.id) || !self.services.server.running() {
+		return Ok(());
+	}
+
+	let local_aliases: Vec<OwnedRoomAliasId> = self
+		.services
+		.alias
+		.local_aliases_for_room(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for alias in &local_aliases {
+		self.services
+			.alias
+			.remove_alias(alias, &self.services.globals.server_user)
+			.await
+			.ok();
+	}
+
+	self.services.directory.set_not_public(room_id);
+
+	if purge {
+		self.purge_room(job, room_id, true).await?;
+	}
+
+	Ok(())
+}
+
+#[implement(Service)]
+async fn create_users(&self, job: &mut Job, users: Vec<NewUser>) -> Result {
+	let report = StdMutex::new(Vec::with_capacity(users.len()));
+	let result = self
+		.process(job, users, |new_user| {
+			let report = &report;
+			async move {
+				let outcome = self.services.onboarding.provision(&new_user).await;
+				let line = match &outcome {
+					| Ok(user_id) => format!("{user_id}: created"),
+					| Err(e) => format!("{}: failed: {e}", new_user.localpart),
+				};
+
+				report.lock().expect("locked").push(line);
+				outcome.map(|_| ())
+			}
+		})
+		.await;
+
+	job.report = report.into_inner().expect("locked");
+	result
+}
+
+impl fmt::Display for JobKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			| Self::DeleteMedia { mxcs } => write!(f, "delete {} media files", mxcs.len()),
+			| Self::DeleteUserMedia { user_id } => write!(f, "delete media of {user_id}"),
+			| Self::DeleteServerMedia { server_name } =>
+				write!(f, "delete media from {server_name}"),
+			| Self::DeleteMediaAtAfterTime { before, .. } => write!(
+				f,
+				"delete media created {} a point in time",
+				if *before { "since" } else { "before" }
+			),
+			| Self::DeleteRoom { room_id, .. } => write!(f, "delete room {room_id}"),
+			| Self::CreateUsers { users } => write!(f, "create {} users", users.len()),
+			| Self::ImportRoom { room_id, .. } => write!(f, "import room {room_id}"),
+			| Self::SweepEmptyRooms => write!(f, "purge rooms without local members"),
+			| Self::PruneNotifications => write!(f, "prune the notification log"),
+			| Self::BackupDatabase => write!(f, "back up the database"),
+		}
+	}
+}
+
+impl fmt::Display for JobStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			| Self::Queued => write!(f, "queued"),
+			| Self::Running => write!(f, "running"),
+			| Self::Completed => write!(f, "completed"),
+			| Self::Failed(e) => write!(f, "failed: {e}"),
+			| Self::Cancelled => write!(f, "cancelled"),
+		}
+	}
+}
@@ -0,0 +1,214 @@
+use std::{collections::BTreeSet, fmt::Debug, sync::Arc};
+
+use conduwuit::{
+	debug, implement, info,
+	utils::{stream::TryIgnore, ReadyExt},
+	Result,
+};
+use database::{Interfix, Map};
+use futures::StreamExt;
+use ruma::{Mxc, OwnedMxcUri, OwnedServerName, OwnedUserId, RoomId};
+use serde::Serialize;
+
+use super::Job;
+use crate::media::collect_mxcs;
+
+/// Maps keyed by the room ID alone.
+const ROOM_KEYED: &[&str] = &[
+	"publicroomids",
+	"roomid_emptysince",
+	"roomid_invitedcount",
+	"roomid_inviteviaservers",
+	"roomid_joinedcount",
+	"roomid_partialstate",
+	"roomid_policylist",
+];
+
+/// Maps keyed by the room ID, then more.
+const ROOM_PREFIXED: &[&str] = &[
+	"readreceiptid_readreceipt",
+	"referencedevents",
+	"roomcount_rejectedpdu",
+	"roomeventid_partialstatequeue",
+	"roomserverids",
+	"roomuserdataid_accountdata",
+	"roomuserid_invitecount",
+	"roomuserid_joined",
+	"roomuserid_knockedcount",
+	"roomuserid_lastprivatereadupdate",
+	"roomuserid_leftcount",
+	"roomuserid_privateread",
+	"roomuseroncejoinedids",
+	"roomusertype_roomuserdataid",
+];
+
+/// Maps keyed by a user ID, then the room ID and possibly more.
+const USER_ROOM_PREFIXED: &[&str] = &[
+	"userroomcount_unreadnotification",
+	"userroomid_highlightcount",
+	"userroomid_invitestate",
+	"userroomid_joined",
+	"userroomid_knockedstate",
+	"userroomid_leftstate",
+	"userroomid_notificationcount",
+	"userroomid_unreadcount",
+];
+
+/// Deletes the events and state of a room and everything stored about it per
+/// user and server. With `media`, also the media its events refer to which no
+/// other room or profile refers to.
+#[implement(super::Service)]
+pub(super) async fn purge_room(&self, job: &Job, room_id: &RoomId, media: bool) -> Result {
+	let mut mxcs = BTreeSet::new();
+	if media {
+		self.services
+			.timeline
+			.pdus(None, room_id, None)
+			.ignore_err()
+			.ready_for_each(|(_, pdu)| {
+				if let Ok(content) = serde_json::from_str(pdu.content.get()) {
+					collect_mxcs(&content, &mut mxcs);
+				}
+			})
+			.await;
+
+		self.retain_unreferenced(room_id, &mut mxcs).await;
+	}
+
+	for uri in &mxcs {
+		if self.is_cancelled(job.id) || !self.services.server.running() {
+			return Ok(());
+		}
+
+		let Ok(mxc) = Mxc::try_from(uri.as_str()) else {
+			continue;
+		};
+
+		if let Err(e) = self.services.media.delete(&mxc).await {
+			debug!(%room_id, "Failed to delete media {uri}: {e}");
+		}
+	}
+
+	let state_lock = self.services.state.mutex.lock(room_id).await;
+	let purged = self.services.timeline.purge_room(room_id).await?;
+	self.services
+		.state
+		.delete_room_state(room_id, &state_lock)
+		.await;
+
+	self.remove_room_keys(room_id).await;
+	drop(state_lock);
+
+	info!(%room_id, "Purged {purged} events and {} media files", mxcs.len());
+
+	Ok(())
+}
+
+/// Drops the media which another room's events or a profile refer to. Every
+/// other room is read through, so this takes long on large servers.
+#[implement(super::Service)]
+async fn retain_unreferenced(&self, room_id: &RoomId, mxcs: &mut BTreeSet<OwnedMxcUri>) {
+	for map in [&self.db.userid_avatarurl, &self.db.useridprofilekey_value] {
+		map.raw_stream()
+			.ignore_err()
+			.ready_for_each(|(_, value)| {
+				let Ok(value) = std::str::from_utf8(value) else {
+					return;
+				};
+
+				let mut referenced = BTreeSet::new();
+				match serde_json::from_str(value) {
+					| Ok(value) => collect_mxcs(&value, &mut referenced),
+					| Err(_) => collect_mxcs(&value.into(), &mut referenced),
+				}
+
+				mxcs.retain(|mxc| !referenced.contains(mxc));
+			})
+			.await;
+	}
+
+	let other_rooms: Vec<_> = self
+		.services
+		.metadata
+		.iter_ids()
+		.ready_filter(|other| *other != room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for other in &other_rooms {
+		if mxcs.is_empty() || !self.services.server.running() {
+			break;
+		}
+
+		self.services
+			.timeline
+			.pdus(None, other, None)
+			.ignore_err()
+			.ready_for_each(|(_, pdu)| {
+				let mut referenced = BTreeSet::new();
+				if let Ok(content) = serde_json::from_str(pdu.content.get()) {
+					collect_mxcs(&content, &mut referenced);
+				}
+
+				mxcs.retain(|mxc| !referenced.contains(mxc));
+			})
+			.await;
+	}
+}
+
+/// Removes the room from the maps storing something about it per user,
+/// server or the room itself. Its short ID and whether it's banned, disabled
+/// or tombstoned are kept.
+#[implement(super::Service)]
+async fn remove_room_keys(&self, room_id: &RoomId) {
+	let state_cache = &self.services.state_cache;
+	let mut users: BTreeSet<OwnedUserId> = state_cache
+		.room_useroncejoined(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for members in [
+		state_cache.room_members_invited(room_id).boxed(),
+		state_cache.room_members_knocked(room_id).boxed(),
+	] {
+		users.extend(members.map(ToOwned::to_owned).collect::<Vec<_>>().await);
+	}
+
+	let servers: Vec<OwnedServerName> = state_cache
+		.room_servers(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for user_id in &users {
+		for name in USER_ROOM_PREFIXED {
+			let map = &self.db.db[*name];
+			map.del((user_id, room_id));
+			remove_prefixed(map, &(user_id, room_id, Interfix)).await;
+		}
+	}
+
+	for server in &servers {
+		self.db.db["serverroomids"].del((server, room_id));
+	}
+
+	for name in ROOM_PREFIXED {
+		remove_prefixed(&self.db.db[*name], &(room_id, Interfix)).await;
+	}
+
+	for name in ROOM_KEYED {
+		self.db.db[*name].remove(room_id);
+	}
+}
+
+async fn remove_prefixed<P>(map: &Arc<Map>, prefix: &P)
+where
+	P: Serialize + ?Sized + Debug,
+{
+	map.keys_prefix_raw(prefix)
+		.ignore_err()
+		.ready_for_each(|key| map.remove(key))
+		.await;
+}
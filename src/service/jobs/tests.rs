@@ -0,0 +1,66 @@
+#![cfg(test)]
+
+use conduwuit::err;
+
+use super::{Job, JobKind, JobStatus};
+
+#[test]
+fn pending_statuses() {
+	assert!(JobStatus::Queued.is_pending());
+	assert!(JobStatus::Running.is_pending());
+	assert!(!JobStatus::Completed.is_pending());
+	assert!(!JobStatus::Failed(String::new()).is_pending());
+	assert!(!JobStatus::Cancelled.is_pending());
+}
+
+#[test]
+fn finished_run_completes() {
+	let status = JobStatus::after_run(Ok(()), false, false);
+	assert!(matches!(status, Some(JobStatus::Completed)));
+}
+
+#[test]
+fn cancelled_run_is_cancelled() {
+	let status = JobStatus::after_run(Ok(()), true, false);
+	assert!(matches!(status, Some(JobStatus::Cancelled)));
+
+	let status = JobStatus::after_run(Ok(()), true, true);
+	assert!(matches!(status, Some(JobStatus::Cancelled)));
+}
+
+#[test]
+fn interrupted_run_stays_running() {
+	let status = JobStatus::after_run(Ok(()), false, true);
+	assert!(status.is_none());
+}
+
+#[test]
+fn failed_run_keeps_error() {
+	let status = JobStatus::after_run(Err(err!("disk full")), false, true);
+	assert!(matches!(status, Some(JobStatus::Failed(e)) if e.contains("disk full")));
+}
+
+#[test]
+fn delete_room_job_from_before_purge() {
+	let job: Job = serde_json::from_str(
+		r#"{
+			"id": 1,
+			"kind": {"delete_room": {"room_id": "!room:example.com", "block": true}},
+			"status": "running",
+			"done": 3,
+			"failed": 0,
+			"total": null,
+			"created": 0
+		}"#,
+	)
+	.unwrap();
+
+	assert!(job.status.is_pending());
+	assert!(job.report.is_empty());
+	assert!(matches!(job.kind, JobKind::DeleteRoom {
+		block: true,
+		purge: false,
+		message: None,
+		..
+	}));
+}
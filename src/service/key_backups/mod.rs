@@ -1,26 +1,41 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
 
 use conduwuit::{
 	err, implement,
-	utils::stream::{ReadyExt, TryIgnore},
+	utils::{
+		stream::{ReadyExt, TryIgnore},
+		MutexMap,
+	},
 	Err, Result,
 };
-use database::{Deserialized, Ignore, Interfix, Json, Map};
+use database::{Database, Deserialized, Ignore, Interfix, Json, Map};
 use futures::StreamExt;
 use ruma::{
 	api::client::backup::{BackupAlgorithm, KeyBackupData, RoomKeyBackup},
 	serde::Raw,
-	OwnedRoomId, RoomId, UserId,
+	OwnedRoomId, OwnedUserId, RoomId, UserId,
 };
+use serde::Serialize;
 
 use crate::{globals, Dep};
 
 pub struct Service {
+	/// Serializes changes to a user's backups, so the count and etag returned
+	/// for a request are the ones right after its changes.
+	write_mutex: MutexMap<OwnedUserId, ()>,
 	db: Data,
 	services: Services,
 }
 
+/// The number of keys and etag of a backup version, taken together.
+#[derive(Clone, Debug)]
+pub struct BackupState {
+	pub count: usize,
+	pub etag: String,
+}
+
 struct Data {
+	db: Arc<Database>,
 	backupid_algorithm: Arc<Map>,
 	backupid_etag: Arc<Map>,
 	backupkeyid_backup: Arc<Map>,
@@ -33,7 +48,9 @@ struct Services {
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
+			write_mutex: MutexMap::new(),
 			db: Data {
+				db: args.db.clone(),
 				backupid_algorithm: args.db["backupid_algorithm"].clone(),
 				backupid_etag: args.db["backupid_etag"].clone(),
 				backupkeyid_backup: args.db["backupkeyid_backup"].clone(),
@@ -66,6 +83,9 @@ pub fn create_backup(
 
 #[implement(Service)]
 pub async fn delete_backup(&self, user_id: &UserId, version: &str) {
+	let _lock = self.write_mutex.lock(user_id).await;
+	let _cork = self.db.db.cork();
+
 	let key = (user_id, version);
 	self.db.backupid_algorithm.del(key);
 	self.db.backupid_etag.del(key);
@@ -88,12 +108,13 @@ pub async fn update_backup<'a>(
 	version: &'a str,
 	backup_metadata: &Raw<BackupAlgorithm>,
 ) -> Result<&'a str> {
+	let _lock = self.write_mutex.lock(user_id).await;
 	let key = (user_id, version);
 	if self.db.backupid_algorithm.qry(&key).await.is_err() {
 		return Err!(Request(NotFound("Tried to update nonexistent backup.")));
 	}
 
-	let count = self.services.globals.next_count().unwrap();
+	let count = self.services.globals.next_count()?;
 	self.db.backupid_etag.put(key, count);
 	self.db
 		.backupid_algorithm
@@ -144,27 +165,91 @@ pub async fn get_backup(&self, user_id: &UserId, version: &str) -> Result<Raw<Ba
 	self.db.backupid_algorithm.qry(&key).await.deserialized()
 }
 
+/// Adds or replaces the given keys of a backup. The keys of one request are
+/// written in a single batch and change the etag once, so a request failing
+/// or cancelled part way leaves the backup as it was; returns the backup's
+/// count and etag right after.
 #[implement(Service)]
-pub async fn add_key(
+pub async fn add_keys<'a, I>(
 	&self,
 	user_id: &UserId,
 	version: &str,
-	room_id: &RoomId,
-	session_id: &str,
-	key_data: &Raw<KeyBackupData>,
-) -> Result<()> {
+	keys: I,
+) -> Result<BackupState>
+where
+	I: IntoIterator<Item = (&'a RoomId, &'a str, &'a Raw<KeyBackupData>)> + Send,
+	I::IntoIter: Send,
+{
+	let _lock = self.write_mutex.lock(user_id).await;
+	self.check_exists(user_id, version).await?;
+
+	let mut batch = Vec::new();
+	for (room_id, session_id, key_data) in keys {
+		let key = (user_id, version, room_id, session_id);
+		if let Ok(current) = self.db.backupkeyid_backup.qry(&key).await.deserialized() {
+			if !replaces(&current, key_data) {
+				continue;
+			}
+		}
+
+		batch.push((database::serialize_key(key)?, key_data.json().get().as_bytes()));
+	}
+
+	let count = self.services.globals.next_count()?;
+	{
+		let _cork = self.db.db.cork();
+		self.db.backupkeyid_backup.insert_batch(batch.into_iter());
+		self.db.backupid_etag.put((user_id, version), count);
+	}
+
+	Ok(self.state_locked(user_id, version).await)
+}
+
+/// Whether an uploaded key should replace the stored one of the session: a
+/// verified key wins over an unverified one, then the key which decrypts
+/// earlier messages, then the one forwarded less often. The key is stored as
+/// uploaded, keeping fields such as MSC3061's `shared_history` flag.
+fn replaces(current: &Raw<KeyBackupData>, new: &Raw<KeyBackupData>) -> bool {
+	let (Ok(current), Ok(new)) = (current.deserialize(), new.deserialize()) else {
+		return true;
+	};
+
+	let rank =
+		|key: &KeyBackupData| (!key.is_verified, key.first_message_index, key.forwarded_count);
+
+	rank(&new) <= rank(&current)
+}
+
+/// Returns the number of keys and the etag of a backup as of the same
+/// moment.
+#[implement(Service)]
+pub async fn backup_state(&self, user_id: &UserId, version: &str) -> BackupState {
+	let _lock = self.write_mutex.lock(user_id).await;
+	self.state_locked(user_id, version).await
+}
+
+#[implement(Service)]
+async fn state_locked(&self, user_id: &UserId, version: &str) -> BackupState {
+	BackupState {
+		count: self.count_keys(user_id, version).await,
+		etag: self.get_etag(user_id, version).await,
+	}
+}
+
+#[implement(Service)]
+async fn check_exists(&self, user_id: &UserId, version: &str) -> Result {
 	let key = (user_id, version);
 	if self.db.backupid_algorithm.qry(&key).await.is_err() {
 		return Err!(Request(NotFound("Tried to update nonexistent backup.")));
 	}
 
-	let count = self.services.globals.next_count().unwrap();
-	self.db.backupid_etag.put(key, count);
+	Ok(())
+}
 
-	let key = (user_id, version, room_id, session_id);
-	self.db
-		.backupkeyid_backup
-		.put_raw(key, key_data.json().get());
+#[implement(Service)]
+fn bump_etag(&self, user_id: &UserId, version: &str) -> Result {
+	let count = self.services.globals.next_count()?;
+	self.db.backupid_etag.put((user_id, version), count);
 
 	Ok(())
 }
@@ -256,27 +341,47 @@ pub async fn get_session(
 }
 
 #[implement(Service)]
-pub async fn delete_all_keys(&self, user_id: &UserId, version: &str) {
+pub async fn delete_all_keys(&self, user_id: &UserId, version: &str) -> Result<BackupState> {
+	let _lock = self.write_mutex.lock(user_id).await;
+	self.check_exists(user_id, version).await?;
+
 	let key = (user_id, version, Interfix);
-	self.db
-		.backupkeyid_backup
-		.keys_prefix_raw(&key)
-		.ignore_err()
-		.ready_for_each(|outdated_key| self.db.backupkeyid_backup.remove(outdated_key))
-		.await;
+	self.delete_prefix(&key).await;
+	self.bump_etag(user_id, version)?;
+
+	Ok(self.state_locked(user_id, version).await)
 }
 
+/// Deletes the keys of all of the given rooms from a backup, changing the
+/// etag once.
 #[implement(Service)]
-pub async fn delete_room_keys(&self, user_id: &UserId, version: &str, room_id: &RoomId) {
-	let key = (user_id, version, room_id, Interfix);
-	self.db
-		.backupkeyid_backup
-		.keys_prefix_raw(&key)
-		.ignore_err()
-		.ready_for_each(|outdated_key| {
-			self.db.backupkeyid_backup.remove(outdated_key);
-		})
-		.await;
+pub async fn delete_rooms_keys(
+	&self,
+	user_id: &UserId,
+	version: &str,
+	room_ids: &[&RoomId],
+) -> Result<BackupState> {
+	let _lock = self.write_mutex.lock(user_id).await;
+	self.check_exists(user_id, version).await?;
+
+	for room_id in room_ids {
+		let key = (user_id, version, room_id, Interfix);
+		self.delete_prefix(&key).await;
+	}
+
+	self.bump_etag(user_id, version)?;
+
+	Ok(self.state_locked(user_id, version).await)
+}
+
+#[implement(Service)]
+pub async fn delete_room_keys(
+	&self,
+	user_id: &UserId,
+	version: &str,
+	room_id: &RoomId,
+) -> Result<BackupState> {
+	self.delete_rooms_keys(user_id, version, &[room_id]).await
 }
 
 #[implement(Service)]
@@ -286,14 +391,27 @@ pub async fn delete_room_key(
 	version: &str,
 	room_id: &RoomId,
 	session_id: &str,
-) {
+) -> Result<BackupState> {
+	let _lock = self.write_mutex.lock(user_id).await;
+	self.check_exists(user_id, version).await?;
+
 	let key = (user_id, version, room_id, session_id);
+	self.db.backupkeyid_backup.del(key);
+	self.bump_etag(user_id, version)?;
+
+	Ok(self.state_locked(user_id, version).await)
+}
+
+#[implement(Service)]
+async fn delete_prefix<K>(&self, prefix: &K)
+where
+	K: Serialize + ?Sized + Debug + Sync,
+{
+	let _cork = self.db.db.cork();
 	self.db
 		.backupkeyid_backup
-		.keys_prefix_raw(&key)
+		.keys_prefix_raw(prefix)
 		.ignore_err()
-		.ready_for_each(|outdated_key| {
-			self.db.backupkeyid_backup.remove(outdated_key);
-		})
+		.ready_for_each(|outdated_key| self.db.backupkeyid_backup.remove(outdated_key))
 		.await;
 }
@@ -0,0 +1,86 @@
+use std::{
+	borrow::Borrow,
+	hash::Hash,
+	ops::{Deref, DerefMut},
+};
+
+/// An LRU cache counting its lookups, so its effectiveness can be reported in
+/// the admin room. Everything but lookups is passed through to the inner
+/// cache.
+pub struct LruCache<K: Eq + Hash, V> {
+	cache: lru_cache::LruCache<K, V>,
+	hits: u64,
+	misses: u64,
+}
+
+/// Usage of a cache since startup.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+	/// Number of entries currently held.
+	pub entries: usize,
+
+	pub capacity: usize,
+
+	/// Lookups which found an entry.
+	pub hits: u64,
+
+	/// Lookups which found none.
+	pub misses: u64,
+}
+
+impl<K: Eq + Hash, V> LruCache<K, V> {
+	#[must_use]
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			cache: lru_cache::LruCache::new(capacity),
+			hits: 0,
+			misses: 0,
+		}
+	}
+
+	/// Looks up an entry, marking it as the most recently used.
+	pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+	where
+		K: Borrow<Q>,
+		Q: Eq + Hash + ?Sized,
+	{
+		let entry = self.cache.get_mut(key);
+		if entry.is_some() {
+			self.hits = self.hits.saturating_add(1);
+		} else {
+			self.misses = self.misses.saturating_add(1);
+		}
+
+		entry
+	}
+
+	#[must_use]
+	pub fn stats(&self) -> CacheStats {
+		CacheStats {
+			entries: self.cache.len(),
+			capacity: self.cache.capacity(),
+			hits: self.hits,
+			misses: self.misses,
+		}
+	}
+}
+
+impl<K: Eq + Hash, V> Deref for LruCache<K, V> {
+	type Target = lru_cache::LruCache<K, V>;
+
+	fn deref(&self) -> &Self::Target { &self.cache }
+}
+
+impl<K: Eq + Hash, V> DerefMut for LruCache<K, V> {
+	fn deref_mut(&mut self) -> &mut Self::Target { &mut self.cache }
+}
+
+impl CacheStats {
+	/// Share of lookups which found an entry, if there were any.
+	#[must_use]
+	pub fn hit_ratio(&self) -> Option<f64> {
+		let lookups = self.hits.saturating_add(self.misses);
+		#[allow(clippy::cast_precision_loss)]
+		(lookups > 0).then(|| self.hits as f64 / lookups as f64)
+	}
+}
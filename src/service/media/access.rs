@@ -0,0 +1,169 @@
+use std::collections::BTreeSet;
+
+use conduwuit::{implement, utils::stream::TryIgnore, Err, PduEvent, Result};
+use database::{Ignore, Interfix};
+use futures::{Stream, StreamExt};
+use ruma::{EventId, Mxc, OwnedMxcUri, RoomId, ServerName, UserId};
+
+/// Checks whether a local user may download media over the authenticated
+/// endpoints. Media uploaded here is visible to its uploader, to admins and to
+/// users who can see an event referring to it. Media fetched from other
+/// servers was already checked by them.
+#[implement(super::Service)]
+pub async fn check_user_access(&self, user: &UserId, mxc: &Mxc<'_>) -> Result {
+	if !self.services.server.config.restrict_media_access {
+		return Ok(());
+	}
+
+	let Some(uploader) = self.db.get_uploader(mxc).await else {
+		return Ok(());
+	};
+
+	if uploader == user
+		|| self.services.admin.user_is_admin(user).await
+		|| self.is_public_media(&uploader, mxc).await
+		|| self
+			.references(mxc)
+			.any(|(room_id, event_id)| {
+				self.services
+					.state_accessor
+					.user_can_see_event(user, room_id, event_id)
+			})
+			.await
+	{
+		return Ok(());
+	}
+
+	Err!(Request(NotFound("Media not found.")))
+}
+
+/// Checks whether another server may download media uploaded here. It must be
+/// able to see an event referring to it.
+#[implement(super::Service)]
+pub async fn check_server_access(&self, origin: &ServerName, mxc: &Mxc<'_>) -> Result {
+	if !self.services.server.config.restrict_media_access {
+		return Ok(());
+	}
+
+	let Some(uploader) = self.db.get_uploader(mxc).await else {
+		return Ok(());
+	};
+
+	if self.is_public_media(&uploader, mxc).await
+		|| self
+			.references(mxc)
+			.any(|(room_id, event_id)| {
+				self.services
+					.state_accessor
+					.server_can_see_event(origin, room_id, event_id)
+			})
+			.await
+	{
+		return Ok(());
+	}
+
+	Err!(Request(NotFound("Media not found.")))
+}
+
+/// Checks whether media uploaded here may be served over the legacy
+/// unauthenticated endpoints, where nobody's identity is known.
+#[implement(super::Service)]
+pub async fn check_legacy_access(&self, mxc: &Mxc<'_>) -> Result {
+	if !self.services.server.config.restrict_media_access {
+		return Ok(());
+	}
+
+	let Some(uploader) = self.db.get_uploader(mxc).await else {
+		return Ok(());
+	};
+
+	if self.is_public_media(&uploader, mxc).await {
+		return Ok(());
+	}
+
+	Err!(Request(NotFound("Media not found.")))
+}
+
+/// Media anyone may see without sharing a room with the uploader: their
+/// avatar, shown in profile lookups, and the avatars of rooms they're in which
+/// are published in the room directory.
+#[implement(super::Service)]
+async fn is_public_media(&self, uploader: &UserId, mxc: &Mxc<'_>) -> bool {
+	let mxc = mxc.to_string();
+	if self
+		.services
+		.users
+		.avatar_url(uploader)
+		.await
+		.is_ok_and(|avatar_url| avatar_url.as_str() == mxc)
+	{
+		return true;
+	}
+
+	let mxc = mxc.as_str();
+
+	self.services
+		.state_cache
+		.rooms_joined(uploader)
+		.filter(|room_id| self.services.directory.is_public_room(room_id))
+		.any(|room_id| async move {
+			self.services
+				.state_accessor
+				.get_avatar(room_id)
+				.await
+				.into_option()
+				.and_then(|avatar| avatar.url)
+				.is_some_and(|url| url.as_str() == mxc)
+		})
+		.await
+}
+
+/// Records the media uploaded here which an event refers to, so that who may
+/// download it follows who may see the event.
+#[implement(super::Service)]
+pub fn index_references(&self, pdu: &PduEvent) {
+	let Ok(content) = serde_json::from_str(pdu.content.get()) else {
+		return;
+	};
+
+	let mut mxcs = BTreeSet::new();
+	collect_mxcs(&content, &mut mxcs);
+	for mxc in &mxcs {
+		let Ok(mxc) = Mxc::try_from(mxc.as_str()) else {
+			continue;
+		};
+
+		if self.services.globals.server_is_ours(mxc.server_name) {
+			self.db
+				.mediaid_eventid
+				.put_raw((&mxc, &pdu.event_id), &pdu.room_id);
+		}
+	}
+}
+
+/// The events referring to the media, with their rooms.
+#[implement(super::Service)]
+fn references<'a>(
+	&'a self,
+	mxc: &Mxc<'_>,
+) -> impl Stream<Item = (&'a RoomId, &'a EventId)> + Send + 'a {
+	self.db
+		.mediaid_eventid
+		.stream_prefix(&(mxc, Interfix))
+		.ignore_err()
+		.map(|((_, event_id), room_id): ((Ignore, &EventId), &RoomId)| (room_id, event_id))
+}
+
+/// Gathers every `mxc://` URI found anywhere in an event's content.
+pub(crate) fn collect_mxcs(value: &serde_json::Value, mxcs: &mut BTreeSet<OwnedMxcUri>) {
+	match value {
+		| serde_json::Value::String(s) if s.starts_with("mxc://") => {
+			mxcs.insert(s.as_str().into());
+		},
+		| serde_json::Value::Array(values) =>
+			values.iter().for_each(|value| collect_mxcs(value, mxcs)),
+		| serde_json::Value::Object(map) =>
+			map.values().for_each(|value| collect_mxcs(value, mxcs)),
+		| _ => (),
+	}
+}
@@ -5,15 +5,21 @@
 	utils::{str_from_bytes, stream::TryIgnore, string_from_bytes, ReadyExt},
 	Err, Result,
 };
-use database::{Database, Interfix, Map};
+use database::{Database, Ignore, Interfix, Map};
 use futures::StreamExt;
-use ruma::{http_headers::ContentDisposition, Mxc, OwnedMxcUri, UserId};
+use ruma::{http_headers::ContentDisposition, Mxc, OwnedMxcUri, OwnedUserId, UserId};
 
 use super::{preview::UrlPreviewData, thumbnail::Dim};
 
 pub(crate) struct Data {
+	pub(super) mediaid_eventid: Arc<Map>,
 	mediaid_file: Arc<Map>,
+	pub(super) mediaid_lastaccess: Arc<Map>,
+	pub(super) mediaid_pendingupload: Arc<Map>,
+	pub(super) mediaid_quarantine: Arc<Map>,
 	mediaid_user: Arc<Map>,
+	pub(super) userid_mediausage: Arc<Map>,
+	pub(super) usermediaid_pendingupload: Arc<Map>,
 	url_previews: Arc<Map>,
 }
 
@@ -27,8 +33,14 @@ pub(super) struct Metadata {
 impl Data {
 	pub(super) fn new(db: &Arc<Database>) -> Self {
 		Self {
+			mediaid_eventid: db["mediaid_eventid"].clone(),
 			mediaid_file: db["mediaid_file"].clone(),
+			mediaid_lastaccess: db["mediaid_lastaccess"].clone(),
+			mediaid_pendingupload: db["mediaid_pendingupload"].clone(),
+			mediaid_quarantine: db["mediaid_quarantine"].clone(),
 			mediaid_user: db["mediaid_user"].clone(),
+			userid_mediausage: db["userid_mediausage"].clone(),
+			usermediaid_pendingupload: db["usermediaid_pendingupload"].clone(),
 			url_previews: db["url_previews"].clone(),
 		}
 	}
@@ -63,6 +75,15 @@ pub(super) async fn delete_file_mxc(&self, mxc: &Mxc<'_>) {
 			.ready_for_each(|key| self.mediaid_file.remove(key))
 			.await;
 
+		self.mediaid_lastaccess.remove(&mxc.to_string());
+		self.mediaid_quarantine.remove(&mxc.to_string());
+
+		self.mediaid_eventid
+			.keys_prefix_raw(&prefix)
+			.ignore_err()
+			.ready_for_each(|key| self.mediaid_eventid.remove(key))
+			.await;
+
 		self.mediaid_user
 			.stream_prefix_raw(&prefix)
 			.ignore_err()
@@ -142,6 +163,16 @@ pub(super) async fn search_file_metadata(
 		Ok(Metadata { content_disposition, content_type, key })
 	}
 
+	/// Gets the user who uploaded the media, if it was uploaded here.
+	pub(super) async fn get_uploader(&self, mxc: &Mxc<'_>) -> Option<OwnedUserId> {
+		self.mediaid_user
+			.stream_prefix(&(mxc, Interfix))
+			.ignore_err()
+			.map(|(_, user): (Ignore, &UserId)| user.to_owned())
+			.next()
+			.await
+	}
+
 	/// Gets all the MXCs associated with a user
 	pub(super) async fn get_all_user_mxcs(&self, user_id: &UserId) -> Vec<OwnedMxcUri> {
 		self.mediaid_user
@@ -212,17 +243,18 @@ pub(super) fn set_url_preview(
 		Ok(())
 	}
 
-	pub(super) async fn get_url_preview(&self, url: &str) -> Result<UrlPreviewData> {
+	/// Returns the cached preview with when it was fetched, in seconds since
+	/// the unix epoch.
+	pub(super) async fn get_url_preview(&self, url: &str) -> Result<(UrlPreviewData, u64)> {
 		let values = self.url_previews.get(url).await?;
 
-		let mut values = values.split(|&b| b == 0xFF);
+		let timestamp = values
+			.get(..8)
+			.map(|b| u64::from_be_bytes(b.try_into().expect("8 bytes")))
+			.unwrap_or_default();
 
-		let _ts = values.next();
-		/* if we ever decide to use timestamp, this is here.
-		match values.next().map(|b| u64::from_be_bytes(b.try_into().expect("valid BE array"))) {
-			Some(0) => None,
-			x => x,
-		};*/
+		// the timestamp is fixed size, and may itself contain the separator
+		let mut values = values.get(9..).unwrap_or_default().split(|&b| b == 0xFF);
 
 		let title = match values
 			.next()
@@ -267,13 +299,15 @@ pub(super) async fn get_url_preview(&self, url: &str) -> Result<UrlPreviewData>
 			| x => x,
 		};
 
-		Ok(UrlPreviewData {
+		let data = UrlPreviewData {
 			title,
 			description,
 			image,
 			image_size,
 			image_width,
 			image_height,
-		})
+		};
+
+		Ok((data, timestamp))
 	}
 }
@@ -0,0 +1,160 @@
+use std::{
+	collections::BTreeMap,
+	fmt::Write,
+	path::{Path, PathBuf},
+};
+
+use conduwuit::{implement, warn, Err, Result};
+use sha2::{Digest, Sha256};
+use tokio::{fs, io::AsyncReadExt};
+
+/// A file in the media directory, as recorded in a manifest.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ManifestEntry {
+	/// File name within the media directory.
+	pub path: String,
+
+	/// Lowercase hex SHA-256 of the file's content.
+	pub sha256: String,
+
+	pub size: u64,
+}
+
+/// Differences between a manifest and the media directory.
+#[derive(Debug, Default)]
+pub struct ManifestReport {
+	pub checked: usize,
+
+	/// Files in the manifest which are gone.
+	pub missing: Vec<String>,
+
+	/// Files whose content no longer matches the manifest.
+	pub mismatched: Vec<String>,
+
+	/// Files which aren't in the manifest.
+	pub unlisted: Vec<String>,
+}
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Records the path, hash and size of every file in the media directory.
+/// Legacy symlinks are skipped as they point at files which are recorded.
+#[implement(super::Service)]
+pub async fn create_manifest(&self) -> Result<Vec<ManifestEntry>> {
+	let dir = self.local_media_dir()?;
+	let mut entries = Vec::new();
+	let mut files = fs::read_dir(&dir).await?;
+	while let Some(file) = files.next_entry().await? {
+		if !file.file_type().await?.is_file() {
+			continue;
+		}
+
+		let Ok(path) = file.file_name().into_string() else {
+			warn!("Skipping media file with a non-UTF-8 name: {:?}", file.path());
+			continue;
+		};
+
+		let (sha256, size) = hash_file(&file.path()).await?;
+		entries.push(ManifestEntry { path, sha256, size });
+	}
+
+	entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+	Ok(entries)
+}
+
+/// Writes a manifest of the media directory to a file, one `<sha256> <size>
+/// <path>` line per media file. Returns how many files were recorded.
+#[implement(super::Service)]
+pub async fn write_manifest(&self, output: &Path) -> Result<usize> {
+	let entries = self.create_manifest().await?;
+	let mut manifest = String::new();
+	for entry in &entries {
+		writeln!(manifest, "{} {} {}", entry.sha256, entry.size, entry.path)?;
+	}
+
+	fs::write(output, manifest).await?;
+
+	Ok(entries.len())
+}
+
+/// Compares the media directory against a manifest written by
+/// `write_manifest`.
+#[implement(super::Service)]
+pub async fn verify_manifest(&self, manifest: &Path) -> Result<ManifestReport> {
+	let mut expected = BTreeMap::new();
+	for line in fs::read_to_string(manifest).await?.lines() {
+		let mut fields = line.splitn(3, ' ');
+		let (Some(sha256), Some(size), Some(path)) =
+			(fields.next(), fields.next(), fields.next())
+		else {
+			return Err!("Invalid manifest line: {line:?}");
+		};
+
+		let size: u64 = size.parse()?;
+		let path = path.to_owned();
+		expected.insert(path.clone(), ManifestEntry { path, sha256: sha256.to_owned(), size });
+	}
+
+	let dir = self.local_media_dir()?;
+	let mut report = ManifestReport::default();
+	for entry in expected.values() {
+		let file: PathBuf = dir.join(&entry.path);
+		match hash_file(&file).await {
+			| Ok((sha256, size)) => {
+				report.checked = report.checked.saturating_add(1);
+				if sha256 != entry.sha256 || size != entry.size {
+					report.mismatched.push(entry.path.clone());
+				}
+			},
+			| Err(_) => report.missing.push(entry.path.clone()),
+		}
+	}
+
+	let mut files = fs::read_dir(&dir).await?;
+	while let Some(file) = files.next_entry().await? {
+		if !file.file_type().await?.is_file() {
+			continue;
+		}
+
+		let name = file.file_name().to_string_lossy().into_owned();
+		if !expected.contains_key(&name) {
+			report.unlisted.push(name);
+		}
+	}
+
+	report.unlisted.sort();
+
+	Ok(report)
+}
+
+#[implement(super::Service)]
+fn local_media_dir(&self) -> Result<PathBuf> {
+	match self.store.local_dir() {
+		| Some(dir) => Ok(dir.to_path_buf()),
+		| None => Err!("Media manifests are only supported with the filesystem media store."),
+	}
+}
+
+async fn hash_file(path: &Path) -> Result<(String, u64)> {
+	let mut file = fs::File::open(path).await?;
+	let mut hasher = Sha256::new();
+	let mut buf = vec![0; READ_BUFFER_SIZE];
+	let mut size: u64 = 0;
+	loop {
+		let read = file.read(&mut buf).await?;
+		if read == 0 {
+			break;
+		}
+
+		hasher.update(&buf[..read]);
+		size = size.saturating_add(read.try_into()?);
+	}
+
+	let mut sha256 = String::with_capacity(64);
+	for byte in hasher.finalize() {
+		write!(sha256, "{byte:02x}")?;
+	}
+
+	Ok((sha256, size))
+}
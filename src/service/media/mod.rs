@@ -1,8 +1,15 @@
+mod access;
 pub mod blurhash;
 mod data;
+mod manifest;
 pub(super) mod migrations;
+mod pending;
 mod preview;
+mod process;
 mod remote;
+mod retention;
+mod scan;
+pub mod store;
 mod tests;
 mod thumbnail;
 use std::{path::PathBuf, sync::Arc, time::SystemTime};
@@ -10,19 +17,27 @@
 use async_trait::async_trait;
 use base64::{engine::general_purpose, Engine as _};
 use conduwuit::{
-	debug, debug_error, debug_info, debug_warn, err, error, trace,
+	debug, debug_error, debug_info, debug_warn, err, error,
+	result::LogErr,
+	trace,
 	utils::{self, MutexMap},
 	warn, Err, Result, Server,
 };
 use ruma::{http_headers::ContentDisposition, Mxc, OwnedMxcUri, UserId};
 use tokio::{
-	fs,
-	io::{AsyncReadExt, AsyncWriteExt, BufReader},
+	sync::{Notify, Semaphore},
+	time::{interval, MissedTickBehavior},
 };
 
+pub(crate) use self::access::collect_mxcs;
 use self::data::{Data, Metadata};
-pub use self::thumbnail::Dim;
-use crate::{client, globals, sending, Dep};
+pub use self::{
+	manifest::{ManifestEntry, ManifestReport},
+	scan::QuarantinedMedia,
+	store::MediaStore,
+	thumbnail::Dim,
+};
+use crate::{admin, client, globals, rooms, sending, users, Dep};
 
 #[derive(Debug)]
 pub struct FileMeta {
@@ -31,17 +46,38 @@ pub struct FileMeta {
 	pub content_disposition: Option<ContentDisposition>,
 }
 
+/// A file read from the media store as it is sent on.
+pub struct FileStream {
+	pub content: store::ByteStream,
+	pub size: u64,
+	pub content_type: Option<String>,
+	pub content_disposition: Option<ContentDisposition>,
+}
+
 pub struct Service {
 	url_preview_mutex: MutexMap<String, ()>,
+	upload_mutex: MutexMap<String, ()>,
+	/// Serializes updates to each user's media usage.
+	usage_mutex: MutexMap<String, ()>,
+	/// Woken when a pending MXC URI is uploaded to.
+	upload_notify: Notify,
+	interrupt: Notify,
+	thumbnail_permits: Semaphore,
+	store: Box<dyn MediaStore>,
 	pub(super) db: Data,
 	services: Services,
 }
 
 struct Services {
 	server: Arc<Server>,
+	admin: Dep<admin::Service>,
 	client: Dep<client::Service>,
+	directory: Dep<rooms::directory::Service>,
 	globals: Dep<globals::Service>,
 	sending: Dep<sending::Service>,
+	state_accessor: Dep<rooms::state_accessor::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	users: Dep<users::Service>,
 }
 
 /// generated MXC ID (`media-id`) length
@@ -58,22 +94,49 @@ impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			url_preview_mutex: MutexMap::new(),
+			upload_mutex: MutexMap::new(),
+			usage_mutex: MutexMap::new(),
+			upload_notify: Notify::new(),
+			interrupt: Notify::new(),
+			thumbnail_permits: Semaphore::new(
+				args.server.config.thumbnail_generation_concurrency.max(1),
+			),
+			store: store::build(&args.server.config)?,
 			db: Data::new(args.db),
 			services: Services {
 				server: args.server.clone(),
+				admin: args.depend::<admin::Service>("admin"),
 				client: args.depend::<client::Service>("client"),
+				directory: args.depend::<rooms::directory::Service>("rooms::directory"),
 				globals: args.depend::<globals::Service>("globals"),
 				sending: args.depend::<sending::Service>("sending"),
+				state_accessor: args
+					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				users: args.depend::<users::Service>("users"),
 			},
 		}))
 	}
 
 	async fn worker(self: Arc<Self>) -> Result<()> {
-		self.create_media_dir().await?;
+		self.store.init().await?;
+
+		let mut i = interval(retention::RETENTION_INTERVAL);
+		i.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		loop {
+			tokio::select! {
+				() = self.interrupt.notified() => break,
+				_ = i.tick() => (),
+			}
+
+			self.prune_remote_media().await.log_err().ok();
+		}
 
 		Ok(())
 	}
 
+	fn interrupt(&self) { self.interrupt.notify_waiters(); }
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
@@ -87,6 +150,19 @@ pub async fn create(
 		content_type: Option<&str>,
 		file: &[u8],
 	) -> Result<()> {
+		let local_user = user.filter(|_| self.services.globals.server_is_ours(mxc.server_name));
+
+		let mut flagged = None;
+		let file = match local_user {
+			| Some(user) => {
+				let file = self.process_upload(content_type, file).await?;
+				self.check_quota(user, file.len()).await?;
+				flagged = self.scan_upload(user, &file).await?;
+				file
+			},
+			| None => file.into(),
+		};
+
 		// Width, Height = 0 if it's not a thumbnail
 		let key = self.db.create_file_metadata(
 			mxc,
@@ -97,8 +173,17 @@ pub async fn create(
 		)?;
 
 		//TODO: Dangling metadata in database if creation fails
-		let mut f = self.create_media_file(&key).await?;
-		f.write_all(file).await?;
+		self.store.put(&key, &file).await?;
+		self.touch(mxc);
+
+		if let Some(user) = local_user {
+			let size = file.len().try_into().unwrap_or(u64::MAX);
+			self.update_media_usage(user, size, 0).await;
+		}
+
+		if let (Some(user), Some(signature)) = (local_user, flagged) {
+			self.quarantine(mxc, user, &signature);
+		}
 
 		Ok(())
 	}
@@ -106,11 +191,21 @@ pub async fn create(
 	/// Deletes a file in the database and from the media directory via an MXC
 	pub async fn delete(&self, mxc: &Mxc<'_>) -> Result<()> {
 		if let Ok(keys) = self.db.search_mxc_metadata_prefix(mxc).await {
+			if let Some(user) = self
+				.db
+				.get_uploader(mxc)
+				.await
+				.filter(|_| self.services.globals.server_is_ours(mxc.server_name))
+			{
+				let size = self.upload_size(mxc).await;
+				self.update_media_usage(&user, 0, size).await;
+			}
+
 			for key in keys {
 				trace!(?mxc, "MXC Key: {key:?}");
-				debug_info!(?mxc, "Deleting from filesystem");
+				debug_info!(?mxc, "Deleting from media store");
 
-				if let Err(e) = self.remove_media_file(&key).await {
+				if let Err(e) = self.store.remove(&key).await {
 					debug_error!(?mxc, "Failed to remove media file: {e}");
 				}
 
@@ -128,7 +223,7 @@ pub async fn delete(&self, mxc: &Mxc<'_>) -> Result<()> {
 	///
 	/// currently, this is only practical for local users
 	pub async fn delete_from_user(&self, user: &UserId) -> Result<usize> {
-		let mxcs = self.db.get_all_user_mxcs(user).await;
+		let mxcs = self.get_user_mxcs(user).await;
 		let mut deletion_count: usize = 0;
 
 		for mxc in mxcs {
@@ -154,14 +249,15 @@ pub async fn delete_from_user(&self, user: &UserId) -> Result<usize> {
 
 	/// Downloads a file.
 	pub async fn get(&self, mxc: &Mxc<'_>) -> Result<Option<FileMeta>> {
+		if self.is_quarantined(mxc).await {
+			return Ok(None);
+		}
+
 		if let Ok(Metadata { content_disposition, content_type, key }) =
 			self.db.search_file_metadata(mxc, &Dim::default()).await
 		{
-			let mut content = Vec::with_capacity(8192);
-			let path = self.get_media_file(&key);
-			BufReader::new(fs::File::open(path).await?)
-				.read_to_end(&mut content)
-				.await?;
+			let content = self.store.get(&key).await?;
+			self.touch(mxc);
 
 			Ok(Some(FileMeta {
 				content: Some(content),
@@ -173,6 +269,37 @@ pub async fn get(&self, mxc: &Mxc<'_>) -> Result<Option<FileMeta>> {
 		}
 	}
 
+	/// Streams a file from the media store instead of reading it into
+	/// memory. Only files we have are streamed; remote ones are fetched with
+	/// `fetch_remote_content` first.
+	pub async fn get_stream(&self, mxc: &Mxc<'_>) -> Result<Option<FileStream>> {
+		if self.is_quarantined(mxc).await {
+			return Ok(None);
+		}
+
+		let Ok(Metadata { content_disposition, content_type, key }) =
+			self.db.search_file_metadata(mxc, &Dim::default()).await
+		else {
+			return Ok(None);
+		};
+
+		let (size, content) = self.store.stream(&key).await?;
+		self.touch(mxc);
+
+		Ok(Some(FileStream {
+			content,
+			size,
+			content_type,
+			content_disposition,
+		}))
+	}
+
+	/// Gets the MXC URIs of all media uploaded by the specified user
+	#[inline]
+	pub async fn get_user_mxcs(&self, user: &UserId) -> Vec<OwnedMxcUri> {
+		self.db.get_all_user_mxcs(user).await
+	}
+
 	/// Gets all the MXC URIs in our media database
 	pub async fn get_all_mxcs(&self) -> Result<Vec<OwnedMxcUri>> {
 		let all_keys = self.db.get_all_media_keys().await;
@@ -224,6 +351,49 @@ pub async fn delete_all_remote_media_at_after_time(
 		after: bool,
 		yes_i_want_to_delete_local_media: bool,
 	) -> Result<usize> {
+		let remote_mxcs = self
+			.get_remote_media_at_after_time(time, before, after, yes_i_want_to_delete_local_media)
+			.await?;
+
+		if remote_mxcs.is_empty() {
+			return Err!(Database("Did not found any eligible MXCs to delete."));
+		}
+
+		debug_info!("Deleting media now in the past {time:?}");
+
+		let mut deletion_count: usize = 0;
+
+		for mxc in remote_mxcs {
+			let Ok(mxc) = mxc.as_str().try_into() else {
+				debug_warn!("Invalid MXC in database, skipping");
+				continue;
+			};
+
+			debug_info!("Deleting MXC {mxc} from database and filesystem");
+
+			match self.delete(&mxc).await {
+				| Ok(()) => {
+					deletion_count = deletion_count.saturating_add(1);
+				},
+				| Err(e) => {
+					warn!("Failed to delete {mxc}, ignoring error and skipping: {e}");
+					continue;
+				},
+			}
+		}
+
+		Ok(deletion_count)
+	}
+
+	/// Gets the MXC URIs of all remote only media files created before or
+	/// after the given time, going by media store metadata.
+	pub async fn get_remote_media_at_after_time(
+		&self,
+		time: SystemTime,
+		before: bool,
+		after: bool,
+		yes_i_want_to_delete_local_media: bool,
+	) -> Result<Vec<OwnedMxcUri>> {
 		let all_keys = self.db.get_all_media_keys().await;
 		let mut remote_mxcs = Vec::with_capacity(all_keys.len());
 
@@ -259,29 +429,10 @@ pub async fn delete_all_remote_media_at_after_time(
 				continue;
 			}
 
-			let path = self.get_media_file(&key);
-
-			let file_metadata = match fs::metadata(path.clone()).await {
-				| Ok(file_metadata) => file_metadata,
+			let file_created_at = match self.store.stat(&key).await {
+				| Ok(stat) => stat.created,
 				| Err(e) => {
-					error!(
-						"Failed to obtain file metadata for MXC {mxc} at file path \
-						 \"{path:?}\", skipping: {e}"
-					);
-					continue;
-				},
-			};
-
-			trace!(%mxc, ?path, "File metadata: {file_metadata:?}");
-
-			let file_created_at = match file_metadata.created() {
-				| Ok(value) => value,
-				| Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {
-					debug!("btime is unsupported, using mtime instead");
-					file_metadata.modified()?
-				},
-				| Err(err) => {
-					error!("Could not delete MXC {mxc} at path {path:?}: {err:?}. Skipping...");
+					error!("Failed to obtain file metadata for MXC {mxc}, skipping: {e}");
 					continue;
 				},
 			};
@@ -293,86 +444,40 @@ pub async fn delete_all_remote_media_at_after_time(
 					"File is within (before) user duration, pushing to list of file paths and \
 					 keys to delete."
 				);
-				remote_mxcs.push(mxc.to_string());
+				remote_mxcs.push(mxc);
 			} else if file_created_at <= time && after {
 				debug!(
 					"File is not within (after) user duration, pushing to list of file paths \
 					 and keys to delete."
 				);
-				remote_mxcs.push(mxc.to_string());
+				remote_mxcs.push(mxc);
 			}
 		}
 
-		if remote_mxcs.is_empty() {
-			return Err!(Database("Did not found any eligible MXCs to delete."));
-		}
-
-		debug_info!("Deleting media now in the past {time:?}");
-
-		let mut deletion_count: usize = 0;
-
-		for mxc in remote_mxcs {
-			let Ok(mxc) = mxc.as_str().try_into() else {
-				debug_warn!("Invalid MXC in database, skipping");
-				continue;
-			};
-
-			debug_info!("Deleting MXC {mxc} from database and filesystem");
-
-			match self.delete(&mxc).await {
-				| Ok(()) => {
-					deletion_count = deletion_count.saturating_add(1);
-				},
-				| Err(e) => {
-					warn!("Failed to delete {mxc}, ignoring error and skipping: {e}");
-					continue;
-				},
-			}
-		}
-
-		Ok(deletion_count)
+		Ok(remote_mxcs)
 	}
 
-	pub async fn create_media_dir(&self) -> Result<()> {
-		let dir = self.get_media_dir();
-		Ok(fs::create_dir_all(dir).await?)
-	}
-
-	async fn remove_media_file(&self, key: &[u8]) -> Result<()> {
-		let path = self.get_media_file(key);
-		let legacy = self.get_media_file_b64(key);
-		debug!(?key, ?path, ?legacy, "Removing media file");
-
-		let file_rm = fs::remove_file(&path);
-		let legacy_rm = fs::remove_file(&legacy);
-		let (file_rm, legacy_rm) = tokio::join!(file_rm, legacy_rm);
-		if let Err(e) = legacy_rm {
-			if self.services.server.config.media_compat_file_link {
-				debug_error!(?key, ?legacy, "Failed to remove legacy media symlink: {e}");
-			}
+	/// A URL the media can be downloaded from directly instead of through us,
+	/// if the media store hands those out.
+	pub async fn get_location(&self, mxc: &Mxc<'_>) -> Result<Option<String>> {
+		if self.is_quarantined(mxc).await {
+			return Ok(None);
 		}
 
-		Ok(file_rm?)
-	}
-
-	async fn create_media_file(&self, key: &[u8]) -> Result<fs::File> {
-		let path = self.get_media_file(key);
-		debug!(?key, ?path, "Creating media file");
-
-		let file = fs::File::create(&path).await?;
-		if self.services.server.config.media_compat_file_link {
-			let legacy = self.get_media_file_b64(key);
-			if let Err(e) = fs::symlink(&path, &legacy).await {
-				debug_error!(
-					key = ?encode_key(key), ?path, ?legacy,
-					"Failed to create legacy media symlink: {e}"
-				);
-			}
-		}
+		let Ok(Metadata { key, .. }) = self.db.search_file_metadata(mxc, &Dim::default()).await
+		else {
+			return Ok(None);
+		};
 
-		Ok(file)
+		self.store.location(&key).await
 	}
 
+	/// Whether media is kept in the media directory, rather than an object
+	/// store.
+	#[inline]
+	#[must_use]
+	pub fn is_stored_locally(&self) -> bool { self.store.local_dir().is_some() }
+
 	#[inline]
 	pub async fn get_metadata(&self, mxc: &Mxc<'_>) -> Option<FileMeta> {
 		self.db
@@ -395,12 +500,7 @@ pub fn get_media_file(&self, key: &[u8]) -> PathBuf { self.get_media_file_sha256
 	#[must_use]
 	pub fn get_media_file_sha256(&self, key: &[u8]) -> PathBuf {
 		let mut r = self.get_media_dir();
-		// Using the hash of the base64 key as the filename
-		// This is to prevent the total length of the path from exceeding the maximum
-		// length in most filesystems
-		let digest = <sha2::Sha256 as sha2::Digest>::digest(key);
-		let encoded = encode_key(&digest);
-		r.push(encoded);
+		r.push(store::file_name(key));
 		r
 	}
 
@@ -416,12 +516,7 @@ pub fn get_media_file_b64(&self, key: &[u8]) -> PathBuf {
 	}
 
 	#[must_use]
-	pub fn get_media_dir(&self) -> PathBuf {
-		let mut r = PathBuf::new();
-		r.push(self.services.server.config.database_path.clone());
-		r.push("media");
-		r
-	}
+	pub fn get_media_dir(&self) -> PathBuf { store::media_dir(&self.services.server.config) }
 }
 
 #[inline]
@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use conduwuit::{
+	implement,
+	utils::{self, millis_since_unix_epoch, stream::TryIgnore, ReadyExt},
+	Err, Error, Result,
+};
+use database::{Deserialized, Ignore, Interfix, Json};
+use ruma::{
+	api::client::error::ErrorKind, http_headers::ContentDisposition, Mxc, OwnedMxcUri,
+	OwnedUserId, UserId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout_at, Instant};
+
+use super::{thumbnail::Dim, MXC_LENGTH};
+
+/// Longest a download waits for the content of a pending MXC URI, whatever
+/// the `timeout_ms` it asked for.
+const MAX_UPLOAD_WAIT: Duration = Duration::from_secs(60);
+
+/// An MXC URI created ahead of its content (MSC2246), which only its creator
+/// may upload to until it expires.
+#[derive(Debug, Deserialize, Serialize)]
+struct PendingUpload {
+	user_id: OwnedUserId,
+	expires_at: u64,
+}
+
+/// Creates an MXC URI for the user to upload content to later, returning it
+/// with when it expires unless uploaded to.
+#[implement(super::Service)]
+pub async fn create_pending(&self, user: &UserId) -> Result<(OwnedMxcUri, u64)> {
+	let now = millis_since_unix_epoch();
+	let mut pending = 0_usize;
+	self.db
+		.usermediaid_pendingupload
+		.stream_prefix(&(user, Interfix))
+		.ignore_err()
+		.ready_for_each(|((_, mxc), expires_at): ((Ignore, &str), u64)| {
+			if expires_at <= now {
+				self.remove_pending(user, mxc);
+			} else {
+				pending = pending.saturating_add(1);
+			}
+		})
+		.await;
+
+	let max_pending = self.services.server.config.max_pending_media_uploads;
+	if pending >= max_pending {
+		return Err(Error::Request(
+			ErrorKind::LimitExceeded { retry_after: None },
+			format!(
+				"You already have the maximum of {max_pending} pending uploads; upload to them \
+				 first."
+			)
+			.into(),
+			http::StatusCode::TOO_MANY_REQUESTS,
+		));
+	}
+
+	let mxc = Mxc {
+		server_name: self.services.globals.server_name(),
+		media_id: &utils::random_string(MXC_LENGTH),
+	};
+
+	let expiry_ms = self
+		.services
+		.server
+		.config
+		.pending_media_upload_expiry_s
+		.saturating_mul(1000);
+
+	let expires_at = now.saturating_add(expiry_ms);
+	let upload = PendingUpload { user_id: user.to_owned(), expires_at };
+	self.db
+		.mediaid_pendingupload
+		.raw_put(mxc.to_string(), Json(upload));
+	self.db
+		.usermediaid_pendingupload
+		.put((user, mxc.to_string()), expires_at);
+
+	Ok((mxc.to_string().into(), expires_at))
+}
+
+/// Uploads the content of an MXC URI created with `create_pending`, waking
+/// the downloads waiting for it.
+#[implement(super::Service)]
+pub async fn upload_pending(
+	&self,
+	mxc: &Mxc<'_>,
+	user: &UserId,
+	content_disposition: Option<&ContentDisposition>,
+	content_type: Option<&str>,
+	file: &[u8],
+) -> Result<()> {
+	let key = mxc.to_string();
+	let _lock = self.upload_mutex.lock(&key).await;
+
+	if self
+		.db
+		.search_file_metadata(mxc, &Dim::default())
+		.await
+		.is_ok()
+	{
+		return Err!(Request(CannotOverwriteMedia("Media has already been uploaded.")));
+	}
+
+	let Ok(upload) = self.pending_upload(&key).await else {
+		return Err!(Request(NotFound("Media not found.")));
+	};
+
+	if upload.user_id != user {
+		return Err!(Request(Forbidden("You did not create this media ID.")));
+	}
+
+	if upload.expires_at <= millis_since_unix_epoch() {
+		return Err!(Request(NotFound("Media not found.")));
+	}
+
+	self.create(mxc, Some(user), content_disposition, content_type, file)
+		.await?;
+
+	self.remove_pending(user, &key);
+	self.upload_notify.notify_waiters();
+
+	Ok(())
+}
+
+/// Waits up to the timeout, at most `MAX_UPLOAD_WAIT`, for the content of a
+/// pending MXC URI to be uploaded. Returns at once if it isn't pending, and
+/// fails with `M_NOT_YET_UPLOADED` if it still is after the timeout.
+#[implement(super::Service)]
+pub async fn wait_for_upload(&self, mxc: &Mxc<'_>, timeout: Duration) -> Result<()> {
+	let key = mxc.to_string();
+	let deadline = Instant::now()
+		.checked_add(timeout.min(MAX_UPLOAD_WAIT))
+		.unwrap_or_else(Instant::now);
+
+	loop {
+		// Registered before checking, so an upload in between isn't missed
+		let notified = self.upload_notify.notified();
+		let Ok(upload) = self.pending_upload(&key).await else {
+			return Ok(());
+		};
+
+		if upload.expires_at <= millis_since_unix_epoch() {
+			return Ok(());
+		}
+
+		if timeout_at(deadline, notified).await.is_err() {
+			return Err!(Request(NotYetUploaded("Media has not been uploaded yet.")));
+		}
+	}
+}
+
+#[implement(super::Service)]
+fn remove_pending(&self, user: &UserId, mxc: &str) {
+	self.db.mediaid_pendingupload.remove(mxc);
+	self.db.usermediaid_pendingupload.del((user, mxc));
+}
+
+#[implement(super::Service)]
+async fn pending_upload(&self, key: &str) -> Result<PendingUpload> {
+	self.db.mediaid_pendingupload.get(key).await.deserialized()
+}
@@ -10,7 +10,7 @@
 use conduwuit::{debug, Err, Result};
 use conduwuit_core::implement;
 use ipaddress::IPAddress;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use url::Url;
 
 use super::Service;
@@ -31,6 +31,14 @@ pub struct UrlPreviewData {
 	pub image_height: Option<u32>,
 }
 
+/// The parts of an oEmbed response used for previews.
+#[derive(Deserialize)]
+#[cfg_attr(not(feature = "url_preview"), allow(dead_code))]
+struct OEmbed {
+	title: Option<String>,
+	thumbnail_url: Option<String>,
+}
+
 #[implement(Service)]
 pub async fn remove_url_preview(&self, url: &str) -> Result<()> {
 	// TODO: also remove the downloaded image
@@ -47,27 +55,40 @@ pub async fn set_url_preview(&self, url: &str, data: &UrlPreviewData) -> Result<
 
 #[implement(Service)]
 pub async fn get_url_preview(&self, url: &Url) -> Result<UrlPreviewData> {
-	if let Ok(preview) = self.db.get_url_preview(url.as_str()).await {
+	if let Ok(preview) = self.cached_url_preview(url.as_str()).await {
 		return Ok(preview);
 	}
 
 	// ensure that only one request is made per URL
 	let _request_lock = self.url_preview_mutex.lock(url.as_str()).await;
 
-	match self.db.get_url_preview(url.as_str()).await {
+	match self.cached_url_preview(url.as_str()).await {
 		| Ok(preview) => Ok(preview),
 		| Err(_) => self.request_url_preview(url).await,
 	}
 }
 
+/// The cached preview of the URL, unless older than `url_preview_cache_ttl`.
 #[implement(Service)]
-async fn request_url_preview(&self, url: &Url) -> Result<UrlPreviewData> {
-	if let Ok(ip) = IPAddress::parse(url.host_str().expect("URL previously validated")) {
-		if !self.services.client.valid_cidr_range(&ip) {
-			return Err!(BadServerResponse("Requesting from this address is forbidden"));
-		}
+async fn cached_url_preview(&self, url: &str) -> Result<UrlPreviewData> {
+	let (preview, fetched_at) = self.db.get_url_preview(url).await?;
+	let now = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.expect("valid system time")
+		.as_secs();
+
+	let ttl = self.services.server.config.url_preview_cache_ttl;
+	if fetched_at.saturating_add(ttl) < now {
+		return Err!(Request(NotFound("Cached URL preview has expired.")));
 	}
 
+	Ok(preview)
+}
+
+#[implement(Service)]
+async fn request_url_preview(&self, url: &Url) -> Result<UrlPreviewData> {
+	self.check_url_host(url)?;
+
 	let client = &self.services.client.url_preview;
 	let response = client.head(url.as_str()).send().await?;
 
@@ -100,12 +121,20 @@ async fn request_url_preview(&self, url: &Url) -> Result<UrlPreviewData> {
 #[cfg(feature = "url_preview")]
 #[implement(Service)]
 pub async fn download_image(&self, url: &str) -> Result<UrlPreviewData> {
-	use conduwuit::utils::random_string;
+	use conduwuit::{err, utils::random_string};
 	use image::ImageReader;
 	use ruma::Mxc;
 
-	let image = self.services.client.url_preview.get(url).send().await?;
-	let image = image.bytes().await?;
+	let parsed = Url::parse(url).map_err(|e| err!(Request(InvalidParam("Invalid URL: {e}"))))?;
+	self.check_url_host(&parsed)?;
+
+	let max_size = self.services.server.config.url_preview_max_image_size;
+	let response = self.services.client.url_preview.get(url).send().await?;
+	let (image, truncated) = read_limited(response, max_size).await?;
+	if truncated {
+		return Err!(Request(TooLarge("Image exceeds url_preview_max_image_size.")));
+	}
+
 	let mxc = Mxc {
 		server_name: self.services.globals.server_name(),
 		media_id: &random_string(super::MXC_LENGTH),
@@ -143,41 +172,125 @@ async fn download_html(&self, url: &str) -> Result<UrlPreviewData> {
 	use webpage::HTML;
 
 	let client = &self.services.client.url_preview;
-	let mut response = client.get(url).send().await?;
-
-	let mut bytes: Vec<u8> = Vec::new();
-	while let Some(chunk) = response.chunk().await? {
-		bytes.extend_from_slice(&chunk);
-		if bytes.len() > self.services.globals.url_preview_max_spider_size() {
-			debug!(
-				"Response body from URL {} exceeds url_preview_max_spider_size ({}), not \
-				 processing the rest of the response body and assuming our necessary data is in \
-				 this range.",
-				url,
-				self.services.globals.url_preview_max_spider_size()
-			);
-			break;
-		}
+	let response = client.get(url).send().await?;
+
+	let max_size = self.services.globals.url_preview_max_spider_size();
+	let (bytes, truncated) = read_limited(response, max_size).await?;
+	if truncated {
+		debug!(
+			"Response body from URL {url} exceeds url_preview_max_spider_size ({max_size}), not \
+			 processing the rest of the response body and assuming our necessary data is in \
+			 this range."
+		);
 	}
+
 	let body = String::from_utf8_lossy(&bytes);
+	let oembed = match Url::parse(url) {
+		| Ok(url) if self.services.server.config.url_preview_oembed =>
+			self.fetch_oembed(&url, &body).await,
+		| _ => None,
+	};
+
 	let Ok(html) = HTML::from_string(body.to_string(), Some(url.to_owned())) else {
 		return Err!(Request(Unknown("Failed to parse HTML")));
 	};
 
-	let mut data = match html.opengraph.images.first() {
+	let image_url = html
+		.opengraph
+		.images
+		.first()
+		.map(|obj| obj.url.clone())
+		.or_else(|| oembed.as_ref()?.thumbnail_url.clone());
+
+	// the preview is still useful without its image
+	let mut data = match image_url {
 		| None => UrlPreviewData::default(),
-		| Some(obj) => self.download_image(&obj.url).await?,
+		| Some(image_url) => self
+			.download_image(&image_url)
+			.await
+			.inspect_err(|e| debug!(%image_url, "Leaving image out of URL preview: {e}"))
+			.unwrap_or_default(),
 	};
 
 	let props = html.opengraph.properties;
 
-	/* use OpenGraph title/description, but fall back to HTML if not available */
-	data.title = props.get("title").cloned().or(html.title);
+	/* use OpenGraph title/description, but fall back to oEmbed or HTML if not
+	 * available */
+	data.title = props
+		.get("title")
+		.cloned()
+		.or_else(|| oembed?.title)
+		.or(html.title);
 	data.description = props.get("description").cloned().or(html.description);
 
 	Ok(data)
 }
 
+/// Fetches the oEmbed endpoint advertised by the page, if it has one which
+/// is allowed to be previewed.
+#[cfg(feature = "url_preview")]
+#[implement(Service)]
+async fn fetch_oembed(&self, url: &Url, body: &str) -> Option<OEmbed> {
+	use std::sync::LazyLock;
+
+	use regex::Regex;
+
+	static LINK: LazyLock<Regex> = LazyLock::new(|| {
+		Regex::new(r#"(?i)<link\s[^>]*type\s*=\s*["']application/json\+oembed["'][^>]*>"#)
+			.expect("valid oEmbed link regex")
+	});
+	static HREF: LazyLock<Regex> = LazyLock::new(|| {
+		Regex::new(r#"(?i)href\s*=\s*["']([^"']+)["']"#).expect("valid href regex")
+	});
+
+	let link = LINK.find(body)?;
+	let href = HREF.captures(link.as_str())?.get(1)?.as_str();
+	let endpoint = url.join(&href.replace("&amp;", "&")).ok()?;
+	if !self.url_preview_allowed(&endpoint) || self.check_url_host(&endpoint).is_err() {
+		debug!(%endpoint, "oEmbed endpoint is not allowed to be previewed");
+		return None;
+	}
+
+	let client = &self.services.client.url_preview;
+	let response = client.get(endpoint.as_str()).send().await.ok()?;
+	let max_size = self.services.globals.url_preview_max_spider_size();
+	match read_limited(response, max_size).await {
+		| Ok((bytes, false)) => serde_json::from_slice(&bytes)
+			.inspect_err(|e| debug!(%endpoint, "Invalid oEmbed response: {e}"))
+			.ok(),
+		| _ => None,
+	}
+}
+
+/// Refuses URLs naming a denied IP address, which the filtering resolver of the
+/// URL preview client never sees.
+#[implement(Service)]
+fn check_url_host(&self, url: &Url) -> Result {
+	if !self.services.client.valid_url_host(url) {
+		return Err!(BadServerResponse("Requesting from this address is forbidden"));
+	}
+
+	Ok(())
+}
+
+/// Reads at most `limit` bytes of the response body, returning whether the
+/// rest was left unread.
+#[cfg(feature = "url_preview")]
+async fn read_limited(mut response: reqwest::Response, limit: usize) -> Result<(Vec<u8>, bool)> {
+	let mut bytes: Vec<u8> = Vec::new();
+	while let Some(chunk) = response.chunk().await? {
+		if bytes.len().saturating_add(chunk.len()) > limit {
+			let rest = limit.saturating_sub(bytes.len());
+			bytes.extend_from_slice(&chunk[..rest]);
+			return Ok((bytes, true));
+		}
+
+		bytes.extend_from_slice(&chunk);
+	}
+
+	Ok((bytes, false))
+}
+
 #[cfg(not(feature = "url_preview"))]
 #[implement(Service)]
 async fn download_html(&self, _url: &str) -> Result<UrlPreviewData> {
@@ -269,7 +382,7 @@ pub fn url_preview_allowed(&self, url: &Url) -> bool {
 							 url_preview_domain_explicit_denylist (check 1/3)",
 							&root_domain
 						);
-						return true;
+						return false;
 					}
 
 					if allowlist_domain_explicit.contains(&root_domain.to_owned()) {
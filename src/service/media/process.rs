@@ -0,0 +1,135 @@
+//! Upload processing
+//!
+//! Strips metadata from and downsizes images uploaded by local users. Like
+//! thumbnails this is gated by 'media_thumbnail'; without it uploads are
+//! stored as they are.
+
+use std::borrow::Cow;
+
+use conduwuit::{implement, Result};
+
+/// Image types which are processed; anything else is stored as uploaded.
+#[cfg(feature = "media_thumbnail")]
+const PROCESSED_CONTENT_TYPES: [&str; 3] = ["image/jpeg", "image/png", "image/webp"];
+
+/// Quality of JPEG images re-encoded on upload.
+#[cfg(feature = "media_thumbnail")]
+const JPEG_QUALITY: u8 = 90;
+
+/// Strips EXIF/GPS and other metadata from an image uploaded by a local user
+/// with `media_strip_metadata`, and scales it down to fit
+/// `media_max_image_dimension`. Returns the upload unchanged when neither
+/// applies or it isn't an image we can decode.
+#[cfg(feature = "media_thumbnail")]
+#[implement(super::Service)]
+#[tracing::instrument(name = "process", level = "debug", skip(self, file))]
+pub(super) async fn process_upload<'a>(
+	&self,
+	content_type: Option<&str>,
+	file: &'a [u8],
+) -> Result<Cow<'a, [u8]>> {
+	use conduwuit::debug_warn;
+
+	let config = &self.services.server.config;
+	let strip = config.media_strip_metadata;
+	let max_dimension = config.media_max_image_dimension;
+	if !strip && max_dimension == 0 {
+		return Ok(Cow::Borrowed(file));
+	}
+
+	let Some(format) = content_type
+		.filter(|content_type| PROCESSED_CONTENT_TYPES.contains(content_type))
+		.and_then(image::ImageFormat::from_mime_type)
+	else {
+		return Ok(Cow::Borrowed(file));
+	};
+
+	// Decoding and encoding is CPU bound; it runs on the dedicated blocking
+	// pool rather than holding up the upload's async worker.
+	let content = file.to_vec();
+	let processed = self
+		.services
+		.server
+		.blocking
+		.spawn("process_upload", move || reencode(&content, format, strip, max_dimension))
+		.await?;
+
+	match processed {
+		| Ok(Some(processed)) => Ok(Cow::Owned(processed)),
+		| Ok(None) => Ok(Cow::Borrowed(file)),
+		| Err(e) => {
+			// Clients aren't to blame for images the decoder can't handle.
+			debug_warn!("Storing upload unprocessed: {e}");
+			Ok(Cow::Borrowed(file))
+		},
+	}
+}
+
+#[cfg(not(feature = "media_thumbnail"))]
+#[implement(super::Service)]
+pub(super) async fn process_upload<'a>(
+	&self,
+	_content_type: Option<&str>,
+	file: &'a [u8],
+) -> Result<Cow<'a, [u8]>> {
+	Ok(Cow::Borrowed(file))
+}
+
+/// Decodes and re-encodes the image in its own format, which leaves all
+/// metadata behind. The EXIF orientation is applied to the pixels first so
+/// the image isn't shown rotated once it's gone. Returns None when there's
+/// nothing to do.
+#[cfg(feature = "media_thumbnail")]
+fn reencode(
+	content: &[u8],
+	format: image::ImageFormat,
+	strip: bool,
+	max_dimension: u32,
+) -> Result<Option<Vec<u8>>> {
+	use std::io::Cursor;
+
+	use conduwuit::err;
+	use image::{
+		codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+		imageops::FilterType,
+		DynamicImage, ImageDecoder, ImageFormat, ImageReader,
+	};
+
+	let mut decoder = ImageReader::with_format(Cursor::new(content), format)
+		.into_decoder()
+		.map_err(|e| err!("Failed to decode image: {e}"))?;
+
+	let orientation = decoder
+		.orientation()
+		.map_err(|e| err!("Failed to read image orientation: {e}"))?;
+
+	let mut image =
+		DynamicImage::from_decoder(decoder).map_err(|e| err!("Failed to decode image: {e}"))?;
+
+	let oversized =
+		max_dimension > 0 && (image.width() > max_dimension || image.height() > max_dimension);
+
+	if !strip && !oversized {
+		return Ok(None);
+	}
+
+	image.apply_orientation(orientation);
+	if oversized {
+		image = image.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+	}
+
+	let mut processed = Vec::new();
+	let written = match format {
+		| ImageFormat::Jpeg => image
+			.to_rgb8()
+			.write_with_encoder(JpegEncoder::new_with_quality(&mut processed, JPEG_QUALITY)),
+		| ImageFormat::WebP => image
+			.to_rgba8()
+			.write_with_encoder(WebPEncoder::new_lossless(&mut processed)),
+		| _ => image.write_with_encoder(PngEncoder::new(&mut processed)),
+	};
+
+	written.map_err(|e| err!("Failed to encode image: {e}"))?;
+
+	Ok(Some(processed))
+}
@@ -437,12 +437,13 @@ fn check_fetch_authorized(&self, mxc: &Mxc<'_>) -> Result<()> {
 	Ok(())
 }
 
+/// The legacy unauthenticated endpoints only serve remote media which was
+/// already cached while frozen, as required since authenticated media.
 #[implement(super::Service)]
 fn check_legacy_freeze(&self) -> Result<()> {
-	self.services
-		.server
-		.config
-		.freeze_legacy_media
-		.then_some(())
-		.ok_or(err!(Request(NotFound("Remote media is frozen."))))
+	if self.services.server.config.freeze_legacy_media {
+		return Err!(Request(NotFound("Remote media is frozen.")));
+	}
+
+	Ok(())
 }
@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use conduwuit::{debug_warn, implement, info, utils::millis_since_unix_epoch, Err, Result};
+use database::Deserialized;
+use futures::StreamExt;
+use ruma::{Mxc, OwnedMxcUri, OwnedUserId, UserId};
+
+use super::Dim;
+
+/// How often remote media is checked against `media_retention_remote_days`.
+pub(super) const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Records that remote media was downloaded or served, which keeps it from
+/// being purged by the retention sweeper. Local media is kept regardless.
+#[implement(super::Service)]
+pub(super) fn touch(&self, mxc: &Mxc<'_>) {
+	if self.services.globals.server_is_ours(mxc.server_name) {
+		return;
+	}
+
+	self.db
+		.mediaid_lastaccess
+		.raw_put(mxc.to_string(), millis_since_unix_epoch());
+}
+
+/// The size in bytes of the media a local user uploaded. Remote media the
+/// user caused to be fetched isn't counted.
+#[implement(super::Service)]
+pub async fn media_usage(&self, user: &UserId) -> u64 {
+	self.db
+		.userid_mediausage
+		.get(user)
+		.await
+		.deserialized()
+		.unwrap_or(0)
+}
+
+/// Adds to or takes from the media usage of a local user.
+#[implement(super::Service)]
+pub(super) async fn update_media_usage(&self, user: &UserId, added: u64, removed: u64) {
+	let _lock = self.usage_mutex.lock(user.as_str()).await;
+	let usage = self
+		.media_usage(user)
+		.await
+		.saturating_add(added)
+		.saturating_sub(removed);
+
+	self.db.userid_mediausage.raw_put(user, usage);
+}
+
+/// Recounts the media usage of every local user from the media store, for
+/// databases from before usage was counted as media is uploaded and deleted.
+#[implement(super::Service)]
+pub async fn recount_media_usage(&self) -> Result {
+	let users: Vec<OwnedUserId> = self
+		.services
+		.users
+		.list_local_users()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for user in &users {
+		let mut usage = 0_u64;
+		for mxc in self.get_user_mxcs(user).await {
+			let Ok(mxc) = Mxc::try_from(mxc.as_str()) else {
+				continue;
+			};
+
+			if self.services.globals.server_is_ours(mxc.server_name) {
+				usage = usage.saturating_add(self.upload_size(&mxc).await);
+			}
+		}
+
+		self.db.userid_mediausage.raw_put(user, usage);
+	}
+
+	Ok(())
+}
+
+/// Fails with `M_TOO_LARGE` if uploading `size` more bytes would take the
+/// local user over `user_media_quota`.
+#[implement(super::Service)]
+pub async fn check_quota(&self, user: &UserId, size: usize) -> Result<()> {
+	let quota = self.services.server.config.user_media_quota;
+	if quota == 0 || !self.services.globals.user_is_local(user) {
+		return Ok(());
+	}
+
+	let size: u64 = size.try_into()?;
+	if self.media_usage(user).await.saturating_add(size) > quota {
+		return Err!(Request(TooLarge("Uploading this file would exceed your media quota.")));
+	}
+
+	Ok(())
+}
+
+/// Remote media last accessed before `cutoff` (milliseconds since the unix
+/// epoch), or taking at least `min_size` bytes if one is given. Media which
+/// was never recorded as accessed counts as accessed now, so media cached
+/// before retention was enabled gets the full retention period.
+#[implement(super::Service)]
+pub async fn unused_remote_media(
+	&self,
+	cutoff: Option<u64>,
+	min_size: Option<u64>,
+) -> Result<Vec<OwnedMxcUri>> {
+	let now = millis_since_unix_epoch();
+	let mut uris = self.get_all_mxcs().await?;
+	uris.sort_unstable();
+	uris.dedup();
+
+	let mut mxcs = Vec::new();
+	for uri in uris {
+		let Ok(mxc) = Mxc::try_from(uri.as_str()) else {
+			continue;
+		};
+
+		if self.services.globals.server_is_ours(mxc.server_name) {
+			continue;
+		}
+
+		let last_access = self
+			.db
+			.mediaid_lastaccess
+			.get(uri.as_str())
+			.await
+			.deserialized::<u64>()
+			.unwrap_or_else(|_| {
+				self.db.mediaid_lastaccess.raw_put(uri.as_str(), now);
+				now
+			});
+
+		if cutoff.is_some_and(|cutoff| last_access >= cutoff) {
+			continue;
+		}
+
+		if let Some(min_size) = min_size {
+			if self.media_size(&mxc).await < min_size {
+				continue;
+			}
+		}
+
+		mxcs.push(uri);
+	}
+
+	Ok(mxcs)
+}
+
+/// Deletes remote media not accessed for `media_retention_remote_days`.
+#[implement(super::Service)]
+pub(super) async fn prune_remote_media(&self) -> Result<usize> {
+	let days = self.services.server.config.media_retention_remote_days;
+	if days == 0 {
+		return Ok(0);
+	}
+
+	let cutoff = millis_since_unix_epoch().saturating_sub(days.saturating_mul(86_400_000));
+	let mxcs = self.unused_remote_media(Some(cutoff), None).await?;
+
+	let mut deleted = 0_usize;
+	for uri in &mxcs {
+		if !self.services.server.running() {
+			break;
+		}
+
+		let Ok(mxc) = Mxc::try_from(uri.as_str()) else {
+			continue;
+		};
+
+		match self.delete(&mxc).await {
+			| Ok(()) => deleted = deleted.saturating_add(1),
+			| Err(e) => debug_warn!(%uri, "Failed to delete unused remote media: {e}"),
+		}
+	}
+
+	if deleted > 0 {
+		info!("Deleted {deleted} remote media files unused for {days} days");
+	}
+
+	Ok(deleted)
+}
+
+/// The size in bytes of an uploaded file in the media store, without its
+/// thumbnails.
+#[implement(super::Service)]
+pub(super) async fn upload_size(&self, mxc: &Mxc<'_>) -> u64 {
+	let Ok(metadata) = self.db.search_file_metadata(mxc, &Dim::default()).await else {
+		return 0;
+	};
+
+	self.store
+		.stat(&metadata.key)
+		.await
+		.map_or(0, |stat| stat.size)
+}
+
+/// The size in bytes of a media file and its thumbnails in the media store.
+#[implement(super::Service)]
+async fn media_size(&self, mxc: &Mxc<'_>) -> u64 {
+	let Ok(keys) = self.db.search_mxc_metadata_prefix(mxc).await else {
+		return 0;
+	};
+
+	let mut size = 0_u64;
+	for key in keys {
+		if let Ok(stat) = self.store.stat(&key).await {
+			size = size.saturating_add(stat.size);
+		}
+	}
+
+	size
+}
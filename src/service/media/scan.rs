@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use conduwuit::{
+	implement, info,
+	utils::{millis_since_unix_epoch, stream::TryIgnore},
+	warn, Err, Result,
+};
+use database::{Deserialized, Json};
+use futures::StreamExt;
+use ruma::{Mxc, OwnedMxcUri, OwnedUserId, UserId};
+use serde::{Deserialize, Serialize};
+use tokio::{
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+	net::{TcpStream, UnixStream},
+	time::timeout,
+};
+
+/// An upload flagged by the content scanner and kept for review.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct QuarantinedMedia {
+	pub user_id: OwnedUserId,
+
+	/// What the scanner found.
+	pub signature: String,
+
+	pub quarantined_at: u64,
+}
+
+enum Verdict {
+	Clean,
+	Flagged(String),
+}
+
+/// Size of the chunks streamed to clamd.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const SCAN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Scans an upload by a local user with `media_scanner`, failing if it's
+/// flagged and not to be quarantined. Returns what was found if the upload is
+/// to be quarantined.
+#[implement(super::Service)]
+pub(super) async fn scan_upload(&self, user: &UserId, content: &[u8]) -> Result<Option<String>> {
+	let config = &self.services.server.config;
+	let Some(scanner) = &config.media_scanner else {
+		return Ok(None);
+	};
+
+	let verdict = match timeout(SCAN_TIMEOUT, scan(scanner, content)).await {
+		| Ok(Ok(verdict)) => verdict,
+		| Ok(Err(e)) if config.media_scan_fail_open => {
+			warn!("Accepting unscanned upload by {user}: {e}");
+			return Ok(None);
+		},
+		| Err(_) if config.media_scan_fail_open => {
+			warn!("Accepting unscanned upload by {user}: scanner timed out");
+			return Ok(None);
+		},
+		| Ok(Err(e)) => {
+			warn!("Rejecting unscanned upload by {user}: {e}");
+			return Err!(Request(Unknown(
+				"Uploads can't be scanned right now; try again later."
+			)));
+		},
+		| Err(_) => {
+			warn!("Rejecting unscanned upload by {user}: scanner timed out");
+			return Err!(Request(Unknown(
+				"Uploads can't be scanned right now; try again later."
+			)));
+		},
+	};
+
+	match verdict {
+		| Verdict::Clean => Ok(None),
+		| Verdict::Flagged(signature) if config.media_scan_quarantine => {
+			info!(%user, %signature, "Quarantining upload flagged by the content scanner");
+			Ok(Some(signature))
+		},
+		| Verdict::Flagged(signature) => {
+			info!(%user, %signature, "Rejecting upload flagged by the content scanner");
+			Err!(Request(Forbidden("Upload was rejected by the content scanner.")))
+		},
+	}
+}
+
+/// Keeps media flagged by `scan_upload` from being downloaded.
+#[implement(super::Service)]
+pub(super) fn quarantine(&self, mxc: &Mxc<'_>, user: &UserId, signature: &str) {
+	let entry = QuarantinedMedia {
+		user_id: user.to_owned(),
+		signature: signature.to_owned(),
+		quarantined_at: millis_since_unix_epoch(),
+	};
+
+	self.db
+		.mediaid_quarantine
+		.raw_put(mxc.to_string(), Json(entry));
+}
+
+#[implement(super::Service)]
+pub async fn is_quarantined(&self, mxc: &Mxc<'_>) -> bool {
+	self.db
+		.mediaid_quarantine
+		.exists(&mxc.to_string())
+		.await
+		.is_ok()
+}
+
+#[implement(super::Service)]
+pub async fn quarantined_media(&self) -> Vec<(OwnedMxcUri, QuarantinedMedia)> {
+	self.db
+		.mediaid_quarantine
+		.stream()
+		.ignore_err()
+		.map(|(mxc, entry): (&str, QuarantinedMedia)| (mxc.into(), entry))
+		.collect()
+		.await
+}
+
+/// Makes quarantined media downloadable again, e.g. after a false positive.
+#[implement(super::Service)]
+pub async fn release_quarantined(&self, mxc: &Mxc<'_>) -> Result<QuarantinedMedia> {
+	let key = mxc.to_string();
+	let Ok(entry) = self
+		.db
+		.mediaid_quarantine
+		.get(&key)
+		.await
+		.deserialized::<QuarantinedMedia>()
+	else {
+		return Err!(Request(NotFound("Media is not quarantined.")));
+	};
+
+	self.db.mediaid_quarantine.remove(&key);
+
+	Ok(entry)
+}
+
+/// Scans the content with clamd, reached through a unix socket path or a
+/// host and port.
+async fn scan(scanner: &str, content: &[u8]) -> Result<Verdict> {
+	if scanner.starts_with('/') {
+		instream(UnixStream::connect(scanner).await?, content).await
+	} else {
+		instream(TcpStream::connect(scanner).await?, content).await
+	}
+}
+
+/// clamd's INSTREAM command: the content in length-prefixed chunks ended by
+/// an empty one, answered by a single null-terminated line.
+async fn instream<S>(mut stream: S, content: &[u8]) -> Result<Verdict>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	stream.write_all(b"zINSTREAM\0").await?;
+	for chunk in content.chunks(CHUNK_SIZE) {
+		let len: u32 = chunk.len().try_into()?;
+		stream.write_all(&len.to_be_bytes()).await?;
+		stream.write_all(chunk).await?;
+	}
+
+	stream.write_all(&0_u32.to_be_bytes()).await?;
+	stream.flush().await?;
+
+	let mut reply = Vec::new();
+	stream.read_to_end(&mut reply).await?;
+	let reply = String::from_utf8_lossy(&reply);
+	let reply = reply.trim_end_matches(['\0', '\n']);
+
+	match reply.strip_prefix("stream: ") {
+		| Some("OK") => Ok(Verdict::Clean),
+		| Some(found) if found.ends_with(" FOUND") =>
+			Ok(Verdict::Flagged(found.trim_end_matches(" FOUND").to_owned())),
+		| _ => Err!("Unexpected reply from clamd: {reply:?}"),
+	}
+}
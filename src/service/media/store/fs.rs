@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use conduwuit::{debug, debug_error, Config, Result};
+use futures::{stream, StreamExt};
+use tokio::{
+	fs,
+	io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use super::{file_name, media_dir, ByteStream, MediaStore, Stat};
+use crate::media::encode_key;
+
+/// Bytes read from a file at a time when streaming it.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Keeps media in the "media" directory next to the database.
+pub(super) struct FsStore {
+	dir: PathBuf,
+	compat_file_link: bool,
+}
+
+impl FsStore {
+	pub(super) fn new(config: &Config) -> Self {
+		Self {
+			dir: media_dir(config),
+			compat_file_link: config.media_compat_file_link,
+		}
+	}
+
+	fn path(&self, key: &[u8]) -> PathBuf { self.dir.join(file_name(key)) }
+
+	/// The full base64 key file name used before the sha256 migration.
+	fn legacy_path(&self, key: &[u8]) -> PathBuf { self.dir.join(encode_key(key)) }
+}
+
+#[async_trait]
+impl MediaStore for FsStore {
+	async fn init(&self) -> Result { Ok(fs::create_dir_all(&self.dir).await?) }
+
+	async fn put(&self, key: &[u8], content: &[u8]) -> Result {
+		let path = self.path(key);
+		debug!(?key, ?path, "Creating media file");
+
+		let mut file = fs::File::create(&path).await?;
+		if self.compat_file_link {
+			let legacy = self.legacy_path(key);
+			if let Err(e) = fs::symlink(&path, &legacy).await {
+				debug_error!(
+					key = ?encode_key(key), ?path, ?legacy,
+					"Failed to create legacy media symlink: {e}"
+				);
+			}
+		}
+
+		file.write_all(content).await?;
+
+		Ok(())
+	}
+
+	async fn get(&self, key: &[u8]) -> Result<Vec<u8>> { Ok(fs::read(self.path(key)).await?) }
+
+	async fn stream(&self, key: &[u8]) -> Result<(u64, ByteStream)> {
+		let file = fs::File::open(self.path(key)).await?;
+		let size = file.metadata().await?.len();
+		let content = stream::try_unfold(file, |mut file| async move {
+			let mut chunk = BytesMut::with_capacity(CHUNK_SIZE);
+			let read = file.read_buf(&mut chunk).await?;
+
+			Ok((read > 0).then(|| (chunk.freeze(), file)))
+		});
+
+		Ok((size, content.boxed()))
+	}
+
+	async fn remove(&self, key: &[u8]) -> Result {
+		let path = self.path(key);
+		let legacy = self.legacy_path(key);
+		debug!(?key, ?path, ?legacy, "Removing media file");
+
+		let file_rm = fs::remove_file(&path);
+		let legacy_rm = fs::remove_file(&legacy);
+		let (file_rm, legacy_rm) = tokio::join!(file_rm, legacy_rm);
+		if let Err(e) = legacy_rm {
+			if self.compat_file_link {
+				debug_error!(?key, ?legacy, "Failed to remove legacy media symlink: {e}");
+			}
+		}
+
+		Ok(file_rm?)
+	}
+
+	async fn stat(&self, key: &[u8]) -> Result<Stat> {
+		let metadata = fs::metadata(self.path(key)).await?;
+		let created = match metadata.created() {
+			| Ok(created) => created,
+			| Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+				debug!("btime is unsupported, using mtime instead");
+				metadata.modified()?
+			},
+			| Err(e) => return Err(e.into()),
+		};
+
+		Ok(Stat { size: metadata.len(), created })
+	}
+
+	fn local_dir(&self) -> Option<&Path> { Some(&self.dir) }
+}
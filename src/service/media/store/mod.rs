@@ -0,0 +1,87 @@
+//! Media storage backends
+//!
+//! The database keeps the metadata of every file; a store keeps the contents,
+//! addressed by the file's metadata key.
+
+mod fs;
+#[cfg(feature = "media_s3")]
+mod s3;
+
+use std::{
+	path::{Path, PathBuf},
+	time::SystemTime,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use conduwuit::{config::MediaBackend, Config, Result};
+use futures::stream::BoxStream;
+
+use super::encode_key;
+
+/// Contents of a file read from a store as it is sent on.
+pub type ByteStream = BoxStream<'static, Result<Bytes>>;
+
+/// What a store knows about a file besides its contents.
+#[derive(Debug)]
+pub struct Stat {
+	pub size: u64,
+	pub created: SystemTime,
+}
+
+#[async_trait]
+pub trait MediaStore: Send + Sync {
+	/// Prepares the store on startup.
+	async fn init(&self) -> Result { Ok(()) }
+
+	async fn put(&self, key: &[u8], content: &[u8]) -> Result;
+
+	async fn get(&self, key: &[u8]) -> Result<Vec<u8>>;
+
+	/// The size and contents of a file, read as the stream is polled rather
+	/// than into memory.
+	async fn stream(&self, key: &[u8]) -> Result<(u64, ByteStream)>;
+
+	async fn remove(&self, key: &[u8]) -> Result;
+
+	async fn stat(&self, key: &[u8]) -> Result<Stat>;
+
+	/// A URL the file can be downloaded from directly, if the store hands
+	/// those out.
+	async fn location(&self, _key: &[u8]) -> Result<Option<String>> { Ok(None) }
+
+	/// The directory of a store keeping files on the local filesystem.
+	fn local_dir(&self) -> Option<&Path> { None }
+}
+
+pub(super) fn build(config: &Config) -> Result<Box<dyn MediaStore>> {
+	match config.media_storage.backend {
+		| MediaBackend::Filesystem => Ok(Box::new(fs::FsStore::new(config))),
+
+		#[cfg(feature = "media_s3")]
+		| MediaBackend::S3 => Ok(Box::new(s3::S3Store::new(&config.media_storage)?)),
+
+		#[cfg(not(feature = "media_s3"))]
+		| MediaBackend::S3 => conduwuit::Err!(Config(
+			"media_storage.backend",
+			"The s3 media storage backend requires building with the media_s3 feature."
+		)),
+	}
+}
+
+/// The media directory of the filesystem store.
+#[must_use]
+pub(super) fn media_dir(config: &Config) -> PathBuf {
+	let mut r = PathBuf::new();
+	r.push(config.database_path.clone());
+	r.push("media");
+	r
+}
+
+/// Name of a file in the store: the hash of the base64 key, so the total
+/// length of the path doesn't exceed the maximum length in most filesystems.
+#[must_use]
+pub(super) fn file_name(key: &[u8]) -> String {
+	let digest = <sha2::Sha256 as sha2::Digest>::digest(key);
+	encode_key(&digest)
+}
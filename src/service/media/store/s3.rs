@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use conduwuit::{config::MediaStorageConfig, debug, err, Err, Error, Result};
+use futures::{StreamExt, TryStreamExt};
+use object_store::{
+	aws::{AmazonS3, AmazonS3Builder},
+	path::Path,
+	signer::Signer,
+	ObjectStore, PutPayload, WriteMultipart,
+};
+
+use super::{file_name, ByteStream, MediaStore, Stat};
+
+/// Files larger than this are uploaded in parts of this size.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Parts of a file uploaded at once.
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+/// Keeps media in an S3-compatible object store.
+pub(super) struct S3Store {
+	store: AmazonS3,
+	prefix: String,
+	presigned_expiry: Option<Duration>,
+}
+
+impl S3Store {
+	pub(super) fn new(config: &MediaStorageConfig) -> Result<Self> {
+		let Some(bucket) = &config.s3_bucket else {
+			return Err!(Config(
+				"media_storage.s3_bucket",
+				"A bucket is required by the s3 media storage backend."
+			));
+		};
+
+		let mut builder = AmazonS3Builder::from_env()
+			.with_bucket_name(bucket)
+			.with_virtual_hosted_style_request(config.s3_virtual_hosted_style);
+
+		if let Some(region) = &config.s3_region {
+			builder = builder.with_region(region);
+		}
+
+		if let Some(endpoint) = &config.s3_endpoint {
+			builder = builder
+				.with_endpoint(endpoint.as_str())
+				.with_allow_http(endpoint.scheme() == "http");
+		}
+
+		if let Some(access_key_id) = &config.s3_access_key_id {
+			builder = builder.with_access_key_id(access_key_id);
+		}
+
+		if let Some(secret_access_key) = &config.s3_secret_access_key {
+			builder = builder.with_secret_access_key(secret_access_key);
+		}
+
+		let store = builder
+			.build()
+			.map_err(|e| err!(Config("media_storage", "Invalid S3 media storage: {e}")))?;
+
+		Ok(Self {
+			store,
+			prefix: config.s3_prefix.clone(),
+			presigned_expiry: config
+				.s3_presigned_redirects
+				.then(|| Duration::from_secs(config.s3_presigned_expiry_s)),
+		})
+	}
+
+	fn path(&self, key: &[u8]) -> Path {
+		Path::from(format!("{}{}", self.prefix, file_name(key)))
+	}
+}
+
+#[async_trait]
+impl MediaStore for S3Store {
+	async fn put(&self, key: &[u8], content: &[u8]) -> Result {
+		let path = self.path(key);
+		debug!(?key, %path, size = content.len(), "Uploading media object");
+
+		if content.len() <= PART_SIZE {
+			self.store
+				.put(&path, PutPayload::from(content.to_vec()))
+				.await
+				.map_err(into_error)?;
+
+			return Ok(());
+		}
+
+		let upload = self.store.put_multipart(&path).await.map_err(into_error)?;
+
+		let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+		for part in content.chunks(PART_SIZE) {
+			writer
+				.wait_for_capacity(MAX_CONCURRENT_PARTS)
+				.await
+				.map_err(into_error)?;
+
+			writer.write(part);
+		}
+
+		writer.finish().await.map_err(into_error)?;
+
+		Ok(())
+	}
+
+	async fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
+		let object = self.store.get(&self.path(key)).await.map_err(into_error)?;
+
+		let mut content = Vec::with_capacity(object.meta.size);
+		let mut stream = object.into_stream();
+		while let Some(chunk) = stream.next().await {
+			content.extend_from_slice(&chunk.map_err(into_error)?);
+		}
+
+		Ok(content)
+	}
+
+	async fn stream(&self, key: &[u8]) -> Result<(u64, ByteStream)> {
+		let object = self.store.get(&self.path(key)).await.map_err(into_error)?;
+		let size = object.meta.size.try_into()?;
+
+		Ok((size, object.into_stream().map_err(into_error).boxed()))
+	}
+
+	async fn remove(&self, key: &[u8]) -> Result {
+		let path = self.path(key);
+		debug!(?key, %path, "Removing media object");
+
+		self.store.delete(&path).await.map_err(into_error)
+	}
+
+	async fn stat(&self, key: &[u8]) -> Result<Stat> {
+		let meta = self.store.head(&self.path(key)).await.map_err(into_error)?;
+
+		Ok(Stat {
+			size: meta.size.try_into()?,
+			created: meta.last_modified.into(),
+		})
+	}
+
+	async fn location(&self, key: &[u8]) -> Result<Option<String>> {
+		let Some(expiry) = self.presigned_expiry else {
+			return Ok(None);
+		};
+
+		let url = self
+			.store
+			.signed_url(http::Method::GET, &self.path(key), expiry)
+			.await
+			.map_err(into_error)?;
+
+		Ok(Some(url.into()))
+	}
+}
+
+fn into_error(e: object_store::Error) -> Error {
+	match e {
+		| object_store::Error::NotFound { .. } => err!(Request(NotFound("Media not found."))),
+		| e => err!("S3 media storage: {e}"),
+	}
+}
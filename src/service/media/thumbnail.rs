@@ -9,12 +9,8 @@
 
 use conduwuit::{checked, err, implement, Result};
 use ruma::{http_headers::ContentDisposition, media::Method, Mxc, UInt, UserId};
-use tokio::{
-	fs,
-	io::{AsyncReadExt, AsyncWriteExt},
-};
 
-use super::{data::Metadata, FileMeta};
+use super::{data::Metadata, FileMeta, FileStream};
 
 /// Dimension specification for a thumbnail.
 #[derive(Debug)]
@@ -22,8 +18,16 @@ pub struct Dim {
 	pub width: u32,
 	pub height: u32,
 	pub method: Method,
+
+	/// Whether the client prefers an animated thumbnail (MSC2705). Not part
+	/// of the stored thumbnail's key: animated images are served as they are.
+	pub animated: bool,
 }
 
+/// Image types which may be animated, served instead of a thumbnail to
+/// clients asking for an animated one.
+const ANIMATABLE_CONTENT_TYPES: [&str; 3] = ["image/gif", "image/webp", "image/apng"];
+
 impl super::Service {
 	/// Uploads or replaces a file thumbnail.
 	#[allow(clippy::too_many_arguments)]
@@ -41,8 +45,7 @@ pub async fn upload_thumbnail(
 				.create_file_metadata(mxc, user, dim, content_disposition, content_type)?;
 
 		//TODO: Dangling metadata in database if creation fails
-		let mut f = self.create_media_file(&key).await?;
-		f.write_all(file).await?;
+		self.store.put(&key, file).await?;
 
 		Ok(())
 	}
@@ -62,12 +65,22 @@ pub async fn upload_thumbnail(
 	/// which crops the image afterwards.
 	#[tracing::instrument(skip(self), name = "thumbnail", level = "debug")]
 	pub async fn get_thumbnail(&self, mxc: &Mxc<'_>, dim: &Dim) -> Result<Option<FileMeta>> {
-		// 0, 0 because that's the original file
+		if self.is_quarantined(mxc).await {
+			return Ok(None);
+		}
+
+		if let Some(metadata) = self.get_animated_original(mxc, dim).await {
+			self.touch(mxc);
+			return self.get_thumbnail_saved(metadata).await;
+		}
+
 		let dim = dim.normalized();
 
 		if let Ok(metadata) = self.db.search_file_metadata(mxc, &dim).await {
+			self.touch(mxc);
 			self.get_thumbnail_saved(metadata).await
 		} else if let Ok(metadata) = self.db.search_file_metadata(mxc, &Dim::default()).await {
+			self.touch(mxc);
 			self.get_thumbnail_generate(mxc, &dim, metadata).await
 		} else {
 			Ok(None)
@@ -75,16 +88,63 @@ pub async fn get_thumbnail(&self, mxc: &Mxc<'_>, dim: &Dim) -> Result<Option<Fil
 	}
 }
 
+/// Streams a thumbnail we already have from the media store instead of reading
+/// it into memory: the original when an animated thumbnail of an animated image
+/// is asked for, or a thumbnail saved earlier. Returns None when the thumbnail
+/// must be generated or fetched first, which `get_thumbnail` does.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), name = "thumbnail_stream", level = "debug")]
+pub async fn get_thumbnail_stream(&self, mxc: &Mxc<'_>, dim: &Dim) -> Result<Option<FileStream>> {
+	if self.is_quarantined(mxc).await {
+		return Ok(None);
+	}
+
+	let metadata = match self.get_animated_original(mxc, dim).await {
+		| Some(metadata) => metadata,
+		| None => match self.db.search_file_metadata(mxc, &dim.normalized()).await {
+			| Ok(metadata) => metadata,
+			| Err(_) => return Ok(None),
+		},
+	};
+
+	let Metadata { content_disposition, content_type, key } = metadata;
+	let (size, content) = self.store.stream(&key).await?;
+	self.touch(mxc);
+
+	Ok(Some(FileStream {
+		content,
+		size,
+		content_type,
+		content_disposition,
+	}))
+}
+
+/// The original file, when the client prefers an animated thumbnail and the
+/// file may be animated.
+#[implement(super::Service)]
+async fn get_animated_original(&self, mxc: &Mxc<'_>, dim: &Dim) -> Option<Metadata> {
+	if !dim.animated {
+		return None;
+	}
+
+	// 0, 0 because that's the original file
+	self.db
+		.search_file_metadata(mxc, &Dim::default())
+		.await
+		.ok()
+		.filter(|metadata| {
+			metadata
+				.content_type
+				.as_deref()
+				.is_some_and(|content_type| ANIMATABLE_CONTENT_TYPES.contains(&content_type))
+		})
+}
+
 /// Using saved thumbnail
 #[implement(super::Service)]
 #[tracing::instrument(name = "saved", level = "debug", skip(self, data))]
 async fn get_thumbnail_saved(&self, data: Metadata) -> Result<Option<FileMeta>> {
-	let mut content = Vec::new();
-	let path = self.get_media_file(&data.key);
-	fs::File::open(path)
-		.await?
-		.read_to_end(&mut content)
-		.await?;
+	let content = self.store.get(&data.key).await?;
 
 	Ok(Some(into_filemeta(data, content)))
 }
@@ -99,28 +159,43 @@ async fn get_thumbnail_generate(
 	dim: &Dim,
 	data: Metadata,
 ) -> Result<Option<FileMeta>> {
-	let mut content = Vec::new();
-	let path = self.get_media_file(&data.key);
-	fs::File::open(path)
-		.await?
-		.read_to_end(&mut content)
-		.await?;
-
-	let Ok(image) = image::load_from_memory(&content) else {
-		// Couldn't parse file to generate thumbnail, send original
-		return Ok(Some(into_filemeta(data, content)));
-	};
+	let content = self.store.get(&data.key).await?;
+
+	// Decoding and scaling is CPU bound; it runs on the blocking pool with at
+	// most `thumbnail_generation_concurrency` images at once.
+	let _permit = self
+		.thumbnail_permits
+		.acquire()
+		.await
+		.map_err(|e| err!("Thumbnail generation is shut down: {e}"))?;
+
+	let requested = Dim::new(dim.width, dim.height, Some(dim.method.clone()));
+	let (content, thumbnail_bytes) =
+		tokio::task::spawn_blocking(move || -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+			let Ok(image) = image::load_from_memory(&content) else {
+				// Couldn't parse file to generate thumbnail, send original
+				return Ok((content, None));
+			};
+
+			if requested.width > image.width() || requested.height > image.height() {
+				return Ok((content, None));
+			}
+
+			let mut thumbnail_bytes = Vec::new();
+			let thumbnail = thumbnail_generate(&image, &requested)?;
+			let mut cursor = std::io::Cursor::new(&mut thumbnail_bytes);
+			thumbnail
+				.write_to(&mut cursor, image::ImageFormat::Png)
+				.map_err(|error| err!(error!(?error, "Error writing PNG thumbnail.")))?;
+
+			Ok((content, Some(thumbnail_bytes)))
+		})
+		.await
+		.map_err(|e| err!("Thumbnail generation failed: {e}"))??;
 
-	if dim.width > image.width() || dim.height > image.height() {
+	let Some(thumbnail_bytes) = thumbnail_bytes else {
 		return Ok(Some(into_filemeta(data, content)));
-	}
-
-	let mut thumbnail_bytes = Vec::new();
-	let thumbnail = thumbnail_generate(&image, dim)?;
-	let mut cursor = std::io::Cursor::new(&mut thumbnail_bytes);
-	thumbnail
-		.write_to(&mut cursor, image::ImageFormat::Png)
-		.map_err(|error| err!(error!(?error, "Error writing PNG thumbnail.")))?;
+	};
 
 	// Save thumbnail in database so we don't have to generate it again next time
 	let thumbnail_key = self.db.create_file_metadata(
@@ -131,8 +206,7 @@ async fn get_thumbnail_generate(
 		data.content_type.as_deref(),
 	)?;
 
-	let mut f = self.create_media_file(&thumbnail_key).await?;
-	f.write_all(&thumbnail_bytes).await?;
+	self.store.put(&thumbnail_key, &thumbnail_bytes).await?;
 
 	Ok(Some(into_filemeta(data, thumbnail_bytes)))
 }
@@ -199,6 +273,17 @@ pub fn new(width: u32, height: u32, method: Option<Method>) -> Self {
 			width,
 			height,
 			method: method.unwrap_or(Method::Scale),
+			animated: false,
+		}
+	}
+
+	/// Sets whether the client asked for an animated thumbnail.
+	#[inline]
+	#[must_use]
+	pub fn with_animated(self, animated: Option<bool>) -> Self {
+		Self {
+			animated: animated.unwrap_or(false),
+			..self
 		}
 	}
 
@@ -229,6 +314,7 @@ pub fn scaled(&self, image: &Self) -> Result<Self> {
 			width: x,
 			height: y,
 			method: Method::Scale,
+			animated: false,
 		})
 	}
 
@@ -260,6 +346,7 @@ fn default() -> Self {
 			width: 0,
 			height: 0,
 			method: Method::Scale,
+			animated: false,
 		}
 	}
 }
@@ -7,9 +7,9 @@
 		stream::{TryExpect, TryIgnore},
 		IterStream, ReadyExt,
 	},
-	warn, Err, Result,
+	warn, Err, PduEvent, Result,
 };
-use futures::{FutureExt, StreamExt};
+use futures::{future::BoxFuture, FutureExt, StreamExt};
 use itertools::Itertools;
 use ruma::{
 	events::{
@@ -29,6 +29,89 @@
 ///   equal or lesser version. These are expected to be backward-compatible.
 pub(crate) const DATABASE_VERSION: u64 = 17;
 
+/// Migrations applied once, in order, each recorded by its name in the
+/// `global` map. Those in the background run while the server is up; they
+/// save their progress as they go and resume from it after a restart.
+const MIGRATIONS: &[Migration] = &[
+	Migration {
+		name: "fix_bad_double_separator_in_state_cache",
+		background: false,
+		run: |services| fix_bad_double_separator_in_state_cache(services).boxed(),
+	},
+	Migration {
+		name: "retroactively_fix_bad_data_from_roomuserid_joined",
+		background: false,
+		run: |services| retroactively_fix_bad_data_from_roomuserid_joined(services).boxed(),
+	},
+	Migration {
+		name: "fix_referencedevents_missing_sep",
+		background: false,
+		run: |services| fix_referencedevents_missing_sep(services).boxed(),
+	},
+	Migration {
+		name: "fix_readreceiptid_readreceipt_duplicates",
+		background: false,
+		run: |services| fix_readreceiptid_readreceipt_duplicates(services).boxed(),
+	},
+	// Rename this whenever the server default push rules change (e.g. with a
+	// ruma update) so everyone's copy of them is brought up to date again.
+	Migration {
+		name: "upgrade_server_default_push_rules_1",
+		background: false,
+		run: |services| services.pusher.upgrade_rulesets().boxed(),
+	},
+	Migration {
+		name: "feat_media_usage_counters",
+		background: false,
+		run: |services| services.media.recount_media_usage().boxed(),
+	},
+	Migration {
+		name: "feat_topological_pdu_index",
+		background: true,
+		run: |services| index_pdus_topologically(services).boxed(),
+	},
+	Migration {
+		name: "feat_media_reference_index",
+		background: true,
+		run: |services| index_media_references(services).boxed(),
+	},
+];
+
+/// Migrations which were applied to databases before version 17 and have to
+/// be applied again.
+const REAPPLIED_BELOW_17: &[&str] =
+	&["fix_referencedevents_missing_sep", "fix_readreceiptid_readreceipt_duplicates"];
+
+/// PDUs added to the topological index between saves of its progress.
+const TOPOLOGICAL_INDEX_BATCH: usize = 10_000;
+
+/// PDUs whose media references are indexed between saves of the progress.
+const MEDIA_REFERENCE_INDEX_BATCH: usize = 10_000;
+
+struct Migration {
+	name: &'static str,
+	background: bool,
+	run: for<'a> fn(&'a Services) -> BoxFuture<'a, Result>,
+}
+
+/// Where a named migration stands, as shown by `!admin db migrations status`.
+#[derive(Debug)]
+pub struct MigrationStatus {
+	pub name: &'static str,
+	pub background: bool,
+	pub state: MigrationState,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum MigrationState {
+	Pending,
+
+	/// A background migration which saved some progress.
+	Running,
+
+	Applied,
+}
+
 pub(crate) async fn migrations(services: &Services) -> Result<()> {
 	let users_count = services.users.count().await;
 
@@ -45,6 +128,10 @@ pub(crate) async fn migrations(services: &Services) -> Result<()> {
 		}
 	}
 
+	if services.server.config.migrations_dry_run {
+		return dry_run(services, users_count == 0).await;
+	}
+
 	if users_count > 0 {
 		migrate(services).await
 	} else {
@@ -52,16 +139,112 @@ pub(crate) async fn migrations(services: &Services) -> Result<()> {
 	}
 }
 
+/// Runs the background migrations which are still pending, in order. Stops
+/// early at shutdown; they're resumed at the next startup.
+pub(crate) async fn background_migrations(services: &Services) -> Result {
+	for migration in MIGRATIONS.iter().filter(|migration| migration.background) {
+		if !services.server.running() {
+			break;
+		}
+
+		if is_applied(services, migration.name).await {
+			continue;
+		}
+
+		info!("Starting background migration {}", migration.name);
+		(migration.run)(services).await?;
+		if !services.server.running() {
+			break;
+		}
+
+		services.db["global"].insert(migration.name, []);
+		info!("Finished background migration {}", migration.name);
+	}
+
+	Ok(())
+}
+
+/// The schema version of the database, with the state of each named
+/// migration.
+pub async fn status(services: &Services) -> (u64, Vec<MigrationStatus>) {
+	let version = services.globals.db.database_version().await;
+	let mut statuses = Vec::with_capacity(MIGRATIONS.len());
+	for migration in MIGRATIONS {
+		let state = if is_applied(services, migration.name).await {
+			MigrationState::Applied
+		} else if services.db["global"]
+			.get(&progress_key(migration.name))
+			.await
+			.is_ok()
+		{
+			MigrationState::Running
+		} else {
+			MigrationState::Pending
+		};
+
+		statuses.push(MigrationStatus {
+			name: migration.name,
+			background: migration.background,
+			state,
+		});
+	}
+
+	(version, statuses)
+}
+
+/// Reports the migrations which would be applied without applying them. The
+/// server then exits instead of starting.
+async fn dry_run(services: &Services, fresh: bool) -> Result {
+	if fresh {
+		info!(
+			"Dry run: the database at {:?} is new and would be initialized with version \
+			 {DATABASE_VERSION}; RocksDB created its files, but nothing was written to them.",
+			services.server.config.database_path
+		);
+
+		return Ok(());
+	}
+
+	let version = services.globals.db.database_version().await;
+	info!("Dry run: database schema version {version}, current version {DATABASE_VERSION}");
+
+	let mut pending: usize = 0;
+	if version < DATABASE_VERSION {
+		info!("Dry run: schema migration from version {version} pending");
+		pending = pending.saturating_add(1);
+	}
+
+	let reapplied = version < 17;
+	for migration in MIGRATIONS {
+		if is_applied(services, migration.name).await
+			&& !(reapplied && REAPPLIED_BELOW_17.contains(&migration.name))
+		{
+			continue;
+		}
+
+		let when = if migration.background {
+			"in the background"
+		} else {
+			"at startup"
+		};
+		info!("Dry run: migration {} pending, to run {when}", migration.name);
+		pending = pending.saturating_add(1);
+	}
+
+	info!("Dry run finished with {pending} migrations pending; exiting without applying them.");
+
+	Ok(())
+}
+
 async fn fresh(services: &Services) -> Result<()> {
 	let db = &services.db;
 
 	services.globals.db.bump_database_version(DATABASE_VERSION);
 
 	db["global"].insert(b"feat_sha256_media", []);
-	db["global"].insert(b"fix_bad_double_separator_in_state_cache", []);
-	db["global"].insert(b"retroactively_fix_bad_data_from_roomuserid_joined", []);
-	db["global"].insert(b"fix_referencedevents_missing_sep", []);
-	db["global"].insert(b"fix_readreceiptid_readreceipt_duplicates", []);
+	for migration in MIGRATIONS {
+		db["global"].insert(migration.name, []);
+	}
 
 	// Create the admin room and server user on first run
 	crate::admin::create_admin_room(services).boxed().await?;
@@ -93,44 +276,35 @@ async fn migrate(services: &Services) -> Result<()> {
 		db_lt_13(services).await?;
 	}
 
-	if db["global"].get(b"feat_sha256_media").await.is_not_found() {
-		media::migrations::migrate_sha256_media(services).await?;
-	} else if config.media_startup_check {
-		media::migrations::checkup_sha256_media(services).await?;
-	}
-
-	if db["global"]
-		.get(b"fix_bad_double_separator_in_state_cache")
-		.await
-		.is_not_found()
-	{
-		fix_bad_double_separator_in_state_cache(services).await?;
+	// object stores have always used the sha256 names
+	if services.media.is_stored_locally() {
+		if db["global"].get(b"feat_sha256_media").await.is_not_found() {
+			media::migrations::migrate_sha256_media(services).await?;
+		} else if config.media_startup_check {
+			media::migrations::checkup_sha256_media(services).await?;
+		}
 	}
 
-	if db["global"]
-		.get(b"retroactively_fix_bad_data_from_roomuserid_joined")
-		.await
-		.is_not_found()
-	{
-		retroactively_fix_bad_data_from_roomuserid_joined(services).await?;
+	if services.globals.db.database_version().await < 17 {
+		for name in REAPPLIED_BELOW_17 {
+			db["global"].remove(name);
+		}
 	}
 
-	if db["global"]
-		.get(b"fix_referencedevents_missing_sep")
-		.await
-		.is_not_found()
-		|| services.globals.db.database_version().await < 17
-	{
-		fix_referencedevents_missing_sep(services).await?;
+	for migration in MIGRATIONS.iter().filter(|migration| !migration.background) {
+		if !is_applied(services, migration.name).await {
+			(migration.run)(services).await?;
+			db["global"].insert(migration.name, []);
+		}
 	}
 
-	if db["global"]
-		.get(b"fix_readreceiptid_readreceipt_duplicates")
-		.await
-		.is_not_found()
-		|| services.globals.db.database_version().await < 17
-	{
-		fix_readreceiptid_readreceipt_duplicates(services).await?;
+	// Resume pagination from as far as the index was built
+	if !is_applied(services, "feat_topological_pdu_index").await {
+		let progress = migration_progress(services, "feat_topological_pdu_index").await;
+		services
+			.rooms
+			.timeline
+			.set_topologically_indexed(Some(&progress));
 	}
 
 	if services.globals.db.database_version().await < 17 {
@@ -372,7 +546,6 @@ async fn fix_bad_double_separator_in_state_cache(services: &Services) -> Result<
 		.await;
 
 	db.db.sort()?;
-	db["global"].insert(b"fix_bad_double_separator_in_state_cache", []);
 
 	info!("Finished fixing");
 	Ok(())
@@ -458,7 +631,6 @@ async fn retroactively_fix_bad_data_from_roomuserid_joined(services: &Services)
 	}
 
 	db.db.sort()?;
-	db["global"].insert(b"retroactively_fix_bad_data_from_roomuserid_joined", []);
 
 	info!("Finished fixing");
 	Ok(())
@@ -502,7 +674,6 @@ async fn fix_referencedevents_missing_sep(services: &Services) -> Result {
 	drop(cork);
 	info!(?total, ?fixed, "Fixed missing record separators in 'referencedevents'.");
 
-	db["global"].insert(b"fix_referencedevents_missing_sep", []);
 	db.db.sort()
 }
 
@@ -552,6 +723,89 @@ async fn fix_readreceiptid_readreceipt_duplicates(services: &Services) -> Result
 	drop(cork);
 	info!(?total, ?fixed, "Fixed undeleted entries in readreceiptid_readreceipt.");
 
-	db["global"].insert(b"fix_readreceiptid_readreceipt_duplicates", []);
 	db.db.sort()
 }
+
+async fn index_pdus_topologically(services: &Services) -> Result {
+	let global = &services.db["global"];
+	let mut from = migration_progress(services, "feat_topological_pdu_index").await;
+	let mut indexed: usize = 0;
+
+	while services.server.running() {
+		let next = services
+			.rooms
+			.timeline
+			.index_topologically(&from, TOPOLOGICAL_INDEX_BATCH)
+			.await;
+
+		indexed = indexed.saturating_add(TOPOLOGICAL_INDEX_BATCH);
+		let Some(next) = next else {
+			global.remove(&progress_key("feat_topological_pdu_index"));
+			info!("Built the topological index of room timelines.");
+			return Ok(());
+		};
+
+		global.insert(&progress_key("feat_topological_pdu_index"), &next);
+		debug!(?indexed, "Building the topological index of room timelines...");
+		from = next;
+	}
+
+	info!(?indexed, "Paused building the topological index until the next startup.");
+	Ok(())
+}
+
+/// Indexes the media uploaded here which the stored PDUs refer to, so their
+/// visibility decides who may download it. Events appended since are indexed
+/// as they come.
+async fn index_media_references(services: &Services) -> Result {
+	let global = &services.db["global"];
+	let mut from = migration_progress(services, "feat_media_reference_index").await;
+	let mut indexed: usize = 0;
+
+	while services.server.running() {
+		let (count, last) = services.db["pduid_pdu"]
+			.raw_stream_from(&from)
+			.ignore_err()
+			.take(MEDIA_REFERENCE_INDEX_BATCH)
+			.ready_fold((0_usize, None), |(count, _), (pdu_id, pdu)| {
+				if let Ok(pdu) = serde_json::from_slice::<PduEvent>(pdu) {
+					services.media.index_references(&pdu);
+				}
+
+				(count.saturating_add(1), Some(pdu_id.to_vec()))
+			})
+			.await;
+
+		indexed = indexed.saturating_add(count);
+		let Some(mut next) = last.filter(|_| count >= MEDIA_REFERENCE_INDEX_BATCH) else {
+			global.remove(&progress_key("feat_media_reference_index"));
+			info!(?indexed, "Indexed the media referred to by room timelines.");
+			return Ok(());
+		};
+
+		// The least key after the last one indexed
+		next.push(0);
+		global.insert(&progress_key("feat_media_reference_index"), &next);
+		debug!(?indexed, "Indexing the media referred to by room timelines...");
+		from = next;
+	}
+
+	info!(?indexed, "Paused indexing media references until the next startup.");
+	Ok(())
+}
+
+/// Key a background migration continues from.
+async fn migration_progress(services: &Services, name: &str) -> Vec<u8> {
+	services.db["global"]
+		.get(&progress_key(name))
+		.await
+		.map(|progress| progress.to_vec())
+		.unwrap_or_default()
+}
+
+async fn is_applied(services: &Services, name: &str) -> bool {
+	services.db["global"].get(name).await.is_ok()
+}
+
+/// Key in the `global` map of a background migration's progress.
+fn progress_key(name: &str) -> String { format!("{name}_progress") }
@@ -1,7 +1,8 @@
 #![allow(refining_impl_trait)]
 
+mod lru;
 mod manager;
-mod migrations;
+pub mod migrations;
 mod service;
 pub mod services;
 
@@ -10,17 +11,28 @@
 pub mod appservice;
 pub mod client;
 pub mod config;
+pub mod deactivation;
+pub mod delayed_events;
+pub mod email;
 pub mod emergency;
 pub mod federation;
 pub mod globals;
+pub mod jobs;
 pub mod key_backups;
 pub mod media;
+pub mod moderation;
+pub mod onboarding;
 pub mod presence;
 pub mod pusher;
+pub mod rate_limiting;
+pub mod registration_tokens;
+pub mod reports;
 pub mod resolver;
 pub mod rooms;
 pub mod sending;
 pub mod server_keys;
+pub mod server_notices;
+pub mod sso;
 pub mod sync;
 pub mod transaction_ids;
 pub mod uiaa;
@@ -31,9 +43,10 @@
 extern crate conduwuit_database as database;
 
 pub use conduwuit::{pdu, PduBuilder, PduCount, PduEvent};
-pub(crate) use service::{Args, Dep, Service};
+pub(crate) use lru::LruCache;
+pub(crate) use service::{Args, Cache, Dep, Service};
 
-pub use crate::services::Services;
+pub use crate::{lru::CacheStats, services::Services};
 
 conduwuit::mod_ctor! {}
 conduwuit::mod_dtor! {}
@@ -0,0 +1,504 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	fmt::{self, Write},
+	sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use conduwuit::{
+	debug, debug_warn, implement,
+	utils::{stream::TryIgnore, ReadyExt},
+	warn, Err, PduEvent, Result, Server,
+};
+use database::Map;
+use futures::StreamExt;
+use regex::RegexSet;
+use ruma::{OwnedRoomId, RoomId, ServerName, UserId};
+use serde::Deserialize;
+
+use crate::{rooms, users, Dep};
+
+pub struct Service {
+	rules: RwLock<Rules>,
+	services: Services,
+	db: Data,
+}
+
+/// The indexed rules, with the matchers compiled from them per kind.
+#[derive(Default)]
+struct Rules {
+	rules: HashMap<RuleKey, Rule>,
+	matchers: BTreeMap<RuleKind, Matcher>,
+}
+
+/// Finds the rules of one kind matching an entity without going through all
+/// of them: literal entities are looked up, globs are matched at once.
+struct Matcher {
+	exact: HashMap<String, RuleKey>,
+	globs: RegexSet,
+	glob_keys: Vec<RuleKey>,
+}
+
+struct Services {
+	server: Arc<Server>,
+	state_accessor: Dep<rooms::state_accessor::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	users: Dep<users::Service>,
+}
+
+struct Data {
+	roomid_policylist: Arc<Map>,
+}
+
+/// Which entities a policy rule applies to (MSC2313).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RuleKind {
+	User,
+	Room,
+	Server,
+}
+
+/// Policy room, kind and state key of the event which set a rule.
+pub type RuleKey = (OwnedRoomId, RuleKind, String);
+
+/// A ban recommended by a subscribed policy list.
+#[derive(Clone, Debug)]
+pub struct Rule {
+	/// User ID, room ID or server name, possibly with `*` and `?` globs.
+	pub entity: String,
+	pub reason: Option<String>,
+	/// Anchored regular expression the glob in `entity` translates to.
+	glob: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RuleEventContent {
+	entity: String,
+	recommendation: String,
+	#[serde(default)]
+	reason: Option<String>,
+}
+
+/// Recommendations enforced as bans; anything else is ignored.
+const BAN_RECOMMENDATIONS: &[&str] = &["m.ban", "org.matrix.mjolnir.ban"];
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			rules: RwLock::default(),
+			services: Services {
+				server: args.server.clone(),
+				state_accessor: args
+					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				users: args.depend::<users::Service>("users"),
+			},
+			db: Data {
+				roomid_policylist: args.db["roomid_policylist"].clone(),
+			},
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		for room_id in self.subscriptions().await {
+			let count = self.index_room(&room_id).await;
+			debug!("Indexed {count} ban rules from policy list {room_id}");
+		}
+
+		Ok(())
+	}
+
+	fn memory_usage(&self, out: &mut dyn Write) -> Result {
+		let count = self.rules.read()?.rules.len();
+		writeln!(out, "policy_rules: {count}")?;
+
+		Ok(())
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Policy rooms whose rules are enforced: those in the config followed by
+/// those subscribed to from the admin room.
+#[implement(Service)]
+pub async fn subscriptions(&self) -> Vec<OwnedRoomId> {
+	let mut rooms = self.services.server.config.policy_list_rooms.clone();
+	self.db
+		.roomid_policylist
+		.keys()
+		.ignore_err()
+		.ready_for_each(|room_id: &RoomId| {
+			if !rooms.iter().any(|r| r == room_id) {
+				rooms.push(room_id.to_owned());
+			}
+		})
+		.await;
+
+	rooms
+}
+
+#[implement(Service)]
+pub async fn is_subscribed(&self, room_id: &RoomId) -> bool {
+	self.services
+		.server
+		.config
+		.policy_list_rooms
+		.iter()
+		.any(|r| r == room_id)
+		|| self.db.roomid_policylist.get(room_id).await.is_ok()
+}
+
+/// Starts enforcing the rules of a policy room the server is joined to,
+/// returning how many ban rules it has.
+#[implement(Service)]
+pub async fn subscribe(&self, room_id: &RoomId) -> Result<usize> {
+	if !self
+		.services
+		.state_cache
+		.server_in_room(&self.services.server.name, room_id)
+		.await
+	{
+		return Err!(Request(NotFound(
+			"This server is not in {room_id}; one of our users needs to join it first."
+		)));
+	}
+
+	self.db.roomid_policylist.insert(room_id, []);
+
+	Ok(self.index_room(room_id).await)
+}
+
+/// Stops enforcing the rules of a policy room subscribed to from the admin
+/// room.
+#[implement(Service)]
+pub fn unsubscribe(&self, room_id: &RoomId) {
+	self.db.roomid_policylist.remove(room_id);
+	let mut rules = self.rules.write().expect("locked for writing");
+	rules
+		.rules
+		.retain(|(policy_room, ..), _| policy_room != room_id);
+	rules.compile();
+}
+
+/// Replaces the indexed rules of a policy room with those in its current
+/// state, returning how many there are.
+#[implement(Service)]
+pub async fn index_room(&self, room_id: &RoomId) -> usize {
+	let rules: Vec<_> = self
+		.services
+		.state_accessor
+		.room_state_full_pdus(room_id)
+		.ignore_err()
+		.ready_filter_map(|pdu| {
+			let kind = RuleKind::from_event_type(&pdu.kind.to_string())?;
+			let state_key = pdu.state_key.clone()?;
+			let rule = self.rule_from_pdu(kind, &pdu)?;
+			Some(((room_id.to_owned(), kind, state_key), rule))
+		})
+		.collect()
+		.await;
+
+	let count = rules.len();
+	let mut indexed = self.rules.write().expect("locked for writing");
+	indexed
+		.rules
+		.retain(|(policy_room, ..), _| policy_room != room_id);
+	indexed.rules.extend(rules);
+	indexed.compile();
+
+	count
+}
+
+/// Keeps the index current as policy rule state events are appended to a
+/// subscribed room. Events which don't set a ban remove any previous rule.
+#[implement(Service)]
+pub async fn update_rule(&self, pdu: &PduEvent) {
+	let Some(kind) = RuleKind::from_event_type(&pdu.kind.to_string()) else {
+		return;
+	};
+
+	let Some(state_key) = pdu.state_key.clone() else {
+		return;
+	};
+
+	if !self.is_subscribed(&pdu.room_id).await {
+		return;
+	}
+
+	let key = (pdu.room_id.clone(), kind, state_key);
+	let rule = self.rule_from_pdu(kind, pdu);
+	let mut rules = self.rules.write().expect("locked for writing");
+	match rule {
+		| Some(rule) => {
+			debug!("Policy list {} bans {kind} {}", pdu.room_id, rule.entity);
+			rules.rules.insert(key, rule);
+		},
+		| None => {
+			rules.rules.remove(&key);
+		},
+	}
+
+	rules.compile();
+}
+
+/// The ban a policy rule event sets, unless it would ban this server itself.
+#[implement(Service)]
+fn rule_from_pdu(&self, kind: RuleKind, pdu: &PduEvent) -> Option<Rule> {
+	let rule = Rule::from_pdu(pdu)?;
+	if kind == RuleKind::Server && rule.matches(self.services.server.name.as_str()) {
+		warn!(
+			"Ignoring the ban of {} in policy list {}, which matches this server",
+			rule.entity, pdu.room_id
+		);
+		return None;
+	}
+
+	Some(rule)
+}
+
+/// All indexed ban rules.
+#[implement(Service)]
+pub fn rules(&self) -> Vec<(RuleKey, Rule)> {
+	let mut rules: Vec<_> = self
+		.rules
+		.read()
+		.expect("locked for reading")
+		.rules
+		.iter()
+		.map(|(key, rule)| (key.clone(), rule.clone()))
+		.collect();
+
+	rules.sort_by(|(a, _), (b, _)| a.cmp(b));
+	rules
+}
+
+/// The rule banning a user, either directly or through their server.
+#[implement(Service)]
+pub fn user_ban(&self, user_id: &UserId) -> Option<Rule> {
+	self.find(RuleKind::User, user_id.as_str())
+		.or_else(|| self.server_ban(user_id.server_name()))
+}
+
+#[implement(Service)]
+pub fn server_ban(&self, server_name: &ServerName) -> Option<Rule> {
+	self.find(RuleKind::Server, server_name.as_str())
+}
+
+#[implement(Service)]
+pub fn room_ban(&self, room_id: &RoomId) -> Option<Rule> {
+	self.find(RuleKind::Room, room_id.as_str())
+}
+
+/// Rejects invites sent by banned users or into banned rooms. Invites sent by
+/// local admins are exempt.
+#[implement(Service)]
+pub async fn check_invite(&self, sender: &UserId, room_id: &RoomId) -> Result {
+	if !self.services.server.config.policy_list_enforce_invites
+		|| self.services.users.is_admin(sender).await
+	{
+		return Ok(());
+	}
+
+	if let Some(rule) = self.user_ban(sender) {
+		let reason = rule.display_reason();
+		return Err!(Request(Forbidden(debug_warn!(
+			"Invites from {sender} are banned by a policy list{reason}"
+		))));
+	}
+
+	if let Some(rule) = self.room_ban(room_id) {
+		let reason = rule.display_reason();
+		return Err!(Request(Forbidden(debug_warn!(
+			"Invites to {room_id} are banned by a policy list{reason}"
+		))));
+	}
+
+	Ok(())
+}
+
+/// Denies joins by banned users or to banned rooms. Local admins are exempt.
+#[implement(Service)]
+pub async fn check_join(&self, user_id: &UserId, room_id: &RoomId) -> Result {
+	if !self.services.server.config.policy_list_enforce_joins
+		|| self.services.users.is_admin(user_id).await
+	{
+		return Ok(());
+	}
+
+	if let Some(rule) = self.user_ban(user_id) {
+		let reason = rule.display_reason();
+		return Err!(Request(Forbidden(debug_warn!(
+			"{user_id} is banned by a policy list{reason}"
+		))));
+	}
+
+	if let Some(rule) = self.room_ban(room_id) {
+		let reason = rule.display_reason();
+		return Err!(Request(Forbidden(warn!(
+			"{user_id} tried joining {room_id} which is banned by a policy list{reason}"
+		))));
+	}
+
+	Ok(())
+}
+
+/// Rejects federation requests from banned servers.
+#[implement(Service)]
+pub fn check_server(&self, origin: &ServerName) -> Result {
+	if !self.services.server.config.policy_list_enforce_federation {
+		return Ok(());
+	}
+
+	if let Some(rule) = self.server_ban(origin) {
+		let reason = rule.display_reason();
+		return Err!(Request(Forbidden(debug_warn!(
+			"Federation requests from {origin} are banned by a policy list{reason}"
+		))));
+	}
+
+	Ok(())
+}
+
+/// Drops federated PDUs sent by banned users or into banned rooms.
+#[implement(Service)]
+pub fn check_pdu(&self, room_id: &RoomId, sender: Option<&UserId>) -> Result {
+	if !self.services.server.config.policy_list_enforce_federation {
+		return Ok(());
+	}
+
+	if let Some(rule) = sender.and_then(|sender| self.user_ban(sender)) {
+		let reason = rule.display_reason();
+		return Err!(Request(Forbidden(debug_warn!(
+			"Events from this sender are banned by a policy list{reason}"
+		))));
+	}
+
+	if let Some(rule) = self.room_ban(room_id) {
+		let reason = rule.display_reason();
+		return Err!(Request(Forbidden(debug_warn!(
+			"Events in {room_id} are banned by a policy list{reason}"
+		))));
+	}
+
+	Ok(())
+}
+
+#[implement(Service)]
+fn find(&self, kind: RuleKind, entity: &str) -> Option<Rule> {
+	let rules = self.rules.read().expect("locked for reading");
+	let matcher = rules.matchers.get(&kind)?;
+	let key = matcher.exact.get(entity).or_else(|| {
+		matcher
+			.globs
+			.matches(entity)
+			.iter()
+			.next()
+			.map(|i| &matcher.glob_keys[i])
+	})?;
+
+	rules.rules.get(key).cloned()
+}
+
+impl Rules {
+	/// Rebuilds the matchers after the rules changed.
+	fn compile(&mut self) {
+		let mut exact: BTreeMap<RuleKind, HashMap<String, RuleKey>> = BTreeMap::new();
+		let mut globs: BTreeMap<RuleKind, (Vec<&str>, Vec<RuleKey>)> = BTreeMap::new();
+		for (key, rule) in &self.rules {
+			let (_, kind, _) = key;
+			match &rule.glob {
+				| Some(glob) => {
+					let (patterns, keys) = globs.entry(*kind).or_default();
+					patterns.push(glob);
+					keys.push(key.clone());
+				},
+				| None => {
+					exact
+						.entry(*kind)
+						.or_default()
+						.insert(rule.entity.clone(), key.clone());
+				},
+			}
+		}
+
+		self.matchers = [RuleKind::User, RuleKind::Room, RuleKind::Server]
+			.into_iter()
+			.map(|kind| {
+				let (patterns, glob_keys) = globs.remove(&kind).unwrap_or_default();
+				let globs = RegexSet::new(patterns).unwrap_or_else(|e| {
+					warn!("Failed to compile {kind} policy rule globs: {e}");
+					RegexSet::empty()
+				});
+
+				let exact = exact.remove(&kind).unwrap_or_default();
+				(kind, Matcher { exact, globs, glob_keys })
+			})
+			.collect();
+	}
+}
+
+impl Rule {
+	fn from_pdu(pdu: &PduEvent) -> Option<Self> {
+		let content: RuleEventContent = pdu.get_content().ok()?;
+		if !BAN_RECOMMENDATIONS.contains(&content.recommendation.as_str()) {
+			return None;
+		}
+
+		let glob = content.entity.contains(['*', '?']).then(|| {
+			let pattern = regex::escape(&content.entity)
+				.replace("\\*", ".*")
+				.replace("\\?", ".");
+
+			format!("^{pattern}$")
+		});
+
+		Some(Self {
+			entity: content.entity,
+			reason: content.reason.filter(|reason| !reason.is_empty()),
+			glob,
+		})
+	}
+
+	/// Whether the rule applies to an entity. Lookups go through the compiled
+	/// matchers instead; this compiles the glob each time.
+	#[must_use]
+	pub fn matches(&self, entity: &str) -> bool {
+		self.glob.as_ref().map_or(self.entity == entity, |glob| {
+			regex::Regex::new(glob).is_ok_and(|glob| glob.is_match(entity))
+		})
+	}
+
+	fn display_reason(&self) -> String {
+		self.reason
+			.as_deref()
+			.map_or_else(String::new, |reason| format!(": {reason}"))
+	}
+}
+
+impl RuleKind {
+	/// Stable and unstable event types used for policy rules.
+	#[must_use]
+	pub fn from_event_type(kind: &str) -> Option<Self> {
+		match kind {
+			| "m.policy.rule.user" | "m.room.rule.user" | "org.matrix.mjolnir.rule.user" =>
+				Some(Self::User),
+			| "m.policy.rule.room" | "m.room.rule.room" | "org.matrix.mjolnir.rule.room" =>
+				Some(Self::Room),
+			| "m.policy.rule.server"
+			| "m.room.rule.server"
+			| "org.matrix.mjolnir.rule.server" => Some(Self::Server),
+			| _ => None,
+		}
+	}
+}
+
+impl fmt::Display for RuleKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			| Self::User => write!(f, "user"),
+			| Self::Room => write!(f, "room"),
+			| Self::Server => write!(f, "server"),
+		}
+	}
+}
@@ -0,0 +1,328 @@
+use std::{fmt, sync::Arc};
+
+use conduwuit::{
+	debug_warn, err, implement, info, pdu::PduBuilder, utils, warn, Err, Result, Server,
+};
+use futures::{future::BoxFuture, FutureExt};
+use ruma::{
+	events::{
+		push_rules::{PushRulesEvent, PushRulesEventContent},
+		room::member::{MembershipState, RoomMemberEventContent},
+		GlobalAccountDataEventType,
+	},
+	push::Ruleset,
+	OwnedRoomOrAliasId, OwnedUserId, RoomOrAliasId, UserId,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{account_data, globals, rooms, server_notices, sso, users, Dep};
+
+pub struct Service {
+	services: Services,
+}
+
+struct Services {
+	server: Arc<Server>,
+	account_data: Dep<account_data::Service>,
+	alias: Dep<rooms::alias::Service>,
+	globals: Dep<globals::Service>,
+	server_notices: Dep<server_notices::Service>,
+	sso: Dep<sso::Service>,
+	state: Dep<rooms::state::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	timeline: Dep<rooms::timeline::Service>,
+	users: Dep<users::Service>,
+}
+
+/// An account to create ahead of its user's first login.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NewUser {
+	pub localpart: String,
+
+	/// Generated if neither this nor `sso` is given. Replaced by its hash
+	/// before the user is queued for creation; see [`Service::hash_passwords`].
+	#[serde(default, skip_serializing)]
+	pub password: Option<String>,
+
+	/// The Argon2 hash of the password, which is stored instead of it.
+	#[serde(default)]
+	pub password_hash: Option<String>,
+
+	/// The identity to link the account to, for users logging in through an
+	/// identity provider.
+	#[serde(default)]
+	pub sso: Option<SsoIdentity>,
+
+	/// Defaults to the localpart.
+	#[serde(default)]
+	pub displayname: Option<String>,
+
+	/// Joined in addition to the rooms every new user joins.
+	#[serde(default)]
+	pub rooms: Vec<OwnedRoomOrAliasId>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SsoIdentity {
+	/// The `id` of an `[[global.identity_provider]]`.
+	pub provider: String,
+	pub subject: String,
+}
+
+/// A step run for every new local user.
+type Hook = for<'a> fn(&'a Service, &'a UserId) -> BoxFuture<'a, Result>;
+
+/// The steps run for new users, in order. A failing step is logged and does
+/// not stop the later ones or fail the registration.
+const HOOKS: [(&str, Hook); 3] = [
+	("default account data", |s, user_id| s.set_default_account_data(user_id).boxed()),
+	("space invites", |s, user_id| s.join_spaces(user_id).boxed()),
+	("welcome message", |s, user_id| s.send_welcome_message(user_id).boxed()),
+];
+
+/// Length of passwords generated for provisioned accounts.
+const GENERATED_PASSWORD_LENGTH: usize = 25;
+
+impl fmt::Display for NewUser {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(&self.localpart) }
+}
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			services: Services {
+				server: args.server.clone(),
+				account_data: args.depend::<account_data::Service>("account_data"),
+				alias: args.depend::<rooms::alias::Service>("rooms::alias"),
+				globals: args.depend::<globals::Service>("globals"),
+				server_notices: args.depend::<server_notices::Service>("server_notices"),
+				sso: args.depend::<sso::Service>("sso"),
+				state: args.depend::<rooms::state::Service>("rooms::state"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
+				users: args.depend::<users::Service>("users"),
+			},
+		}))
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Runs the configured steps for a user who just registered or logged in for
+/// the first time. Not meant for guests or appservice users.
+#[implement(Service)]
+pub async fn run_registration_hooks(&self, user_id: &UserId) {
+	for (name, hook) in HOOKS {
+		if let Err(e) = hook(self, user_id).await {
+			debug_warn!(%user_id, "Registration step {name} failed: {e}");
+		}
+	}
+}
+
+/// Replaces the passwords of the users to create with their hashes,
+/// generating one for each user given neither a password nor an identity
+/// provider subject. Returns the generated passwords, which aren't kept
+/// anywhere else, along with their users' localparts.
+#[implement(Service)]
+pub fn hash_passwords(&self, new_users: &mut [NewUser]) -> Result<Vec<(String, String)>> {
+	let mut generated = Vec::new();
+	for new_user in new_users {
+		let password = match new_user.password.take() {
+			| Some(password) => password,
+			| None if new_user.sso.is_none() && new_user.password_hash.is_none() => {
+				let password = utils::random_string(GENERATED_PASSWORD_LENGTH);
+				generated.push((new_user.localpart.clone(), password.clone()));
+				password
+			},
+			| None => continue,
+		};
+
+		let hash = utils::hash::password(&password).map_err(|e| {
+			err!(Request(InvalidParam(
+				"Password of {:?} does not meet the requirements: {e}",
+				new_user.localpart
+			)))
+		})?;
+
+		new_user.password_hash = Some(hash);
+	}
+
+	Ok(generated)
+}
+
+/// Creates an account as registration would, linking its identity provider
+/// subject if one is given, and joins it to its rooms. Passwords are
+/// expected to be hashed by [`Service::hash_passwords`] beforehand, so they
+/// aren't stored with the job. Failing to join a room does not fail the
+/// provisioning.
+#[implement(Service)]
+pub async fn provision(&self, new_user: &NewUser) -> Result<OwnedUserId> {
+	let server_name = self.services.globals.server_name();
+	let user_id = UserId::parse_with_server_name(new_user.localpart.to_lowercase(), server_name)
+		.ok()
+		.filter(|user_id| !user_id.is_historical() && user_id.server_name() == server_name)
+		.ok_or_else(|| {
+			err!(Request(InvalidUsername("{:?} is not a valid username.", new_user.localpart)))
+		})?;
+
+	if self.services.users.exists(&user_id).await {
+		return Err!(Request(UserInUse("{user_id} already exists.")));
+	}
+
+	match &new_user.password_hash {
+		| Some(hash) => self.services.users.set_password_hash(&user_id, Some(hash)),
+		| None => self
+			.services
+			.users
+			.set_password(&user_id, new_user.password.as_deref())?,
+	}
+
+	if let Some(SsoIdentity { provider, subject }) = &new_user.sso {
+		self.services
+			.sso
+			.link_identity(provider, subject, &user_id)?;
+	}
+
+	let displayname = new_user
+		.displayname
+		.clone()
+		.unwrap_or_else(|| user_id.localpart().to_owned());
+
+	self.services
+		.users
+		.set_displayname(&user_id, Some(displayname));
+
+	self.services
+		.account_data
+		.update(
+			None,
+			&user_id,
+			GlobalAccountDataEventType::PushRules.to_string().into(),
+			&serde_json::to_value(PushRulesEvent {
+				content: PushRulesEventContent {
+					global: Ruleset::server_default(&user_id),
+				},
+			})
+			.expect("to json always works"),
+		)
+		.await?;
+
+	self.run_registration_hooks(&user_id).await;
+
+	for room in &new_user.rooms {
+		if let Err(e) = self.join_room(&user_id, room).await {
+			debug_warn!(%user_id, "Not joining provisioned user to {room}: {e}");
+		}
+	}
+
+	info!("Provisioned user {user_id}");
+
+	Ok(user_id)
+}
+
+/// Sets the configured global account data, which may replace the default
+/// push rules.
+#[implement(Service)]
+async fn set_default_account_data(&self, user_id: &UserId) -> Result {
+	for (event_type, content) in &self.services.server.config.registration_account_data {
+		let event = serde_json::json!({
+			"type": event_type,
+			"content": content,
+		});
+
+		self.services
+			.account_data
+			.update(None, user_id, event_type.as_str().into(), &event)
+			.await?;
+	}
+
+	Ok(())
+}
+
+/// Invites the user to the configured spaces from the server user and accepts
+/// the invites on their behalf. The server user must be in the spaces and
+/// allowed to invite. A space which can't be joined is reported and doesn't
+/// keep the user out of the others.
+#[implement(Service)]
+async fn join_spaces(&self, user_id: &UserId) -> Result {
+	let mut failed = Vec::new();
+	for space in &self.services.server.config.registration_space_invites {
+		match self.join_room(user_id, space).await {
+			| Ok(()) => info!("Joined {user_id} to {space} upon registration"),
+			| Err(e) => {
+				warn!("Failed to join {user_id} to {space} upon registration: {e}");
+				failed.push(space.as_str());
+			},
+		}
+	}
+
+	if !failed.is_empty() {
+		return Err!("Could not join {user_id} to {}", failed.join(", "));
+	}
+
+	Ok(())
+}
+
+/// Invites the user to a room from the server user and accepts the invite on
+/// their behalf.
+#[implement(Service)]
+async fn join_room(&self, user_id: &UserId, room: &RoomOrAliasId) -> Result {
+	let server_user = &self.services.globals.server_user;
+	let room_id = self.services.alias.resolve(room).await?;
+	if !self
+		.services
+		.state_cache
+		.is_joined(server_user, &room_id)
+		.await
+	{
+		return Err!("The server user is not in {room}.");
+	}
+
+	let state_lock = self.services.state.mutex.lock(&room_id).await;
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(
+				user_id.to_string(),
+				&RoomMemberEventContent::new(MembershipState::Invite),
+			),
+			server_user,
+			&room_id,
+			&state_lock,
+		)
+		.await?;
+
+	let content = RoomMemberEventContent {
+		displayname: self.services.users.displayname(user_id).await.ok(),
+		avatar_url: self.services.users.avatar_url(user_id).await.ok(),
+		blurhash: self.services.users.blurhash(user_id).await.ok(),
+		..RoomMemberEventContent::new(MembershipState::Join)
+	};
+
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(user_id.to_string(), &content),
+			user_id,
+			&room_id,
+			&state_lock,
+		)
+		.await?;
+
+	Ok(())
+}
+
+/// Sends the configured welcome message to the user's server notices room.
+#[implement(Service)]
+async fn send_welcome_message(&self, user_id: &UserId) -> Result {
+	let Some(message) = &self.services.server.config.registration_welcome_message else {
+		return Ok(());
+	};
+
+	self.services
+		.server_notices
+		.send_notice(user_id, message)
+		.await?;
+
+	Ok(())
+}
@@ -5,7 +5,7 @@
 
 use async_trait::async_trait;
 use conduwuit::{
-	checked, debug, debug_warn, error, result::LogErr, trace, Error, Result, Server,
+	checked, debug, debug_warn, error, result::LogErr, trace, Err, Error, Result, Server,
 };
 use database::Database;
 use futures::{stream::FuturesUnordered, Stream, StreamExt, TryFutureExt};
@@ -14,7 +14,7 @@
 use tokio::time::sleep;
 
 use self::{data::Data, presence::Presence};
-use crate::{globals, users, Dep};
+use crate::{globals, sending, sending::EduBuf, users, Dep};
 
 pub struct Service {
 	timer_channel: (Sender<TimerType>, Receiver<TimerType>),
@@ -29,6 +29,7 @@ struct Services {
 	server: Arc<Server>,
 	db: Arc<Database>,
 	globals: Dep<globals::Service>,
+	sending: Dep<sending::Service>,
 	users: Dep<users::Service>,
 }
 
@@ -50,6 +51,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				server: args.server.clone(),
 				db: args.db.clone(),
 				globals: args.depend::<globals::Service>("globals"),
+				sending: args.depend::<sending::Service>("sending"),
 				users: args.depend::<users::Service>("users"),
 			},
 		}))
@@ -118,8 +120,13 @@ pub async fn ping_presence(&self, user_id: &UserId, new_state: &PresenceState) -
 			return Ok(());
 		}
 
+		// A message forbidden since it was set is cleared instead of refusing the ping
 		let status_msg = match last_presence {
-			| Ok((_, ref presence)) => presence.content.status_msg.clone(),
+			| Ok((_, ref presence)) => presence
+				.content
+				.status_msg
+				.clone()
+				.filter(|msg| !self.status_msg_forbidden(msg)),
 			| Err(_) => Some(String::new()),
 		};
 
@@ -143,6 +150,15 @@ pub async fn set_presence(
 			| &_ => state,
 		};
 
+		let status_msg = match self.moderate_status_msg(status_msg) {
+			| Ok(status_msg) => status_msg,
+			| Err(e) if !self.services.globals.user_is_local(user_id) => {
+				debug_warn!(%user_id, "Dropping presence update: {e}");
+				return Ok(());
+			},
+			| Err(e) => return Err(e),
+		};
+
 		self.db
 			.set_presence(user_id, presence_state, currently_active, last_active_ago, status_msg)
 			.await?;
@@ -164,9 +180,50 @@ pub async fn set_presence(
 				})?;
 		}
 
+		if let Ok(presence) = self.get_presence(user_id).await {
+			let mut buf = EduBuf::new();
+			serde_json::to_writer(&mut buf, &presence).expect("Serialized m.presence event");
+			self.services
+				.sending
+				.send_edu_appservices_user(user_id, buf)
+				.await?;
+		}
+
 		Ok(())
 	}
 
+	/// Truncates a status message longer than `presence_status_msg_max_length`
+	/// and refuses one matching `forbidden_presence_status_patterns`.
+	fn moderate_status_msg(&self, status_msg: Option<String>) -> Result<Option<String>> {
+		let Some(mut status_msg) = status_msg else {
+			return Ok(None);
+		};
+
+		if self.status_msg_forbidden(&status_msg) {
+			return Err!(Request(Forbidden("This status message is not allowed.")));
+		}
+
+		let max_length = self.services.server.config.presence_status_msg_max_length;
+		if max_length > 0 && status_msg.len() > max_length {
+			let end = (0..=max_length)
+				.rev()
+				.find(|&end| status_msg.is_char_boundary(end))
+				.unwrap_or(0);
+
+			status_msg.truncate(end);
+		}
+
+		Ok(Some(status_msg))
+	}
+
+	fn status_msg_forbidden(&self, status_msg: &str) -> bool {
+		self.services
+			.server
+			.config
+			.forbidden_presence_status_patterns
+			.is_match(status_msg)
+	}
+
 	/// Removes the presence record for the given user from the database.
 	///
 	/// TODO: Why is this not used?
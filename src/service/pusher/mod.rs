@@ -1,10 +1,13 @@
-use std::{fmt::Debug, mem, sync::Arc};
+mod room_default;
+mod rules;
+
+use std::{collections::BTreeMap, fmt::Debug, mem, sync::Arc};
 
 use bytes::BytesMut;
 use conduwuit::{
 	debug_warn, err, trace,
-	utils::{stream::TryIgnore, string_from_bytes},
-	warn, Err, PduEvent, Result,
+	utils::{stream::TryIgnore, string_from_bytes, ReadyExt},
+	warn, Err, PduEvent, Result, Server,
 };
 use database::{Deserialized, Ignore, Interfix, Json, Map};
 use futures::{Stream, StreamExt};
@@ -19,17 +22,18 @@
 		IncomingResponse, MatrixVersion, OutgoingRequest, SendAccessToken,
 	},
 	events::{
-		room::power_levels::RoomPowerLevelsEventContent, AnySyncTimelineEvent, StateEventType,
-		TimelineEventType,
-	},
-	push::{
-		Action, PushConditionPowerLevelsCtx, PushConditionRoomCtx, PushFormat, Ruleset, Tweak,
+		room::power_levels::RoomPowerLevelsEventContent, StateEventType, TimelineEventType,
 	},
-	serde::Raw,
-	uint, RoomId, UInt, UserId,
+	push::{Action, PushFormat, Ruleset, Tweak},
+	uint, UInt, UserId,
 };
+use serde_json::Value as JsonValue;
 
-use crate::{client, globals, rooms, sending, users, Dep};
+pub use self::room_default::{
+	NotificationDefaultEventContent, NotificationDefaultsEventContent, NotificationLevel,
+	NOTIFICATION_DEFAULTS_ACCOUNT_DATA_TYPE, NOTIFICATION_DEFAULT_EVENT_TYPE,
+};
+use crate::{account_data, client, globals, rooms, sending, users, Dep};
 
 pub struct Service {
 	db: Data,
@@ -37,6 +41,8 @@ pub struct Service {
 }
 
 struct Services {
+	server: Arc<Server>,
+	account_data: Dep<account_data::Service>,
 	globals: Dep<globals::Service>,
 	client: Dep<client::Service>,
 	state_accessor: Dep<rooms::state_accessor::Service>,
@@ -56,6 +62,8 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				senderkey_pusher: args.db["senderkey_pusher"].clone(),
 			},
 			services: Services {
+				server: args.server.clone(),
+				account_data: args.depend::<account_data::Service>("account_data"),
 				globals: args.depend::<globals::Service>("globals"),
 				client: args.depend::<client::Service>("client"),
 				state_accessor: args
@@ -126,20 +134,41 @@ pub async fn set_pusher(
 				self.db.senderkey_pusher.put(key, Json(pusher));
 			},
 			| set_pusher::v3::PusherAction::Delete(ids) => {
-				let key = (sender, ids.pushkey.as_str());
-				self.db.senderkey_pusher.del(key);
-
-				self.services
-					.sending
-					.cleanup_events(None, Some(sender), Some(ids.pushkey.as_str()))
-					.await
-					.ok();
+				self.delete_pusher(sender, ids.pushkey.as_str()).await;
 			},
 		}
 
 		Ok(())
 	}
 
+	/// Removes the pusher along with the notifications queued for it.
+	pub async fn delete_pusher(&self, sender: &UserId, pushkey: &str) {
+		let key = (sender, pushkey);
+		self.db.senderkey_pusher.del(key);
+
+		self.services
+			.sending
+			.cleanup_events(None, Some(sender), Some(pushkey))
+			.await
+			.ok();
+	}
+
+	/// Queues an update of the badge count for each of the user's pushers,
+	/// such as after their unread notifications were read.
+	pub async fn send_badge_updates(&self, user: &UserId) {
+		self.get_pushkeys(user)
+			.ready_for_each(|pushkey| {
+				if let Err(e) = self
+					.services
+					.sending
+					.send_push_badge(user, pushkey.to_owned())
+				{
+					warn!(%user, "Failed to queue badge update: {e}");
+				}
+			})
+			.await;
+	}
+
 	pub async fn get_pusher(&self, sender: &UserId, pushkey: &str) -> Result<Pusher> {
 		let senderkey = (sender, pushkey);
 		self.db
@@ -263,7 +292,7 @@ pub async fn send_push_notice(
 		user: &UserId,
 		unread: UInt,
 		pusher: &Pusher,
-		ruleset: Ruleset,
+		mut ruleset: Ruleset,
 		pdu: &PduEvent,
 	) -> Result<()> {
 		let mut notify = None;
@@ -281,6 +310,9 @@ pub async fn send_push_notice(
 			})
 			.unwrap_or_default();
 
+		self.apply_room_default(user, &pdu.room_id, &mut ruleset)
+			.await;
+
 		for action in self
 			.get_actions(user, &ruleset, &power_levels, &pdu.to_sync_room_event(), &pdu.room_id)
 			.await
@@ -304,58 +336,17 @@ pub async fn send_push_notice(
 		}
 
 		if notify == Some(true) {
-			self.send_notice(unread, pusher, tweaks, pdu).await?;
+			self.send_notice(user, unread, pusher, tweaks, pdu).await?;
 		}
 		// Else the event triggered no actions
 
 		Ok(())
 	}
 
-	#[tracing::instrument(skip(self, user, ruleset, pdu), level = "debug")]
-	pub async fn get_actions<'a>(
-		&self,
-		user: &UserId,
-		ruleset: &'a Ruleset,
-		power_levels: &RoomPowerLevelsEventContent,
-		pdu: &Raw<AnySyncTimelineEvent>,
-		room_id: &RoomId,
-	) -> &'a [Action] {
-		let power_levels = PushConditionPowerLevelsCtx {
-			users: power_levels.users.clone(),
-			users_default: power_levels.users_default,
-			notifications: power_levels.notifications.clone(),
-		};
-
-		let room_joined_count = self
-			.services
-			.state_cache
-			.room_joined_count(room_id)
-			.await
-			.unwrap_or(1)
-			.try_into()
-			.unwrap_or_else(|_| uint!(0));
-
-		let user_display_name = self
-			.services
-			.users
-			.displayname(user)
-			.await
-			.unwrap_or_else(|_| user.localpart().to_owned());
-
-		let ctx = PushConditionRoomCtx {
-			room_id: room_id.to_owned(),
-			member_count: room_joined_count,
-			user_id: user.to_owned(),
-			user_display_name,
-			power_levels: Some(power_levels),
-		};
-
-		ruleset.get_actions(pdu, &ctx)
-	}
-
-	#[tracing::instrument(skip(self, unread, pusher, tweaks, event))]
+	#[tracing::instrument(skip(self, user, unread, pusher, tweaks, event))]
 	async fn send_notice(
 		&self,
+		user: &UserId,
 		unread: UInt,
 		pusher: &Pusher,
 		tweaks: Vec<Tweak>,
@@ -408,11 +399,7 @@ async fn send_notice(
 
 				notifi.event_id = Some((*event.event_id).to_owned());
 				notifi.room_id = Some((*event.room_id).to_owned());
-				if http
-					.data
-					.get("org.matrix.msc4076.disable_badge_count")
-					.is_none() && http.data.get("disable_badge_count").is_none()
-				{
+				if !badge_count_disabled(&http.data) {
 					notifi.counts = NotificationCounts::new(unread, uint!(0));
 				} else {
 					// counts will not be serialised if it's the default (0, 0)
@@ -421,11 +408,8 @@ async fn send_notice(
 				}
 
 				if event_id_only {
-					self.send_request(
-						&http.url,
-						send_event_notification::v1::Request::new(notifi),
-					)
-					.await?;
+					self.send_notification(user, pusher, &http.url, notifi)
+						.await?;
 				} else {
 					if event.kind == TimelineEventType::RoomEncrypted
 						|| tweaks
@@ -462,11 +446,8 @@ async fn send_notice(
 						.await
 						.ok();
 
-					self.send_request(
-						&http.url,
-						send_event_notification::v1::Request::new(notifi),
-					)
-					.await?;
+					self.send_notification(user, pusher, &http.url, notifi)
+						.await?;
 				}
 
 				Ok(())
@@ -476,4 +457,61 @@ async fn send_notice(
 			| _ => Ok(()),
 		}
 	}
+
+	/// Sends the user's unread count alone, so their app's badge is updated
+	/// once they've read notifications elsewhere. Pushers which opted out of
+	/// badge counts are skipped.
+	#[tracing::instrument(skip(self, user, unread, pusher))]
+	pub async fn send_badge_update(
+		&self,
+		user: &UserId,
+		unread: UInt,
+		pusher: &Pusher,
+	) -> Result {
+		let PusherKind::Http(http) = &pusher.kind else {
+			return Ok(());
+		};
+
+		if badge_count_disabled(&http.data) {
+			return Ok(());
+		}
+
+		let mut device = Device::new(pusher.ids.app_id.clone(), pusher.ids.pushkey.clone());
+		device.data.data.clone_from(&http.data);
+		device.data.format.clone_from(&http.format);
+
+		let mut notifi = Notification::new(vec![device]);
+		notifi.prio = NotificationPriority::Low;
+		notifi.counts = NotificationCounts::new(unread, uint!(0));
+
+		self.send_notification(user, pusher, &http.url, notifi)
+			.await
+	}
+
+	/// Sends the notification to the push gateway, removing the pusher if
+	/// the gateway rejects its pushkey.
+	async fn send_notification(
+		&self,
+		user: &UserId,
+		pusher: &Pusher,
+		url: &str,
+		notification: Notification,
+	) -> Result {
+		let response = self
+			.send_request(url, send_event_notification::v1::Request::new(notification))
+			.await?;
+
+		let pushkey = pusher.ids.pushkey.as_str();
+		if response.rejected.iter().any(|rejected| rejected == pushkey) {
+			warn!(%user, %pushkey, "Push gateway {url} rejected pushkey, removing pusher");
+			self.delete_pusher(user, pushkey).await;
+		}
+
+		Ok(())
+	}
+}
+
+fn badge_count_disabled(data: &BTreeMap<String, JsonValue>) -> bool {
+	data.contains_key("org.matrix.msc4076.disable_badge_count")
+		|| data.contains_key("disable_badge_count")
 }
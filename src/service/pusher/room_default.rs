@@ -0,0 +1,115 @@
+use conduwuit::{debug_warn, implement};
+use database::Deserialized;
+use ruma::{
+	events::StateEventType,
+	push::{Action, NewConditionalPushRule, NewPushRule, PushCondition, RuleKind, Ruleset},
+	RoomId, UserId,
+};
+use serde::{Deserialize, Serialize};
+
+/// State event through which room moderators suggest how members should be
+/// notified of the room's messages.
+pub const NOTIFICATION_DEFAULT_EVENT_TYPE: &str = "im.conduwuit.room.notification_default";
+
+/// Global account data event through which users opt in to or out of room
+/// notification defaults.
+pub const NOTIFICATION_DEFAULTS_ACCOUNT_DATA_TYPE: &str = "im.conduwuit.notification_defaults";
+
+/// Prefix of the ID of the underride rule applying a room's default.
+const RULE_ID_PREFIX: &str = "im.conduwuit.notification_default.";
+
+/// Content of the `im.conduwuit.room.notification_default` state event.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NotificationDefaultEventContent {
+	pub level: NotificationLevel,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationLevel {
+	/// Notify for every message.
+	All,
+
+	/// Only notify for mentions and keywords, which are matched by rules
+	/// taking precedence over underride rules.
+	MentionsAndKeywords,
+}
+
+/// Content of the `im.conduwuit.notification_defaults` global account data
+/// event.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NotificationDefaultsEventContent {
+	/// Whether room notification defaults apply to the user; the
+	/// `room_notification_defaults_opt_in` config option decides if unset.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub enabled: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct NotificationDefaultsEvent {
+	content: NotificationDefaultsEventContent,
+}
+
+/// Adds the room's suggested notification level to a local user's ruleset
+/// as an underride rule, unless the user opted out or has their own rule for
+/// the room.
+#[implement(super::Service)]
+pub async fn apply_room_default(&self, user: &UserId, room_id: &RoomId, ruleset: &mut Ruleset) {
+	if has_room_rule(ruleset, room_id) || !self.room_defaults_enabled(user).await {
+		return;
+	}
+
+	let Ok(content) = self
+		.services
+		.state_accessor
+		.room_state_get_content::<NotificationDefaultEventContent>(
+			room_id,
+			&StateEventType::from(NOTIFICATION_DEFAULT_EVENT_TYPE),
+			"",
+		)
+		.await
+	else {
+		return;
+	};
+
+	let actions = match content.level {
+		| NotificationLevel::All => vec![Action::Notify],
+		| NotificationLevel::MentionsAndKeywords => Vec::new(),
+	};
+
+	let conditions = vec![PushCondition::EventMatch {
+		key: "room_id".to_owned(),
+		pattern: room_id.to_string(),
+	}];
+
+	let rule_id = format!("{RULE_ID_PREFIX}{room_id}");
+	let rule = NewConditionalPushRule::new(rule_id, conditions, actions);
+	if let Err(e) = ruleset.insert(NewPushRule::Underride(rule), None, None) {
+		debug_warn!("Failed to apply the notification default of {room_id} for {user}: {e}");
+	}
+}
+
+/// Whether room notification defaults apply to the user.
+#[implement(super::Service)]
+pub async fn room_defaults_enabled(&self, user: &UserId) -> bool {
+	self.services
+		.account_data
+		.get_raw(None, user, NOTIFICATION_DEFAULTS_ACCOUNT_DATA_TYPE)
+		.await
+		.deserialized::<NotificationDefaultsEvent>()
+		.ok()
+		.and_then(|event| event.content.enabled)
+		.unwrap_or(
+			self.services
+				.server
+				.config
+				.room_notification_defaults_opt_in,
+		)
+}
+
+/// Whether the user customized notifications for the room, through a room
+/// rule or an override rule (how clients mute rooms) for it.
+fn has_room_rule(ruleset: &Ruleset, room_id: &RoomId) -> bool {
+	ruleset.get(RuleKind::Room, room_id.as_str()).is_some()
+		|| ruleset.get(RuleKind::Override, room_id.as_str()).is_some()
+}
@@ -0,0 +1,181 @@
+use conduwuit::{debug_warn, implement, warn, Err, Result};
+use futures::StreamExt;
+use ruma::{
+	events::{
+		push_rules::{PushRulesEvent, PushRulesEventContent},
+		room::power_levels::RoomPowerLevelsEventContent,
+		AnySyncTimelineEvent, GlobalAccountDataEventType,
+	},
+	push::{
+		Action, PredefinedContentRuleId, PredefinedOverrideRuleId, PushConditionPowerLevelsCtx,
+		PushConditionRoomCtx, RuleKind, Ruleset,
+	},
+	serde::Raw,
+	uint, OwnedUserId, RoomId, UserId,
+};
+
+/// Gets the user's push rules for evaluating events. Users without push rules,
+/// or whose stored rules are invalid, get the server default.
+#[implement(super::Service)]
+pub async fn get_ruleset(&self, user_id: &UserId) -> Ruleset {
+	match self.get_stored_ruleset(user_id).await {
+		| Ok(ruleset) => ruleset,
+		| Err(e) => {
+			// Don't overwrite what's stored; the user may still fix it.
+			debug_warn!(%user_id, "Invalid push rules, using the server default: {e}");
+			Ruleset::server_default(user_id)
+		},
+	}
+}
+
+/// Gets the user's push rules as stored, or the server default if they have
+/// none. Fails if the stored rules are invalid.
+#[implement(super::Service)]
+pub async fn get_stored_ruleset(&self, user_id: &UserId) -> Result<Ruleset> {
+	let event: Result<PushRulesEvent> = self
+		.services
+		.account_data
+		.get_global(user_id, GlobalAccountDataEventType::PushRules)
+		.await;
+
+	match event {
+		| Ok(event) => Ok(event.content.global),
+		| Err(e) if e.is_not_found() => Ok(Ruleset::server_default(user_id)),
+		| Err(e) => Err!(Database("Invalid push rules account data event in database: {e}")),
+	}
+}
+
+/// Stores the user's push rules.
+#[implement(super::Service)]
+pub async fn set_ruleset(&self, user_id: &UserId, ruleset: &Ruleset) -> Result {
+	let event = PushRulesEvent {
+		content: PushRulesEventContent { global: ruleset.clone() },
+	};
+
+	self.services
+		.account_data
+		.update(
+			None,
+			user_id,
+			GlobalAccountDataEventType::PushRules.to_string().into(),
+			&serde_json::to_value(event)?,
+		)
+		.await
+}
+
+/// Brings the copy of the server default rules held by each local user's push
+/// rules up to date. Run once by a startup migration; users whose rules are
+/// invalid are skipped.
+#[implement(super::Service)]
+pub async fn upgrade_rulesets(&self) -> Result {
+	let users: Vec<OwnedUserId> = self
+		.services
+		.users
+		.list_local_users()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for user_id in &users {
+		let event: Result<PushRulesEvent> = self
+			.services
+			.account_data
+			.get_global(user_id, GlobalAccountDataEventType::PushRules)
+			.await;
+
+		let mut ruleset = match event {
+			| Ok(event) => event.content.global,
+			| Err(e) if e.is_not_found() => continue,
+			| Err(e) => {
+				warn!(%user_id, "Not upgrading invalid push rules: {e}");
+				continue;
+			},
+		};
+
+		upgrade_ruleset(user_id, &mut ruleset);
+		self.set_ruleset(user_id, &ruleset).await?;
+	}
+
+	Ok(())
+}
+
+/// Evaluates the ruleset against the event, returning the actions of the
+/// first matching rule. Conditions are evaluated as per the spec, including
+/// `event_match`, `event_property_is`, `event_property_contains`,
+/// `room_member_count` and `sender_notification_permission`; the intentional
+/// mentions (MSC3952) rules match `m.mentions` of the event's content.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self, user, ruleset, pdu), level = "debug")]
+pub async fn get_actions<'a>(
+	&self,
+	user: &UserId,
+	ruleset: &'a Ruleset,
+	power_levels: &RoomPowerLevelsEventContent,
+	pdu: &Raw<AnySyncTimelineEvent>,
+	room_id: &RoomId,
+) -> &'a [Action] {
+	let power_levels = PushConditionPowerLevelsCtx {
+		users: power_levels.users.clone(),
+		users_default: power_levels.users_default,
+		notifications: power_levels.notifications.clone(),
+	};
+
+	let room_joined_count = self
+		.services
+		.state_cache
+		.room_joined_count(room_id)
+		.await
+		.unwrap_or(1)
+		.try_into()
+		.unwrap_or_else(|_| uint!(0));
+
+	// The display name matched is the one the user has in the room, which
+	// may differ from their global one.
+	let user_display_name = match self
+		.services
+		.state_accessor
+		.get_member(room_id, user)
+		.await
+		.ok()
+		.and_then(|member| member.displayname)
+	{
+		| Some(displayname) => displayname,
+		| None => self
+			.services
+			.users
+			.displayname(user)
+			.await
+			.unwrap_or_else(|_| user.localpart().to_owned()),
+	};
+
+	let ctx = PushConditionRoomCtx {
+		room_id: room_id.to_owned(),
+		member_count: room_joined_count,
+		user_id: user.to_owned(),
+		user_display_name,
+		power_levels: Some(power_levels),
+	};
+
+	ruleset.get_actions(pdu, &ctx)
+}
+
+/// Brings the copy of the server default rules in the ruleset up to date,
+/// keeping whether the user enabled them and their actions. The legacy
+/// mention rules are dropped in favour of the intentional mentions rules
+/// (MSC4210).
+fn upgrade_ruleset(user_id: &UserId, ruleset: &mut Ruleset) {
+	#[allow(deprecated)]
+	{
+		ruleset
+			.remove(RuleKind::Override, PredefinedOverrideRuleId::ContainsDisplayName)
+			.ok();
+		ruleset
+			.remove(RuleKind::Override, PredefinedOverrideRuleId::RoomNotif)
+			.ok();
+		ruleset
+			.remove(RuleKind::Content, PredefinedContentRuleId::ContainsUserName)
+			.ok();
+	}
+
+	ruleset.update_with_server_default(Ruleset::server_default(user_id));
+}
@@ -0,0 +1,389 @@
+mod tests;
+
+use std::{
+	cmp::Reverse,
+	collections::{BinaryHeap, HashMap},
+	fmt::{self, Write},
+	net::IpAddr,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use conduwuit::{implement, utils::bytes::pretty, Error, Result, Server};
+use ruma::{
+	api::client::error::{ErrorKind, RetryAfter},
+	OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, ServerName, UserId,
+};
+
+use crate::{appservice, globals, users, Dep};
+
+pub struct Service {
+	buckets: Mutex<Buckets>,
+	origins: Mutex<HashMap<OwnedServerName, OriginStats>>,
+	services: Services,
+}
+
+struct Services {
+	server: Arc<Server>,
+	appservice: Dep<appservice::Service>,
+	globals: Dep<globals::Service>,
+	users: Dep<users::Service>,
+}
+
+/// The buckets in use, with when each is expected to have refilled
+/// completely. A full bucket is the same as none, so buckets are dropped once
+/// they're seen full; only the buckets due are looked at on each use.
+#[derive(Default)]
+struct Buckets {
+	map: HashMap<BucketKey, Bucket>,
+	expiry: BinaryHeap<Reverse<(Instant, BucketKey)>>,
+}
+
+/// Classes of client actions limited separately for each local user.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Action {
+	Message,
+	Invite,
+	Join,
+
+	/// Requesting a login token for another device.
+	LoginToken,
+
+	/// Paginating backwards in a way which may fetch history from other
+	/// servers.
+	Backfill,
+}
+
+/// What a token bucket is kept for.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum BucketKey {
+	/// A local user performing a class of actions.
+	User(OwnedUserId, Action),
+
+	/// A remote server sending PDUs into a room.
+	Federation(OwnedServerName, OwnedRoomId),
+
+	/// A remote server sending transactions.
+	Transaction(OwnedServerName),
+
+	/// Validation emails sent to an address.
+	Email(String),
+
+	/// Validation emails requested from a client address.
+	EmailClient(IpAddr),
+}
+
+/// Tokens left in a bucket as of its last use. Buckets refill continuously at
+/// the configured rate up to the configured burst.
+#[derive(Clone, Copy, Debug)]
+pub struct Bucket {
+	pub tokens: f64,
+	updated: Instant,
+}
+
+/// Transactions received from a remote server since startup.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OriginStats {
+	/// Transactions being processed right now.
+	pub active: usize,
+	pub accepted: u64,
+	pub rate_limited: u64,
+	pub concurrency_limited: u64,
+}
+
+/// Holds one of a remote server's concurrent transaction slots until dropped.
+pub struct TransactionGuard<'a> {
+	service: &'a Service,
+	origin: OwnedServerName,
+}
+
+/// Suggested wait for a remote server with too many transactions in flight.
+const CONCURRENCY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			buckets: Mutex::new(Buckets::default()),
+			origins: Mutex::new(HashMap::new()),
+			services: Services {
+				server: args.server.clone(),
+				appservice: args.depend::<appservice::Service>("appservice"),
+				globals: args.depend::<globals::Service>("globals"),
+				users: args.depend::<users::Service>("users"),
+			},
+		}))
+	}
+
+	fn memory_usage(&self, out: &mut dyn Write) -> Result {
+		let count = self.buckets.lock()?.map.len();
+		let bytes = count.saturating_mul(
+			size_of::<(BucketKey, Bucket)>().saturating_add(size_of::<(Instant, BucketKey)>()),
+		);
+		writeln!(out, "rate_limit_buckets: {count} ({})", pretty(bytes))?;
+
+		let origins = self.origins.lock()?.len();
+		writeln!(out, "rate_limit_origins: {origins}")?;
+
+		Ok(())
+	}
+
+	fn clear_cache(&self) {
+		let mut buckets = self.buckets.lock().expect("locked");
+		buckets.map.clear();
+		buckets.expiry.clear();
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Takes a token from the user's bucket for the action, failing with
+/// M_LIMIT_EXCEEDED when it is empty. Admins, the server user and appservice
+/// users not opted in to rate limiting are exempt.
+#[implement(Service)]
+pub async fn check_user(&self, user_id: &UserId, action: Action) -> Result {
+	if *user_id == *self.services.globals.server_user
+		|| self.services.users.is_admin(user_id).await
+		|| self.services.appservice.is_rate_limit_exempt(user_id).await
+	{
+		return Ok(());
+	}
+
+	self.take(BucketKey::User(user_id.to_owned(), action))
+}
+
+/// Takes a token from the bucket of a remote server sending PDUs into a room,
+/// failing with M_LIMIT_EXCEEDED when it is empty.
+#[implement(Service)]
+pub fn check_federation(&self, origin: &ServerName, room_id: &RoomId) -> Result {
+	self.take(BucketKey::Federation(origin.to_owned(), room_id.to_owned()))
+}
+
+/// Admits a transaction from a remote server, failing with M_LIMIT_EXCEEDED
+/// when it sends transactions too quickly or has too many in flight. The slot
+/// is released when the guard is dropped.
+#[implement(Service)]
+pub fn start_transaction(&self, origin: &ServerName) -> Result<TransactionGuard<'_>> {
+	let max = self
+		.services
+		.server
+		.config
+		.max_concurrent_federation_txns_per_origin;
+	let mut origins = self.origins.lock().expect("locked");
+	let stats = origins.entry(origin.to_owned()).or_default();
+	if max > 0 && stats.active >= max {
+		stats.concurrency_limited = stats.concurrency_limited.saturating_add(1);
+		return Err(limit_exceeded(CONCURRENCY_RETRY_AFTER));
+	}
+
+	if let Err(e) = self.take(BucketKey::Transaction(origin.to_owned())) {
+		stats.rate_limited = stats.rate_limited.saturating_add(1);
+		return Err(e);
+	}
+
+	stats.active = stats.active.saturating_add(1);
+	stats.accepted = stats.accepted.saturating_add(1);
+
+	Ok(TransactionGuard { service: self, origin: origin.to_owned() })
+}
+
+/// Takes a token from the buckets of both an email address and the client
+/// asking for a validation email to be sent to it, failing with
+/// M_LIMIT_EXCEEDED when either is empty.
+#[implement(Service)]
+pub fn check_email(&self, email: &str, client: IpAddr) -> Result {
+	self.take(BucketKey::Email(email.to_owned()))?;
+	self.take(BucketKey::EmailClient(client))
+}
+
+/// Transaction counters of every remote server which has sent us one.
+#[implement(Service)]
+pub fn origin_stats(&self) -> Vec<(OwnedServerName, OriginStats)> {
+	self.origins
+		.lock()
+		.expect("locked")
+		.iter()
+		.map(|(origin, stats)| (origin.clone(), *stats))
+		.collect()
+}
+
+/// Current state of every bucket, refilled as of now.
+#[implement(Service)]
+pub fn buckets(&self) -> Vec<(BucketKey, Bucket)> {
+	let now = Instant::now();
+	self.buckets
+		.lock()
+		.expect("locked")
+		.map
+		.iter()
+		.map(|(key, bucket)| {
+			let (rate, burst) = self.limits(key);
+			let mut bucket = *bucket;
+			bucket.refill(now, rate, burst);
+			(key.clone(), bucket)
+		})
+		.collect()
+}
+
+/// Refills all buckets matching the predicate, returning how many there were.
+#[implement(Service)]
+pub fn reset<F>(&self, mut matches: F) -> usize
+where
+	F: FnMut(&BucketKey) -> bool,
+{
+	// Their entries in the expiry queue are dropped when they come due.
+	let mut buckets = self.buckets.lock().expect("locked");
+	let before = buckets.map.len();
+	buckets.map.retain(|key, _| !matches(key));
+
+	before.saturating_sub(buckets.map.len())
+}
+
+#[implement(Service)]
+fn take(&self, key: BucketKey) -> Result {
+	let (rate, burst) = self.limits(&key);
+	if rate <= 0.0 {
+		return Ok(());
+	}
+
+	let now = Instant::now();
+	let mut buckets = self.buckets.lock().expect("locked");
+	self.expire(&mut buckets, now);
+
+	let Buckets { map, expiry } = &mut *buckets;
+	let bucket = map.entry(key).or_insert_with_key(|key| {
+		expiry.push(Reverse((now, key.clone())));
+		Bucket { tokens: f64::from(burst), updated: now }
+	});
+
+	bucket.take(now, rate, burst).map_err(limit_exceeded)
+}
+
+/// Drops the buckets due to have refilled completely. Those used since they
+/// were queued are queued again for when they will be full.
+#[implement(Service)]
+fn expire(&self, buckets: &mut Buckets, now: Instant) {
+	let Buckets { map, expiry } = buckets;
+	while let Some(Reverse((due, _))) = expiry.peek() {
+		if *due > now {
+			break;
+		}
+
+		let Some(Reverse((_, key))) = expiry.pop() else {
+			break;
+		};
+
+		let Some(bucket) = map.get_mut(&key) else {
+			continue;
+		};
+
+		let (rate, burst) = self.limits(&key);
+		bucket.refill(now, rate, burst);
+		// Buckets a moment from full count as full, so this always ends.
+		match bucket
+			.full_in(rate, burst)
+			.and_then(|full_in| now.checked_add(full_in))
+			.filter(|due| *due > now)
+		{
+			| Some(due) => expiry.push(Reverse((due, key))),
+			| None => {
+				map.remove(&key);
+			},
+		}
+	}
+}
+
+#[implement(Service)]
+fn limits(&self, key: &BucketKey) -> (f64, u32) {
+	let config = &self.services.server.config;
+	match key {
+		| BucketKey::User(_, Action::Message) =>
+			(config.rate_limit_message_per_second, config.rate_limit_message_burst),
+		| BucketKey::User(_, Action::Invite) =>
+			(config.rate_limit_invite_per_second, config.rate_limit_invite_burst),
+		| BucketKey::User(_, Action::Join) =>
+			(config.rate_limit_join_per_second, config.rate_limit_join_burst),
+		| BucketKey::User(_, Action::LoginToken) =>
+			(config.rate_limit_login_token_per_second, config.rate_limit_login_token_burst),
+		| BucketKey::User(_, Action::Backfill) =>
+			(config.rate_limit_backfill_per_second, config.rate_limit_backfill_burst),
+		| BucketKey::Federation(..) => (
+			config.rate_limit_federation_pdu_per_second,
+			config.rate_limit_federation_pdu_burst,
+		),
+		| BucketKey::Transaction(_) => (
+			config.rate_limit_federation_txn_per_second,
+			config.rate_limit_federation_txn_burst,
+		),
+		| BucketKey::Email(_) | BucketKey::EmailClient(_) =>
+			(config.rate_limit_email_per_second, config.rate_limit_email_burst),
+	}
+}
+
+fn limit_exceeded(retry_after: Duration) -> Error {
+	Error::Request(
+		ErrorKind::LimitExceeded {
+			retry_after: Some(RetryAfter::Delay(retry_after)),
+		},
+		"Too many requests, slow down.".into(),
+		http::StatusCode::TOO_MANY_REQUESTS,
+	)
+}
+
+impl Bucket {
+	fn refill(&mut self, now: Instant, rate: f64, burst: u32) {
+		let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+		self.tokens = elapsed.mul_add(rate, self.tokens).min(f64::from(burst));
+		self.updated = now;
+	}
+
+	/// Takes a token after refilling, or returns how long until one is
+	/// available.
+	fn take(&mut self, now: Instant, rate: f64, burst: u32) -> Result<(), Duration> {
+		self.refill(now, rate, burst);
+		if self.tokens >= 1.0 {
+			self.tokens -= 1.0;
+			return Ok(());
+		}
+
+		Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+	}
+
+	/// How long until the bucket is full, or `None` if it is already.
+	fn full_in(&self, rate: f64, burst: u32) -> Option<Duration> {
+		let missing = f64::from(burst) - self.tokens;
+		(missing > 0.0 && rate > 0.0).then(|| Duration::from_secs_f64(missing / rate))
+	}
+}
+
+impl Drop for TransactionGuard<'_> {
+	fn drop(&mut self) {
+		let mut origins = self.service.origins.lock().expect("locked");
+		if let Some(stats) = origins.get_mut(&self.origin) {
+			stats.active = stats.active.saturating_sub(1);
+		}
+	}
+}
+
+impl fmt::Display for Action {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			| Self::Message => write!(f, "message"),
+			| Self::Invite => write!(f, "invite"),
+			| Self::Join => write!(f, "join"),
+			| Self::Backfill => write!(f, "backfill"),
+			| Self::LoginToken => write!(f, "login token"),
+		}
+	}
+}
+
+impl fmt::Display for BucketKey {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			| Self::User(user_id, action) => write!(f, "{user_id} {action}"),
+			| Self::Federation(origin, room_id) => write!(f, "{origin} in {room_id}"),
+			| Self::Transaction(origin) => write!(f, "{origin} transactions"),
+			| Self::Email(email) => write!(f, "emails to {email}"),
+			| Self::EmailClient(client) => write!(f, "emails requested from {client}"),
+		}
+	}
+}
@@ -0,0 +1,62 @@
+#![cfg(test)]
+
+use std::time::{Duration, Instant};
+
+use super::Bucket;
+
+fn full(now: Instant, burst: u32) -> Bucket { Bucket { tokens: f64::from(burst), updated: now } }
+
+#[test]
+fn burst_then_limited() {
+	let now = Instant::now();
+	let mut bucket = full(now, 3);
+
+	assert!(bucket.take(now, 1.0, 3).is_ok());
+	assert!(bucket.take(now, 1.0, 3).is_ok());
+	assert!(bucket.take(now, 1.0, 3).is_ok());
+
+	let retry_after = bucket.take(now, 1.0, 3).unwrap_err();
+	assert_eq!(retry_after, Duration::from_secs(1));
+}
+
+#[test]
+fn retry_after_counts_partial_tokens() {
+	let now = Instant::now();
+	let mut bucket = Bucket { tokens: 0.5, updated: now };
+
+	let retry_after = bucket.take(now, 0.25, 5).unwrap_err();
+	assert_eq!(retry_after, Duration::from_secs(2));
+}
+
+#[test]
+fn refills_over_time() {
+	let now = Instant::now();
+	let mut bucket = full(now, 2);
+	assert!(bucket.take(now, 2.0, 2).is_ok());
+	assert!(bucket.take(now, 2.0, 2).is_ok());
+	assert!(bucket.take(now, 2.0, 2).is_err());
+
+	let later = now + Duration::from_millis(500);
+	assert!(bucket.take(later, 2.0, 2).is_ok());
+	assert!(bucket.take(later, 2.0, 2).is_err());
+}
+
+#[test]
+fn refill_capped_at_burst() {
+	let now = Instant::now();
+	let mut bucket = Bucket { tokens: 0.0, updated: now };
+
+	bucket.refill(now + Duration::from_secs(60), 1.0, 4);
+	assert!((bucket.tokens - 4.0).abs() < f64::EPSILON);
+}
+
+#[test]
+fn full_in() {
+	let now = Instant::now();
+	let mut bucket = full(now, 4);
+	assert_eq!(bucket.full_in(2.0, 4), None);
+
+	bucket.tokens = 1.0;
+	assert_eq!(bucket.full_in(2.0, 4), Some(Duration::from_millis(1500)));
+	assert_eq!(bucket.full_in(0.0, 4), None);
+}
@@ -0,0 +1,302 @@
+use std::{sync::Arc, time::Duration};
+
+use conduwuit::{
+	err, implement,
+	utils::{self, millis_since_unix_epoch, stream::TryIgnore, ReadyExt},
+	Err, Result, Server,
+};
+use database::{Deserialized, Ignore, Interfix, Json, Map};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{globals, uiaa, Dep};
+
+pub struct Service {
+	/// Serializes changes to the use counters so tokens can't be overused.
+	lock: Mutex<()>,
+	services: Services,
+	db: Data,
+}
+
+struct Services {
+	server: Arc<Server>,
+	globals: Dep<globals::Service>,
+	uiaa: Dep<uiaa::Service>,
+}
+
+struct Data {
+	registrationtoken_info: Arc<Map>,
+	registrationtokensession_reservedts: Arc<Map>,
+}
+
+/// A registration token managed from the admin room (MSC3231).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenInfo {
+	/// Registrations allowed in total; unlimited if none.
+	pub uses_allowed: Option<u64>,
+
+	/// Registrations which passed the token stage but haven't finished yet.
+	/// Counted from the reservations when the token is read.
+	#[serde(default, skip_serializing)]
+	pub pending: u64,
+
+	/// Registrations completed with this token.
+	pub completed: u64,
+
+	/// Milliseconds since the unix epoch after which the token is invalid.
+	pub expiry_time: Option<u64>,
+}
+
+/// Length of generated tokens.
+const TOKEN_LENGTH: usize = 16;
+
+/// Longest token accepted when one is chosen by an admin.
+const MAX_TOKEN_LENGTH: usize = 64;
+
+/// How long a registration which passed the token stage holds on to a use of
+/// the token; abandoned registrations give it back after this.
+const RESERVATION_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			lock: Mutex::new(()),
+			services: Services {
+				server: args.server.clone(),
+				globals: args.depend::<globals::Service>("globals"),
+				uiaa: args.depend::<uiaa::Service>("uiaa"),
+			},
+			db: Data {
+				registrationtoken_info: args.db["registrationtoken_info"].clone(),
+				registrationtokensession_reservedts: args.db
+					["registrationtokensession_reservedts"]
+					.clone(),
+			},
+		}))
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Whether registering requires a token: one is configured, a usable one has
+/// been created from the admin room, or `registration_requires_token` is set.
+#[implement(Service)]
+pub async fn required(&self) -> bool {
+	self.services.server.config.registration_requires_token
+		|| self.services.globals.registration_token.is_some()
+		|| self
+			.services
+			.server
+			.config
+			.registration_token_file
+			.is_some()
+		|| self
+			.tokens()
+			.ready_any(|(_, info)| info.is_usable(millis_since_unix_epoch()))
+			.await
+}
+
+/// Whether the token would currently be accepted, without using it.
+#[implement(Service)]
+pub async fn is_valid(&self, token: &str) -> bool {
+	self.is_configured(token).await
+		|| self
+			.get(token)
+			.await
+			.is_ok_and(|info| info.is_usable(millis_since_unix_epoch()))
+}
+
+/// Checks a token for the registration of a UIAA session. A token from the
+/// store holds one use for the session until `complete` or `release` is
+/// called for it, or for `RESERVATION_LIFETIME` if neither is.
+#[implement(Service)]
+pub async fn reserve(&self, token: &str, session: &str) -> Result<bool> {
+	if self.is_configured(token).await {
+		return Ok(true);
+	}
+
+	let _lock = self.lock.lock().await;
+	let Ok(mut info) = self.get(token).await else {
+		return Ok(false);
+	};
+
+	let key = (token, session);
+	if self
+		.db
+		.registrationtokensession_reservedts
+		.qry(&key)
+		.await
+		.is_ok()
+	{
+		info.pending = info.pending.saturating_sub(1);
+	}
+
+	if !info.is_usable(millis_since_unix_epoch()) {
+		return Ok(false);
+	}
+
+	self.db
+		.registrationtokensession_reservedts
+		.put(key, millis_since_unix_epoch());
+
+	Ok(true)
+}
+
+/// Counts a reserved token as used by a finished registration.
+#[implement(Service)]
+pub async fn complete(&self, token: &str, session: &str) {
+	let _lock = self.lock.lock().await;
+	self.db
+		.registrationtokensession_reservedts
+		.del((token, session));
+
+	if let Ok(mut info) = self.get(token).await {
+		info.completed = info.completed.saturating_add(1);
+		self.save(token, &info);
+	}
+}
+
+/// Returns a reserved token after a registration failed.
+#[implement(Service)]
+pub async fn release(&self, token: &str, session: &str) {
+	let _lock = self.lock.lock().await;
+	self.db
+		.registrationtokensession_reservedts
+		.del((token, session));
+}
+
+/// Counts the unexpired reservations of a token, removing the expired ones.
+#[implement(Service)]
+async fn count_pending(&self, token: &str) -> u64 {
+	let reservations = &self.db.registrationtokensession_reservedts;
+	let lifetime = u64::try_from(RESERVATION_LIFETIME.as_millis()).unwrap_or(u64::MAX);
+	let expired_before = millis_since_unix_epoch().saturating_sub(lifetime);
+
+	let mut pending: u64 = 0;
+	reservations
+		.stream_prefix(&(token, Interfix))
+		.ignore_err()
+		.ready_for_each(|((_, session), reserved_at): ((Ignore, &str), u64)| {
+			if reserved_at < expired_before {
+				reservations.del((token, session));
+			} else {
+				pending = pending.saturating_add(1);
+			}
+		})
+		.await;
+
+	pending
+}
+
+/// Adds a token to the store, generating a random one if none is given.
+#[implement(Service)]
+pub async fn create(
+	&self,
+	token: Option<String>,
+	uses_allowed: Option<u64>,
+	expiry_time: Option<u64>,
+) -> Result<(String, TokenInfo)> {
+	let token = token.unwrap_or_else(|| utils::random_string(TOKEN_LENGTH));
+	if token.is_empty()
+		|| token.len() > MAX_TOKEN_LENGTH
+		|| !token
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '~' | '-'))
+	{
+		return Err!(Request(InvalidParam(
+			"Tokens must be 1 to {MAX_TOKEN_LENGTH} characters from A-Z, a-z, 0-9, '.', '_', \
+			 '~' and '-'."
+		)));
+	}
+
+	let _lock = self.lock.lock().await;
+	if self.get(&token).await.is_ok() {
+		return Err!(Request(InvalidParam("Token {token} already exists.")));
+	}
+
+	let info = TokenInfo {
+		uses_allowed,
+		pending: 0,
+		completed: 0,
+		expiry_time,
+	};
+
+	self.save(&token, &info);
+	Ok((token, info))
+}
+
+/// Removes a token from the store. Registrations which already passed the
+/// token stage are unaffected.
+#[implement(Service)]
+pub async fn revoke(&self, token: &str) -> Result {
+	let _lock = self.lock.lock().await;
+	if self.get(token).await.is_err() {
+		return Err!(Request(NotFound("Token {token} not found.")));
+	}
+
+	self.db.registrationtoken_info.remove(token);
+	self.db
+		.registrationtokensession_reservedts
+		.keys_prefix_raw(&(token, Interfix))
+		.ignore_err()
+		.ready_for_each(|key| self.db.registrationtokensession_reservedts.remove(key))
+		.await;
+
+	Ok(())
+}
+
+/// All tokens in the store.
+#[implement(Service)]
+pub fn tokens(&self) -> impl Stream<Item = (String, TokenInfo)> + Send + '_ {
+	self.db
+		.registrationtoken_info
+		.stream()
+		.ignore_err()
+		.map(|(token, info): (&str, TokenInfo)| (token.to_owned(), info))
+		.then(|(token, mut info)| async move {
+			info.pending = self.count_pending(&token).await;
+			(token, info)
+		})
+}
+
+#[implement(Service)]
+pub async fn get(&self, token: &str) -> Result<TokenInfo> {
+	let mut info: TokenInfo = self
+		.db
+		.registrationtoken_info
+		.get(token)
+		.await
+		.deserialized()
+		.map_err(|_| err!(Request(NotFound("Token {token} not found."))))?;
+
+	info.pending = self.count_pending(token).await;
+
+	Ok(info)
+}
+
+#[implement(Service)]
+async fn is_configured(&self, token: &str) -> bool {
+	self.services
+		.uiaa
+		.read_tokens()
+		.await
+		.is_ok_and(|tokens| tokens.contains(token))
+}
+
+#[implement(Service)]
+fn save(&self, token: &str, info: &TokenInfo) {
+	self.db.registrationtoken_info.raw_put(token, Json(info));
+}
+
+impl TokenInfo {
+	/// Whether the token is unexpired and has uses left, counting pending
+	/// registrations as uses.
+	#[must_use]
+	pub fn is_usable(&self, now: u64) -> bool {
+		let used = self.pending.saturating_add(self.completed);
+
+		self.expiry_time.is_none_or(|expiry| now < expiry)
+			&& self.uses_allowed.is_none_or(|allowed| used < allowed)
+	}
+}
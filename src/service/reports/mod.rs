@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use conduwuit::{
+	err, implement,
+	utils::{millis_since_unix_epoch, stream::TryIgnore},
+	Err, Result,
+};
+use database::{Deserialized, Ignore, Json, Map};
+use futures::{Stream, StreamExt};
+use ruma::{OwnedEventId, OwnedRoomId, OwnedUserId};
+use serde::{Deserialize, Serialize};
+
+use crate::{globals, Dep};
+
+pub struct Service {
+	services: Services,
+	db: Data,
+}
+
+struct Services {
+	globals: Dep<globals::Service>,
+}
+
+struct Data {
+	reportid_report: Arc<Map>,
+}
+
+/// A room or event reported by a local user, kept until an admin resolves it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Report {
+	pub id: u64,
+	pub reporter: OwnedUserId,
+	pub room_id: OwnedRoomId,
+
+	/// The reported event; none when the whole room was reported.
+	pub event_id: Option<OwnedEventId>,
+
+	pub reason: Option<String>,
+
+	/// From -100 (most offensive) to 0, for event reports.
+	pub score: Option<i64>,
+
+	/// Milliseconds since the unix epoch.
+	pub created: u64,
+
+	/// When an admin resolved the report, in milliseconds since the unix
+	/// epoch.
+	#[serde(default)]
+	pub resolved: Option<u64>,
+}
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			services: Services {
+				globals: args.depend::<globals::Service>("globals"),
+			},
+			db: Data {
+				reportid_report: args.db["reportid_report"].clone(),
+			},
+		}))
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Stores a new report. Returns it with its id.
+#[implement(Service)]
+pub fn add(
+	&self,
+	reporter: OwnedUserId,
+	room_id: OwnedRoomId,
+	event_id: Option<OwnedEventId>,
+	reason: Option<String>,
+	score: Option<i64>,
+) -> Result<Report> {
+	let report = Report {
+		id: self.services.globals.next_count()?,
+		reporter,
+		room_id,
+		event_id,
+		reason,
+		score,
+		created: millis_since_unix_epoch(),
+		resolved: None,
+	};
+
+	self.save(&report);
+	Ok(report)
+}
+
+/// Marks a report as dealt with.
+#[implement(Service)]
+pub async fn resolve(&self, id: u64) -> Result<Report> {
+	let mut report = self.get_report(id).await?;
+	if report.resolved.is_some() {
+		return Err!(Request(InvalidParam("Report {id} has already been resolved.")));
+	}
+
+	report.resolved = Some(millis_since_unix_epoch());
+	self.save(&report);
+
+	Ok(report)
+}
+
+#[implement(Service)]
+pub async fn get_report(&self, id: u64) -> Result<Report> {
+	self.db
+		.reportid_report
+		.qry(&id)
+		.await
+		.deserialized()
+		.map_err(|_| err!(Request(NotFound("Report {id} not found."))))
+}
+
+/// All reports, oldest first.
+#[implement(Service)]
+pub fn reports(&self) -> impl Stream<Item = Report> + Send + '_ {
+	self.db
+		.reportid_report
+		.stream()
+		.ignore_err()
+		.map(|(_, report): (Ignore, Report)| report)
+}
+
+#[implement(Service)]
+fn save(&self, report: &Report) { self.db.reportid_report.put(report.id, Json(report)); }
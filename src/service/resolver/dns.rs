@@ -3,6 +3,7 @@
 use conduwuit::{err, Result, Server};
 use futures::FutureExt;
 use hickory_resolver::{lookup_ip::LookupIp, TokioAsyncResolver};
+use ipaddress::IPAddress;
 use reqwest::dns::{Addrs, Name, Resolve, Resolving};
 
 use super::cache::{Cache, CachedOverride};
@@ -19,6 +20,14 @@ pub(crate) struct Hooked {
 	server: Arc<Server>,
 }
 
+/// Resolves only to addresses outside of the denied ranges, so requests to
+/// user-supplied URLs can't reach internal services.
+pub(crate) struct Filtered {
+	resolver: Arc<TokioAsyncResolver>,
+	server: Arc<Server>,
+	denylist: Vec<IPAddress>,
+}
+
 type ResolvingResult = Result<Addrs, Box<dyn std::error::Error + Send + Sync>>;
 
 impl Resolver {
@@ -78,6 +87,14 @@ pub(super) fn build(server: &Arc<Server>, cache: Arc<Cache>) -> Result<Arc<Self>
 			server: server.clone(),
 		}))
 	}
+
+	pub(crate) fn filtered(&self, denylist: Vec<IPAddress>) -> Arc<Filtered> {
+		Arc::new(Filtered {
+			resolver: self.resolver.clone(),
+			server: self.server.clone(),
+			denylist,
+		})
+	}
 }
 
 impl Resolve for Resolver {
@@ -93,6 +110,13 @@ fn resolve(&self, name: Name) -> Resolving {
 	}
 }
 
+impl Resolve for Filtered {
+	fn resolve(&self, name: Name) -> Resolving {
+		filtered_resolve(self.server.clone(), self.resolver.clone(), self.denylist.clone(), name)
+			.boxed()
+	}
+}
+
 #[tracing::instrument(
 	level = "debug",
 	skip_all,
@@ -123,6 +147,32 @@ async fn hooked_resolve(
 	}
 }
 
+async fn filtered_resolve(
+	server: Arc<Server>,
+	resolver: Arc<TokioAsyncResolver>,
+	denylist: Vec<IPAddress>,
+	name: Name,
+) -> ResolvingResult {
+	use std::{io, io::ErrorKind::PermissionDenied};
+
+	let allowed: Vec<SocketAddr> = resolve_to_reqwest(server, resolver, name)
+		.await?
+		.filter(|addr| {
+			IPAddress::parse(addr.ip().to_string())
+				.is_ok_and(|ip| denylist.iter().all(|cidr| !cidr.includes(&ip)))
+		})
+		.collect();
+
+	if allowed.is_empty() {
+		return Err(Box::new(io::Error::new(
+			PermissionDenied,
+			"Name resolves only to forbidden addresses",
+		)));
+	}
+
+	Ok(Box::new(allowed.into_iter()))
+}
+
 async fn resolve_to_reqwest(
 	server: Arc<Server>,
 	resolver: Arc<TokioAsyncResolver>,
@@ -5,9 +5,8 @@
 
 use conduwuit::{err, utils, utils::math::usize_from_f64, Err, Result};
 use database::Map;
-use lru_cache::LruCache;
 
-use crate::rooms::short::ShortEventId;
+use crate::{rooms::short::ShortEventId, LruCache};
 
 pub(super) struct Data {
 	shorteventid_authchain: Arc<Map>,
@@ -19,7 +19,7 @@
 use ruma::{EventId, OwnedEventId, RoomId};
 
 use self::data::Data;
-use crate::{rooms, rooms::short::ShortEventId, Dep};
+use crate::{rooms, rooms::short::ShortEventId, Cache, Dep};
 
 pub struct Service {
 	services: Services,
@@ -44,6 +44,14 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		}))
 	}
 
+	fn caches(&self) -> Vec<Cache<'_>> {
+		vec![Cache {
+			name: "auth_chain_cache",
+			base_capacity: |config| config.auth_chain_cache_capacity,
+			cache: &self.db.auth_chain_cache,
+		}]
+	}
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
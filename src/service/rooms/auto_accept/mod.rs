@@ -0,0 +1,284 @@
+use std::{
+	future::Future,
+	pin::Pin,
+	sync::{Arc, RwLock as StdRwLock, Weak},
+	time::Duration,
+};
+
+use async_trait::async_trait;
+use conduwuit::{debug, debug_warn, implement, pdu::PduBuilder, Result, Server};
+use database::Deserialized;
+use loole::{Receiver, Sender};
+use ruma::{
+	events::room::member::{MembershipState, RoomMemberEventContent},
+	OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, UserId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::{account_data, globals, rooms, users, Dep};
+
+pub struct Service {
+	invite_channel: (Sender<PendingInvite>, Receiver<PendingInvite>),
+	pub joiner: StdRwLock<Option<Joiner>>,
+	services: Services,
+}
+
+struct Services {
+	server: Arc<Server>,
+	account_data: Dep<account_data::Service>,
+	globals: Dep<globals::Service>,
+	state: Dep<rooms::state::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	timeline: Dep<rooms::timeline::Service>,
+	users: Dep<users::Service>,
+	services: StdRwLock<Option<Weak<crate::Services>>>,
+}
+
+/// An invite of a local user which may be auto-accepted.
+#[derive(Clone, Debug)]
+pub struct PendingInvite {
+	pub user_id: OwnedUserId,
+	pub room_id: OwnedRoomId,
+	pub sender: OwnedUserId,
+
+	/// Servers to try joining through if we are not in the room.
+	pub servers: Vec<OwnedServerName>,
+}
+
+/// Prototype of the join callback. Joining rooms we are not in takes the
+/// federation join flow, which lives in the reloadable api module.
+pub type Joiner = fn(Arc<crate::Services>, PendingInvite) -> JoinerFuture;
+
+/// Return type of the joiner
+pub type JoinerFuture = Pin<Box<dyn Future<Output = Result> + Send>>;
+
+/// Content of the `im.conduwuit.auto_accept_invites` global account data
+/// event, through which users pick whose invites are accepted for them.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AutoAcceptInvitesEventContent {
+	/// Set to false to never auto-accept, even invites the server default
+	/// would accept.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub enabled: Option<bool>,
+
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub users: Vec<OwnedUserId>,
+
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub servers: Vec<OwnedServerName>,
+}
+
+#[derive(Deserialize)]
+struct AutoAcceptInvitesEvent {
+	content: AutoAcceptInvitesEventContent,
+}
+
+pub const ACCOUNT_DATA_TYPE: &str = "im.conduwuit.auto_accept_invites";
+
+/// Attempts at joining a room before giving up on an invite.
+const JOIN_ATTEMPTS: u32 = 3;
+
+/// Time for the inviting server to send the invite into the room before we
+/// try to join it, multiplied by the attempt number.
+const JOIN_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			invite_channel: loole::unbounded(),
+			joiner: StdRwLock::new(None),
+			services: Services {
+				server: args.server.clone(),
+				account_data: args.depend::<account_data::Service>("account_data"),
+				globals: args.depend::<globals::Service>("globals"),
+				state: args.depend::<rooms::state::Service>("rooms::state"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
+				users: args.depend::<users::Service>("users"),
+				services: None.into(),
+			},
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		let receiver = self.invite_channel.1.clone();
+		while let Ok(invite) = receiver.recv_async().await {
+			if self
+				.services
+				.users
+				.user_is_ignored(&invite.sender, &invite.user_id)
+				.await
+			{
+				self.reject(invite).await;
+			} else if self
+				.should_auto_accept(&invite.user_id, &invite.sender)
+				.await
+			{
+				self.accept(invite).await;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn interrupt(&self) {
+		let (sender, _) = &self.invite_channel;
+		if !sender.is_closed() {
+			sender.close();
+		}
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Called for every invite membership change; invites of local users are
+/// checked against their auto-accept settings in the background. Invites from
+/// users they ignore are rejected instead.
+#[implement(Service)]
+pub fn queue_invite(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+	sender: &UserId,
+	invite_via: Option<&[OwnedServerName]>,
+) {
+	if !self.services.globals.user_is_local(user_id) || user_id == sender {
+		return;
+	}
+
+	let mut servers = invite_via.map(<[_]>::to_vec).unwrap_or_default();
+	servers.push(sender.server_name().to_owned());
+	if let Some(server) = room_id.server_name() {
+		servers.push(server.to_owned());
+	}
+	servers.retain(|server| !self.services.globals.server_is_ours(server));
+	servers.sort_unstable();
+	servers.dedup();
+
+	let invite = PendingInvite {
+		user_id: user_id.to_owned(),
+		room_id: room_id.to_owned(),
+		sender: sender.to_owned(),
+		servers,
+	};
+
+	if let Err(e) = self.invite_channel.0.send(invite) {
+		debug_warn!("Failed to queue invite for auto-accept: {e}");
+	}
+}
+
+/// Whether an invite from `sender` to the local `user_id` should be accepted
+/// without asking. The user's account data is combined with the server
+/// defaults, and can opt out of them.
+#[implement(Service)]
+pub async fn should_auto_accept(&self, user_id: &UserId, sender: &UserId) -> bool {
+	let settings = self.settings(user_id).await;
+	if settings.enabled == Some(false) {
+		return false;
+	}
+
+	let config = &self.services.server.config;
+	let from_user = |users: &[OwnedUserId]| users.iter().any(|user| user == sender);
+	let from_server =
+		|servers: &[OwnedServerName]| servers.iter().any(|server| server == sender.server_name());
+
+	from_user(&settings.users)
+		|| from_server(&settings.servers)
+		|| from_user(&config.auto_accept_invites_from_users)
+		|| from_server(&config.auto_accept_invites_from_servers)
+}
+
+#[implement(Service)]
+pub async fn settings(&self, user_id: &UserId) -> AutoAcceptInvitesEventContent {
+	self.services
+		.account_data
+		.get_raw(None, user_id, ACCOUNT_DATA_TYPE)
+		.await
+		.deserialized::<AutoAcceptInvitesEvent>()
+		.map(|event| event.content)
+		.unwrap_or_default()
+}
+
+#[implement(Service)]
+async fn accept(&self, invite: PendingInvite) {
+	let Some(joiner) = *self.joiner.read().expect("locked for reading") else {
+		debug!("No joiner installed; not auto-accepting invite");
+		return;
+	};
+
+	let Some(services) = self
+		.services
+		.services
+		.read()
+		.expect("locked for reading")
+		.clone()
+		.and_then(|weak| weak.upgrade())
+	else {
+		return;
+	};
+
+	let PendingInvite { user_id, room_id, .. } = &invite;
+	for attempt in 0..JOIN_ATTEMPTS {
+		if !self.services.state_cache.is_invited(user_id, room_id).await {
+			debug!(%user_id, %room_id, "Invite is gone; not auto-accepting");
+			return;
+		}
+
+		let resident = self
+			.services
+			.state_cache
+			.server_in_room(self.services.globals.server_name(), room_id)
+			.await;
+
+		if !resident {
+			sleep(JOIN_RETRY_DELAY.saturating_mul(attempt.saturating_add(1))).await;
+		}
+
+		match joiner(services.clone(), invite.clone()).await {
+			| Ok(()) => {
+				debug!(%user_id, %room_id, "Auto-accepted invite");
+				return;
+			},
+			| Err(e) => debug_warn!(%user_id, %room_id, "Failed to auto-accept invite: {e}"),
+		}
+	}
+}
+
+/// Rejects an invite from a user the invited user ignores. Invites arrive as
+/// room events when we are resident in the room, so the rejection is a leave
+/// event we create ourselves; like any room event, it is sent to the other
+/// servers in the room.
+#[implement(Service)]
+async fn reject(&self, invite: PendingInvite) {
+	let PendingInvite { user_id, room_id, .. } = &invite;
+	let state_lock = self.services.state.mutex.lock(room_id).await;
+	let rejected = self
+		.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(
+				user_id.to_string(),
+				&RoomMemberEventContent::new(MembershipState::Leave),
+			),
+			user_id,
+			room_id,
+			&state_lock,
+		)
+		.await;
+
+	drop(state_lock);
+	match rejected {
+		| Ok(_) => debug!(%user_id, %room_id, "Rejected invite from ignored user"),
+		| Err(e) => debug_warn!(%user_id, %room_id, "Failed to reject invite: {e}"),
+	}
+}
+
+/// Sets the self-reference to crate::Services which is passed to the joiner.
+#[implement(Service)]
+pub(crate) fn set_services(&self, services: Option<&Arc<crate::Services>>) {
+	let receiver = &mut *self.services.services.write().expect("locked for writing");
+	let weak = services.map(Arc::downgrade);
+	*receiver = weak;
+}
@@ -59,6 +59,12 @@ pub(super) async fn fetch_and_handle_outliers<'a>(
 			continue;
 		}
 
+		// Don't fetch events again which we already rejected
+		if self.services.outlier.is_rejected(id).await {
+			debug!("Not fetching {id} which was previously rejected");
+			continue;
+		}
+
 		// c. Ask origin server over federation
 		// We also handle its auth chain here so we don't get a stack overflow in
 		// handle_outlier_pdu.
@@ -18,7 +18,7 @@
 	skip_all,
 	fields(%origin),
 )]
-pub(super) async fn fetch_state(
+pub(crate) async fn fetch_state(
 	&self,
 	origin: &ServerName,
 	create_event: &PduEvent,
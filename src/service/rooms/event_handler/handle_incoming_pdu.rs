@@ -3,7 +3,7 @@
 	time::Instant,
 };
 
-use conduwuit::{debug, debug::INFO_SPAN_LEVEL, err, implement, warn, Err, Result};
+use conduwuit::{debug, debug::INFO_SPAN_LEVEL, defer, err, implement, warn, Err, Result};
 use futures::{
 	future::{try_join5, OptionFuture},
 	FutureExt,
@@ -54,6 +54,15 @@ pub async fn handle_incoming_pdu<'a>(
 	value: BTreeMap<String, CanonicalJsonValue>,
 	is_timeline_event: bool,
 ) -> Result<Option<RawPduId>> {
+	let started = Instant::now();
+	defer! {{
+		self.services
+			.server
+			.metrics
+			.incoming_pdu_durations
+			.observe(started.elapsed());
+	}};
+
 	// 1. Skip the PDU if we already have it as a timeline event
 	if let Ok(pdu_id) = self.services.timeline.get_pdu_id(event_id).await {
 		return Ok(Some(pdu_id));
@@ -112,6 +121,15 @@ pub async fn handle_incoming_pdu<'a>(
 		return Ok(None);
 	}
 
+	// The current state of a room we only have partial state for is incomplete, so
+	// the event is handled once the full state has been fetched
+	if self.services.partial_state.is_partial_state(room_id).await {
+		self.services
+			.partial_state
+			.queue_event(origin, room_id, event_id);
+		return Ok(None);
+	}
+
 	// Skip old events
 	let first_ts_in_room = self
 		.services
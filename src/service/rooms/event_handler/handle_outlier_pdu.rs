@@ -93,9 +93,21 @@ pub(super) async fn handle_outlier_pdu<'a>(
 	debug!("Checking based on auth events");
 	// Build map of auth events
 	let mut auth_events = HashMap::with_capacity(incoming_pdu.auth_events.len());
+	let mut missing_auth_events = false;
 	for id in &incoming_pdu.auth_events {
+		if let Ok(reason) = self.services.outlier.get_rejection_reason(id).await {
+			let reason = format!("Auth event {id} was rejected: {reason}");
+			self.services
+				.outlier
+				.add_pdu_rejected(room_id, event_id, &val, &reason)
+				.await;
+
+			return Err!(Request(Forbidden("{reason}")));
+		}
+
 		let Ok(auth_event) = self.services.timeline.get_pdu(id).map_ok(Arc::new).await else {
 			warn!("Could not find auth event {id}");
+			missing_auth_events = true;
 			continue;
 		};
 
@@ -147,8 +159,20 @@ pub(super) async fn handle_outlier_pdu<'a>(
 	.await
 	.map_err(|e| err!(Request(Forbidden("Auth check failed: {e:?}"))))?;
 
+	// The event is only rejected when it fails auth against all of its auth
+	// events; otherwise it may pass once the missing ones are fetched.
+	if !auth_check && missing_auth_events {
+		return Err!(Request(Forbidden("Auth check failed with some auth events missing")));
+	}
+
 	if !auth_check {
-		return Err!(Request(Forbidden("Auth check failed")));
+		let reason = "Auth check failed";
+		self.services
+			.outlier
+			.add_pdu_rejected(room_id, event_id, &val, reason)
+			.await;
+
+		return Err!(Request(Forbidden("{reason}")));
 	}
 
 	trace!("Validation successful.");
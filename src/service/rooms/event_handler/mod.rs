@@ -13,25 +13,27 @@
 use std::{
 	collections::HashMap,
 	fmt::Write,
-	sync::{Arc, RwLock as StdRwLock},
+	sync::{Arc, Mutex as StdMutex, RwLock as StdRwLock},
 	time::Instant,
 };
 
 use conduwuit::{
-	utils::{MutexMap, TryFutureExtExt},
+	utils::{math::usize_from_f64, MutexMap, TryFutureExtExt},
 	Err, PduEvent, Result, Server,
 };
 use futures::TryFutureExt;
 use ruma::{
-	events::room::create::RoomCreateEventContent, state_res::RoomVersion, OwnedEventId,
-	OwnedRoomId, RoomId, RoomVersionId,
+	events::room::create::RoomCreateEventContent,
+	state_res::{RoomVersion, StateMap},
+	OwnedEventId, OwnedRoomId, RoomId, RoomVersionId,
 };
 
-use crate::{globals, rooms, sending, server_keys, Dep};
+use crate::{globals, rooms, sending, server_keys, Cache, Dep, LruCache};
 
 pub struct Service {
 	pub mutex_federation: RoomMutexMap,
 	pub federation_handletime: StdRwLock<HandleTimeMap>,
+	pub stateres_cache: StdMutex<StateResLruCache>,
 	services: Services,
 }
 
@@ -41,6 +43,7 @@ struct Services {
 	auth_chain: Dep<rooms::auth_chain::Service>,
 	metadata: Dep<rooms::metadata::Service>,
 	outlier: Dep<rooms::outlier::Service>,
+	partial_state: Dep<rooms::partial_state::Service>,
 	pdu_metadata: Dep<rooms::pdu_metadata::Service>,
 	server_keys: Dep<server_keys::Service>,
 	short: Dep<rooms::short::Service>,
@@ -53,18 +56,26 @@ struct Services {
 
 type RoomMutexMap = MutexMap<OwnedRoomId, ()>;
 type HandleTimeMap = HashMap<OwnedRoomId, (OwnedEventId, Instant)>;
+type StateResLruCache = LruCache<StateResKey, Arc<StateMap<OwnedEventId>>>;
+type StateResKey = [u8; 32];
 
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let config = &args.server.config;
+		let cache_capacity =
+			f64::from(config.stateres_cache_capacity) * config.cache_capacity_modifier;
 		Ok(Arc::new(Self {
 			mutex_federation: RoomMutexMap::new(),
 			federation_handletime: HandleTimeMap::new().into(),
+			stateres_cache: LruCache::new(usize_from_f64(cache_capacity)?).into(),
 			services: Services {
 				globals: args.depend::<globals::Service>("globals"),
 				sending: args.depend::<sending::Service>("sending"),
 				auth_chain: args.depend::<rooms::auth_chain::Service>("rooms::auth_chain"),
 				metadata: args.depend::<rooms::metadata::Service>("rooms::metadata"),
 				outlier: args.depend::<rooms::outlier::Service>("rooms::outlier"),
+				partial_state: args
+					.depend::<rooms::partial_state::Service>("rooms::partial_state"),
 				server_keys: args.depend::<server_keys::Service>("server_keys"),
 				pdu_metadata: args.depend::<rooms::pdu_metadata::Service>("rooms::pdu_metadata"),
 				short: args.depend::<rooms::short::Service>("rooms::short"),
@@ -90,9 +101,22 @@ fn memory_usage(&self, out: &mut dyn Write) -> Result<()> {
 			.len();
 		writeln!(out, "federation_handletime: {federation_handletime}")?;
 
+		let stateres_cache = self.stateres_cache.lock().expect("locked").len();
+		writeln!(out, "stateres_cache: {stateres_cache}")?;
+
 		Ok(())
 	}
 
+	fn clear_cache(&self) { self.stateres_cache.lock().expect("locked").clear(); }
+
+	fn caches(&self) -> Vec<Cache<'_>> {
+		vec![Cache {
+			name: "stateres_cache",
+			base_capacity: |config| config.stateres_cache_capacity,
+			cache: &self.stateres_cache,
+		}]
+	}
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
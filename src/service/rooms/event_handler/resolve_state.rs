@@ -14,7 +14,9 @@
 	state_res::{self, StateMap},
 	OwnedEventId, RoomId, RoomVersionId,
 };
+use sha2::{Digest, Sha256};
 
+use super::StateResKey;
 use crate::rooms::state_compressor::CompressedState;
 
 #[implement(super::Service)]
@@ -116,7 +118,22 @@ pub async fn state_resolution<'a, StateSets>(
 where
 	StateSets: Iterator<Item = &'a StateMap<OwnedEventId>> + Clone + Send,
 {
-	state_res::resolve(
+	// Catching up on a backlog of federation transactions re-resolves the same
+	// forks over and over; the outcome only depends on the state sets since the
+	// auth chains and the events themselves are derived from them.
+	let key = state_resolution_key(room_version, state_sets.clone());
+	if let Some(state) = self
+		.stateres_cache
+		.lock()
+		.expect("locked")
+		.get_mut(&key)
+		.map(|state| state.as_ref().clone())
+	{
+		trace!("Using memoized state resolution result");
+		return Ok(state);
+	}
+
+	let state = state_res::resolve(
 		room_version,
 		state_sets,
 		auth_chain_sets,
@@ -125,5 +142,54 @@ pub async fn state_resolution<'a, StateSets>(
 		automatic_width(),
 	)
 	.map_err(|e| err!(error!("State resolution failed: {e:?}")))
-	.await
+	.await?;
+
+	self.stateres_cache
+		.lock()
+		.expect("locked")
+		.insert(key, Arc::new(state.clone()));
+
+	Ok(state)
+}
+
+/// Digest of the inputs of a state resolution independent of the iteration
+/// order of the state maps and of the order the forks were given in. Each
+/// entry is length-prefixed so distinct inputs can't serialize alike, and
+/// SHA-256 keeps distinct inputs from colliding on one cached result.
+fn state_resolution_key<'a, StateSets>(
+	room_version: &RoomVersionId,
+	state_sets: StateSets,
+) -> StateResKey
+where
+	StateSets: Iterator<Item = &'a StateMap<OwnedEventId>>,
+{
+	let mut set_digests: Vec<StateResKey> = state_sets
+		.map(|state_set| {
+			let mut entries: Vec<_> = state_set
+				.iter()
+				.map(|((event_type, state_key), event_id)| {
+					(event_type.to_string(), state_key.as_str(), event_id.as_str())
+				})
+				.collect();
+
+			entries.sort_unstable();
+			let mut hasher = Sha256::new();
+			for (event_type, state_key, event_id) in &entries {
+				for part in [event_type.as_str(), *state_key, *event_id] {
+					hasher.update(part.len().to_be_bytes());
+					hasher.update(part);
+				}
+			}
+
+			hasher.finalize().into()
+		})
+		.collect();
+
+	set_digests.sort_unstable();
+	let mut hasher = Sha256::new();
+	hasher.update(room_version.as_str().len().to_be_bytes());
+	hasher.update(room_version.as_str());
+	set_digests.iter().for_each(|digest| hasher.update(digest));
+
+	hasher.finalize().into()
 }
@@ -19,7 +19,7 @@
 #[implement(super::Service)]
 // request and build the state from a known point and resolve if > 1 prev_event
 #[tracing::instrument(name = "state", level = "debug", skip_all)]
-pub(super) async fn state_at_incoming_degree_one(
+pub async fn state_at_incoming_degree_one(
 	&self,
 	incoming_pdu: &Arc<PduEvent>,
 ) -> Result<Option<HashMap<u64, OwnedEventId>>> {
@@ -71,10 +71,23 @@ pub(super) async fn state_at_incoming_resolved(
 	incoming_pdu: &Arc<PduEvent>,
 	room_id: &RoomId,
 	room_version_id: &RoomVersionId,
+) -> Result<Option<HashMap<u64, OwnedEventId>>> {
+	self.state_after_events(&incoming_pdu.prev_events, room_id, room_version_id)
+		.await
+}
+
+/// Resolves the state after the given events, which must all have state
+/// associated with them. None when one of them is unknown or stateless.
+#[implement(super::Service)]
+#[tracing::instrument(name = "state", level = "debug", skip_all)]
+pub async fn state_after_events(
+	&self,
+	event_ids: &[OwnedEventId],
+	room_id: &RoomId,
+	room_version_id: &RoomVersionId,
 ) -> Result<Option<HashMap<u64, OwnedEventId>>> {
 	trace!("Calculating extremity statehashes...");
-	let Ok(extremity_sstatehashes) = incoming_pdu
-		.prev_events
+	let Ok(extremity_sstatehashes) = event_ids
 		.iter()
 		.try_stream()
 		.broad_and_then(|prev_eventid| {
@@ -1,4 +1,13 @@
-use std::{borrow::Borrow, collections::BTreeMap, iter::once, sync::Arc, time::Instant};
+use std::{
+	borrow::Borrow,
+	collections::BTreeMap,
+	iter::once,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	time::Instant,
+};
 
 use conduwuit::{
 	debug, debug_info, err, implement, trace,
@@ -75,11 +84,17 @@ pub(super) async fn upgrade_outlier_to_timeline_pdu(
 	debug!("Performing auth check");
 	// 11. Check the auth of the event passes based on the state of the event
 	let state_fetch_state = &state_at_incoming_event;
+	let missing_state = &AtomicBool::new(false);
 	let state_fetch = |k: &'static StateEventType, s: String| async move {
 		let shortstatekey = self.services.short.get_shortstatekey(k, &s).await.ok()?;
 
 		let event_id = state_fetch_state.get(&shortstatekey)?;
-		self.services.timeline.get_pdu(event_id).await.ok()
+		let pdu = self.services.timeline.get_pdu(event_id).await.ok();
+		if pdu.is_none() {
+			missing_state.store(true, Ordering::Relaxed);
+		}
+
+		pdu
 	};
 
 	let auth_check = state_res::event_auth::auth_check(
@@ -91,8 +106,22 @@ pub(super) async fn upgrade_outlier_to_timeline_pdu(
 	.await
 	.map_err(|e| err!(Request(Forbidden("Auth check failed: {e:?}"))))?;
 
+	// Only reject the event when it fails auth against state we could load
+	// entirely; otherwise it may pass once the missing events are fetched.
+	if !auth_check && missing_state.load(Ordering::Relaxed) {
+		return Err!(Request(Forbidden(
+			"Auth check failed with some of the state at the event missing."
+		)));
+	}
+
 	if !auth_check {
-		return Err!(Request(Forbidden("Event has failed auth check with state at the event.")));
+		let reason = "Event has failed auth check with state at the event.";
+		self.services
+			.outlier
+			.add_pdu_rejected(room_id, &incoming_pdu.event_id, &val, reason)
+			.await;
+
+		return Err!(Request(Forbidden("{reason}")));
 	}
 
 	debug!("Gathering auth events");
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use conduwuit::{implement, utils::stream::TryIgnore, Result};
+use conduwuit::{implement, utils::stream::TryIgnore, Result, Server};
 use database::Map;
 use futures::{Stream, StreamExt};
 use ruma::RoomId;
@@ -14,12 +14,15 @@ pub struct Service {
 
 struct Data {
 	disabledroomids: Arc<Map>,
+	ephemeraldisabledroomids: Arc<Map>,
 	bannedroomids: Arc<Map>,
+	tombstonedroomids: Arc<Map>,
 	roomid_shortroomid: Arc<Map>,
 	pduid_pdu: Arc<Map>,
 }
 
 struct Services {
+	server: Arc<Server>,
 	short: Dep<rooms::short::Service>,
 }
 
@@ -28,11 +31,14 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			db: Data {
 				disabledroomids: args.db["disabledroomids"].clone(),
+				ephemeraldisabledroomids: args.db["ephemeraldisabledroomids"].clone(),
 				bannedroomids: args.db["bannedroomids"].clone(),
+				tombstonedroomids: args.db["tombstonedroomids"].clone(),
 				roomid_shortroomid: args.db["roomid_shortroomid"].clone(),
 				pduid_pdu: args.db["pduid_pdu"].clone(),
 			},
 			services: Services {
+				server: args.server.clone(),
 				short: args.depend::<rooms::short::Service>("rooms::short"),
 			},
 		}))
@@ -98,3 +104,54 @@ pub async fn is_disabled(&self, room_id: &RoomId) -> bool {
 pub async fn is_banned(&self, room_id: &RoomId) -> bool {
 	self.db.bannedroomids.get(room_id).await.is_ok()
 }
+
+/// Marks a room as purged for having no local members, so it isn't purged
+/// again while it stays empty.
+#[implement(Service)]
+#[inline]
+pub fn tombstone_room(&self, room_id: &RoomId, tombstoned: bool) {
+	if tombstoned {
+		self.db.tombstonedroomids.insert(room_id, []);
+	} else {
+		self.db.tombstonedroomids.remove(room_id);
+	}
+}
+
+#[implement(Service)]
+#[inline]
+pub async fn is_tombstoned(&self, room_id: &RoomId) -> bool {
+	self.db.tombstonedroomids.get(room_id).await.is_ok()
+}
+
+/// Drops typing notifications in the room and keeps public read receipts in
+/// it from federation, in addition to the rooms in the
+/// `ephemeral_disabled_rooms` config option.
+#[implement(Service)]
+#[inline]
+pub fn disable_ephemeral(&self, room_id: &RoomId, disabled: bool) {
+	if disabled {
+		self.db.ephemeraldisabledroomids.insert(room_id, []);
+	} else {
+		self.db.ephemeraldisabledroomids.remove(room_id);
+	}
+}
+
+/// Lists the rooms with ephemeral events disabled at runtime, not including
+/// the ones from the config.
+#[implement(Service)]
+pub fn list_ephemeral_disabled_rooms(&self) -> impl Stream<Item = &RoomId> + Send + '_ {
+	self.db.ephemeraldisabledroomids.keys().ignore_err()
+}
+
+/// Whether typing notifications are dropped in the room and public read
+/// receipts kept from federation, by the config or at runtime.
+#[implement(Service)]
+pub async fn is_ephemeral_disabled(&self, room_id: &RoomId) -> bool {
+	self.services
+		.server
+		.config
+		.ephemeral_disabled_rooms
+		.iter()
+		.any(|disabled| disabled == room_id)
+		|| self.db.ephemeraldisabledroomids.get(room_id).await.is_ok()
+}
@@ -1,10 +1,12 @@
 pub mod alias;
 pub mod auth_chain;
+pub mod auto_accept;
 pub mod directory;
 pub mod event_handler;
 pub mod lazy_loading;
 pub mod metadata;
 pub mod outlier;
+pub mod partial_state;
 pub mod pdu_metadata;
 pub mod read_receipt;
 pub mod search;
@@ -14,6 +16,7 @@
 pub mod state_accessor;
 pub mod state_cache;
 pub mod state_compressor;
+pub mod stats;
 pub mod threads;
 pub mod timeline;
 pub mod typing;
@@ -24,11 +27,13 @@
 pub struct Service {
 	pub alias: Arc<alias::Service>,
 	pub auth_chain: Arc<auth_chain::Service>,
+	pub auto_accept: Arc<auto_accept::Service>,
 	pub directory: Arc<directory::Service>,
 	pub event_handler: Arc<event_handler::Service>,
 	pub lazy_loading: Arc<lazy_loading::Service>,
 	pub metadata: Arc<metadata::Service>,
 	pub outlier: Arc<outlier::Service>,
+	pub partial_state: Arc<partial_state::Service>,
 	pub pdu_metadata: Arc<pdu_metadata::Service>,
 	pub read_receipt: Arc<read_receipt::Service>,
 	pub search: Arc<search::Service>,
@@ -38,6 +43,7 @@ pub struct Service {
 	pub state_accessor: Arc<state_accessor::Service>,
 	pub state_cache: Arc<state_cache::Service>,
 	pub state_compressor: Arc<state_compressor::Service>,
+	pub stats: Arc<stats::Service>,
 	pub threads: Arc<threads::Service>,
 	pub timeline: Arc<timeline::Service>,
 	pub typing: Arc<typing::Service>,
@@ -1,24 +1,56 @@
 use std::sync::Arc;
 
-use conduwuit::{implement, Result};
-use database::{Deserialized, Json, Map};
-use ruma::{CanonicalJsonObject, EventId};
+use conduwuit::{
+	implement,
+	utils::{millis_since_unix_epoch, stream::TryIgnore},
+	Result,
+};
+use database::{Deserialized, Ignore, Interfix, Json, Map};
+use futures::{Stream, StreamExt};
+use ruma::{CanonicalJsonObject, EventId, OwnedEventId, RoomId};
+use serde::{Deserialize, Serialize};
 
-use crate::PduEvent;
+use crate::{globals, Dep, PduEvent};
 
 pub struct Service {
+	services: Services,
 	db: Data,
 }
 
+struct Services {
+	globals: Dep<globals::Service>,
+}
+
 struct Data {
 	eventid_outlierpdu: Arc<Map>,
+	eventid_rejectreason: Arc<Map>,
+	roomcount_rejectedpdu: Arc<Map>,
 }
 
+/// An event which failed authorization, kept for inspection and so it is not
+/// repeatedly fetched when referenced by other events.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RejectedPdu {
+	pub event_id: OwnedEventId,
+	pub reason: String,
+	/// Milliseconds since the unix epoch at which we rejected the event.
+	pub rejected_at: u64,
+	pub pdu: CanonicalJsonObject,
+}
+
+/// Only the most recent rejections in each room are retained.
+const REJECTED_PDUS_PER_ROOM: usize = 256;
+
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
+			services: Services {
+				globals: args.depend::<globals::Service>("globals"),
+			},
 			db: Data {
 				eventid_outlierpdu: args.db["eventid_outlierpdu"].clone(),
+				eventid_rejectreason: args.db["eventid_rejectreason"].clone(),
+				roomcount_rejectedpdu: args.db["roomcount_rejectedpdu"].clone(),
 			},
 		}))
 	}
@@ -52,3 +84,86 @@ pub async fn get_pdu_outlier(&self, event_id: &EventId) -> Result<PduEvent> {
 pub fn add_pdu_outlier(&self, event_id: &EventId, pdu: &CanonicalJsonObject) {
 	self.db.eventid_outlierpdu.raw_put(event_id, Json(pdu));
 }
+
+/// Record a PDU which was rejected by authorization along with the reason.
+/// The oldest rejections in the room are dropped beyond a fixed bound.
+#[implement(Service)]
+#[tracing::instrument(skip(self, pdu), level = "debug")]
+pub async fn add_pdu_rejected(
+	&self,
+	room_id: &RoomId,
+	event_id: &EventId,
+	pdu: &CanonicalJsonObject,
+	reason: &str,
+) {
+	let Ok(count) = self.services.globals.next_count() else {
+		return;
+	};
+
+	let rejected = RejectedPdu {
+		event_id: event_id.to_owned(),
+		reason: reason.to_owned(),
+		rejected_at: millis_since_unix_epoch(),
+		pdu: pdu.clone(),
+	};
+
+	self.db.eventid_rejectreason.insert(event_id, reason);
+	self.db
+		.roomcount_rejectedpdu
+		.put((room_id, count), Json(rejected));
+
+	self.prune_rejected(room_id).await;
+}
+
+#[implement(Service)]
+async fn prune_rejected(&self, room_id: &RoomId) {
+	type KeyVal<'a> = ((Ignore, u64), RejectedPdu);
+
+	let prefix = (room_id, Interfix);
+	let expired: Vec<_> = self
+		.db
+		.roomcount_rejectedpdu
+		.rev_stream_prefix(&prefix)
+		.ignore_err()
+		.skip(REJECTED_PDUS_PER_ROOM)
+		.map(|((_, count), rejected): KeyVal<'_>| (count, rejected.event_id))
+		.collect()
+		.await;
+
+	for (count, event_id) in expired {
+		self.db.eventid_rejectreason.remove(event_id.as_str());
+		self.db.roomcount_rejectedpdu.del((room_id, count));
+	}
+}
+
+/// Returns the reason an event was rejected, if it was.
+#[implement(Service)]
+pub async fn get_rejection_reason(&self, event_id: &EventId) -> Result<String> {
+	self.db
+		.eventid_rejectreason
+		.get(event_id)
+		.await
+		.deserialized()
+}
+
+#[implement(Service)]
+#[inline]
+pub async fn is_rejected(&self, event_id: &EventId) -> bool {
+	self.db.eventid_rejectreason.get(event_id).await.is_ok()
+}
+
+/// Iterates the retained rejected PDUs of a room, most recent first.
+#[implement(Service)]
+pub fn rejected_pdus<'a>(
+	&'a self,
+	room_id: &'a RoomId,
+) -> impl Stream<Item = RejectedPdu> + Send + 'a {
+	type KeyVal<'a> = (Ignore, RejectedPdu);
+
+	let prefix = (room_id, Interfix);
+	self.db
+		.roomcount_rejectedpdu
+		.rev_stream_prefix(&prefix)
+		.ignore_err()
+		.map(|(_, rejected): KeyVal<'_>| rejected)
+}
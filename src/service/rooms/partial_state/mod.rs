@@ -0,0 +1,368 @@
+use std::{
+	borrow::Borrow,
+	collections::HashMap,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use conduwuit::{
+	debug, debug_warn, implement, info, utils::stream::TryIgnore, warn, Err, Result,
+};
+use database::{Deserialized, Ignore, Interfix, Json, Map};
+use futures::{Stream, StreamExt};
+use loole::{Receiver, Sender};
+use ruma::{
+	events::StateEventType, CanonicalJsonValue, EventId, OwnedEventId, OwnedRoomId,
+	OwnedServerName, RoomId, ServerName,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::{
+	globals, rooms,
+	rooms::{
+		short::ShortStateKey,
+		state_compressor::{CompressedState, HashSetCompressStateEvent},
+	},
+	Dep,
+};
+
+pub struct Service {
+	resync_channel: (Sender<OwnedRoomId>, Receiver<OwnedRoomId>),
+	/// Rooms whose resync failed, with when to retry and how often it failed.
+	retries: Mutex<HashMap<OwnedRoomId, (Instant, u32)>>,
+	services: Services,
+	db: Data,
+}
+
+struct Services {
+	globals: Dep<globals::Service>,
+	event_handler: Dep<rooms::event_handler::Service>,
+	outlier: Dep<rooms::outlier::Service>,
+	state: Dep<rooms::state::Service>,
+	state_accessor: Dep<rooms::state_accessor::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	state_compressor: Dep<rooms::state_compressor::Service>,
+}
+
+struct Data {
+	roomeventid_partialstatequeue: Arc<Map>,
+	roomid_partialstate: Arc<Map>,
+}
+
+/// How often rooms whose resync failed are checked for a retry.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The delay before retrying a failed resync doubles from this...
+const RETRY_BACKOFF_MIN: Duration = Duration::from_secs(30);
+
+/// ...up to this.
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(60 * 60);
+
+/// What we remember about a room joined with partial state until the full
+/// state has been fetched.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PartialState {
+	/// Our join event; the full state is requested at this event.
+	pub event_id: OwnedEventId,
+
+	/// Servers the resident server told us are in the room. Events are also
+	/// sent to these since our own member list is incomplete.
+	pub servers: Vec<OwnedServerName>,
+}
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			resync_channel: loole::unbounded(),
+			retries: Mutex::default(),
+			services: Services {
+				globals: args.depend::<globals::Service>("globals"),
+				event_handler: args
+					.depend::<rooms::event_handler::Service>("rooms::event_handler"),
+				outlier: args.depend::<rooms::outlier::Service>("rooms::outlier"),
+				state: args.depend::<rooms::state::Service>("rooms::state"),
+				state_accessor: args
+					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				state_compressor: args
+					.depend::<rooms::state_compressor::Service>("rooms::state_compressor"),
+			},
+			db: Data {
+				roomeventid_partialstatequeue: args.db["roomeventid_partialstatequeue"].clone(),
+				roomid_partialstate: args.db["roomid_partialstate"].clone(),
+			},
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		// Resume any resyncs which were interrupted by a restart.
+		let pending: Vec<OwnedRoomId> = self
+			.partial_state_rooms()
+			.map(ToOwned::to_owned)
+			.collect()
+			.await;
+
+		for room_id in pending {
+			self.queue_resync(&room_id);
+		}
+
+		let receiver = self.resync_channel.1.clone();
+		let mut i = interval(RETRY_INTERVAL);
+		i.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		loop {
+			tokio::select! {
+				room_id = receiver.recv_async() => {
+					let Ok(room_id) = room_id else {
+						break;
+					};
+
+					self.handle_resync(&room_id).await;
+				},
+				_ = i.tick() => self.queue_due_retries(),
+			}
+		}
+
+		Ok(())
+	}
+
+	fn interrupt(&self) {
+		let (sender, _) = &self.resync_channel;
+		if !sender.is_closed() {
+			sender.close();
+		}
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Marks a room as only partially joined. The caller should follow up with
+/// `queue_resync()` once the join event has been appended.
+#[implement(Service)]
+pub fn mark_partial_state(
+	&self,
+	room_id: &RoomId,
+	event_id: OwnedEventId,
+	servers: Vec<OwnedServerName>,
+) {
+	let partial = PartialState { event_id, servers };
+	self.db.roomid_partialstate.raw_put(room_id, Json(partial));
+}
+
+/// Schedules the background fetch of the full state of a partial state room.
+#[implement(Service)]
+pub fn queue_resync(&self, room_id: &RoomId) {
+	let (sender, _) = &self.resync_channel;
+	if let Err(e) = sender.send(room_id.to_owned()) {
+		debug_warn!("Failed to queue full state resync for {room_id}: {e}");
+	}
+}
+
+/// Holds back an incoming timeline event for a partial state room, which we
+/// can't authorize against the current state yet. It was already persisted as
+/// an outlier and is handled again once the full state has been fetched.
+#[implement(Service)]
+pub fn queue_event(&self, origin: &ServerName, room_id: &RoomId, event_id: &EventId) {
+	debug!(%room_id, %event_id, "Queueing event until the room has full state");
+	self.db
+		.roomeventid_partialstatequeue
+		.put_raw((room_id, event_id), origin);
+}
+
+#[implement(Service)]
+#[inline]
+pub async fn is_partial_state(&self, room_id: &RoomId) -> bool {
+	self.db.roomid_partialstate.get(room_id).await.is_ok()
+}
+
+#[implement(Service)]
+pub async fn get_partial_state(&self, room_id: &RoomId) -> Result<PartialState> {
+	self.db
+		.roomid_partialstate
+		.get(room_id)
+		.await
+		.deserialized()
+}
+
+/// Servers which the resident server reported as participating in a room we
+/// only have partial state for. Empty for rooms with full state.
+#[implement(Service)]
+pub async fn servers_in_room(&self, room_id: &RoomId) -> Vec<OwnedServerName> {
+	self.get_partial_state(room_id)
+		.await
+		.map(|partial| partial.servers)
+		.unwrap_or_default()
+}
+
+#[implement(Service)]
+pub fn partial_state_rooms(&self) -> impl Stream<Item = &RoomId> + Send + '_ {
+	self.db.roomid_partialstate.keys().ignore_err()
+}
+
+/// Resyncs a room, scheduling a retry with backoff if it fails.
+#[implement(Service)]
+async fn handle_resync(&self, room_id: &RoomId) {
+	match self.resync(room_id).await {
+		| Ok(()) => {
+			self.retries.lock().expect("locked").remove(room_id);
+			self.handle_queued_events(room_id).await;
+		},
+		| Err(e) => {
+			let mut retries = self.retries.lock().expect("locked");
+			let failures = retries.get(room_id).map_or(0, |(_, failures)| *failures);
+			let backoff = RETRY_BACKOFF_MIN
+				.saturating_mul(2_u32.saturating_pow(failures))
+				.min(RETRY_BACKOFF_MAX);
+
+			warn!(%room_id, "Failed to fetch full state, retrying in {backoff:?}: {e}");
+			let now = Instant::now();
+			retries.insert(
+				room_id.to_owned(),
+				(now.checked_add(backoff).unwrap_or(now), failures.saturating_add(1)),
+			);
+		},
+	}
+}
+
+/// Queues the resyncs whose backoff has passed.
+#[implement(Service)]
+fn queue_due_retries(&self) {
+	let now = Instant::now();
+	let mut retries = self.retries.lock().expect("locked");
+	for (room_id, (retry_at, _)) in retries.iter_mut() {
+		if *retry_at <= now {
+			// Not again until this attempt has been handled.
+			*retry_at = now.checked_add(RETRY_BACKOFF_MAX).unwrap_or(now);
+			self.queue_resync(room_id);
+		}
+	}
+}
+
+/// Handles the timeline events held back while the room had partial state,
+/// oldest first.
+#[implement(Service)]
+async fn handle_queued_events(&self, room_id: &RoomId) {
+	let prefix = (room_id, Interfix);
+	let queued: Vec<(OwnedEventId, OwnedServerName)> = self
+		.db
+		.roomeventid_partialstatequeue
+		.stream_prefix(&prefix)
+		.ignore_err()
+		.map(|((_, event_id), origin): ((Ignore, &EventId), &ServerName)| {
+			(event_id.to_owned(), origin.to_owned())
+		})
+		.collect()
+		.await;
+
+	let mut events = Vec::with_capacity(queued.len());
+	for (event_id, origin) in queued {
+		self.db
+			.roomeventid_partialstatequeue
+			.del((room_id, &event_id));
+
+		match self.services.outlier.get_outlier_pdu_json(&event_id).await {
+			| Ok(value) => events.push((event_id, origin, value)),
+			| Err(e) => debug_warn!(%event_id, "Queued event is gone: {e}"),
+		}
+	}
+
+	events.sort_by_key(|(_, _, value)| match value.get("depth") {
+		| Some(CanonicalJsonValue::Integer(depth)) => i64::from(*depth),
+		| _ => 0,
+	});
+
+	for (event_id, origin, value) in events {
+		if let Err(e) = self
+			.services
+			.event_handler
+			.handle_incoming_pdu(&origin, room_id, &event_id, value, true)
+			.await
+		{
+			debug_warn!(%event_id, "Failed to handle queued event: {e}");
+		}
+	}
+}
+
+/// Fetches the full state at our join event from one of the servers in the
+/// room and merges it into the current state, then clears the partial state
+/// mark.
+#[implement(Service)]
+#[tracing::instrument(skip(self), level = "info")]
+async fn resync(&self, room_id: &RoomId) -> Result {
+	let Ok(partial) = self.get_partial_state(room_id).await else {
+		return Ok(());
+	};
+
+	let create_event = self
+		.services
+		.state_accessor
+		.room_state_get(room_id, &StateEventType::RoomCreate, "")
+		.await?;
+
+	let mut full_state = None;
+	for server in &partial.servers {
+		if self.services.globals.server_is_ours(server) {
+			continue;
+		}
+
+		match self
+			.services
+			.event_handler
+			.fetch_state(server, &create_event, room_id, &partial.event_id)
+			.await
+		{
+			| Ok(Some(state)) => {
+				full_state = Some(state);
+				break;
+			},
+			| Ok(None) => continue,
+			| Err(e) => debug_warn!(%server, "Failed to fetch full state: {e}"),
+		}
+	}
+
+	let Some(full_state) = full_state else {
+		return Err!("No server could provide the full state of {room_id}");
+	};
+
+	let state_lock = self.services.state.mutex.lock(room_id).await;
+	let current_shortstatehash = self.services.state.get_room_shortstatehash(room_id).await?;
+
+	// Anything we already have is newer than the state at our join.
+	let mut state: HashMap<ShortStateKey, OwnedEventId> = self
+		.services
+		.state_accessor
+		.state_full_ids(current_shortstatehash)
+		.collect()
+		.await;
+
+	for (shortstatekey, event_id) in full_state {
+		state.entry(shortstatekey).or_insert(event_id);
+	}
+
+	let compressed: CompressedState = self
+		.services
+		.state_compressor
+		.compress_state_events(state.iter().map(|(ssk, eid)| (ssk, eid.borrow())))
+		.collect()
+		.await;
+
+	let HashSetCompressStateEvent { shortstatehash, added, removed } = self
+		.services
+		.state_compressor
+		.save_state(room_id, Arc::new(compressed))
+		.await?;
+
+	self.services
+		.state
+		.force_state(room_id, shortstatehash, added, removed, &state_lock)
+		.await?;
+
+	self.services.state_cache.update_joined_count(room_id).await;
+	self.db.roomid_partialstate.remove(room_id);
+
+	info!("Finished fetching full state; room is no longer partial state");
+
+	Ok(())
+}
@@ -4,10 +4,13 @@
 	utils::{stream::TryIgnore, ReadyExt},
 	Result,
 };
-use database::{Deserialized, Json, Map};
+use database::{Deserialized, Ignore, Json, Map};
 use futures::{Stream, StreamExt};
 use ruma::{
-	events::{receipt::ReceiptEvent, AnySyncEphemeralRoomEvent},
+	events::{
+		receipt::{ReceiptEvent, ReceiptThread},
+		AnySyncEphemeralRoomEvent,
+	},
 	serde::Raw,
 	CanonicalJsonObject, RoomId, UserId,
 };
@@ -46,13 +49,21 @@ pub(super) async fn readreceipt_update(
 		room_id: &RoomId,
 		event: &ReceiptEvent,
 	) {
-		// Remove old entry
+		// Remove old entry; receipts in other threads are kept
+		let thread = receipt_thread(event);
 		let last_possible_key = (room_id, u64::MAX);
 		self.readreceiptid_readreceipt
-			.rev_keys_from_raw(&last_possible_key)
+			.rev_stream_from_raw(&last_possible_key)
 			.ignore_err()
-			.ready_take_while(|key| key.starts_with(room_id.as_bytes()))
-			.ready_filter_map(|key| key.ends_with(user_id.as_bytes()).then_some(key))
+			.ready_take_while(|(key, _)| key.starts_with(room_id.as_bytes()))
+			.ready_filter_map(|(key, val)| {
+				let same_thread = || {
+					serde_json::from_slice::<ReceiptEvent>(val)
+						.is_ok_and(|prev| receipt_thread(&prev) == thread)
+				};
+
+				(key.ends_with(user_id.as_bytes()) && same_thread()).then_some(key)
+			})
 			.ready_for_each(|key| self.readreceiptid_readreceipt.del(key))
 			.await;
 
@@ -86,6 +97,20 @@ pub(super) fn readreceipts_since<'a>(
 			.ignore_err()
 	}
 
+	pub(super) async fn last_receipt_count(&self, room_id: &RoomId) -> u64 {
+		type Key<'a> = (&'a RoomId, u64, Ignore);
+
+		let last_possible_key = (room_id, u64::MAX);
+		self.readreceiptid_readreceipt
+			.rev_keys_from(&last_possible_key)
+			.ignore_err()
+			.ready_take_while(|(room_id_, ..): &Key<'_>| *room_id_ == room_id)
+			.map(|(_, count, _): Key<'_>| count)
+			.next()
+			.await
+			.unwrap_or(0)
+	}
+
 	pub(super) fn private_read_set(&self, room_id: &RoomId, user_id: &UserId, pdu_count: u64) {
 		let key = (room_id, user_id);
 		let next_count = self.services.globals.next_count().unwrap();
@@ -116,3 +141,16 @@ pub(super) async fn last_privateread_update(
 			.unwrap_or(0)
 	}
 }
+
+/// The thread of a receipt event; ours only ever hold a single receipt.
+fn receipt_thread(event: &ReceiptEvent) -> ReceiptThread {
+	event
+		.content
+		.0
+		.values()
+		.flat_map(|receipts| receipts.values())
+		.flat_map(|receipts| receipts.values())
+		.map(|receipt| receipt.thread.clone())
+		.next()
+		.unwrap_or(ReceiptThread::Unthreaded)
+}
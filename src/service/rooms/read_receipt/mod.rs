@@ -2,11 +2,11 @@
 
 use std::{collections::BTreeMap, sync::Arc};
 
-use conduwuit::{debug, err, warn, PduCount, PduId, RawPduId, Result};
+use conduwuit::{debug, err, result::LogErr, warn, PduCount, PduId, RawPduId, Result};
 use futures::{try_join, Stream, TryFutureExt};
 use ruma::{
 	events::{
-		receipt::{ReceiptEvent, ReceiptEventContent, Receipts},
+		receipt::{ReceiptEvent, ReceiptEventContent, ReceiptType, Receipts},
 		AnySyncEphemeralRoomEvent, SyncEphemeralRoomEvent,
 	},
 	serde::Raw,
@@ -14,7 +14,7 @@
 };
 
 use self::data::{Data, ReceiptItem};
-use crate::{rooms, sending, Dep};
+use crate::{rooms, sending, sending::EduBuf, Dep};
 
 pub struct Service {
 	services: Services,
@@ -43,19 +43,42 @@ fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
 impl Service {
-	/// Replaces the previous read receipt.
+	/// Replaces the user's previous read receipt in the same thread. Private
+	/// receipts are dropped: they're only kept by `private_read_set`, which
+	/// neither federates nor shares them.
 	pub async fn readreceipt_update(
 		&self,
 		user_id: &UserId,
 		room_id: &RoomId,
 		event: &ReceiptEvent,
 	) {
-		self.db.readreceipt_update(user_id, room_id, event).await;
+		let event = without_private(event);
+		if event.content.0.is_empty() {
+			return;
+		}
+
+		self.db.readreceipt_update(user_id, room_id, &event).await;
 		self.services
 			.sending
 			.flush_room(room_id)
 			.await
 			.expect("room flush failed");
+
+		self.appservice_send(room_id, &event).await;
+	}
+
+	/// Pushes the receipts of the event to the appservices receiving
+	/// ephemeral events.
+	async fn appservice_send(&self, room_id: &RoomId, event: &ReceiptEvent) {
+		let mut buf = EduBuf::new();
+		serde_json::to_writer(&mut buf, event).expect("Serialized m.receipt event");
+
+		self.services
+			.sending
+			.send_edu_appservices_room(room_id, buf)
+			.await
+			.log_err()
+			.ok();
 	}
 
 	/// Gets the latest private read receipt from the user in the room
@@ -110,6 +133,13 @@ pub fn readreceipts_since<'a>(
 		self.db.readreceipts_since(room_id, since)
 	}
 
+	/// Returns the count of the latest read receipt in the room, or 0 without
+	/// any.
+	#[inline]
+	pub async fn last_receipt_count(&self, room_id: &RoomId) -> u64 {
+		self.db.last_receipt_count(room_id).await
+	}
+
 	/// Sets a private read marker at PDU `count`.
 	#[inline]
 	#[tracing::instrument(skip(self), level = "debug")]
@@ -135,6 +165,17 @@ pub async fn last_privateread_update(&self, user_id: &UserId, room_id: &RoomId)
 	}
 }
 
+/// The event without its private receipts.
+fn without_private(event: &ReceiptEvent) -> ReceiptEvent {
+	let mut event = event.clone();
+	for receipts in event.content.0.values_mut() {
+		receipts.remove(&ReceiptType::ReadPrivate);
+	}
+
+	event.content.0.retain(|_, receipts| !receipts.is_empty());
+	event
+}
+
 #[must_use]
 pub fn pack_receipts<I>(receipts: I) -> Raw<SyncEphemeralRoomEvent<ReceiptEventContent>>
 where
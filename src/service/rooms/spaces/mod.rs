@@ -5,15 +5,15 @@
 	fmt::{Display, Formatter},
 	str::FromStr,
 	sync::Arc,
+	time::{Duration, Instant},
 };
 
 use conduwuit::{
 	checked, debug_info, err,
-	utils::{math::usize_from_f64, IterStream},
-	Error, Result,
+	utils::{math::usize_from_f64, random_string, IterStream},
+	Err, Error, Result,
 };
 use futures::{StreamExt, TryFutureExt};
-use lru_cache::LruCache;
 use ruma::{
 	api::{
 		client::{self, error::ErrorKind, space::SpaceHierarchyRoomsChunk},
@@ -23,28 +23,60 @@
 		},
 	},
 	events::{
-		room::join_rules::{JoinRule, RoomJoinRulesEventContent},
 		space::child::{HierarchySpaceChildEvent, SpaceChildEventContent},
 		StateEventType,
 	},
 	serde::Raw,
 	space::SpaceRoomJoinRule,
-	OwnedRoomId, OwnedServerName, RoomId, ServerName, UInt, UserId,
+	OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, ServerName, UInt, UserId,
 };
 use tokio::sync::Mutex;
 
-use crate::{rooms, rooms::short::ShortRoomId, sending, Dep};
+use crate::{rooms, rooms::short::ShortRoomId, sending, Cache, Dep, LruCache};
 
 pub struct CachedSpaceHierarchySummary {
 	summary: SpaceHierarchyParentSummary,
 }
 
+/// Key for the resolved children of a room: (room, suggested_only)
+pub type SpaceHierarchyChildrenKey = (OwnedRoomId, bool);
+
+pub struct CachedSpaceHierarchyChildren {
+	children: Vec<(OwnedRoomId, Vec<OwnedServerName>)>,
+	inserted: Instant,
+}
+
+/// Resolved children are only reused for a short time so that pagination over
+/// large spaces does not re-resolve the children state for every page, while
+/// still picking up changes made by remote servers reasonably soon.
+const CHILDREN_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Rooms still to be visited by a depth-first traversal, one entry per level
+type HierarchyStack = Vec<Vec<(OwnedRoomId, Vec<OwnedServerName>)>>;
+
+/// Remaining traversal of a client hierarchy request, stored under the
+/// `next_batch` token so that the next page resumes from the exact same
+/// position and child order, even if the space changed in the meantime.
+#[derive(Clone)]
+pub struct HierarchySession {
+	user_id: OwnedUserId,
+	room_id: OwnedRoomId,
+	max_depth: u64,
+	suggested_only: bool,
+	stack: HierarchyStack,
+	parents: VecDeque<OwnedRoomId>,
+	inserted: Instant,
+}
+
+const HIERARCHY_TOKEN_LENGTH: usize = 32;
+
 pub enum SummaryAccessibility {
 	Accessible(Box<SpaceHierarchyParentSummary>),
 	Inaccessible,
 }
 
-// TODO: perhaps use some better form of token rather than just room count
+/// Token naming the path of rooms to start after. No longer handed out, but
+/// still accepted from clients which obtained one before sessions were used.
 #[derive(Debug, Eq, PartialEq)]
 pub struct PaginationToken {
 	/// Path down the hierarchy of the room to start the response at,
@@ -127,6 +159,10 @@ pub struct Service {
 	services: Services,
 	pub roomid_spacehierarchy_cache:
 		Mutex<LruCache<OwnedRoomId, Option<CachedSpaceHierarchySummary>>>,
+	pub roomid_spacehierarchy_children_cache:
+		Mutex<LruCache<SpaceHierarchyChildrenKey, CachedSpaceHierarchyChildren>>,
+	pub hierarchy_sessions: Mutex<LruCache<String, HierarchySession>>,
+	hierarchy_session_ttl: Duration,
 }
 
 struct Services {
@@ -144,6 +180,8 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		let config = &args.server.config;
 		let cache_size = f64::from(config.roomid_spacehierarchy_cache_capacity);
 		let cache_size = cache_size * config.cache_capacity_modifier;
+		let session_capacity = f64::from(config.hierarchy_pagination_session_capacity);
+		let session_capacity = session_capacity * config.cache_capacity_modifier;
 		Ok(Arc::new(Self {
 			services: Services {
 				state_accessor: args
@@ -157,9 +195,34 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				sending: args.depend::<sending::Service>("sending"),
 			},
 			roomid_spacehierarchy_cache: Mutex::new(LruCache::new(usize_from_f64(cache_size)?)),
+			roomid_spacehierarchy_children_cache: Mutex::new(LruCache::new(usize_from_f64(
+				cache_size,
+			)?)),
+			hierarchy_sessions: Mutex::new(LruCache::new(usize_from_f64(session_capacity)?)),
+			hierarchy_session_ttl: Duration::from_secs(config.hierarchy_pagination_session_ttl),
 		}))
 	}
 
+	fn caches(&self) -> Vec<Cache<'_>> {
+		vec![
+			Cache {
+				name: "roomid_spacehierarchy_cache",
+				base_capacity: |config| config.roomid_spacehierarchy_cache_capacity,
+				cache: &self.roomid_spacehierarchy_cache,
+			},
+			Cache {
+				name: "roomid_spacehierarchy_children_cache",
+				base_capacity: |config| config.roomid_spacehierarchy_cache_capacity,
+				cache: &self.roomid_spacehierarchy_children_cache,
+			},
+			Cache {
+				name: "hierarchy_sessions",
+				base_capacity: |config| config.hierarchy_pagination_session_capacity,
+				cache: &self.hierarchy_sessions,
+			},
+		]
+	}
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
@@ -185,7 +248,8 @@ pub async fn get_federation_hierarchy(
 				let mut children = Vec::new();
 				let mut inaccessible_children = Vec::new();
 
-				for (child, _via) in get_parent_children_via(&room, suggested_only) {
+				let room_children = self.get_cached_children(&room, suggested_only).await;
+				for (child, _via) in room_children {
 					match self
 						.get_summary_and_children_local(
 							&child,
@@ -392,13 +456,13 @@ async fn get_room_summary(
 	) -> Result<SpaceHierarchyParentSummary, Error> {
 		let room_id: &RoomId = current_room;
 
-		let join_rule = self
+		let view = self
 			.services
 			.state_accessor
-			.room_state_get_content(room_id, &StateEventType::RoomJoinRules, "")
-			.await
-			.map_or(JoinRule::Invite, |c: RoomJoinRulesEventContent| c.join_rule);
+			.room_summary_view(room_id)
+			.await?;
 
+		let join_rule = view.join_rule;
 		let allowed_room_ids = self
 			.services
 			.state_accessor
@@ -422,80 +486,91 @@ async fn get_room_summary(
 		}
 
 		Ok(SpaceHierarchyParentSummary {
-			canonical_alias: self
-				.services
-				.state_accessor
-				.get_canonical_alias(room_id)
-				.await
-				.ok(),
-			name: self.services.state_accessor.get_name(room_id).await.ok(),
-			num_joined_members: self
-				.services
-				.state_cache
-				.room_joined_count(room_id)
-				.await
-				.unwrap_or(0)
+			canonical_alias: view.canonical_alias,
+			name: view.name,
+			num_joined_members: view
+				.joined_members
 				.try_into()
 				.expect("user count should not be that big"),
 			room_id: room_id.to_owned(),
-			topic: self
-				.services
-				.state_accessor
-				.get_room_topic(room_id)
-				.await
-				.ok(),
-			world_readable: self
-				.services
-				.state_accessor
-				.is_world_readable(room_id)
-				.await,
-			guest_can_join: self.services.state_accessor.guest_can_join(room_id).await,
-			avatar_url: self
-				.services
-				.state_accessor
-				.get_avatar(room_id)
-				.await
-				.into_option()
-				.unwrap_or_default()
-				.url,
+			topic: view.topic,
+			world_readable: view.world_readable,
+			guest_can_join: view.guest_can_join,
+			avatar_url: view.avatar_url,
 			join_rule: join_rule.into(),
-			room_type: self
-				.services
-				.state_accessor
-				.get_room_type(room_id)
-				.await
-				.ok(),
+			room_type: view.room_type,
 			children_state,
 			allowed_room_ids,
 		})
 	}
 
+	/// Gets one page of the space hierarchy for a client. `from` is either a
+	/// `next_batch` token handed out by a previous page, which resumes the
+	/// stored traversal, or a legacy token naming the path of short room ids
+	/// to start after.
 	pub async fn get_client_hierarchy(
 		&self,
 		sender_user: &UserId,
 		room_id: &RoomId,
 		limit: usize,
-		short_room_ids: Vec<ShortRoomId>,
+		from: Option<&str>,
 		max_depth: u64,
 		suggested_only: bool,
 	) -> Result<client::space::get_hierarchy::v1::Response> {
-		let mut parents = VecDeque::new();
+		let root = || {
+			vec![vec![(room_id.to_owned(), match room_id.server_name() {
+				| Some(server_name) => vec![server_name.into()],
+				| None => vec![],
+			})]]
+		};
+
+		let (mut stack, mut parents, short_room_ids) = match from {
+			| None => (root(), VecDeque::new(), Vec::new()),
+			| Some(token) => match self.get_hierarchy_session(token).await {
+				| Some(session) => {
+					if session.user_id != sender_user || session.room_id != room_id {
+						return Err!(Request(InvalidParam(
+							"Pagination token was issued for a different request."
+						)));
+					}
+
+					if session.suggested_only != suggested_only || session.max_depth != max_depth
+					{
+						return Err!(Request(InvalidParam(
+							"suggested_only and max_depth cannot change on paginated requests"
+						)));
+					}
+
+					(session.stack, session.parents, Vec::new())
+				},
+				| None => {
+					let token = PaginationToken::from_str(token).map_err(|_| {
+						err!(Request(InvalidParam("Unknown or expired pagination token.")))
+					})?;
+
+					if token.suggested_only != suggested_only
+						|| u64::from(token.max_depth) != max_depth
+					{
+						return Err!(Request(InvalidParam(
+							"suggested_only and max_depth cannot change on paginated requests"
+						)));
+					}
+
+					(root(), VecDeque::new(), token.short_room_ids)
+				},
+			},
+		};
 
 		// Don't start populating the results if we have to start at a specific room.
 		let mut populate_results = short_room_ids.is_empty();
 
-		let mut stack = vec![vec![(room_id.to_owned(), match room_id.server_name() {
-			| Some(server_name) => vec![server_name.into()],
-			| None => vec![],
-		})]];
-
 		let mut results = Vec::with_capacity(limit);
 
-		while let Some((current_room, via)) = { next_room_to_traverse(&mut stack, &mut parents) }
-		{
-			if results.len() >= limit {
+		while results.len() < limit {
+			let Some((current_room, via)) = next_room_to_traverse(&mut stack, &mut parents)
+			else {
 				break;
-			}
+			};
 
 			match (
 				self.get_summary_and_children_client(
@@ -508,12 +583,13 @@ pub async fn get_client_hierarchy(
 				current_room == room_id,
 			) {
 				| (Some(SummaryAccessibility::Accessible(summary)), _) => {
-					let mut children: Vec<(OwnedRoomId, Vec<OwnedServerName>)> =
-						get_parent_children_via(&summary, suggested_only)
-							.into_iter()
-							.filter(|(room, _)| parents.iter().all(|parent| parent != room))
-							.rev()
-							.collect();
+					let mut children: Vec<(OwnedRoomId, Vec<OwnedServerName>)> = self
+						.get_cached_children(&summary, suggested_only)
+						.await
+						.into_iter()
+						.filter(|(room, _)| parents.iter().all(|parent| parent != room))
+						.rev()
+						.collect();
 
 					if populate_results {
 						results.push(summary_to_chunk(*summary.clone()));
@@ -577,38 +653,78 @@ pub async fn get_client_hierarchy(
 			}
 		}
 
-		Ok(client::space::get_hierarchy::v1::Response {
-			next_batch: if let Some((room, _)) = next_room_to_traverse(&mut stack, &mut parents) {
-				parents.pop_front();
-				parents.push_back(room);
-
-				let next_short_room_ids: Vec<_> = parents
-					.iter()
-					.stream()
-					.filter_map(|room_id| async move {
-						self.services.short.get_shortroomid(room_id).await.ok()
-					})
-					.collect()
-					.await;
-
-				(next_short_room_ids != short_room_ids && !next_short_room_ids.is_empty()).then(
-					|| {
-						PaginationToken {
-							short_room_ids: next_short_room_ids,
-							limit: UInt::new(max_depth)
-								.expect("When sent in request it must have been valid UInt"),
-							max_depth: UInt::new(max_depth)
-								.expect("When sent in request it must have been valid UInt"),
-							suggested_only,
-						}
-						.to_string()
-					},
-				)
-			} else {
-				None
-			},
-			rooms: results,
-		})
+		let next_batch = if stack.iter().any(|children| !children.is_empty()) {
+			let token = random_string(HIERARCHY_TOKEN_LENGTH);
+			self.hierarchy_sessions
+				.lock()
+				.await
+				.insert(token.clone(), HierarchySession {
+					user_id: sender_user.to_owned(),
+					room_id: room_id.to_owned(),
+					max_depth,
+					suggested_only,
+					stack,
+					parents,
+					inserted: Instant::now(),
+				});
+
+			Some(token)
+		} else {
+			None
+		};
+
+		Ok(client::space::get_hierarchy::v1::Response { next_batch, rooms: results })
+	}
+
+	/// Returns the stored traversal for a `next_batch` token, unless it has
+	/// expired. The session is kept so that a client may retry the same page.
+	async fn get_hierarchy_session(&self, token: &str) -> Option<HierarchySession> {
+		let mut sessions = self.hierarchy_sessions.lock().await;
+		let session = sessions.get_mut(token)?;
+		if session.inserted.elapsed() < self.hierarchy_session_ttl {
+			return Some(session.clone());
+		}
+
+		sessions.remove(token);
+		None
+	}
+
+	/// Returns the children of a summary, reusing a previous resolution for the
+	/// same (room, suggested_only) if it has not yet expired.
+	async fn get_cached_children(
+		&self,
+		summary: &SpaceHierarchyParentSummary,
+		suggested_only: bool,
+	) -> Vec<(OwnedRoomId, Vec<OwnedServerName>)> {
+		let key = (summary.room_id.clone(), suggested_only);
+		let mut cache = self.roomid_spacehierarchy_children_cache.lock().await;
+		if let Some(cached) = cache.get_mut(&key) {
+			if cached.inserted.elapsed() < CHILDREN_CACHE_TTL {
+				return cached.children.clone();
+			}
+		}
+
+		let children = get_parent_children_via(summary, suggested_only);
+		cache.insert(key, CachedSpaceHierarchyChildren {
+			children: children.clone(),
+			inserted: Instant::now(),
+		});
+
+		children
+	}
+
+	/// Drops all cached hierarchy information for a room; used when its
+	/// m.space.child state changes.
+	pub async fn invalidate_room(&self, room_id: &RoomId) {
+		self.roomid_spacehierarchy_cache
+			.lock()
+			.await
+			.remove(room_id);
+
+		let mut children_cache = self.roomid_spacehierarchy_children_cache.lock().await;
+		for suggested_only in [false, true] {
+			children_cache.remove(&(room_id.to_owned(), suggested_only));
+		}
 	}
 
 	/// Simply returns the stripped m.space.child events of a room
@@ -1,6 +1,6 @@
 #![cfg(test)]
 
-use std::str::FromStr;
+use std::{collections::VecDeque, str::FromStr};
 
 use ruma::{
 	api::federation::space::{SpaceHierarchyParentSummary, SpaceHierarchyParentSummaryInit},
@@ -9,7 +9,7 @@
 	UInt,
 };
 
-use crate::rooms::spaces::{get_parent_children_via, PaginationToken};
+use crate::rooms::spaces::{get_parent_children_via, next_room_to_traverse, PaginationToken};
 
 #[test]
 fn get_summary_children() {
@@ -140,3 +140,42 @@ fn pagination_token_to_string() {
 		"9,34_3_1_true"
 	);
 }
+
+#[test]
+fn traversal_resumes_from_stored_stack() {
+	// A session saved after the root's first child, which has children of its
+	// own; children are stored last first.
+	let mut stack = vec![
+		vec![],
+		vec![
+			(owned_room_id!("!c:example.org"), vec![]),
+			(owned_room_id!("!b:example.org"), vec![]),
+		],
+		vec![
+			(owned_room_id!("!a2:example.org"), vec![]),
+			(owned_room_id!("!a1:example.org"), vec![]),
+		],
+	];
+	let mut parents =
+		VecDeque::from([owned_room_id!("!root:example.org"), owned_room_id!("!a:example.org")]);
+
+	let mut next = || next_room_to_traverse(&mut stack, &mut parents).map(|(room_id, _)| room_id);
+
+	assert_eq!(next(), Some(owned_room_id!("!a1:example.org")));
+	assert_eq!(next(), Some(owned_room_id!("!a2:example.org")));
+	assert_eq!(next(), Some(owned_room_id!("!b:example.org")));
+	assert_eq!(next(), Some(owned_room_id!("!c:example.org")));
+	assert_eq!(next(), None);
+}
+
+#[test]
+fn traversal_drops_exhausted_parents() {
+	let mut stack = vec![vec![(owned_room_id!("!b:example.org"), vec![])], vec![]];
+	let mut parents =
+		VecDeque::from([owned_room_id!("!root:example.org"), owned_room_id!("!a:example.org")]);
+
+	let next = next_room_to_traverse(&mut stack, &mut parents);
+
+	assert_eq!(next.map(|(room_id, _)| room_id), Some(owned_room_id!("!b:example.org")));
+	assert_eq!(parents, [owned_room_id!("!root:example.org")]);
+}
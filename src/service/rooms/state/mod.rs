@@ -143,12 +143,7 @@ pub async fn force_state(
 						.await?;
 				},
 				| TimelineEventType::SpaceChild => {
-					self.services
-						.spaces
-						.roomid_spacehierarchy_cache
-						.lock()
-						.await
-						.remove(&pdu.room_id);
+					self.services.spaces.invalidate_room(&pdu.room_id).await;
 				},
 				| _ => continue,
 			}
@@ -358,6 +353,8 @@ pub fn set_room_state(
 		self.db
 			.roomid_shortstatehash
 			.raw_aput::<BUFSIZE, _, _>(room_id, shortstatehash);
+
+		self.services.state_accessor.invalidate_room_flags(room_id);
 	}
 
 	/// Returns the room's version.
@@ -414,6 +411,21 @@ pub async fn set_forward_extremities<'a, I>(
 		}
 	}
 
+	/// Forgets the room's current state and forward extremities, for rooms
+	/// whose PDUs are being purged. State snapshots are shared by hash and
+	/// remain.
+	pub async fn delete_room_state(&self, room_id: &RoomId, _state_lock: &RoomMutexGuard) {
+		self.db.roomid_shortstatehash.remove(room_id);
+
+		let prefix = (room_id, Interfix);
+		self.db
+			.roomid_pduleaves
+			.keys_prefix_raw(&prefix)
+			.ignore_err()
+			.ready_for_each(|key| self.db.roomid_pduleaves.remove(key))
+			.await;
+	}
+
 	/// This fetches auth events from the current state.
 	#[tracing::instrument(skip(self, content), level = "debug")]
 	pub async fn get_auth_events(
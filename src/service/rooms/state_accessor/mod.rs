@@ -1,6 +1,9 @@
+mod room_flags;
 mod room_state;
 mod server_can;
 mod state;
+mod state_at;
+mod summary;
 mod user_can;
 
 use std::{
@@ -14,7 +17,6 @@
 	Result,
 };
 use database::Map;
-use lru_cache::LruCache;
 use ruma::{
 	events::{
 		room::{
@@ -22,9 +24,7 @@
 			canonical_alias::RoomCanonicalAliasEventContent,
 			create::RoomCreateEventContent,
 			encryption::RoomEncryptionEventContent,
-			guest_access::{GuestAccess, RoomGuestAccessEventContent},
-			history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
-			join_rules::{AllowRule, JoinRule, RoomJoinRulesEventContent, RoomMembership},
+			join_rules::{AllowRule, JoinRule, RoomMembership},
 			member::RoomMemberEventContent,
 			name::RoomNameEventContent,
 			topic::RoomTopicEventContent,
@@ -37,11 +37,13 @@
 	OwnedUserId, RoomId, UserId,
 };
 
-use crate::{rooms, rooms::short::ShortStateHash, Dep};
+pub use self::{room_flags::RoomFlags, summary::RoomSummaryView};
+use crate::{rooms, rooms::short::ShortStateHash, Cache, Dep, LruCache};
 
 pub struct Service {
 	pub server_visibility_cache: Mutex<LruCache<(OwnedServerName, ShortStateHash), bool>>,
 	pub user_visibility_cache: Mutex<LruCache<(OwnedUserId, ShortStateHash), bool>>,
+	pub room_flags_cache: Mutex<LruCache<OwnedRoomId, (ShortStateHash, RoomFlags)>>,
 	services: Services,
 	db: Data,
 }
@@ -65,6 +67,8 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			f64::from(config.server_visibility_cache_capacity) * config.cache_capacity_modifier;
 		let user_visibility_cache_capacity =
 			f64::from(config.user_visibility_cache_capacity) * config.cache_capacity_modifier;
+		let room_flags_cache_capacity =
+			f64::from(config.room_flags_cache_capacity) * config.cache_capacity_modifier;
 
 		Ok(Arc::new(Self {
 			server_visibility_cache: StdMutex::new(LruCache::new(usize_from_f64(
@@ -73,6 +77,9 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			user_visibility_cache: StdMutex::new(LruCache::new(usize_from_f64(
 				user_visibility_cache_capacity,
 			)?)),
+			room_flags_cache: StdMutex::new(LruCache::new(usize_from_f64(
+				room_flags_cache_capacity,
+			)?)),
 			services: Services {
 				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
 				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
@@ -114,8 +121,21 @@ fn memory_usage(&self, out: &mut dyn Write) -> Result {
 			},
 		);
 
+		let (rfc_count, rfc_bytes) = self.room_flags_cache.lock()?.iter().fold(
+			(0_usize, 0_usize),
+			|(count, bytes), (key, val)| {
+				(
+					count.expected_add(1),
+					bytes
+						.expected_add(key.capacity())
+						.expected_add(size_of_val(val)),
+				)
+			},
+		);
+
 		writeln!(out, "server_visibility_cache: {svc_count} ({})", pretty(svc_bytes))?;
 		writeln!(out, "user_visibility_cache: {uvc_count} ({})", pretty(uvc_bytes))?;
+		writeln!(out, "room_flags_cache: {rfc_count} ({})", pretty(rfc_bytes))?;
 
 		Ok(())
 	}
@@ -123,6 +143,27 @@ fn memory_usage(&self, out: &mut dyn Write) -> Result {
 	fn clear_cache(&self) {
 		self.server_visibility_cache.lock().expect("locked").clear();
 		self.user_visibility_cache.lock().expect("locked").clear();
+		self.room_flags_cache.lock().expect("locked").clear();
+	}
+
+	fn caches(&self) -> Vec<Cache<'_>> {
+		vec![
+			Cache {
+				name: "server_visibility_cache",
+				base_capacity: |config| config.server_visibility_cache_capacity,
+				cache: &self.server_visibility_cache,
+			},
+			Cache {
+				name: "user_visibility_cache",
+				base_capacity: |config| config.user_visibility_cache_capacity,
+				cache: &self.user_visibility_cache,
+			},
+			Cache {
+				name: "room_flags_cache",
+				base_capacity: |config| config.room_flags_cache_capacity,
+				cache: &self.room_flags_cache,
+			},
+		]
 	}
 
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
@@ -155,20 +196,16 @@ pub async fn get_member(
 
 	/// Checks if guests are able to view room content without joining
 	pub async fn is_world_readable(&self, room_id: &RoomId) -> bool {
-		self.room_state_get_content(room_id, &StateEventType::RoomHistoryVisibility, "")
+		self.room_flags(room_id)
 			.await
-			.map(|c: RoomHistoryVisibilityEventContent| {
-				c.history_visibility == HistoryVisibility::WorldReadable
-			})
-			.unwrap_or(false)
+			.is_ok_and(|flags| flags.world_readable)
 	}
 
 	/// Checks if guests are able to join a given room
 	pub async fn guest_can_join(&self, room_id: &RoomId) -> bool {
-		self.room_state_get_content(room_id, &StateEventType::RoomGuestAccess, "")
+		self.room_flags(room_id)
 			.await
-			.map(|c: RoomGuestAccessEventContent| c.guest_access == GuestAccess::CanJoin)
-			.unwrap_or(false)
+			.is_ok_and(|flags| flags.guest_can_join)
 	}
 
 	/// Gets the primary alias from canonical alias event
@@ -193,11 +230,9 @@ pub async fn get_join_rule(
 		&self,
 		room_id: &RoomId,
 	) -> Result<(SpaceRoomJoinRule, Vec<OwnedRoomId>)> {
-		self.room_state_get_content(room_id, &StateEventType::RoomJoinRules, "")
+		self.room_flags(room_id)
 			.await
-			.map(|c: RoomJoinRulesEventContent| {
-				(c.join_rule.clone().into(), self.allowed_room_ids(c.join_rule))
-			})
+			.map(|flags| (flags.join_rule.clone().into(), self.allowed_room_ids(flags.join_rule)))
 			.or_else(|_| Ok((SpaceRoomJoinRule::Invite, vec![])))
 	}
 
@@ -236,8 +271,8 @@ pub async fn get_room_encryption(
 	}
 
 	pub async fn is_encrypted_room(&self, room_id: &RoomId) -> bool {
-		self.room_state_get(room_id, &StateEventType::RoomEncryption, "")
+		self.room_flags(room_id)
 			.await
-			.is_ok()
+			.is_ok_and(|flags| flags.encrypted)
 	}
 }
@@ -0,0 +1,108 @@
+use conduwuit::{implement, Result};
+use futures::future::join4;
+use ruma::{
+	events::{
+		room::{
+			guest_access::{GuestAccess, RoomGuestAccessEventContent},
+			history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
+			join_rules::{JoinRule, RoomJoinRulesEventContent},
+		},
+		StateEventType,
+	},
+	RoomId,
+};
+
+use crate::rooms::short::ShortStateHash;
+
+/// Flags derived from a room's state which are checked on hot paths like
+/// sync, push and federation.
+#[derive(Clone, Debug)]
+pub struct RoomFlags {
+	/// An `m.room.encryption` event is in the state.
+	pub encrypted: bool,
+
+	/// The history visibility is `world_readable`.
+	pub world_readable: bool,
+
+	/// The guest access is `can_join`.
+	pub guest_can_join: bool,
+
+	/// The join rule, `invite` if there is no `m.room.join_rules` event.
+	pub join_rule: JoinRule,
+}
+
+/// Flags of the room's current state.
+#[implement(super::Service)]
+pub async fn room_flags(&self, room_id: &RoomId) -> Result<RoomFlags> {
+	let shortstatehash = self.services.state.get_room_shortstatehash(room_id).await?;
+
+	Ok(self.room_flags_at(room_id, shortstatehash).await)
+}
+
+/// Flags of the room at the given state. The room's entry in the cache is
+/// only reused if it was computed for the same state.
+#[implement(super::Service)]
+pub async fn room_flags_at(&self, room_id: &RoomId, shortstatehash: ShortStateHash) -> RoomFlags {
+	if let Some((cached_shortstatehash, flags)) = self
+		.room_flags_cache
+		.lock()
+		.expect("locked")
+		.get_mut(room_id)
+	{
+		if *cached_shortstatehash == shortstatehash {
+			return flags.clone();
+		}
+	}
+
+	let flags = self.compute_room_flags(shortstatehash).await;
+	self.room_flags_cache
+		.lock()
+		.expect("locked")
+		.insert(room_id.to_owned(), (shortstatehash, flags.clone()));
+
+	flags
+}
+
+/// Drops the room's cached flags; called whenever the room's current state
+/// changes.
+#[implement(super::Service)]
+pub fn invalidate_room_flags(&self, room_id: &RoomId) {
+	self.room_flags_cache
+		.lock()
+		.expect("locked")
+		.remove(room_id);
+}
+
+#[implement(super::Service)]
+async fn compute_room_flags(&self, shortstatehash: ShortStateHash) -> RoomFlags {
+	let encrypted = self.state_get_shortid(shortstatehash, &StateEventType::RoomEncryption, "");
+
+	let history_visibility = self.state_get_content::<RoomHistoryVisibilityEventContent>(
+		shortstatehash,
+		&StateEventType::RoomHistoryVisibility,
+		"",
+	);
+
+	let guest_access = self.state_get_content::<RoomGuestAccessEventContent>(
+		shortstatehash,
+		&StateEventType::RoomGuestAccess,
+		"",
+	);
+
+	let join_rules = self.state_get_content::<RoomJoinRulesEventContent>(
+		shortstatehash,
+		&StateEventType::RoomJoinRules,
+		"",
+	);
+
+	let (encrypted, history_visibility, guest_access, join_rules) =
+		join4(encrypted, history_visibility, guest_access, join_rules).await;
+
+	RoomFlags {
+		encrypted: encrypted.is_ok(),
+		world_readable: history_visibility
+			.is_ok_and(|c| c.history_visibility == HistoryVisibility::WorldReadable),
+		guest_can_join: guest_access.is_ok_and(|c| c.guest_access == GuestAccess::CanJoin),
+		join_rule: join_rules.map_or(JoinRule::Invite, |c| c.join_rule),
+	}
+}
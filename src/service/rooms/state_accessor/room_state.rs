@@ -36,7 +36,9 @@ pub fn room_state_full<'a>(
 		.try_flatten_stream()
 }
 
-/// Returns the full room state pdus
+/// Returns the full room state pdus, ordered by short state key. Consumers
+/// should convert each pdu as it arrives rather than collecting them; large
+/// rooms have hundreds of thousands of state events.
 #[implement(super::Service)]
 #[tracing::instrument(skip(self), level = "debug")]
 pub fn room_state_full_pdus<'a>(
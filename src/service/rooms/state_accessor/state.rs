@@ -4,7 +4,7 @@
 	at, err, implement, pair_of,
 	utils::{
 		result::FlatOk,
-		stream::{BroadbandExt, IterStream, ReadyExt, TryExpect},
+		stream::{IterStream, ReadyExt, TryExpect, WidebandExt},
 	},
 	PduEvent, Result,
 };
@@ -217,6 +217,10 @@ pub fn state_full(
 		})
 }
 
+/// Streams every state PDU at the given state. The PDUs are fetched
+/// concurrently, at most `automatic_width()` at a time, and yielded in the
+/// order the state is stored: by short state key, not by event type and state
+/// key.
 #[implement(super::Service)]
 pub fn state_full_pdus(
 	&self,
@@ -231,7 +235,7 @@ pub fn state_full_pdus(
 		.short
 		.multi_get_eventid_from_short(short_ids)
 		.ready_filter_map(Result::ok)
-		.broad_filter_map(move |event_id: OwnedEventId| async move {
+		.wide_filter_map(move |event_id: OwnedEventId| async move {
 			self.services.timeline.get_pdu(&event_id).await.ok()
 		})
 }
@@ -293,7 +297,10 @@ pub fn state_full_shortids(
 }
 
 #[implement(super::Service)]
-async fn load_full_state(&self, shortstatehash: ShortStateHash) -> Result<Arc<CompressedState>> {
+pub(super) async fn load_full_state(
+	&self,
+	shortstatehash: ShortStateHash,
+) -> Result<Arc<CompressedState>> {
 	self.services
 		.state_compressor
 		.load_shortstatehash_info(shortstatehash)
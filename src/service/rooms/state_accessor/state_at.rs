@@ -0,0 +1,62 @@
+use conduwuit::{err, implement, pdu::PduCount, Err, Result};
+use futures::StreamExt;
+use ruma::{EventId, OwnedEventId, RoomId};
+
+use crate::rooms::short::ShortStateHash;
+
+/// Resolves `at`, either an event ID or a sync or pagination token, to the
+/// state of the room at that point in time. For an event this is the state it
+/// was sent in, like in `/context`. Also returns the event the point is
+/// defined by, whose visibility decides who may see the state.
+#[implement(super::Service)]
+pub async fn state_at(
+	&self,
+	room_id: &RoomId,
+	at: &str,
+) -> Result<(ShortStateHash, OwnedEventId)> {
+	if at.starts_with('$') {
+		let event_id = EventId::parse(at)
+			.map_err(|e| err!(Request(InvalidParam("Invalid event ID in `at`: {e}"))))?;
+
+		let pdu = self
+			.services
+			.timeline
+			.get_pdu(&event_id)
+			.await
+			.map_err(|_| err!(Request(NotFound("Event not found."))))?;
+
+		if pdu.room_id != room_id {
+			return Err!(Request(NotFound("Event not found in this room.")));
+		}
+
+		let shortstatehash = self
+			.pdu_shortstatehash(&event_id)
+			.await
+			.map_err(|_| err!(Request(NotFound("The state at this event is not known."))))?;
+
+		return Ok((shortstatehash, event_id));
+	}
+
+	let count: PduCount = at.parse().map_err(|_| {
+		err!(Request(InvalidParam("`at` must be an event ID or a sync or pagination token.")))
+	})?;
+
+	// The state the first event after the token was sent in is the state at the
+	// token; without one the token is at the present.
+	if let Some((_, pdu)) = self
+		.services
+		.timeline
+		.pdus(None, room_id, Some(count))
+		.next()
+		.await
+		.transpose()?
+	{
+		let shortstatehash = self.pdu_shortstatehash(&pdu.event_id).await?;
+		return Ok((shortstatehash, pdu.event_id));
+	}
+
+	let pdu = self.services.timeline.latest_pdu_in_room(room_id).await?;
+	let shortstatehash = self.services.state.get_room_shortstatehash(room_id).await?;
+
+	Ok((shortstatehash, pdu.event_id))
+}
@@ -0,0 +1,192 @@
+use conduwuit::{
+	implement,
+	utils::stream::{IterStream, WidebandExt},
+	PduEvent, Result,
+};
+use futures::{future::join, StreamExt};
+use ruma::{
+	events::{
+		room::{
+			avatar::RoomAvatarEventContent,
+			canonical_alias::RoomCanonicalAliasEventContent,
+			create::RoomCreateEventContent,
+			encryption::RoomEncryptionEventContent,
+			guest_access::{GuestAccess, RoomGuestAccessEventContent},
+			history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
+			join_rules::{JoinRule, RoomJoinRulesEventContent},
+			name::RoomNameEventContent,
+			topic::RoomTopicEventContent,
+		},
+		StateEventType,
+	},
+	room::RoomType,
+	EventEncryptionAlgorithm, OwnedEventId, OwnedMxcUri, OwnedRoomAliasId, RoomId,
+};
+
+use crate::rooms::state_compressor::{compress_state_event, parse_compressed_state_event};
+
+/// What the room summary, public room directory and space hierarchy show
+/// about a room, read from its current state.
+#[derive(Clone, Debug)]
+pub struct RoomSummaryView {
+	pub name: Option<String>,
+	pub avatar_url: Option<OwnedMxcUri>,
+	pub topic: Option<String>,
+	pub canonical_alias: Option<OwnedRoomAliasId>,
+
+	/// The join rule, `invite` if there is no `m.room.join_rules` event.
+	pub join_rule: JoinRule,
+
+	pub world_readable: bool,
+	pub guest_can_join: bool,
+	pub room_type: Option<RoomType>,
+	pub encryption: Option<EventEncryptionAlgorithm>,
+	pub joined_members: u64,
+	pub invited_members: u64,
+}
+
+impl Default for RoomSummaryView {
+	fn default() -> Self {
+		Self {
+			name: None,
+			avatar_url: None,
+			topic: None,
+			canonical_alias: None,
+			join_rule: JoinRule::Invite,
+			world_readable: false,
+			guest_can_join: false,
+			room_type: None,
+			encryption: None,
+			joined_members: 0,
+			invited_members: 0,
+		}
+	}
+}
+
+/// State events read for a `RoomSummaryView`; all have an empty state key.
+const SUMMARY_EVENT_TYPES: [StateEventType; 9] = [
+	StateEventType::RoomName,
+	StateEventType::RoomAvatar,
+	StateEventType::RoomTopic,
+	StateEventType::RoomCanonicalAlias,
+	StateEventType::RoomJoinRules,
+	StateEventType::RoomHistoryVisibility,
+	StateEventType::RoomGuestAccess,
+	StateEventType::RoomCreate,
+	StateEventType::RoomEncryption,
+];
+
+/// Gathers the room's summary from a single expansion of its current state,
+/// rather than resolving the state once per field.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn room_summary_view(&self, room_id: &RoomId) -> Result<RoomSummaryView> {
+	let shortstatehash = self.services.state.get_room_shortstatehash(room_id).await?;
+	let full_state = self.load_full_state(shortstatehash).await?;
+
+	let pdus = SUMMARY_EVENT_TYPES
+		.iter()
+		.stream()
+		.wide_filter_map(|event_type| {
+			let full_state = &full_state;
+			async move {
+				let shortstatekey = self
+					.services
+					.short
+					.get_shortstatekey(event_type, "")
+					.await
+					.ok()?;
+
+				let start = compress_state_event(shortstatekey, 0);
+				let end = compress_state_event(shortstatekey, u64::MAX);
+				let (_, shorteventid) = full_state
+					.range(start..=end)
+					.next()
+					.copied()
+					.map(parse_compressed_state_event)?;
+
+				let event_id: OwnedEventId = self
+					.services
+					.short
+					.get_eventid_from_short(shorteventid)
+					.await
+					.ok()?;
+
+				self.services.timeline.get_pdu(&event_id).await.ok()
+			}
+		})
+		.collect::<Vec<PduEvent>>();
+
+	let counts = join(
+		self.services.state_cache.room_joined_count(room_id),
+		self.services.state_cache.room_invited_count(room_id),
+	);
+
+	let (pdus, (joined_members, invited_members)) = join(pdus, counts).await;
+
+	let mut view = RoomSummaryView {
+		joined_members: joined_members.unwrap_or(0),
+		invited_members: invited_members.unwrap_or(0),
+		..RoomSummaryView::default()
+	};
+
+	for pdu in pdus {
+		match StateEventType::from(pdu.kind.to_string()) {
+			| StateEventType::RoomName => {
+				view.name = pdu.get_content().ok().map(|c: RoomNameEventContent| c.name);
+			},
+			| StateEventType::RoomAvatar => {
+				view.avatar_url = pdu
+					.get_content()
+					.ok()
+					.and_then(|c: RoomAvatarEventContent| c.url);
+			},
+			| StateEventType::RoomTopic => {
+				view.topic = pdu
+					.get_content()
+					.ok()
+					.map(|c: RoomTopicEventContent| c.topic);
+			},
+			| StateEventType::RoomCanonicalAlias => {
+				view.canonical_alias = pdu
+					.get_content()
+					.ok()
+					.and_then(|c: RoomCanonicalAliasEventContent| c.alias);
+			},
+			| StateEventType::RoomJoinRules => {
+				if let Ok(c) = pdu.get_content::<RoomJoinRulesEventContent>() {
+					view.join_rule = c.join_rule;
+				}
+			},
+			| StateEventType::RoomHistoryVisibility => {
+				view.world_readable =
+					pdu.get_content()
+						.is_ok_and(|c: RoomHistoryVisibilityEventContent| {
+							c.history_visibility == HistoryVisibility::WorldReadable
+						});
+			},
+			| StateEventType::RoomGuestAccess => {
+				view.guest_can_join =
+					pdu.get_content()
+						.is_ok_and(|c: RoomGuestAccessEventContent| {
+							c.guest_access == GuestAccess::CanJoin
+						});
+			},
+			| StateEventType::RoomCreate => {
+				view.room_type = pdu
+					.get_content()
+					.ok()
+					.and_then(|c: RoomCreateEventContent| c.room_type);
+			},
+			| StateEventType::RoomEncryption => {
+				view.encryption = pdu
+					.get_content()
+					.ok()
+					.map(|c: RoomEncryptionEventContent| c.algorithm);
+			},
+			| _ => {},
+		}
+	}
+
+	Ok(view)
+}
@@ -1,4 +1,15 @@
-use conduwuit::{error, implement, pdu::PduBuilder, Err, Error, Result};
+use std::collections::HashMap;
+
+use conduwuit::{
+	error, implement,
+	pdu::PduBuilder,
+	utils::{
+		stream::{BroadbandExt, ReadyExt},
+		IterStream,
+	},
+	Err, Error, Result,
+};
+use futures::{FutureExt, StreamExt};
 use ruma::{
 	events::{
 		room::{
@@ -8,10 +19,10 @@
 		},
 		StateEventType, TimelineEventType,
 	},
-	EventId, RoomId, UserId,
+	EventId, Int, OwnedUserId, RoomId, UserId,
 };
 
-use crate::rooms::state::RoomMutexGuard;
+use crate::rooms::{short::ShortStateHash, state::RoomMutexGuard, timeline::PdusIterItem};
 
 /// Checks if a given user can redact a given event
 ///
@@ -96,17 +107,92 @@ pub async fn user_can_see_event(
 		return true;
 	};
 
-	if let Some(visibility) = self
-		.user_visibility_cache
-		.lock()
-		.expect("locked")
-		.get_mut(&(user_id.to_owned(), shortstatehash))
-	{
-		return *visibility;
+	if let Some(visibility) = self.cached_user_visibility(user_id, shortstatehash) {
+		return visibility;
 	}
 
 	let currently_member = self.services.state_cache.is_joined(user_id, room_id).await;
 
+	self.user_visibility(user_id, shortstatehash, currently_member)
+		.await
+}
+
+/// Filters the events to those the user is allowed to see, as
+/// [`user_can_see_event`] would each of them. The user's membership is only
+/// resolved once, and the history visibility once for each state the events
+/// were sent in, which consecutive events mostly share.
+#[implement(super::Service)]
+#[tracing::instrument(skip_all, level = "trace")]
+pub async fn user_can_see_events(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+	events: Vec<PdusIterItem>,
+) -> Vec<PdusIterItem> {
+	let shortstatehashes: Vec<_> = events
+		.iter()
+		.stream()
+		.broad_then(|(_, pdu)| self.pdu_shortstatehash(&pdu.event_id).map(Result::ok))
+		.collect()
+		.await;
+
+	let mut visibility: HashMap<ShortStateHash, bool> = HashMap::new();
+	let mut currently_member = None;
+	for &shortstatehash in shortstatehashes.iter().flatten() {
+		if visibility.contains_key(&shortstatehash) {
+			continue;
+		}
+
+		if let Some(visible) = self.cached_user_visibility(user_id, shortstatehash) {
+			visibility.insert(shortstatehash, visible);
+			continue;
+		}
+
+		let currently_member = match currently_member {
+			| Some(currently_member) => currently_member,
+			| None => *currently_member
+				.insert(self.services.state_cache.is_joined(user_id, room_id).await),
+		};
+
+		let visible = self
+			.user_visibility(user_id, shortstatehash, currently_member)
+			.await;
+
+		visibility.insert(shortstatehash, visible);
+	}
+
+	events
+		.into_iter()
+		.zip(shortstatehashes)
+		.filter(|(_, shortstatehash)| {
+			shortstatehash.is_none_or(|shortstatehash| visibility[&shortstatehash])
+		})
+		.map(|(event, _)| event)
+		.collect()
+}
+
+#[implement(super::Service)]
+fn cached_user_visibility(
+	&self,
+	user_id: &UserId,
+	shortstatehash: ShortStateHash,
+) -> Option<bool> {
+	self.user_visibility_cache
+		.lock()
+		.expect("locked")
+		.get_mut(&(user_id.to_owned(), shortstatehash))
+		.copied()
+}
+
+/// Resolves whether the user may see events sent in the state, caching the
+/// result.
+#[implement(super::Service)]
+async fn user_visibility(
+	&self,
+	user_id: &UserId,
+	shortstatehash: ShortStateHash,
+	currently_member: bool,
+) -> bool {
 	let history_visibility = self
 		.state_get_content(shortstatehash, &StateEventType::RoomHistoryVisibility, "")
 		.await
@@ -163,6 +249,28 @@ pub async fn user_can_see_state_events(&self, user_id: &UserId, room_id: &RoomId
 	}
 }
 
+/// Whether a client without an access token is allowed to see an event, i.e.
+/// the room's history was world readable at that event.
+#[implement(super::Service)]
+pub async fn anonymous_can_see_event(&self, event_id: &EventId) -> bool {
+	let Ok(shortstatehash) = self.pdu_shortstatehash(event_id).await else {
+		return false;
+	};
+
+	self.state_get_content(shortstatehash, &StateEventType::RoomHistoryVisibility, "")
+		.await
+		.is_ok_and(|c: RoomHistoryVisibilityEventContent| {
+			c.history_visibility == HistoryVisibility::WorldReadable
+		})
+}
+
+/// Whether a client without an access token is allowed to see the current
+/// state of a room.
+#[implement(super::Service)]
+pub async fn anonymous_can_see_state_events(&self, room_id: &RoomId) -> bool {
+	self.is_world_readable(room_id).await
+}
+
 #[implement(super::Service)]
 pub async fn user_can_invite(
 	&self,
@@ -185,3 +293,47 @@ pub async fn user_can_invite(
 		.await
 		.is_ok()
 }
+
+/// Selects a local user to authorise a restricted join of a remote user into
+/// the room (`join_authorised_via_users_server`). The user must be joined and
+/// have the power to invite; the most powerful such user is preferred so the
+/// choice is stable across make_join and send_join.
+#[implement(super::Service)]
+pub async fn get_join_authorising_user(&self, room_id: &RoomId) -> Option<OwnedUserId> {
+	let power_levels = self.room_power_levels(room_id).await;
+
+	self.services
+		.state_cache
+		.local_users_in_room(room_id)
+		.map(|user_id| (power_levels.for_user(user_id), user_id))
+		.ready_filter(|(power, _)| *power >= power_levels.invite)
+		.ready_fold(None::<(Int, &UserId)>, |best, (power, user_id)| match best {
+			| Some((best_power, _)) if best_power >= power => best,
+			| _ => Some((power, user_id)),
+		})
+		.await
+		.map(|(_, user_id)| user_id.to_owned())
+}
+
+/// Checks whether a local user may authorise a restricted join into the room.
+#[implement(super::Service)]
+pub async fn user_can_authorise_join(&self, room_id: &RoomId, user_id: &UserId) -> bool {
+	if !self.services.state_cache.is_joined(user_id, room_id).await {
+		return false;
+	}
+
+	let power_levels = self.room_power_levels(room_id).await;
+	power_levels.for_user(user_id) >= power_levels.invite
+}
+
+#[implement(super::Service)]
+async fn room_power_levels(&self, room_id: &RoomId) -> RoomPowerLevels {
+	self.room_state_get_content::<RoomPowerLevelsEventContent>(
+		room_id,
+		&StateEventType::RoomPowerLevels,
+		"",
+	)
+	.await
+	.unwrap_or_default()
+	.into()
+}
@@ -38,6 +38,7 @@ pub struct Service {
 
 struct Services {
 	account_data: Dep<account_data::Service>,
+	auto_accept: Dep<rooms::auto_accept::Service>,
 	globals: Dep<globals::Service>,
 	state_accessor: Dep<rooms::state_accessor::Service>,
 	users: Dep<users::Service>,
@@ -70,6 +71,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			appservice_in_room_cache: RwLock::new(HashMap::new()),
 			services: Services {
 				account_data: args.depend::<account_data::Service>("account_data"),
+				auto_accept: args.depend::<rooms::auto_accept::Service>("rooms::auto_accept"),
 				globals: args.depend::<globals::Service>("globals"),
 				state_accessor: args
 					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
@@ -257,13 +259,29 @@ pub async fn update_membership(
 				self.mark_as_joined(user_id, room_id);
 			},
 			| MembershipState::Invite => {
-				// We want to know if the sender is ignored by the receiver
-				if self.services.users.user_is_ignored(sender, user_id).await {
-					return Ok(());
+				// Invites from users the receiver ignores are never shown to them;
+				// they are still queued so they get rejected.
+				if !self.services.users.user_is_ignored(sender, user_id).await {
+					self.mark_as_invited(user_id, room_id, last_state, invite_via.clone())
+						.await;
 				}
 
-				self.mark_as_invited(user_id, room_id, last_state, invite_via)
-					.await;
+				self.services.auto_accept.queue_invite(
+					user_id,
+					room_id,
+					sender,
+					invite_via.as_deref(),
+				);
+			},
+			| MembershipState::Knock => {
+				// When knocking over federation the resident server provides the stripped
+				// state; our own view of the room is likely empty, so don't replace it.
+				let knocked_state = match last_state {
+					| Some(state) if !state.is_empty() => Some(state),
+					| _ => self.knock_state(user_id, room_id).await.ok(),
+				};
+
+				self.mark_as_knocked(user_id, room_id, knocked_state);
 			},
 			| MembershipState::Leave | MembershipState::Ban => {
 				self.mark_as_left(user_id, room_id);
@@ -13,13 +13,12 @@
 };
 use database::Map;
 use futures::{Stream, StreamExt};
-use lru_cache::LruCache;
 use ruma::{EventId, RoomId};
 
 use crate::{
 	rooms,
 	rooms::short::{ShortEventId, ShortId, ShortStateHash, ShortStateKey},
-	Dep,
+	Cache, Dep, LruCache,
 };
 
 pub struct Service {
@@ -111,6 +110,14 @@ fn memory_usage(&self, out: &mut dyn Write) -> Result {
 
 	fn clear_cache(&self) { self.stateinfo_cache.lock().expect("locked").clear(); }
 
+	fn caches(&self) -> Vec<Cache<'_>> {
+		vec![Cache {
+			name: "stateinfo_cache",
+			base_capacity: |config| config.stateinfo_cache_capacity,
+			cache: &self.stateinfo_cache,
+		}]
+	}
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
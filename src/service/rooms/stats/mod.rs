@@ -0,0 +1,211 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use conduwuit::{
+	debug, debug_warn, implement,
+	utils::{millis_since_unix_epoch, stream::TryIgnore, ReadyExt},
+	PduBuilder, Result, Server,
+};
+use futures::StreamExt;
+use ruma::{
+	events::{StateEventType, TimelineEventType},
+	OwnedRoomId, RoomId,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::value::to_raw_value;
+use tokio::{
+	sync::Notify,
+	time::{interval, MissedTickBehavior},
+};
+
+use crate::{globals, rooms, Dep};
+
+pub struct Service {
+	interrupt: Notify,
+	services: Services,
+}
+
+struct Services {
+	server: Arc<Server>,
+	globals: Dep<globals::Service>,
+	state: Dep<rooms::state::Service>,
+	state_accessor: Dep<rooms::state_accessor::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	timeline: Dep<rooms::timeline::Service>,
+}
+
+/// Content of the `conduwuit.room.stats` state event. A room admin opts the
+/// room in by setting `enabled`; the statistics are filled in by the server
+/// user every `room_stats_interval` until it's unset again.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RoomStatsEventContent {
+	#[serde(default)]
+	pub enabled: bool,
+
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub member_count: Option<u64>,
+
+	/// Messages sent in the room over the last 24 hours.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub messages_per_day: Option<u64>,
+
+	/// When the statistics were computed, in milliseconds since the unix
+	/// epoch.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub updated_at: Option<u64>,
+}
+
+pub const STATS_EVENT_TYPE: &str = "conduwuit.room.stats";
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			interrupt: Notify::new(),
+			services: Services {
+				server: args.server.clone(),
+				globals: args.depend::<globals::Service>("globals"),
+				state: args.depend::<rooms::state::Service>("rooms::state"),
+				state_accessor: args
+					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
+			},
+		}))
+	}
+
+	#[tracing::instrument(skip_all, name = "room_stats", level = "debug")]
+	async fn worker(self: Arc<Self>) -> Result {
+		let period = self.services.server.config.room_stats_interval;
+		if period == 0 {
+			debug!("Disabling room statistics");
+			return Ok(());
+		}
+
+		let period = Duration::from_secs(period);
+		let mut i = interval(period);
+		i.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		i.reset_after(period);
+		loop {
+			tokio::select! {
+				() = self.interrupt.notified() => break,
+				_ = i.tick() => (),
+			}
+
+			self.publish_all().await;
+		}
+
+		Ok(())
+	}
+
+	fn interrupt(&self) { self.interrupt.notify_waiters(); }
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+#[implement(Service)]
+async fn publish_all(&self) {
+	let rooms: Vec<OwnedRoomId> = self
+		.services
+		.state_cache
+		.rooms_joined(&self.services.globals.server_user)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for room_id in rooms {
+		if let Err(e) = self.publish(&room_id).await {
+			debug_warn!(%room_id, "Failed to publish room statistics: {e}");
+		}
+	}
+}
+
+/// Updates the statistics of a room which opted in as the server user, which
+/// must be joined to it. Nothing is sent if they haven't changed.
+#[implement(Service)]
+pub async fn publish(&self, room_id: &RoomId) -> Result {
+	let server_user = &self.services.globals.server_user;
+	if !self
+		.services
+		.state_cache
+		.is_joined(server_user, room_id)
+		.await
+	{
+		return Ok(());
+	}
+
+	let event_type = StateEventType::from(STATS_EVENT_TYPE);
+	let Ok(event) = self
+		.services
+		.state_accessor
+		.room_state_get(room_id, &event_type, "")
+		.await
+	else {
+		return Ok(());
+	};
+
+	let current: RoomStatsEventContent = event.get_content()?;
+	if !current.enabled {
+		return Ok(());
+	}
+
+	let now = millis_since_unix_epoch();
+	let stats = RoomStatsEventContent {
+		enabled: true,
+		member_count: self
+			.services
+			.state_cache
+			.room_joined_count(room_id)
+			.await
+			.ok(),
+		messages_per_day: Some(
+			self.messages_since(room_id, now.saturating_sub(DAY_MILLIS))
+				.await,
+		),
+		updated_at: Some(now),
+	};
+
+	if stats.member_count == current.member_count
+		&& stats.messages_per_day == current.messages_per_day
+	{
+		return Ok(());
+	}
+
+	let state_lock = self.services.state.mutex.lock(room_id).await;
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type: TimelineEventType::from(STATS_EVENT_TYPE),
+				content: to_raw_value(&stats)?,
+				state_key: Some(String::new()),
+				..Default::default()
+			},
+			server_user,
+			room_id,
+			&state_lock,
+		)
+		.await?;
+
+	Ok(())
+}
+
+/// Messages sent into the room since `since` (milliseconds since the unix
+/// epoch), encrypted ones included.
+#[implement(Service)]
+async fn messages_since(&self, room_id: &RoomId, since: u64) -> u64 {
+	self.services
+		.timeline
+		.pdus_rev(None, room_id, None)
+		.ignore_err()
+		.ready_take_while(move |(_, pdu)| u64::from(pdu.origin_server_ts) >= since)
+		.ready_filter(|(_, pdu)| {
+			matches!(pdu.kind, TimelineEventType::RoomMessage | TimelineEventType::RoomEncrypted)
+		})
+		.count()
+		.await
+		.try_into()
+		.unwrap_or(u64::MAX)
+}
@@ -1,27 +1,50 @@
-use std::{borrow::Borrow, sync::Arc};
+use std::{
+	borrow::Borrow,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
+};
 
 use conduwuit::{
 	at, err,
 	result::{LogErr, NotFound},
 	utils,
-	utils::stream::TryReadyExt,
+	utils::{
+		stream::{TryIgnore, TryReadyExt},
+		ReadyExt,
+	},
 	Err, PduCount, PduEvent, Result,
 };
 use database::{Database, Deserialized, Json, KeyVal, Map};
-use futures::{future::select_ok, pin_mut, FutureExt, Stream, TryFutureExt, TryStreamExt};
+use futures::{
+	future::select_ok, pin_mut, FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt,
+};
 use ruma::{api::Direction, CanonicalJsonObject, EventId, OwnedUserId, RoomId, UserId};
+use serde::Deserialize;
 
-use super::{PduId, RawPduId};
+use super::{PduId, Position, RawPduId};
 use crate::{rooms, rooms::short::ShortRoomId, Dep};
 
 pub(super) struct Data {
 	eventid_outlierpdu: Arc<Map>,
 	eventid_pduid: Arc<Map>,
 	pduid_pdu: Arc<Map>,
+	roomdepthid_pduid: Arc<Map>,
+	roomsynctoken_shortstatehash: Arc<Map>,
+	threadid_userids: Arc<Map>,
+	tofrom_relation: Arc<Map>,
+	tokenids: Arc<Map>,
 	userroomid_highlightcount: Arc<Map>,
 	userroomid_notificationcount: Arc<Map>,
+	userroomid_unreadcount: Arc<Map>,
 	pub(super) db: Arc<Database>,
 	services: Services,
+
+	/// Rooms with a lesser shortroomid are completely in the topological
+	/// index. The others are paginated in stream order until the index is
+	/// built.
+	topologically_indexed_until: AtomicU64,
 }
 
 struct Services {
@@ -37,12 +60,19 @@ pub(super) fn new(args: &crate::Args<'_>) -> Self {
 			eventid_outlierpdu: db["eventid_outlierpdu"].clone(),
 			eventid_pduid: db["eventid_pduid"].clone(),
 			pduid_pdu: db["pduid_pdu"].clone(),
+			roomdepthid_pduid: db["roomdepthid_pduid"].clone(),
+			roomsynctoken_shortstatehash: db["roomsynctoken_shortstatehash"].clone(),
+			threadid_userids: db["threadid_userids"].clone(),
+			tofrom_relation: db["tofrom_relation"].clone(),
+			tokenids: db["tokenids"].clone(),
 			userroomid_highlightcount: db["userroomid_highlightcount"].clone(),
 			userroomid_notificationcount: db["userroomid_notificationcount"].clone(),
+			userroomid_unreadcount: db["userroomid_unreadcount"].clone(),
 			db: args.db.clone(),
 			services: Services {
 				short: args.depend::<rooms::short::Service>("rooms::short"),
 			},
+			topologically_indexed_until: AtomicU64::new(ShortRoomId::MAX),
 		}
 	}
 
@@ -190,17 +220,21 @@ pub(super) async fn append_pdu(
 		self.pduid_pdu.raw_put(pdu_id, Json(json));
 		self.eventid_pduid.insert(pdu.event_id.as_bytes(), pdu_id);
 		self.eventid_outlierpdu.remove(pdu.event_id.as_bytes());
+		self.roomdepthid_pduid
+			.insert(&topological_key(pdu_id, pdu.depth.into()), pdu_id);
 	}
 
 	pub(super) fn prepend_backfill_pdu(
 		&self,
 		pdu_id: &RawPduId,
-		event_id: &EventId,
+		pdu: &PduEvent,
 		json: &CanonicalJsonObject,
 	) {
 		self.pduid_pdu.raw_put(pdu_id, Json(json));
-		self.eventid_pduid.insert(event_id, pdu_id);
-		self.eventid_outlierpdu.remove(event_id);
+		self.eventid_pduid.insert(pdu.event_id.as_bytes(), pdu_id);
+		self.eventid_outlierpdu.remove(pdu.event_id.as_bytes());
+		self.roomdepthid_pduid
+			.insert(&topological_key(pdu_id, pdu.depth.into()), pdu_id);
 	}
 
 	/// Removes a pdu and creates a new one with the same id.
@@ -256,6 +290,268 @@ pub(super) fn pdus<'a>(
 			.try_flatten_stream()
 	}
 
+	/// Every PDU stored for a room, backfilled ones included, in storage
+	/// order.
+	pub(super) fn all_pdus<'a>(
+		&'a self,
+		shortroomid: &'a [u8; size_of::<ShortRoomId>()],
+	) -> impl Stream<Item = Result<(RawPduId, PduEvent)>> + Send + 'a {
+		self.pduid_pdu
+			.raw_stream_prefix(shortroomid)
+			.ready_and_then(|(pdu_id, pdu)| Ok((pdu_id.into(), serde_json::from_slice(pdu)?)))
+	}
+
+	/// Removes a PDU along with the relations to it.
+	pub(super) async fn remove_pdu(&self, pdu_id: &RawPduId, pdu: &PduEvent) {
+		self.pduid_pdu.remove(pdu_id);
+		self.eventid_pduid.remove(pdu.event_id.as_bytes());
+		self.roomdepthid_pduid
+			.remove(&topological_key(pdu_id, pdu.depth.into()));
+
+		let target = pdu_id.pdu_count().into_unsigned().to_be_bytes();
+		self.tofrom_relation
+			.raw_keys_prefix(&target)
+			.ignore_err()
+			.ready_for_each(|key| self.tofrom_relation.remove(key))
+			.await;
+	}
+
+	/// Removes the room's search index, threads and the state recorded at its
+	/// sync tokens, for rooms whose PDUs are purged.
+	pub(super) async fn remove_room_indexes(&self, shortroomid: ShortRoomId) {
+		let prefix = shortroomid.to_be_bytes();
+		for map in [&self.tokenids, &self.threadid_userids, &self.roomsynctoken_shortstatehash] {
+			map.raw_keys_prefix(&prefix)
+				.ignore_err()
+				.ready_for_each(|key| map.remove(key))
+				.await;
+		}
+	}
+
+	/// Iterates over the room's events in topological order (by depth) in the
+	/// direction, starting after the position `from`. Rooms not yet in the
+	/// index are iterated in stream order from the stream count of `from`.
+	pub(super) fn pdus_topological<'a>(
+		&'a self,
+		user_id: Option<&'a UserId>,
+		room_id: &'a RoomId,
+		from: Position,
+		dir: Direction,
+	) -> impl Stream<Item = Result<PdusIterItem>> + Send + 'a {
+		self.is_topologically_indexed(room_id)
+			.map(move |indexed| match (indexed, dir) {
+				| (true, _) => self
+					.pdus_topological_indexed(user_id, room_id, from, dir)
+					.boxed(),
+				| (false, Direction::Forward) =>
+					self.pdus(user_id, room_id, from.count()).boxed(),
+				| (false, Direction::Backward) =>
+					self.pdus_rev(user_id, room_id, from.count()).boxed(),
+			})
+			.flatten_stream()
+	}
+
+	fn pdus_topological_indexed<'a>(
+		&'a self,
+		user_id: Option<&'a UserId>,
+		room_id: &'a RoomId,
+		from: Position,
+		dir: Direction,
+	) -> impl Stream<Item = Result<PdusIterItem>> + Send + 'a {
+		self.topological_start(room_id, from, dir)
+			.map_ok(move |start| {
+				let prefix: [u8; size_of::<ShortRoomId>()] = start[..size_of::<ShortRoomId>()]
+					.try_into()
+					.expect("topological key starts with the shortroomid");
+
+				let pdu_ids = match dir {
+					| Direction::Forward =>
+						self.roomdepthid_pduid.raw_stream_from(&start).boxed(),
+					| Direction::Backward =>
+						self.roomdepthid_pduid.rev_raw_stream_from(&start).boxed(),
+				};
+
+				pdu_ids
+					.ready_try_take_while(move |(key, _)| Ok(key.starts_with(&prefix)))
+					.map_ok(|(_, pdu_id)| RawPduId::from(pdu_id))
+					.and_then(move |pdu_id| async move {
+						let pdu = self.pduid_pdu.get(&pdu_id).await?;
+						Self::each_pdu((pdu_id.as_bytes(), &pdu), user_id)
+					})
+			})
+			.try_flatten_stream()
+	}
+
+	/// Counts the room's events before `until` in topological order, stopping
+	/// at `limit`.
+	pub(super) async fn count_pdus_topological_rev(
+		&self,
+		room_id: &RoomId,
+		until: Position,
+		limit: usize,
+	) -> Result<usize> {
+		if !self.is_topologically_indexed(room_id).await {
+			let count = self
+				.pdus_rev(None, room_id, until.count())
+				.ignore_err()
+				.take(limit)
+				.count()
+				.await;
+
+			return Ok(count);
+		}
+
+		let start = self
+			.topological_start(room_id, until, Direction::Backward)
+			.await?;
+
+		let prefix: [u8; size_of::<ShortRoomId>()] = start[..size_of::<ShortRoomId>()]
+			.try_into()
+			.expect("topological key starts with the shortroomid");
+
+		let count = self
+			.roomdepthid_pduid
+			.rev_raw_keys_from(&start)
+			.ignore_err()
+			.ready_take_while(|key| key.starts_with(&prefix))
+			.take(limit)
+			.count()
+			.await;
+
+		Ok(count)
+	}
+
+	/// Returns the key in the topological index iteration in the direction
+	/// starts from for a pagination token. A topological position names the
+	/// key itself; for a stream position, iteration starts after the event at
+	/// it, or when there's none, at the nearest one in stream order.
+	async fn topological_start(
+		&self,
+		room_id: &RoomId,
+		from: Position,
+		dir: Direction,
+	) -> Result<Vec<u8>> {
+		let shortroomid: ShortRoomId = self
+			.services
+			.short
+			.get_shortroomid(room_id)
+			.await
+			.map_err(|e| err!(Request(NotFound("Room {room_id:?} not found: {e:?}"))))?;
+
+		let from = match from {
+			| Position::Topological { depth, count } => {
+				let next: RawPduId = PduId {
+					shortroomid,
+					shorteventid: count.saturating_inc(dir),
+				}
+				.into();
+
+				return Ok(topological_key(&next, depth));
+			},
+			| Position::Stream(count) => count,
+		};
+
+		let pdu_id: RawPduId = PduId { shortroomid, shorteventid: from }.into();
+		if let Ok(pdu) = self.get_pdu_from_id(&pdu_id).await {
+			let next: RawPduId = PduId {
+				shortroomid,
+				shorteventid: from.saturating_inc(dir),
+			}
+			.into();
+
+			return Ok(topological_key(&next, pdu.depth.into()));
+		}
+
+		let nearest = match dir {
+			| Direction::Forward => self.pdus(None, room_id, from).boxed(),
+			| Direction::Backward => self.pdus_rev(None, room_id, from).boxed(),
+		}
+		.try_next()
+		.await?;
+
+		if let Some((count, pdu)) = nearest {
+			let pdu_id: RawPduId = PduId { shortroomid, shorteventid: count }.into();
+			return Ok(topological_key(&pdu_id, pdu.depth.into()));
+		}
+
+		// Nothing left in this direction; start past the end of the room
+		Ok(match dir {
+			| Direction::Forward => {
+				let last: RawPduId = PduId {
+					shortroomid,
+					shorteventid: PduCount::max(),
+				}
+				.into();
+
+				topological_key(&last, u64::MAX)
+			},
+			| Direction::Backward => shortroomid.to_be_bytes().to_vec(),
+		})
+	}
+
+	/// Adds up to `limit` stored PDUs, from the one at `from` on, to the
+	/// topological index. Returns the key to continue from, or `None` once
+	/// every PDU is indexed.
+	pub(super) async fn index_topologically(&self, from: &[u8], limit: usize) -> Option<Vec<u8>> {
+		#[derive(Deserialize)]
+		struct ExtractDepth {
+			depth: u64,
+		}
+
+		let (indexed, last) = self
+			.pduid_pdu
+			.raw_stream_from(from)
+			.ignore_err()
+			.take(limit)
+			.ready_fold((0_usize, None), |(indexed, _), (pdu_id, pdu)| {
+				if let Ok(ExtractDepth { depth }) = serde_json::from_slice(pdu) {
+					let pdu_id: RawPduId = pdu_id.into();
+					self.roomdepthid_pduid
+						.insert(&topological_key(&pdu_id, depth), pdu_id);
+				}
+
+				(indexed.saturating_add(1), Some(pdu_id.to_vec()))
+			})
+			.await;
+
+		// The least key after the last one indexed
+		let next = last.filter(|_| indexed >= limit).map(|mut key| {
+			key.push(0);
+			key
+		});
+
+		self.set_topologically_indexed(next.as_deref());
+		next
+	}
+
+	/// Records how far the topological index was built, as the key indexing
+	/// continues from, or `None` if it's complete.
+	pub(super) fn set_topologically_indexed(&self, next: Option<&[u8]>) {
+		let until = next.map_or(ShortRoomId::MAX, |key| {
+			key.get(..size_of::<ShortRoomId>())
+				.and_then(|shortroomid| shortroomid.try_into().ok())
+				.map_or(0, ShortRoomId::from_be_bytes)
+		});
+
+		self.topologically_indexed_until
+			.store(until, Ordering::Relaxed);
+	}
+
+	async fn is_topologically_indexed(&self, room_id: &RoomId) -> bool {
+		let until = self.topologically_indexed_until.load(Ordering::Relaxed);
+		if until == ShortRoomId::MAX {
+			return true;
+		}
+
+		// Unknown rooms are left to the index, which reports them not found
+		self.services
+			.short
+			.get_shortroomid(room_id)
+			.await
+			.ok()
+			.is_none_or(|shortroomid| shortroomid < until)
+	}
+
 	fn each_pdu((pdu_id, pdu): KeyVal<'_>, user_id: Option<&UserId>) -> Result<PdusIterItem> {
 		let pdu_id: RawPduId = pdu_id.into();
 
@@ -275,6 +571,7 @@ pub(super) fn increment_notification_counts(
 		room_id: &RoomId,
 		notifies: Vec<OwnedUserId>,
 		highlights: Vec<OwnedUserId>,
+		unreads: Vec<OwnedUserId>,
 	) {
 		let _cork = self.db.cork();
 
@@ -291,6 +588,13 @@ pub(super) fn increment_notification_counts(
 			userroom_id.extend_from_slice(room_id.as_bytes());
 			increment(&self.userroomid_highlightcount, &userroom_id);
 		}
+
+		for user in unreads {
+			let mut userroom_id = user.as_bytes().to_vec();
+			userroom_id.push(0xFF);
+			userroom_id.extend_from_slice(room_id.as_bytes());
+			increment(&self.userroomid_unreadcount, &userroom_id);
+		}
 	}
 
 	async fn count_to_id(
@@ -316,6 +620,18 @@ async fn count_to_id(
 	}
 }
 
+/// Key of a PDU in the topological index: the room, the PDU's depth, then the
+/// rest of its id ordering PDUs of the same depth as they were stored.
+fn topological_key(pdu_id: &RawPduId, depth: u64) -> Vec<u8> {
+	let (shortroomid, shorteventid) = pdu_id.as_bytes().split_at(size_of::<ShortRoomId>());
+
+	let mut key = Vec::with_capacity(pdu_id.as_bytes().len().saturating_add(size_of::<u64>()));
+	key.extend_from_slice(shortroomid);
+	key.extend_from_slice(&depth.to_be_bytes());
+	key.extend_from_slice(shorteventid);
+	key
+}
+
 //TODO: this is an ABA
 fn increment(db: &Arc<Map>, key: &[u8]) {
 	let old = db.get_blocking(key);
@@ -1,12 +1,13 @@
 mod data;
+mod position;
 
 use std::{
 	borrow::Borrow,
 	cmp,
-	collections::{BTreeMap, HashSet},
+	collections::{BTreeMap, HashMap, HashSet},
 	fmt::Write,
 	iter::once,
-	sync::Arc,
+	sync::{Arc, Mutex as StdMutex},
 };
 
 use conduwuit::{
@@ -22,10 +23,9 @@
 	future, future::ready, pin_mut, Future, FutureExt, Stream, StreamExt, TryStreamExt,
 };
 use ruma::{
-	api::federation,
+	api::{federation, Direction},
 	canonical_json::to_canonical_value,
 	events::{
-		push_rules::PushRulesEvent,
 		room::{
 			create::RoomCreateEventContent,
 			encrypted::Relation,
@@ -33,9 +33,9 @@
 			power_levels::RoomPowerLevelsEventContent,
 			redaction::RoomRedactionEventContent,
 		},
-		GlobalAccountDataEventType, StateEventType, TimelineEventType,
+		StateEventType, TimelineEventType,
 	},
-	push::{Action, Ruleset, Tweak},
+	push::{Action, Tweak},
 	state_res::{self, Event, RoomVersion},
 	uint, CanonicalJsonObject, CanonicalJsonValue, EventId, OwnedEventId, OwnedRoomId,
 	OwnedServerName, OwnedUserId, RoomId, RoomVersionId, ServerName, UserId,
@@ -44,12 +44,16 @@
 use serde_json::value::{to_raw_value, RawValue as RawJsonValue};
 
 use self::data::Data;
-pub use self::data::PdusIterItem;
+pub use self::{data::PdusIterItem, position::Position};
 use crate::{
-	account_data, admin, appservice,
+	admin, appservice,
 	appservice::NamespaceRegex,
-	globals, pusher, rooms,
-	rooms::{short::ShortRoomId, state_compressor::CompressedState},
+	globals, media, moderation, pusher, rooms,
+	rooms::{
+		short::ShortRoomId,
+		state_compressor::CompressedState,
+		user::{Notification, UnreadEvent},
+	},
 	sending, server_keys, users, Dep,
 };
 
@@ -79,11 +83,17 @@ pub struct Service {
 	services: Services,
 	db: Data,
 	pub mutex_insert: RoomMutexMap,
+	backfills: StdMutex<HashMap<OwnedRoomId, usize>>,
+}
+
+/// Holds one of a room's concurrent backfill slots until dropped.
+struct BackfillGuard<'a> {
+	service: &'a Service,
+	room_id: OwnedRoomId,
 }
 
 struct Services {
 	server: Arc<Server>,
-	account_data: Dep<account_data::Service>,
 	appservice: Dep<appservice::Service>,
 	admin: Dep<admin::Service>,
 	alias: Dep<rooms::alias::Service>,
@@ -103,6 +113,10 @@ struct Services {
 	search: Dep<rooms::search::Service>,
 	spaces: Dep<rooms::spaces::Service>,
 	event_handler: Dep<rooms::event_handler::Service>,
+	partial_state: Dep<rooms::partial_state::Service>,
+	metadata: Dep<rooms::metadata::Service>,
+	moderation: Dep<moderation::Service>,
+	media: Dep<media::Service>,
 }
 
 type RoomMutexMap = MutexMap<OwnedRoomId, ()>;
@@ -113,7 +127,6 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			services: Services {
 				server: args.server.clone(),
-				account_data: args.depend::<account_data::Service>("account_data"),
 				appservice: args.depend::<appservice::Service>("appservice"),
 				admin: args.depend::<admin::Service>("admin"),
 				alias: args.depend::<rooms::alias::Service>("rooms::alias"),
@@ -135,9 +148,15 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				spaces: args.depend::<rooms::spaces::Service>("rooms::spaces"),
 				event_handler: args
 					.depend::<rooms::event_handler::Service>("rooms::event_handler"),
+				partial_state: args
+					.depend::<rooms::partial_state::Service>("rooms::partial_state"),
+				metadata: args.depend::<rooms::metadata::Service>("rooms::metadata"),
+				moderation: args.depend::<moderation::Service>("moderation"),
+				media: args.depend::<media::Service>("media"),
 			},
 			db: Data::new(&args),
 			mutex_insert: RoomMutexMap::new(),
+			backfills: StdMutex::new(HashMap::new()),
 		}))
 	}
 
@@ -351,7 +370,8 @@ pub async fn append_pdu<'a, Leafs>(
 			.private_read_set(&pdu.room_id, &pdu.sender, count1);
 		self.services
 			.user
-			.reset_notification_counts(&pdu.sender, &pdu.room_id);
+			.reset_notification_counts(&pdu.sender, &pdu.room_id)
+			.await;
 
 		let count2 = PduCount::Normal(self.services.globals.next_count().unwrap());
 		let pdu_id: RawPduId = PduId { shortroomid, shorteventid: count2 }.into();
@@ -361,6 +381,8 @@ pub async fn append_pdu<'a, Leafs>(
 
 		drop(insert_lock);
 
+		self.services.media.index_references(pdu);
+
 		// See if the event matches any known pushers
 		let power_levels: RoomPowerLevelsEventContent = self
 			.services
@@ -384,6 +406,31 @@ pub async fn append_pdu<'a, Leafs>(
 
 		let mut notifies = Vec::with_capacity(push_target.len().saturating_add(1));
 		let mut highlights = Vec::with_capacity(push_target.len().saturating_add(1));
+		let mut unreads = Vec::with_capacity(push_target.len().saturating_add(1));
+
+		// Threaded receipts only clear the notifications of their own thread
+		let relation = pdu
+			.get_content::<ExtractRelatesTo>()
+			.ok()
+			.map(|content| content.relates_to);
+
+		let thread = match &relation {
+			| Some(Relation::Thread(thread)) => Some(thread.event_id.clone()),
+			| _ => None,
+		};
+
+		// Messages count as unread (MSC2654) unless they're edits of an earlier
+		// one; state and other events never do.
+		let countable = !matches!(relation, Some(Relation::Replacement(_)))
+			&& matches!(
+				pdu.kind,
+				TimelineEventType::RoomMessage
+					| TimelineEventType::RoomEncrypted
+					| TimelineEventType::Sticker
+					| TimelineEventType::CallInvite
+					| TimelineEventType::PollStart
+					| TimelineEventType::UnstablePollStart
+			);
 
 		if pdu.kind == TimelineEventType::RoomMember {
 			if let Some(state_key) = &pdu.state_key {
@@ -396,25 +443,23 @@ pub async fn append_pdu<'a, Leafs>(
 		}
 
 		for user in &push_target {
-			let rules_for_user = self
-				.services
-				.account_data
-				.get_global(user, GlobalAccountDataEventType::PushRules)
-				.await
-				.map_or_else(
-					|_| Ruleset::server_default(user),
-					|ev: PushRulesEvent| ev.content.global,
-				);
+			let mut rules_for_user = self.services.pusher.get_ruleset(user).await;
 
-			let mut highlight = false;
-			let mut notify = false;
+			self.services
+				.pusher
+				.apply_room_default(user, &pdu.room_id, &mut rules_for_user)
+				.await;
 
-			for action in self
+			let actions = self
 				.services
 				.pusher
 				.get_actions(user, &rules_for_user, &power_levels, &sync_pdu, &pdu.room_id)
-				.await
-			{
+				.await;
+
+			let mut highlight = false;
+			let mut notify = false;
+
+			for action in actions {
 				match action {
 					| Action::Notify => notify = true,
 					| Action::SetTweak(Tweak::Highlight(true)) => {
@@ -431,12 +476,47 @@ pub async fn append_pdu<'a, Leafs>(
 
 			if notify {
 				notifies.push(user.clone());
+
+				let notification = Notification {
+					room_id: pdu.room_id.clone(),
+					event_id: pdu.event_id.clone(),
+					actions: actions.to_vec(),
+					ts: pdu.origin_server_ts.into(),
+					received: utils::millis_since_unix_epoch(),
+				};
+
+				self.services
+					.user
+					.log_notification(user, count2.into_unsigned(), &notification);
 			}
 
 			if highlight {
 				highlights.push(user.clone());
 			}
 
+			// Events matching a rule which doesn't suppress them (i.e. has any
+			// actions) count as unread even without notifying.
+			let unread = countable && !actions.is_empty();
+			if unread {
+				unreads.push(user.clone());
+			}
+
+			if notify || highlight || unread {
+				let event = UnreadEvent {
+					notify,
+					highlight,
+					unread,
+					thread: thread.clone(),
+				};
+
+				self.services.user.add_unread_event(
+					user,
+					&pdu.room_id,
+					count2.into_unsigned(),
+					&event,
+				);
+			}
+
 			self.services
 				.pusher
 				.get_pushkeys(user)
@@ -450,7 +530,7 @@ pub async fn append_pdu<'a, Leafs>(
 		}
 
 		self.db
-			.increment_notification_counts(&pdu.room_id, notifies, highlights);
+			.increment_notification_counts(&pdu.room_id, notifies, highlights, unreads);
 
 		match pdu.kind {
 			| TimelineEventType::RoomRedaction => {
@@ -487,12 +567,7 @@ pub async fn append_pdu<'a, Leafs>(
 			},
 			| TimelineEventType::SpaceChild =>
 				if let Some(_state_key) = &pdu.state_key {
-					self.services
-						.spaces
-						.roomid_spacehierarchy_cache
-						.lock()
-						.await
-						.remove(&pdu.room_id);
+					self.services.spaces.invalidate_room(&pdu.room_id).await;
 				},
 			| TimelineEventType::RoomMember => {
 				if let Some(state_key) = &pdu.state_key {
@@ -529,14 +604,16 @@ pub async fn append_pdu<'a, Leafs>(
 				if let Some(body) = content.body {
 					self.services.search.index_pdu(shortroomid, &pdu_id, &body);
 
-					if self.services.admin.is_admin_command(pdu, &body).await {
+					if let Some(tier) = self.services.admin.command_tier(pdu, &body).await {
 						self.services
 							.admin
-							.command(body, Some((*pdu.event_id).into()))?;
+							.command(body, Some((*pdu.event_id).into()), tier)?;
 					}
 				}
 			},
-			| _ => {},
+			| _ => {
+				self.services.moderation.update_rule(pdu).await;
+			},
 		}
 
 		if let Ok(content) = pdu.get_content::<ExtractRelatesToEventId>() {
@@ -918,6 +995,15 @@ pub async fn build_and_append_pdu(
 			.collect()
 			.await;
 
+		// Our member list is incomplete while the room has partial state, so also
+		// include the servers the resident server told us about when we joined
+		servers.extend(
+			self.services
+				.partial_state
+				.servers_in_room(&pdu.room_id)
+				.await,
+		);
+
 		// In case we are kicking or banning a user, we need to inform their server of
 		// the change
 		if pdu.kind == TimelineEventType::RoomMember {
@@ -1020,6 +1106,57 @@ pub fn pdus<'a>(
 			.pdus(user_id, room_id, from.unwrap_or_else(PduCount::min))
 	}
 
+	/// Iteration in topological order (by depth) in the direction, from after
+	/// the position `from`. Used for pagination.
+	#[tracing::instrument(skip(self), level = "debug")]
+	pub fn pdus_topological<'a>(
+		&'a self,
+		user_id: Option<&'a UserId>,
+		room_id: &'a RoomId,
+		from: Position,
+		dir: Direction,
+	) -> impl Stream<Item = Result<PdusIterItem>> + Send + 'a {
+		self.db.pdus_topological(user_id, room_id, from, dir)
+	}
+
+	/// Adds up to `limit` stored PDUs, from the one at `from` on, to the
+	/// topological index. Returns the key to continue from, or `None` once
+	/// every PDU is indexed. Rooms are paginated in stream order until all of
+	/// their PDUs are indexed.
+	pub(crate) async fn index_topologically(&self, from: &[u8], limit: usize) -> Option<Vec<u8>> {
+		self.db.index_topologically(from, limit).await
+	}
+
+	/// Resumes from how far the topological index was built before a restart:
+	/// the key indexing continues from, or `None` if it's complete.
+	pub(crate) fn set_topologically_indexed(&self, next: Option<&[u8]>) {
+		self.db.set_topologically_indexed(next);
+	}
+
+	/// Permanently removes every PDU of a room along with their relations,
+	/// the room's search index, threads and the state at its sync tokens. The
+	/// room's state is left alone. Returns how many PDUs were removed.
+	#[tracing::instrument(skip(self), level = "debug")]
+	pub async fn purge_room(&self, room_id: &RoomId) -> Result<usize> {
+		let shortroomid = self.services.short.get_shortroomid(room_id).await?;
+		let prefix = shortroomid.to_be_bytes();
+
+		let _cork = self.db.db.cork();
+		let purged = self
+			.db
+			.all_pdus(&prefix)
+			.ignore_err()
+			.fold(0_usize, |purged, (pdu_id, pdu)| async move {
+				self.db.remove_pdu(&pdu_id, &pdu).await;
+				purged.saturating_add(1)
+			})
+			.await;
+
+		self.db.remove_room_indexes(shortroomid).await;
+
+		Ok(purged)
+	}
+
 	/// Replace a PDU with the redacted form.
 	#[tracing::instrument(name = "redact", level = "debug", skip(self))]
 	pub async fn redact_pdu(
@@ -1058,7 +1195,12 @@ pub async fn redact_pdu(
 	}
 
 	#[tracing::instrument(name = "backfill", level = "debug", skip(self))]
-	pub async fn backfill_if_required(&self, room_id: &RoomId, from: PduCount) -> Result<()> {
+	pub async fn backfill_if_required(&self, room_id: &RoomId, from: Position) -> Result<()> {
+		if self.services.metadata.is_tombstoned(room_id).await {
+			// Purged for having no local members; its history isn't fetched again
+			return Ok(());
+		}
+
 		if self
 			.services
 			.state_cache
@@ -1075,16 +1217,31 @@ pub async fn backfill_if_required(&self, room_id: &RoomId, from: PduCount) -> Re
 			return Ok(());
 		}
 
+		let threshold = self.services.server.config.backfill_threshold.max(1);
+		if self
+			.db
+			.count_pdus_topological_rev(room_id, from, threshold)
+			.await? >= threshold
+		{
+			// No backfill required, there are still enough events before
+			return Ok(());
+		}
+
 		let first_pdu = self
 			.first_item_in_room(room_id)
 			.await
 			.expect("Room is not empty");
 
-		if first_pdu.0 < from {
-			// No backfill required, there are still events between them
+		if first_pdu.1.kind == TimelineEventType::RoomCreate {
+			// The room's history is complete
 			return Ok(());
 		}
 
+		let Some(_backfill) = self.start_backfill(room_id) else {
+			debug!("Backfill limit reached in room {room_id}, paginating local history");
+			return Ok(());
+		};
+
 		let power_levels: RoomPowerLevelsEventContent = self
 			.services
 			.state_accessor
@@ -1168,6 +1325,28 @@ pub async fn backfill_if_required(&self, room_id: &RoomId, from: PduCount) -> Re
 		Ok(())
 	}
 
+	/// Registers a backfill in progress for the room, or None when it already
+	/// has `backfill_concurrency` running.
+	fn start_backfill(&self, room_id: &RoomId) -> Option<BackfillGuard<'_>> {
+		let max = self.services.server.config.backfill_concurrency;
+		if max == 0 {
+			return None;
+		}
+
+		let mut backfills = self.backfills.lock().expect("locked");
+		let active = backfills.entry(room_id.to_owned()).or_default();
+		if *active >= max {
+			return None;
+		}
+
+		*active = active.saturating_add(1);
+
+		Some(BackfillGuard {
+			service: self,
+			room_id: room_id.to_owned(),
+		})
+	}
+
 	#[tracing::instrument(skip(self, pdu), level = "debug")]
 	pub async fn backfill_pdu(&self, origin: &ServerName, pdu: Box<RawJsonValue>) -> Result<()> {
 		let (room_id, event_id, value) =
@@ -1210,7 +1389,7 @@ pub async fn backfill_pdu(&self, origin: &ServerName, pdu: Box<RawJsonValue>) ->
 		.into();
 
 		// Insert pdu
-		self.db.prepend_backfill_pdu(&pdu_id, &event_id, &value);
+		self.db.prepend_backfill_pdu(&pdu_id, &pdu, &value);
 
 		drop(insert_lock);
 
@@ -1225,6 +1404,67 @@ pub async fn backfill_pdu(&self, origin: &ServerName, pdu: Box<RawJsonValue>) ->
 		debug!("Prepended backfill pdu");
 		Ok(())
 	}
+
+	/// Appends an event taken from a room export with the given state before
+	/// it. Like backfilled events, it is only stored and indexed: nobody is
+	/// notified or pushed, nothing is sent to appservices or other servers and
+	/// neither the room's state nor its forward extremities change.
+	#[tracing::instrument(skip_all, fields(event_id = %pdu.event_id), level = "debug")]
+	pub async fn append_imported_pdu(
+		&self,
+		pdu: &PduEvent,
+		pdu_json: &CanonicalJsonObject,
+		state_ids_compressed: Arc<CompressedState>,
+	) -> Result<RawPduId> {
+		let shortroomid = self
+			.services
+			.short
+			.get_shortroomid(&pdu.room_id)
+			.await
+			.map_err(|_| err!(Database("Room does not exist")))?;
+
+		self.services
+			.state
+			.set_event_state(&pdu.event_id, &pdu.room_id, state_ids_compressed)
+			.await?;
+
+		self.services
+			.pdu_metadata
+			.mark_as_referenced(&pdu.room_id, pdu.prev_events.iter().map(AsRef::as_ref));
+
+		let insert_lock = self.mutex_insert.lock(&pdu.room_id).await;
+		let count = PduCount::Normal(self.services.globals.next_count()?);
+		let pdu_id: RawPduId = PduId { shortroomid, shorteventid: count }.into();
+		self.db.append_pdu(&pdu_id, pdu, pdu_json, count).await;
+		drop(insert_lock);
+
+		if pdu.kind == TimelineEventType::RoomMessage {
+			let content: ExtractBody = pdu.get_content()?;
+			if let Some(body) = content.body {
+				self.services.search.index_pdu(shortroomid, &pdu_id, &body);
+			}
+		}
+
+		let related = match pdu.get_content::<ExtractRelatesTo>() {
+			| Ok(ExtractRelatesTo {
+				relates_to: Relation::Reply { in_reply_to },
+			}) => Some(in_reply_to.event_id),
+			| _ => pdu
+				.get_content::<ExtractRelatesToEventId>()
+				.ok()
+				.map(|content| content.relates_to.event_id),
+		};
+
+		if let Some(related) = related {
+			if let Ok(related_pducount) = self.get_pdu_count(&related).await {
+				self.services
+					.pdu_metadata
+					.add_relation(count, related_pducount);
+			}
+		}
+
+		Ok(pdu_id)
+	}
 }
 
 #[implement(Service)]
@@ -1299,3 +1539,15 @@ async fn check_pdu_for_admin_room(&self, pdu: &PduEvent, sender: &UserId) -> Res
 
 	Ok(())
 }
+
+impl Drop for BackfillGuard<'_> {
+	fn drop(&mut self) {
+		let mut backfills = self.service.backfills.lock().expect("locked");
+		if let Some(active) = backfills.get_mut(&self.room_id) {
+			*active = active.saturating_sub(1);
+			if *active == 0 {
+				backfills.remove(&self.room_id);
+			}
+		}
+	}
+}
@@ -0,0 +1,75 @@
+use std::{fmt, str::FromStr};
+
+use conduwuit::{err, Error, PduCount, PduEvent, Result};
+
+/// Position in a room's timeline given by a pagination token.
+///
+/// `/messages` walks the timeline in topological order and issues tokens
+/// naming both the depth and the stream count of an event,
+/// `t<depth>-<count>`, so pagination resumes exactly where it stopped. Stream
+/// tokens, e.g. the `prev_batch` of `/sync`, are plain counts; pagination
+/// from one starts at the event nearest to it in the stream.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Position {
+	Topological {
+		depth: u64,
+		count: PduCount,
+	},
+	Stream(PduCount),
+}
+
+impl Position {
+	/// The position of the event in topological order.
+	#[must_use]
+	pub fn of(count: PduCount, pdu: &PduEvent) -> Self {
+		Self::Topological { depth: pdu.depth.into(), count }
+	}
+
+	/// The stream count of the position.
+	#[must_use]
+	pub fn count(self) -> PduCount {
+		match self {
+			| Self::Topological { count, .. } | Self::Stream(count) => count,
+		}
+	}
+
+	/// Orders topological positions as the topological index does: by depth,
+	/// then by stream count. None for stream positions.
+	#[must_use]
+	pub fn topological_key(self) -> Option<(u64, PduCount)> {
+		match self {
+			| Self::Topological { depth, count } => Some((depth, count)),
+			| Self::Stream(_) => None,
+		}
+	}
+}
+
+impl fmt::Display for Position {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			| Self::Topological { depth, count } => write!(f, "t{depth}-{count}"),
+			| Self::Stream(count) => write!(f, "{count}"),
+		}
+	}
+}
+
+impl FromStr for Position {
+	type Err = Error;
+
+	fn from_str(token: &str) -> Result<Self> {
+		let Some(topological) = token.strip_prefix('t') else {
+			return Ok(Self::Stream(token.parse()?));
+		};
+
+		let (depth, count) = topological
+			.split_once('-')
+			.ok_or_else(|| err!(Request(InvalidParam("Invalid pagination token."))))?;
+
+		Ok(Self::Topological {
+			depth: depth
+				.parse()
+				.map_err(|_| err!(Request(InvalidParam("Invalid pagination token."))))?,
+			count: count.parse()?,
+		})
+	}
+}
@@ -1,17 +1,17 @@
-use std::{collections::BTreeMap, sync::Arc};
-
-use conduwuit::{
-	debug_info, trace,
-	utils::{self, IterStream},
-	Result, Server,
+use std::{
+	collections::{BTreeMap, HashMap},
+	future::Future,
+	sync::{Arc, Mutex as StdMutex},
 };
-use futures::StreamExt;
+
+use conduwuit::{debug_info, utils, Result, Server};
 use ruma::{
 	api::federation::transactions::edu::{Edu, TypingContent},
 	events::SyncEphemeralRoomEvent,
 	OwnedRoomId, OwnedUserId, RoomId, UserId,
 };
-use tokio::sync::{broadcast, RwLock};
+use serde_json::json;
+use tokio::sync::{watch, RwLock};
 
 use crate::{globals, sending, sending::EduBuf, users, Dep};
 
@@ -22,7 +22,8 @@ pub struct Service {
 	pub typing: RwLock<BTreeMap<OwnedRoomId, BTreeMap<OwnedUserId, u64>>>,
 	/// timestamp of the last change to typing users
 	pub last_typing_update: RwLock<BTreeMap<OwnedRoomId, u64>>,
-	pub typing_update_sender: broadcast::Sender<OwnedRoomId>,
+	/// Wakes syncs waiting on a typing update in the room
+	typing_watchers: StdMutex<HashMap<OwnedRoomId, watch::Sender<()>>>,
 }
 
 struct Services {
@@ -42,7 +43,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			},
 			typing: RwLock::new(BTreeMap::new()),
 			last_typing_update: RwLock::new(BTreeMap::new()),
-			typing_update_sender: broadcast::channel(100).0,
+			typing_watchers: StdMutex::new(HashMap::new()),
 		}))
 	}
 
@@ -72,15 +73,16 @@ pub async fn typing_add(
 			.await
 			.insert(room_id.to_owned(), self.services.globals.next_count()?);
 
-		if self.typing_update_sender.send(room_id.to_owned()).is_err() {
-			trace!("receiver found what it was looking for and is no longer interested");
-		}
+		self.notify_update(room_id);
 
 		// update federation
 		if self.services.globals.user_is_local(user_id) {
 			self.federation_send(room_id, user_id, true).await?;
 		}
 
+		self.appservice_send(room_id, self.typing_users(room_id).await)
+			.await?;
+
 		Ok(())
 	}
 
@@ -100,24 +102,42 @@ pub async fn typing_remove(&self, user_id: &UserId, room_id: &RoomId) -> Result<
 			.await
 			.insert(room_id.to_owned(), self.services.globals.next_count()?);
 
-		if self.typing_update_sender.send(room_id.to_owned()).is_err() {
-			trace!("receiver found what it was looking for and is no longer interested");
-		}
+		self.notify_update(room_id);
 
 		// update federation
 		if self.services.globals.user_is_local(user_id) {
 			self.federation_send(room_id, user_id, false).await?;
 		}
 
+		self.appservice_send(room_id, self.typing_users(room_id).await)
+			.await?;
+
 		Ok(())
 	}
 
-	pub async fn wait_for_update(&self, room_id: &RoomId) {
-		let mut receiver = self.typing_update_sender.subscribe();
-		while let Ok(next) = receiver.recv().await {
-			if next == room_id {
-				break;
-			}
+	/// Returns a future completing on the next typing update in the room. The
+	/// wait is registered before returning, so no update is missed between
+	/// the call and the first poll.
+	pub fn wait_for_update(&self, room_id: &RoomId) -> impl Future<Output = ()> + Send + 'static {
+		let mut receiver = self
+			.typing_watchers
+			.lock()
+			.expect("locked")
+			.entry(room_id.to_owned())
+			.or_insert_with(|| watch::channel(()).0)
+			.subscribe();
+
+		async move {
+			receiver.changed().await.ok();
+		}
+	}
+
+	/// Wakes everyone waiting on a typing update in the room.
+	fn notify_update(&self, room_id: &RoomId) {
+		let sender = self.typing_watchers.lock().expect("locked").remove(room_id);
+
+		if let Some(sender) = sender {
+			sender.send_replace(());
 		}
 	}
 
@@ -147,15 +167,15 @@ async fn typings_maintain(&self, room_id: &RoomId) -> Result<()> {
 				room.remove(user);
 			}
 
+			let typing_users = room.keys().cloned().collect();
+
 			// update clients
 			self.last_typing_update
 				.write()
 				.await
 				.insert(room_id.to_owned(), self.services.globals.next_count()?);
 
-			if self.typing_update_sender.send(room_id.to_owned()).is_err() {
-				trace!("receiver found what it was looking for and is no longer interested");
-			}
+			self.notify_update(room_id);
 
 			// update federation
 			for user in &removable {
@@ -163,6 +183,8 @@ async fn typings_maintain(&self, room_id: &RoomId) -> Result<()> {
 					self.federation_send(room_id, user, false).await?;
 				}
 			}
+
+			self.appservice_send(room_id, typing_users).await?;
 		}
 
 		Ok(())
@@ -194,19 +216,11 @@ pub async fn typings_all(
 			});
 		};
 
+		let ignored = self.services.users.ignored_users(sender_user).await;
 		let user_ids: Vec<_> = typing_indicators
 			.into_keys()
-			.stream()
-			.filter_map(|typing_user_id| async move {
-				(!self
-					.services
-					.users
-					.user_is_ignored(&typing_user_id, sender_user)
-					.await)
-					.then_some(typing_user_id)
-			})
-			.collect()
-			.await;
+			.filter(|typing_user_id| !ignored.contains(typing_user_id))
+			.collect();
 
 		Ok(SyncEphemeralRoomEvent {
 			content: ruma::events::typing::TypingEventContent { user_ids },
@@ -238,4 +252,31 @@ async fn federation_send(
 
 		Ok(())
 	}
+
+	async fn typing_users(&self, room_id: &RoomId) -> Vec<OwnedUserId> {
+		self.typing
+			.read()
+			.await
+			.get(room_id)
+			.map(|room| room.keys().cloned().collect())
+			.unwrap_or_default()
+	}
+
+	/// Pushes the users typing in the room to the appservices receiving
+	/// ephemeral events.
+	async fn appservice_send(&self, room_id: &RoomId, user_ids: Vec<OwnedUserId>) -> Result<()> {
+		let edu = json!({
+			"type": "m.typing",
+			"room_id": room_id,
+			"content": { "user_ids": user_ids },
+		});
+
+		let mut buf = EduBuf::new();
+		serde_json::to_writer(&mut buf, &edu).expect("Serialized m.typing event");
+
+		self.services
+			.sending
+			.send_edu_appservices_room(room_id, buf)
+			.await
+	}
 }
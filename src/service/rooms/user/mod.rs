@@ -1,9 +1,18 @@
+mod notifications;
+
 use std::sync::Arc;
 
-use conduwuit::{implement, Result};
-use database::{Database, Deserialized, Map};
-use ruma::{RoomId, UserId};
+use conduwuit::{
+	implement,
+	utils::{stream::TryIgnore, ReadyExt},
+	Result,
+};
+use database::{Database, Deserialized, Ignore, Interfix, Json, Map};
+use futures::StreamExt;
+use ruma::{events::receipt::ReceiptThread, OwnedEventId, RoomId, UserId};
+use serde::{Deserialize, Serialize};
 
+pub use self::notifications::Notification;
 use crate::{globals, rooms, rooms::short::ShortStateHash, Dep};
 
 pub struct Service {
@@ -17,6 +26,25 @@ struct Data {
 	userroomid_highlightcount: Arc<Map>,
 	roomuserid_lastnotificationread: Arc<Map>,
 	roomsynctoken_shortstatehash: Arc<Map>,
+	userroomcount_unreadnotification: Arc<Map>,
+	useridcount_notification: Arc<Map>,
+	userroomid_unreadcount: Arc<Map>,
+}
+
+/// An event which notified the user or counts as unread to them, and isn't
+/// covered by their receipts yet.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UnreadEvent {
+	pub notify: bool,
+	pub highlight: bool,
+
+	/// Whether the event counts towards the unread count (MSC2654): a message
+	/// which push rules don't suppress.
+	#[serde(default)]
+	pub unread: bool,
+
+	/// Root of the thread the event is in, if any.
+	pub thread: Option<OwnedEventId>,
 }
 
 struct Services {
@@ -33,6 +61,10 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				userroomid_highlightcount: args.db["userroomid_highlightcount"].clone(),
 				roomuserid_lastnotificationread: args.db["userroomid_highlightcount"].clone(),
 				roomsynctoken_shortstatehash: args.db["roomsynctoken_shortstatehash"].clone(),
+				userroomcount_unreadnotification: args.db["userroomcount_unreadnotification"]
+					.clone(),
+				useridcount_notification: args.db["useridcount_notification"].clone(),
+				userroomid_unreadcount: args.db["userroomid_unreadcount"].clone(),
 			},
 
 			services: Services {
@@ -46,10 +78,120 @@ fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
 #[implement(Service)]
-pub fn reset_notification_counts(&self, user_id: &UserId, room_id: &RoomId) {
+pub async fn reset_notification_counts(&self, user_id: &UserId, room_id: &RoomId) {
+	let prefix = (user_id, room_id, Interfix);
+	self.db
+		.userroomcount_unreadnotification
+		.keys_prefix_raw(&prefix)
+		.ignore_err()
+		.ready_for_each(|key| self.db.userroomcount_unreadnotification.del(key))
+		.await;
+
+	self.set_notification_counts(user_id, room_id, 0, 0, 0);
+}
+
+/// Records an event notifying or unread to the user, which counts towards
+/// their unread notifications until one of their receipts covers it. The
+/// counters themselves are incremented along with the event.
+#[implement(Service)]
+pub fn add_unread_event(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+	count: u64,
+	event: &UnreadEvent,
+) {
+	let key = (user_id, room_id, count);
+	self.db
+		.userroomcount_unreadnotification
+		.put(key, Json(event));
+}
+
+/// Number of unread messages of the user in the room (MSC2654).
+#[implement(Service)]
+pub async fn unread_count(&self, user_id: &UserId, room_id: &RoomId) -> u64 {
+	let key = (user_id, room_id);
+	self.db
+		.userroomid_unreadcount
+		.qry(&key)
+		.await
+		.deserialized()
+		.unwrap_or(0)
+}
+
+/// Clears the unread notifications covered by a public or private receipt at
+/// PDU `count` and recounts the rest. An unthreaded receipt covers every
+/// thread; a threaded one only its own. Returns whether the notification
+/// count changed.
+#[implement(Service)]
+pub async fn mark_notifications_read(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+	count: u64,
+	thread: &ReceiptThread,
+) -> bool {
+	type KeyVal<'a> = ((&'a UserId, &'a RoomId, u64), UnreadEvent);
+
+	let covers = |notification: &UnreadEvent| match thread {
+		| ReceiptThread::Unthreaded => true,
+		| ReceiptThread::Main => notification.thread.is_none(),
+		| ReceiptThread::Thread(root) => notification.thread.as_ref() == Some(root),
+		| _ => false,
+	};
+
+	let previous = self.notification_count(user_id, room_id).await;
+
+	let prefix = (user_id, room_id, Interfix);
+	let (notifications, highlights, unreads) = self
+		.db
+		.userroomcount_unreadnotification
+		.stream_prefix(&prefix)
+		.ignore_err()
+		.ready_filter(|((.., pdu_count), notification): &KeyVal<'_>| {
+			if *pdu_count <= count && covers(notification) {
+				self.db
+					.userroomcount_unreadnotification
+					.del((user_id, room_id, *pdu_count));
+				return false;
+			}
+
+			true
+		})
+		.ready_fold(
+			(0_u64, 0_u64, 0_u64),
+			|(notifications, highlights, unreads), (_, notification)| {
+				(
+					notifications.saturating_add(notification.notify.into()),
+					highlights.saturating_add(notification.highlight.into()),
+					unreads.saturating_add(notification.unread.into()),
+				)
+			},
+		)
+		.await;
+
+	self.set_notification_counts(user_id, room_id, notifications, highlights, unreads);
+
+	notifications != previous
+}
+
+#[implement(Service)]
+fn set_notification_counts(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+	notifications: u64,
+	highlights: u64,
+	unreads: u64,
+) {
 	let userroom_id = (user_id, room_id);
-	self.db.userroomid_highlightcount.put(userroom_id, 0_u64);
-	self.db.userroomid_notificationcount.put(userroom_id, 0_u64);
+	self.db.userroomid_unreadcount.put(userroom_id, unreads);
+	self.db
+		.userroomid_highlightcount
+		.put(userroom_id, highlights);
+	self.db
+		.userroomid_notificationcount
+		.put(userroom_id, notifications);
 
 	let roomuser_id = (room_id, user_id);
 	let count = self.services.globals.next_count().unwrap();
@@ -69,6 +211,19 @@ pub async fn notification_count(&self, user_id: &UserId, room_id: &RoomId) -> u6
 		.unwrap_or(0)
 }
 
+/// Number of unread notifications of the user across all their rooms, as
+/// shown by the badge of their apps.
+#[implement(Service)]
+pub async fn total_notification_count(&self, user_id: &UserId) -> u64 {
+	let prefix = (user_id, Interfix);
+	self.db
+		.userroomid_notificationcount
+		.stream_prefix(&prefix)
+		.ignore_err()
+		.ready_fold(0_u64, |total, (_, count): (Ignore, u64)| total.saturating_add(count))
+		.await
+}
+
 #[implement(Service)]
 pub async fn highlight_count(&self, user_id: &UserId, room_id: &RoomId) -> u64 {
 	let key = (user_id, room_id);
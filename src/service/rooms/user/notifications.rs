@@ -0,0 +1,94 @@
+//! Notification log
+//!
+//! Events which notified a user, kept for the `/notifications` endpoint
+//! whether or not they were read since.
+
+use conduwuit::{
+	implement,
+	utils::{stream::TryIgnore, ReadyExt},
+};
+use database::Json;
+use futures::{Stream, StreamExt};
+use ruma::{push::Action, OwnedEventId, OwnedRoomId, RoomId, UserId};
+use serde::{Deserialize, Serialize};
+
+/// An event which notified the user.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Notification {
+	pub room_id: OwnedRoomId,
+	pub event_id: OwnedEventId,
+
+	/// Actions of the push rule which matched the event.
+	pub actions: Vec<Action>,
+
+	/// Milliseconds since the unix epoch.
+	pub ts: u64,
+
+	/// When this server received the event, in milliseconds since the unix
+	/// epoch. The log is pruned by this rather than by `ts`, which the
+	/// sending server chooses.
+	#[serde(default)]
+	pub received: u64,
+}
+
+/// Logs an event at PDU `count` which notified the user.
+#[implement(super::Service)]
+pub fn log_notification(&self, user_id: &UserId, count: u64, notification: &Notification) {
+	let key = (user_id, count);
+	self.db
+		.useridcount_notification
+		.put(key, Json(notification));
+}
+
+/// Notifications of the user from before PDU `until`, newest first.
+#[implement(super::Service)]
+pub fn notifications_until<'a>(
+	&'a self,
+	user_id: &'a UserId,
+	until: u64,
+) -> impl Stream<Item = (u64, Notification)> + Send + 'a {
+	type KeyVal<'a> = ((&'a UserId, u64), Notification);
+
+	let from = (user_id, until.saturating_sub(1));
+	self.db
+		.useridcount_notification
+		.rev_stream_from(&from)
+		.ignore_err()
+		.ready_take_while(move |((user, _), _): &KeyVal<'_>| *user == user_id)
+		.map(|((_, count), notification): KeyVal<'_>| (count, notification))
+}
+
+/// Whether a notification of the user at PDU `count` was covered by one of
+/// their receipts.
+#[implement(super::Service)]
+pub async fn notification_read(&self, user_id: &UserId, room_id: &RoomId, count: u64) -> bool {
+	let key = (user_id, room_id, count);
+	!self
+		.db
+		.userroomcount_unreadnotification
+		.contains(&key)
+		.await
+}
+
+/// Removes logged notifications received before `cutoff` milliseconds since
+/// the unix epoch. Entries logged before the receive time was recorded go by
+/// their `ts` instead.
+#[implement(super::Service)]
+pub async fn prune_notifications(&self, cutoff: u64) {
+	type KeyVal<'a> = ((&'a UserId, u64), Notification);
+
+	self.db
+		.useridcount_notification
+		.stream()
+		.ignore_err()
+		.ready_filter(|(_, notification): &KeyVal<'_>| {
+			let received = match notification.received {
+				| 0 => notification.ts,
+				| received => received,
+			};
+
+			received < cutoff
+		})
+		.ready_for_each(|(key, _)| self.db.useridcount_notification.del(key))
+		.await;
+}
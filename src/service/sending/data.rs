@@ -229,7 +229,7 @@ fn parse_servercurrentevent(key: &[u8], value: &[u8]) -> Result<(Destination, Se
 			if value.is_empty() {
 				SendingEvent::Pdu(event.into())
 			} else {
-				// I'm pretty sure this should never be called
+				// Badge updates
 				SendingEvent::Edu(value.into())
 			},
 		)
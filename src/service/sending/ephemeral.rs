@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use conduwuit::{implement, Result};
+use futures::StreamExt;
+use ruma::{
+	api::appservice::event::push_events::v1::DeviceLists, DeviceId, OneTimeKeyAlgorithm,
+	OwnedDeviceId, OwnedUserId, RoomId, UInt, UserId,
+};
+
+use super::{Destination, EduBuf, Msg, SendingEvent};
+use crate::appservice::RegistrationInfo;
+
+/// Device changes waiting to be pushed to an MSC3202 appservice.
+#[derive(Debug, Default)]
+pub(super) struct DeviceUpdates {
+	/// Users sharing a room with the appservice whose device list changed.
+	changed: BTreeSet<OwnedUserId>,
+
+	/// Devices of the appservice's users whose one-time keys were claimed.
+	one_time_keys: BTreeSet<(OwnedUserId, OwnedDeviceId)>,
+}
+
+impl DeviceUpdates {
+	pub(super) fn is_empty(&self) -> bool {
+		self.changed.is_empty() && self.one_time_keys.is_empty()
+	}
+}
+
+/// One-time key counts of devices, by user and device.
+pub(super) type OneTimeKeyCounts =
+	BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, BTreeMap<OneTimeKeyAlgorithm, UInt>>>;
+
+pub(super) type FallbackKeyTypes =
+	BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, Vec<OneTimeKeyAlgorithm>>>;
+
+/// Queues an ephemeral event of the room to the appservices which receive
+/// ephemeral events and are in the room (MSC2409).
+#[implement(super::Service)]
+#[tracing::instrument(skip(self, serialized), level = "debug")]
+pub async fn send_edu_appservices_room(&self, room_id: &RoomId, serialized: EduBuf) -> Result {
+	let mut appservices = Vec::new();
+	for info in self
+		.ephemeral_appservices(|info| info.receive_ephemeral)
+		.await
+	{
+		if self
+			.services
+			.state_cache
+			.appservice_in_room(room_id, &info)
+			.await
+		{
+			appservices.push(info.registration.id);
+		}
+	}
+
+	self.send_edu_appservices(appservices, serialized)
+}
+
+/// Queues an ephemeral event about the user, e.g. their presence, to the
+/// appservices which receive ephemeral events and either claim the user or
+/// share a room with them (MSC2409).
+#[implement(super::Service)]
+#[tracing::instrument(skip(self, serialized), level = "debug")]
+pub async fn send_edu_appservices_user(&self, user_id: &UserId, serialized: EduBuf) -> Result {
+	let mut appservices = Vec::new();
+	for info in self
+		.ephemeral_appservices(|info| info.receive_ephemeral)
+		.await
+	{
+		if self.appservice_interested_in_user(&info, user_id).await {
+			appservices.push(info.registration.id);
+		}
+	}
+
+	self.send_edu_appservices(appservices, serialized)
+}
+
+/// Notes the user's device list changed for the MSC3202 appservices which
+/// claim the user or share a room with them, and pushes it to them.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn device_list_changed_appservices(&self, user_id: &UserId) -> Result {
+	let mut appservices = Vec::new();
+	for info in self
+		.ephemeral_appservices(|info| info.device_masquerading)
+		.await
+	{
+		if self.appservice_interested_in_user(&info, user_id).await {
+			appservices.push(info.registration.id);
+		}
+	}
+
+	let mut device_updates = self.device_updates.lock()?;
+	for id in &appservices {
+		device_updates
+			.entry(id.clone())
+			.or_default()
+			.changed
+			.insert(user_id.to_owned());
+	}
+
+	drop(device_updates);
+	self.flush_appservices(appservices)
+}
+
+/// Notes one-time keys of the device were claimed for the MSC3202
+/// appservices claiming the user, and pushes the new count to them.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn one_time_keys_changed_appservices(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+) -> Result {
+	let appservices: Vec<_> = self
+		.ephemeral_appservices(|info| {
+			info.device_masquerading && info.is_exclusive_user_match(user_id)
+		})
+		.await
+		.into_iter()
+		.map(|info| info.registration.id)
+		.collect();
+
+	let mut device_updates = self.device_updates.lock()?;
+	for id in &appservices {
+		device_updates
+			.entry(id.clone())
+			.or_default()
+			.one_time_keys
+			.insert((user_id.to_owned(), device_id.to_owned()));
+	}
+
+	drop(device_updates);
+	self.flush_appservices(appservices)
+}
+
+/// Takes the device changes waiting to be pushed to the appservice, along
+/// with the current one-time key counts of the devices concerned. There are
+/// none unless the appservice uses MSC3202.
+#[implement(super::Service)]
+pub(super) async fn take_device_updates(
+	&self,
+	appservice: &RegistrationInfo,
+) -> (DeviceUpdates, DeviceLists, OneTimeKeyCounts, FallbackKeyTypes) {
+	let updates = appservice
+		.device_masquerading
+		.then(|| {
+			self.device_updates
+				.lock()
+				.expect("locked")
+				.remove(&appservice.registration.id)
+		})
+		.flatten()
+		.unwrap_or_default();
+
+	let mut device_lists = DeviceLists::new();
+	device_lists.changed = updates.changed.iter().cloned().collect();
+
+	let mut one_time_key_counts = OneTimeKeyCounts::new();
+	let mut fallback_key_types = FallbackKeyTypes::new();
+	for (user_id, device_id) in &updates.one_time_keys {
+		let counts = self
+			.services
+			.users
+			.count_one_time_keys(user_id, device_id)
+			.await;
+
+		one_time_key_counts
+			.entry(user_id.clone())
+			.or_default()
+			.insert(device_id.clone(), counts);
+
+		let unused = self
+			.services
+			.users
+			.unused_fallback_key_types(user_id, device_id)
+			.await;
+
+		fallback_key_types
+			.entry(user_id.clone())
+			.or_default()
+			.insert(device_id.clone(), unused);
+	}
+
+	(updates, device_lists, one_time_key_counts, fallback_key_types)
+}
+
+/// Puts back device changes taken for a transaction which failed, so they
+/// are pushed with the next one.
+#[implement(super::Service)]
+pub(super) fn restore_device_updates(&self, appservice_id: &str, updates: DeviceUpdates) {
+	let mut device_updates = self.device_updates.lock().expect("locked");
+	let pending = device_updates.entry(appservice_id.to_owned()).or_default();
+
+	pending.changed.extend(updates.changed);
+	pending.one_time_keys.extend(updates.one_time_keys);
+}
+
+#[implement(super::Service)]
+async fn ephemeral_appservices<F>(&self, filter: F) -> Vec<RegistrationInfo>
+where
+	F: Fn(&RegistrationInfo) -> bool,
+{
+	self.services
+		.appservice
+		.read()
+		.await
+		.values()
+		.filter(|info| filter(info))
+		.cloned()
+		.collect()
+}
+
+#[implement(super::Service)]
+async fn appservice_interested_in_user(&self, info: &RegistrationInfo, user_id: &UserId) -> bool {
+	if info.is_user_match(user_id) {
+		return true;
+	}
+
+	let rooms: Vec<_> = self
+		.services
+		.state_cache
+		.rooms_joined(user_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for room_id in &rooms {
+		if self
+			.services
+			.state_cache
+			.appservice_in_room(room_id, info)
+			.await
+		{
+			return true;
+		}
+	}
+
+	false
+}
+
+#[implement(super::Service)]
+fn send_edu_appservices(&self, appservices: Vec<String>, serialized: EduBuf) -> Result {
+	let requests: Vec<_> = appservices
+		.into_iter()
+		.map(|id| (Destination::Appservice(id), SendingEvent::Edu(serialized.clone())))
+		.collect();
+
+	let _cork = self.db.db.cork();
+	let keys = self.db.queue_requests(requests.iter().map(|(o, e)| (e, o)));
+
+	for ((dest, event), queue_id) in requests.into_iter().zip(keys) {
+		self.dispatch(Msg { dest, event, queue_id })?;
+	}
+
+	Ok(())
+}
+
+#[implement(super::Service)]
+fn flush_appservices(&self, appservices: Vec<String>) -> Result {
+	appservices
+		.into_iter()
+		.map(Destination::Appservice)
+		.try_for_each(|dest| {
+			self.dispatch(Msg {
+				dest,
+				event: SendingEvent::Flush,
+				queue_id: Vec::<u8>::new(),
+			})
+		})
+}
@@ -1,13 +1,17 @@
 mod appservice;
 mod data;
 mod dest;
+mod ephemeral;
 mod sender;
+mod status;
 
 use std::{
+	collections::HashMap,
 	fmt::Debug,
 	hash::{DefaultHasher, Hash, Hasher},
 	iter::once,
-	sync::Arc,
+	sync::{Arc, Mutex},
+	time::Instant,
 };
 
 use async_trait::async_trait;
@@ -24,14 +28,14 @@
 use smallvec::SmallVec;
 use tokio::task::JoinSet;
 
-use self::data::Data;
+use self::{data::Data, ephemeral::DeviceUpdates};
 pub use self::{
 	dest::Destination,
 	sender::{EDU_LIMIT, PDU_LIMIT},
+	status::{AppserviceStatus, QueueDepth},
 };
 use crate::{
-	account_data, client, federation, globals, presence, pusher, rooms,
-	rooms::timeline::RawPduId, users, Dep,
+	client, federation, globals, presence, pusher, rooms, rooms::timeline::RawPduId, users, Dep,
 };
 
 pub struct Service {
@@ -39,11 +43,21 @@ pub struct Service {
 	server: Arc<Server>,
 	services: Services,
 	channels: Vec<(loole::Sender<Msg>, loole::Receiver<Msg>)>,
+
+	/// Device changes not yet pushed to MSC3202 appservices, by appservice id.
+	device_updates: Mutex<HashMap<String, DeviceUpdates>>,
+
+	/// Delivery to each appservice, by appservice id.
+	appservice_status: Mutex<HashMap<String, AppserviceStatus>>,
+
+	/// Since when pushes to each failing pusher have been failing.
+	push_failing_since: Mutex<HashMap<Destination, Instant>>,
 }
 
 struct Services {
 	client: Dep<client::Service>,
 	globals: Dep<globals::Service>,
+	metadata: Dep<rooms::metadata::Service>,
 	state: Dep<rooms::state::Service>,
 	state_cache: Dep<rooms::state_cache::Service>,
 	user: Dep<rooms::user::Service>,
@@ -51,7 +65,6 @@ struct Services {
 	presence: Dep<presence::Service>,
 	read_receipt: Dep<rooms::read_receipt::Service>,
 	timeline: Dep<rooms::timeline::Service>,
-	account_data: Dep<account_data::Service>,
 	appservice: Dep<crate::appservice::Service>,
 	pusher: Dep<pusher::Service>,
 	federation: Dep<federation::Service>,
@@ -88,6 +101,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			services: Services {
 				client: args.depend::<client::Service>("client"),
 				globals: args.depend::<globals::Service>("globals"),
+				metadata: args.depend::<rooms::metadata::Service>("rooms::metadata"),
 				state: args.depend::<rooms::state::Service>("rooms::state"),
 				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
 				user: args.depend::<rooms::user::Service>("rooms::user"),
@@ -95,12 +109,14 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				presence: args.depend::<presence::Service>("presence"),
 				read_receipt: args.depend::<rooms::read_receipt::Service>("rooms::read_receipt"),
 				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
-				account_data: args.depend::<account_data::Service>("account_data"),
 				appservice: args.depend::<crate::appservice::Service>("appservice"),
 				pusher: args.depend::<pusher::Service>("pusher"),
 				federation: args.depend::<federation::Service>("federation"),
 			},
 			channels: (0..num_senders).map(|_| loole::unbounded()).collect(),
+			device_updates: Mutex::new(HashMap::new()),
+			appservice_status: Mutex::new(HashMap::new()),
+			push_failing_since: Mutex::new(HashMap::new()),
 		}))
 	}
 
@@ -155,6 +171,21 @@ pub fn send_pdu_push(&self, pdu_id: &RawPduId, user: &UserId, pushkey: String) -
 		})
 	}
 
+	/// Queues an update of the badge count for the pusher. Push destinations
+	/// have no EDUs; their EDU events stand for badge updates instead.
+	#[tracing::instrument(skip(self, user, pushkey), level = "debug")]
+	pub fn send_push_badge(&self, user: &UserId, pushkey: String) -> Result {
+		let dest = Destination::Push(user.to_owned(), pushkey);
+		let event = SendingEvent::Edu(EduBuf::from_slice(b"{}"));
+		let _cork = self.db.db.cork();
+		let keys = self.db.queue_requests(once((&event, &dest)));
+		self.dispatch(Msg {
+			dest,
+			event,
+			queue_id: keys.into_iter().next().expect("request queue key"),
+		})
+	}
+
 	#[tracing::instrument(skip(self), level = "debug")]
 	pub fn send_pdu_appservice(&self, appservice_id: String, pdu_id: RawPduId) -> Result {
 		let dest = Destination::Appservice(appservice_id);
@@ -352,6 +383,8 @@ pub async fn cleanup_events(
 					.delete_all_requests_for(&Destination::Appservice(appservice_id.to_owned()))
 					.await;
 
+				self.appservice_status.lock()?.remove(appservice_id);
+
 				Ok(())
 			},
 			| _ => {
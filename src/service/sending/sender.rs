@@ -10,7 +10,7 @@
 
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use conduwuit::{
-	debug, err, error,
+	debug, debug_warn, err, error,
 	result::LogErr,
 	trace,
 	utils::{
@@ -19,7 +19,7 @@
 		stream::{BroadbandExt, IterStream, WidebandExt},
 		ReadyExt,
 	},
-	warn, Error, Result,
+	warn, Error, PduEvent, Result,
 };
 use futures::{
 	future::{BoxFuture, OptionFuture},
@@ -30,6 +30,7 @@
 use ruma::{
 	api::{
 		appservice::event::push_events::v1::EphemeralData,
+		client::push::Pusher,
 		federation::transactions::{
 			edu::{
 				DeviceListUpdateContent, Edu, PresenceContent, PresenceUpdate, ReceiptContent,
@@ -39,20 +40,17 @@
 		},
 	},
 	device_id,
-	events::{
-		push_rules::PushRulesEvent, receipt::ReceiptType, AnySyncEphemeralRoomEvent,
-		GlobalAccountDataEventType,
-	},
-	push,
+	events::{receipt::ReceiptType, AnySyncEphemeralRoomEvent},
 	serde::Raw,
 	uint, CanonicalJsonObject, MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedServerName,
-	OwnedUserId, RoomId, RoomVersionId, ServerName, UInt,
+	OwnedUserId, RoomId, RoomVersionId, ServerName, UInt, UserId,
 };
 use serde_json::value::{to_raw_value, RawValue as RawJsonValue};
 
 use super::{
 	appservice, data::QueueItem, Destination, EduBuf, EduVec, Msg, SendingEvent, Service,
 };
+use crate::rooms::timeline::RawPduId;
 
 #[derive(Debug)]
 enum TransactionStatus {
@@ -137,13 +135,19 @@ async fn handle_response<'a>(
 	) {
 		match response {
 			| Ok(dest) => self.handle_response_ok(&dest, futures, statuses).await,
-			| Err((dest, e)) => Self::handle_response_err(dest, statuses, &e),
+			| Err((dest, e)) => self.handle_response_err(dest, futures, statuses, &e),
 		};
 	}
 
-	fn handle_response_err(dest: Destination, statuses: &mut CurTransactionStatus, e: &Error) {
+	fn handle_response_err<'a>(
+		&'a self,
+		dest: Destination,
+		futures: &mut SendingFutures<'a>,
+		statuses: &mut CurTransactionStatus,
+		e: &Error,
+	) {
 		debug!(dest = ?dest, "{e:?}");
-		statuses.entry(dest).and_modify(|e| {
+		statuses.entry(dest.clone()).and_modify(|e| {
 			*e = match e {
 				| TransactionStatus::Running => TransactionStatus::Failed(1, Instant::now()),
 				| TransactionStatus::Retrying(ref n) =>
@@ -153,6 +157,127 @@ fn handle_response_err(dest: Destination, statuses: &mut CurTransactionStatus, e
 				},
 			}
 		});
+
+		match dest {
+			| Destination::Appservice(_) =>
+				self.schedule_appservice_retry(dest, futures, statuses, e),
+			| Destination::Push(..) => self.schedule_push_retry(dest, futures, statuses, e),
+			| Destination::Federation(_) => {},
+		}
+	}
+
+	/// Appservices are retried on a timer rather than when more events are
+	/// queued for them, so delivery resumes once they are back up. Events
+	/// queued meanwhile wait for the retry to succeed.
+	fn schedule_appservice_retry<'a>(
+		&'a self,
+		dest: Destination,
+		futures: &mut SendingFutures<'a>,
+		statuses: &mut CurTransactionStatus,
+		e: &Error,
+	) {
+		let Destination::Appservice(id) = &dest else {
+			return;
+		};
+
+		let Some(&TransactionStatus::Failed(tries, _)) = statuses.get(&dest) else {
+			return;
+		};
+
+		let config = &self.server.config;
+		let min = Duration::from_secs(config.appservice_retry_backoff_min);
+		let max = Duration::from_secs(config.appservice_retry_backoff_limit);
+		let delay = min.saturating_mul(tries).saturating_mul(tries).min(max);
+
+		warn!("Transaction to appservice {id} failed, retrying in {delay:?}: {e}");
+		self.record_appservice_failure(id, e, delay);
+
+		statuses.insert(dest.clone(), TransactionStatus::Retrying(tries));
+		futures.push(self.retry_appservice(dest, delay).boxed());
+	}
+
+	async fn retry_appservice(&self, dest: Destination, delay: Duration) -> SendingResult {
+		tokio::time::sleep(delay).await;
+
+		let Destination::Appservice(id) = &dest else {
+			return Ok(dest);
+		};
+
+		// The appservice was unregistered meanwhile
+		if !self.services.appservice.read().await.contains_key(id) {
+			return Ok(dest);
+		}
+
+		let mut events: Vec<_> = self
+			.db
+			.active_requests_for(&dest)
+			.map(|(_, event)| event)
+			.collect()
+			.await;
+
+		// Only device updates failed to be pushed; they were put back
+		if events.is_empty() {
+			events.push(SendingEvent::Flush);
+		}
+
+		self.send_events(dest, events).await
+	}
+
+	/// Pushes are retried on a timer like appservice transactions, so
+	/// notifications aren't lost when the push gateway hiccups. A pusher whose
+	/// gateway keeps failing for `pusher_failure_timeout` is removed instead.
+	fn schedule_push_retry<'a>(
+		&'a self,
+		dest: Destination,
+		futures: &mut SendingFutures<'a>,
+		statuses: &mut CurTransactionStatus,
+		e: &Error,
+	) {
+		let Destination::Push(user_id, pushkey) = &dest else {
+			return;
+		};
+
+		let Some(&TransactionStatus::Failed(tries, _)) = statuses.get(&dest) else {
+			return;
+		};
+
+		let config = &self.server.config;
+		let failing_for = self.record_push_failure(&dest);
+		statuses.insert(dest.clone(), TransactionStatus::Retrying(tries));
+
+		if failing_for >= Duration::from_secs(config.pusher_failure_timeout) {
+			warn!(%user_id, %pushkey, "Push gateway failing for {failing_for:?}, removing pusher: {e}");
+			futures.push(self.remove_pusher(dest).boxed());
+			return;
+		}
+
+		let min = Duration::from_secs(config.pusher_retry_backoff_min);
+		let max = Duration::from_secs(config.pusher_retry_backoff_limit);
+		let delay = min.saturating_mul(tries).saturating_mul(tries).min(max);
+
+		debug_warn!(%user_id, %pushkey, "Push failed, retrying in {delay:?}: {e}");
+		futures.push(self.retry_push(dest, delay).boxed());
+	}
+
+	async fn retry_push(&self, dest: Destination, delay: Duration) -> SendingResult {
+		tokio::time::sleep(delay).await;
+
+		let events: Vec<_> = self
+			.db
+			.active_requests_for(&dest)
+			.map(|(_, event)| event)
+			.collect()
+			.await;
+
+		self.send_events(dest, events).await
+	}
+
+	async fn remove_pusher(&self, dest: Destination) -> SendingResult {
+		if let Destination::Push(user_id, pushkey) = &dest {
+			self.services.pusher.delete_pusher(user_id, pushkey).await;
+		}
+
+		Ok(dest)
 	}
 
 	#[allow(clippy::needless_pass_by_ref_mut)]
@@ -165,6 +290,12 @@ async fn handle_response_ok<'a>(
 		let _cork = self.db.db.cork();
 		self.db.delete_all_active_requests_for(dest).await;
 
+		match dest {
+			| Destination::Appservice(id) => self.record_appservice_success(id),
+			| Destination::Push(..) => self.record_push_success(dest),
+			| Destination::Federation(_) => {},
+		}
+
 		// Find events that have been added since starting the last request
 		let new_events = self
 			.db
@@ -255,7 +386,9 @@ async fn startup_netburst<'a>(
 			}
 
 			let entry = txns.entry(dest.clone()).or_default();
-			if self.server.config.startup_netburst_keep >= 0 && entry.len() >= keep {
+			let appservice = matches!(dest, Destination::Appservice(_));
+			if !appservice && self.server.config.startup_netburst_keep >= 0 && entry.len() >= keep
+			{
 				warn!("Dropping unsent event {dest:?} {:?}", String::from_utf8_lossy(&key));
 				self.db.delete_active_request(&key);
 			} else {
@@ -264,7 +397,8 @@ async fn startup_netburst<'a>(
 		}
 
 		for (dest, events) in txns {
-			if self.server.config.startup_netburst && !events.is_empty() {
+			let appservice = matches!(dest, Destination::Appservice(_));
+			if (self.server.config.startup_netburst || appservice) && !events.is_empty() {
 				statuses.insert(dest.clone(), TransactionStatus::Running);
 				futures.push(self.send_events(dest.clone(), events));
 			}
@@ -346,7 +480,7 @@ fn select_events_current(
 					let min = self.server.config.sender_timeout;
 					let max = self.server.config.sender_retry_backoff_limit;
 					if continue_exponential_backoff_secs(min, max, time.elapsed(), *tries)
-						&& !matches!(dest, Destination::Appservice(_))
+						&& matches!(dest, Destination::Federation(_))
 					{
 						allow = false;
 					} else {
@@ -418,48 +552,55 @@ async fn select_edus_device_changes(
 		events_len: &AtomicUsize,
 	) -> EduVec {
 		let mut events = EduVec::new();
-		let server_rooms = self.services.state_cache.server_rooms(server_name);
 
-		pin_mut!(server_rooms);
-		let mut device_list_changes = HashSet::<OwnedUserId>::new();
-		while let Some(room_id) = server_rooms.next().await {
-			let keys_changed = self
-				.services
-				.users
-				.room_keys_changed(room_id, since.0, None)
-				.ready_filter(|(user_id, _)| self.services.globals.user_is_local(user_id));
+		// Everything before the batch was received by the server
+		self.services
+			.users
+			.forget_server_device_list_changes(server_name, since.0)
+			.await;
 
-			pin_mut!(keys_changed);
-			while let Some((user_id, count)) = keys_changed.next().await {
-				if count > since.1 {
-					break;
-				}
+		let keys_changed =
+			self.services
+				.users
+				.server_device_list_changes(server_name, since.0, since.1);
 
-				max_edu_count.fetch_max(count, Ordering::Relaxed);
-				if !device_list_changes.insert(user_id.into()) {
-					continue;
-				}
+		pin_mut!(keys_changed);
+		let mut device_list_changes = HashSet::<OwnedUserId>::new();
+		while let Some((user_id, count)) = keys_changed.next().await {
+			max_edu_count.fetch_max(count, Ordering::Relaxed);
+			if !device_list_changes.insert(user_id.into()) {
+				continue;
+			}
 
-				// Empty prev id forces synapse to resync; because synapse resyncs,
-				// we can just insert placeholder data
-				let edu = Edu::DeviceListUpdate(DeviceListUpdateContent {
-					user_id: user_id.into(),
-					device_id: device_id!("placeholder").to_owned(),
-					device_display_name: Some("Placeholder".to_owned()),
-					stream_id: uint!(1),
-					prev_id: Vec::new(),
-					deleted: None,
-					keys: None,
-				});
-
-				let mut buf = EduBuf::new();
-				serde_json::to_writer(&mut buf, &edu)
-					.expect("failed to serialize device list update to JSON");
-
-				events.push(buf);
-				if events_len.fetch_add(1, Ordering::Relaxed) >= SELECT_EDU_LIMIT - 1 {
-					return events;
-				}
+			let stream_id = self
+				.services
+				.users
+				.get_devicelist_version(user_id)
+				.await
+				.ok()
+				.and_then(|version| version.try_into().ok())
+				.unwrap_or(uint!(1));
+
+			// Empty prev id forces the server to resync from /user/devices, which serves
+			// the same stream_id; because it resyncs, we can just insert placeholder
+			// data
+			let edu = Edu::DeviceListUpdate(DeviceListUpdateContent {
+				user_id: user_id.into(),
+				device_id: device_id!("placeholder").to_owned(),
+				device_display_name: Some("Placeholder".to_owned()),
+				stream_id,
+				prev_id: Vec::new(),
+				deleted: None,
+				keys: None,
+			});
+
+			let mut buf = EduBuf::new();
+			serde_json::to_writer(&mut buf, &edu)
+				.expect("failed to serialize device list update to JSON");
+
+			events.push(buf);
+			if events_len.fetch_add(1, Ordering::Relaxed) >= SELECT_EDU_LIMIT - 1 {
+				return events;
 			}
 		}
 
@@ -520,6 +661,10 @@ async fn select_edus_receipts_room(
 		max_edu_count: &AtomicU64,
 		num: &mut usize,
 	) -> ReceiptMap {
+		// Our users' public receipts stay on this server in rooms with
+		// ephemeral events disabled
+		let suppressed = self.services.metadata.is_ephemeral_disabled(room_id).await;
+
 		let receipts = self
 			.services
 			.read_receipt
@@ -533,7 +678,7 @@ async fn select_edus_receipts_room(
 			}
 
 			max_edu_count.fetch_max(count, Ordering::Relaxed);
-			if !self.services.globals.user_is_local(user_id) {
+			if suppressed || !self.services.globals.user_is_local(user_id) {
 				continue;
 			}
 
@@ -677,7 +822,7 @@ async fn send_events_dest_appservice(
 		id: String,
 		events: Vec<SendingEvent>,
 	) -> SendingResult {
-		let Some(appservice) = self.services.appservice.get_registration(&id).await else {
+		let Some(appservice) = self.services.appservice.read().await.get(&id).cloned() else {
 			return Err((
 				Destination::Appservice(id.clone()),
 				err!(Database(warn!(?id, "Missing appservice registration"))),
@@ -713,23 +858,47 @@ async fn send_events_dest_appservice(
 			}
 		}
 
-		let txn_hash = calculate_hash(events.iter().filter_map(|e| match e {
-			| SendingEvent::Edu(b) => Some(&**b),
-			| SendingEvent::Pdu(b) => Some(b.as_ref()),
-			| SendingEvent::Flush => None,
-		}));
+		let (
+			device_updates,
+			device_lists,
+			device_one_time_keys_count,
+			device_unused_fallback_key_types,
+		) = self.take_device_updates(&appservice).await;
+
+		if pdu_jsons.is_empty() && edu_jsons.is_empty() && device_updates.is_empty() {
+			return Ok(Destination::Appservice(id));
+		}
+
+		// Transactions only carrying device updates would otherwise all hash alike
+		// and be dropped by the appservice as retries.
+		let device_count = (!device_updates.is_empty())
+			.then(|| self.services.globals.next_count().ok())
+			.flatten()
+			.map(u64::to_be_bytes);
+
+		let txn_hash = calculate_hash(
+			events
+				.iter()
+				.filter_map(|e| match e {
+					| SendingEvent::Edu(b) => Some(&**b),
+					| SendingEvent::Pdu(b) => Some(b.as_ref()),
+					| SendingEvent::Flush => None,
+				})
+				.chain(device_count.as_ref().map(<[u8; 8]>::as_slice)),
+		);
 
 		let txn_id = &*URL_SAFE_NO_PAD.encode(txn_hash);
 
-		//debug_assert!(pdu_jsons.len() + edu_jsons.len() > 0, "sending empty
-		// transaction");
 		let client = &self.services.client.appservice;
 		match appservice::send_request(
 			client,
-			appservice,
+			appservice.registration,
 			ruma::api::appservice::event::push_events::v1::Request {
 				events: pdu_jsons,
 				txn_id: txn_id.into(),
+				device_lists,
+				device_one_time_keys_count,
+				device_unused_fallback_key_types,
 				ephemeral: edu_jsons,
 				to_device: Vec::new(), // TODO
 			},
@@ -737,7 +906,10 @@ async fn send_events_dest_appservice(
 		.await
 		{
 			| Ok(_) => Ok(Destination::Appservice(id)),
-			| Err(e) => Err((Destination::Appservice(id), e)),
+			| Err(e) => {
+				self.restore_device_updates(&id, device_updates);
+				Err((Destination::Appservice(id), e))
+			},
 		}
 	}
 
@@ -755,66 +927,80 @@ async fn send_events_dest_push(
 		pushkey: String,
 		events: Vec<SendingEvent>,
 	) -> SendingResult {
+		let dest = Destination::Push(user_id.clone(), pushkey.clone());
 		let Ok(pusher) = self.services.pusher.get_pusher(&user_id, &pushkey).await else {
-			return Err((
-				Destination::Push(user_id.clone(), pushkey.clone()),
-				err!(Database(error!(?user_id, ?pushkey, "Missing pusher"))),
-			));
+			// Removed meanwhile; its notifications went with it
+			debug!(?user_id, ?pushkey, "Missing pusher");
+			return Ok(dest);
 		};
 
-		let mut pdus = Vec::with_capacity(
-			events
-				.iter()
-				.filter(|event| matches!(event, SendingEvent::Pdu(_)))
-				.count(),
-		);
+		let mut badge_update = false;
 		for event in &events {
 			match event {
 				| SendingEvent::Pdu(pdu_id) => {
-					if let Ok(pdu) = self.services.timeline.get_pdu_from_id(pdu_id).await {
-						pdus.push(pdu);
-					}
-				},
-				| SendingEvent::Edu(_) | SendingEvent::Flush => {
-					// Push gateways don't need EDUs (?) and flush only;
-					// no new content
+					let Ok(pdu) = self.services.timeline.get_pdu_from_id(pdu_id).await else {
+						continue;
+					};
+
+					self.send_pdu_push(&dest, &user_id, &pusher, pdu_id, &pdu)
+						.await
+						.map_err(|e| (dest.clone(), e))?;
 				},
+				| SendingEvent::Edu(_) => badge_update = true,
+				| SendingEvent::Flush => {},
 			}
 		}
 
-		for pdu in pdus {
-			// Redacted events are not notification targets (we don't send push for them)
-			if pdu.contains_unsigned_property("redacted_because", serde_json::Value::is_string) {
-				continue;
-			}
-
-			let rules_for_user = self
-				.services
-				.account_data
-				.get_global(&user_id, GlobalAccountDataEventType::PushRules)
+		if badge_update {
+			let unread = self.unread_badge(&user_id).await;
+			self.services
+				.pusher
+				.send_badge_update(&user_id, unread, &pusher)
 				.await
-				.map_or_else(
-					|_| push::Ruleset::server_default(&user_id),
-					|ev: PushRulesEvent| ev.content.global,
-				);
+				.map_err(|e| (dest.clone(), e))?;
+		}
 
-			let unread: UInt = self
-				.services
-				.user
-				.notification_count(&user_id, &pdu.room_id)
-				.await
-				.try_into()
-				.expect("notification count can't go that high");
+		Ok(dest)
+	}
 
-			let _response = self
-				.services
-				.pusher
-				.send_push_notice(&user_id, unread, &pusher, rules_for_user, &pdu)
-				.await
-				.map_err(|e| (Destination::Push(user_id.clone(), pushkey.clone()), e));
+	/// Pushes the event unless it was redacted. Once the gateway accepted it,
+	/// it's removed from the active requests so a retry of the rest of the
+	/// batch doesn't push it twice.
+	async fn send_pdu_push(
+		&self,
+		dest: &Destination,
+		user_id: &UserId,
+		pusher: &Pusher,
+		pdu_id: &RawPduId,
+		pdu: &PduEvent,
+	) -> Result {
+		// Redacted events are not notification targets (we don't send push for them)
+		if pdu.contains_unsigned_property("redacted_because", serde_json::Value::is_string) {
+			return Ok(());
 		}
 
-		Ok(Destination::Push(user_id, pushkey))
+		let rules_for_user = self.services.pusher.get_ruleset(user_id).await;
+		let unread = self.unread_badge(user_id).await;
+
+		self.services
+			.pusher
+			.send_push_notice(user_id, unread, pusher, rules_for_user, pdu)
+			.await?;
+
+		let mut key = dest.get_prefix();
+		key.extend(pdu_id.as_ref());
+		self.db.delete_active_request(&key);
+
+		Ok(())
+	}
+
+	async fn unread_badge(&self, user_id: &UserId) -> UInt {
+		self.services
+			.user
+			.total_notification_count(user_id)
+			.await
+			.try_into()
+			.unwrap_or(UInt::MAX)
 	}
 
 	async fn send_events_dest_federation(
@@ -863,12 +1049,24 @@ async fn send_events_dest_federation(
 			edus,
 		};
 
+		let started = Instant::now();
 		let result = self
 			.services
 			.federation
 			.execute_on(&self.services.client.sender, &server, request)
 			.await;
 
+		let metrics = &self.server.metrics;
+		metrics
+			.federation_transaction_durations
+			.observe(server.as_str(), started.elapsed());
+
+		if result.is_err() {
+			metrics
+				.federation_transaction_failures
+				.increment(server.as_str());
+		}
+
 		for (event_id, result) in result.iter().flat_map(|resp| resp.pdus.iter()) {
 			if let Err(e) = result {
 				warn!(
@@ -889,6 +1087,43 @@ pub async fn convert_to_outgoing_federation_event(
 		&self,
 		mut pdu_json: CanonicalJsonObject,
 	) -> Box<RawJsonValue> {
+		self.strip_outgoing_federation_event(&mut pdu_json).await;
+
+		// TODO: another option would be to convert it to a canonical string to validate
+		// size and return a Result<Raw<...>>
+		// serde_json::from_str::<Raw<_>>(
+		//     ruma::serde::to_canonical_json_string(pdu_json).expect("CanonicalJson is
+		// valid serde_json::Value"), )
+		// .expect("Raw::from_value always works")
+
+		to_raw_value(&pdu_json).expect("CanonicalJson is valid serde_json::Value")
+	}
+
+	/// Converts many events at once, like the state of a room for a join.
+	/// Serializing them is done on the blocking pool so large rooms don't stall
+	/// the async workers.
+	pub async fn convert_to_outgoing_federation_events(
+		&self,
+		mut pdus: Vec<CanonicalJsonObject>,
+	) -> Result<Vec<Box<RawJsonValue>>> {
+		for pdu_json in &mut pdus {
+			self.strip_outgoing_federation_event(pdu_json).await;
+		}
+
+		self.server
+			.blocking
+			.spawn("serialize_outgoing_events", move || {
+				pdus.iter()
+					.map(|pdu_json| {
+						to_raw_value(pdu_json).expect("CanonicalJson is valid serde_json::Value")
+					})
+					.collect()
+			})
+			.await
+	}
+
+	/// Removes the fields which aren't sent over federation.
+	async fn strip_outgoing_federation_event(&self, pdu_json: &mut CanonicalJsonObject) {
 		if let Some(unsigned) = pdu_json
 			.get_mut("unsigned")
 			.and_then(|val| val.as_object_mut())
@@ -911,14 +1146,5 @@ pub async fn convert_to_outgoing_federation_event(
 		} else {
 			pdu_json.remove("event_id");
 		}
-
-		// TODO: another option would be to convert it to a canonical string to validate
-		// size and return a Result<Raw<...>>
-		// serde_json::from_str::<Raw<_>>(
-		//     ruma::serde::to_canonical_json_string(pdu_json).expect("CanonicalJson is
-		// valid serde_json::Value"), )
-		// .expect("Raw::from_value always works")
-
-		to_raw_value(&pdu_json).expect("CanonicalJson is valid serde_json::Value")
 	}
 }
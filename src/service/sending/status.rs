@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use conduwuit::{implement, Error};
+use futures::StreamExt;
+
+use super::Destination;
+
+/// Delivery to an appservice since startup.
+#[derive(Clone, Debug, Default)]
+pub struct AppserviceStatus {
+	/// Transactions the appservice accepted.
+	pub delivered: u64,
+
+	/// Attempts which failed since the last accepted transaction.
+	pub failures: u32,
+
+	pub last_success: Option<SystemTime>,
+	pub last_error: Option<(SystemTime, String)>,
+
+	/// When the failed transaction is attempted again.
+	pub next_retry: Option<SystemTime>,
+}
+
+/// Transactions waiting for an appservice.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueueDepth {
+	/// Events in the transaction being sent or retried.
+	pub active: usize,
+
+	/// Events waiting for it to succeed.
+	pub queued: usize,
+}
+
+#[implement(super::Service)]
+#[must_use]
+pub fn appservice_status(&self, appservice_id: &str) -> AppserviceStatus {
+	self.appservice_status
+		.lock()
+		.expect("locked")
+		.get(appservice_id)
+		.cloned()
+		.unwrap_or_default()
+}
+
+#[implement(super::Service)]
+pub async fn appservice_queue_depth(&self, appservice_id: &str) -> QueueDepth {
+	let dest = Destination::Appservice(appservice_id.to_owned());
+	let active = self.db.active_requests_for(&dest).count();
+	let queued = self.db.queued_requests(&dest).count();
+	let (active, queued) = futures::join!(active, queued);
+
+	QueueDepth { active, queued }
+}
+
+#[implement(super::Service)]
+pub(super) fn record_appservice_success(&self, appservice_id: &str) {
+	let mut statuses = self.appservice_status.lock().expect("locked");
+	let status = statuses.entry(appservice_id.to_owned()).or_default();
+	status.delivered = status.delivered.saturating_add(1);
+	status.failures = 0;
+	status.last_success = Some(SystemTime::now());
+	status.next_retry = None;
+}
+
+#[implement(super::Service)]
+pub(super) fn record_appservice_failure(
+	&self,
+	appservice_id: &str,
+	error: &Error,
+	retry_in: Duration,
+) {
+	let now = SystemTime::now();
+	let mut statuses = self.appservice_status.lock().expect("locked");
+	let status = statuses.entry(appservice_id.to_owned()).or_default();
+	status.failures = status.failures.saturating_add(1);
+	status.last_error = Some((now, error.to_string()));
+	status.next_retry = now.checked_add(retry_in);
+}
+
+/// Notes a failed push, returning for how long pushes to the pusher have been
+/// failing.
+#[implement(super::Service)]
+pub(super) fn record_push_failure(&self, dest: &Destination) -> Duration {
+	self.push_failing_since
+		.lock()
+		.expect("locked")
+		.entry(dest.clone())
+		.or_insert_with(Instant::now)
+		.elapsed()
+}
+
+#[implement(super::Service)]
+pub(super) fn record_push_success(&self, dest: &Destination) {
+	self.push_failing_since.lock().expect("locked").remove(dest);
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
-use conduwuit::{debug, debug_info, err, error, utils, utils::string_from_bytes, Result};
-use database::Database;
+use conduwuit::{debug, debug_info, err, error, utils, utils::string_from_bytes, Err, Result};
+use database::{Database, Map};
 use ruma::{api::federation::discovery::VerifyKey, serde::Base64, signatures::Ed25519KeyPair};
 
 use super::VerifyKeys;
@@ -63,3 +63,55 @@ fn remove(db: &Arc<Database>) {
 	let global = &db["global"];
 	global.remove(b"keypair");
 }
+
+/// DER encoding of a PKCS#8 v1 Ed25519 private key up to the seed, which is the
+/// only field that follows (RFC 8410).
+const PKCS8_SEED_PREFIX: [u8; 16] = [
+	0x30, 0x2E, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2B, 0x65, 0x70, 0x04, 0x22, 0x04,
+	0x20,
+];
+
+/// Returns the version and 32-byte seed of the stored keypair.
+pub(super) fn export(global: &Map) -> Result<(String, Vec<u8>)> {
+	let val = global
+		.get_blocking(b"keypair")
+		.map_err(|e| err!(Database("Failed to read keypair: {e}")))?;
+
+	let vlen = val
+		.iter()
+		.position(|&b| b == b'\xFF')
+		.ok_or_else(|| err!(Database("Invalid keypair entry")))?;
+
+	let version = string_from_bytes(&val[..vlen])?;
+	let der = &val[vlen.saturating_add(1)..];
+
+	// Both PKCS#8 versions wrap the seed in the same OCTET STRING
+	let marker = &PKCS8_SEED_PREFIX[12..];
+	let seed = der
+		.windows(marker.len())
+		.position(|window| window == marker)
+		.map(|pos| pos.saturating_add(marker.len()))
+		.and_then(|start| der.get(start..start.saturating_add(32)))
+		.ok_or_else(|| err!(Database("Invalid keypair DER")))?;
+
+	Ok((version, seed.to_vec()))
+}
+
+/// Replaces the stored keypair with one made from a 32-byte seed. The server
+/// keeps signing with the loaded keypair until it restarts.
+pub(super) fn import(global: &Map, version: &str, seed: &[u8]) -> Result {
+	let len = seed.len();
+	if len != 32 {
+		return Err!(Request(InvalidParam("Ed25519 seeds are 32 bytes, got {len}.")));
+	}
+
+	let der = [PKCS8_SEED_PREFIX.as_slice(), seed].concat();
+	Ed25519KeyPair::from_der(&der, version.to_owned())
+		.map_err(|e| err!(Request(InvalidParam("Invalid ed25519 key: {e:?}"))))?;
+
+	let value: (&str, Vec<u8>) = (version, der);
+	global.raw_put(b"keypair", &value);
+	debug_info!("Imported Ed25519 keypair: {version:?}");
+
+	Ok(())
+}
@@ -5,23 +5,27 @@
 mod sign;
 mod verify;
 
-use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use std::{collections::BTreeMap, path::Path, sync::Arc, time::Duration};
 
 use conduwuit::{
-	implement,
+	err, implement,
 	utils::{timepoint_from_now, IterStream},
-	Result, Server,
+	Err, Result, Server,
 };
 use database::{Deserialized, Json, Map};
 use futures::StreamExt;
 use ruma::{
 	api::federation::discovery::{ServerSigningKeys, VerifyKey},
-	serde::Raw,
+	serde::{base64::Standard, Base64, Raw},
 	signatures::{Ed25519KeyPair, PublicKeyMap, PublicKeySet},
 	CanonicalJsonObject, MilliSecondsSinceUnixEpoch, OwnedServerSigningKeyId, RoomVersionId,
 	ServerName, ServerSigningKeyId,
 };
 use serde_json::value::RawValue as RawJsonValue;
+use tokio::{
+	fs::{self, OpenOptions},
+	io::AsyncWriteExt,
+};
 
 use crate::{globals, sending, Dep};
 
@@ -40,6 +44,7 @@ struct Services {
 }
 
 struct Data {
+	global: Arc<Map>,
 	server_signingkeys: Arc<Map>,
 }
 
@@ -64,6 +69,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				server: args.server.clone(),
 			},
 			db: Data {
+				global: args.db["global"].clone(),
 				server_signingkeys: args.db["server_signingkeys"].clone(),
 			},
 		}))
@@ -91,6 +97,60 @@ pub fn active_verify_key(&self) -> (&ServerSigningKeyId, &VerifyKey) {
 		.expect("missing active verify_key")
 }
 
+/// Writes the stored signing key to a new file, readable only by the server's
+/// user, in the format of Synapse's `signing.key` file: `ed25519 <version>
+/// <unpadded base64 seed>`. The key never leaves the server otherwise.
+#[implement(Service)]
+pub async fn export_signing_key(&self, path: &Path) -> Result {
+	let (version, seed) = keypair::export(&self.db.global)?;
+	let key = format!("ed25519 {version} {}\n", Base64::<Standard>::new(seed).encode());
+
+	let mut options = OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	options.mode(0o600);
+
+	let mut file = options
+		.open(path)
+		.await
+		.map_err(|e| err!("Failed to create {}: {e}", path.display()))?;
+
+	file.write_all(key.as_bytes()).await?;
+	file.sync_all().await?;
+
+	Ok(())
+}
+
+/// Stores the signing key read from a file in the format written by
+/// `export_signing_key`, which is used after the next restart. Returns the ID
+/// of the imported key.
+#[implement(Service)]
+pub async fn import_signing_key(&self, path: &Path) -> Result<OwnedServerSigningKeyId> {
+	let key = fs::read_to_string(path)
+		.await
+		.map_err(|e| err!("Failed to read {}: {e}", path.display()))?;
+
+	let mut parts = key.split_whitespace();
+	let (Some("ed25519"), Some(version), Some(seed), None) =
+		(parts.next(), parts.next(), parts.next(), parts.next())
+	else {
+		return Err!(Request(InvalidParam(
+			"Expected a key in the format `ed25519 <version> <base64 seed>`."
+		)));
+	};
+
+	let key_id = format!("ed25519:{version}")
+		.try_into()
+		.map_err(|e| err!(Request(InvalidParam("Invalid key version: {e}"))))?;
+
+	let seed = Base64::<Standard>::parse(seed)
+		.map_err(|e| err!(Request(InvalidParam("Invalid base64 seed: {e}"))))?;
+
+	keypair::import(&self.db.global, version, seed.as_bytes())?;
+
+	Ok(key_id)
+}
+
 #[implement(Service)]
 async fn add_signing_keys(&self, new_keys: ServerSigningKeys) {
 	let origin = &new_keys.server_name;
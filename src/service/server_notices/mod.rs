@@ -0,0 +1,418 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use conduwuit::{
+	debug_warn, err, implement, info,
+	pdu::PduBuilder,
+	utils::{MutexMap, ReadyExt},
+	Err, Result, Server,
+};
+use database::{Deserialized, Map};
+use futures::StreamExt;
+use loole::{Receiver, Sender};
+use ruma::{
+	events::{
+		room::{
+			create::RoomCreateEventContent,
+			history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
+			join_rules::{JoinRule, RoomJoinRulesEventContent},
+			member::{MembershipState, RoomMemberEventContent},
+			message::RoomMessageEventContent,
+			name::RoomNameEventContent,
+			power_levels::RoomPowerLevelsEventContent,
+		},
+		tag::{TagEvent, TagEventContent, TagInfo},
+		RoomAccountDataEventType,
+	},
+	int, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UserId,
+};
+
+use crate::{account_data, admin, globals, rooms, users, Dep};
+
+pub struct Service {
+	announce_channel: (Sender<String>, Receiver<String>),
+	/// Serializes finding or creating each user's notices room, so concurrent
+	/// notices don't create one room each.
+	room_mutex: MutexMap<OwnedUserId, ()>,
+	services: Services,
+	db: Data,
+}
+
+struct Services {
+	server: Arc<Server>,
+	account_data: Dep<account_data::Service>,
+	admin: Dep<admin::Service>,
+	globals: Dep<globals::Service>,
+	short: Dep<rooms::short::Service>,
+	state: Dep<rooms::state::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	timeline: Dep<rooms::timeline::Service>,
+	users: Dep<users::Service>,
+}
+
+struct Data {
+	userid_servernoticesroomid: Arc<Map>,
+}
+
+/// Room tag clients use to present the room as server notices.
+const SERVER_NOTICE_TAG: &str = "m.server_notice";
+
+/// How many users an announcement is sent to between progress reports.
+const ANNOUNCE_PROGRESS_INTERVAL: usize = 100;
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			announce_channel: loole::unbounded(),
+			room_mutex: MutexMap::new(),
+			services: Services {
+				server: args.server.clone(),
+				account_data: args.depend::<account_data::Service>("account_data"),
+				admin: args.depend::<admin::Service>("admin"),
+				globals: args.depend::<globals::Service>("globals"),
+				short: args.depend::<rooms::short::Service>("rooms::short"),
+				state: args.depend::<rooms::state::Service>("rooms::state"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
+				users: args.depend::<users::Service>("users"),
+			},
+			db: Data {
+				userid_servernoticesroomid: args.db["userid_servernoticesroomid"].clone(),
+			},
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		let receiver = self.announce_channel.1.clone();
+		while let Ok(body) = receiver.recv_async().await {
+			self.run_announcement(&body).await;
+		}
+
+		Ok(())
+	}
+
+	fn interrupt(&self) {
+		let (sender, _) = &self.announce_channel;
+		if !sender.is_closed() {
+			sender.close();
+		}
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Sends a server notice to a local user, creating their notices room first if
+/// they don't have one yet. The user is (re-)invited if they left it.
+#[implement(Service)]
+pub async fn send_notice(&self, user_id: &UserId, body: &str) -> Result<OwnedEventId> {
+	let notices_user = self.notices_user().await?;
+	if *user_id == *notices_user {
+		return Err!("Cannot send server notices to the server notices user itself.");
+	}
+
+	if !self.services.users.is_active_local(user_id).await {
+		return Err!("User {user_id} is not an active local user.");
+	}
+
+	let room_lock = self.room_mutex.lock(user_id).await;
+	let room_id = match self.get_notices_room(user_id).await {
+		| Ok(room_id) => room_id,
+		| Err(_) => self.create_notices_room(&notices_user, user_id).await?,
+	};
+	drop(room_lock);
+
+	let state_lock = self.services.state.mutex.lock(&room_id).await;
+
+	let in_room = self.services.state_cache.is_joined(user_id, &room_id).await
+		|| self
+			.services
+			.state_cache
+			.is_invited(user_id, &room_id)
+			.await;
+
+	if !in_room {
+		self.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(
+					user_id.to_string(),
+					&RoomMemberEventContent::new(MembershipState::Invite),
+				),
+				&notices_user,
+				&room_id,
+				&state_lock,
+			)
+			.await?;
+	}
+
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::timeline(&RoomMessageEventContent::notice_markdown(body)),
+			&notices_user,
+			&room_id,
+			&state_lock,
+		)
+		.await
+}
+
+/// Queues an announcement to every active local user. It is sent in the
+/// background at the configured pace, with progress reported to the admin
+/// room.
+#[implement(Service)]
+pub fn announce(&self, body: String) -> Result {
+	self.announce_channel
+		.0
+		.send(body)
+		.map_err(|e| err!("Failed to queue announcement: {e}"))
+}
+
+#[implement(Service)]
+async fn run_announcement(&self, body: &str) {
+	let recipients = match self.notice_recipients().await {
+		| Ok(recipients) => recipients,
+		| Err(e) => {
+			self.services
+				.admin
+				.send_text(&format!("Failed to start announcement: {e}"))
+				.await;
+			return;
+		},
+	};
+
+	let total = recipients.len();
+	let interval = Duration::from_millis(
+		self.services
+			.server
+			.config
+			.server_notices_announce_interval_ms,
+	);
+
+	info!("Sending announcement to {total} users");
+	let (mut sent, mut failed) = (0_usize, 0_usize);
+	for (i, user_id) in recipients.iter().enumerate() {
+		if !self.services.server.running() {
+			break;
+		}
+
+		match self.send_notice(user_id, body).await {
+			| Ok(_) => sent = sent.saturating_add(1),
+			| Err(e) => {
+				debug_warn!(%user_id, "Failed to send announcement: {e}");
+				failed = failed.saturating_add(1);
+			},
+		}
+
+		let done = i.saturating_add(1);
+		if done % ANNOUNCE_PROGRESS_INTERVAL == 0 && done < total {
+			self.services
+				.admin
+				.send_text(&format!("Announcement progress: {done}/{total} users"))
+				.await;
+		}
+
+		tokio::time::sleep(interval).await;
+	}
+
+	self.services
+		.admin
+		.send_text(&format!(
+			"Announcement finished: sent to {sent} of {total} users, {failed} failed."
+		))
+		.await;
+}
+
+/// Active local users, except the notices user and the server user.
+#[implement(Service)]
+async fn notice_recipients(&self) -> Result<Vec<OwnedUserId>> {
+	let notices_user = self.notices_user().await?;
+	let recipients = self
+		.services
+		.users
+		.list_local_users()
+		.ready_filter(|user_id| **user_id != *notices_user)
+		.ready_filter(|user_id| **user_id != *self.services.globals.server_user)
+		.filter_map(|user_id| async move {
+			self.services
+				.users
+				.is_active_local(user_id)
+				.await
+				.then(|| user_id.to_owned())
+		})
+		.collect()
+		.await;
+
+	Ok(recipients)
+}
+
+#[implement(Service)]
+pub async fn get_notices_room(&self, user_id: &UserId) -> Result<OwnedRoomId> {
+	self.db
+		.userid_servernoticesroomid
+		.get(user_id)
+		.await
+		.deserialized()
+}
+
+/// The user notices are sent from; the server user unless another local user
+/// is configured. A configured user is created on first use.
+#[implement(Service)]
+async fn notices_user(&self) -> Result<OwnedUserId> {
+	let Some(localpart) = self
+		.services
+		.server
+		.config
+		.server_notices_localpart
+		.as_ref()
+	else {
+		return Ok(self.services.globals.server_user.clone());
+	};
+
+	let user_id =
+		UserId::parse_with_server_name(localpart.as_str(), self.services.globals.server_name())?;
+	if !self.services.users.exists(&user_id).await {
+		self.services.users.create(&user_id, None)?;
+		self.services
+			.users
+			.set_displayname(&user_id, Some("Server Notices".to_owned()));
+	}
+
+	Ok(user_id)
+}
+
+#[implement(Service)]
+async fn create_notices_room(
+	&self,
+	notices_user: &UserId,
+	user_id: &UserId,
+) -> Result<OwnedRoomId> {
+	let room_id = RoomId::new(self.services.globals.server_name());
+	let room_version = &self.services.server.config.default_room_version;
+
+	let _short_id = self
+		.services
+		.short
+		.get_or_create_shortroomid(&room_id)
+		.await;
+
+	let state_lock = self.services.state.mutex.lock(&room_id).await;
+
+	let create_content = {
+		use RoomVersionId::*;
+		match room_version {
+			| V1 | V2 | V3 | V4 | V5 | V6 | V7 | V8 | V9 | V10 =>
+				RoomCreateEventContent::new_v1(notices_user.to_owned()),
+			| _ => RoomCreateEventContent::new_v11(),
+		}
+	};
+
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(String::new(), &RoomCreateEventContent {
+				federate: false,
+				room_version: room_version.clone(),
+				..create_content
+			}),
+			notices_user,
+			&room_id,
+			&state_lock,
+		)
+		.await?;
+
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(
+				notices_user.to_string(),
+				&RoomMemberEventContent::new(MembershipState::Join),
+			),
+			notices_user,
+			&room_id,
+			&state_lock,
+		)
+		.await?;
+
+	// Only the notices user may speak in the room.
+	let users = BTreeMap::from_iter([(notices_user.to_owned(), int!(100))]);
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(String::new(), &RoomPowerLevelsEventContent {
+				users,
+				events_default: int!(100),
+				..Default::default()
+			}),
+			notices_user,
+			&room_id,
+			&state_lock,
+		)
+		.await?;
+
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(String::new(), &RoomJoinRulesEventContent::new(JoinRule::Invite)),
+			notices_user,
+			&room_id,
+			&state_lock,
+		)
+		.await?;
+
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(
+				String::new(),
+				&RoomHistoryVisibilityEventContent::new(HistoryVisibility::Shared),
+			),
+			notices_user,
+			&room_id,
+			&state_lock,
+		)
+		.await?;
+
+	let room_name = self.services.server.config.server_notices_room_name.clone();
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(String::new(), &RoomNameEventContent::new(room_name)),
+			notices_user,
+			&room_id,
+			&state_lock,
+		)
+		.await?;
+
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(
+				user_id.to_string(),
+				&RoomMemberEventContent::new(MembershipState::Invite),
+			),
+			notices_user,
+			&room_id,
+			&state_lock,
+		)
+		.await?;
+
+	let mut tags = BTreeMap::new();
+	tags.insert(SERVER_NOTICE_TAG.into(), TagInfo::new());
+	let tag_event = TagEvent { content: TagEventContent { tags } };
+	self.services
+		.account_data
+		.update(
+			Some(&room_id),
+			user_id,
+			RoomAccountDataEventType::Tag,
+			&serde_json::to_value(tag_event).expect("to json value always works"),
+		)
+		.await?;
+
+	self.db
+		.userid_servernoticesroomid
+		.insert(user_id, room_id.as_bytes());
+
+	Ok(room_id)
+}
@@ -2,13 +2,19 @@
 	any::Any,
 	collections::BTreeMap,
 	fmt::Write,
+	hash::Hash,
 	ops::Deref,
-	sync::{Arc, OnceLock, RwLock, Weak},
+	sync::{Arc, Mutex as StdMutex, OnceLock, RwLock, Weak},
 };
 
 use async_trait::async_trait;
-use conduwuit::{err, error::inspect_log, utils::string::SplitInfallible, Err, Result, Server};
+use conduwuit::{
+	err, error::inspect_log, utils::string::SplitInfallible, Config, Err, Result, Server,
+};
 use database::Database;
+use tokio::sync::Mutex;
+
+use crate::{CacheStats, LruCache};
 
 /// Abstract interface for a Service
 #[async_trait]
@@ -36,11 +42,67 @@ fn clear_cache(&self) {}
 	/// Memory usage report in a markdown string.
 	fn memory_usage(&self, _out: &mut dyn Write) -> Result<()> { Ok(()) }
 
+	/// Caches whose capacity can be changed at runtime.
+	fn caches(&self) -> Vec<Cache<'_>> { Vec::new() }
+
 	/// Return the name of the service.
 	/// i.e. `crate::service::make_name(std::module_path!())`
 	fn name(&self) -> &str;
 }
 
+/// A cache reported by `Service::caches`.
+pub(crate) struct Cache<'a> {
+	/// Unique name used to address the cache from the admin room.
+	pub(crate) name: &'static str,
+
+	/// Configured capacity, before `cache_capacity_modifier` is applied.
+	pub(crate) base_capacity: fn(&Config) -> u32,
+
+	pub(crate) cache: &'a dyn Resizable,
+}
+
+/// A cache whose capacity can be changed while the server is running.
+#[async_trait]
+pub(crate) trait Resizable: Send + Sync {
+	/// Entries, capacity and lookups since startup.
+	async fn stats(&self) -> CacheStats;
+
+	/// Changes the capacity in place, evicting the least recently used entries
+	/// if it shrinks.
+	async fn set_capacity(&self, capacity: usize);
+
+	/// Removes every entry.
+	async fn clear(&self);
+}
+
+#[async_trait]
+impl<K, V> Resizable for StdMutex<LruCache<K, V>>
+where
+	K: Eq + Hash + Send,
+	V: Send,
+{
+	async fn stats(&self) -> CacheStats { self.lock().expect("locked").stats() }
+
+	async fn set_capacity(&self, capacity: usize) {
+		self.lock().expect("locked").set_capacity(capacity);
+	}
+
+	async fn clear(&self) { self.lock().expect("locked").clear(); }
+}
+
+#[async_trait]
+impl<K, V> Resizable for Mutex<LruCache<K, V>>
+where
+	K: Eq + Hash + Send,
+	V: Send,
+{
+	async fn stats(&self) -> CacheStats { self.lock().await.stats() }
+
+	async fn set_capacity(&self, capacity: usize) { self.lock().await.set_capacity(capacity); }
+
+	async fn clear(&self) { self.lock().await.clear(); }
+}
+
 /// Args are passed to `Service::build` when a service is constructed. This
 /// allows for arguments to change with limited impact to the many services.
 pub(crate) struct Args<'a> {
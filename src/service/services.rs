@@ -5,16 +5,21 @@
 	sync::{Arc, RwLock},
 };
 
-use conduwuit::{debug, debug_info, info, trace, Result, Server};
+use conduwuit::{
+	debug, debug_info, info, result::LogErr, trace, utils::math::usize_from_f64, Err, Result,
+	Server,
+};
 use database::Database;
-use tokio::sync::Mutex;
+use tokio::{sync::Mutex, task::JoinHandle};
 
 use crate::{
-	account_data, admin, appservice, client, config, emergency, federation, globals, key_backups,
+	account_data, admin, appservice, client, config, deactivation, delayed_events, email,
+	emergency, federation, globals, jobs, key_backups,
 	manager::Manager,
-	media, presence, pusher, resolver, rooms, sending, server_keys, service,
+	media, moderation, onboarding, presence, pusher, rate_limiting, registration_tokens, reports,
+	resolver, rooms, sending, server_keys, server_notices, service,
 	service::{Args, Map, Service},
-	sync, transaction_ids, uiaa, updates, users,
+	sso, sync, transaction_ids, uiaa, updates, users, CacheStats,
 };
 
 pub struct Services {
@@ -23,17 +28,28 @@ pub struct Services {
 	pub appservice: Arc<appservice::Service>,
 	pub config: Arc<config::Service>,
 	pub client: Arc<client::Service>,
+	pub deactivation: Arc<deactivation::Service>,
+	pub delayed_events: Arc<delayed_events::Service>,
+	pub email: Arc<email::Service>,
 	pub emergency: Arc<emergency::Service>,
 	pub globals: Arc<globals::Service>,
+	pub jobs: Arc<jobs::Service>,
 	pub key_backups: Arc<key_backups::Service>,
 	pub media: Arc<media::Service>,
+	pub moderation: Arc<moderation::Service>,
+	pub onboarding: Arc<onboarding::Service>,
 	pub presence: Arc<presence::Service>,
 	pub pusher: Arc<pusher::Service>,
+	pub rate_limiting: Arc<rate_limiting::Service>,
+	pub registration_tokens: Arc<registration_tokens::Service>,
+	pub reports: Arc<reports::Service>,
 	pub resolver: Arc<resolver::Service>,
 	pub rooms: rooms::Service,
 	pub federation: Arc<federation::Service>,
 	pub sending: Arc<sending::Service>,
 	pub server_keys: Arc<server_keys::Service>,
+	pub server_notices: Arc<server_notices::Service>,
+	pub sso: Arc<sso::Service>,
 	pub sync: Arc<sync::Service>,
 	pub transaction_ids: Arc<transaction_ids::Service>,
 	pub uiaa: Arc<uiaa::Service>,
@@ -41,6 +57,7 @@ pub struct Services {
 	pub users: Arc<users::Service>,
 
 	manager: Mutex<Option<Arc<Manager>>>,
+	migrations: Mutex<Option<JoinHandle<()>>>,
 	pub(crate) service: Arc<Map>,
 	pub server: Arc<Server>,
 	pub db: Arc<Database>,
@@ -70,20 +87,31 @@ macro_rules! build {
 			resolver: build!(resolver::Service),
 			client: build!(client::Service),
 			config: build!(config::Service),
+			deactivation: build!(deactivation::Service),
+			delayed_events: build!(delayed_events::Service),
+			email: build!(email::Service),
 			emergency: build!(emergency::Service),
 			globals: build!(globals::Service),
+			jobs: build!(jobs::Service),
 			key_backups: build!(key_backups::Service),
 			media: build!(media::Service),
+			moderation: build!(moderation::Service),
+			onboarding: build!(onboarding::Service),
 			presence: build!(presence::Service),
 			pusher: build!(pusher::Service),
+			rate_limiting: build!(rate_limiting::Service),
+			registration_tokens: build!(registration_tokens::Service),
+			reports: build!(reports::Service),
 			rooms: rooms::Service {
 				alias: build!(rooms::alias::Service),
 				auth_chain: build!(rooms::auth_chain::Service),
+				auto_accept: build!(rooms::auto_accept::Service),
 				directory: build!(rooms::directory::Service),
 				event_handler: build!(rooms::event_handler::Service),
 				lazy_loading: build!(rooms::lazy_loading::Service),
 				metadata: build!(rooms::metadata::Service),
 				outlier: build!(rooms::outlier::Service),
+				partial_state: build!(rooms::partial_state::Service),
 				pdu_metadata: build!(rooms::pdu_metadata::Service),
 				read_receipt: build!(rooms::read_receipt::Service),
 				search: build!(rooms::search::Service),
@@ -93,6 +121,7 @@ macro_rules! build {
 				state_accessor: build!(rooms::state_accessor::Service),
 				state_cache: build!(rooms::state_cache::Service),
 				state_compressor: build!(rooms::state_compressor::Service),
+				stats: build!(rooms::stats::Service),
 				threads: build!(rooms::threads::Service),
 				timeline: build!(rooms::timeline::Service),
 				typing: build!(rooms::typing::Service),
@@ -101,6 +130,8 @@ macro_rules! build {
 			federation: build!(federation::Service),
 			sending: build!(sending::Service),
 			server_keys: build!(server_keys::Service),
+			server_notices: build!(server_notices::Service),
+			sso: build!(sso::Service),
 			sync: build!(sync::Service),
 			transaction_ids: build!(transaction_ids::Service),
 			uiaa: build!(uiaa::Service),
@@ -108,6 +139,7 @@ macro_rules! build {
 			users: build!(users::Service),
 
 			manager: Mutex::new(None),
+			migrations: Mutex::new(None),
 			service,
 			server,
 			db,
@@ -118,7 +150,17 @@ pub async fn start(self: &Arc<Self>) -> Result<Arc<Self>> {
 		debug_info!("Starting services...");
 
 		self.admin.set_services(Some(Arc::clone(self)).as_ref());
+		self.rooms
+			.auto_accept
+			.set_services(Some(Arc::clone(self)).as_ref());
+		self.deactivation
+			.set_services(Some(Arc::clone(self)).as_ref());
 		super::migrations::migrations(self).await?;
+		if self.server.config.migrations_dry_run {
+			debug_info!("Services startup skipped for the dry run.");
+			return Ok(Arc::clone(self));
+		}
+
 		self.manager
 			.lock()
 			.await
@@ -127,6 +169,18 @@ pub async fn start(self: &Arc<Self>) -> Result<Arc<Self>> {
 			.start()
 			.await?;
 
+		// Long migrations carry on while the server is up; they stop at shutdown,
+		// which waits for them in stop().
+		let services = Arc::clone(self);
+		let migrations = self.server.runtime().spawn(async move {
+			super::migrations::background_migrations(&services)
+				.await
+				.log_err()
+				.ok();
+		});
+
+		_ = self.migrations.lock().await.insert(migrations);
+
 		// reset dormant online/away statuses to offline, and set the server user as
 		// online
 		if self.server.config.allow_local_presence && !self.db.is_read_only() {
@@ -145,7 +199,10 @@ pub async fn stop(&self) {
 		info!("Shutting down services...");
 
 		// set the server user as offline
-		if self.server.config.allow_local_presence && !self.db.is_read_only() {
+		if self.server.config.allow_local_presence
+			&& !self.server.config.migrations_dry_run
+			&& !self.db.is_read_only()
+		{
 			_ = self
 				.presence
 				.ping_presence(&self.globals.server_user, &ruma::presence::PresenceState::Offline)
@@ -153,11 +210,17 @@ pub async fn stop(&self) {
 		}
 
 		self.interrupt();
+		if let Some(migrations) = self.migrations.lock().await.take() {
+			migrations.await.log_err().ok();
+		}
+
 		if let Some(manager) = self.manager.lock().await.as_ref() {
 			manager.stop().await;
 		}
 
 		self.admin.set_services(None);
+		self.rooms.auto_accept.set_services(None);
+		self.deactivation.set_services(None);
 
 		debug_info!("Services shutdown complete.");
 	}
@@ -184,6 +247,12 @@ pub async fn clear_cache(&self) {
 			.lock()
 			.await
 			.clear();
+		self.rooms
+			.spaces
+			.roomid_spacehierarchy_children_cache
+			.lock()
+			.await
+			.clear();
 	}
 
 	pub async fn memory_usage(&self) -> Result<String> {
@@ -204,9 +273,91 @@ pub async fn memory_usage(&self) -> Result<String> {
 			.len();
 		writeln!(out, "roomid_spacehierarchy_cache: {roomid_spacehierarchy_cache}")?;
 
+		let roomid_spacehierarchy_children_cache = self
+			.rooms
+			.spaces
+			.roomid_spacehierarchy_children_cache
+			.lock()
+			.await
+			.len();
+		writeln!(
+			out,
+			"roomid_spacehierarchy_children_cache: {roomid_spacehierarchy_children_cache}"
+		)?;
+
 		Ok(out)
 	}
 
+	/// Name and usage of every resizable cache.
+	pub async fn caches(&self) -> Vec<(&'static str, CacheStats)> {
+		let mut out = Vec::new();
+		for service in self.services() {
+			for cache in service.caches() {
+				out.push((cache.name, cache.cache.stats().await));
+			}
+		}
+
+		out
+	}
+
+	/// Changes the capacity of a single cache in place.
+	pub async fn set_cache_capacity(&self, name: &str, capacity: usize) -> Result {
+		for service in self.services() {
+			if let Some(cache) = service
+				.caches()
+				.into_iter()
+				.find(|cache| cache.name == name)
+			{
+				cache.cache.set_capacity(capacity).await;
+				return Ok(());
+			}
+		}
+
+		Err!("No cache named {name:?}.")
+	}
+
+	/// Removes every entry of a single cache.
+	pub async fn clear_cache_named(&self, name: &str) -> Result {
+		for service in self.services() {
+			if let Some(cache) = service
+				.caches()
+				.into_iter()
+				.find(|cache| cache.name == name)
+			{
+				cache.cache.clear().await;
+				return Ok(());
+			}
+		}
+
+		Err!("No cache named {name:?}.")
+	}
+
+	/// Resizes every cache to its configured capacity scaled by the modifier,
+	/// returning how many were resized.
+	pub async fn resize_caches(&self, modifier: f64) -> Result<usize> {
+		let config = self.server.config.clone();
+		let mut resized: usize = 0;
+		for service in self.services() {
+			for cache in service.caches() {
+				let base = f64::from((cache.base_capacity)(&config));
+				let capacity = usize_from_f64(base * modifier)?;
+				cache.cache.set_capacity(capacity).await;
+				resized = resized.saturating_add(1);
+			}
+		}
+
+		Ok(resized)
+	}
+
+	fn services(&self) -> Vec<Arc<dyn Service>> {
+		self.service
+			.read()
+			.expect("locked for reading")
+			.values()
+			.filter_map(|(service, ..)| service.upgrade())
+			.collect()
+	}
+
 	fn interrupt(&self) {
 		debug!("Interrupting services...");
 		for (name, (service, ..)) in self.service.read().expect("locked for reading").iter() {
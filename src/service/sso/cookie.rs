@@ -0,0 +1,73 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use conduwuit::{implement, utils::time::now_millis, Err, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::CALLBACK_PATH;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Name of the cookie tying a login attempt to the browser which started it.
+const COOKIE_NAME: &str = "conduwuit_sso_session";
+
+/// Returns the `Set-Cookie` value binding the login attempt to the browser.
+/// The identity provider's callback is only accepted along with it, so a
+/// callback URL leaked from one browser can't be completed in another.
+#[implement(super::Service)]
+pub(super) fn session_cookie(&self, state: &str) -> String {
+	let lifetime = self.session_lifetime().as_secs();
+	let expires = now_millis() / 1000 + lifetime;
+	let signature = URL_SAFE_NO_PAD.encode(self.sign(state, expires).finalize().into_bytes());
+
+	format!(
+		"{COOKIE_NAME}={expires}.{signature}; Path={CALLBACK_PATH}; Max-Age={lifetime}; \
+		 HttpOnly; Secure; SameSite=Lax"
+	)
+}
+
+/// Checks the cookie set when the login attempt started is present, signed
+/// by us for the same attempt and not expired.
+#[implement(super::Service)]
+pub(super) fn verify_session_cookie(&self, cookies: Option<&str>, state: &str) -> Result {
+	let Some(value) = cookies
+		.into_iter()
+		.flat_map(|cookies| cookies.split(';'))
+		.find_map(|cookie| {
+			cookie
+				.trim()
+				.strip_prefix(COOKIE_NAME)
+				.and_then(|cookie| cookie.strip_prefix('='))
+		})
+	else {
+		return Err!(Request(Forbidden("The login was started in another browser.")));
+	};
+
+	let Some((expires, signature)) = value.split_once('.').and_then(|(expires, signature)| {
+		let expires = expires.parse::<u64>().ok()?;
+		let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+		Some((expires, signature))
+	}) else {
+		return Err!(Request(Forbidden("Malformed login session cookie.")));
+	};
+
+	if self.sign(state, expires).verify_slice(&signature).is_err() {
+		return Err!(Request(Forbidden("The login was started in another browser.")));
+	}
+
+	if expires < now_millis() / 1000 {
+		return Err!(Request(Forbidden("The login session expired.")));
+	}
+
+	Ok(())
+}
+
+#[implement(super::Service)]
+fn sign(&self, state: &str, expires: u64) -> HmacSha256 {
+	let mut mac = HmacSha256::new_from_slice(self.cookie_key.as_bytes())
+		.expect("HMAC can take key of any size");
+
+	mac.update(state.as_bytes());
+	mac.update(b".");
+	mac.update(expires.to_string().as_bytes());
+	mac
+}
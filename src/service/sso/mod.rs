@@ -0,0 +1,495 @@
+mod cookie;
+mod oidc;
+
+use std::{
+	collections::HashMap,
+	net::IpAddr,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use conduwuit::{
+	config::IdentityProviderConfig, debug_warn, err, implement, info, utils, Err, Result, Server,
+};
+use database::{Deserialized, Map};
+use ruma::{
+	events::{
+		push_rules::{PushRulesEvent, PushRulesEventContent},
+		GlobalAccountDataEventType,
+	},
+	push::Ruleset,
+	OwnedUserId, UserId,
+};
+use url::Url;
+
+use self::oidc::{Claims, ProviderMetadata};
+use crate::{account_data, client, email, globals, onboarding, users, Dep};
+
+pub struct Service {
+	/// Logins and reauthentications waiting on an identity provider, by the
+	/// `state` given to it.
+	pending: Mutex<HashMap<String, Pending>>,
+
+	/// UIAA sessions reauthenticated through an identity provider, with the
+	/// user who did so.
+	reauthenticated: Mutex<HashMap<String, OwnedUserId>>,
+
+	/// Discovery documents of the identity providers, by provider id.
+	metadata: Mutex<HashMap<String, Arc<ProviderMetadata>>>,
+
+	/// Key signing the session cookies; generated on startup, so logins in
+	/// progress don't survive a restart anyway.
+	cookie_key: String,
+	services: Services,
+	db: Data,
+}
+
+struct Services {
+	server: Arc<Server>,
+	account_data: Dep<account_data::Service>,
+	client: Dep<client::Service>,
+	email: Dep<email::Service>,
+	globals: Dep<globals::Service>,
+	onboarding: Dep<onboarding::Service>,
+	users: Dep<users::Service>,
+}
+
+struct Data {
+	ssoidentity_userid: Arc<Map>,
+}
+
+/// What an identity provider is being visited for.
+#[derive(Clone, Debug)]
+pub enum Intent {
+	/// `m.login.sso`; the client is sent back to the URL with a login token.
+	Login {
+		redirect_url: String,
+	},
+
+	/// The `m.login.sso` UIAA stage of the session.
+	Reauth {
+		session: String,
+	},
+}
+
+/// Where to send the browser to log in with an identity provider.
+#[derive(Debug)]
+pub struct Redirection {
+	/// The identity provider's login page.
+	pub location: Url,
+
+	/// `Set-Cookie` value tying the login attempt to the browser, which has
+	/// to be sent back along with the identity provider's callback.
+	pub cookie: String,
+}
+
+/// Where the browser goes once the identity provider sent it back.
+#[derive(Debug)]
+pub enum Completion {
+	/// Back to the client, with a `loginToken` to log in with, once the user
+	/// confirmed that they meant to log in to it.
+	Login {
+		redirect_url: Url,
+	},
+
+	/// Back to the client, which retries its request for the UIAA session.
+	Reauth,
+}
+
+struct Pending {
+	provider: String,
+	intent: Intent,
+	nonce: String,
+	client: IpAddr,
+	created: Instant,
+}
+
+/// Path identity providers send the browser back to.
+pub const CALLBACK_PATH: &str = "/_conduwuit/sso/callback";
+
+const STATE_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 32;
+const COOKIE_KEY_LENGTH: usize = 64;
+const LOGIN_TOKEN_LENGTH: usize = 32;
+
+/// Logins in progress a single address may have at a time.
+const MAX_PENDING_PER_CLIENT: usize = 16;
+
+/// Logins in progress across all addresses; with the time each is kept for,
+/// this bounds the memory unauthenticated clients can take up.
+const MAX_PENDING: usize = 4096;
+
+/// Schemes which would run in the page of the confirmation step instead of
+/// opening a client.
+const FORBIDDEN_REDIRECT_SCHEMES: &[&str] = &["javascript", "data", "vbscript"];
+
+/// Length of the random password of provisioned accounts, which is never
+/// shown to anyone; their users log in through the identity provider.
+const PASSWORD_LENGTH: usize = 32;
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			pending: Mutex::new(HashMap::new()),
+			reauthenticated: Mutex::new(HashMap::new()),
+			metadata: Mutex::new(HashMap::new()),
+			cookie_key: utils::random_string(COOKIE_KEY_LENGTH),
+			services: Services {
+				server: args.server.clone(),
+				account_data: args.depend::<account_data::Service>("account_data"),
+				client: args.depend::<client::Service>("client"),
+				email: args.depend::<email::Service>("email"),
+				globals: args.depend::<globals::Service>("globals"),
+				onboarding: args.depend::<onboarding::Service>("onboarding"),
+				users: args.depend::<users::Service>("users"),
+			},
+			db: Data {
+				ssoidentity_userid: args.db["ssoidentity_userid"].clone(),
+			},
+		}))
+	}
+
+	fn clear_cache(&self) { self.metadata.lock().expect("locked").clear(); }
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Whether any identity provider is configured.
+#[implement(Service)]
+#[must_use]
+pub fn enabled(&self) -> bool { !self.providers().is_empty() }
+
+#[implement(Service)]
+#[must_use]
+pub fn providers(&self) -> &[IdentityProviderConfig] {
+	&self.services.server.config.identity_providers
+}
+
+/// Returns where to redirect the browser of `client` to log in with the
+/// identity provider. Without a provider id the first configured one is used.
+#[implement(Service)]
+pub async fn start(
+	&self,
+	provider_id: Option<&str>,
+	intent: Intent,
+	client: IpAddr,
+) -> Result<Redirection> {
+	let provider = match provider_id {
+		| Some(id) => self.providers().iter().find(|p| p.id == id),
+		| None => self.providers().first(),
+	}
+	.ok_or_else(|| err!(Request(NotFound("Unknown identity provider."))))?;
+
+	if let Intent::Login { redirect_url } = &intent {
+		let Ok(url) = Url::parse(redirect_url) else {
+			return Err!(Request(InvalidParam("redirectUrl is not a valid URL.")));
+		};
+
+		if FORBIDDEN_REDIRECT_SCHEMES.contains(&url.scheme()) {
+			return Err!(Request(InvalidParam("redirectUrl can't be a {} URL.", url.scheme())));
+		}
+
+		let allowed = &self.services.server.config.sso_allowed_redirect_urls;
+		if !allowed.is_match(redirect_url) {
+			return Err!(Request(Forbidden("Logging in to this client is not allowed.")));
+		}
+	}
+
+	let metadata = self.metadata(provider).await?;
+	let state = utils::random_string(STATE_LENGTH);
+	let nonce = utils::random_string(NONCE_LENGTH);
+	let mut url = metadata.authorization_endpoint.clone();
+	url.query_pairs_mut()
+		.append_pair("response_type", "code")
+		.append_pair("client_id", &provider.client_id)
+		.append_pair("redirect_uri", self.callback_url().as_str())
+		.append_pair("scope", &provider.scopes.join(" "))
+		.append_pair("state", &state)
+		.append_pair("nonce", &nonce);
+
+	let cookie = self.session_cookie(&state);
+	let lifetime = self.session_lifetime();
+	let mut pending = self.pending.lock().expect("locked");
+	pending.retain(|_, p| p.created.elapsed() < lifetime);
+	let from_client = pending.values().filter(|p| p.client == client).count();
+	if from_client >= MAX_PENDING_PER_CLIENT || pending.len() >= MAX_PENDING {
+		return Err!(Request(Forbidden("Too many logins in progress, try again later.")));
+	}
+
+	pending.insert(state, Pending {
+		provider: provider.id.clone(),
+		intent,
+		nonce,
+		client,
+		created: Instant::now(),
+	});
+
+	Ok(Redirection { location: url, cookie })
+}
+
+/// Handles the browser of `client` being sent back by an identity provider
+/// with the `Cookie` header it sent, logging in or reauthenticating the user
+/// it identified. Each login attempt can only be finished once.
+#[implement(Service)]
+pub async fn finish(
+	&self,
+	state: &str,
+	code: &str,
+	cookies: Option<&str>,
+	client: IpAddr,
+) -> Result<Completion> {
+	self.verify_session_cookie(cookies, state)?;
+
+	let lifetime = self.session_lifetime();
+	let pending = self
+		.pending
+		.lock()
+		.expect("locked")
+		.remove(state)
+		.filter(|p| p.created.elapsed() < lifetime)
+		.ok_or_else(|| err!(Request(Forbidden("Unknown or expired login attempt."))))?;
+
+	if self.services.server.config.sso_bind_client_ip && pending.client != client {
+		let started = pending.client;
+		debug_warn!("SSO login started from {started} was finished from {client}");
+		return Err!(Request(Forbidden("The login was started from another address.")));
+	}
+
+	let provider = self
+		.providers()
+		.iter()
+		.find(|p| p.id == pending.provider)
+		.ok_or_else(|| err!(Request(NotFound("Unknown identity provider."))))?;
+
+	let metadata = self.metadata(provider).await?;
+	let claims = self
+		.claims(provider, &metadata, code, &pending.nonce)
+		.await?;
+	let user_id = self.resolve_user(provider, &claims).await?;
+	if self.services.users.is_deactivated(&user_id).await? {
+		return Err!(Request(UserDeactivated("The user has been deactivated.")));
+	}
+
+	match pending.intent {
+		| Intent::Login { redirect_url } => {
+			let mut redirect_url =
+				Url::parse(&redirect_url).expect("checked when starting the login");
+
+			let token = utils::random_string(LOGIN_TOKEN_LENGTH);
+			self.services.users.create_login_token(&user_id, &token);
+			redirect_url
+				.query_pairs_mut()
+				.append_pair("loginToken", &token);
+
+			info!("{user_id} logged in through identity provider {}", provider.id);
+			Ok(Completion::Login { redirect_url })
+		},
+		| Intent::Reauth { session } => {
+			self.reauthenticated
+				.lock()
+				.expect("locked")
+				.insert(session, user_id);
+
+			Ok(Completion::Reauth)
+		},
+	}
+}
+
+/// Whether the user completed the `m.login.sso` stage of the UIAA session.
+/// The stage can only be used once.
+#[implement(Service)]
+pub fn take_reauthenticated(&self, session: &str, user_id: &UserId) -> bool {
+	let mut reauthenticated = self.reauthenticated.lock().expect("locked");
+	if reauthenticated
+		.get(session)
+		.is_some_and(|user| user == user_id)
+	{
+		reauthenticated.remove(session);
+		return true;
+	}
+
+	false
+}
+
+/// Time a user has to log in with an identity provider.
+#[implement(Service)]
+fn session_lifetime(&self) -> Duration {
+	Duration::from_secs(self.services.server.config.sso_session_lifetime)
+}
+
+/// The URL identity providers send the browser back to; it has to be
+/// registered with every provider.
+#[implement(Service)]
+#[must_use]
+pub fn callback_url(&self) -> String {
+	let base = self
+		.services
+		.server
+		.config
+		.well_known
+		.client
+		.as_ref()
+		.map_or_else(
+			|| format!("https://{}", self.services.globals.server_name()),
+			|url| url.as_str().trim_end_matches('/').to_owned(),
+		);
+
+	format!("{base}{CALLBACK_PATH}")
+}
+
+/// Links a local account to the subject of an identity provider, so logging
+/// in through the provider logs into it.
+#[implement(Service)]
+pub fn link_identity(&self, provider: &str, subject: &str, user_id: &UserId) -> Result {
+	if !self.providers().iter().any(|config| config.id == provider) {
+		return Err!(Request(InvalidParam("Identity provider {provider} is not configured.")));
+	}
+
+	self.db.ssoidentity_userid.put((provider, subject), user_id);
+	info!("Linked {user_id} to subject {subject} of identity provider {provider}");
+
+	Ok(())
+}
+
+/// Finds the account linked to the identity, linking or creating one on
+/// first login as the provider's config allows.
+#[implement(Service)]
+async fn resolve_user(
+	&self,
+	provider: &IdentityProviderConfig,
+	claims: &Claims,
+) -> Result<OwnedUserId> {
+	let subject = claims
+		.get("sub")
+		.and_then(|sub| sub.as_str())
+		.expect("checked when fetching the claims");
+
+	let key = (provider.id.as_str(), subject);
+	if let Ok(user_id) = self
+		.db
+		.ssoidentity_userid
+		.qry(&key)
+		.await
+		.deserialized::<OwnedUserId>()
+	{
+		return Ok(user_id);
+	}
+
+	let Some(localpart) = claim(claims, &provider.localpart_claim) else {
+		return Err!(Request(Forbidden(
+			"The identity provider did not share a username for this account."
+		)));
+	};
+
+	let server_name = self.services.globals.server_name();
+	let user_id = UserId::parse_with_server_name(localpart.to_lowercase(), server_name)
+		.ok()
+		.filter(|user_id| !user_id.is_historical() && user_id.server_name() == server_name)
+		.ok_or_else(|| {
+			err!(Request(InvalidUsername("{localpart:?} is not a valid username.")))
+		})?;
+
+	if self.services.users.exists(&user_id).await {
+		let linkable = provider.link_existing_accounts
+			&& self.vouches_for_email(provider, claims, &user_id).await;
+
+		if !linkable {
+			return Err!(Request(UserInUse("The username {localpart} is already taken.")));
+		}
+	} else if provider.provision_accounts {
+		self.provision(provider, &user_id, claims).await?;
+	} else {
+		return Err!(Request(Forbidden("No account is linked to this identity.")));
+	}
+
+	self.db.ssoidentity_userid.put(key, &user_id);
+	info!("Linked {user_id} to subject {subject} of identity provider {}", provider.id);
+
+	Ok(user_id)
+}
+
+/// Whether the identity provider verified an email address which is bound to
+/// the existing account; a matching username alone doesn't prove the identity
+/// is the account's owner.
+#[implement(Service)]
+async fn vouches_for_email(
+	&self,
+	provider: &IdentityProviderConfig,
+	claims: &Claims,
+	user_id: &UserId,
+) -> bool {
+	let Some(email) = verified_email(provider, claims) else {
+		return false;
+	};
+
+	self.services
+		.email
+		.find_user(email)
+		.await
+		.is_ok_and(|owner| owner == user_id)
+}
+
+/// Creates the account of a user logging in for the first time.
+#[implement(Service)]
+async fn provision(
+	&self,
+	provider: &IdentityProviderConfig,
+	user_id: &UserId,
+	claims: &Claims,
+) -> Result {
+	let password = utils::random_string(PASSWORD_LENGTH);
+	self.services.users.create(user_id, Some(&password))?;
+
+	let displayname = claim(claims, &provider.displayname_claim)
+		.map_or_else(|| user_id.localpart().to_owned(), ToOwned::to_owned);
+
+	self.services
+		.users
+		.set_displayname(user_id, Some(displayname));
+
+	self.services
+		.account_data
+		.update(
+			None,
+			user_id,
+			GlobalAccountDataEventType::PushRules.to_string().into(),
+			&serde_json::to_value(PushRulesEvent {
+				content: PushRulesEventContent { global: Ruleset::server_default(user_id) },
+			})
+			.expect("to json always works"),
+		)
+		.await?;
+
+	if let Some(email) = verified_email(provider, claims) {
+		if let Err(e) = self.services.email.bind(user_id, email).await {
+			debug_warn!("Not binding {email} to {user_id}: {e}");
+		}
+	}
+
+	self.services
+		.onboarding
+		.run_registration_hooks(user_id)
+		.await;
+
+	info!("New user {user_id} registered through identity provider {}", provider.id);
+
+	Ok(())
+}
+
+/// The email address of the user, if the identity provider marked it as
+/// verified.
+fn verified_email<'a>(provider: &IdentityProviderConfig, claims: &'a Claims) -> Option<&'a str> {
+	let verified = claims
+		.get("email_verified")
+		.and_then(serde_json::Value::as_bool)
+		.unwrap_or(false);
+
+	claim(claims, &provider.email_claim).filter(|_| verified)
+}
+
+fn claim<'a>(claims: &'a Claims, name: &str) -> Option<&'a str> {
+	claims
+		.get(name)
+		.and_then(serde_json::Value::as_str)
+		.filter(|value| !value.is_empty())
+}
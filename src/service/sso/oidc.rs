@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use conduwuit::{debug_warn, err, implement, Err, Result};
+use serde::Deserialize;
+use serde_json::{Map as JsonMap, Value as JsonValue};
+use url::Url;
+
+use super::IdentityProviderConfig;
+
+/// The parts of a provider's OpenID Connect discovery document used for the
+/// authorization code flow.
+#[derive(Debug, Deserialize)]
+pub(super) struct ProviderMetadata {
+	issuer: String,
+	pub(super) authorization_endpoint: Url,
+	token_endpoint: Url,
+	userinfo_endpoint: Url,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+	access_token: String,
+	id_token: String,
+}
+
+/// Claims about a user returned by a provider's userinfo endpoint.
+pub(super) type Claims = JsonMap<String, JsonValue>;
+
+/// Returns the provider's discovery document, fetching it on first use.
+#[implement(super::Service)]
+pub(super) async fn metadata(
+	&self,
+	provider: &IdentityProviderConfig,
+) -> Result<Arc<ProviderMetadata>> {
+	if let Some(metadata) = self.metadata.lock().expect("locked").get(&provider.id) {
+		return Ok(metadata.clone());
+	}
+
+	let issuer = provider.issuer.as_str().trim_end_matches('/');
+	let url = format!("{issuer}/.well-known/openid-configuration");
+	let response = self
+		.services
+		.client
+		.default
+		.get(&url)
+		.send()
+		.await?
+		.error_for_status()?
+		.text()
+		.await?;
+
+	let metadata: ProviderMetadata = serde_json::from_str(&response)?;
+	let announced = metadata.issuer.trim_end_matches('/');
+	if announced != issuer {
+		let id = &provider.id;
+		return Err!(BadServerResponse(
+			"Identity provider {id} announced issuer {announced:?} instead of {issuer:?}"
+		));
+	}
+
+	let metadata = Arc::new(metadata);
+	self.metadata
+		.lock()
+		.expect("locked")
+		.insert(provider.id.clone(), metadata.clone());
+
+	Ok(metadata)
+}
+
+/// Exchanges an authorization code for the user's claims. The claims are
+/// read from the userinfo endpoint, which is reached over TLS with the
+/// access token, so the ID token's signature doesn't need to be verified;
+/// it is only checked to have been issued for this login attempt.
+#[implement(super::Service)]
+pub(super) async fn claims(
+	&self,
+	provider: &IdentityProviderConfig,
+	metadata: &ProviderMetadata,
+	code: &str,
+	nonce: &str,
+) -> Result<Claims> {
+	let redirect_uri = self.callback_url();
+	let params = [
+		("grant_type", "authorization_code"),
+		("code", code),
+		("redirect_uri", redirect_uri.as_str()),
+		("client_id", provider.client_id.as_str()),
+		("client_secret", provider.client_secret.as_str()),
+	];
+
+	let response = self
+		.services
+		.client
+		.default
+		.post(metadata.token_endpoint.clone())
+		.form(&params)
+		.send()
+		.await?;
+
+	if !response.status().is_success() {
+		let (id, status) = (&provider.id, response.status());
+		let body = response.text().await.unwrap_or_default();
+		debug_warn!("Token request to identity provider {id} failed: {status} {body}");
+		return Err!(Request(Forbidden("The identity provider rejected the login.")));
+	}
+
+	let token: TokenResponse = serde_json::from_str(&response.text().await?)?;
+	check_id_token(provider, metadata, &token.id_token, nonce)?;
+
+	let response = self
+		.services
+		.client
+		.default
+		.get(metadata.userinfo_endpoint.clone())
+		.bearer_auth(&token.access_token)
+		.send()
+		.await?
+		.error_for_status()?
+		.text()
+		.await?;
+
+	let claims: Claims = serde_json::from_str(&response)?;
+	if !claims.get("sub").is_some_and(JsonValue::is_string) {
+		let id = &provider.id;
+		return Err!(BadServerResponse(
+			"Identity provider {id} returned claims without a subject"
+		));
+	}
+
+	Ok(claims)
+}
+
+/// Checks the ID token names us as its audience and carries the nonce of the
+/// login attempt, so a token issued for another login can't be replayed.
+fn check_id_token(
+	provider: &IdentityProviderConfig,
+	metadata: &ProviderMetadata,
+	id_token: &str,
+	nonce: &str,
+) -> Result {
+	let id = &provider.id;
+	let claims: Claims = id_token
+		.split('.')
+		.nth(1)
+		.and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+		.and_then(|payload| serde_json::from_slice(&payload).ok())
+		.ok_or_else(|| {
+			err!(BadServerResponse("Identity provider {id} sent a malformed ID token"))
+		})?;
+
+	let issued_for_us = match claims.get("aud") {
+		| Some(JsonValue::String(aud)) => *aud == provider.client_id,
+		| Some(JsonValue::Array(aud)) => aud.iter().any(|aud| *aud == *provider.client_id),
+		| _ => false,
+	};
+
+	let issuer = claims.get("iss").and_then(JsonValue::as_str);
+	if !issued_for_us || issuer != Some(metadata.issuer.as_str()) {
+		return Err!(Request(Forbidden(
+			"The identity provider issued the login to someone else."
+		)));
+	}
+
+	if claims.get("nonce").and_then(JsonValue::as_str) != Some(nonce) {
+		debug_warn!("Identity provider {id} returned an ID token for another login attempt");
+		return Err!(Request(Forbidden("The login attempt does not match.")));
+	}
+
+	Ok(())
+}
@@ -1,11 +1,17 @@
+mod snapshot;
+mod tests;
 mod watch;
 
 use std::{
-	collections::{BTreeMap, BTreeSet},
+	collections::{BTreeMap, BTreeSet, HashMap},
+	fmt::Write,
 	sync::{Arc, Mutex, Mutex as StdMutex},
 };
 
-use conduwuit::{Result, Server};
+use conduwuit::{
+	utils::{bytes::pretty, math::usize_from_f64},
+	Err, Result, Server,
+};
 use database::Map;
 use ruma::{
 	api::client::sync::sync_events::{
@@ -16,13 +22,25 @@
 	DeviceId, OwnedDeviceId, OwnedRoomId, OwnedUserId, UserId,
 };
 
-use crate::{rooms, Dep};
+pub use self::{
+	snapshot::{Snapshot, SNAPSHOT_TIMELINE_LEN},
+	watch::Watcher,
+};
+use crate::{rooms, Cache, Dep, LruCache};
 
 pub struct Service {
 	db: Data,
 	services: Services,
 	connections: DbConnections<DbConnectionsKey, DbConnectionsVal>,
 	snake_connections: DbConnections<SnakeConnectionsKey, SnakeConnectionsVal>,
+	active_syncs: StdMutex<HashMap<OwnedUserId, usize>>,
+	snapshot_cache: StdMutex<LruCache<OwnedRoomId, Arc<Snapshot>>>,
+}
+
+/// Holds one of a user's concurrent `/sync` slots until dropped.
+pub struct SyncGuard<'a> {
+	service: &'a Service,
+	user_id: OwnedUserId,
 }
 
 pub struct Data {
@@ -33,6 +51,7 @@ pub struct Data {
 	userroomid_notificationcount: Arc<Map>,
 	userroomid_highlightcount: Arc<Map>,
 	pduid_pdu: Arc<Map>,
+	roomid_shortstatehash: Arc<Map>,
 	keychangeid_userid: Arc<Map>,
 	roomusertype_roomuserdataid: Arc<Map>,
 	readreceiptid_readreceipt: Arc<Map>,
@@ -42,7 +61,11 @@ pub struct Data {
 struct Services {
 	server: Arc<Server>,
 	short: Dep<rooms::short::Service>,
+	state: Dep<rooms::state::Service>,
+	state_accessor: Dep<rooms::state_accessor::Service>,
 	state_cache: Dep<rooms::state_cache::Service>,
+	timeline: Dep<rooms::timeline::Service>,
+	read_receipt: Dep<rooms::read_receipt::Service>,
 	typing: Dep<rooms::typing::Service>,
 }
 
@@ -70,6 +93,10 @@ struct SnakeSyncCache {
 
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let config = &args.server.config;
+		let snapshot_cache_capacity =
+			f64::from(config.sync_snapshot_cache_capacity) * config.cache_capacity_modifier;
+
 		Ok(Arc::new(Self {
 			db: Data {
 				todeviceid_events: args.db["todeviceid_events"].clone(),
@@ -79,6 +106,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				userroomid_notificationcount: args.db["userroomid_notificationcount"].clone(),
 				userroomid_highlightcount: args.db["userroomid_highlightcount"].clone(),
 				pduid_pdu: args.db["pduid_pdu"].clone(),
+				roomid_shortstatehash: args.db["roomid_shortstatehash"].clone(),
 				keychangeid_userid: args.db["keychangeid_userid"].clone(),
 				roomusertype_roomuserdataid: args.db["roomusertype_roomuserdataid"].clone(),
 				readreceiptid_readreceipt: args.db["readreceiptid_readreceipt"].clone(),
@@ -87,14 +115,46 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			services: Services {
 				server: args.server.clone(),
 				short: args.depend::<rooms::short::Service>("rooms::short"),
+				state: args.depend::<rooms::state::Service>("rooms::state"),
+				state_accessor: args
+					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
 				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
+				read_receipt: args.depend::<rooms::read_receipt::Service>("rooms::read_receipt"),
 				typing: args.depend::<rooms::typing::Service>("rooms::typing"),
 			},
 			connections: StdMutex::new(BTreeMap::new()),
 			snake_connections: StdMutex::new(BTreeMap::new()),
+			active_syncs: StdMutex::new(HashMap::new()),
+			snapshot_cache: StdMutex::new(LruCache::new(usize_from_f64(
+				snapshot_cache_capacity,
+			)?)),
 		}))
 	}
 
+	fn memory_usage(&self, out: &mut dyn Write) -> Result {
+		let (count, bytes) = self.snapshot_cache.lock()?.iter().fold(
+			(0_usize, 0_usize),
+			|(count, bytes), (_, snapshot)| {
+				(count.saturating_add(1), bytes.saturating_add(snapshot.size()))
+			},
+		);
+
+		writeln!(out, "sync_snapshot_cache: {count} ({})", pretty(bytes))?;
+
+		Ok(())
+	}
+
+	fn clear_cache(&self) { self.snapshot_cache.lock().expect("locked").clear(); }
+
+	fn caches(&self) -> Vec<Cache<'_>> {
+		vec![Cache {
+			name: "sync_snapshot_cache",
+			base_capacity: |config| config.sync_snapshot_cache_capacity,
+			cache: &self.snapshot_cache,
+		}]
+	}
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
@@ -112,6 +172,34 @@ fn some_or_sticky<T>(target: &mut Option<T>, cached: Option<T>) {
 }
 
 impl Service {
+	/// Registers a `/sync` request in progress for the user, failing with
+	/// M_FORBIDDEN when they already have the configured maximum running.
+	pub fn start_sync(&self, user_id: &UserId) -> Result<SyncGuard<'_>> {
+		let max = self.services.server.config.max_concurrent_syncs_per_user;
+		let mut active_syncs = self.active_syncs.lock().expect("locked");
+		let active = active_syncs.entry(user_id.to_owned()).or_default();
+		if max > 0 && *active >= max {
+			return Err!(Request(Forbidden("Too many concurrent sync requests.")));
+		}
+
+		*active = active.saturating_add(1);
+
+		Ok(SyncGuard {
+			service: self,
+			user_id: user_id.to_owned(),
+		})
+	}
+
+	/// Number of `/sync` requests the user has in progress.
+	pub fn active_syncs(&self, user_id: &UserId) -> usize {
+		self.active_syncs
+			.lock()
+			.expect("locked")
+			.get(user_id)
+			.copied()
+			.unwrap_or(0)
+	}
+
 	pub fn snake_connection_cached(
 		&self,
 		user_id: OwnedUserId,
@@ -487,3 +575,15 @@ pub fn update_snake_sync_subscriptions(
 		cached.subscriptions = subscriptions;
 	}
 }
+
+impl Drop for SyncGuard<'_> {
+	fn drop(&mut self) {
+		let mut active_syncs = self.service.active_syncs.lock().expect("locked");
+		if let Some(active) = active_syncs.get_mut(&self.user_id) {
+			*active = active.saturating_sub(1);
+			if *active == 0 {
+				active_syncs.remove(&self.user_id);
+			}
+		}
+	}
+}
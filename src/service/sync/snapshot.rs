@@ -0,0 +1,203 @@
+//! Initial sync snapshots
+//!
+//! The parts of a room's initial sync which are the same for every member:
+//! its current state, most recent timeline events and read receipts. A
+//! snapshot is reused until the room's state, timeline or receipts move on.
+
+use std::{hash::Hash, sync::Arc};
+
+use conduwuit::{
+	implement,
+	utils::{stream::TryIgnore, ReadyExt},
+	PduCount, Result,
+};
+use futures::{future::try_join3, FutureExt, StreamExt};
+use ruma::{
+	events::{AnySyncEphemeralRoomEvent, StateEventType},
+	serde::Raw,
+	OwnedEventId, OwnedUserId, RoomId, UserId,
+};
+
+use crate::{rooms::short::ShortStateHash, LruCache};
+
+/// Timeline events held by a snapshot; initial syncs asking for more load
+/// their timeline themselves.
+pub const SNAPSHOT_TIMELINE_LEN: usize = 100;
+
+/// Room data shared by the initial syncs of its members. Events are held by
+/// ID and loaded by each sync, so a snapshot stays small whatever the size of
+/// the events.
+pub struct Snapshot {
+	version: Version,
+
+	/// Every event of the room's current state, with the member for
+	/// membership events so lazy loading can leave them out unloaded.
+	pub state: Vec<(Option<OwnedUserId>, OwnedEventId)>,
+
+	/// The most recent timeline events, oldest first.
+	pub timeline: Vec<(PduCount, OwnedEventId)>,
+
+	/// Whether the room has older timeline events than those held.
+	pub limited: bool,
+
+	/// The latest read receipt of each user.
+	pub receipts: Vec<(OwnedUserId, Raw<AnySyncEphemeralRoomEvent>)>,
+
+	/// Approximate memory held, in bytes.
+	size: usize,
+}
+
+impl Snapshot {
+	/// The room state the snapshot holds.
+	#[inline]
+	#[must_use]
+	pub fn shortstatehash(&self) -> ShortStateHash { self.version.0 }
+
+	/// Approximate memory held, in bytes.
+	#[inline]
+	#[must_use]
+	pub fn size(&self) -> usize { self.size }
+}
+
+/// What a snapshot was taken at: the room's state, its last PDU and its last
+/// read receipt.
+type Version = (ShortStateHash, PduCount, u64);
+
+/// Returns the initial sync snapshot of the room, taking a new one when the
+/// cached one is out of date.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn initial_snapshot(&self, room_id: &RoomId) -> Result<Arc<Snapshot>> {
+	let shortstatehash = self.services.state.get_room_shortstatehash(room_id);
+	let count = self.services.timeline.last_timeline_count(None, room_id);
+	let receipt_count = self
+		.services
+		.read_receipt
+		.last_receipt_count(room_id)
+		.map(Ok);
+
+	let version: Version = try_join3(shortstatehash, count, receipt_count).await?;
+	let cached = self
+		.snapshot_cache
+		.lock()?
+		.get_mut(room_id)
+		.filter(|snapshot| snapshot.version == version)
+		.cloned();
+
+	if let Some(snapshot) = cached {
+		return Ok(snapshot);
+	}
+
+	let snapshot = Arc::new(self.take_snapshot(room_id, version).await);
+	self.cache_snapshot(room_id, snapshot.clone())?;
+
+	Ok(snapshot)
+}
+
+/// Caches a snapshot, evicting the least recently used ones while the cache
+/// holds more than `sync_snapshot_cache_capacity_mb`.
+#[implement(super::Service)]
+fn cache_snapshot(&self, room_id: &RoomId, snapshot: Arc<Snapshot>) -> Result {
+	let config = &self.services.server.config;
+	let max_bytes = config.sync_snapshot_cache_capacity_mb * 1024.0 * 1024.0;
+
+	let mut cache = self.snapshot_cache.lock()?;
+	cache.insert(room_id.to_owned(), snapshot);
+	evict_to_size(&mut cache, max_bytes, |snapshot| snapshot.size);
+
+	Ok(())
+}
+
+/// Evicts the least recently used entries until those left take no more than
+/// `max_bytes`, always keeping the most recent one.
+#[allow(clippy::cast_precision_loss, clippy::as_conversions)]
+pub(super) fn evict_to_size<K, V, F>(cache: &mut LruCache<K, V>, max_bytes: f64, size: F)
+where
+	K: Eq + Hash,
+	F: Fn(&V) -> usize,
+{
+	let mut total = cache
+		.iter()
+		.map(|(_, value)| size(value))
+		.fold(0_usize, usize::saturating_add);
+
+	while cache.len() > 1 && total as f64 > max_bytes {
+		let Some((_, evicted)) = cache.remove_lru() else {
+			break;
+		};
+
+		total = total.saturating_sub(size(&evicted));
+	}
+}
+
+#[implement(super::Service)]
+async fn take_snapshot(&self, room_id: &RoomId, version: Version) -> Snapshot {
+	let (shortstatehash, count, _) = version;
+
+	let state = self
+		.services
+		.state_accessor
+		.state_full_pdus(shortstatehash)
+		.map(|pdu| {
+			let member = (pdu.kind == StateEventType::RoomMember.into())
+				.then(|| pdu.state_key.as_deref().map(UserId::parse))
+				.flatten()
+				.and_then(Result::ok);
+
+			(member, pdu.event_id)
+		})
+		.collect::<Vec<_>>();
+
+	let timeline = self
+		.services
+		.timeline
+		.pdus_rev(None, room_id, None)
+		.ignore_err()
+		.ready_skip_while(|&(pducount, _)| pducount > count)
+		.take(SNAPSHOT_TIMELINE_LEN.saturating_add(1))
+		.map(|(pducount, pdu)| (pducount, pdu.event_id))
+		.collect::<Vec<_>>();
+
+	let receipts = self
+		.services
+		.read_receipt
+		.readreceipts_since(room_id, 0)
+		.map(|(user_id, _, event)| (user_id.to_owned(), event))
+		.collect::<Vec<_>>();
+
+	let (state, mut timeline, receipts) = futures::join!(state, timeline, receipts);
+
+	let limited = timeline.len() > SNAPSHOT_TIMELINE_LEN;
+	timeline.truncate(SNAPSHOT_TIMELINE_LEN);
+	timeline.reverse();
+
+	let state_size = state.iter().map(|(member, event_id)| {
+		size_of::<(Option<OwnedUserId>, OwnedEventId)>()
+			.saturating_add(member.as_ref().map_or(0, |member| member.as_str().len()))
+			.saturating_add(event_id.as_str().len())
+	});
+
+	let timeline_size = timeline.iter().map(|(_, event_id)| {
+		size_of::<(PduCount, OwnedEventId)>().saturating_add(event_id.as_str().len())
+	});
+
+	let receipts_size = receipts.iter().map(|(user_id, event)| {
+		size_of::<(OwnedUserId, Raw<AnySyncEphemeralRoomEvent>)>()
+			.saturating_add(user_id.as_str().len())
+			.saturating_add(event.json().get().len())
+	});
+
+	let size = state_size
+		.chain(timeline_size)
+		.chain(receipts_size)
+		.fold(size_of::<Snapshot>(), usize::saturating_add);
+
+	Snapshot {
+		version,
+		state,
+		timeline,
+		limited,
+		receipts,
+		size,
+	}
+}
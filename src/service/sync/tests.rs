@@ -0,0 +1,48 @@
+#![cfg(test)]
+
+use super::snapshot::evict_to_size;
+use crate::LruCache;
+
+fn cache(entries: &[(u32, usize)]) -> LruCache<u32, usize> {
+	let mut cache = LruCache::new(entries.len().saturating_add(1));
+	for &(key, size) in entries {
+		cache.insert(key, size);
+	}
+
+	cache
+}
+
+fn keys(cache: &LruCache<u32, usize>) -> Vec<u32> { cache.iter().map(|(key, _)| *key).collect() }
+
+#[test]
+fn evicts_nothing_under_limit() {
+	let mut cache = cache(&[(1, 10), (2, 20), (3, 30)]);
+	evict_to_size(&mut cache, 60.0, |size| *size);
+
+	assert_eq!(keys(&cache), [1, 2, 3]);
+}
+
+#[test]
+fn evicts_least_recently_used_first() {
+	let mut cache = cache(&[(1, 10), (2, 20), (3, 30)]);
+	cache.get_mut(&1);
+	evict_to_size(&mut cache, 40.0, |size| *size);
+
+	assert_eq!(keys(&cache), [1, 3]);
+}
+
+#[test]
+fn evicts_until_under_limit() {
+	let mut cache = cache(&[(1, 10), (2, 20), (3, 30), (4, 5)]);
+	evict_to_size(&mut cache, 35.0, |size| *size);
+
+	assert_eq!(keys(&cache), [3, 4]);
+}
+
+#[test]
+fn keeps_newest_when_oversized() {
+	let mut cache = cache(&[(1, 10), (2, 100)]);
+	evict_to_size(&mut cache, 50.0, |size| *size);
+
+	assert_eq!(keys(&cache), [2]);
+}
@@ -1,112 +1,145 @@
-use conduwuit::{implement, trace, Result};
-use futures::{pin_mut, stream::FuturesUnordered, FutureExt, StreamExt};
-use ruma::{DeviceId, UserId};
+//! Long-polling sync wakeups
+//!
+//! A sync with nothing to return waits for a change to the data streams its
+//! next response is built from: the user's joined rooms, their to-device
+//! events, account data and key changes. Watches are registered per user and
+//! device on just those streams, so an event in a room the user isn't in
+//! doesn't wake their sync.
+
+use std::{
+	future::Future,
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use conduwuit::{implement, trace};
+use futures::{
+	future::{ready, BoxFuture},
+	pin_mut,
+	stream::FuturesUnordered,
+	FutureExt, StreamExt,
+};
+use ruma::{DeviceId, RoomId, UserId};
+
+/// Completes on the first change to any of the streams registered by
+/// [`watch`](super::Service::watch), or at server shutdown.
+pub struct Watcher<'a> {
+	futures: FuturesUnordered<BoxFuture<'a, ()>>,
+}
 
+/// Registers watches on everything the user's next sync is built from. This
+/// is to be awaited before building the response, so changes made while it's
+/// built still wake the returned watcher.
 #[implement(super::Service)]
 #[tracing::instrument(skip(self), level = "debug")]
-pub async fn watch(&self, user_id: &UserId, device_id: &DeviceId) -> Result {
-	let userid_bytes = user_id.as_bytes().to_vec();
-	let mut userid_prefix = userid_bytes.clone();
+pub async fn watch<'a>(&'a self, user_id: &'a UserId, device_id: &'a DeviceId) -> Watcher<'a> {
+	let mut watcher = Watcher { futures: FuturesUnordered::new() };
+	if !self.services.server.running() {
+		watcher.futures.push(ready(()).boxed());
+		return watcher;
+	}
+
+	watcher.futures.extend(self.watch_user(user_id, device_id));
+
+	let rooms_joined = self.services.state_cache.rooms_joined(user_id);
+
+	pin_mut!(rooms_joined);
+	while let Some(room_id) = rooms_joined.next().await {
+		watcher
+			.futures
+			.extend(self.watch_room(user_id, room_id).await);
+	}
+
+	// Server shutdown
+	watcher
+		.futures
+		.push(self.services.server.until_shutdown().boxed());
+
+	trace!(futures = watcher.futures.len(), "watch registered");
+	watcher
+}
+
+/// Streams of the user themselves: to-device events, membership changes,
+/// notification counts, global account data and keys.
+#[implement(super::Service)]
+fn watch_user(&self, user_id: &UserId, device_id: &DeviceId) -> Vec<BoxFuture<'_, ()>> {
+	let userid_bytes = user_id.as_bytes();
+	let mut userid_prefix = userid_bytes.to_vec();
 	userid_prefix.push(0xFF);
 
 	let mut userdeviceid_prefix = userid_prefix.clone();
 	userdeviceid_prefix.extend_from_slice(device_id.as_bytes());
 	userdeviceid_prefix.push(0xFF);
 
-	let mut futures = FuturesUnordered::new();
-
-	// Return when *any* user changed their key
-	// TODO: only send for user they share a room with
-	futures.push(self.db.todeviceid_events.watch_prefix(&userdeviceid_prefix));
+	let mut globaluserdata_prefix = vec![0xFF];
+	globaluserdata_prefix.extend_from_slice(&userid_prefix);
 
-	futures.push(self.db.userroomid_joined.watch_prefix(&userid_prefix));
-	futures.push(self.db.userroomid_invitestate.watch_prefix(&userid_prefix));
-	futures.push(self.db.userroomid_leftstate.watch_prefix(&userid_prefix));
-	futures.push(
+	vec![
+		// To-device events for this device
+		self.db.todeviceid_events.watch_prefix(&userdeviceid_prefix),
+		// Joins, invites and leaves
+		self.db.userroomid_joined.watch_prefix(&userid_prefix),
+		self.db.userroomid_invitestate.watch_prefix(&userid_prefix),
+		self.db.userroomid_leftstate.watch_prefix(&userid_prefix),
+		// Notification counts
 		self.db
 			.userroomid_notificationcount
 			.watch_prefix(&userid_prefix),
-	);
-	futures.push(
 		self.db
 			.userroomid_highlightcount
 			.watch_prefix(&userid_prefix),
-	);
+		// Global account data
+		self.db
+			.roomusertype_roomuserdataid
+			.watch_prefix(&globaluserdata_prefix),
+		// Key changes (used when user is not joined to any rooms)
+		self.db.keychangeid_userid.watch_prefix(&userid_prefix),
+		// One time keys
+		self.db
+			.userid_lastonetimekeyupdate
+			.watch_prefix(userid_bytes),
+	]
+}
 
-	// Events for rooms we are in
-	let rooms_joined = self.services.state_cache.rooms_joined(user_id);
+/// Streams of one of the user's joined rooms: its timeline, state, account
+/// data, key changes and EDUs.
+#[implement(super::Service)]
+async fn watch_room(&self, user_id: &UserId, room_id: &RoomId) -> Vec<BoxFuture<'_, ()>> {
+	let Ok(short_roomid) = self.services.short.get_shortroomid(room_id).await else {
+		return Vec::new();
+	};
 
-	pin_mut!(rooms_joined);
-	while let Some(room_id) = rooms_joined.next().await {
-		let Ok(short_roomid) = self.services.short.get_shortroomid(room_id).await else {
-			continue;
-		};
+	let roomid_bytes = room_id.as_bytes();
+	let mut roomid_prefix = roomid_bytes.to_vec();
+	roomid_prefix.push(0xFF);
 
-		let roomid_bytes = room_id.as_bytes().to_vec();
-		let mut roomid_prefix = roomid_bytes.clone();
-		roomid_prefix.push(0xFF);
+	let mut roomuser_prefix = roomid_prefix.clone();
+	roomuser_prefix.extend_from_slice(user_id.as_bytes());
+	roomuser_prefix.push(0xFF);
 
+	vec![
 		// Key changes
-		futures.push(self.db.keychangeid_userid.watch_prefix(&roomid_prefix));
-
+		self.db.keychangeid_userid.watch_prefix(&roomid_prefix),
 		// Room account data
-		let mut roomuser_prefix = roomid_prefix.clone();
-		roomuser_prefix.extend_from_slice(&userid_prefix);
-
-		futures.push(
-			self.db
-				.roomusertype_roomuserdataid
-				.watch_prefix(&roomuser_prefix),
-		);
-
-		// PDUs
-		let short_roomid = short_roomid.to_be_bytes().to_vec();
-		futures.push(self.db.pduid_pdu.watch_prefix(&short_roomid));
-
-		// EDUs
-		let typing_room_id = room_id.to_owned();
-		let typing_wait_for_update = async move {
-			self.services.typing.wait_for_update(&typing_room_id).await;
-		};
-
-		futures.push(typing_wait_for_update.boxed());
-		futures.push(
-			self.db
-				.readreceiptid_readreceipt
-				.watch_prefix(&roomid_prefix),
-		);
-	}
-
-	let mut globaluserdata_prefix = vec![0xFF];
-	globaluserdata_prefix.extend_from_slice(&userid_prefix);
-
-	futures.push(
 		self.db
 			.roomusertype_roomuserdataid
-			.watch_prefix(&globaluserdata_prefix),
-	);
-
-	// More key changes (used when user is not joined to any rooms)
-	futures.push(self.db.keychangeid_userid.watch_prefix(&userid_prefix));
-
-	// One time keys
-	futures.push(
+			.watch_prefix(&roomuser_prefix),
+		// PDUs
+		self.db.pduid_pdu.watch_prefix(&short_roomid.to_be_bytes()),
+		// State replaced without new PDUs, e.g. by an admin
+		self.db.roomid_shortstatehash.watch_prefix(&roomid_prefix),
+		// EDUs
+		self.services.typing.wait_for_update(room_id).boxed(),
 		self.db
-			.userid_lastonetimekeyupdate
-			.watch_prefix(&userid_bytes),
-	);
+			.readreceiptid_readreceipt
+			.watch_prefix(&roomid_prefix),
+	]
+}
 
-	// Server shutdown
-	futures.push(self.services.server.until_shutdown().boxed());
+impl Future for Watcher<'_> {
+	type Output = ();
 
-	if !self.services.server.running() {
-		return Ok(());
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+		self.futures.poll_next_unpin(cx).map(|_| ())
 	}
-
-	// Wait until one of them finds something
-	trace!(futures = futures.len(), "watch started");
-	futures.next().await;
-	trace!(futures = futures.len(), "watch finished");
-
-	Ok(())
 }
@@ -1,8 +1,12 @@
 use std::sync::Arc;
 
-use conduwuit::{implement, Result};
-use database::{Handle, Map};
-use ruma::{DeviceId, TransactionId, UserId};
+use conduwuit::{
+	implement,
+	utils::stream::{ReadyExt, TryIgnore},
+	Result,
+};
+use database::{Handle, Interfix, Map};
+use ruma::{DeviceId, EventId, ServerName, TransactionId, UserId};
 
 pub struct Service {
 	db: Data,
@@ -10,6 +14,7 @@ pub struct Service {
 
 struct Data {
 	userdevicetxnid_response: Arc<Map>,
+	servertxnideventid_accepted: Arc<Map>,
 }
 
 impl crate::Service for Service {
@@ -17,6 +22,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			db: Data {
 				userdevicetxnid_response: args.db["userdevicetxnid_response"].clone(),
+				servertxnideventid_accepted: args.db["servertxnideventid_accepted"].clone(),
 			},
 		}))
 	}
@@ -52,3 +58,40 @@ pub async fn existing_txnid(
 	let key = (user_id, device_id, txn_id);
 	self.db.userdevicetxnid_response.qry(&key).await
 }
+
+/// Remembers that a PDU from a federation transaction which could not be
+/// completed was accepted, so it can be skipped when the transaction is
+/// retried.
+#[implement(Service)]
+pub fn add_federation_txn_accepted(
+	&self,
+	origin: &ServerName,
+	txn_id: &TransactionId,
+	event_id: &EventId,
+) {
+	let key = (origin, txn_id, event_id);
+	self.db.servertxnideventid_accepted.put_raw(key, []);
+}
+
+#[implement(Service)]
+pub async fn is_federation_txn_accepted(
+	&self,
+	origin: &ServerName,
+	txn_id: &TransactionId,
+	event_id: &EventId,
+) -> bool {
+	let key = (origin, txn_id, event_id);
+	self.db.servertxnideventid_accepted.contains(&key).await
+}
+
+/// Forgets the progress of a federation transaction once it was completed.
+#[implement(Service)]
+pub async fn clear_federation_txn(&self, origin: &ServerName, txn_id: &TransactionId) {
+	let prefix = (origin, txn_id, Interfix);
+	self.db
+		.servertxnideventid_accepted
+		.keys_prefix_raw(&prefix)
+		.ignore_err()
+		.ready_for_each(|key| self.db.servertxnideventid_accepted.remove(key))
+		.await;
+}
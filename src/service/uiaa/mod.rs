@@ -12,15 +12,19 @@
 use ruma::{
 	api::client::{
 		error::ErrorKind,
-		uiaa::{AuthData, AuthType, Password, UiaaInfo, UserIdentifier},
+		uiaa::{AuthData, AuthFlow, AuthType, Password, UiaaInfo, UserIdentifier},
 	},
 	CanonicalJsonValue, DeviceId, OwnedDeviceId, OwnedUserId, UserId,
 };
 
-use crate::{config, globals, users, Dep};
+use crate::{config, email, globals, registration_tokens, sso, users, Dep};
 
 pub struct Service {
 	userdevicesessionid_uiaarequest: RwLock<RequestMap>,
+
+	/// Credentials of the completed registration token and email stages, so
+	/// handlers can use them once every stage is done.
+	sessionid_auth: RwLock<BTreeMap<String, Vec<AuthData>>>,
 	db: Data,
 	services: Services,
 }
@@ -29,6 +33,9 @@ struct Services {
 	globals: Dep<globals::Service>,
 	users: Dep<users::Service>,
 	config: Dep<config::Service>,
+	registration_tokens: Dep<registration_tokens::Service>,
+	email: Dep<email::Service>,
+	sso: Dep<sso::Service>,
 }
 
 struct Data {
@@ -44,6 +51,7 @@ impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			userdevicesessionid_uiaarequest: RwLock::new(RequestMap::new()),
+			sessionid_auth: RwLock::new(BTreeMap::new()),
 			db: Data {
 				userdevicesessionid_uiaainfo: args.db["userdevicesessionid_uiaainfo"].clone(),
 			},
@@ -51,6 +59,10 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				globals: args.depend::<globals::Service>("globals"),
 				users: args.depend::<users::Service>("users"),
 				config: args.depend::<config::Service>("config"),
+				registration_tokens: args
+					.depend::<registration_tokens::Service>("registration_tokens"),
+				email: args.depend::<email::Service>("email"),
+				sso: args.depend::<sso::Service>("sso"),
 			},
 		}))
 	}
@@ -78,6 +90,19 @@ pub async fn read_tokens(&self) -> Result<HashSet<String>> {
 	Ok(tokens)
 }
 
+/// Flows with which users confirm their identity before sensitive actions:
+/// their password, or an identity provider if any is configured.
+#[implement(Service)]
+#[must_use]
+pub fn reauth_flows(&self) -> Vec<AuthFlow> {
+	let mut flows = vec![AuthFlow { stages: vec![AuthType::Password] }];
+	if self.services.sso.enabled() {
+		flows.push(AuthFlow { stages: vec![AuthType::Sso] });
+	}
+
+	flows
+}
+
 /// Creates a new Uiaa session. Make sure the session token is unique.
 #[implement(Service)]
 pub fn create(
@@ -174,9 +199,15 @@ pub async fn try_auth(
 			uiaainfo.completed.push(AuthType::Password);
 		},
 		| AuthData::RegistrationToken(t) => {
-			let tokens = self.read_tokens().await?;
-			if tokens.contains(t.token.trim()) {
+			let session = uiaainfo.session.as_deref().expect("session is always set");
+			if self
+				.services
+				.registration_tokens
+				.reserve(t.token.trim(), session)
+				.await?
+			{
 				uiaainfo.completed.push(AuthType::RegistrationToken);
+				self.record_auth(&uiaainfo, auth);
 			} else {
 				uiaainfo.auth_error = Some(ruma::api::client::error::StandardErrorBody {
 					kind: ErrorKind::forbidden(),
@@ -185,9 +216,40 @@ pub async fn try_auth(
 				return Ok((false, uiaainfo));
 			}
 		},
+		| AuthData::EmailIdentity(e) => {
+			let creds = &e.thirdparty_id_creds;
+			if self
+				.services
+				.email
+				.validated_email(creds.sid.as_str(), creds.client_secret.as_str(), None)
+				.await
+				.is_ok()
+			{
+				uiaainfo.completed.push(AuthType::EmailIdentity);
+				self.record_auth(&uiaainfo, auth);
+			} else {
+				uiaainfo.auth_error = Some(ruma::api::client::error::StandardErrorBody {
+					kind: ErrorKind::ThreepidAuthFailed,
+					message: "The email address has not been validated.".to_owned(),
+				});
+				return Ok((false, uiaainfo));
+			}
+		},
 		| AuthData::Dummy(_) => {
 			uiaainfo.completed.push(AuthType::Dummy);
 		},
+		| AuthData::FallbackAcknowledgement(_) => {
+			// The client is back from the `m.login.sso` fallback page
+			let session = uiaainfo.session.as_deref().expect("session is always set");
+			let sso_offered = uiaainfo
+				.flows
+				.iter()
+				.any(|flow| flow.stages.contains(&AuthType::Sso));
+
+			if sso_offered && self.services.sso.take_reauthenticated(session, user_id) {
+				uiaainfo.completed.push(AuthType::Sso);
+			}
+		},
 		| k => error!("type not supported: {:?}", k),
 	}
 
@@ -225,6 +287,28 @@ pub async fn try_auth(
 	Ok((true, uiaainfo))
 }
 
+/// Returns the credentials given for the registration token and email stages
+/// of a completed session.
+#[implement(Service)]
+pub fn take_completed_auth(&self, session: &str) -> Vec<AuthData> {
+	self.sessionid_auth
+		.write()
+		.expect("locked for writing")
+		.remove(session)
+		.unwrap_or_default()
+}
+
+#[implement(Service)]
+fn record_auth(&self, uiaainfo: &UiaaInfo, auth: &AuthData) {
+	let session = uiaainfo.session.clone().expect("session is always set");
+	self.sessionid_auth
+		.write()
+		.expect("locked for writing")
+		.entry(session)
+		.or_default()
+		.push(auth.clone());
+}
+
 #[implement(Service)]
 fn set_uiaa_request(
 	&self,
@@ -0,0 +1,189 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::{Arc, Mutex},
+};
+
+use conduwuit::{implement, Err, Result};
+use ruma::{
+	api::federation, encryption::OneTimeKey, serde::Raw, DeviceId, OneTimeKeyAlgorithm,
+	OneTimeKeyName, OwnedDeviceId, OwnedKeyId, OwnedServerName, OwnedUserId, ServerName, UserId,
+};
+use tokio::sync::broadcast;
+
+/// The keys to claim, by user and device.
+pub type ClaimKeys = BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, OneTimeKeyAlgorithm>>;
+
+/// The claimed keys, by user and device.
+pub type OneTimeKeys = BTreeMap<
+	OwnedUserId,
+	BTreeMap<
+		OwnedDeviceId,
+		BTreeMap<OwnedKeyId<OneTimeKeyAlgorithm, OneTimeKeyName>, Raw<OneTimeKey>>,
+	>,
+>;
+
+type ClaimResult = Arc<Result<OneTimeKeys, String>>;
+
+/// Claims for a server which wait for the one in flight to return, to be sent
+/// together.
+pub(super) struct ClaimBatch {
+	keys: ClaimKeys,
+	sender: broadcast::Sender<ClaimResult>,
+}
+
+pub(super) type ClaimBatches = Mutex<HashMap<OwnedServerName, ClaimBatch>>;
+
+enum Role {
+	/// Sends the batch once the server has no claim in flight.
+	Leader,
+
+	/// Joined a batch and gets the leader's result.
+	Member(broadcast::Receiver<ClaimResult>),
+
+	/// Asks for a key of a device the batch already asks for, and the server
+	/// gives out one key per device and request.
+	Alone,
+}
+
+/// Claims one of the local device's one-time keys, or its fallback key once
+/// it has run out of them.
+#[implement(super::Service)]
+pub async fn claim_one_time_key(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+	algorithm: &OneTimeKeyAlgorithm,
+) -> Result<(OwnedKeyId<OneTimeKeyAlgorithm, OneTimeKeyName>, Raw<OneTimeKey>)> {
+	match self.take_one_time_key(user_id, device_id, algorithm).await {
+		| Ok(one_time_key) => Ok(one_time_key),
+		| Err(_) => self.take_fallback_key(user_id, device_id, algorithm).await,
+	}
+}
+
+/// Claims one-time keys of a remote server's users. At most one claim per
+/// server is in flight; claims made meanwhile are sent as one request once it
+/// returns, so setting up sessions in large rooms doesn't flood the server.
+#[implement(super::Service)]
+pub async fn claim_remote_one_time_keys(
+	&self,
+	server: &ServerName,
+	keys: ClaimKeys,
+) -> Result<OneTimeKeys> {
+	let role = {
+		let mut batches = self.claim_batches.lock().expect("locked");
+		match batches.get_mut(server) {
+			| Some(batch) if overlaps(&batch.keys, &keys) => Role::Alone,
+			| Some(batch) => {
+				for (user_id, devices) in &keys {
+					batch
+						.keys
+						.entry(user_id.clone())
+						.or_default()
+						.extend(devices.clone());
+				}
+
+				Role::Member(batch.sender.subscribe())
+			},
+			| None => {
+				let (sender, _) = broadcast::channel(1);
+				batches.insert(server.to_owned(), ClaimBatch { keys: keys.clone(), sender });
+				Role::Leader
+			},
+		}
+	};
+
+	match role {
+		| Role::Alone => self.send_claim(server, keys).await,
+		| Role::Member(mut receiver) => match receiver.recv().await.as_deref() {
+			| Ok(Ok(claimed)) => Ok(select(claimed, &keys)),
+			| Ok(Err(e)) => Err!("Claiming keys from {server} failed: {e}"),
+			| Err(_) => Err!("Claiming keys from {server} was cancelled."),
+		},
+		| Role::Leader => {
+			let pending = Pending {
+				batches: &self.claim_batches,
+				server,
+				taken: false,
+			};
+
+			let _in_flight = self.claim_mutex.lock(server).await;
+			let batch = pending.take();
+
+			let claimed = self.send_claim(server, batch.keys).await;
+			let shared = claimed
+				.as_ref()
+				.map(Clone::clone)
+				.map_err(ToString::to_string);
+			batch.sender.send(Arc::new(shared)).ok();
+
+			claimed.map(|claimed| select(&claimed, &keys))
+		},
+	}
+}
+
+#[implement(super::Service)]
+async fn send_claim(&self, server: &ServerName, one_time_keys: ClaimKeys) -> Result<OneTimeKeys> {
+	let request = federation::keys::claim_keys::v1::Request { one_time_keys };
+	let response = self
+		.services
+		.sending
+		.send_federation_request(server, request)
+		.await?;
+
+	Ok(response.one_time_keys)
+}
+
+/// The leader's pending batch, dropped with the leader if the claim is
+/// cancelled before it was sent, so its members fail instead of waiting
+/// forever.
+struct Pending<'a> {
+	batches: &'a ClaimBatches,
+	server: &'a ServerName,
+	taken: bool,
+}
+
+impl Pending<'_> {
+	fn take(mut self) -> ClaimBatch {
+		self.taken = true;
+		self.batches
+			.lock()
+			.expect("locked")
+			.remove(self.server)
+			.expect("batch of its leader is pending")
+	}
+}
+
+impl Drop for Pending<'_> {
+	fn drop(&mut self) {
+		if !self.taken {
+			self.batches.lock().expect("locked").remove(self.server);
+		}
+	}
+}
+
+fn overlaps(batch: &ClaimKeys, keys: &ClaimKeys) -> bool {
+	keys.iter().any(|(user_id, devices)| {
+		batch.get(user_id).is_some_and(|batched| {
+			devices
+				.keys()
+				.any(|device_id| batched.contains_key(device_id))
+		})
+	})
+}
+
+/// The keys claimed for the given devices out of a batch's.
+fn select(claimed: &OneTimeKeys, keys: &ClaimKeys) -> OneTimeKeys {
+	keys.iter()
+		.filter_map(|(user_id, devices)| {
+			let claimed = claimed.get(user_id)?;
+			let devices = devices
+				.keys()
+				.filter_map(|device_id| {
+					Some((device_id.clone(), claimed.get(device_id)?.clone()))
+				})
+				.collect();
+
+			Some((user_id.clone(), devices))
+		})
+		.collect()
+}
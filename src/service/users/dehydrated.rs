@@ -0,0 +1,102 @@
+use conduwuit::{err, implement, Err, Result};
+use database::{Deserialized, Json};
+use ruma::{
+	api::client::{dehydrated_device::DehydratedDeviceData, device::Device},
+	serde::Raw,
+	DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, UserId,
+};
+use serde::{Deserialize, Serialize};
+
+use super::increment;
+
+/// A user's dehydrated device (MSC3814): a device without an access token
+/// whose keys are stored encrypted on the server, so a new login can take
+/// over the to-device messages it received while no device was online.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DehydratedDevice {
+	pub device_id: OwnedDeviceId,
+
+	/// The pickled device, encrypted by the client.
+	pub device_data: Raw<DehydratedDeviceData>,
+}
+
+/// Stores the user's dehydrated device, replacing and removing any previous
+/// one. The device exists like any other, so it gets to-device messages and
+/// its keys are published, but it has no access token.
+#[implement(super::Service)]
+pub async fn set_dehydrated_device(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+	display_name: Option<String>,
+	device_data: Raw<DehydratedDeviceData>,
+) -> Result {
+	// Checked before the previous dehydrated device is removed, so a rejected
+	// request leaves it in place. Its own ID may be reused.
+	let previous = self.get_dehydrated_device(user_id).await.ok();
+	let key = (user_id, device_id);
+	if previous
+		.as_ref()
+		.is_none_or(|previous| previous.device_id != device_id)
+		&& self.db.userdeviceid_metadata.qry(&key).await.is_ok()
+	{
+		return Err!(Request(InvalidParam("A device with this ID already exists.")));
+	}
+
+	if let Some(previous) = previous {
+		self.remove_device(user_id, &previous.device_id).await;
+	}
+
+	let device = Device {
+		device_id: device_id.into(),
+		display_name,
+		last_seen_ip: None,
+		last_seen_ts: Some(MilliSecondsSinceUnixEpoch::now()),
+	};
+
+	increment(&self.db.userid_devicelistversion, user_id.as_bytes());
+	self.db.userdeviceid_metadata.put(key, Json(device));
+
+	let dehydrated = DehydratedDevice { device_id: device_id.into(), device_data };
+	self.db
+		.userid_dehydrateddevice
+		.raw_put(user_id, Json(dehydrated));
+
+	Ok(())
+}
+
+#[implement(super::Service)]
+pub async fn get_dehydrated_device(&self, user_id: &UserId) -> Result<DehydratedDevice> {
+	self.db
+		.userid_dehydrateddevice
+		.get(user_id)
+		.await
+		.deserialized()
+		.map_err(|_| err!(Request(NotFound("No dehydrated device is stored."))))
+}
+
+/// Removes the user's dehydrated device along with its keys and messages.
+/// Returns its ID.
+#[implement(super::Service)]
+pub async fn remove_dehydrated_device(&self, user_id: &UserId) -> Result<OwnedDeviceId> {
+	let dehydrated = self.get_dehydrated_device(user_id).await?;
+	self.remove_device(user_id, &dehydrated.device_id).await;
+
+	Ok(dehydrated.device_id)
+}
+
+#[implement(super::Service)]
+pub async fn is_dehydrated_device(&self, user_id: &UserId, device_id: &DeviceId) -> bool {
+	self.get_dehydrated_device(user_id)
+		.await
+		.is_ok_and(|dehydrated| dehydrated.device_id == device_id)
+}
+
+/// Forgets the dehydrated device if it is the given one, which is being
+/// removed.
+#[implement(super::Service)]
+pub(super) async fn forget_dehydrated_device(&self, user_id: &UserId, device_id: &DeviceId) {
+	if self.is_dehydrated_device(user_id, device_id).await {
+		self.db.userid_dehydrateddevice.remove(user_id);
+	}
+}
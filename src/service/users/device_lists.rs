@@ -0,0 +1,111 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use conduwuit::{
+	implement,
+	utils::{millis_since_unix_epoch, stream::TryIgnore, ReadyExt},
+};
+use futures::{Stream, StreamExt};
+use ruma::{OwnedServerName, ServerName, UserId};
+
+/// A change queued for a server, with when it was queued in milliseconds since
+/// the unix epoch.
+type KeyVal<'a> = ((&'a ServerName, u64), (&'a UserId, u64));
+
+/// Changes a server hasn't acknowledged for this long are dropped. A server
+/// back after that long has to resync the device lists it cares about anyway.
+const UNACKNOWLEDGED_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Queues a device list update of the local user for each remote server
+/// sharing an encrypted room with them, as the `count`th change. Servers which
+/// can't have encrypted to the user's devices aren't told. Changes left
+/// unacknowledged by a server for too long are dropped on the way.
+#[implement(super::Service)]
+pub(super) async fn queue_device_list_update(&self, user_id: &UserId, count: u64) {
+	let mut servers = BTreeSet::<OwnedServerName>::new();
+	let mut rooms = self
+		.services
+		.state_cache
+		.rooms_joined(user_id)
+		.filter(|room_id| self.services.state_accessor.is_encrypted_room(room_id))
+		.boxed();
+
+	while let Some(room_id) = rooms.next().await {
+		self.services
+			.state_cache
+			.room_servers(room_id)
+			.ready_filter(|server| !self.services.globals.server_is_ours(server))
+			.ready_for_each(|server| {
+				servers.insert(server.to_owned());
+			})
+			.await;
+	}
+
+	let now = millis_since_unix_epoch();
+	let ttl = u64::try_from(UNACKNOWLEDGED_TTL.as_millis()).unwrap_or(u64::MAX);
+
+	for server in &servers {
+		self.expire_server_device_list_changes(server, now.saturating_sub(ttl))
+			.await;
+
+		let key = (server, count);
+		self.db
+			.servernamecount_devicelistuserid
+			.put(key, (user_id, now));
+	}
+}
+
+/// Drops the changes queued for the server before `cutoff` milliseconds since
+/// the unix epoch, which it never acknowledged.
+#[implement(super::Service)]
+async fn expire_server_device_list_changes(&self, server: &ServerName, cutoff: u64) {
+	let start = (server, 0_u64);
+	self.db
+		.servernamecount_devicelistuserid
+		.stream_from(&start)
+		.ignore_err()
+		.ready_take_while(|((server_, _), (_, queued)): &KeyVal<'_>| {
+			*server_ == server && *queued < cutoff
+		})
+		.ready_for_each(|(key, _): KeyVal<'_>| {
+			self.db.servernamecount_devicelistuserid.del(key);
+		})
+		.await;
+}
+
+/// The local users whose device lists changed after `from` up to `to`, as
+/// queued for the server, with the count of each change.
+#[implement(super::Service)]
+pub fn server_device_list_changes<'a>(
+	&'a self,
+	server: &'a ServerName,
+	from: u64,
+	to: u64,
+) -> impl Stream<Item = (&UserId, u64)> + Send + 'a {
+	let start = (server, from.saturating_add(1));
+	self.db
+		.servernamecount_devicelistuserid
+		.stream_from(&start)
+		.ignore_err()
+		.ready_take_while(move |((server_, count), _): &KeyVal<'_>| {
+			*server_ == server && *count <= to
+		})
+		.map(|((_, count), (user_id, _)): KeyVal<'_>| (user_id, count))
+}
+
+/// Forgets the changes queued for the server up to `until`, which it has
+/// received.
+#[implement(super::Service)]
+pub async fn forget_server_device_list_changes(&self, server: &ServerName, until: u64) {
+	let start = (server, 0_u64);
+	self.db
+		.servernamecount_devicelistuserid
+		.keys_from(&start)
+		.ignore_err()
+		.ready_take_while(|(server_, count): &(&ServerName, u64)| {
+			*server_ == server && *count <= until
+		})
+		.ready_for_each(|key: (&ServerName, u64)| {
+			self.db.servernamecount_devicelistuserid.del(key);
+		})
+		.await;
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use conduwuit::{err, implement, result::LogErr, Result};
+use database::{Deserialized, Json};
+use ruma::{
+	encryption::OneTimeKey, serde::Raw, DeviceId, OneTimeKeyAlgorithm, OneTimeKeyName,
+	OwnedKeyId, UserId,
+};
+use serde::{Deserialize, Serialize};
+
+/// A device's fallback keys, one per algorithm.
+type FallbackKeys = BTreeMap<OneTimeKeyAlgorithm, FallbackKey>;
+
+/// The key given out when a device has run out of one-time keys. It is kept
+/// until the device replaces it, and marked as used once claimed so the
+/// device learns to upload a new one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct FallbackKey {
+	key_id: OwnedKeyId<OneTimeKeyAlgorithm, OneTimeKeyName>,
+	key: Raw<OneTimeKey>,
+	used: bool,
+}
+
+/// Stores a fallback key, replacing the device's previous one of the same
+/// algorithm. Uploading the current key again keeps whether it was used.
+#[implement(super::Service)]
+pub async fn add_fallback_key(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+	key_id: &OwnedKeyId<OneTimeKeyAlgorithm, OneTimeKeyName>,
+	key: &Raw<OneTimeKey>,
+) {
+	let mut keys = self.fallback_keys(user_id, device_id).await;
+	let algorithm = key_id.algorithm();
+	if keys
+		.get(&algorithm)
+		.is_some_and(|current| current.key_id == *key_id)
+	{
+		return;
+	}
+
+	keys.insert(algorithm, FallbackKey {
+		key_id: key_id.clone(),
+		key: key.clone(),
+		used: false,
+	});
+
+	self.db
+		.userdeviceid_fallbackkeys
+		.put((user_id, device_id), Json(keys));
+}
+
+/// Gives out the device's fallback key of the algorithm and marks it as used.
+/// Only meant for devices without one-time keys left.
+#[implement(super::Service)]
+pub async fn take_fallback_key(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+	algorithm: &OneTimeKeyAlgorithm,
+) -> Result<(OwnedKeyId<OneTimeKeyAlgorithm, OneTimeKeyName>, Raw<OneTimeKey>)> {
+	let mut keys = self.fallback_keys(user_id, device_id).await;
+	let fallback = keys
+		.get_mut(algorithm)
+		.ok_or_else(|| err!(Request(NotFound("No fallback key found"))))?;
+
+	let taken = (fallback.key_id.clone(), fallback.key.clone());
+	if !fallback.used {
+		fallback.used = true;
+		self.db
+			.userdeviceid_fallbackkeys
+			.put((user_id, device_id), Json(keys));
+
+		let count = self.services.globals.next_count()?;
+		self.db.userid_lastonetimekeyupdate.raw_put(user_id, count);
+
+		self.services
+			.sending
+			.one_time_keys_changed_appservices(user_id, device_id)
+			.await
+			.log_err()
+			.ok();
+	}
+
+	Ok(taken)
+}
+
+/// The algorithms the device has a fallback key of which was not claimed yet,
+/// for `device_unused_fallback_key_types` in sync.
+#[implement(super::Service)]
+pub async fn unused_fallback_key_types(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+) -> Vec<OneTimeKeyAlgorithm> {
+	self.fallback_keys(user_id, device_id)
+		.await
+		.into_iter()
+		.filter(|(_, fallback)| !fallback.used)
+		.map(|(algorithm, _)| algorithm)
+		.collect()
+}
+
+#[implement(super::Service)]
+pub(super) fn remove_fallback_keys(&self, user_id: &UserId, device_id: &DeviceId) {
+	self.db.userdeviceid_fallbackkeys.del((user_id, device_id));
+}
+
+#[implement(super::Service)]
+async fn fallback_keys(&self, user_id: &UserId, device_id: &DeviceId) -> FallbackKeys {
+	self.db
+		.userdeviceid_fallbackkeys
+		.qry(&(user_id, device_id))
+		.await
+		.deserialized()
+		.unwrap_or_default()
+}
@@ -0,0 +1,50 @@
+//! Ignored users
+//!
+//! Users a user listed in their `m.ignored_user_list` account data. Their
+//! events are filtered out of what the user is served, and their invites
+//! rejected.
+
+use std::collections::HashSet;
+
+use conduwuit::implement;
+use ruma::{
+	events::{ignored_user_list::IgnoredUserListEvent, GlobalAccountDataEventType},
+	OwnedUserId, UserId,
+};
+
+/// The users ignored by a user, loaded once for filtering many events.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoredUsers(HashSet<OwnedUserId>);
+
+impl IgnoredUsers {
+	#[inline]
+	#[must_use]
+	pub fn contains(&self, user_id: &UserId) -> bool { self.0.contains(user_id) }
+
+	#[inline]
+	#[must_use]
+	pub fn is_empty(&self) -> bool { self.0.is_empty() }
+}
+
+/// Loads the users ignored by the user. Missing or invalid account data
+/// ignores no one.
+#[implement(super::Service)]
+pub async fn ignored_users(&self, user_id: &UserId) -> IgnoredUsers {
+	self.services
+		.account_data
+		.get_global(user_id, GlobalAccountDataEventType::IgnoredUserList)
+		.await
+		.map(|ignored: IgnoredUserListEvent| {
+			IgnoredUsers(ignored.content.ignored_users.into_keys().collect())
+		})
+		.unwrap_or_default()
+}
+
+/// Returns true/false based on whether the recipient/receiving user has
+/// blocked the sender
+#[implement(super::Service)]
+pub async fn user_is_ignored(&self, sender_user: &UserId, recipient_user: &UserId) -> bool {
+	self.ignored_users(recipient_user)
+		.await
+		.contains(sender_user)
+}
@@ -1,29 +1,98 @@
-use std::{collections::BTreeMap, mem, sync::Arc};
+mod claim;
+mod dehydrated;
+mod device_lists;
+mod fallback;
+mod ignored;
+mod signing;
+mod to_device;
 
+use std::{collections::BTreeMap, mem, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
 use conduwuit::{
-	at, debug_warn, err, trace,
-	utils::{self, stream::TryIgnore, string::Unquoted, ReadyExt},
+	at, debug, debug_warn, err,
+	result::LogErr,
+	trace,
+	utils::{self, stream::TryIgnore, string::Unquoted, MutexMap, ReadyExt},
 	Err, Error, Result, Server,
 };
-use database::{Deserialized, Ignore, Interfix, Json, Map};
+use database::{Database, Deserialized, Ignore, Interfix, Json, Map};
 use futures::{Stream, StreamExt, TryFutureExt};
 use ruma::{
 	api::client::{device::Device, error::ErrorKind, filter::FilterDefinition},
 	encryption::{CrossSigningKey, DeviceKeys, OneTimeKey},
-	events::{
-		ignored_user_list::IgnoredUserListEvent, AnyToDeviceEvent, GlobalAccountDataEventType,
-	},
+	events::AnyToDeviceEvent,
 	serde::Raw,
 	DeviceId, KeyId, MilliSecondsSinceUnixEpoch, OneTimeKeyAlgorithm, OneTimeKeyId,
-	OneTimeKeyName, OwnedDeviceId, OwnedKeyId, OwnedMxcUri, OwnedUserId, RoomId, UInt, UserId,
+	OneTimeKeyName, OwnedDeviceId, OwnedKeyId, OwnedMxcUri, OwnedServerName, OwnedUserId, RoomId,
+	UInt, UserId,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio::{
+	sync::Notify,
+	time::{interval, MissedTickBehavior},
+};
 
-use crate::{account_data, admin, globals, rooms, Dep};
+use self::claim::ClaimBatches;
+pub use self::{
+	claim::{ClaimKeys, OneTimeKeys},
+	dehydrated::DehydratedDevice,
+	ignored::IgnoredUsers,
+};
+use crate::{account_data, admin, globals, rooms, sending, Dep};
 
 pub struct Service {
 	services: Services,
 	db: Data,
+	claim_batches: ClaimBatches,
+	claim_mutex: MutexMap<OwnedServerName, ()>,
+	interrupt: Notify,
+}
+
+/// How often expired and excess to-device messages are removed.
+const TO_DEVICE_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Global account data type under which users store their [`InvitePolicy`].
+pub const INVITE_POLICY_EVENT_TYPE: &str = "im.conduwuit.invite_policy";
+
+/// Which invites a user wants to receive.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct InvitePolicy {
+	#[serde(default)]
+	pub mode: InvitePolicyMode,
+
+	/// Users whose invites are accepted unless the mode is `block_all`.
+	#[serde(default)]
+	pub allowed_users: Vec<OwnedUserId>,
+
+	/// Servers whose users' invites are accepted unless the mode is
+	/// `block_all`.
+	#[serde(default)]
+	pub allowed_servers: Vec<OwnedServerName>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvitePolicyMode {
+	/// Accept every invite.
+	#[default]
+	AllowAll,
+
+	/// Reject every invite.
+	BlockAll,
+
+	/// Reject invites from servers which take part in none of the user's
+	/// joined rooms, unless they are allowed.
+	BlockUnknownServers,
+
+	/// Reject every invite from users and servers which are not allowed.
+	Allowlist,
+}
+
+#[derive(Deserialize)]
+struct InvitePolicyEvent {
+	content: InvitePolicy,
 }
 
 struct Services {
@@ -33,21 +102,27 @@ struct Services {
 	globals: Dep<globals::Service>,
 	state_accessor: Dep<rooms::state_accessor::Service>,
 	state_cache: Dep<rooms::state_cache::Service>,
+	sending: Dep<sending::Service>,
 }
 
 struct Data {
+	db: Arc<Database>,
 	keychangeid_userid: Arc<Map>,
 	keyid_key: Arc<Map>,
 	onetimekeyid_onetimekeys: Arc<Map>,
+	servernamecount_devicelistuserid: Arc<Map>,
 	openidtoken_expiresatuserid: Arc<Map>,
 	logintoken_expiresatuserid: Arc<Map>,
+	todevicecount_queuedts: Arc<Map>,
 	todeviceid_events: Arc<Map>,
 	token_userdeviceid: Arc<Map>,
+	userdeviceid_fallbackkeys: Arc<Map>,
 	userdeviceid_metadata: Arc<Map>,
 	userdeviceid_token: Arc<Map>,
 	userfilterid_filter: Arc<Map>,
 	userid_avatarurl: Arc<Map>,
 	userid_blurhash: Arc<Map>,
+	userid_dehydrateddevice: Arc<Map>,
 	userid_devicelistversion: Arc<Map>,
 	userid_displayname: Arc<Map>,
 	userid_lastonetimekeyupdate: Arc<Map>,
@@ -58,6 +133,7 @@ struct Data {
 	useridprofilekey_value: Arc<Map>,
 }
 
+#[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
@@ -69,20 +145,27 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				state_accessor: args
 					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
 				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				sending: args.depend::<sending::Service>("sending"),
 			},
 			db: Data {
+				db: args.db.clone(),
 				keychangeid_userid: args.db["keychangeid_userid"].clone(),
 				keyid_key: args.db["keyid_key"].clone(),
 				onetimekeyid_onetimekeys: args.db["onetimekeyid_onetimekeys"].clone(),
+				servernamecount_devicelistuserid: args.db["servernamecount_devicelistuserid"]
+					.clone(),
 				openidtoken_expiresatuserid: args.db["openidtoken_expiresatuserid"].clone(),
 				logintoken_expiresatuserid: args.db["logintoken_expiresatuserid"].clone(),
+				todevicecount_queuedts: args.db["todevicecount_queuedts"].clone(),
 				todeviceid_events: args.db["todeviceid_events"].clone(),
 				token_userdeviceid: args.db["token_userdeviceid"].clone(),
+				userdeviceid_fallbackkeys: args.db["userdeviceid_fallbackkeys"].clone(),
 				userdeviceid_metadata: args.db["userdeviceid_metadata"].clone(),
 				userdeviceid_token: args.db["userdeviceid_token"].clone(),
 				userfilterid_filter: args.db["userfilterid_filter"].clone(),
 				userid_avatarurl: args.db["userid_avatarurl"].clone(),
 				userid_blurhash: args.db["userid_blurhash"].clone(),
+				userid_dehydrateddevice: args.db["userid_dehydrateddevice"].clone(),
 				userid_devicelistversion: args.db["userid_devicelistversion"].clone(),
 				userid_displayname: args.db["userid_displayname"].clone(),
 				userid_lastonetimekeyupdate: args.db["userid_lastonetimekeyupdate"].clone(),
@@ -92,27 +175,91 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				userid_usersigningkeyid: args.db["userid_usersigningkeyid"].clone(),
 				useridprofilekey_value: args.db["useridprofilekey_value"].clone(),
 			},
+			claim_batches: ClaimBatches::default(),
+			claim_mutex: MutexMap::new(),
+			interrupt: Notify::new(),
 		}))
 	}
 
+	async fn worker(self: Arc<Self>) -> Result {
+		let mut i = interval(TO_DEVICE_PRUNE_INTERVAL);
+		i.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		loop {
+			tokio::select! {
+				() = self.interrupt.notified() => break,
+				_ = i.tick() => (),
+			}
+
+			let removed = self.prune_to_device_events().await;
+			if removed > 0 {
+				debug!("Removed {removed} expired or excess to-device messages");
+			}
+		}
+
+		Ok(())
+	}
+
+	fn interrupt(&self) { self.interrupt.notify_waiters(); }
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
 impl Service {
-	/// Returns true/false based on whether the recipient/receiving user has
-	/// blocked the sender
-	pub async fn user_is_ignored(&self, sender_user: &UserId, recipient_user: &UserId) -> bool {
+	/// Returns the invite policy the user stored in their account data, or
+	/// the default which accepts every invite.
+	pub async fn invite_policy(&self, user_id: &UserId) -> InvitePolicy {
 		self.services
 			.account_data
-			.get_global(recipient_user, GlobalAccountDataEventType::IgnoredUserList)
+			.get_global(user_id, INVITE_POLICY_EVENT_TYPE.into())
 			.await
-			.is_ok_and(|ignored: IgnoredUserListEvent| {
-				ignored
-					.content
-					.ignored_users
-					.keys()
-					.any(|blocked_user| blocked_user == sender_user)
-			})
+			.map(|event: InvitePolicyEvent| event.content)
+			.unwrap_or_default()
+	}
+
+	/// Returns whether a local user accepts invites from the sender according
+	/// to their invite policy. The server user can always invite; users the
+	/// recipient ignores never can.
+	pub async fn accepts_invite_from(
+		&self,
+		recipient_user: &UserId,
+		sender_user: &UserId,
+	) -> bool {
+		if *sender_user == *self.services.globals.server_user {
+			return true;
+		}
+
+		if self.user_is_ignored(sender_user, recipient_user).await {
+			return false;
+		}
+
+		let policy = self.invite_policy(recipient_user).await;
+		let allowed = policy.allowed_users.iter().any(|user| user == sender_user)
+			|| policy
+				.allowed_servers
+				.iter()
+				.any(|server| server == sender_user.server_name());
+
+		match policy.mode {
+			| InvitePolicyMode::AllowAll => true,
+			| InvitePolicyMode::BlockAll => false,
+			| InvitePolicyMode::Allowlist => allowed,
+			| InvitePolicyMode::BlockUnknownServers =>
+				allowed
+					|| self
+						.services
+						.globals
+						.server_is_ours(sender_user.server_name())
+					|| self
+						.services
+						.state_cache
+						.rooms_joined(recipient_user)
+						.any(|room_id| {
+							self.services
+								.state_cache
+								.server_in_room(sender_user.server_name(), room_id)
+						})
+						.await,
+		}
 	}
 
 	/// Check if a user is an admin
@@ -224,6 +371,13 @@ pub fn set_password(&self, user_id: &UserId, password: Option<&str>) -> Result<(
 		Ok(())
 	}
 
+	/// Sets the user's password to an Argon2 hash made beforehand, or none.
+	pub fn set_password_hash(&self, user_id: &UserId, hash: Option<&str>) {
+		self.db
+			.userid_password
+			.insert(user_id, hash.unwrap_or_default());
+	}
+
 	/// Returns the displayname of a user on this homeserver.
 	pub async fn displayname(&self, user_id: &UserId) -> Result<String> {
 		self.db.userid_displayname.get(user_id).await.deserialized()
@@ -284,6 +438,14 @@ pub async fn create_device(
 			))));
 		}
 
+		let max_devices = self.services.server.config.max_devices_per_user;
+		if max_devices > 0 && self.count_devices(user_id).await >= max_devices {
+			return Err!(Request(Forbidden(
+				"You already have the maximum of {max_devices} devices; log out of one of them \
+				 first."
+			)));
+		}
+
 		let key = (user_id, device_id);
 		let val = Device {
 			device_id: device_id.into(),
@@ -297,6 +459,11 @@ pub async fn create_device(
 		self.set_token(user_id, device_id, token).await
 	}
 
+	/// Returns the number of devices of a user.
+	pub async fn count_devices(&self, user_id: &UserId) -> usize {
+		self.all_device_ids(user_id).count().await
+	}
+
 	/// Removes a device from a user.
 	pub async fn remove_device(&self, user_id: &UserId, device_id: &DeviceId) {
 		let userdeviceid = (user_id, device_id);
@@ -317,7 +484,9 @@ pub async fn remove_device(&self, user_id: &UserId, device_id: &DeviceId) {
 			.await;
 
 		// TODO: Remove onetimekeys
+		self.remove_fallback_keys(user_id, device_id);
 
+		self.forget_dehydrated_device(user_id, device_id).await;
 		increment(&self.db.userid_devicelistversion, user_id.as_bytes());
 
 		self.db.userdeviceid_metadata.del(userdeviceid);
@@ -466,7 +635,17 @@ pub async fn take_one_time_key(
 			.next()
 			.await;
 
-		one_time_key.ok_or_else(|| err!(Request(NotFound("No one-time-key found"))))
+		let one_time_key =
+			one_time_key.ok_or_else(|| err!(Request(NotFound("No one-time-key found"))))?;
+
+		self.services
+			.sending
+			.one_time_keys_changed_appservices(user_id, device_id)
+			.await
+			.log_err()
+			.ok();
+
+		Ok(one_time_key)
 	}
 
 	pub async fn count_one_time_keys(
@@ -525,6 +704,15 @@ pub async fn add_cross_signing_keys(
 
 		let (master_key_key, _) = parse_master_key(user_id, master_key)?;
 
+		// Keep the signatures our users made on remote keys
+		let is_remote = !self.services.globals.user_is_local(user_id);
+		let master_key = if is_remote {
+			self.with_stored_signatures(&master_key_key, master_key)
+				.await?
+		} else {
+			master_key.clone()
+		};
+
 		self.db
 			.keyid_key
 			.insert(&master_key_key, master_key.json().get().as_bytes());
@@ -556,6 +744,13 @@ pub async fn add_cross_signing_keys(
 			let mut self_signing_key_key = prefix.clone();
 			self_signing_key_key.extend_from_slice(self_signing_key_id.as_bytes());
 
+			let self_signing_key = if is_remote {
+				self.with_stored_signatures(&self_signing_key_key, self_signing_key)
+					.await?
+			} else {
+				self_signing_key.clone()
+			};
+
 			self.db
 				.keyid_key
 				.insert(&self_signing_key_key, self_signing_key.json().get().as_bytes());
@@ -698,6 +893,19 @@ pub async fn mark_device_key_update(&self, user_id: &UserId) {
 
 		let key = (user_id, count);
 		self.db.keychangeid_userid.put_raw(key, user_id);
+
+		// Every change gets a new stream_id for federation
+		if self.services.globals.user_is_local(user_id) {
+			increment(&self.db.userid_devicelistversion, user_id.as_bytes());
+			self.queue_device_list_update(user_id, count).await;
+		}
+
+		self.services
+			.sending
+			.device_list_changed_appservices(user_id)
+			.await
+			.log_err()
+			.ok();
 	}
 
 	pub async fn get_device_keys<'a>(
@@ -773,17 +981,45 @@ pub async fn add_to_device_event(
 		event_type: &str,
 		content: serde_json::Value,
 	) {
+		self.add_to_device_events(
+			sender,
+			[(target_user_id, target_device_id)],
+			event_type,
+			&content,
+		);
+	}
+
+	/// Queues the same to-device event for many devices at once. The event is
+	/// serialized once and all deliveries share one count, so fanning out to
+	/// thousands of devices costs a single write per device.
+	pub fn add_to_device_events<'a, I>(
+		&self,
+		sender: &UserId,
+		targets: I,
+		event_type: &str,
+		content: &serde_json::Value,
+	) where
+		I: IntoIterator<Item = (&'a UserId, &'a DeviceId)>,
+	{
 		let count = self.services.globals.next_count().unwrap();
+		let event = serde_json::to_vec(&json!({
+			"type": event_type,
+			"sender": sender,
+			"content": content,
+		}))
+		.expect("to-device event can be serialized");
 
-		let key = (target_user_id, target_device_id, count);
-		self.db.todeviceid_events.put(
-			key,
-			Json(json!({
-				"type": event_type,
-				"sender": sender,
-				"content": content,
-			})),
-		);
+		let _cork = self.db.db.cork();
+		if self.services.server.config.to_device_message_expiry_s > 0 {
+			self.db
+				.todevicecount_queuedts
+				.put(count, utils::millis_since_unix_epoch());
+		}
+
+		for (target_user_id, target_device_id) in targets {
+			let key = (target_user_id, target_device_id, count);
+			self.db.todeviceid_events.put_raw(key, &event);
+		}
 	}
 
 	pub fn get_to_device_events<'a>(
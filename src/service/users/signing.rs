@@ -0,0 +1,84 @@
+use std::collections::BTreeSet;
+
+use conduwuit::{
+	implement,
+	utils::{IterStream, ReadyExt},
+	Result,
+};
+use futures::StreamExt;
+use ruma::{
+	api::federation::transactions::edu::{Edu, SigningKeyUpdateContent},
+	encryption::CrossSigningKey,
+	serde::Raw,
+	OwnedServerName, UserId,
+};
+
+use crate::sending::EduBuf;
+
+/// Sends the local user's current master and self-signing keys, with only
+/// their own signatures, to every server sharing a room with them, so remote
+/// users see new keys and signatures without querying first.
+#[implement(super::Service)]
+pub async fn send_signing_key_update(&self, user_id: &UserId) -> Result {
+	debug_assert!(
+		self.services.globals.user_is_local(user_id),
+		"tried to send signing key update of remote user",
+	);
+
+	let owner_only = |_: &UserId| false;
+	let Ok(master_key) = self.get_master_key(None, user_id, &owner_only).await else {
+		return Ok(());
+	};
+
+	let mut content = SigningKeyUpdateContent::new(user_id.to_owned());
+	content.master_key = Some(master_key);
+	content.self_signing_key = self
+		.get_self_signing_key(None, user_id, &owner_only)
+		.await
+		.ok();
+
+	let mut servers = BTreeSet::<OwnedServerName>::new();
+	let mut rooms = self.services.state_cache.rooms_joined(user_id).boxed();
+	while let Some(room_id) = rooms.next().await {
+		self.services
+			.state_cache
+			.room_servers(room_id)
+			.ready_for_each(|server| {
+				if !self.services.globals.server_is_ours(server) {
+					servers.insert(server.to_owned());
+				}
+			})
+			.await;
+	}
+
+	let mut buf = EduBuf::new();
+	serde_json::to_writer(&mut buf, &Edu::SigningKeyUpdate(content))
+		.expect("Serialized Edu::SigningKeyUpdate");
+
+	self.services
+		.sending
+		.send_edu_servers(servers.iter().map(AsRef::as_ref).stream(), buf)
+		.await
+}
+
+/// Merges the signatures stored with the key under `key_key` into a newer copy
+/// of it, as from a remote server, which only knows its own users' signatures.
+/// Signatures in the newer copy replace stored ones by the same user.
+#[implement(super::Service)]
+pub(super) async fn with_stored_signatures(
+	&self,
+	key_key: &[u8],
+	key: &Raw<CrossSigningKey>,
+) -> Result<Raw<CrossSigningKey>> {
+	let Ok(stored) = self.db.keyid_key.get(key_key).await else {
+		return Ok(key.clone());
+	};
+
+	let stored: CrossSigningKey = serde_json::from_slice(&stored)?;
+	let mut key: CrossSigningKey = key.deserialize()?;
+	let mut signatures = stored.signatures;
+	signatures.append(&mut key.signatures);
+	key.signatures = signatures;
+
+	Ok(Raw::new(&key)?)
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use conduwuit::{
+	implement,
+	utils::{millis_since_unix_epoch, stream::TryIgnore, ReadyExt},
+};
+use futures::StreamExt;
+use ruma::{DeviceId, OwnedDeviceId, OwnedUserId, UserId};
+
+type Key<'a> = (&'a UserId, &'a DeviceId, u64);
+
+/// Undelivered to-device messages per device, optionally of one user only,
+/// largest queues first.
+#[implement(super::Service)]
+pub async fn to_device_queue_sizes(
+	&self,
+	user_id: Option<&UserId>,
+) -> Vec<(OwnedUserId, OwnedDeviceId, usize)> {
+	let mut sizes = HashMap::<(OwnedUserId, OwnedDeviceId), usize>::new();
+	let count = |(user_id, device_id, _): Key<'_>| {
+		let size = sizes
+			.entry((user_id.to_owned(), device_id.to_owned()))
+			.or_default();
+
+		*size = size.saturating_add(1);
+	};
+
+	match user_id {
+		| Some(user_id) =>
+			self.db
+				.todeviceid_events
+				.keys_prefix(&(user_id, database::Interfix))
+				.ignore_err()
+				.ready_for_each(count)
+				.await,
+		| None =>
+			self.db
+				.todeviceid_events
+				.keys()
+				.ignore_err()
+				.ready_for_each(count)
+				.await,
+	}
+
+	let mut sizes: Vec<_> = sizes
+		.into_iter()
+		.map(|((user_id, device_id), size)| (user_id, device_id, size))
+		.collect();
+
+	sizes.sort_by(|a, b| b.2.cmp(&a.2));
+	sizes
+}
+
+/// Removes to-device messages queued longer than `to_device_message_expiry_s`
+/// and evicts the oldest messages of devices with more than
+/// `to_device_queue_limit` queued. Returns how many were removed.
+#[implement(super::Service)]
+pub async fn prune_to_device_events(&self) -> usize {
+	let config = &self.services.server.config;
+	let expired_until = self
+		.expire_to_device_counts(config.to_device_message_expiry_s)
+		.await;
+
+	// One pass deleting expired messages and counting the rest per device
+	let mut removed = 0_usize;
+	let mut queued = HashMap::<(OwnedUserId, OwnedDeviceId), usize>::new();
+	self.db
+		.todeviceid_events
+		.keys()
+		.ignore_err()
+		.ready_for_each(|key: Key<'_>| {
+			let (user_id, device_id, count) = key;
+			if expired_until.is_some_and(|until| count <= until) {
+				self.db.todeviceid_events.del(key);
+				removed = removed.saturating_add(1);
+				return;
+			}
+
+			let size = queued
+				.entry((user_id.to_owned(), device_id.to_owned()))
+				.or_default();
+
+			*size = size.saturating_add(1);
+		})
+		.await;
+
+	let limit = config.to_device_queue_limit;
+	if limit == 0 {
+		return removed;
+	}
+
+	for ((user_id, device_id), size) in queued {
+		let Some(excess) = size.checked_sub(limit).filter(|&excess| excess > 0) else {
+			continue;
+		};
+
+		self.db
+			.todeviceid_events
+			.keys_prefix(&(&user_id, &device_id, database::Interfix))
+			.ignore_err()
+			.take(excess)
+			.ready_for_each(|key: Key<'_>| self.db.todeviceid_events.del(key))
+			.await;
+
+		removed = removed.saturating_add(excess);
+	}
+
+	removed
+}
+
+/// Forgets when messages older than the expiry were queued, returning the
+/// count of the newest of them; older messages are to be deleted. Without an
+/// expiry nothing is recorded, and what was recorded before is dropped.
+#[implement(super::Service)]
+async fn expire_to_device_counts(&self, expiry_s: u64) -> Option<u64> {
+	if expiry_s == 0 {
+		self.db
+			.todevicecount_queuedts
+			.keys()
+			.ignore_err()
+			.ready_for_each(|count: u64| self.db.todevicecount_queuedts.del(count))
+			.await;
+
+		return None;
+	}
+
+	let cutoff = millis_since_unix_epoch().saturating_sub(expiry_s.saturating_mul(1000));
+	let mut expired_until = None;
+	self.db
+		.todevicecount_queuedts
+		.stream()
+		.ignore_err()
+		.ready_take_while(|&(_, queued_ts): &(u64, u64)| queued_ts < cutoff)
+		.ready_for_each(|(count, _)| {
+			self.db.todevicecount_queuedts.del(count);
+			expired_until = Some(count);
+		})
+		.await;
+
+	expired_until
+}